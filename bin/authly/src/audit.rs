@@ -0,0 +1,62 @@
+//! Export and verification of the audit trail, for archival outside Authly.
+
+use std::{fs::File, io::BufWriter, path::Path};
+
+use anyhow::{anyhow, bail};
+use authly_domain::{
+    audit::{self, AuditExportFormat},
+    IsLeaderDb,
+};
+use tracing::info;
+
+use crate::{build_secrets, encryption, start_hiqlite, tls, EnvConfig};
+
+/// Write the recorded access control decisions of the local node's database to `out`.
+pub async fn export(format: AuditExportFormat, out: &Path) -> anyhow::Result<()> {
+    if out.exists() {
+        bail!("export target {out:?} already exists");
+    }
+
+    tls::init_tls_ring();
+
+    let env_config = EnvConfig::load();
+    let hql = start_hiqlite(&env_config).await?;
+
+    let mut writer = BufWriter::new(File::create(out)?);
+    let count = audit::export_access_control_audit(&hql, format, &mut writer).await?;
+
+    info!(?out, count, "audit trail exported");
+
+    hql.shutdown().await?;
+
+    Ok(())
+}
+
+/// Verify the hash chain of the recorded access control decisions in the local node's database.
+pub async fn verify() -> anyhow::Result<()> {
+    tls::init_tls_ring();
+
+    let env_config = EnvConfig::load();
+    let hql = start_hiqlite(&env_config).await?;
+
+    let secrets = build_secrets(&env_config)?;
+    let result = match encryption::load_decrypted_deks(
+        &hql,
+        IsLeaderDb(hql.is_leader_db().await),
+        secrets.as_ref(),
+    )
+    .await
+    {
+        Ok(deks) => audit::verify_access_control_audit_chain(&hql, &deks)
+            .await
+            .map_err(|err| anyhow!("audit trail verification failed: {err}")),
+        Err(err) => Err(anyhow!("audit chain key can't be loaded: {err}")),
+    };
+
+    hql.shutdown().await?;
+
+    let count = result?;
+    info!(count, "audit trail verified");
+
+    Ok(())
+}
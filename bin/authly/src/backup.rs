@@ -0,0 +1,179 @@
+//! Backup and restore of the full Authly state.
+//!
+//! All Authly state lives in the database: directories, entities, policies, the instance identity and certificates,
+//! and the Data Encryption Keys, which are stored encrypted by the master key.
+//! The master key itself stays in the secrets backend and is never part of a backup,
+//! the backup only refers to it by version.
+
+use std::path::Path;
+
+use anyhow::{anyhow, bail};
+use authly_domain::{
+    encryption::gen_prop_deks,
+    repo::{backup_repo, crypto_repo},
+    IsLeaderDb,
+};
+use authly_sqlite::{SqlitePool, Storage};
+use tracing::info;
+
+use crate::{
+    build_secrets, encryption, hiqlite_db_path, hiqlite_node_config, start_hiqlite, tls, EnvConfig,
+};
+
+/// Write a consistent snapshot of the local node's database to `out`.
+pub async fn backup(env_config: &EnvConfig, out: &Path) -> anyhow::Result<()> {
+    if out.exists() {
+        bail!("backup target {out:?} already exists");
+    }
+
+    tls::init_tls_ring();
+
+    let hql = start_hiqlite(env_config).await?;
+
+    backup_repo::snapshot_into(&hql, path_str(out)?).await?;
+
+    info!(?out, "backup written");
+
+    hql.shutdown().await?;
+
+    Ok(())
+}
+
+/// Rehydrate a fresh node from the backup at `input`.
+///
+/// Before anything is written, the configured secrets backend must be able to
+/// decrypt the backup's master key version and all of its Data Encryption Keys.
+///
+/// The backup is restored into the database file of the stopped node, on a single connection.
+/// That bypasses replication, so only a single-node cluster can be restored, nodes are added afterwards.
+pub async fn restore(env_config: &EnvConfig, input: &Path) -> anyhow::Result<()> {
+    if !input.is_file() {
+        bail!("backup {input:?} not found");
+    }
+
+    if hiqlite_node_config(env_config).nodes.len() > 1 {
+        bail!("restore must run on a single-node cluster");
+    }
+
+    tls::init_tls_ring();
+
+    let secrets = build_secrets(env_config)?;
+
+    // The node creates and migrates its database, which is then restored while the node is stopped
+    let hql = start_hiqlite(env_config).await?;
+    let initialized = crypto_repo::load_cr_master_version(&hql).await?.is_some();
+    hql.shutdown().await?;
+
+    if initialized {
+        bail!("refusing to restore into an already initialized node");
+    }
+
+    {
+        let backup_db = SqlitePool::new(Storage::File(input.to_path_buf()), 1);
+        let master_version = crypto_repo::load_cr_master_version(&backup_db)
+            .await?
+            .ok_or_else(|| anyhow!("backup contains no master key version"))?;
+
+        let decrypted_master = encryption::decrypt_master(master_version, secrets.as_ref())
+            .await
+            .map_err(|err| {
+                anyhow!("backup master key can't be fetched from secrets backend: {err}")
+            })?;
+
+        gen_prop_deks(&backup_db, &decrypted_master, IsLeaderDb(false))
+            .await
+            .map_err(|err| anyhow!("backup DEKs can't be decrypted: {err}"))?;
+    }
+
+    let local_db = SqlitePool::new(Storage::File(hiqlite_db_path(env_config)), 1);
+    backup_repo::restore_from(&local_db, path_str(input)?).await?;
+
+    info!(?input, "backup restored");
+
+    Ok(())
+}
+
+fn path_str(path: &Path) -> anyhow::Result<&str> {
+    path.to_str()
+        .ok_or_else(|| anyhow!("path {path:?} is not valid UTF-8"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{SocketAddr, TcpListener},
+        path::PathBuf,
+    };
+
+    use authly_common::id::ServiceId;
+    use authly_domain::{ctx::GetDb, repo::service_repo};
+    use hexhex::hex_literal;
+
+    use crate::{initialize, Init};
+
+    use super::*;
+
+    const TESTSERVICE: ServiceId =
+        ServiceId::from_raw_array(hex_literal!("f3e799137c034e1eb4cd3e4f65705932"));
+
+    /// The configuration of a single-node cluster rooted at `dir`, listening on unused ports
+    fn node_env_config(dir: PathBuf) -> EnvConfig {
+        // both listeners are held until both ports are picked, so the ports differ
+        let listeners = [(); 2].map(|_| TcpListener::bind("127.0.0.1:0").unwrap());
+        let [api_addr, raft_addr]: [SocketAddr; 2] = listeners
+            .each_ref()
+            .map(|listener| listener.local_addr().unwrap());
+
+        let env_config = EnvConfig {
+            data_dir: dir.join("data"),
+            etc_dir: dir.join("etc"),
+            document_path: vec![],
+            cluster_api_nodes: Some(vec![api_addr]),
+            cluster_raft_nodes: Some(vec![raft_addr]),
+            danger_disable_encryption: true,
+            i_understand_this_is_insecure: true,
+            shutdown_drain_timeout_ms: 0,
+            ..Default::default()
+        };
+
+        tls::issue_cluster_key("localhost", env_config.cluster_tls_path()).unwrap();
+
+        env_config
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_backup_and_restore_commands() {
+        let dir = std::env::temp_dir().join(format!("authly-backup-{}", ServiceId::random()));
+        let backup_path = dir.join("backup.db");
+
+        let source = EnvConfig {
+            document_path: vec![PathBuf::from("../../examples/demo")],
+            ..node_env_config(dir.join("source"))
+        };
+        let Init {
+            ctx,
+            env_config: source,
+            ..
+        } = initialize(source).await.unwrap();
+        ctx.hql.shutdown().await.unwrap();
+
+        backup(&source, &backup_path).await.unwrap();
+
+        let target = node_env_config(dir.join("target"));
+        restore(&target, &backup_path).await.unwrap();
+
+        // a second restore is refused
+        assert!(restore(&target, &backup_path).await.is_err());
+
+        // the restored node starts with the state of the source node
+        let Init { ctx, .. } = initialize(target).await.unwrap();
+        let label = service_repo::find_service_label_by_eid(ctx.get_db(), TESTSERVICE)
+            .await
+            .unwrap();
+        ctx.hql.shutdown().await.unwrap();
+
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(label.as_deref(), Some("testservice"));
+    }
+}
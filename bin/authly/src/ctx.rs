@@ -1,20 +1,34 @@
 //! trait implementations for AuthlyCtx
 
-use std::{fs, sync::Arc};
+use std::sync::Arc;
 
 use authly_common::id::{PersonaId, ServiceId};
 use authly_domain::{
+    audit::AuditQueue,
     builtins::Builtins,
-    bus::{service_events::ServiceEventDispatcher, BusError, ClusterMessage},
+    bus::{
+        entity_events::EntityEventNotifier, service_events::ServiceEventDispatcher, BusError,
+        ClusterMessage,
+    },
     cert::{client_cert, CertificateParamsExt},
+    clock::Clock,
+    clock_skew::ClockSkewMonitor,
     ctx::{
-        ClusterBus, Directories, GetBuiltins, GetDb, GetDecryptedDeks, GetHttpClient, GetInstance,
+        ClusterBus, Directories, EntityEventBus, GetAuditQueue, GetBuiltins, GetClock,
+        GetClockSkew, GetDb, GetDecryptedDeks, GetHttpClient, GetIdGenerator, GetInstance,
+        GetMetadataCache, GetPolicyEngineCache, GetSessionCache, GetSettings, GetStats,
         HostsConfig, KubernetesConfig, LoadInstance, RedistributeCertificates, ServiceBus,
         SetInstance, WebAuthn,
     },
     directory::PersonaDirectory,
     encryption::DecryptedDeks,
+    id_generator::IdGenerator,
     instance::AuthlyInstance,
+    metadata_cache::ServiceMetadataCache,
+    policy_cache::PolicyEngineCache,
+    session_cache::SessionCache,
+    settings::Settings,
+    stats::{AuthlyStats, RaftRole},
     webauthn::{
         PasskeyAuthentication, PasskeyRegistration, Webauthn, WebauthnBuilder, WebauthnError,
     },
@@ -22,13 +36,16 @@ use authly_domain::{
 use authly_hiqlite::HiqliteClient;
 use http::Uri;
 use indexmap::IndexMap;
+use openraft::ServerState;
 use reqwest::Url;
 use serde::{de::DeserializeOwned, Serialize};
 use time::Duration;
 use tracing::error;
 use uuid::Uuid;
 
-use crate::{platform::CertificateDistributionPlatform, AuthlyCtx, CacheEntry};
+use crate::{
+    etc_export::write_atomic, platform::CertificateDistributionPlatform, AuthlyCtx, CacheEntry,
+};
 
 impl GetDb for AuthlyCtx {
     type Db = HiqliteClient;
@@ -68,6 +85,12 @@ impl SetInstance for AuthlyCtx {
     }
 }
 
+impl GetSettings for AuthlyCtx {
+    fn get_settings(&self) -> arc_swap::Guard<Arc<Settings>> {
+        self.settings.load()
+    }
+}
+
 impl GetDecryptedDeks for AuthlyCtx {
     fn get_decrypted_deks(&self) -> arc_swap::Guard<Arc<DecryptedDeks>> {
         self.deks.load()
@@ -101,6 +124,36 @@ impl ServiceBus for AuthlyCtx {
     }
 }
 
+impl GetSessionCache for AuthlyCtx {
+    fn get_session_cache(&self) -> &dyn SessionCache {
+        &self.session_cache
+    }
+}
+
+impl GetMetadataCache for AuthlyCtx {
+    fn get_metadata_cache(&self) -> &ServiceMetadataCache {
+        &self.metadata_cache
+    }
+}
+
+impl GetPolicyEngineCache for AuthlyCtx {
+    fn get_policy_engine_cache(&self) -> &PolicyEngineCache {
+        &self.policy_engine_cache
+    }
+}
+
+impl EntityEventBus for AuthlyCtx {
+    fn entity_event_notifier(&self) -> &EntityEventNotifier {
+        &self.entity_event_notifier
+    }
+}
+
+impl GetAuditQueue for AuthlyCtx {
+    fn get_audit_queue(&self) -> &AuditQueue {
+        &self.audit_queue
+    }
+}
+
 impl RedistributeCertificates for AuthlyCtx {
     async fn redistribute_certificates_if_leader(&self) {
         crate::platform::redistribute_certificates(self).await;
@@ -114,11 +167,14 @@ impl Directories for AuthlyCtx {
 
     fn handle_service_tls_reexport_to_file(&self, service_ids: Vec<ServiceId>) {
         if self.export_tls_to_etc {
-            for svc_eid in service_ids {
-                if let Err(err) = export_service_identity(svc_eid, self) {
-                    error!(?err, ?svc_eid, "unable to export identity");
+            let ctx = self.clone();
+            tokio::spawn(async move {
+                for svc_eid in service_ids {
+                    if let Err(err) = export_service_identity(svc_eid, &ctx).await {
+                        error!(?err, ?svc_eid, "unable to export identity");
+                    }
                 }
-            }
+            });
         }
     }
 }
@@ -132,6 +188,7 @@ impl HostsConfig for AuthlyCtx {
         matches!(
             self.state.cert_distribution_platform,
             CertificateDistributionPlatform::KubernetesConfigMap
+                | CertificateDistributionPlatform::KubernetesSecret { .. }
         )
     }
 }
@@ -142,6 +199,52 @@ impl KubernetesConfig for AuthlyCtx {
     }
 }
 
+impl GetClock for AuthlyCtx {
+    fn get_clock(&self) -> &dyn Clock {
+        self.state.clock.as_ref()
+    }
+}
+
+impl GetIdGenerator for AuthlyCtx {
+    fn get_id_generator(&self) -> &dyn IdGenerator {
+        self.state.id_generator.as_ref()
+    }
+}
+
+impl GetClockSkew for AuthlyCtx {
+    fn get_clock_skew(&self) -> &ClockSkewMonitor {
+        &self.state.clock_skew
+    }
+}
+
+impl GetStats for AuthlyCtx {
+    fn get_stats(&self) -> &AuthlyStats {
+        &self.state.stats
+    }
+
+    async fn raft_role(&self) -> RaftRole {
+        match self.metrics_db().await.state {
+            ServerState::Leader => RaftRole::Leader,
+            ServerState::Follower => RaftRole::Follower,
+            ServerState::Candidate => RaftRole::Candidate,
+            ServerState::Learner => RaftRole::Learner,
+            ServerState::Shutdown => RaftRole::Shutdown,
+        }
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.hql.is_read_only()
+    }
+
+    fn is_insecure_mode(&self) -> bool {
+        self.state.secrets.is_insecure()
+    }
+
+    fn slow_query_count(&self) -> u64 {
+        self.hql.slow_query_log().count()
+    }
+}
+
 /// WebAuthn caching uses the CBOR serialization format,
 /// because it's known to work well and supported upstream (webauthn-rs).
 /// postcard/bincode does not work.
@@ -279,18 +382,19 @@ impl WebAuthn for AuthlyCtx {
     }
 }
 
-fn export_service_identity(svc_eid: ServiceId, ctx: &AuthlyCtx) -> anyhow::Result<()> {
+async fn export_service_identity(svc_eid: ServiceId, ctx: &AuthlyCtx) -> anyhow::Result<()> {
     let pem = ctx
         .get_instance()
         .sign_with_local_ca(
             client_cert("service", svc_eid, time::Duration::days(7)).with_new_key_pair(),
         )
+        .await?
         .certificate_and_key_pem();
 
-    let path = ctx.etc_dir.join(format!("service/{svc_eid}/identity.pem"));
-    fs::create_dir_all(path.parent().unwrap())?;
-
-    std::fs::write(path, pem)?;
+    write_atomic(
+        &ctx.etc_layout.service_identity_path(svc_eid),
+        pem.as_bytes(),
+    )?;
 
     Ok(())
 }
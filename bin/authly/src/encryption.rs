@@ -74,7 +74,7 @@ async fn gen_new_master(secrets: &dyn AuthlySecrets) -> anyhow::Result<Decrypted
     })
 }
 
-async fn decrypt_master(
+pub(crate) async fn decrypt_master(
     encrypted: MasterVersion,
     secrets: &dyn AuthlySecrets,
 ) -> anyhow::Result<DecryptedMaster> {
@@ -1,12 +1,19 @@
-use std::{net::SocketAddr, path::PathBuf};
+use std::{net::SocketAddr, path::PathBuf, time::Duration};
 
-use authly_domain::serde_util::Hex;
+use authly_db::slow_query::SlowQueryLog;
+use authly_domain::{
+    id_generator::IdStrategy,
+    serde_util::Hex,
+    tls::{AlpnProtocol, TlsPolicy, TlsVersion},
+};
 use figment::{
     providers::{Env, Serialized},
     Figment,
 };
 use serde::{Deserialize, Serialize};
 
+use crate::platform::K8sCaDistribution;
+
 /// Configuration values always read from the environment.
 ///
 /// These values are closely tied to the platform Authly runs on,
@@ -25,27 +32,100 @@ pub struct EnvConfig {
     /// The port on which to run the API/web server
     pub server_port: u16,
 
+    /// The number of requests the API/web server processes concurrently, excess requests are rejected
+    pub max_concurrent_requests: usize,
+
+    /// The number of TLS connections the API/web server admits, further connections are closed before the TLS handshake
+    pub max_connections: usize,
+
+    /// The number of requests the API/web server processes concurrently on one connection, excess requests are rejected
+    pub max_requests_per_connection: usize,
+
+    /// The oldest TLS version accepted by the API/web server
+    pub tls_min_version: TlsVersion,
+
+    /// TLS cipher suites accepted by the API/web server, all supported suites when empty
+    pub tls_cipher_suites: Vec<String>,
+
+    /// TLS key exchange groups accepted by the API/web server, all supported groups when empty
+    pub tls_named_groups: Vec<String>,
+
+    /// Protocols offered through ALPN by the API/web server, in order of preference
+    pub tls_alpn_protocols: Vec<AlpnProtocol>,
+
     /// A list of paths to scan for documents during startup.
     pub document_path: Vec<PathBuf>,
 
+    /// Reject documents in the document path with keys that are not part of the document schema
+    pub document_strict: bool,
+
     /// Configuration directory
     pub etc_dir: PathBuf,
 
+    /// Directory of the exported CA certificates, relative to `etc_dir`
+    pub etc_certs_dir: PathBuf,
+
+    /// Directory of the exported service identities, relative to `etc_dir`
+    pub etc_service_dir: PathBuf,
+
     /// Database directory
     pub data_dir: PathBuf,
 
+    /// How the IDs of new entities and objects are generated
+    pub id_strategy: IdStrategy,
+
+    /// How many milliseconds shutdown may take, draining in-flight requests and leaving the cluster.
+    /// Authly terminates when the time is up, so this should be shorter than the grace period of the orchestrator.
+    pub shutdown_drain_timeout_ms: u64,
+
+    /// Log database statements that take longer than this many milliseconds
+    pub slow_query_threshold_ms: Option<u64>,
+
+    /// How many milliseconds the clock of the node may drift from the other nodes of the cluster, `0` disables the check
+    pub max_clock_drift_ms: u64,
+
     /// OpenBao URL for master encryption key storage
     pub bao_url: Option<String>,
 
     /// OpenBao token support for legacy setups
     pub bao_token: Option<String>,
 
+    /// OpenBao URLs to fail over to, in order, when `bao_url` is unavailable
+    pub bao_fallback_urls: Vec<String>,
+
+    /// Path to a PKCS#11 module. When set, the instance key is held in the HSM.
+    pub pkcs11_module: Option<PathBuf>,
+
+    /// Label of the PKCS#11 token holding the instance key, the first token is used when unset
+    pub pkcs11_token_label: Option<String>,
+
+    /// User PIN of the PKCS#11 token
+    pub pkcs11_pin: Option<String>,
+
+    /// Label of the instance key pair in the PKCS#11 token
+    pub pkcs11_key_label: String,
+
+    /// ID or ARN of an AWS KMS key. When set, the instance key is held in KMS.
+    pub aws_kms_key_id: Option<String>,
+
+    /// ID or ARN of an AWS KMS key for signing access tokens
+    pub aws_kms_token_key_id: Option<String>,
+
+    /// AWS region of the KMS keys
+    pub aws_region: Option<String>,
+
+    /// KMS endpoint URL, overriding the regional endpoint
+    pub aws_kms_endpoint: Option<String>,
+
     pub cluster_node_id: Option<u64>,
     pub cluster_api_nodes: Option<Vec<SocketAddr>>,
     pub cluster_raft_nodes: Option<Vec<SocketAddr>>,
     pub cluster_raft_secret: String,
     pub cluster_api_secret: String,
 
+    /// Verify the TLS certificates of other cluster nodes
+    pub cluster_tls_verify: bool,
+
     pub k8s: bool,
     pub k8s_namespace: String,
     pub k8s_statefulset: Option<String>,
@@ -54,11 +134,27 @@ pub struct EnvConfig {
     pub k8s_auth_hostname: Option<String>,
     pub k8s_auth_server_port: Option<u16>,
 
+    /// Check that the Kubernetes service accounts of services exist, after documents are loaded
+    pub k8s_verify_service_accounts: bool,
+
+    /// Whether the CA bundle is distributed in a ConfigMap or a Secret
+    pub k8s_ca_distribution: K8sCaDistribution,
+
+    /// The namespaces the CA bundle Secret is written to
+    pub k8s_ca_namespaces: Vec<String>,
+
     /// Whether to export certificates and identities to AUTHLY_ETC_DIR
     pub export_tls_to_etc: bool,
 
     /// Disable at-rest encryption by using a constant secret backend
     pub danger_disable_encryption: bool,
+
+    /// Acknowledgment that `danger_disable_encryption` makes Authly insecure.
+    /// Authly refuses to start with encryption disabled unless this is also set.
+    ///
+    /// Read from the unprefixed `I_UNDERSTAND_THIS_IS_INSECURE` environment variable,
+    /// so that it can't be set by accident along with the other `AUTHLY_` variables.
+    pub i_understand_this_is_insecure: bool,
 }
 
 const NULL_ID: [u8; 32] = [0; 32];
@@ -66,7 +162,8 @@ const NULL_ID: [u8; 32] = [0; 32];
 impl EnvConfig {
     pub fn load() -> Self {
         let cfg: Self = Figment::from(Serialized::defaults(Self::default()))
-            .merge(Env::prefixed("AUTHLY_"))
+            .merge(Env::prefixed("AUTHLY_").ignore(&["I_UNDERSTAND_THIS_IS_INSECURE"]))
+            .merge(Env::raw().only(&["I_UNDERSTAND_THIS_IS_INSECURE"]))
             .extract()
             .unwrap();
 
@@ -80,6 +177,35 @@ impl EnvConfig {
     pub fn cluster_tls_path(&self) -> ClusterTlsPath {
         ClusterTlsPath(self.etc_dir.join("cluster"))
     }
+
+    pub fn slow_query_log(&self) -> SlowQueryLog {
+        SlowQueryLog::new(self.slow_query_threshold_ms.map(Duration::from_millis))
+    }
+
+    pub fn max_clock_drift(&self) -> Option<Duration> {
+        match self.max_clock_drift_ms {
+            0 => None,
+            ms => Some(Duration::from_millis(ms)),
+        }
+    }
+
+    pub fn shutdown_drain_timeout(&self) -> Duration {
+        Duration::from_millis(self.shutdown_drain_timeout_ms)
+    }
+
+    /// The delay before the database node shuts down, a part of the shutdown drain timeout
+    pub fn hiqlite_shutdown_delay_millis(&self) -> u32 {
+        (self.shutdown_drain_timeout_ms / 5).min(5000) as u32
+    }
+
+    pub fn tls_policy(&self) -> TlsPolicy {
+        TlsPolicy {
+            min_version: self.tls_min_version,
+            cipher_suites: self.tls_cipher_suites.clone(),
+            named_groups: self.tls_named_groups.clone(),
+            alpn_protocols: self.tls_alpn_protocols.clone(),
+        }
+    }
 }
 
 impl Default for EnvConfig {
@@ -89,20 +215,46 @@ impl Default for EnvConfig {
 
             hostname: "authly".to_string(),
             server_port: 443,
+            max_concurrent_requests: 1024,
+            max_connections: 4096,
+            max_requests_per_connection: 128,
+            tls_min_version: TlsVersion::Tls12,
+            tls_cipher_suites: vec![],
+            tls_named_groups: vec![],
+            tls_alpn_protocols: AlpnProtocol::DEFAULT.to_vec(),
 
             document_path: vec![PathBuf::from("/etc/authly/documents")],
+            document_strict: true,
 
             etc_dir: PathBuf::from("/etc/authly"),
+            etc_certs_dir: PathBuf::from("certs"),
+            etc_service_dir: PathBuf::from("service"),
             data_dir: PathBuf::from("/var/lib/authly/data"),
+            id_strategy: IdStrategy::Random,
+            shutdown_drain_timeout_ms: 25_000,
+            slow_query_threshold_ms: None,
+            max_clock_drift_ms: 30_000,
 
             bao_url: None,
             bao_token: None,
+            bao_fallback_urls: vec![],
+
+            pkcs11_module: None,
+            pkcs11_token_label: None,
+            pkcs11_pin: None,
+            pkcs11_key_label: "authly-instance".to_string(),
+
+            aws_kms_key_id: None,
+            aws_kms_token_key_id: None,
+            aws_region: None,
+            aws_kms_endpoint: None,
 
             cluster_node_id: None,
             cluster_raft_nodes: None,
             cluster_api_nodes: None,
             cluster_raft_secret: "superultramegasecret1".to_string(),
             cluster_api_secret: "superultramegasecret2".to_string(),
+            cluster_tls_verify: false,
 
             k8s: false,
             k8s_namespace: "default".to_string(),
@@ -111,9 +263,13 @@ impl Default for EnvConfig {
             k8s_replicas: 1,
             k8s_auth_hostname: None,
             k8s_auth_server_port: None,
+            k8s_verify_service_accounts: false,
+            k8s_ca_distribution: K8sCaDistribution::ConfigMap,
+            k8s_ca_namespaces: vec![],
 
             export_tls_to_etc: false,
             danger_disable_encryption: false,
+            i_understand_this_is_insecure: false,
         }
     }
 }
@@ -0,0 +1,142 @@
+//! Export of certificates and identities to files in the etc directory.
+//!
+//! Files are replaced atomically, so that a reader never sees a partially written certificate during a rotation.
+
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use authly_common::id::ServiceId;
+use rand::Rng;
+
+use crate::env_config::EnvConfig;
+
+/// Where exported files are placed
+#[derive(Clone, Debug)]
+pub struct EtcLayout {
+    /// Directory of the CA certificates
+    pub certs_dir: PathBuf,
+    /// Directory containing one subdirectory per service, holding its identity
+    pub service_dir: PathBuf,
+}
+
+impl EtcLayout {
+    /// The configured layout, relative paths are resolved against `AUTHLY_ETC_DIR`
+    pub fn from_env_config(env_config: &EnvConfig) -> Self {
+        Self {
+            certs_dir: env_config.etc_dir.join(&env_config.etc_certs_dir),
+            service_dir: env_config.etc_dir.join(&env_config.etc_service_dir),
+        }
+    }
+
+    pub fn root_ca_path(&self) -> PathBuf {
+        self.certs_dir.join("root.crt")
+    }
+
+    pub fn local_ca_path(&self) -> PathBuf {
+        self.certs_dir.join("local.crt")
+    }
+
+    pub fn service_identity_path(&self, svc_eid: ServiceId) -> PathBuf {
+        self.service_dir.join(format!("{svc_eid}/identity.pem"))
+    }
+}
+
+/// Replace the contents of a file atomically.
+///
+/// The contents are written to a temporary file in the same directory, which is then renamed over the target.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let dir = path
+        .parent()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path has no parent"))?;
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path has no file name"))?;
+
+    fs::create_dir_all(dir)?;
+
+    let tmp_path = dir.join(format!(
+        ".{}.{:016x}.tmp",
+        file_name.to_string_lossy(),
+        rand::thread_rng().gen::<u64>()
+    ));
+
+    let result = (|| {
+        let mut file = fs::File::create(&tmp_path)?;
+        file.write_all(contents)?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, path)
+    })();
+
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        thread,
+    };
+
+    use super::*;
+
+    /// A certificate and key pair where every line carries the generation it belongs to
+    fn identity_pem(generation: usize) -> String {
+        let cert = format!("cert {generation}\n").repeat(2000);
+        let key = format!("key {generation}\n").repeat(1000);
+        format!("{cert}{key}")
+    }
+
+    #[test]
+    fn test_concurrent_reader_sees_whole_identity() {
+        let dir = std::env::temp_dir().join(format!(
+            "authly-etc-export-{:016x}",
+            rand::thread_rng().gen::<u64>()
+        ));
+        let path = dir.join("service/identity.pem");
+        write_atomic(&path, identity_pem(0).as_bytes()).unwrap();
+
+        let done = Arc::new(AtomicBool::new(false));
+        let reader = thread::spawn({
+            let path = path.clone();
+            let done = done.clone();
+            move || {
+                let mut reads = 0;
+                loop {
+                    let finished = done.load(Ordering::SeqCst);
+                    let pem = fs::read_to_string(&path).unwrap();
+                    let generation = pem.lines().next().unwrap().strip_prefix("cert ").unwrap();
+                    let generation: usize = generation.parse().unwrap();
+
+                    assert_eq!(pem, identity_pem(generation), "torn read");
+                    reads += 1;
+
+                    if finished {
+                        return reads;
+                    }
+                }
+            }
+        });
+
+        for generation in 1..200 {
+            write_atomic(&path, identity_pem(generation).as_bytes()).unwrap();
+        }
+        done.store(true, Ordering::SeqCst);
+
+        assert!(reader.join().unwrap() > 0);
+
+        // no temporary files are left behind
+        assert_eq!(fs::read_dir(path.parent().unwrap()).unwrap().count(), 1);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
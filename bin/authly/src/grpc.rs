@@ -7,15 +7,21 @@ use authly_connect::{
 };
 use authly_domain::ctx::GetInstance;
 use authly_service::proto::{
-    mandate_submission::AuthlyMandateSubmissionServerImpl, service_server::AuthlyServiceServerImpl,
+    admin_server::AuthlyAdminServerImpl, health_server::AuthlyHealthServerImpl,
+    mandate_submission::AuthlyMandateSubmissionServerImpl, policy_server::AuthlyPolicyServerImpl,
+    service_server::AuthlyServiceServerImpl, token_server::AuthlyTokenServerImpl,
 };
 
 use crate::{tls, AuthlyCtx};
 
 // gRPC entry point
-pub(crate) fn main_service_grpc_router(ctx: AuthlyCtx) -> anyhow::Result<axum::Router> {
+pub(crate) async fn main_service_grpc_router(ctx: AuthlyCtx) -> anyhow::Result<axum::Router> {
     Ok(tonic::service::Routes::default()
         .add_service(AuthlyServiceServerImpl::new_service(ctx.clone()))
+        .add_service(AuthlyHealthServerImpl::new_service(ctx.clone()))
+        .add_service(AuthlyAdminServerImpl::new_service(ctx.clone()))
+        .add_service(AuthlyTokenServerImpl::new_service(ctx.clone()))
+        .add_service(AuthlyPolicyServerImpl::new_service(ctx.clone()))
         .add_service(AuthlyConnectServer::new(AuthlyConnectServerImpl {
             services: HashMap::from([(
                 TunnelSecurity::Secure,
@@ -27,7 +33,8 @@ pub(crate) fn main_service_grpc_router(ctx: AuthlyCtx) -> anyhow::Result<axum::R
                         "authly-connect",
                         &ctx.get_instance(),
                         std::time::Duration::from_secs(365 * 100),
-                    )?,
+                    )
+                    .await?,
                 },
             )]),
             cancel: ctx.shutdown.clone(),
@@ -6,11 +6,12 @@ use std::{
 use anyhow::anyhow;
 use authly_common::id::ServiceId;
 use authly_domain::{
-    cert::{client_cert, server_cert, Cert, CertificateParamsExt},
-    ctx::{GetBuiltins, GetDb, GetInstance},
+    cert::{client_cert, server_cert, CertificateParamsExt},
+    ctx::{GetInstance, GetStats},
     instance::AuthlyInstance,
     remote_addr::{remote_addr_middleware, RemoteAddr},
-    repo::service_repo,
+    service::{authenticate_k8s_service_account, K8sAuthenticationError, K8S_UNVERIFIED_NAMESPACE},
+    tls::{alpn_protocol_ids, AlpnProtocol},
 };
 use axum::{body::Bytes, extract::State, response::IntoResponse, routing::post, Extension};
 use axum_extra::{
@@ -52,7 +53,7 @@ pub async fn spawn_k8s_auth_server(env_config: &EnvConfig, ctx: &AuthlyCtx) -> a
     };
 
     let jwt_verifier = fetch_k8s_jwk_jwt_verifier().await?;
-    let rustls_config_factory = rustls_server_config(env_config, &ctx.get_instance())?;
+    let rustls_config_factory = rustls_server_config(env_config, &ctx.get_instance()).await?;
 
     let server =
         tower_server::Builder::new(SocketAddr::new(Ipv4Addr::new(0, 0, 0, 0).into(), port))
@@ -112,40 +113,49 @@ async fn v0_authenticate_handler(
     bearer_authorization: TypedHeader<Authorization<Bearer>>,
     public_key: Bytes,
 ) -> Result<axum::response::Response, CsrError> {
-    let token_data = state.jwt_verifier.verify(bearer_authorization.token())?;
+    let token_data = state
+        .jwt_verifier
+        .verify(bearer_authorization.token())
+        .inspect_err(|_| {
+            state
+                .ctx
+                .get_stats()
+                .record_k8s_authentication(K8S_UNVERIFIED_NAMESPACE, false)
+        })?;
 
     let kubernetes_io = token_data.claims.kubernetes_io;
     let common_name = kubernetes_io.serviceaccount.name.clone();
-    let eid = service_repo::find_service_eid_by_k8s_local_service_account_name(
-        state.ctx.get_db(),
+    let eid = authenticate_k8s_service_account(
+        &state.ctx,
         &kubernetes_io.namespace,
         &kubernetes_io.serviceaccount.name,
-        state.ctx.get_builtins(),
     )
     .await
-    .map_err(|err| {
-        error!(?err, "failed to look up k8s service account");
-        CsrError::Internal
+    .map_err(|err| match err {
+        K8sAuthenticationError::NotFound => {
+            info!("service account is not registered");
+            CsrError::ServiceAccountNotFound
+        }
+        K8sAuthenticationError::Db(err) => {
+            error!(?err, "failed to look up k8s service account");
+            CsrError::Internal
+        }
     })?;
 
-    let Some(eid) = eid else {
-        info!("service account is not registered");
-        return Err(CsrError::ServiceAccountNotFound);
-    };
-
-    let signed_client_cert = tokio::task::spawn_blocking(move || -> Result<Cert<_>, CsrError> {
-        let service_public_key = SubjectPublicKeyInfo::from_der(&public_key)
-            .map_err(|_err| CsrError::InvalidPublicKey(eid))?;
+    let service_public_key = SubjectPublicKeyInfo::from_der(&public_key)
+        .map_err(|_err| CsrError::InvalidPublicKey(eid))?;
 
-        Ok(state.ctx.get_instance().sign_with_local_ca(
+    let signed_client_cert = state
+        .ctx
+        .get_instance()
+        .sign_with_local_ca(
             client_cert(&common_name, eid, CERT_VALIDITY_PERIOD).with_owned_key(service_public_key),
-        ))
-    })
-    .await
-    .map_err(|err| {
-        error!(?err, "signer join error");
-        CsrError::Internal
-    })??;
+        )
+        .await
+        .map_err(|err| {
+            error!(?err, "unable to sign client certificate");
+            CsrError::Internal
+        })?;
 
     info!(?eid, ?remote_addr, "authenticated");
 
@@ -207,6 +217,7 @@ impl JwtVerifier {
         })
     }
 
+    #[tracing::instrument(skip_all)]
     fn verify(&self, token: &str) -> Result<TokenData<claims::KubernetesJwtClaims>, CsrError> {
         let token_data = jsonwebtoken::decode(token, &self.decoding_key, &self.validation)
             .map_err(|err| {
@@ -218,6 +229,7 @@ impl JwtVerifier {
     }
 }
 
+#[tracing::instrument]
 async fn fetch_k8s_jwk_jwt_verifier() -> anyhow::Result<JwtVerifier> {
     let service_account_token = std::fs::read_to_string(K8S_SA_TOKENFILE)?;
     let k8s_ca = std::fs::read(K8S_SA_CERTFILE)?;
@@ -238,7 +250,7 @@ async fn fetch_k8s_jwk_jwt_verifier() -> anyhow::Result<JwtVerifier> {
     JwtVerifier::from_jwk_set(jwk_set)
 }
 
-fn rustls_server_config(
+async fn rustls_server_config(
     env_config: &EnvConfig,
     instance: &AuthlyInstance,
 ) -> anyhow::Result<ServerConfig> {
@@ -249,14 +261,16 @@ fn rustls_server_config(
         .as_deref()
         .unwrap_or(&env_config.hostname);
 
-    let server_cert = instance.sign_with_local_ca(
-        server_cert(
-            "authly",
-            vec![hostname.to_string()],
-            time::Duration::days(365),
-        )?
-        .with_new_key_pair(),
-    );
+    let server_cert = instance
+        .sign_with_local_ca(
+            server_cert(
+                "authly",
+                vec![hostname.to_string()],
+                time::Duration::days(365),
+            )?
+            .with_new_key_pair(),
+        )
+        .await?;
 
     let server_private_key_der = PrivateKeyDer::try_from(server_cert.key.serialize_der())
         .map_err(|err| anyhow!("k8s auth server private key: {err}"))?;
@@ -265,7 +279,8 @@ fn rustls_server_config(
         .with_no_client_auth()
         .with_single_cert(vec![server_cert.der.clone()], server_private_key_der)?;
 
-    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec(), b"http/1.0".to_vec()];
+    config.alpn_protocols =
+        alpn_protocol_ids(&[AlpnProtocol::H2, AlpnProtocol::Http11, AlpnProtocol::Http10]);
 
     Ok(config)
 }
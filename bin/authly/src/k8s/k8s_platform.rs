@@ -1,5 +1,10 @@
-use authly_domain::ctx::GetInstance;
-use k8s_openapi::api::core::v1::ConfigMap;
+use std::collections::BTreeMap;
+
+use authly_domain::{ctx::GetInstance, instance::AuthlyInstance};
+use k8s_openapi::{
+    api::core::v1::{ConfigMap, Secret},
+    ByteString,
+};
 use kube::{
     api::{ObjectMeta, Patch, PatchParams},
     Api, Client,
@@ -7,6 +12,9 @@ use kube::{
 
 use crate::AuthlyCtx;
 
+/// The name of the ConfigMap or Secret holding the CA bundle
+const CA_BUNDLE_NAME: &str = "authly-certs.crt";
+
 pub async fn k8s_redistribute_certificates(ctx: AuthlyCtx) {
     let client = Client::try_default().await.unwrap();
 
@@ -15,43 +23,55 @@ pub async fn k8s_redistribute_certificates(ctx: AuthlyCtx) {
     }
 }
 
+/// Write the CA bundle to a Secret in each of the target namespaces
+pub async fn k8s_redistribute_certificate_secrets(ctx: &AuthlyCtx, namespaces: &[String]) {
+    let client = Client::try_default().await.unwrap();
+
+    write_ca_secrets(client, namespaces, &ca_bundle(&ctx.get_instance())).await;
+}
+
+/// The PEM encoded CA certificates that clients need for verifying Authly
+fn ca_bundle(instance: &AuthlyInstance) -> BTreeMap<String, String> {
+    [
+        (
+            "root.crt".to_string(),
+            instance.trust_root_ca().certificate_pem(),
+        ),
+        (
+            "local.crt".to_string(),
+            instance.local_ca().certificate_pem(),
+        ),
+    ]
+    .into()
+}
+
+fn ca_bundle_metadata(namespace: &str) -> ObjectMeta {
+    ObjectMeta {
+        name: Some(CA_BUNDLE_NAME.to_string()),
+        namespace: Some(namespace.to_string()),
+        annotations: Some(
+            [(
+                "kubernetes.io/description".to_string(),
+                "CA bundle for verifying Authly".to_string(),
+            )]
+            .into(),
+        ),
+        ..Default::default()
+    }
+}
+
 async fn write_client_configmap(client: Client, ctx: &AuthlyCtx) -> anyhow::Result<()> {
     let configmap_api: Api<ConfigMap> = Api::namespaced(client.clone(), client.default_namespace());
-    let name = "authly-certs.crt";
-    let instance = ctx.get_instance();
     let configmap = ConfigMap {
-        metadata: ObjectMeta {
-            name: Some(name.to_string()),
-            namespace: Some(client.default_namespace().into()),
-            annotations: Some(
-                [(
-                    "kubernetes.io/description".to_string(),
-                    "CA bundle for verifying Authly".to_string(),
-                )]
-                .into(),
-            ),
-            ..Default::default()
-        },
+        metadata: ca_bundle_metadata(client.default_namespace()),
         binary_data: None,
-        data: Some(
-            [
-                (
-                    "root.crt".to_string(),
-                    instance.trust_root_ca().certificate_pem(),
-                ),
-                (
-                    "local.crt".to_string(),
-                    instance.local_ca().certificate_pem(),
-                ),
-            ]
-            .into(),
-        ),
+        data: Some(ca_bundle(&ctx.get_instance())),
         immutable: None,
     };
 
     configmap_api
         .patch(
-            name,
+            CA_BUNDLE_NAME,
             &PatchParams::apply("authly"),
             &Patch::Apply(configmap),
         )
@@ -59,3 +79,142 @@ async fn write_client_configmap(client: Client, ctx: &AuthlyCtx) -> anyhow::Resu
 
     Ok(())
 }
+
+/// Write the CA bundle Secret to each namespace, an empty list means the namespace of the client.
+///
+/// A failing namespace does not prevent writing to the others.
+async fn write_ca_secrets(
+    client: Client,
+    namespaces: &[String],
+    ca_bundle: &BTreeMap<String, String>,
+) {
+    let default_namespaces = [client.default_namespace().to_string()];
+    let namespaces = if namespaces.is_empty() {
+        &default_namespaces[..]
+    } else {
+        namespaces
+    };
+
+    for namespace in namespaces {
+        if let Err(err) = write_ca_secret(client.clone(), namespace, ca_bundle).await {
+            tracing::error!(?err, ?namespace, "could not write authly-client Secret");
+        }
+    }
+}
+
+async fn write_ca_secret(
+    client: Client,
+    namespace: &str,
+    ca_bundle: &BTreeMap<String, String>,
+) -> anyhow::Result<()> {
+    let secret_api: Api<Secret> = Api::namespaced(client, namespace);
+    let secret = Secret {
+        metadata: ca_bundle_metadata(namespace),
+        data: Some(
+            ca_bundle
+                .iter()
+                .map(|(key, pem)| (key.clone(), ByteString(pem.as_bytes().to_vec())))
+                .collect(),
+        ),
+        type_: Some("Opaque".to_string()),
+        ..Default::default()
+    };
+
+    secret_api
+        .patch(
+            CA_BUNDLE_NAME,
+            &PatchParams::apply("authly"),
+            &Patch::Apply(secret),
+        )
+        .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use wiremock::{matchers::method, Mock, MockServer, Request, Respond, ResponseTemplate};
+
+    use super::*;
+
+    /// Server-side apply returns the applied object
+    struct EchoApply;
+
+    impl Respond for EchoApply {
+        fn respond(&self, request: &Request) -> ResponseTemplate {
+            ResponseTemplate::new(200).set_body_raw(request.body.clone(), "application/json")
+        }
+    }
+
+    fn bundle(root: &str, local: &str) -> BTreeMap<String, String> {
+        [
+            ("root.crt".to_string(), root.to_string()),
+            ("local.crt".to_string(), local.to_string()),
+        ]
+        .into()
+    }
+
+    /// The latest Secret applied to each namespace
+    async fn applied_secrets(k8s_api: &MockServer) -> BTreeMap<String, Secret> {
+        k8s_api
+            .received_requests()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|request| {
+                assert_eq!(request.method, http::Method::PATCH);
+                let secret: Secret = serde_json::from_slice(&request.body).unwrap();
+                let namespace = secret.metadata.namespace.clone().unwrap();
+                assert_eq!(
+                    request.url.path(),
+                    format!("/api/v1/namespaces/{namespace}/secrets/{CA_BUNDLE_NAME}")
+                );
+                (namespace, secret)
+            })
+            .collect()
+    }
+
+    fn secret_data(secret: &Secret, key: &str) -> String {
+        String::from_utf8(secret.data.as_ref().unwrap()[key].0.clone()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_ca_secret_distribution() {
+        let k8s_api = MockServer::start().await;
+        Mock::given(method("PATCH"))
+            .respond_with(EchoApply)
+            .mount(&k8s_api)
+            .await;
+
+        let client = Client::try_from(kube::Config::new(k8s_api.uri().parse().unwrap())).unwrap();
+        let namespaces = ["apps".to_string(), "jobs".to_string()];
+
+        write_ca_secrets(client.clone(), &namespaces, &bundle("root-1", "local-1")).await;
+
+        let secrets = applied_secrets(&k8s_api).await;
+        assert_eq!(secrets.keys().collect::<Vec<_>>(), ["apps", "jobs"]);
+        for secret in secrets.values() {
+            assert_eq!(secret_data(secret, "root.crt"), "root-1");
+            assert_eq!(secret_data(secret, "local.crt"), "local-1");
+        }
+
+        // rotation of the local CA
+        write_ca_secrets(client.clone(), &namespaces, &bundle("root-1", "local-2")).await;
+
+        let secrets = applied_secrets(&k8s_api).await;
+        for secret in secrets.values() {
+            assert_eq!(secret_data(secret, "local.crt"), "local-2");
+        }
+
+        // defaults to the namespace of the client
+        k8s_api.reset().await;
+        Mock::given(method("PATCH"))
+            .respond_with(EchoApply)
+            .mount(&k8s_api)
+            .await;
+        write_ca_secrets(client, &[], &bundle("root-1", "local-2")).await;
+
+        let secrets = applied_secrets(&k8s_api).await;
+        assert_eq!(secrets.keys().collect::<Vec<_>>(), ["default"]);
+    }
+}
@@ -0,0 +1,42 @@
+use authly_domain::{
+    ctx::{GetBuiltins, GetDb},
+    repo::service_repo,
+};
+use k8s_openapi::api::core::v1::ServiceAccount;
+use kube::{Api, Client};
+use tracing::{info, warn};
+
+use crate::AuthlyCtx;
+
+/// Check that the local Kubernetes service accounts of services exist, logging the ones that don't.
+///
+/// Services can't authenticate through the k8s auth server with an account that doesn't exist,
+/// which is easier to notice here than from a pod failing to start.
+pub async fn verify_service_accounts(ctx: &AuthlyCtx) {
+    if let Err(err) = try_verify_service_accounts(ctx).await {
+        warn!(?err, "could not verify kubernetes service accounts");
+    }
+}
+
+async fn try_verify_service_accounts(ctx: &AuthlyCtx) -> anyhow::Result<()> {
+    let client = Client::try_default().await?;
+    let accounts =
+        service_repo::list_local_k8s_service_accounts(ctx.get_db(), ctx.get_builtins()).await?;
+
+    for (svc_eid, namespace, name) in accounts {
+        let api: Api<ServiceAccount> = Api::namespaced(client.clone(), &namespace);
+
+        match api.get_opt(&name).await? {
+            Some(_) => info!(
+                ?svc_eid,
+                namespace, name, "kubernetes service account found"
+            ),
+            None => warn!(
+                ?svc_eid,
+                namespace, name, "kubernetes service account does not exist"
+            ),
+        }
+    }
+
+    Ok(())
+}
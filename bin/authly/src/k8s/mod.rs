@@ -1,2 +1,3 @@
 pub mod k8s_auth_server;
 pub mod k8s_platform;
+pub mod k8s_service_accounts;
@@ -9,52 +9,73 @@ use std::{
     time::Duration,
 };
 
-use anyhow::anyhow;
+use anyhow::{anyhow, Context};
 use arc_swap::ArcSwap;
 use authly_domain::{
+    audit::{AuditAppender, AuditQueue},
+    aws_kms::{AwsCredentials, AwsKmsSigner},
     builtins::Builtins,
-    bus::{service_events::ServiceEventDispatcher, ServiceMessage},
-    ctx::{GetDb, ServiceBus},
+    bus::{
+        admin_events::{record_admin_event, AdminEventKind},
+        entity_events::EntityEventNotifier,
+        service_events::ServiceEventDispatcher,
+    },
+    clock::{Clock, SystemClock},
+    clock_skew::{self, ClockSkewMonitor},
+    ctx::{GetDb, GetSettings, ServiceBus},
     directory::{load_persona_directories, PersonaDirectory},
     encryption::DecryptedDeks,
-    instance::AuthlyInstance,
+    id_generator::IdGenerator,
+    instance::{AuthlyInstance, InstanceKeySource},
+    load_shed::{self, ConnectionLimit, RequestLimit},
+    metadata_cache::ServiceMetadataCache,
     migration::Migrations,
-    remote_addr::remote_addr_middleware,
-    repo::{crypto_repo, init_repo, settings_repo},
+    policy_cache::PolicyEngineCache,
+    readiness::{self, Readiness},
+    repo::{crypto_repo, init_repo, settings_repo, webauthn_repo},
+    request_id::request_id_middleware,
+    session_cache::LruSessionCache,
     settings::Settings,
+    stats::AuthlyStats,
+    token_signing::TokenSigningKey,
     webauthn::Webauthn,
     IsLeaderDb,
 };
-use authly_hiqlite::HiqliteClient;
-use axum::{response::IntoResponse, Json};
+use authly_hiqlite::{HiqliteClient, Leadership};
+use authly_secrets::AuthlySecrets;
 pub use env_config::EnvConfig;
+use etc_export::EtcLayout;
 use hiqlite::cache_idx::CacheIndex;
 use http::Uri;
 use indexmap::IndexMap;
 use load_docs::load_cfg_documents;
 use openraft::RaftMetrics;
-use platform::CertificateDistributionPlatform;
+use platform::{CertificateDistributionPlatform, K8sCaDistribution};
 use serde_json::json;
 use tokio_util::sync::CancellationToken;
-use tower_server::Scheme;
-use tracing::info;
+use tracing::{info, warn};
 use util::protocol_router::ProtocolRouter;
 
 // These are public for the integration test crate
+pub mod audit;
+pub mod backup;
 pub mod ctx;
 pub mod encryption;
 pub mod env_config;
 pub mod grpc;
+pub mod pkcs11;
 pub mod platform;
 pub mod tls;
 
 mod cluster_bus;
+mod etc_export;
 mod k8s;
 mod load_docs;
 mod util;
 
 const HIQLITE_API_PORT: u16 = 7855;
 const HIQLITE_RAFT_PORT: u16 = 7856;
+const HIQLITE_DB_FILENAME: &str = "authly.db";
 
 /// Common context for the whole application.
 ///
@@ -87,15 +108,34 @@ struct AuthlyState {
     /// Dynamically updatable settings:
     settings: ArcSwap<Settings>,
     svc_event_dispatcher: ServiceEventDispatcher,
+    entity_event_notifier: EntityEventNotifier,
+    /// Access control decisions waiting to be recorded in the audit log
+    audit_queue: AuditQueue,
+    /// In-memory cache of authenticated sessions
+    session_cache: LruSessionCache,
+    /// In-memory cache of metadata served to services
+    metadata_cache: ServiceMetadataCache,
+    /// In-memory cache of the policy engines of services
+    policy_engine_cache: PolicyEngineCache,
+    /// In-memory statistics counters
+    stats: AuthlyStats,
+    /// Generates the IDs of new entities and objects
+    id_generator: Box<dyn IdGenerator>,
+    /// The source of the current time
+    clock: Box<dyn Clock>,
+    /// The clock offsets of the other nodes of the cluster
+    clock_skew: ClockSkewMonitor,
     /// Data Encryption Keys
     deks: ArcSwap<DecryptedDeks>,
+    /// The backend holding the master encryption key
+    secrets: Arc<dyn AuthlySecrets>,
     persona_directories: ArcSwap<IndexMap<String, PersonaDirectory>>,
     internet_http_client: reqwest::Client,
     webauthn_per_uri: Mutex<HashMap<Uri, Arc<Webauthn>>>,
     /// Signal triggered when the app is shutting down:
     shutdown: CancellationToken,
     cert_distribution_platform: CertificateDistributionPlatform,
-    etc_dir: PathBuf,
+    etc_layout: EtcLayout,
     export_tls_to_etc: bool,
     hostname: String,
     /// The kubernetes namespace the local Authly runs in (if any, default is "default")
@@ -105,10 +145,15 @@ struct AuthlyState {
 pub struct Init {
     ctx: AuthlyCtx,
     env_config: EnvConfig,
+    audit_appender: AuditAppender,
 }
 
 pub async fn serve() -> anyhow::Result<()> {
-    let Init { ctx, env_config } = initialize().await?;
+    let Init {
+        ctx,
+        env_config,
+        mut audit_appender,
+    } = initialize(EnvConfig::load()).await?;
 
     info!(
         "root CA:\n{}",
@@ -121,37 +166,172 @@ pub async fn serve() -> anyhow::Result<()> {
         k8s::k8s_auth_server::spawn_k8s_auth_server(&env_config, &ctx).await?;
     }
 
-    let main_server = tower_server::Builder::new(SocketAddr::new(
-        Ipv4Addr::new(0, 0, 0, 0).into(),
-        env_config.server_port,
-    ))
-    .with_scheme(Scheme::Https)
-    .with_tls_config(
-        tls::main_service_tls_configurer(env_config.hostname.clone(), ctx.clone()).await?,
-    )
-    .with_connection_middleware(remote_addr_middleware)
-    .with_tls_connection_middleware(authly_common::mtls_server::MTLSMiddleware)
-    .with_graceful_shutdown(ctx.shutdown.clone())
-    .bind()
-    .await?;
-
-    tokio::spawn(
-        main_server.serve(
-            ProtocolRouter::default()
-                .with_grpc(grpc::main_service_grpc_router(ctx.clone())?)
-                .or_default(main_service_http_router(ctx.clone()))
-                .into_service(),
-        ),
-    );
+    let request_limit = RequestLimit::new(env_config.max_concurrent_requests)?;
+    let main_router = main_service_router(ctx.clone(), request_limit.clone()).await?;
+    spawn_main_tcp_listener(&env_config, &ctx, main_router).await?;
 
     // spawn service pinger
     {
         let ctx = ctx.clone();
         tokio::spawn(async move {
+            loop {
+                let service_ping = ctx.get_settings().service_ping;
+
+                tokio::select! {
+                    _ = tokio::time::sleep(service_ping.interval) => {
+                        ctx.service_event_dispatcher().ping_all(service_ping.max_missed);
+                    }
+                    _ = ctx.shutdown.cancelled() => {
+                        return;
+                    }
+                }
+            }
+        });
+    }
+
+    // spawn secrets backend health probe
+    {
+        let ctx = ctx.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = tokio::time::sleep(Duration::from_secs(30)) => {
+                        let result = ctx.secrets.health().await;
+                        if let Err(err) = &result {
+                            warn!(?err, "secrets backend is unhealthy");
+                        }
+                        ctx.stats.record_secrets_health(result.is_ok());
+                    }
+                    _ = ctx.shutdown.cancelled() => {
+                        return;
+                    }
+                }
+            }
+        });
+    }
+
+    // spawn quorum watcher, switching to read-only mode while the cluster has lost quorum
+    {
+        let ctx = ctx.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = tokio::time::sleep(Duration::from_secs(1)) => {
+                        ctx.hql.update_read_only().await;
+                    }
+                    _ = ctx.shutdown.cancelled() => {
+                        return;
+                    }
+                }
+            }
+        });
+    }
+
+    // spawn clock check, for detecting clock skew between the nodes
+    {
+        let ctx = ctx.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = tokio::time::sleep(clock_skew::CLOCK_CHECK_INTERVAL) => {
+                        if let Err(err) = clock_skew::broadcast_clock_check(&ctx).await {
+                            warn!(?err, "unable to broadcast clock check");
+                        }
+                    }
+                    _ = ctx.shutdown.cancelled() => {
+                        return;
+                    }
+                }
+            }
+        });
+    }
+
+    // spawn audit appender, recording the queued access control decisions in batches
+    {
+        let ctx = ctx.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    appended = audit_appender.append_next(&ctx) => {
+                        if !appended {
+                            return;
+                        }
+                    }
+                    _ = ctx.shutdown.cancelled() => {
+                        audit_appender.flush(&ctx).await;
+                        return;
+                    }
+                }
+            }
+        });
+    }
+
+    // spawn audit and entity event retention purge
+    {
+        let ctx = ctx.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = tokio::time::sleep(Duration::from_secs(60 * 60)) => {
+                        let is_leader = IsLeaderDb(ctx.hql.is_leader_db().await);
+                        match authly_domain::audit::purge_expired_audit(
+                            &ctx,
+                            is_leader,
+                            time::OffsetDateTime::now_utc(),
+                        )
+                        .await
+                        {
+                            Ok(0) => {}
+                            Ok(count) => info!(count, "purged expired audit records"),
+                            Err(err) => warn!(?err, "unable to purge audit records"),
+                        }
+                        match authly_domain::bus::entity_events::purge_expired_events(
+                            &ctx,
+                            is_leader,
+                            time::OffsetDateTime::now_utc(),
+                        )
+                        .await
+                        {
+                            Ok(0) => {}
+                            Ok(count) => info!(count, "purged expired entity events"),
+                            Err(err) => warn!(?err, "unable to purge entity events"),
+                        }
+                    }
+                    _ = ctx.shutdown.cancelled() => {
+                        return;
+                    }
+                }
+            }
+        });
+    }
+
+    // spawn leadership watcher, recording an admin event when this node becomes the leader.
+    // the leadership is updated by the quorum watcher.
+    {
+        let ctx = ctx.clone();
+        let mut leadership = ctx.hql.subscribe_leadership();
+        tokio::spawn(async move {
+            let mut leader_term = None;
             loop {
                 tokio::select! {
-                    _ = tokio::time::sleep(Duration::from_secs(60 * 5)) => {
-                        ctx.service_event_dispatcher().broadcast_all(ServiceMessage::Ping);
+                    changed = leadership.changed() => {
+                        if changed.is_err() {
+                            return;
+                        }
+                        let Leadership { node_id, term, is_leader } =
+                            *leadership.borrow_and_update();
+                        if is_leader && leader_term != Some(term) {
+                            if let Err(err) = record_admin_event(
+                                &ctx,
+                                AdminEventKind::LeaderChanged,
+                                json!({ "nodeId": node_id, "term": term }),
+                            )
+                            .await
+                            {
+                                warn!(?err, "unable to record leadership event");
+                            }
+                        }
+                        leader_term = is_leader.then_some(term);
                     }
                     _ = ctx.shutdown.cancelled() => {
                         return;
@@ -161,6 +341,26 @@ pub async fn serve() -> anyhow::Result<()> {
         });
     }
 
+    // the node is ready once the documents in the document path are applied, also when applied by another node
+    let readiness = Readiness::default();
+    {
+        let ctx = ctx.clone();
+        let readiness = readiness.clone();
+        let expected = load_docs::expected_cfg_documents(&env_config)?;
+        tokio::spawn(async move {
+            let applied = readiness::open_when_documents_applied(
+                &ctx,
+                &readiness,
+                &expected,
+                Duration::from_secs(1),
+            );
+            tokio::select! {
+                _ = applied => {}
+                _ = ctx.shutdown.cancelled() => {}
+            }
+        });
+    }
+
     let shutdown = ctx.shutdown.clone();
 
     tokio::spawn(
@@ -168,15 +368,76 @@ pub async fn serve() -> anyhow::Result<()> {
             .with_graceful_shutdown(shutdown.clone())
             .bind()
             .await?
-            .serve(axum::Router::new().route(
-                "/health/readiness",
-                axum::routing::get(|| async { Json(json!({ "status": "UP" })).into_response() }),
-            )),
+            .serve(readiness::router(readiness)),
     );
 
     // App is fully running, wait for it to shut down
     shutdown.cancelled().await;
 
+    // message streams end on the shutdown signal, in-flight requests are drained
+    let drain_timeout = env_config.shutdown_drain_timeout();
+    info!(?drain_timeout, "shutting down");
+    let drained = load_shed::drain_within(&request_limit, drain_timeout, async {
+        if let Err(err) = ctx.hql.shutdown().await {
+            warn!(?err, "database shutdown failed");
+        }
+    })
+    .await;
+    if !drained {
+        warn!(?drain_timeout, "shutdown drain timed out, terminating");
+    }
+
+    Ok(())
+}
+
+/// The main service routing, independent of the listener serving it.
+///
+/// An HTTP/3 listener would bind QUIC with the `h3` ALPN protocol and serve these same routers.
+async fn main_service_router(
+    ctx: AuthlyCtx,
+    request_limit: RequestLimit,
+) -> anyhow::Result<ProtocolRouter> {
+    let grpc_router = ClockSkewMonitor::layer(
+        ctx.clone(),
+        grpc::main_service_grpc_router(ctx.clone()).await?,
+    );
+    let http_router = ClockSkewMonitor::layer(ctx.clone(), main_service_http_router(ctx));
+
+    Ok(ProtocolRouter::default()
+        .with_grpc(request_limit.layer(grpc_router))
+        .or_default(request_limit.layer(http_router)))
+}
+
+/// Serve the main service over TLS on TCP, negotiating HTTP/1.1 or HTTP/2 through ALPN
+async fn spawn_main_tcp_listener(
+    env_config: &EnvConfig,
+    ctx: &AuthlyCtx,
+    router: ProtocolRouter,
+) -> anyhow::Result<()> {
+    let connection_limit = ConnectionLimit::new(
+        env_config.max_connections,
+        env_config.max_requests_per_connection,
+    );
+    let listener = tokio::net::TcpListener::bind(SocketAddr::new(
+        Ipv4Addr::new(0, 0, 0, 0).into(),
+        env_config.server_port,
+    ))
+    .await?;
+    let tls_configs = tls::main_service_tls_configurer(
+        env_config.hostname.clone(),
+        env_config.tls_policy(),
+        ctx.clone(),
+    )
+    .await?;
+
+    tokio::spawn(connection_limit.serve_tls(
+        listener,
+        tls_configs,
+        authly_common::mtls_server::MTLSMiddleware,
+        router.into_service(),
+        ctx.shutdown.clone(),
+    ));
+
     Ok(())
 }
 
@@ -184,11 +445,13 @@ fn main_service_http_router(ctx: AuthlyCtx) -> axum::Router {
     axum::Router::new()
         .merge(authly_web::router())
         .merge(authly_service::openapi::router::router())
+        .merge(authly_service::scim::router())
+        .layer(axum::middleware::from_fn(request_id_middleware))
         .with_state(ctx.clone())
 }
 
 pub async fn configure() -> anyhow::Result<()> {
-    initialize().await?;
+    initialize(EnvConfig::load()).await?;
 
     Ok(())
 }
@@ -205,30 +468,15 @@ impl CacheIndex for CacheEntry {
     }
 }
 
-async fn initialize() -> anyhow::Result<Init> {
+async fn initialize(env_config: EnvConfig) -> anyhow::Result<Init> {
     tls::init_tls_ring();
 
-    let env_config = EnvConfig::load();
-    let secrets = authly_secrets::AuthlySecretsBuilder {
-        authly_uid: env_config.uid.0,
-        danger_disable_encryption: env_config.danger_disable_encryption,
-        bao_url: env_config.bao_url.clone(),
-        bao_token: env_config.bao_token.clone(),
+    let secrets: Arc<dyn AuthlySecrets> = build_secrets(&env_config)?.into();
+    if let Err(err) = secrets.health().await {
+        warn!(?err, "secrets backend is unhealthy");
     }
-    .build(reqwest::Client::new())
-    .map_err(|err| anyhow!("fatal: Failed to select secrets backend: {err}"))?;
-
-    info!("using `{}` secret backend", secrets.name());
-
-    let node_config = hiqlite_node_config(&env_config);
-    let hql = HiqliteClient::new(hiqlite::start_node_with_cache::<CacheEntry>(node_config).await?);
 
-    hql.wait_until_healthy_db().await;
-
-    hql.migrate::<Migrations>().await.map_err(|err| {
-        tracing::error!(?err, "failed to migrate");
-        err
-    })?;
+    let hql = start_hiqlite(&env_config).await?;
 
     let builtins =
         init_repo::load_authly_builtins(&hql, IsLeaderDb(hql.is_leader_db().await)).await?;
@@ -239,12 +487,50 @@ async fn initialize() -> anyhow::Result<Init> {
         secrets.as_ref(),
     )
     .await?;
-    let instance =
-        crypto_repo::load_authly_instance(IsLeaderDb(hql.is_leader_db().await), &hql, &deks)
-            .await?;
+    if hql.is_leader_db().await {
+        let count = webauthn_repo::encrypt_plaintext_passkeys(&hql, &deks).await?;
+        if count > 0 {
+            info!("encrypted {count} plaintext passkeys");
+        }
+    }
+    if env_config.pkcs11_module.is_some() && env_config.aws_kms_key_id.is_some() {
+        return Err(anyhow!(
+            "AUTHLY_PKCS11_MODULE and AUTHLY_AWS_KMS_KEY_ID can't both hold the instance key"
+        ));
+    }
+    let instance_key_source = match pkcs11::Pkcs11Signer::from_env_config(&env_config)? {
+        Some(signer) => InstanceKeySource::External(Arc::new(signer)),
+        None => match &env_config.aws_kms_key_id {
+            Some(key_id) => {
+                InstanceKeySource::External(Arc::new(aws_kms_signer(&env_config, key_id).await?))
+            }
+            None => InstanceKeySource::Database,
+        },
+    };
+    let id_generator = env_config.id_strategy.generator();
+    let mut instance = crypto_repo::load_authly_instance(
+        IsLeaderDb(hql.is_leader_db().await),
+        &hql,
+        &deks,
+        instance_key_source,
+        id_generator.as_ref(),
+    )
+    .await?;
+    if let Some(key_id) = &env_config.aws_kms_token_key_id {
+        let signer = aws_kms_signer(&env_config, key_id).await?;
+        instance = instance.with_external_token_signing_key(TokenSigningKey::from_signer(
+            Arc::new(signer),
+            time::OffsetDateTime::UNIX_EPOCH,
+        ));
+    }
 
     let cert_distribution_platform = if env_config.k8s {
-        CertificateDistributionPlatform::KubernetesConfigMap
+        match env_config.k8s_ca_distribution {
+            K8sCaDistribution::ConfigMap => CertificateDistributionPlatform::KubernetesConfigMap,
+            K8sCaDistribution::Secret => CertificateDistributionPlatform::KubernetesSecret {
+                namespaces: env_config.k8s_ca_namespaces.clone(),
+            },
+        }
     } else {
         CertificateDistributionPlatform::EtcDir
     };
@@ -252,6 +538,7 @@ async fn initialize() -> anyhow::Result<Init> {
     let persona_directories = load_persona_directories(&hql, &deks).await?;
 
     let shutdown = tower_server::signal::termination_signal();
+    let (audit_queue, audit_appender) = AuditQueue::new();
 
     let ctx = AuthlyCtx {
         state: Arc::new(AuthlyState {
@@ -265,8 +552,18 @@ async fn initialize() -> anyhow::Result<Init> {
             webauthn_per_uri: Default::default(),
             cert_distribution_platform,
             svc_event_dispatcher: ServiceEventDispatcher::new(shutdown.clone()),
+            entity_event_notifier: EntityEventNotifier::default(),
+            audit_queue,
+            session_cache: LruSessionCache::default(),
+            metadata_cache: ServiceMetadataCache::default(),
+            policy_engine_cache: PolicyEngineCache::default(),
+            id_generator,
+            clock: Box::new(SystemClock),
+            clock_skew: ClockSkewMonitor::default().with_max_drift(env_config.max_clock_drift()),
+            stats: AuthlyStats::default(),
+            secrets,
             shutdown,
-            etc_dir: env_config.etc_dir.clone(),
+            etc_layout: EtcLayout::from_env_config(&env_config),
             export_tls_to_etc: env_config.export_tls_to_etc,
             hostname: env_config.hostname.clone(),
             k8s_local_namespace: env_config.k8s_namespace.clone(),
@@ -277,6 +574,10 @@ async fn initialize() -> anyhow::Result<Init> {
 
     if ctx.hql.is_leader_db().await {
         load_cfg_documents(&env_config, &ctx).await?;
+
+        if env_config.k8s && env_config.k8s_verify_service_accounts {
+            k8s::k8s_service_accounts::verify_service_accounts(&ctx).await;
+        }
     }
 
     let settings = settings_repo::load_local_settings(ctx.get_db()).await?;
@@ -285,7 +586,69 @@ async fn initialize() -> anyhow::Result<Init> {
 
     ctx.settings.store(Arc::new(settings));
 
-    Ok(Init { ctx, env_config })
+    Ok(Init {
+        ctx,
+        env_config,
+        audit_appender,
+    })
+}
+
+fn build_secrets(env_config: &EnvConfig) -> anyhow::Result<Box<dyn AuthlySecrets>> {
+    let secrets = authly_secrets::AuthlySecretsBuilder {
+        authly_uid: env_config.uid.0,
+        danger_disable_encryption: env_config.danger_disable_encryption,
+        i_understand_this_is_insecure: env_config.i_understand_this_is_insecure,
+        bao_url: env_config.bao_url.clone(),
+        bao_token: env_config.bao_token.clone(),
+        bao_fallback_urls: env_config.bao_fallback_urls.clone(),
+    }
+    .build(reqwest::Client::new())
+    .map_err(|err| anyhow!("fatal: Failed to select secrets backend: {err}"))?;
+
+    info!("using `{}` secret backend", secrets.name());
+
+    Ok(secrets)
+}
+
+async fn aws_kms_signer(env_config: &EnvConfig, key_id: &str) -> anyhow::Result<AwsKmsSigner> {
+    let region = env_config
+        .aws_region
+        .clone()
+        .context("AUTHLY_AWS_REGION is required with AWS KMS keys")?;
+
+    AwsKmsSigner::connect(
+        reqwest::Client::new(),
+        region,
+        env_config.aws_kms_endpoint.as_deref(),
+        key_id.to_string(),
+        AwsCredentials::from_env()?,
+    )
+    .await
+}
+
+/// Start the local hiqlite node and run database migrations
+async fn start_hiqlite(env_config: &EnvConfig) -> anyhow::Result<HiqliteClient> {
+    let node_config = hiqlite_node_config(env_config);
+    let hql = HiqliteClient::new(hiqlite::start_node_with_cache::<CacheEntry>(node_config).await?)
+        .with_slow_query_log(env_config.slow_query_log());
+
+    hql.wait_until_healthy_db().await;
+
+    hql.migrate::<Migrations>().await.map_err(|err| {
+        tracing::error!(?err, "failed to migrate");
+        err
+    })?;
+
+    Ok(hql)
+}
+
+/// The SQLite database file of the local hiqlite node
+fn hiqlite_db_path(env_config: &EnvConfig) -> PathBuf {
+    env_config
+        .data_dir
+        .join("state_machine")
+        .join("db")
+        .join(HIQLITE_DB_FILENAME)
 }
 
 fn hiqlite_node_config(env_config: &EnvConfig) -> hiqlite::NodeConfig {
@@ -304,7 +667,10 @@ fn hiqlite_node_config(env_config: &EnvConfig) -> hiqlite::NodeConfig {
             .unwrap()
             .to_string()
             .into(),
-        danger_tls_no_verify: true,
+        // Cluster nodes authenticate each other with the raft and API secrets,
+        // and `issue-cluster-key` produces self-signed certificates that can't be verified.
+        // Verification requires node certificates issued by a CA in the system trust store.
+        danger_tls_no_verify: !env_config.cluster_tls_verify,
     };
 
     let node_id = if env_config.k8s {
@@ -375,7 +741,9 @@ fn hiqlite_node_config(env_config: &EnvConfig) -> hiqlite::NodeConfig {
         node_id,
         nodes: hiqlite_nodes,
         data_dir: env_config.data_dir.to_str().unwrap().to_string().into(),
-        filename_db: "authly.db".into(),
+        filename_db: HIQLITE_DB_FILENAME.into(),
+        // hiqlite statement logging includes parameter values, which may be secrets.
+        // Slow statements are logged without values through `AUTHLY_SLOW_QUERY_THRESHOLD_MS` instead.
         log_statements: false,
         prepared_statement_cache_capacity: 1024,
         read_pool_size: 4,
@@ -404,7 +772,28 @@ fn hiqlite_node_config(env_config: &EnvConfig) -> hiqlite::NodeConfig {
         tls_api: Some(cluster_tls_config),
         secret_raft: env_config.cluster_raft_secret.clone(),
         secret_api: env_config.cluster_api_secret.clone(),
-        shutdown_delay_millis: 5000,
+        shutdown_delay_millis: env_config.hiqlite_shutdown_delay_millis(),
         ..Default::default()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{build_secrets, env_config::EnvConfig};
+
+    #[test]
+    fn test_disabled_encryption_requires_acknowledgment() {
+        let env_config = EnvConfig {
+            danger_disable_encryption: true,
+            ..Default::default()
+        };
+        assert!(build_secrets(&env_config).is_err());
+
+        let env_config = EnvConfig {
+            danger_disable_encryption: true,
+            i_understand_this_is_insecure: true,
+            ..Default::default()
+        };
+        assert!(build_secrets(&env_config).unwrap().is_insecure());
+    }
+}
@@ -1,27 +1,38 @@
-use std::{fs, os::unix::ffi::OsStrExt};
+use std::{fs, os::unix::ffi::OsStrExt, path::PathBuf};
 
 use anyhow::anyhow;
-use authly_common::{
-    document::Document,
-    id::{DirectoryId, ServiceId},
-};
+use authly_common::id::{DirectoryId, ServiceId};
 use authly_domain::{
     audit::Actor,
     ctx::GetDb,
     directory::{self, DirectoryKind},
-    document::{compiled_document::DocumentMeta, doc_compiler::compile_doc},
+    document::{
+        assertion::parse_document,
+        compiled_document::DocumentMeta,
+        doc_compiler::compile_doc,
+        error::DocError,
+        import::{self, ComposedSource},
+        interpolate::interpolate_env,
+        strict::check_unknown_fields,
+    },
+    readiness::ExpectedDocument,
     repo::directory_repo::DbDirectory,
 };
+use serde_spanned::Spanned;
 use tracing::info;
 
 use crate::{AuthlyCtx, EnvConfig};
 
-/// Load documents from file
-pub(crate) async fn load_cfg_documents(
-    env_config: &EnvConfig,
-    ctx: &AuthlyCtx,
-) -> anyhow::Result<()> {
-    let doc_directories = DbDirectory::query_by_kind(ctx.get_db(), DirectoryKind::Document).await?;
+/// A document file in the document path
+struct CfgDocument {
+    path: PathBuf,
+    source: ComposedSource,
+    meta: DocumentMeta,
+}
+
+/// Read the documents in the document path, in the order they are applied
+fn read_cfg_documents(env_config: &EnvConfig) -> anyhow::Result<Vec<CfgDocument>> {
+    let mut documents = vec![];
 
     for dir_path in &env_config.document_path {
         let Ok(entries) = fs::read_dir(dir_path) else {
@@ -42,50 +53,103 @@ pub(crate) async fn load_cfg_documents(
         for path in file_paths {
             let path = std::path::absolute(path).unwrap();
 
-            let source = fs::read_to_string(&path)
-                .map_err(|_| anyhow!("document {path:?} failed to load"))?;
-
-            let document = Document::from_toml(&source)?;
-
+            let root = fs::canonicalize(path.parent().unwrap())?;
+            let source = import::compose(&path, |path| {
+                // imports are checked lexically by compose, this catches symlinks out of the directory
+                if !fs::canonicalize(path)?.starts_with(&root) {
+                    return Err(anyhow!("resolves outside of the document directory"));
+                }
+                Ok(interpolate_env(&fs::read_to_string(path)?)?)
+            })
+            .map_err(|err| anyhow!("document {path:?} failed to load: {err}"))?;
+
+            // the hash covers the interpolated source and its imports,
+            // so a changed variable or fragment causes the document to be re-applied
             let meta = DocumentMeta {
                 url: format!("file://{}", path.to_str().unwrap()),
                 hash: {
                     let mut hasher = blake3::Hasher::new();
-                    hasher.update(source.as_bytes());
+                    hasher.update(source.source.as_bytes());
                     hasher.finalize().into()
                 },
             };
 
-            let dir_id = DirectoryId::from_uint(document.authly_document.id.get_ref().as_u128());
-
-            if should_process(dir_id, &meta, &doc_directories) {
-                info!(?path, "load");
-
-                let compiled_doc = match compile_doc(ctx, document, meta).await {
-                    Ok(doc) => doc,
-                    Err(errors) => {
-                        for error in errors {
-                            tracing::error!("doc error: {error:?}");
-                        }
-                        return Err(anyhow!("document error"));
-                    }
-                };
-
-                directory::apply_document(
-                    ctx,
-                    compiled_doc,
-                    Actor(ServiceId::from_uint(0).upcast()),
-                )
-                .await?;
-            } else {
-                info!(?path, "unchanged");
+            documents.push(CfgDocument { path, source, meta });
+        }
+    }
+
+    Ok(documents)
+}
+
+/// Load documents from file
+pub(crate) async fn load_cfg_documents(
+    env_config: &EnvConfig,
+    ctx: &AuthlyCtx,
+) -> anyhow::Result<()> {
+    let doc_directories = DbDirectory::query_by_kind(ctx.get_db(), DirectoryKind::Document).await?;
+
+    for CfgDocument { path, source, meta } in read_cfg_documents(env_config)? {
+        let (document, extensions) = parse_document(&source.source)?;
+
+        let dir_id = DirectoryId::from_uint(document.authly_document.id.get_ref().as_u128());
+
+        if should_process(dir_id, &meta, &doc_directories) {
+            info!(?path, "load");
+
+            if env_config.document_strict {
+                if let Err(errors) = check_unknown_fields(&source.source) {
+                    log_doc_errors(&source, errors);
+                    return Err(anyhow!("document error"));
+                }
             }
+
+            let compiled_doc = match compile_doc(ctx, document, extensions, meta).await {
+                Ok(doc) => doc,
+                Err(errors) => {
+                    log_doc_errors(&source, errors);
+                    return Err(anyhow!("document error"));
+                }
+            };
+
+            directory::apply_document(ctx, compiled_doc, Actor(ServiceId::from_uint(0).upcast()))
+                .await?;
+        } else {
+            info!(?path, "unchanged");
         }
     }
 
     Ok(())
 }
 
+fn log_doc_errors(source: &ComposedSource, errors: Vec<Spanned<DocError>>) {
+    for error in errors {
+        let code = error.get_ref().code();
+        match source.locate(error.span()) {
+            Some((file, span)) => {
+                tracing::error!(?file, ?span, code, "doc error: {}", error.get_ref());
+            }
+            None => tracing::error!(code, "doc error: {}", error.get_ref()),
+        }
+    }
+}
+
+/// The documents in the document path that have to be applied before the node is ready
+pub(crate) fn expected_cfg_documents(
+    env_config: &EnvConfig,
+) -> anyhow::Result<Vec<ExpectedDocument>> {
+    read_cfg_documents(env_config)?
+        .into_iter()
+        .map(|CfgDocument { source, meta, .. }| {
+            let (document, _) = parse_document(&source.source)?;
+
+            Ok(ExpectedDocument {
+                dir_id: DirectoryId::from_uint(document.authly_document.id.get_ref().as_u128()),
+                hash: meta.hash,
+            })
+        })
+        .collect()
+}
+
 fn should_process(
     dir_id: DirectoryId,
     meta: &DocumentMeta,
@@ -1,11 +1,10 @@
-use std::env;
+use std::{env, path::PathBuf};
 
-use authly::{configure, env_config::ClusterTlsPath, serve, EnvConfig};
-use authly_domain::cert::{server_cert, CertificateParamsExt};
+use authly::{audit, backup, configure, env_config::ClusterTlsPath, serve, tls, EnvConfig};
+use authly_domain::audit::AuditExportFormat;
 use clap::{Parser, Subcommand};
 use mimalloc::MiMalloc;
 use rand::{rngs::OsRng, Rng};
-use time::Duration;
 use tracing::info;
 use tracing_subscriber::EnvFilter;
 
@@ -37,6 +36,29 @@ enum Command {
 
     /// Issue a cluster key. Exports to `$AUTHLY_ETC_DIR/cluster/`.
     IssueClusterKey,
+
+    /// Write a consistent snapshot of the Authly state to a file, then exit
+    Backup {
+        #[arg(long)]
+        out: PathBuf,
+    },
+
+    /// Restore a fresh node from a backup file, then exit
+    Restore {
+        #[arg(long = "in")]
+        input: PathBuf,
+    },
+
+    /// Export the audit trail to a file, as `jsonl` or `csv`, then exit
+    ExportAudit {
+        #[arg(long, default_value = "jsonl")]
+        format: AuditExportFormat,
+        #[arg(long)]
+        out: PathBuf,
+    },
+
+    /// Verify that the audit trail has not been tampered with, then exit
+    VerifyAudit,
 }
 
 #[tokio::main]
@@ -50,7 +72,10 @@ async fn main() -> anyhow::Result<()> {
     match Cli::parse().command {
         Some(Command::Serve) => {
             info!("🔒 Authly v{VERSION}");
-            serve().await?
+            serve().await?;
+
+            // terminate right away, tasks still running after the shutdown drain are abandoned
+            std::process::exit(0);
         }
         Some(Command::Ready) => {
             reqwest::Client::new()
@@ -60,6 +85,10 @@ async fn main() -> anyhow::Result<()> {
                 .error_for_status()?;
         }
         Some(Command::Configure) => configure().await?,
+        Some(Command::Backup { out }) => backup::backup(&EnvConfig::load(), &out).await?,
+        Some(Command::Restore { input }) => backup::restore(&EnvConfig::load(), &input).await?,
+        Some(Command::ExportAudit { format, out }) => audit::export(format, &out).await?,
+        Some(Command::VerifyAudit) => audit::verify().await?,
         Some(Command::GenerateAuthlyUid) => {
             let mut id = [0u8; 32];
             OsRng.fill(id.as_mut_slice());
@@ -69,10 +98,10 @@ async fn main() -> anyhow::Result<()> {
         Some(Command::IssueClusterKey) => {
             let env_config = EnvConfig::load();
 
-            issue_cluster_key(&env_config.hostname, env_config.cluster_tls_path())?;
+            tls::issue_cluster_key(&env_config.hostname, env_config.cluster_tls_path())?;
 
             if env_config.k8s {
-                issue_cluster_key(
+                tls::issue_cluster_key(
                     &format!("*.{host}", host = &env_config.k8s_headless_svc),
                     ClusterTlsPath(env_config.etc_dir.join("cluster-k8s")),
                 )?;
@@ -83,16 +112,3 @@ async fn main() -> anyhow::Result<()> {
 
     Ok(())
 }
-
-fn issue_cluster_key(hostname: &str, tls_path: ClusterTlsPath) -> anyhow::Result<()> {
-    let req = server_cert("authly", vec![hostname.to_string()], Duration::days(10000))?
-        .with_new_key_pair();
-    let certificate = req.params.self_signed(&req.key)?;
-
-    std::fs::create_dir_all(&tls_path.0)?;
-
-    std::fs::write(tls_path.key_path(), req.key.serialize_pem())?;
-    std::fs::write(tls_path.cert_path(), certificate.pem())?;
-
-    Ok(())
-}
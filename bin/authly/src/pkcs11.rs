@@ -0,0 +1,151 @@
+//! Instance key held in an HSM, accessed through PKCS#11.
+//!
+//! The HSM is expected to hold a P-256 key pair, with the same label on the private and public key objects.
+//! Authly only ever asks the HSM to sign, the private key is never read.
+
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{anyhow, Context};
+use async_trait::async_trait;
+use authly_domain::signer::{ecdsa_fixed_to_der, Signer};
+use cryptoki::{
+    context::{CInitializeArgs, Pkcs11},
+    mechanism::Mechanism,
+    object::{Attribute, AttributeType, KeyType, ObjectClass, ObjectHandle},
+    session::{Session, UserType},
+    types::AuthPin,
+};
+use rcgen::{SignatureAlgorithm, PKCS_ECDSA_P256_SHA256};
+use tracing::info;
+
+use crate::env_config::EnvConfig;
+
+pub struct Pkcs11Signer {
+    /// PKCS#11 sessions can't be used concurrently, and every call on them blocks
+    session: Arc<Mutex<Session>>,
+    private_key: ObjectHandle,
+    public_key_raw: Vec<u8>,
+}
+
+impl Pkcs11Signer {
+    /// Open the HSM configured in the environment, if any
+    pub fn from_env_config(env_config: &EnvConfig) -> anyhow::Result<Option<Self>> {
+        let Some(module) = &env_config.pkcs11_module else {
+            return Ok(None);
+        };
+
+        let pin = env_config
+            .pkcs11_pin
+            .as_deref()
+            .context("AUTHLY_PKCS11_PIN is required with AUTHLY_PKCS11_MODULE")?;
+
+        Self::open(
+            module,
+            env_config.pkcs11_token_label.as_deref(),
+            pin,
+            &env_config.pkcs11_key_label,
+        )
+        .map(Some)
+    }
+
+    pub fn open(
+        module: &Path,
+        token_label: Option<&str>,
+        pin: &str,
+        key_label: &str,
+    ) -> anyhow::Result<Self> {
+        let pkcs11 = Pkcs11::new(module)
+            .with_context(|| format!("unable to load PKCS#11 module {module:?}"))?;
+        pkcs11.initialize(CInitializeArgs::OsThreads)?;
+
+        let mut slot = None;
+        for candidate in pkcs11.get_slots_with_token()? {
+            let label = pkcs11.get_token_info(candidate)?.label().trim().to_string();
+            if token_label.is_none_or(|token_label| token_label == label) {
+                slot = Some(candidate);
+                break;
+            }
+        }
+        let slot = slot.context("PKCS#11 token not found")?;
+
+        let session = pkcs11.open_ro_session(slot)?;
+        session.login(UserType::User, Some(&AuthPin::new(pin.into())))?;
+
+        let find_key = |class: ObjectClass| -> anyhow::Result<ObjectHandle> {
+            session
+                .find_objects(&[
+                    Attribute::Class(class),
+                    Attribute::KeyType(KeyType::EC),
+                    Attribute::Label(key_label.as_bytes().to_vec()),
+                ])?
+                .into_iter()
+                .next()
+                .ok_or_else(|| anyhow!("PKCS#11 key `{key_label}` not found"))
+        };
+
+        let private_key = find_key(ObjectClass::PRIVATE_KEY)?;
+        let public_key = find_key(ObjectClass::PUBLIC_KEY)?;
+
+        let public_key_raw = match session
+            .get_attributes(public_key, &[AttributeType::EcPoint])?
+            .into_iter()
+            .next()
+        {
+            Some(Attribute::EcPoint(point)) => ec_point_from_der(point)?,
+            _ => return Err(anyhow!("PKCS#11 public key has no EC point")),
+        };
+
+        info!(key_label, "using PKCS#11 instance key");
+
+        Ok(Self {
+            session: Arc::new(Mutex::new(session)),
+            private_key,
+            public_key_raw,
+        })
+    }
+}
+
+#[async_trait]
+impl Signer for Pkcs11Signer {
+    fn algorithm(&self) -> &'static SignatureAlgorithm {
+        &PKCS_ECDSA_P256_SHA256
+    }
+
+    fn public_key_raw(&self) -> &[u8] {
+        &self.public_key_raw
+    }
+
+    async fn sign(&self, msg: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let session = self.session.clone();
+        let private_key = self.private_key;
+        let msg = msg.to_vec();
+
+        // The HSM round-trip blocks, and must not hold up the async runtime
+        let signature = tokio::task::spawn_blocking(move || {
+            session
+                .lock()
+                .unwrap()
+                .sign(&Mechanism::EcdsaSha256, private_key, &msg)
+        })
+        .await
+        .context("PKCS#11 signing task failed")?
+        .context("PKCS#11 signing failed")?;
+
+        // PKCS#11 produces the fixed size `r || s` signature format
+        Ok(ecdsa_fixed_to_der(&signature))
+    }
+}
+
+/// `CKA_EC_POINT` is usually the DER encoding of an OCTET STRING wrapping the point
+fn ec_point_from_der(point: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+    match point.as_slice() {
+        [0x04, 0x41, rest @ ..] if rest.len() == 65 => Ok(rest.to_vec()),
+        [0x04, ..] if point.len() == 65 => Ok(point),
+        _ => Err(anyhow!(
+            "unsupported EC point encoding, expected an uncompressed P-256 point"
+        )),
+    }
+}
@@ -1,9 +1,29 @@
-use crate::{k8s::k8s_platform::k8s_redistribute_certificates, AuthlyCtx};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    etc_export::write_atomic,
+    k8s::k8s_platform::{k8s_redistribute_certificate_secrets, k8s_redistribute_certificates},
+    AuthlyCtx,
+};
 
 /// How Authly can distribute certificates
 pub enum CertificateDistributionPlatform {
     EtcDir,
     KubernetesConfigMap,
+    /// Write the CA bundle to a Secret in each of the namespaces.
+    /// An empty list means the namespace Authly runs in.
+    KubernetesSecret {
+        namespaces: Vec<String>,
+    },
+}
+
+/// The kind of Kubernetes object used to distribute the CA bundle
+#[derive(Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum K8sCaDistribution {
+    #[default]
+    ConfigMap,
+    Secret,
 }
 
 /// Perform certificate redistribution
@@ -19,30 +39,29 @@ pub(crate) async fn redistribute_certificates(ctx: &AuthlyCtx) {
                 tracing::error!(
                     ?err,
                     "certificates could not be written to {:?}",
-                    ctx.etc_dir
+                    ctx.etc_layout.certs_dir
                 );
             }
         }
         CertificateDistributionPlatform::KubernetesConfigMap => {
             k8s_redistribute_certificates(ctx.clone()).await;
         }
+        CertificateDistributionPlatform::KubernetesSecret { namespaces } => {
+            k8s_redistribute_certificate_secrets(ctx, namespaces).await;
+        }
     }
 }
 
 pub(crate) fn reexport_certs_to_etc(ctx: &AuthlyCtx) -> anyhow::Result<()> {
-    std::fs::create_dir_all(ctx.etc_dir.join("certs"))?;
-
-    std::fs::write(
-        ctx.etc_dir.join("certs/root.crt"),
-        ctx.instance
-            .load()
-            .trust_root_ca()
-            .certificate_pem()
-            .as_bytes(),
+    let instance = ctx.instance.load();
+
+    write_atomic(
+        &ctx.etc_layout.root_ca_path(),
+        instance.trust_root_ca().certificate_pem().as_bytes(),
     )?;
-    std::fs::write(
-        ctx.etc_dir.join("certs/local.crt"),
-        ctx.instance.load().local_ca().certificate_pem().as_bytes(),
+    write_atomic(
+        &ctx.etc_layout.local_ca_path(),
+        instance.local_ca().certificate_pem().as_bytes(),
     )?;
 
     Ok(())
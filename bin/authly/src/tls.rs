@@ -4,21 +4,41 @@ use anyhow::anyhow;
 use authly_domain::{
     cert::{server_cert, CertificateParamsExt},
     ctx::GetInstance,
+    tls::{alpn_protocol_ids, AlpnProtocol, TlsPolicy},
 };
-use futures_util::StreamExt;
+use futures_util::{stream::BoxStream, StreamExt};
 use rustls::{pki_types::PrivateKeyDer, server::WebPkiClientVerifier, RootCertStore, ServerConfig};
 use tracing::info;
 
-use crate::{AuthlyCtx, AuthlyInstance};
+use crate::{env_config::ClusterTlsPath, AuthlyCtx, AuthlyInstance};
 
 pub fn init_tls_ring() {
     let _ = rustls::crypto::ring::default_provider().install_default();
 }
 
+/// Issue a self-signed cluster key and certificate for `hostname`, written to `tls_path`
+pub fn issue_cluster_key(hostname: &str, tls_path: ClusterTlsPath) -> anyhow::Result<()> {
+    let req = server_cert(
+        "authly",
+        vec![hostname.to_string()],
+        time::Duration::days(10000),
+    )?
+    .with_new_key_pair();
+    let certificate = req.params.self_signed(&req.key)?;
+
+    std::fs::create_dir_all(&tls_path.0)?;
+
+    std::fs::write(tls_path.key_path(), req.key.serialize_pem())?;
+    std::fs::write(tls_path.cert_path(), certificate.pem())?;
+
+    Ok(())
+}
+
 pub(super) async fn main_service_tls_configurer(
     hostname: String,
+    tls_policy: TlsPolicy,
     ctx: AuthlyCtx,
-) -> anyhow::Result<impl tower_server::tls::TlsConfigurer> {
+) -> anyhow::Result<BoxStream<'static, Arc<ServerConfig>>> {
     // The root cert store is currently not changing
     let root_cert_store = {
         let mut store = RootCertStore::empty();
@@ -31,14 +51,17 @@ pub(super) async fn main_service_tls_configurer(
     // The first TLS config is produced immediately
     let initial = futures_util::stream::iter([generate_mutual_tls_server_config(
         &hostname,
+        &tls_policy,
         ctx.clone(),
         ctx.settings.load().server_cert_rotation_rate,
         root_cert_store.clone(),
-    )?]);
+    )
+    .await?]);
 
     // The following configs are produced after delay
     let rotation_stream = futures_util::stream::unfold((), move |_| {
         let hostname = hostname.clone();
+        let tls_policy = tls_policy.clone();
         let ctx = ctx.clone();
         let root_cert_store = root_cert_store.clone();
         async move {
@@ -47,10 +70,12 @@ pub(super) async fn main_service_tls_configurer(
 
             let server_config = generate_mutual_tls_server_config(
                 &hostname,
+                &tls_policy,
                 ctx.clone(),
                 ctx.settings.load().server_cert_rotation_rate,
                 root_cert_store,
             )
+            .await
             .expect("unable to regenerate server TLS config");
 
             Some((server_config, ()))
@@ -60,8 +85,9 @@ pub(super) async fn main_service_tls_configurer(
     Ok(initial.chain(rotation_stream).boxed())
 }
 
-fn generate_mutual_tls_server_config(
+async fn generate_mutual_tls_server_config(
     hostname: &str,
+    tls_policy: &TlsPolicy,
     ctx: AuthlyCtx,
     rotation_rate: std::time::Duration,
     root_cert_store: Arc<RootCertStore>,
@@ -69,22 +95,26 @@ fn generate_mutual_tls_server_config(
     // Make the certificate valid for twice the rotation rate
     let not_after = time::Duration::try_from(rotation_rate)? * 2;
 
-    let server_cert = ctx.get_instance().sign_with_local_ca(
-        server_cert("authly", vec![hostname.to_string()], not_after)?.with_new_key_pair(),
-    );
+    let server_cert = ctx
+        .get_instance()
+        .sign_with_local_ca(
+            server_cert("authly", vec![hostname.to_string()], not_after)?.with_new_key_pair(),
+        )
+        .await?;
 
     let server_private_key_der = PrivateKeyDer::try_from(server_cert.key.serialize_der())
         .map_err(|err| anyhow!("server private key: {err}"))?;
 
-    let mut config = rustls::server::ServerConfig::builder()
+    let mut config = tls_policy
+        .server_config_builder()?
         .with_client_cert_verifier(WebPkiClientVerifier::builder(root_cert_store).build()?)
         .with_single_cert(vec![server_cert.der], server_private_key_der)?;
 
-    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    tls_policy.apply_alpn(&mut config);
     Ok(Arc::new(config))
 }
 
-pub fn generate_tls_server_config(
+pub async fn generate_tls_server_config(
     hostname: &str,
     instance: &AuthlyInstance,
     rotation_rate: std::time::Duration,
@@ -92,9 +122,11 @@ pub fn generate_tls_server_config(
     // Make the certificate valid for twice the rotation rate
     let not_after = time::Duration::try_from(rotation_rate)? * 2;
 
-    let server_cert = instance.sign_with_local_ca(
-        server_cert("authly", vec![hostname.to_string()], not_after)?.with_new_key_pair(),
-    );
+    let server_cert = instance
+        .sign_with_local_ca(
+            server_cert("authly", vec![hostname.to_string()], not_after)?.with_new_key_pair(),
+        )
+        .await?;
 
     let server_private_key_der = PrivateKeyDer::try_from(server_cert.key.serialize_der())
         .map_err(|err| anyhow!("server private key: {err}"))?;
@@ -103,6 +135,6 @@ pub fn generate_tls_server_config(
         .with_no_client_auth()
         .with_single_cert(vec![server_cert.der], server_private_key_der)?;
 
-    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    config.alpn_protocols = alpn_protocol_ids(AlpnProtocol::DEFAULT);
     Ok(Arc::new(config))
 }
@@ -2,7 +2,7 @@ use http::header::CONTENT_TYPE;
 use hyper::body::Incoming;
 use std::error::Error as StdError;
 
-#[derive(Default)]
+#[derive(Clone, Default)]
 pub struct ProtocolRouter {
     routers: Vec<(Protocol, axum::Router)>,
 }
@@ -58,6 +58,7 @@ impl ProtocolRouter {
     }
 }
 
+#[derive(Clone, Copy)]
 enum Protocol {
     Grpc,
     Default,
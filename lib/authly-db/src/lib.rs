@@ -6,6 +6,7 @@ use thiserror::Error;
 
 pub mod literal;
 pub mod param;
+pub mod slow_query;
 
 #[derive(Error, Debug)]
 pub enum DbError {
@@ -27,6 +28,14 @@ pub enum DbError {
     #[error("binary encoding")]
     BinaryEncoding,
 
+    /// A write reached a node that is not the cluster leader, e.g. during an election
+    #[error("not leader, current leader: {leader:?}")]
+    NotLeader { leader: Option<u64> },
+
+    /// A write was rejected because the cluster has lost quorum, and the node only serves reads
+    #[error("read-only mode")]
+    ReadOnly,
+
     #[error("other")]
     Other(Cow<'static, str>),
 }
@@ -101,11 +110,71 @@ pub trait Db: Send + Sync + 'static {
     /// Refer to a statement+column index, which is valid inside a transaction.
     fn stmt_column(stmt_index: usize, column_index: usize) -> Self::Param;
 
-    /// Execute multiple statements in a transaction
+    /// Execute multiple statements in a transaction.
+    ///
+    /// Every statement is attempted, and the transaction commits only if all of them succeed.
+    /// Otherwise it is rolled back, none of the statements take effect,
+    /// and the failing statements can be found in the per-statement results.
+    ///
+    /// The outer error means the transaction could not be run at all.
     fn transact(
         &self,
         sql: Vec<(Cow<'static, str>, Vec<Self::Param>)>,
-    ) -> impl Future<Output = Result<Vec<Result<usize, DbError>>, DbError>> + Send;
+    ) -> impl Future<Output = Result<TxnResults, DbError>> + Send;
+}
+
+/// Whether a transaction took effect
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TxnOutcome {
+    Committed,
+    RolledBack,
+}
+
+/// The outcome of [Db::transact], with one result per statement
+#[derive(Debug)]
+pub struct TxnResults {
+    pub outcome: TxnOutcome,
+    pub results: Vec<Result<usize, DbError>>,
+}
+
+impl TxnResults {
+    /// Results of a transaction executing `stmt_count` statements.
+    ///
+    /// The transaction commits only if there is a successful result for every statement.
+    pub fn new(stmt_count: usize, results: Vec<Result<usize, DbError>>) -> Self {
+        let outcome = if results.len() == stmt_count && results.iter().all(Result::is_ok) {
+            TxnOutcome::Committed
+        } else {
+            TxnOutcome::RolledBack
+        };
+
+        Self { outcome, results }
+    }
+
+    pub fn is_committed(&self) -> bool {
+        matches!(self.outcome, TxnOutcome::Committed)
+    }
+
+    /// The number of affected rows per statement of a committed transaction, or the first statement error
+    pub fn into_result(self) -> DbResult<Vec<usize>> {
+        let committed = self.is_committed();
+        let rows: Vec<usize> = self.results.into_iter().try_collect()?;
+
+        if committed {
+            Ok(rows)
+        } else {
+            Err(DbError::Other("transaction rolled back".into()))
+        }
+    }
+}
+
+impl IntoIterator for TxnResults {
+    type Item = Result<usize, DbError>;
+    type IntoIter = std::vec::IntoIter<Result<usize, DbError>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.results.into_iter()
+    }
 }
 
 pub trait Row {
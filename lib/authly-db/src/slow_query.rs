@@ -0,0 +1,53 @@
+use std::{
+    future::Future,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use tracing::warn;
+
+/// Logs and counts statements that take longer than a threshold.
+///
+/// Only the SQL text is logged. Parameters are bound through placeholders like `$1`,
+/// so their values, which may be secrets, never end up in the log.
+#[derive(Clone, Default)]
+pub struct SlowQueryLog {
+    threshold: Option<Duration>,
+    count: Arc<AtomicU64>,
+}
+
+impl SlowQueryLog {
+    /// Log statements exceeding `threshold`, a `None` threshold disables logging
+    pub fn new(threshold: Option<Duration>) -> Self {
+        Self {
+            threshold,
+            count: Default::default(),
+        }
+    }
+
+    /// The number of slow statements observed so far
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    /// Run a database operation, logging `sql` if it exceeds the threshold
+    pub async fn observe<T>(&self, sql: &str, operation: impl Future<Output = T>) -> T {
+        let Some(threshold) = self.threshold else {
+            return operation.await;
+        };
+
+        let started = Instant::now();
+        let output = operation.await;
+        let elapsed = started.elapsed();
+
+        if elapsed >= threshold {
+            self.count.fetch_add(1, Ordering::Relaxed);
+            warn!(elapsed_ms = elapsed.as_millis() as u64, sql, "slow query");
+        }
+
+        output
+    }
+}
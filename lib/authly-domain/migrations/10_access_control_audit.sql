@@ -0,0 +1,15 @@
+-- Access control decisions recorded for auditing, according to the ACCESS_CONTROL_AUDIT setting.
+-- Only IDs are recorded, never attribute or property values of the subject.
+-- `subject_eid` and `policy_id` are empty when there is no subject entity or deciding policy.
+CREATE TABLE access_control_audit (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    created_at DATETIME NOT NULL,
+    svc_eid BLOB NOT NULL,
+    subject_eid BLOB NOT NULL,
+    resource_attrs BLOB NOT NULL,
+    allowed INTEGER NOT NULL,
+    reason TEXT NOT NULL,
+    policy_id BLOB NOT NULL
+);
+
+CREATE INDEX access_control_audit_created_at ON access_control_audit (created_at);
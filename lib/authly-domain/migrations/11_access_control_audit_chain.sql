@@ -0,0 +1,4 @@
+-- Access control audit records form a hash chain: `hash` covers the record and the `hash` of the record before it.
+-- Records written before the chain was introduced have empty hashes.
+ALTER TABLE access_control_audit ADD COLUMN prev_hash BLOB NOT NULL DEFAULT X'';
+ALTER TABLE access_control_audit ADD COLUMN hash BLOB NOT NULL DEFAULT X'';
@@ -0,0 +1,9 @@
+-- Usage counters of the ACCESS_CONTROL_QUOTA setting, one per subject entity and quota attribute.
+-- `count` is the number of allowed requests in the fixed window starting at `window_start`.
+CREATE TABLE quota_counter (
+    subject_eid BLOB NOT NULL,
+    attr_id BLOB NOT NULL,
+    window_start INTEGER NOT NULL,
+    count INTEGER NOT NULL,
+    PRIMARY KEY (subject_eid, attr_id)
+);
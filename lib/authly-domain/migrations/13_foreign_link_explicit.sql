@@ -0,0 +1,3 @@
+-- Foreign identities linked by the persona itself, instead of resolved at login.
+-- An explicit link always resolves to its persona, regardless of the email address of the foreign identity.
+ALTER TABLE obj_foreign_dir_link ADD COLUMN explicit INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,2 @@
+-- Default policy bindings of a directory, applying to the services of the directory without policy bindings of their own.
+ALTER TABLE polbind ADD COLUMN dir_default INTEGER NOT NULL DEFAULT 0;
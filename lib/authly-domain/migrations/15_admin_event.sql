@@ -0,0 +1,23 @@
+-- Outbox of audit and system events, for a live tail in admin tooling.
+-- Subscribers keep track of the last `seq` they have processed.
+-- `detail` is a JSON object, its contents depend on `kind`.
+CREATE TABLE admin_event (
+    seq INTEGER PRIMARY KEY AUTOINCREMENT,
+    created_at DATETIME NOT NULL,
+    kind TEXT NOT NULL,
+    detail TEXT NOT NULL
+);
+
+CREATE TRIGGER admin_event_access_control_audit AFTER INSERT ON access_control_audit
+BEGIN
+    INSERT INTO admin_event (created_at, kind, detail)
+    VALUES (
+        NEW.created_at,
+        'access_control',
+        json_object(
+            'auditId', NEW.id,
+            'allowed', json(CASE WHEN NEW.allowed != 0 THEN 'true' ELSE 'false' END),
+            'reason', NEW.reason
+        )
+    );
+END;
@@ -0,0 +1,2 @@
+-- The direction entity attributes of the property are inherited in along membership relations: down, up, both or none.
+ALTER TABLE prop ADD COLUMN inheritance TEXT NOT NULL DEFAULT 'none';
@@ -0,0 +1,3 @@
+-- Whether the key took over signing access tokens from the instance key (compatibility mode).
+-- Only then can tokens signed by the instance key exist, and the instance key remains valid for verifying them.
+ALTER TABLE token_signing_key ADD COLUMN supersedes_instance INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,7 @@
+-- The last entity attribute event acknowledged by each subscribing service.
+-- Events acknowledged by every subscriber have been delivered, and are purged from the outbox.
+CREATE TABLE ent_attr_event_subscriber (
+    svc_eid BLOB NOT NULL PRIMARY KEY,
+    acked_seq INTEGER NOT NULL,
+    upd DATETIME NOT NULL
+);
@@ -0,0 +1,6 @@
+-- SCIM bearer tokens expire, and may be revoked before they do.
+-- Tokens issued before they had a lifetime expire 90 days after they were issued.
+ALTER TABLE scim_token ADD COLUMN expires_at DATETIME NOT NULL DEFAULT 0;
+ALTER TABLE scim_token ADD COLUMN revoked_at DATETIME;
+
+UPDATE scim_token SET expires_at = created_at + 90 * 24 * 60 * 60;
@@ -0,0 +1,13 @@
+-- Attribute sets left out of access tokens, stored when the token is issued.
+-- They are resolved by the entity and the reference carried in the token,
+-- `attrs` holds the concatenated attribute ids.
+CREATE TABLE token_attrs_ref (
+    eid BLOB NOT NULL,
+    id TEXT NOT NULL,
+    svc_eid BLOB NOT NULL,
+    attrs BLOB NOT NULL,
+    expires_at DATETIME NOT NULL,
+    PRIMARY KEY (eid, id)
+);
+
+CREATE INDEX token_attrs_ref_expires_at ON token_attrs_ref (expires_at);
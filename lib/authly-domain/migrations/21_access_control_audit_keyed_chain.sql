@@ -0,0 +1,4 @@
+-- The audit chain hashes are now keyed by the DEK of the builtin AuditChain property.
+-- Unkeyed hashes can be recomputed by anyone who modifies a record, so they are cleared,
+-- and the records written before are treated like records written before the chain was introduced.
+UPDATE access_control_audit SET prev_hash = X'', hash = X'';
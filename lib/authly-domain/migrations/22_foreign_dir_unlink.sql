@@ -0,0 +1,10 @@
+-- Foreign identities unlinked from their persona by the persona itself.
+-- An unlinked identity is not linked again through its email address, only explicitly.
+CREATE TABLE obj_foreign_dir_unlink (
+    dir_key INTEGER NOT NULL REFERENCES directory(key) DEFERRABLE INITIALLY DEFERRED,
+    foreign_id BLOB NOT NULL,
+    obj_id BLOB NOT NULL,
+    upd DATETIME NOT NULL,
+
+    PRIMARY KEY (dir_key, foreign_id)
+);
@@ -0,0 +1,3 @@
+-- Removed entity attributes are recorded by the statements removing them, stamped with the time of the application.
+-- The trigger stamped them with the time of each node evaluating it, which differs between the nodes of a cluster.
+DROP TRIGGER ent_attr_event_delete;
@@ -0,0 +1 @@
+ALTER TABLE session ADD COLUMN persistent INTEGER NOT NULL DEFAULT 0;
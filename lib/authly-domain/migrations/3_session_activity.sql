@@ -0,0 +1,4 @@
+ALTER TABLE session ADD COLUMN created_at DATETIME NOT NULL DEFAULT 0;
+ALTER TABLE session ADD COLUMN last_seen DATETIME NOT NULL DEFAULT 0;
+
+UPDATE session SET created_at = CAST(strftime('%s', 'now') AS INTEGER), last_seen = CAST(strftime('%s', 'now') AS INTEGER);
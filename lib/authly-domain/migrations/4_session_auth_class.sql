@@ -0,0 +1,4 @@
+ALTER TABLE session ADD COLUMN auth_class INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE session ADD COLUMN authenticated_at DATETIME NOT NULL DEFAULT 0;
+
+UPDATE session SET authenticated_at = created_at;
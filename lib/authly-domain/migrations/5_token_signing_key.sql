@@ -0,0 +1,8 @@
+-- Dedicated keys for signing access tokens.
+-- When the table is empty, tokens are signed with the instance key (compatibility mode).
+CREATE TABLE token_signing_key (
+    kid TEXT NOT NULL PRIMARY KEY,
+    created_at DATETIME NOT NULL,
+    private_key_nonce BLOB NOT NULL,
+    private_key_ciph BLOB NOT NULL
+);
@@ -0,0 +1,21 @@
+-- Outbox of entity attribute changes, for downstream systems that mirror Authly.
+-- Subscribers keep track of the last `seq` they have processed.
+CREATE TABLE ent_attr_event (
+    seq INTEGER PRIMARY KEY AUTOINCREMENT,
+    eid BLOB NOT NULL,
+    attr_id BLOB NOT NULL,
+    added INTEGER NOT NULL,
+    created_at DATETIME NOT NULL
+);
+
+CREATE TRIGGER ent_attr_event_insert AFTER INSERT ON ent_attr
+BEGIN
+    INSERT INTO ent_attr_event (eid, attr_id, added, created_at)
+    SELECT NEW.eid, attr.id, 1, NEW.upd FROM attr WHERE attr.key = NEW.attr_key;
+END;
+
+CREATE TRIGGER ent_attr_event_delete AFTER DELETE ON ent_attr
+BEGIN
+    INSERT INTO ent_attr_event (eid, attr_id, added, created_at)
+    SELECT OLD.eid, attr.id, 0, CAST(strftime('%s', 'now') AS INTEGER) FROM attr WHERE attr.key = OLD.attr_key;
+END;
@@ -0,0 +1,20 @@
+-- Resources provisioned by SCIM clients, in addition to their identities and attributes.
+-- Entities listed here are owned by the SCIM directory.
+CREATE TABLE scim_resource (
+    dir_key INTEGER NOT NULL REFERENCES directory(key) DEFERRABLE INITIALLY DEFERRED,
+    eid BLOB NOT NULL PRIMARY KEY,
+    -- User or Group
+    resource_type TEXT NOT NULL,
+    external_id TEXT,
+    display_name TEXT,
+    active INTEGER NOT NULL,
+    created_at DATETIME NOT NULL,
+    upd DATETIME NOT NULL
+);
+
+-- Bearer tokens used by SCIM clients, each acting on behalf of a service entity
+CREATE TABLE scim_token (
+    token_hash BLOB NOT NULL PRIMARY KEY,
+    svc_eid BLOB NOT NULL,
+    created_at DATETIME NOT NULL
+);
@@ -0,0 +1,8 @@
+-- Just-in-time provisioning of personas logging in through an OAuth directory.
+-- `jit_allowed_domains` is a comma-separated list of email domains, all domains are allowed when NULL.
+-- `jit_claim_attributes` is a comma-separated list of `claim.path=namespace:property` mappings.
+-- Existing directories keep provisioning personas on their first login, like before this migration.
+ALTER TABLE dir_oauth ADD COLUMN jit_enabled INTEGER NOT NULL DEFAULT 1;
+ALTER TABLE dir_oauth ADD COLUMN jit_allowed_domains TEXT;
+ALTER TABLE dir_oauth ADD COLUMN jit_username_path TEXT;
+ALTER TABLE dir_oauth ADD COLUMN jit_claim_attributes TEXT;
@@ -0,0 +1,5 @@
+-- Passkeys are encrypted with the DEK of the builtin Passkey property.
+-- Encrypted rows store an empty `pk_json`. Rows where `pk_nonce` is NULL are legacy plaintext,
+-- which gets encrypted by the leader at startup.
+ALTER TABLE ent_passkey ADD COLUMN pk_nonce BLOB;
+ALTER TABLE ent_passkey ADD COLUMN pk_ciph BLOB;
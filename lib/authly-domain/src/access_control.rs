@@ -1,8 +1,35 @@
-use authly_common::id::{AttrId, ServiceId};
-use authly_db::DbError;
+use std::sync::Arc;
+
+use authly_common::{
+    id::{AttrId, EntityId, PolicyId, PropId, ServiceId},
+    policy::{
+        code::PolicyValue,
+        engine::{AccessControlParams, NoOpPolicyTracer, PolicyEngine},
+    },
+};
+use authly_db::{DbError, DbResult};
 use fnv::FnvHashSet;
+use futures_util::{stream, StreamExt, TryStreamExt};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use tracing::warn;
+
+use crate::{
+    audit::QueuedDecision,
+    ctx::{
+        GetAuditQueue, GetClock, GetDb, GetHttpClient, GetPolicyEngineCache, GetSettings, GetStats,
+    },
+    id::{BuiltinAttr, BuiltinProp},
+    repo::{
+        entity_repo, policy_repo,
+        quota_repo::{self, QuotaWindow},
+        service_repo::{self, PropertyKind},
+    },
+    settings::{AccessControlLimits, ExternalDecisionFailMode},
+};
 
-use crate::{ctx::GetDb, id::BuiltinAttr, repo::entity_repo};
+/// The maximum number of peer entities resolved concurrently for one access control request
+const PEER_ENTITY_CONCURRENCY: usize = 8;
 
 pub enum SvcAccessControlError {
     Denied,
@@ -40,6 +67,14 @@ pub mod role {
             BuiltinAttr::AuthlyRoleGrantMandate
         }
     }
+
+    pub struct Admin;
+
+    impl AuthlyRole for Admin {
+        fn role() -> BuiltinAttr {
+            BuiltinAttr::AuthlyRoleAdmin
+        }
+    }
 }
 
 pub trait VerifyAuthlyRole {
@@ -66,7 +101,7 @@ pub async fn authorize_peer_service(
     svc_eid: ServiceId,
     required_authly_roles: &[BuiltinAttr],
 ) -> Result<AuthorizedPeerService, SvcAccessControlError> {
-    let attributes = entity_repo::list_entity_attrs(deps.get_db(), svc_eid.upcast())
+    let attributes = entity_repo::list_resolved_entity_attrs(deps.get_db(), svc_eid.upcast())
         .await
         .map_err(SvcAccessControlError::Db)?;
 
@@ -81,3 +116,563 @@ pub async fn authorize_peer_service(
         attributes,
     })
 }
+
+/// Resolve the entity attributes of the peers of a service.
+///
+/// The property mappings of the peers are looked up concurrently, up to a fixed limit
+/// so that a single request can't occupy the whole database connection pool.
+pub async fn resolve_peer_entity_attrs(
+    deps: &impl GetDb,
+    peer_entity_ids: impl IntoIterator<Item = ServiceId>,
+) -> DbResult<FnvHashSet<AttrId>> {
+    stream::iter(peer_entity_ids)
+        .map(|peer_eid| {
+            service_repo::get_service_property_mapping(
+                deps.get_db(),
+                peer_eid,
+                PropertyKind::Entity,
+            )
+        })
+        .buffer_unordered(PEER_ENTITY_CONCURRENCY)
+        .try_fold(
+            FnvHashSet::default(),
+            |mut attrs, property_mapping| async move {
+                for (_, properties) in property_mapping {
+                    for (_, attributes) in properties {
+                        attrs.extend(attributes.into_iter().map(|(_, attr)| attr));
+                    }
+                }
+                Ok(attrs)
+            },
+        )
+        .await
+}
+
+/// An access control request with more attributes than the [AccessControlLimits] allow
+#[derive(thiserror::Error, Debug)]
+#[error("too many {side} attributes in request: {count}, at most {limit} are allowed")]
+pub struct AttributeLimitExceeded {
+    pub side: &'static str,
+    pub count: usize,
+    pub limit: usize,
+}
+
+/// Check the number of attributes sent in an access control request.
+///
+/// This must be done before the attributes are parsed or resolved,
+/// so that the cost of an oversized request is never paid.
+pub fn check_attribute_limits(
+    limits: AccessControlLimits,
+    subject_attrs: usize,
+    resource_attrs: usize,
+) -> Result<(), AttributeLimitExceeded> {
+    for (side, count, limit) in [
+        ("subject", subject_attrs, limits.max_subject_attributes),
+        ("resource", resource_attrs, limits.max_resource_attributes),
+    ] {
+        if count > limit {
+            return Err(AttributeLimitExceeded { side, count, limit });
+        }
+    }
+
+    Ok(())
+}
+
+pub enum ResourceAttrError {
+    /// The attribute is not a resource attribute of any namespace
+    Unknown(AttrId),
+    /// The attribute belongs to a namespace the service does not own, and has not been granted through a service domain
+    ForeignNamespace {
+        attr: AttrId,
+        namespace: String,
+    },
+    Db(DbError),
+}
+
+/// Resolve the resource attributes of an access control request.
+///
+/// Every `required` attribute must be a resource attribute of the service, so an unknown attribute is rejected.
+/// This isolates the namespaces of services from each other:
+/// a service can't inject attributes of other namespaces, whose policies it should have no say in.
+///
+/// An `optional` attribute the service does not know is left out, as if the resource does not have it.
+pub async fn resolve_resource_attrs(
+    deps: &impl GetDb,
+    svc_eid: ServiceId,
+    required: impl IntoIterator<Item = AttrId>,
+    optional: impl IntoIterator<Item = AttrId>,
+) -> Result<FnvHashSet<AttrId>, ResourceAttrError> {
+    let property_mapping =
+        service_repo::get_service_property_mapping(deps.get_db(), svc_eid, PropertyKind::Resource)
+            .await
+            .map_err(ResourceAttrError::Db)?;
+
+    let known: FnvHashSet<AttrId> = property_mapping
+        .into_iter()
+        .flat_map(|(_, properties)| properties)
+        .flat_map(|(_, attributes)| attributes)
+        .map(|(_, attr)| attr)
+        .collect();
+
+    let mut resource_attrs = FnvHashSet::default();
+    for attr in required {
+        if !known.contains(&attr) {
+            return Err(
+                match service_repo::find_resource_attr_namespace_label(deps.get_db(), attr)
+                    .await
+                    .map_err(ResourceAttrError::Db)?
+                {
+                    Some(namespace) => ResourceAttrError::ForeignNamespace { attr, namespace },
+                    None => ResourceAttrError::Unknown(attr),
+                },
+            );
+        }
+        resource_attrs.insert(attr);
+    }
+    resource_attrs.extend(optional.into_iter().filter(|attr| known.contains(attr)));
+
+    Ok(resource_attrs)
+}
+
+/// The reason behind an access control decision
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DecisionReason {
+    /// A policy allowed access
+    Allowed,
+    /// The request carried neither an access token nor any peer entity attributes
+    NoSubject,
+    /// The request carried no resource attributes
+    NoResource,
+    /// The service is not bound to any policies
+    NoPolicies,
+    /// The applicable policies did not allow access
+    PolicyDenied,
+    /// Policy evaluation failed
+    EvaluationError,
+    /// The external policy decision point of the service allowed access
+    ExternalAllowed,
+    /// The external policy decision point of the service denied access
+    ExternalDenied,
+    /// The external policy decision point of the service was unavailable, and the fail mode decided
+    ExternalUnavailable,
+    /// Access was allowed, but the subject has used up its quota for the resource
+    QuotaExceeded,
+    /// Access was allowed, but the quota could not be counted because the node is in read-only mode
+    QuotaUnavailable,
+    /// Access was allowed, but the decision could not be queued for the audit log
+    AuditUnavailable,
+}
+
+impl DecisionReason {
+    /// A stable, machine readable code for this reason
+    pub const fn code(self) -> &'static str {
+        match self {
+            Self::Allowed => "allowed",
+            Self::NoSubject => "no_subject",
+            Self::NoResource => "no_resource",
+            Self::NoPolicies => "no_policies",
+            Self::PolicyDenied => "policy_denied",
+            Self::EvaluationError => "evaluation_error",
+            Self::ExternalAllowed => "external_allowed",
+            Self::ExternalDenied => "external_denied",
+            Self::ExternalUnavailable => "external_unavailable",
+            Self::QuotaExceeded => "quota_exceeded",
+            Self::QuotaUnavailable => "quota_unavailable",
+            Self::AuditUnavailable => "audit_unavailable",
+        }
+    }
+
+    /// A human readable explanation
+    pub const fn message(self) -> &'static str {
+        match self {
+            Self::Allowed => "access allowed by policy",
+            Self::NoSubject => "no access token or peer entity attributes in request",
+            Self::NoResource => "no resource attributes in request",
+            Self::NoPolicies => "no policies are bound to the service",
+            Self::PolicyDenied => "access denied by policy",
+            Self::EvaluationError => "policy evaluation failed",
+            Self::ExternalAllowed => "access allowed by external decision point",
+            Self::ExternalDenied => "access denied by external decision point",
+            Self::ExternalUnavailable => "external decision point unavailable",
+            Self::QuotaExceeded => "access quota exceeded",
+            Self::QuotaUnavailable => "access quota can't be counted in read-only mode",
+            Self::AuditUnavailable => "access control audit unavailable",
+        }
+    }
+}
+
+pub struct AccessControlDecision {
+    pub value: PolicyValue,
+    pub reason: DecisionReason,
+}
+
+impl AccessControlDecision {
+    /// A decision of the local policy engine, which only allows with [DecisionReason::Allowed]
+    fn local(reason: DecisionReason) -> Self {
+        Self {
+            value: match reason {
+                DecisionReason::Allowed => PolicyValue::Allow,
+                _ => PolicyValue::Deny,
+            },
+            reason,
+        }
+    }
+}
+
+/// Get the policy engine of a service, loading it into the cache if necessary.
+///
+/// The returned engine must be used for the whole evaluation of a request,
+/// it stays the same even if the policies are reloaded in the meantime.
+pub async fn svc_policy_engine(
+    deps: &(impl GetDb + GetPolicyEngineCache),
+    svc_eid: ServiceId,
+) -> DbResult<Arc<PolicyEngine>> {
+    let cache = deps.get_policy_engine_cache();
+    if let Some(engine) = cache.get(svc_eid) {
+        return Ok(engine);
+    }
+
+    let generation = cache.generation();
+    let engine = Arc::new(policy_repo::load_svc_policy_engine(deps.get_db(), svc_eid).await?);
+    cache.insert(generation, svc_eid, engine.clone());
+
+    Ok(engine)
+}
+
+/// Evaluate an access control request, and explain the outcome.
+///
+/// The engine denies by default, so when access is denied the request itself is inspected
+/// to tell an incomplete request apart from an actual policy denial.
+pub fn evaluate(engine: &PolicyEngine, params: &AccessControlParams) -> AccessControlDecision {
+    let reason = match engine.eval(params, &mut NoOpPolicyTracer) {
+        Ok(PolicyValue::Allow) => DecisionReason::Allowed,
+        Ok(_) => {
+            if params.subject_attrs.is_empty() && params.subject_eids.is_empty() {
+                DecisionReason::NoSubject
+            } else if params.resource_attrs.is_empty() {
+                DecisionReason::NoResource
+            } else if engine.get_policy_count() == 0 {
+                DecisionReason::NoPolicies
+            } else {
+                DecisionReason::PolicyDenied
+            }
+        }
+        Err(err) => {
+            warn!(?err, "policy engine error");
+            DecisionReason::EvaluationError
+        }
+    };
+
+    AccessControlDecision::local(reason)
+}
+
+/// Evaluate an access control request with the local policy engine of a service.
+///
+/// When the decision cache is enabled, a decision made for the same inputs by the current engine is reused until it expires.
+/// Evaluation errors are never cached.
+pub async fn evaluate_local(
+    deps: &(impl GetDb + GetSettings + GetPolicyEngineCache + GetClock),
+    svc_eid: ServiceId,
+    params: &AccessControlParams,
+) -> DbResult<AccessControlDecision> {
+    let config = deps.get_settings().decision_cache;
+    let cache = deps.get_policy_engine_cache();
+    let now = deps.get_clock().now();
+
+    // read before loading the engine, a decision must never be cached for a newer generation than its engine
+    let generation = cache.generation();
+    if config.ttl.is_some() {
+        if let Some(reason) = cache.get_decision(generation, svc_eid, params, now) {
+            return Ok(AccessControlDecision::local(reason));
+        }
+    }
+
+    let engine = svc_policy_engine(deps, svc_eid).await?;
+    let decision = evaluate(&engine, params);
+    if decision.reason != DecisionReason::EvaluationError {
+        cache.insert_decision(generation, svc_eid, params, decision.reason, now, config);
+    }
+
+    Ok(decision)
+}
+
+/// The request sent to an external policy decision point, in the shape of an OPA data API request
+#[derive(Serialize)]
+struct ExternalDecisionRequest {
+    input: ExternalDecisionInput,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ExternalDecisionInput {
+    service_id: ServiceId,
+    subject_entity_ids: Vec<ExternalSubjectEntityId>,
+    subject_attributes: Vec<AttrId>,
+    resource_attributes: Vec<AttrId>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ExternalSubjectEntityId {
+    property_id: PropId,
+    entity_id: EntityId,
+}
+
+/// The response from an external policy decision point, `true` allows access
+#[derive(Deserialize)]
+struct ExternalDecisionResponse {
+    result: bool,
+}
+
+/// Delegate an access control request to the external policy decision point configured for the service.
+///
+/// Returns `None` when the service has no external decision point,
+/// or when it is unavailable and the `EXTERNAL_DECISION_FAIL_MODE` is `local`.
+pub async fn evaluate_external(
+    deps: &(impl GetDb + GetSettings + GetHttpClient),
+    svc_eid: ServiceId,
+    params: &AccessControlParams,
+) -> DbResult<Option<AccessControlDecision>> {
+    let external_decision = deps.get_settings().external_decision.clone();
+    if external_decision.points.is_empty() {
+        return Ok(None);
+    }
+
+    let Some(svc_label) = service_repo::find_service_label_by_eid(deps.get_db(), svc_eid).await?
+    else {
+        return Ok(None);
+    };
+    let Some(point) = external_decision
+        .points
+        .iter()
+        .find(|point| point.service == svc_label)
+    else {
+        return Ok(None);
+    };
+
+    let mut subject_attributes: Vec<AttrId> = params.subject_attrs.iter().copied().collect();
+    subject_attributes.sort();
+    let mut resource_attributes: Vec<AttrId> = params.resource_attrs.iter().copied().collect();
+    resource_attributes.sort();
+
+    let request = ExternalDecisionRequest {
+        input: ExternalDecisionInput {
+            service_id: svc_eid,
+            subject_entity_ids: params
+                .subject_eids
+                .iter()
+                .map(|(prop_id, entity_id)| ExternalSubjectEntityId {
+                    property_id: *prop_id,
+                    entity_id: *entity_id,
+                })
+                .collect(),
+            subject_attributes,
+            resource_attributes,
+        },
+    };
+
+    let result = async {
+        deps.get_internet_http_client()
+            .post(point.url.clone())
+            .timeout(external_decision.timeout)
+            .json(&request)
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<ExternalDecisionResponse>()
+            .await
+    }
+    .await;
+
+    let reason = match result {
+        Ok(ExternalDecisionResponse { result: true }) => DecisionReason::ExternalAllowed,
+        Ok(ExternalDecisionResponse { result: false }) => DecisionReason::ExternalDenied,
+        Err(err) => {
+            warn!(?err, url = %point.url, "external policy decision point unavailable");
+
+            return Ok(match external_decision.fail_mode {
+                ExternalDecisionFailMode::Local => None,
+                ExternalDecisionFailMode::Open => Some(AccessControlDecision {
+                    value: PolicyValue::Allow,
+                    reason: DecisionReason::ExternalUnavailable,
+                }),
+                ExternalDecisionFailMode::Closed => Some(AccessControlDecision {
+                    value: PolicyValue::Deny,
+                    reason: DecisionReason::ExternalUnavailable,
+                }),
+            });
+        }
+    };
+
+    Ok(Some(AccessControlDecision {
+        value: match reason {
+            DecisionReason::ExternalAllowed => PolicyValue::Allow,
+            _ => PolicyValue::Deny,
+        },
+        reason,
+    }))
+}
+
+/// Enforce the `ACCESS_CONTROL_QUOTA` settings on a decision, consuming quota when access is allowed.
+///
+/// A quota applies when the resource has its attribute and the subject is an entity.
+/// Windows are fixed, aligned to multiples of the window length since the Unix epoch.
+/// Denied requests never consume quota, and an allowed request exceeding any of its quotas is denied
+/// without consuming any of the others.
+///
+/// Quotas fail closed: in read-only mode, where quota can't be consumed, a request that has a quota is denied.
+pub async fn enforce_quotas(
+    deps: &(impl GetDb + GetSettings + GetStats),
+    params: &AccessControlParams,
+    decision: AccessControlDecision,
+    now: OffsetDateTime,
+) -> DbResult<AccessControlDecision> {
+    let quotas = deps.get_settings().access_control_quotas.clone();
+    if quotas.is_empty() || !matches!(decision.value, PolicyValue::Allow) {
+        return Ok(decision);
+    }
+    let Some(subject_eid) = params
+        .subject_eids
+        .get(&PropId::from(BuiltinProp::Entity))
+        .copied()
+    else {
+        return Ok(decision);
+    };
+
+    let mut windows = vec![];
+    for quota in quotas {
+        let attr_id = quota_repo::find_quota_attr_id(
+            deps.get_db(),
+            &quota.namespace,
+            &quota.property,
+            &quota.attribute,
+        )
+        .await?;
+
+        let Some(attr_id) = attr_id.filter(|attr_id| params.resource_attrs.contains(attr_id))
+        else {
+            continue;
+        };
+
+        let window = quota.window.as_secs() as i64;
+        windows.push(QuotaWindow {
+            attr_id,
+            window_start: now.unix_timestamp().div_euclid(window) * window,
+            limit: quota.limit,
+        });
+    }
+
+    if !windows.is_empty() && deps.is_read_only() {
+        return Ok(AccessControlDecision {
+            value: PolicyValue::Deny,
+            reason: DecisionReason::QuotaUnavailable,
+        });
+    }
+
+    if !quota_repo::try_consume_all(deps.get_db(), subject_eid, &windows).await? {
+        return Ok(AccessControlDecision {
+            value: PolicyValue::Deny,
+            reason: DecisionReason::QuotaExceeded,
+        });
+    }
+
+    Ok(decision)
+}
+
+/// Queue an access control decision for the audit log, as configured by the `ACCESS_CONTROL_AUDIT` settings.
+///
+/// The subject is only recorded by its entity ID and the resource by its attribute IDs.
+/// Subject attributes are left out, as they may reveal more about a user than the decision needs.
+/// The record is written later by the [AuditAppender](crate::audit::AuditAppender), off the request path.
+///
+/// Auditing fails closed: an allowing decision that can't be queued is turned into a denial.
+/// In read-only mode, where the audit log can't be appended to, audited decisions are not queued
+/// and allowing ones are denied right away, instead of once the queue has filled up.
+pub fn audit_decision(
+    deps: &(impl GetSettings + GetAuditQueue + GetStats),
+    svc_eid: ServiceId,
+    params: AccessControlParams,
+    decision: AccessControlDecision,
+    now: OffsetDateTime,
+) -> AccessControlDecision {
+    let allowed = matches!(decision.value, PolicyValue::Allow);
+    let audit = deps.get_settings().access_control_audit;
+    if !audit.is_audited(allowed) || rand::random::<f64>() >= audit.sample_rate {
+        return decision;
+    }
+
+    let audit_unavailable = AccessControlDecision {
+        value: PolicyValue::Deny,
+        reason: if allowed {
+            DecisionReason::AuditUnavailable
+        } else {
+            decision.reason
+        },
+    };
+
+    if deps.is_read_only() {
+        warn!(
+            ?svc_eid,
+            "unable to audit access control decision in read-only mode"
+        );
+        return audit_unavailable;
+    }
+
+    let queued = deps.get_audit_queue().enqueue(QueuedDecision {
+        created_at: now,
+        svc_eid,
+        params,
+        allowed,
+        reason: decision.reason,
+    });
+
+    match queued {
+        Ok(()) => decision,
+        Err(err) => {
+            warn!(?err, ?svc_eid, "unable to audit access control decision");
+            audit_unavailable
+        }
+    }
+}
+
+/// Find the first policy of the given class that applies to the request.
+///
+/// Each candidate is evaluated on its own, as an allow policy, so that a match shows up as [PolicyValue::Allow].
+pub(crate) async fn find_deciding_policy(
+    deps: &impl GetDb,
+    svc_eid: ServiceId,
+    params: &AccessControlParams,
+    class: &PolicyValue,
+) -> DbResult<Option<PolicyId>> {
+    let policy_data = policy_repo::load_svc_policies_with_bindings(deps.get_db(), svc_eid).await?;
+
+    for policy in &policy_data.policies {
+        if std::mem::discriminant(&policy.data().class) != std::mem::discriminant(class) {
+            continue;
+        }
+
+        let mut engine = PolicyEngine::default();
+        engine.add_policy(
+            *policy.id(),
+            PolicyValue::Allow,
+            policy.data().to_bytecode(),
+        );
+        for binding in &policy_data.bindings {
+            if binding.policies.contains(policy.id()) {
+                engine.add_trigger(
+                    binding.attr_matcher.clone(),
+                    [*policy.id()].into_iter().collect(),
+                );
+            }
+        }
+
+        if matches!(
+            engine.eval(params, &mut NoOpPolicyTracer),
+            Ok(PolicyValue::Allow)
+        ) {
+            return Ok(Some(*policy.id()));
+        }
+    }
+
+    Ok(None)
+}
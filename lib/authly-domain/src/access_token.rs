@@ -5,11 +5,16 @@
 //! The access token is implemented as a JSON Web Token.
 //! The access token is used directly when doing access control.
 //!
+//! Large attribute sets can be left out of the token and replaced by an [AttributesRef],
+//! to keep the token within the header size limits of proxies.
+//! The attribute set is stored when the token is issued, and resolved by the entity and the reference:
+//! by Authly when it verifies the token, and by services verifying tokens locally through the `GetEntityAttributes` gRPC method.
 
 use authly_common::{
     access_token::{Authly, AuthlyAccessTokenClaims},
-    id::AttrId,
+    id::{AttrId, EntityId, Id128DynamicArrayConv, ServiceId},
 };
+use authly_db::{DbError, DbResult};
 use axum::RequestPartsExt;
 use axum_extra::{
     headers::{authorization::Bearer, Authorization},
@@ -17,46 +22,288 @@ use axum_extra::{
 };
 use fnv::FnvHashSet;
 use http::{request::Parts, StatusCode};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use time::OffsetDateTime;
 
-use crate::{ctx::GetInstance, instance::AuthlyInstance, session::Session};
+use crate::{
+    ctx::{GetClock, GetDb, GetInstance, GetSettings},
+    instance::AuthlyInstance,
+    repo::{scim_repo, service_repo, token_attrs_repo},
+    session::Session,
+};
+
+/// The lifetime of an access token
+pub const EXPIRATION: time::Duration = time::Duration::days(365);
 
-const EXPIRATION: time::Duration = time::Duration::days(365);
+/// How long past its expiry an access token is still accepted, to tolerate clock skew between issuer and verifier
+const EXPIRATION_LEEWAY: time::Duration = time::Duration::seconds(60);
 
 #[derive(Debug)]
 pub enum AccessTokenError {
     EncodeError,
 
     Unverified(anyhow::Error),
+
+    Db(DbError),
+}
+
+/// The attribute set of an access token, left out of the token itself
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize, Debug)]
+pub struct AttributesRef {
+    /// The service the token was issued to, which decides the projected attributes
+    pub service: ServiceId,
+
+    /// Identifies the attribute set stored for the entity when the token was issued
+    pub id: String,
 }
 
-/// An access token is created from scratch every time.
+impl AttributesRef {
+    /// The reference to an attribute set of an entity, issued to a service.
+    ///
+    /// Issuing the same set again gives the same reference.
+    pub fn new(eid: EntityId, service: ServiceId, attributes: &FnvHashSet<AttrId>) -> Self {
+        let mut hasher = Sha256::new();
+        hasher.update(eid.to_array_dynamic());
+        hasher.update(service.to_array_dynamic());
+        hasher.update(attribute_set_hash(attributes));
+
+        Self {
+            service,
+            id: hexhex::hex(hasher.finalize()).to_string(),
+        }
+    }
+}
+
+/// How the user attributes are carried in an access token
+pub enum TokenAttributes {
+    Embedded(FnvHashSet<AttrId>),
+    /// The attributes are stored under the reference, which the token carries instead
+    Referenced(AttributesRef, FnvHashSet<AttrId>),
+}
+
+/// The claims of an access token issued by Authly
+#[derive(Serialize, Deserialize)]
+pub struct AccessTokenClaims {
+    #[serde(flatten)]
+    pub claims: AuthlyAccessTokenClaims,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub authly_attributes_ref: Option<AttributesRef>,
+}
+
+/// Create an access token with the attributes embedded.
 ///
-/// This is likely to be pretty "hot", request wise, consider caching the JWT in memory based on the session token.
-/// There's a benchmark for it which reveals it runs in about 30 µs on my development machine.
-pub fn create_access_token(
+/// There's a benchmark for it which reveals it runs in about 30 µs on my development machine,
+/// unless the signing key is remote, see [crate::token_signing::TokenSigningKey::encode_access_token].
+pub async fn create_access_token(
     session: &Session,
     user_attributes: FnvHashSet<AttrId>,
     instance: &AuthlyInstance,
+    now: OffsetDateTime,
+) -> Result<String, AccessTokenError> {
+    let claims = AccessTokenClaims {
+        claims: create_access_token_claims(session, user_attributes, now),
+        authly_attributes_ref: None,
+    };
+
+    sign_access_token(&claims, instance, now).await
+}
+
+/// Create an access token with the given attributes, embedded or by reference.
+///
+/// Referenced attributes are stored for as long as the token is valid.
+pub async fn create_access_token_with(
+    deps: &impl GetDb,
+    session: &Session,
+    attributes: TokenAttributes,
+    instance: &AuthlyInstance,
+    now: OffsetDateTime,
 ) -> Result<String, AccessTokenError> {
-    let jwt_header = jsonwebtoken::Header::new(jsonwebtoken::Algorithm::ES256);
-    let claims = create_access_token_claims(session, user_attributes);
+    match attributes {
+        TokenAttributes::Embedded(attributes) => {
+            create_access_token(session, attributes, instance, now).await
+        }
+        TokenAttributes::Referenced(attributes_ref, attributes) => {
+            token_attrs_repo::upsert_token_attrs_ref(
+                deps.get_db(),
+                session.eid,
+                &attributes_ref.id,
+                attributes_ref.service,
+                &attributes,
+                now + EXPIRATION + EXPIRATION_LEEWAY,
+                now,
+            )
+            .await
+            .map_err(AccessTokenError::Db)?;
 
-    jsonwebtoken::encode(&jwt_header, &claims, &instance.local_jwt_encoding_key())
+            let claims = AccessTokenClaims {
+                claims: create_access_token_claims(session, FnvHashSet::default(), now),
+                authly_attributes_ref: Some(attributes_ref),
+            };
+
+            sign_access_token(&claims, instance, now).await
+        }
+    }
+}
+
+async fn sign_access_token(
+    claims: &AccessTokenClaims,
+    instance: &AuthlyInstance,
+    now: OffsetDateTime,
+) -> Result<String, AccessTokenError> {
+    let signing_key = instance.token_signing_key();
+    let mut jwt_header = jsonwebtoken::Header::new(jsonwebtoken::Algorithm::ES256);
+    jwt_header.kid = Some(signing_key.kid().to_string());
+
+    signing_key
+        .encode_access_token(&jwt_header, claims, claims_key(claims), now)
+        .await
         .map_err(|_| AccessTokenError::EncodeError)
 }
 
+/// Identifies the claims of an access token, apart from their timestamps
+fn claims_key(claims: &AccessTokenClaims) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(claims.claims.authly.entity_id.to_array_dynamic());
+    hasher.update(attribute_set_hash(&claims.claims.authly.entity_attributes));
+    if let Some(attributes_ref) = &claims.authly_attributes_ref {
+        hasher.update(attributes_ref.service.to_array_dynamic());
+        hasher.update(&attributes_ref.id);
+    }
+
+    hasher.finalize().into()
+}
+
+/// Select how the attributes of a user are carried in an access token issued to the given service.
+///
+/// The attributes are embedded, unless the set is larger than the `TOKEN_ATTRIBUTE_EMBED_LIMIT` of the service.
+pub async fn select_token_attributes(
+    deps: &(impl GetDb + GetSettings),
+    eid: EntityId,
+    svc_eid: ServiceId,
+    user_attributes: FnvHashSet<AttrId>,
+) -> DbResult<TokenAttributes> {
+    let user_attributes = project_token_attributes(deps, svc_eid, user_attributes).await?;
+    let embed_limits = deps.get_settings().token_attribute_embed_limit.clone();
+    if embed_limits
+        .iter()
+        .all(|item| user_attributes.len() <= item.limit)
+    {
+        return Ok(TokenAttributes::Embedded(user_attributes));
+    }
+
+    let Some(svc_label) = service_repo::find_service_label_by_eid(deps.get_db(), svc_eid).await?
+    else {
+        return Ok(TokenAttributes::Embedded(user_attributes));
+    };
+
+    match embed_limits.iter().find(|item| item.service == svc_label) {
+        Some(item) if user_attributes.len() > item.limit => Ok(TokenAttributes::Referenced(
+            AttributesRef::new(eid, svc_eid, &user_attributes),
+            user_attributes,
+        )),
+        _ => Ok(TokenAttributes::Embedded(user_attributes)),
+    }
+}
+
+/// Resolve the attribute set referenced by an access token of an entity.
+///
+/// The attributes are the ones the token was issued with, like embedded attributes.
+pub async fn resolve_attributes_ref(
+    deps: &impl GetDb,
+    eid: EntityId,
+    attributes_ref: &AttributesRef,
+    now: OffsetDateTime,
+) -> Result<FnvHashSet<AttrId>, AccessTokenError> {
+    token_attrs_repo::find_token_attrs_ref(
+        deps.get_db(),
+        eid,
+        &attributes_ref.id,
+        attributes_ref.service,
+        now,
+    )
+    .await
+    .map_err(AccessTokenError::Db)?
+    .ok_or_else(|| AccessTokenError::Unverified(anyhow::anyhow!("unknown attribute reference")))
+}
+
+/// An order independent hash of an attribute set
+fn attribute_set_hash(attributes: &FnvHashSet<AttrId>) -> String {
+    let mut attributes: Vec<_> = attributes
+        .iter()
+        .map(|attr_id| attr_id.to_array_dynamic().to_vec())
+        .collect();
+    attributes.sort();
+
+    let mut hasher = Sha256::new();
+    for attr_id in attributes {
+        hasher.update(attr_id);
+    }
+
+    hexhex::hex(hasher.finalize()).to_string()
+}
+
+/// Select the attributes of a user to put in an access token issued to the given service.
+///
+/// All attributes are included, unless the `TOKEN_ATTRIBUTE_PROJECTION` setting
+/// restricts the service to the attributes of specific entity properties.
+pub async fn project_token_attributes(
+    deps: &(impl GetDb + GetSettings),
+    svc_eid: ServiceId,
+    mut user_attributes: FnvHashSet<AttrId>,
+) -> DbResult<FnvHashSet<AttrId>> {
+    let projection = deps.get_settings().token_attribute_projection.clone();
+    if projection.is_empty() {
+        return Ok(user_attributes);
+    }
+
+    let Some(svc_label) = service_repo::find_service_label_by_eid(deps.get_db(), svc_eid).await?
+    else {
+        return Ok(user_attributes);
+    };
+
+    let mut projected = FnvHashSet::default();
+    let mut is_projected = false;
+
+    for item in projection.iter().filter(|item| item.service == svc_label) {
+        is_projected = true;
+        projected.extend(
+            scim_repo::list_labeled_property_attrs(deps.get_db(), &item.namespace, &item.property)
+                .await?
+                .into_iter()
+                .map(|(attr_id, _)| attr_id),
+        );
+    }
+
+    if is_projected {
+        user_attributes.retain(|attr_id| projected.contains(attr_id));
+    }
+
+    Ok(user_attributes)
+}
+
 pub fn create_access_token_claims(
     session: &Session,
     user_attributes: FnvHashSet<AttrId>,
+    now: OffsetDateTime,
+) -> AuthlyAccessTokenClaims {
+    entity_access_token_claims(session.eid, user_attributes, now)
+}
+
+/// The claims of an access token issued to an entity, without signing them
+pub fn entity_access_token_claims(
+    eid: EntityId,
+    user_attributes: FnvHashSet<AttrId>,
+    now: OffsetDateTime,
 ) -> AuthlyAccessTokenClaims {
-    let now = time::OffsetDateTime::now_utc();
     let expiration = now + EXPIRATION;
 
     AuthlyAccessTokenClaims {
         iat: now.unix_timestamp(),
         exp: expiration.unix_timestamp(),
         authly: Authly {
-            entity_id: session.eid,
+            entity_id: eid,
             entity_attributes: user_attributes,
         },
     }
@@ -65,15 +312,60 @@ pub fn create_access_token_claims(
 pub fn verify_access_token(
     access_token: &str,
     instance: &AuthlyInstance,
+    now: OffsetDateTime,
+) -> Result<AuthlyAccessTokenClaims, AccessTokenError> {
+    decode_access_token(access_token, instance, now).map(|claims| claims.claims)
+}
+
+/// Verify an access token, resolving the attributes of tokens carrying an [AttributesRef]
+pub async fn verify_and_resolve_access_token(
+    access_token: &str,
+    deps: &(impl GetDb + GetInstance + GetClock),
 ) -> Result<AuthlyAccessTokenClaims, AccessTokenError> {
-    let validation = jsonwebtoken::Validation::new(jsonwebtoken::Algorithm::ES256);
-    let token_data = jsonwebtoken::decode::<AuthlyAccessTokenClaims>(
+    let now = deps.get_clock().now();
+    let AccessTokenClaims {
+        mut claims,
+        authly_attributes_ref,
+    } = decode_access_token(access_token, &deps.get_instance(), now)?;
+
+    if let Some(attributes_ref) = authly_attributes_ref {
+        claims.authly.entity_attributes =
+            resolve_attributes_ref(deps, claims.authly.entity_id, &attributes_ref, now).await?;
+    }
+
+    Ok(claims)
+}
+
+/// Verify an access token, without resolving an attribute reference.
+///
+/// The expiry is checked against `now` rather than the system clock.
+pub fn decode_access_token(
+    access_token: &str,
+    instance: &AuthlyInstance,
+    now: OffsetDateTime,
+) -> Result<AccessTokenClaims, AccessTokenError> {
+    let jwt_header = jsonwebtoken::decode_header(access_token)
+        .map_err(|err| AccessTokenError::Unverified(err.into()))?;
+    let signing_key = instance
+        .token_signing_keys()
+        .find(jwt_header.kid.as_deref(), now)
+        .ok_or_else(|| AccessTokenError::Unverified(anyhow::anyhow!("unknown signing key")))?;
+
+    let mut validation = jsonwebtoken::Validation::new(jsonwebtoken::Algorithm::ES256);
+    validation.validate_exp = false;
+    let token_data = jsonwebtoken::decode::<AccessTokenClaims>(
         access_token,
-        instance.local_jwt_decoding_key(),
+        signing_key.decoding_key(),
         &validation,
     )
     .map_err(|err| AccessTokenError::Unverified(err.into()))?;
 
+    if token_data.claims.claims.exp < (now - EXPIRATION_LEEWAY).unix_timestamp() {
+        return Err(AccessTokenError::Unverified(anyhow::anyhow!(
+            "access token expired"
+        )));
+    }
+
     Ok(token_data.claims)
 }
 
@@ -84,7 +376,7 @@ pub struct VerifiedAccessToken {
 
 impl<Ctx: Sync> axum::extract::FromRequestParts<Ctx> for VerifiedAccessToken
 where
-    Ctx: GetInstance + Send + Sync,
+    Ctx: GetDb + GetInstance + GetSettings + GetClock + Send + Sync,
 {
     type Rejection = (StatusCode, &'static str);
 
@@ -95,7 +387,8 @@ where
             .await
             .map_err(|_| (StatusCode::UNAUTHORIZED, "no access token"))?;
 
-        let claims = verify_access_token(authorization.token(), &ctx.get_instance())
+        let claims = verify_and_resolve_access_token(authorization.token(), ctx)
+            .await
             .map_err(|_| (StatusCode::UNAUTHORIZED, "invalid access token"))?;
 
         Ok(Self { claims })
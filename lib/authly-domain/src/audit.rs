@@ -1,5 +1,437 @@
-use authly_common::id::EntityId;
+use std::{io::Write, time::Duration};
+
+use authly_common::{
+    id::{AttrId, EntityId, PolicyId, PropId, ServiceId},
+    policy::{code::PolicyValue, engine::AccessControlParams},
+};
+use authly_db::{Db, DbError, DbResult};
+use serde::Serialize;
+use tokio::sync::mpsc;
+use tracing::warn;
+
+use crate::{
+    access_control::{self, DecisionReason},
+    bus::entity_events::announce_events,
+    ctx::{ClusterBus, GetDb, GetDecryptedDeks, GetSettings},
+    encryption::{AesKey, DecryptedDeks},
+    id::BuiltinProp,
+    repo::access_control_audit_repo::{self, DbAccessControlAudit},
+    IsLeaderDb,
+};
+
+/// How many records are read from the database at a time when exporting
+const EXPORT_BATCH_SIZE: usize = 1000;
+
+/// How many access control decisions can wait to be recorded
+const AUDIT_QUEUE_CAPACITY: usize = 4096;
+
+/// How many queued access control decisions are recorded in one transaction
+const APPEND_BATCH_SIZE: usize = 256;
+
+/// How long to wait before retrying a batch of decisions that could not be recorded
+const APPEND_RETRY_DELAY: Duration = Duration::from_secs(1);
 
 /// The response Actor behind some action
 #[derive(Clone, Copy, Debug)]
 pub struct Actor(pub EntityId);
+
+/// A recorded access control decision, as presented outside Authly
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccessControlAuditEntry {
+    pub id: i64,
+    #[serde(with = "time::serde::rfc3339")]
+    pub created_at: time::OffsetDateTime,
+    pub service_id: ServiceId,
+    pub subject_id: Option<EntityId>,
+    pub resource_attributes: Vec<AttrId>,
+    pub allowed: bool,
+    pub reason: String,
+    pub policy_id: Option<PolicyId>,
+}
+
+impl From<DbAccessControlAudit> for AccessControlAuditEntry {
+    fn from(record: DbAccessControlAudit) -> Self {
+        Self {
+            id: record.id,
+            created_at: record.created_at,
+            service_id: record.svc_eid,
+            subject_id: record.subject_eid,
+            resource_attributes: record.resource_attrs,
+            allowed: record.allowed,
+            reason: record.reason,
+            policy_id: record.policy_id,
+        }
+    }
+}
+
+/// An access control decision waiting to be recorded in the audit log
+pub struct QueuedDecision {
+    pub created_at: time::OffsetDateTime,
+    pub svc_eid: ServiceId,
+    pub params: AccessControlParams,
+    pub allowed: bool,
+    pub reason: DecisionReason,
+}
+
+/// The audit queue can't take more decisions
+#[derive(thiserror::Error, Debug)]
+#[error("access control audit queue is full")]
+pub struct AuditQueueFull;
+
+/// The sending side of the queue of access control decisions to record
+#[derive(Clone)]
+pub struct AuditQueue(mpsc::Sender<QueuedDecision>);
+
+impl AuditQueue {
+    /// Create the queue, with the appender that records the queued decisions
+    pub fn new() -> (Self, AuditAppender) {
+        Self::with_capacity(AUDIT_QUEUE_CAPACITY)
+    }
+
+    /// Create a queue holding at most `capacity` decisions waiting to be recorded
+    pub fn with_capacity(capacity: usize) -> (Self, AuditAppender) {
+        let (sender, receiver) = mpsc::channel(capacity);
+        (
+            Self(sender),
+            AuditAppender {
+                receiver,
+                batch: Vec::with_capacity(APPEND_BATCH_SIZE),
+            },
+        )
+    }
+
+    /// Queue a decision without waiting.
+    ///
+    /// Fails when the queue is full, which happens when the appender can't keep up or can't write,
+    /// so that the caller can refuse access instead of leaving it unaudited.
+    pub fn enqueue(&self, decision: QueuedDecision) -> Result<(), AuditQueueFull> {
+        self.0.try_send(decision).map_err(|_| AuditQueueFull)
+    }
+}
+
+/// Records queued access control decisions in the audit log, in batches.
+///
+/// A batch that can't be recorded is kept and retried, and no more decisions are taken
+/// from the queue until it is recorded. The queue then fills up, and further audited decisions are denied.
+pub struct AuditAppender {
+    receiver: mpsc::Receiver<QueuedDecision>,
+    batch: Vec<QueuedDecision>,
+}
+
+impl AuditAppender {
+    /// Wait for queued decisions and record them, or retry the batch that could not be recorded.
+    ///
+    /// Returns `false` when the queue is gone, so there will be nothing more to record.
+    pub async fn append_next(
+        &mut self,
+        deps: &(impl GetDb + GetDecryptedDeks + ClusterBus),
+    ) -> bool {
+        if self.batch.is_empty() {
+            if self
+                .receiver
+                .recv_many(&mut self.batch, APPEND_BATCH_SIZE)
+                .await
+                == 0
+            {
+                return false;
+            }
+        } else {
+            tokio::time::sleep(APPEND_RETRY_DELAY).await;
+        }
+
+        self.append_batch(deps).await;
+        true
+    }
+
+    /// Record the decisions queued so far, without waiting for more.
+    ///
+    /// Stops at the first batch that can't be recorded.
+    pub async fn flush(&mut self, deps: &(impl GetDb + GetDecryptedDeks + ClusterBus)) {
+        loop {
+            while self.batch.len() < APPEND_BATCH_SIZE {
+                let Ok(decision) = self.receiver.try_recv() else {
+                    break;
+                };
+                self.batch.push(decision);
+            }
+
+            if self.batch.is_empty() || !self.append_batch(deps).await {
+                return;
+            }
+        }
+    }
+
+    /// Record the current batch, returning whether it was recorded
+    async fn append_batch(&mut self, deps: &(impl GetDb + GetDecryptedDeks + ClusterBus)) -> bool {
+        if self.batch.is_empty() {
+            return true;
+        }
+
+        let mut records = Vec::with_capacity(self.batch.len());
+        for decision in &self.batch {
+            match audit_record(deps, decision).await {
+                Ok(record) => records.push(record),
+                Err(err) => {
+                    warn!(
+                        ?err,
+                        "unable to find the policy deciding an audited decision"
+                    );
+                    return false;
+                }
+            }
+        }
+
+        let deks = deps.load_decrypted_deks();
+        let key = match audit_chain_key(&deks) {
+            Ok(key) => key,
+            Err(err) => {
+                warn!(?err, "no key for the audit chain");
+                return false;
+            }
+        };
+
+        if let Err(err) = DbAccessControlAudit::append_all(deps.get_db(), key, &records).await {
+            warn!(
+                ?err,
+                count = records.len(),
+                "unable to record audited decisions, will retry"
+            );
+            return false;
+        }
+        self.batch.clear();
+
+        // the admin events are written by a trigger on the audit table
+        announce_events(deps).await;
+        true
+    }
+}
+
+async fn audit_record(
+    deps: &impl GetDb,
+    decision: &QueuedDecision,
+) -> DbResult<DbAccessControlAudit> {
+    let policy_id = match decision.reason {
+        DecisionReason::Allowed | DecisionReason::PolicyDenied => {
+            let class = if decision.allowed {
+                PolicyValue::Allow
+            } else {
+                PolicyValue::Deny
+            };
+            access_control::find_deciding_policy(deps, decision.svc_eid, &decision.params, &class)
+                .await?
+        }
+        _ => None,
+    };
+
+    let mut resource_attrs: Vec<AttrId> = decision.params.resource_attrs.iter().copied().collect();
+    resource_attrs.sort();
+
+    Ok(DbAccessControlAudit {
+        id: 0,
+        created_at: decision.created_at,
+        svc_eid: decision.svc_eid,
+        subject_eid: decision
+            .params
+            .subject_eids
+            .get(&PropId::from(BuiltinProp::Entity))
+            .copied(),
+        resource_attrs,
+        allowed: decision.allowed,
+        reason: decision.reason.code().to_string(),
+        policy_id,
+        prev_hash: vec![],
+        hash: vec![],
+    })
+}
+
+/// File formats for exporting the audit trail
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AuditExportFormat {
+    /// One JSON object per line
+    Jsonl,
+    /// Comma separated values with a header row, resource attributes are separated by spaces
+    Csv,
+}
+
+impl std::str::FromStr for AuditExportFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        match value {
+            "jsonl" => Ok(Self::Jsonl),
+            "csv" => Ok(Self::Csv),
+            _ => Err(anyhow::anyhow!("expected `jsonl` or `csv`")),
+        }
+    }
+}
+
+const CSV_HEADER: &str =
+    "id,created_at,service_id,subject_id,resource_attributes,allowed,reason,policy_id";
+
+/// Write all recorded access control decisions to `out`, oldest first.
+///
+/// Returns the number of exported records.
+pub async fn export_access_control_audit(
+    deps: &impl Db,
+    format: AuditExportFormat,
+    out: &mut impl Write,
+) -> anyhow::Result<usize> {
+    if format == AuditExportFormat::Csv {
+        writeln!(out, "{CSV_HEADER}")?;
+    }
+
+    let mut after_id = 0;
+    let mut count = 0;
+
+    loop {
+        let records = DbAccessControlAudit::query_after(deps, after_id, EXPORT_BATCH_SIZE).await?;
+        let Some(last) = records.last() else {
+            break;
+        };
+        after_id = last.id;
+
+        for record in records {
+            let entry = AccessControlAuditEntry::from(record);
+            match format {
+                AuditExportFormat::Jsonl => {
+                    serde_json::to_writer(&mut *out, &entry)?;
+                    writeln!(out)?;
+                }
+                AuditExportFormat::Csv => write_csv_row(out, &entry)?,
+            }
+            count += 1;
+        }
+    }
+
+    out.flush()?;
+
+    Ok(count)
+}
+
+fn write_csv_row(out: &mut impl Write, entry: &AccessControlAuditEntry) -> anyhow::Result<()> {
+    let created_at = entry
+        .created_at
+        .format(&time::format_description::well_known::Rfc3339)?;
+    let resource_attributes = entry
+        .resource_attributes
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    let fields = [
+        entry.id.to_string(),
+        created_at,
+        entry.service_id.to_string(),
+        entry
+            .subject_id
+            .map(|eid| eid.to_string())
+            .unwrap_or_default(),
+        resource_attributes,
+        entry.allowed.to_string(),
+        entry.reason.clone(),
+        entry.policy_id.map(|id| id.to_string()).unwrap_or_default(),
+    ];
+
+    let row = fields
+        .iter()
+        .map(|field| csv_field(field))
+        .collect::<Vec<_>>()
+        .join(",");
+    writeln!(out, "{row}")?;
+
+    Ok(())
+}
+
+/// Quote a CSV field if it contains a separator, quote or line break
+fn csv_field(field: &str) -> std::borrow::Cow<'_, str> {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\"")).into()
+    } else {
+        field.into()
+    }
+}
+
+/// A break in the hash chain of access control audit records
+#[derive(thiserror::Error, Debug)]
+pub enum AuditChainError {
+    #[error("audit record {0} does not match its hash, it has been modified")]
+    Modified(i64),
+    #[error("audit record {0} does not link to the record before it, records have been inserted or removed")]
+    Unlinked(i64),
+    #[error("no key for the audit chain: {0}")]
+    Key(anyhow::Error),
+    #[error("db error: {0}")]
+    Db(#[from] DbError),
+}
+
+/// Verify the hash chain of the access control audit records, returning the number of verified records.
+///
+/// Records written before the chain was introduced are skipped.
+/// The oldest record may link to a purged record, so the chain can only show that nothing
+/// has been removed after the oldest record, and before the newest.
+pub async fn verify_access_control_audit_chain(
+    deps: &impl Db,
+    deks: &DecryptedDeks,
+) -> Result<usize, AuditChainError> {
+    let key = audit_chain_key(deks).map_err(AuditChainError::Key)?;
+    let mut prev_hash: Option<Vec<u8>> = None;
+    let mut after_id = 0;
+    let mut count = 0;
+
+    loop {
+        let records = DbAccessControlAudit::query_after(deps, after_id, EXPORT_BATCH_SIZE).await?;
+        let Some(last) = records.last() else {
+            break;
+        };
+        after_id = last.id;
+
+        for record in records {
+            if record.hash.is_empty() {
+                if prev_hash.is_some() {
+                    return Err(AuditChainError::Modified(record.id));
+                }
+                continue;
+            }
+
+            if prev_hash
+                .as_ref()
+                .is_some_and(|prev_hash| *prev_hash != record.prev_hash)
+            {
+                return Err(AuditChainError::Unlinked(record.id));
+            }
+            if record.chain_hash(key, &record.prev_hash).as_slice() != record.hash {
+                return Err(AuditChainError::Modified(record.id));
+            }
+
+            prev_hash = Some(record.hash);
+            count += 1;
+        }
+    }
+
+    Ok(count)
+}
+
+/// The key of the hash chain of the access control audit records
+pub fn audit_chain_key(deks: &DecryptedDeks) -> anyhow::Result<&AesKey> {
+    deks.get(BuiltinProp::AuditChain.into())
+}
+
+/// Purge audit records older than the `AUDIT_RETENTION` setting.
+///
+/// Only the leader purges, followers have the deletion replicated to them.
+pub async fn purge_expired_audit(
+    deps: &(impl GetDb + GetSettings),
+    is_leader: IsLeaderDb,
+    now: time::OffsetDateTime,
+) -> DbResult<usize> {
+    let Some(retention) = deps.get_settings().audit_retention else {
+        return Ok(0);
+    };
+    if !is_leader.0 {
+        return Ok(0);
+    }
+
+    access_control_audit_repo::purge_audit_before(deps.get_db(), now - retention).await
+}
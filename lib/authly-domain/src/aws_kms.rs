@@ -0,0 +1,331 @@
+//! Instance and token signing keys held in AWS KMS.
+//!
+//! Only the public key leaves KMS. Messages are hashed locally and sent as digests to the KMS `Sign` API.
+//! Requests are authenticated with AWS Signature Version 4, implemented here to avoid pulling in the AWS SDK.
+
+use anyhow::{anyhow, Context};
+use async_trait::async_trait;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use hmac::{Hmac, Mac};
+use rcgen::{PublicKeyData, SignatureAlgorithm, SubjectPublicKeyInfo, PKCS_ECDSA_P256_SHA256};
+use reqwest::Url;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use time::OffsetDateTime;
+use tracing::info;
+
+use crate::signer::Signer;
+
+const CONTENT_TYPE: &str = "application/x-amz-json-1.1";
+
+/// Credentials for signing AWS requests
+#[derive(Clone)]
+pub struct AwsCredentials {
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    pub session_token: Option<String>,
+}
+
+impl AwsCredentials {
+    /// Read credentials from the standard `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY` and `AWS_SESSION_TOKEN` variables
+    pub fn from_env() -> anyhow::Result<Self> {
+        Ok(Self {
+            access_key_id: std::env::var("AWS_ACCESS_KEY_ID")
+                .context("AWS_ACCESS_KEY_ID is required for AWS KMS")?,
+            secret_access_key: std::env::var("AWS_SECRET_ACCESS_KEY")
+                .context("AWS_SECRET_ACCESS_KEY is required for AWS KMS")?,
+            session_token: std::env::var("AWS_SESSION_TOKEN").ok(),
+        })
+    }
+}
+
+/// A P-256 signing key held in AWS KMS
+pub struct AwsKmsSigner {
+    client: reqwest::Client,
+    endpoint: Url,
+    region: String,
+    key_id: String,
+    credentials: AwsCredentials,
+    public_key_raw: Vec<u8>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct GetPublicKeyRequest<'a> {
+    key_id: &'a str,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct GetPublicKeyResponse {
+    public_key: String,
+    key_spec: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct SignRequest<'a> {
+    key_id: &'a str,
+    message: String,
+    message_type: &'static str,
+    signing_algorithm: &'static str,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct SignResponse {
+    signature: String,
+}
+
+impl AwsKmsSigner {
+    /// Connect to a KMS key and fetch its public key.
+    ///
+    /// The endpoint defaults to the regional KMS endpoint.
+    pub async fn connect(
+        client: reqwest::Client,
+        region: String,
+        endpoint: Option<&str>,
+        key_id: String,
+        credentials: AwsCredentials,
+    ) -> anyhow::Result<Self> {
+        let endpoint = match endpoint {
+            Some(endpoint) => Url::parse(endpoint)?,
+            None => Url::parse(&format!("https://kms.{region}.amazonaws.com/"))?,
+        };
+
+        let mut signer = Self {
+            client,
+            endpoint,
+            region,
+            key_id,
+            credentials,
+            public_key_raw: vec![],
+        };
+
+        let output: GetPublicKeyResponse = signer
+            .call(
+                "TrentService.GetPublicKey",
+                &GetPublicKeyRequest {
+                    key_id: &signer.key_id,
+                },
+            )
+            .await?;
+
+        if output.key_spec != "ECC_NIST_P256" {
+            return Err(anyhow!(
+                "KMS key {} has unsupported key spec {}, expected ECC_NIST_P256",
+                signer.key_id,
+                output.key_spec
+            ));
+        }
+
+        let spki = SubjectPublicKeyInfo::from_der(&STANDARD.decode(output.public_key)?)?;
+        signer.public_key_raw = spki.der_bytes().to_vec();
+
+        info!(key_id = signer.key_id, "using AWS KMS key");
+
+        Ok(signer)
+    }
+
+    async fn call<T: DeserializeOwned>(
+        &self,
+        target: &str,
+        input: &impl Serialize,
+    ) -> anyhow::Result<T> {
+        let body = serde_json::to_vec(input)?;
+        let amz_date = amz_date(OffsetDateTime::now_utc());
+
+        let mut headers = vec![
+            ("content-type", CONTENT_TYPE.to_string()),
+            ("host", host_header(&self.endpoint)?),
+            ("x-amz-date", amz_date.clone()),
+            ("x-amz-target", target.to_string()),
+        ];
+        if let Some(session_token) = &self.credentials.session_token {
+            headers.push(("x-amz-security-token", session_token.clone()));
+        }
+        headers.sort();
+
+        let authorization = sigv4_authorization(
+            &self.credentials,
+            &self.region,
+            "kms",
+            "POST",
+            self.endpoint.path(),
+            &headers,
+            &body,
+            &amz_date,
+        );
+
+        let mut request = self.client.post(self.endpoint.clone());
+        for (name, value) in headers {
+            // reqwest derives the host header from the URL
+            if name != "host" {
+                request = request.header(name, value);
+            }
+        }
+
+        let response = request
+            .header("authorization", authorization)
+            .body(body)
+            .send()
+            .await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let message = response.text().await.unwrap_or_default();
+            return Err(anyhow!("KMS {target} failed with {status}: {message}"));
+        }
+
+        Ok(response.json().await?)
+    }
+}
+
+#[async_trait]
+impl Signer for AwsKmsSigner {
+    fn algorithm(&self) -> &'static SignatureAlgorithm {
+        &PKCS_ECDSA_P256_SHA256
+    }
+
+    fn public_key_raw(&self) -> &[u8] {
+        &self.public_key_raw
+    }
+
+    fn is_remote(&self) -> bool {
+        true
+    }
+
+    async fn sign(&self, msg: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let output: SignResponse = self
+            .call(
+                "TrentService.Sign",
+                &SignRequest {
+                    key_id: &self.key_id,
+                    message: STANDARD.encode(Sha256::digest(msg)),
+                    message_type: "DIGEST",
+                    signing_algorithm: "ECDSA_SHA_256",
+                },
+            )
+            .await?;
+
+        // KMS produces ASN.1 DER encoded ECDSA signatures
+        Ok(STANDARD.decode(output.signature)?)
+    }
+}
+
+/// The `Authorization` header value of an AWS Signature Version 4 signed request.
+///
+/// `headers` are the headers to sign, with lowercase names, in sorted order.
+#[allow(clippy::too_many_arguments)]
+fn sigv4_authorization(
+    credentials: &AwsCredentials,
+    region: &str,
+    service: &str,
+    method: &str,
+    path: &str,
+    headers: &[(&str, String)],
+    body: &[u8],
+    amz_date: &str,
+) -> String {
+    let date = &amz_date[..8];
+    let scope = format!("{date}/{region}/{service}/aws4_request");
+
+    let canonical_headers: String = headers
+        .iter()
+        .map(|(name, value)| format!("{name}:{}\n", value.trim()))
+        .collect();
+    let signed_headers = headers
+        .iter()
+        .map(|(name, _)| *name)
+        .collect::<Vec<_>>()
+        .join(";");
+
+    let canonical_request = format!(
+        "{method}\n{path}\n\n{canonical_headers}\n{signed_headers}\n{}",
+        hexhex::hex(Sha256::digest(body))
+    );
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{amz_date}\n{scope}\n{}",
+        hexhex::hex(Sha256::digest(canonical_request.as_bytes()))
+    );
+
+    let key = [date, region, service, "aws4_request"].into_iter().fold(
+        format!("AWS4{}", credentials.secret_access_key).into_bytes(),
+        |key, data| hmac_sha256(&key, data.as_bytes()),
+    );
+    let signature = hexhex::hex(hmac_sha256(&key, string_to_sign.as_bytes()));
+
+    format!(
+        "AWS4-HMAC-SHA256 Credential={}/{scope}, SignedHeaders={signed_headers}, Signature={signature}",
+        credentials.access_key_id
+    )
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// The `x-amz-date` timestamp format, e.g. `20150830T123600Z`
+fn amz_date(time: OffsetDateTime) -> String {
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        time.year(),
+        time.month() as u8,
+        time.day(),
+        time.hour(),
+        time.minute(),
+        time.second()
+    )
+}
+
+fn host_header(url: &Url) -> anyhow::Result<String> {
+    let host = url.host_str().context("KMS endpoint has no host")?;
+    Ok(match url.port() {
+        Some(port) => format!("{host}:{port}"),
+        None => host.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The `get-vanilla` case of the AWS Signature Version 4 test suite
+    #[test]
+    fn sigv4_test_suite_get_vanilla() {
+        let credentials = AwsCredentials {
+            access_key_id: "AKIDEXAMPLE".to_string(),
+            secret_access_key: "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".to_string(),
+            session_token: None,
+        };
+
+        let authorization = sigv4_authorization(
+            &credentials,
+            "us-east-1",
+            "service",
+            "GET",
+            "/",
+            &[
+                ("host", "example.amazonaws.com".to_string()),
+                ("x-amz-date", "20150830T123600Z".to_string()),
+            ],
+            b"",
+            "20150830T123600Z",
+        );
+
+        assert_eq!(
+            authorization,
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/service/aws4_request, \
+             SignedHeaders=host;x-amz-date, \
+             Signature=5fa00fa31553b73ebf1942676e86291e8372ff2a2260956d9b8aae1d763fbf31"
+        );
+    }
+
+    #[test]
+    fn amz_date_format() {
+        let time = OffsetDateTime::from_unix_timestamp(1440938160).unwrap();
+        assert_eq!(amz_date(time), "20150830T123600Z");
+    }
+}
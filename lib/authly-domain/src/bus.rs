@@ -1,9 +1,11 @@
 use std::net::SocketAddr;
 
-use authly_common::id::DirectoryId;
+use authly_common::id::{DirectoryId, EntityId};
 use serde::{Deserialize, Serialize};
 use tokio::sync::broadcast;
 
+pub mod admin_events;
+pub mod entity_events;
 pub mod handler;
 pub mod service_events;
 
@@ -28,6 +30,10 @@ pub enum ClusterMessage {
     /// 3. Notify all connected clients
     InstanceChanged,
 
+    /// A new token signing key has been written to the database.
+    /// This should trigger re-load of the AuthlyInstance.
+    TokenSigningKeyChanged,
+
     /// An directory caused a change to the database.
     /// It can also mean the directory was added or removed.
     DirectoryChanged {
@@ -35,9 +41,29 @@ pub enum ClusterMessage {
         dir_id: DirectoryId,
     },
 
+    /// Sessions of an entity were revoked.
+    /// Any cached sessions of the entity must be evicted.
+    SessionsRevoked {
+        /// The entity whose sessions were revoked
+        eid: EntityId,
+    },
+
     /// Broadcast message to all connected service instances
     ServiceBroadcast(ServiceMessage),
 
+    /// The time of the clock of a node, for detecting clock skew between the nodes.
+    /// See [crate::clock_skew].
+    ClockCheck {
+        /// Identifies the sending node
+        node: u64,
+        #[serde(with = "time::serde::rfc3339")]
+        sent_at: time::OffsetDateTime,
+    },
+
+    /// New events were written to an outbox.
+    /// This should wake up the event subscribers connected to each node.
+    EventsAppended,
+
     /// This message does not mean anything, a healthcheck module can send this message
     /// to "itself" and check whether it's received again.
     ClusterPing,
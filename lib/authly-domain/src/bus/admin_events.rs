@@ -0,0 +1,122 @@
+//! Audit and system events, for a live tail in admin tooling.
+//!
+//! Events are appended to an outbox table, either directly or by a database trigger for access control audit records.
+//! Like entity events, subscribers resume from the sequence number of the last event they processed.
+
+use authly_db::DbResult;
+use tokio::sync::watch;
+
+use crate::{
+    bus::entity_events::announce_events,
+    ctx::{ClusterBus, EntityEventBus, GetDb},
+    repo::admin_event_repo,
+};
+
+/// The kinds of events written to the outbox
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AdminEventKind {
+    /// An access control decision was audited, written by a database trigger
+    AccessControl,
+    /// The local node became the cluster leader
+    LeaderChanged,
+    /// A directory was applied
+    DirectoryChanged,
+    /// A certificate was issued to a service
+    CertificateIssued,
+    /// An operator simulated the login of a persona
+    SimulatedLogin,
+}
+
+impl AdminEventKind {
+    pub const fn code(self) -> &'static str {
+        match self {
+            Self::AccessControl => "access_control",
+            Self::LeaderChanged => "leader_changed",
+            Self::DirectoryChanged => "directory_changed",
+            Self::CertificateIssued => "certificate_issued",
+            Self::SimulatedLogin => "simulated_login",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct AdminEvent {
+    /// The position of the event in the outbox
+    pub seq: i64,
+    pub kind: String,
+    pub detail: serde_json::Value,
+    pub created_at: time::OffsetDateTime,
+}
+
+/// Append an event to the outbox and wake up the subscribers of the cluster
+pub async fn record_admin_event(
+    deps: &(impl GetDb + ClusterBus),
+    kind: AdminEventKind,
+    detail: serde_json::Value,
+) -> DbResult<()> {
+    admin_event_repo::insert_admin_event(
+        deps.get_db(),
+        kind,
+        &detail,
+        time::OffsetDateTime::now_utc(),
+    )
+    .await?;
+    announce_events(deps).await;
+
+    Ok(())
+}
+
+/// A subscription to admin events, starting after a given sequence number
+pub struct AdminEventSubscription {
+    cursor: i64,
+    notified: watch::Receiver<()>,
+}
+
+impl AdminEventSubscription {
+    /// Subscribe to events after `after_seq`. Use `0` to receive every event in the outbox.
+    pub fn new(deps: &impl EntityEventBus, after_seq: i64) -> Self {
+        Self {
+            cursor: after_seq,
+            notified: deps.entity_event_notifier().subscribe(),
+        }
+    }
+
+    /// The sequence number of the last event returned from this subscription
+    pub fn cursor(&self) -> i64 {
+        self.cursor
+    }
+
+    /// Fetch pending events without waiting
+    pub async fn poll(&mut self, deps: &impl GetDb, limit: usize) -> DbResult<Vec<AdminEvent>> {
+        let events = admin_event_repo::list_admin_events(deps.get_db(), self.cursor, limit).await?;
+
+        if let Some(last) = events.last() {
+            self.cursor = last.seq;
+        }
+
+        Ok(events)
+    }
+
+    /// Wait until there are events after the cursor, and return them.
+    ///
+    /// Returns an empty list if the notifier is gone, which happens on shutdown.
+    pub async fn next_batch(
+        &mut self,
+        deps: &impl GetDb,
+        limit: usize,
+    ) -> DbResult<Vec<AdminEvent>> {
+        loop {
+            // mark as seen before polling, so that notifications arriving in between aren't missed
+            self.notified.borrow_and_update();
+
+            let events = self.poll(deps, limit).await?;
+            if !events.is_empty() {
+                return Ok(events);
+            }
+
+            if self.notified.changed().await.is_err() {
+                return Ok(vec![]);
+            }
+        }
+    }
+}
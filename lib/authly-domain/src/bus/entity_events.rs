@@ -0,0 +1,166 @@
+//! Entity attribute change events, for downstream systems that mirror Authly.
+//!
+//! Every change to an entity attribute assignment is recorded in an outbox table in the same
+//! database transaction as the change itself. Subscribers track the sequence number of the last
+//! event they processed, and resume from it after reconnecting, so no events are lost while they're away.
+//!
+//! Subscribers on every node are woken up through the cluster message bus when events are written.
+//! Events that every known subscriber has acknowledged are purged from the outbox,
+//! and so are the events older than the `ENTITY_EVENT_RETENTION` setting, along with subscribers that have been away for as long.
+
+use std::sync::Arc;
+
+use authly_common::id::{AttrId, EntityId, ServiceId};
+use authly_db::DbResult;
+use tokio::sync::watch;
+use tracing::warn;
+
+use crate::{
+    bus::ClusterMessage,
+    ctx::{ClusterBus, EntityEventBus, GetClock, GetDb, GetSettings},
+    repo::entity_event_repo,
+    IsLeaderDb,
+};
+
+/// Whether an attribute was assigned to or removed from an entity
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AttributeChange {
+    Added,
+    Removed,
+}
+
+#[derive(Clone, Debug)]
+pub struct EntityAttributeEvent {
+    /// The position of the event in the outbox
+    pub seq: i64,
+    pub eid: EntityId,
+    pub attr_id: AttrId,
+    pub change: AttributeChange,
+    pub created_at: time::OffsetDateTime,
+}
+
+/// Wakes up local subscribers when new events may have been written to an outbox
+#[derive(Clone)]
+pub struct EntityEventNotifier(Arc<watch::Sender<()>>);
+
+impl Default for EntityEventNotifier {
+    fn default() -> Self {
+        Self(Arc::new(watch::channel(()).0))
+    }
+}
+
+impl EntityEventNotifier {
+    pub fn notify(&self) {
+        self.0.send_replace(());
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<()> {
+        self.0.subscribe()
+    }
+}
+
+/// Wake up the event subscribers of every cluster node, after writing to an outbox
+pub async fn announce_events(deps: &impl ClusterBus) {
+    if let Err(err) = deps
+        .broadcast_to_cluster(ClusterMessage::EventsAppended)
+        .await
+    {
+        warn!(?err, "unable to announce new events");
+    }
+}
+
+/// Record that a subscriber has processed the events up to `seq`,
+/// and purge the events that every subscriber has processed
+pub async fn acknowledge_events(
+    deps: &(impl GetDb + GetClock),
+    svc_eid: ServiceId,
+    seq: i64,
+) -> DbResult<()> {
+    entity_event_repo::acknowledge_entity_attribute_events(
+        deps.get_db(),
+        svc_eid,
+        seq,
+        deps.get_clock().now(),
+    )
+    .await?;
+    entity_event_repo::delete_acknowledged_entity_attribute_events(deps.get_db()).await?;
+
+    Ok(())
+}
+
+/// Purge events older than the `ENTITY_EVENT_RETENTION` setting, and forget subscribers that have been away for as long.
+///
+/// Only the leader purges, followers have the deletion replicated to them.
+pub async fn purge_expired_events(
+    deps: &(impl GetDb + GetSettings),
+    is_leader: IsLeaderDb,
+    now: time::OffsetDateTime,
+) -> DbResult<usize> {
+    if !is_leader.0 {
+        return Ok(0);
+    }
+
+    let retention = deps.get_settings().entity_event_retention;
+    entity_event_repo::purge_entity_attribute_events_before(deps.get_db(), now - retention).await
+}
+
+/// A subscription to entity attribute events, starting after a given sequence number
+pub struct EntityAttributeSubscription {
+    cursor: i64,
+    notified: watch::Receiver<()>,
+}
+
+impl EntityAttributeSubscription {
+    /// Subscribe to events after `after_seq`. Use `0` to receive every event in the outbox.
+    pub fn new(deps: &impl EntityEventBus, after_seq: i64) -> Self {
+        Self {
+            cursor: after_seq,
+            notified: deps.entity_event_notifier().subscribe(),
+        }
+    }
+
+    /// The sequence number of the last event returned from this subscription
+    pub fn cursor(&self) -> i64 {
+        self.cursor
+    }
+
+    /// Fetch pending events without waiting
+    pub async fn poll(
+        &mut self,
+        deps: &impl GetDb,
+        limit: usize,
+    ) -> DbResult<Vec<EntityAttributeEvent>> {
+        let events =
+            entity_event_repo::list_entity_attribute_events(deps.get_db(), self.cursor, limit)
+                .await?;
+
+        if let Some(last) = events.last() {
+            self.cursor = last.seq;
+        }
+
+        Ok(events)
+    }
+
+    /// Wait until there are events after the cursor, and return them.
+    ///
+    /// Returns an empty list if the notifier is gone, which happens on shutdown.
+    pub async fn next_batch(
+        &mut self,
+        deps: &impl GetDb,
+        limit: usize,
+    ) -> DbResult<Vec<EntityAttributeEvent>> {
+        loop {
+            // mark as seen before polling, so that notifications arriving in between aren't missed
+            self.notified.borrow_and_update();
+
+            let events = self.poll(deps, limit).await?;
+            if !events.is_empty() {
+                return Ok(events);
+            }
+
+            if self.notified.changed().await.is_err() {
+                return Ok(vec![]);
+            }
+        }
+    }
+}
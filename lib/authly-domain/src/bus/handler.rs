@@ -3,7 +3,13 @@ use tracing::info;
 
 use crate::{
     bus::{ClusterMessage, ServiceMessage},
-    ctx::{ClusterBus, GetDb, GetDecryptedDeks, RedistributeCertificates, ServiceBus, SetInstance},
+    ctx::{
+        ClusterBus, EntityEventBus, GetClock, GetClockSkew, GetDb, GetDecryptedDeks,
+        GetIdGenerator, GetInstance, GetMetadataCache, GetPolicyEngineCache, GetSessionCache,
+        RedistributeCertificates, ServiceBus, SetInstance,
+    },
+    encryption::DecryptedDeks,
+    instance::AuthlyInstance,
     repo::{
         crypto_repo::load_authly_instance,
         directory_repo::{query_dir_key, DbDirectoryService},
@@ -15,10 +21,18 @@ use crate::{
 pub async fn authly_node_handle_incoming_message(
     deps: &(impl GetDb
           + GetDecryptedDeks
+          + GetInstance
+          + GetIdGenerator
           + SetInstance
           + RedistributeCertificates
           + ClusterBus
-          + ServiceBus),
+          + ServiceBus
+          + EntityEventBus
+          + GetSessionCache
+          + GetMetadataCache
+          + GetPolicyEngineCache
+          + GetClockSkew
+          + GetClock),
     message: ClusterMessage,
 ) -> anyhow::Result<()> {
     // Step 1: central processing
@@ -27,7 +41,7 @@ pub async fn authly_node_handle_incoming_message(
             // step 1: re-load instance
             // IsLeaderDb is not important when not starting up the first time
             let deks = deps.load_decrypted_deks();
-            let new_instance = load_authly_instance(IsLeaderDb(true), deps.get_db(), &deks).await?;
+            let new_instance = reload_authly_instance(deps, &deks).await?;
             deps.set_instance(new_instance);
 
             // step 2: make sure new certificates are redistributed.
@@ -40,8 +54,16 @@ pub async fn authly_node_handle_incoming_message(
             ))
             .await?;
         }
+        ClusterMessage::TokenSigningKeyChanged => {
+            let deks = deps.load_decrypted_deks();
+            let new_instance = reload_authly_instance(deps, &deks).await?;
+            deps.set_instance(new_instance);
+        }
         ClusterMessage::DirectoryChanged { dir_id } => {
             info!(?dir_id, "directory changed");
+            deps.get_metadata_cache().invalidate_all();
+            deps.get_policy_engine_cache().invalidate_all();
+
             let dir_key = query_dir_key(deps.get_db(), dir_id)
                 .await?
                 .context("no such directory")?;
@@ -50,12 +72,25 @@ pub async fn authly_node_handle_incoming_message(
                 deps.service_event_dispatcher()
                     .broadcast(service.svc_eid, ServiceMessage::ReloadCache);
             }
+
+            // the directory may have changed entity attributes
+            deps.entity_event_notifier().notify();
+        }
+        ClusterMessage::SessionsRevoked { eid } => {
+            deps.get_session_cache().invalidate_entity(eid);
         }
         ClusterMessage::ServiceBroadcast(message) => {
             info!(?message, "service broadcast");
 
             deps.service_event_dispatcher().broadcast_all(message);
         }
+        ClusterMessage::ClockCheck { node, sent_at } => {
+            deps.get_clock_skew()
+                .record(node, sent_at, deps.get_clock().now());
+        }
+        ClusterMessage::EventsAppended => {
+            deps.entity_event_notifier().notify();
+        }
         ClusterMessage::ClusterPing => {
             info!(?message, "TODO: handle cluster ping");
         }
@@ -63,3 +98,31 @@ pub async fn authly_node_handle_incoming_message(
 
     Ok(())
 }
+
+/// Re-load the instance from the database, keeping the externally held keys of the current instance
+async fn reload_authly_instance(
+    deps: &(impl GetDb + GetInstance + GetIdGenerator),
+    deks: &DecryptedDeks,
+) -> anyhow::Result<AuthlyInstance> {
+    let (key_source, external_token_signing_key) = {
+        let instance = deps.get_instance();
+        (
+            instance.key_source().clone(),
+            instance.external_token_signing_key().cloned(),
+        )
+    };
+
+    let mut new_instance = load_authly_instance(
+        IsLeaderDb(true),
+        deps.get_db(),
+        deks,
+        key_source,
+        deps.get_id_generator(),
+    )
+    .await?;
+    if let Some(key) = external_token_signing_key {
+        new_instance = new_instance.with_external_token_signing_key(key);
+    }
+
+    Ok(new_instance)
+}
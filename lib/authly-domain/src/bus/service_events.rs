@@ -1,14 +1,15 @@
 use std::{
     collections::BTreeMap,
     sync::{Arc, RwLock},
-    time::Duration,
+    time::{Duration, Instant},
 };
 
 use authly_common::id::ServiceId;
 use fnv::FnvHashMap;
+use serde::Serialize;
 use tokio::sync::mpsc::error::TrySendError;
 use tokio_util::sync::CancellationToken;
-use tracing::{error, info};
+use tracing::{debug, error, info, warn};
 
 use crate::bus::{ServiceMessage, ServiceMessageConnection};
 
@@ -16,9 +17,34 @@ type MsgSender = tokio::sync::mpsc::Sender<ServiceMessage>;
 
 type SenderMap = FnvHashMap<ServiceId, Vec<ServiceMessageConnection>>;
 
+/// The ping health of a connected service
+#[derive(Clone, Serialize, Debug)]
+pub struct ServiceHealth {
+    /// Whether the service gets notifications
+    pub responsive: bool,
+    /// Pings in a row left unanswered
+    pub missed_pings: u32,
+    /// The round-trip time of the last answered ping, in milliseconds
+    pub round_trip_ms: Option<u128>,
+    #[serde(skip)]
+    ping_sent_at: Option<Instant>,
+}
+
+impl Default for ServiceHealth {
+    fn default() -> Self {
+        Self {
+            responsive: true,
+            missed_pings: 0,
+            round_trip_ms: None,
+            ping_sent_at: None,
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct ServiceEventDispatcher {
     map: Arc<RwLock<SenderMap>>,
+    health: Arc<RwLock<FnvHashMap<ServiceId, ServiceHealth>>>,
     cancel: CancellationToken,
 }
 
@@ -26,6 +52,7 @@ impl ServiceEventDispatcher {
     pub fn new(cancel: CancellationToken) -> Self {
         Self {
             map: Default::default(),
+            health: Default::default(),
             cancel,
         }
     }
@@ -36,6 +63,87 @@ impl ServiceEventDispatcher {
 
         let mut map = self.map.write().unwrap();
         map.entry(svc_eid).or_default().push(connection);
+
+        // a service connecting again starts out as responsive
+        self.health
+            .write()
+            .unwrap()
+            .insert(svc_eid, ServiceHealth::default());
+    }
+
+    /// Ping all connected services.
+    ///
+    /// A ping not answered before the next one counts as missed.
+    /// A service missing more than `max_missed` pings in a row becomes unresponsive,
+    /// and is left out of other messages until it answers a ping.
+    pub fn ping_all(&self, max_missed: Option<u32>) {
+        let now = Instant::now();
+        let connected = self.statistics();
+
+        {
+            let mut health = self.health.write().unwrap();
+
+            for svc_eid in connected.into_keys() {
+                let svc_health = health.entry(svc_eid).or_default();
+                if svc_health.ping_sent_at.is_some() {
+                    svc_health.missed_pings += 1;
+                }
+                svc_health.ping_sent_at = Some(now);
+
+                if let Some(max_missed) = max_missed {
+                    if svc_health.responsive && svc_health.missed_pings > max_missed {
+                        warn!(
+                            ?svc_eid,
+                            missed_pings = svc_health.missed_pings,
+                            "service is unresponsive"
+                        );
+                        svc_health.responsive = false;
+                    }
+                }
+            }
+        }
+
+        self.broadcast_all(ServiceMessage::Ping);
+    }
+
+    /// Record the answer to a ping from a service
+    pub fn record_pong(&self, svc_eid: ServiceId) {
+        let returned = {
+            let mut health = self.health.write().unwrap();
+            let svc_health = health.entry(svc_eid).or_default();
+
+            if let Some(ping_sent_at) = svc_health.ping_sent_at.take() {
+                svc_health.round_trip_ms = Some(ping_sent_at.elapsed().as_millis());
+            }
+            svc_health.missed_pings = 0;
+
+            !std::mem::replace(&mut svc_health.responsive, true)
+        };
+
+        if returned {
+            info!(?svc_eid, "service is responsive again");
+
+            // notifications were missed while unresponsive
+            self.broadcast(svc_eid, ServiceMessage::ReloadCache);
+        }
+    }
+
+    /// The ping health of each connected service
+    pub fn health(&self) -> BTreeMap<ServiceId, ServiceHealth> {
+        let connected = self.statistics();
+        let health = self.health.read().unwrap();
+
+        connected
+            .into_keys()
+            .map(|svc_eid| (svc_eid, health.get(&svc_eid).cloned().unwrap_or_default()))
+            .collect()
+    }
+
+    fn is_responsive(&self, svc_eid: ServiceId) -> bool {
+        match self.health.read().unwrap().get(&svc_eid) {
+            Some(health) => health.responsive,
+            None => true,
+        }
     }
 
     /// Broadcast to all services and connections
@@ -49,6 +157,11 @@ impl ServiceEventDispatcher {
 
     /// Broadcast to a single service (all connections)
     pub fn broadcast(&self, svc_eid: ServiceId, msg: ServiceMessage) {
+        if msg != ServiceMessage::Ping && !self.is_responsive(svc_eid) {
+            debug!(?svc_eid, ?msg, "service is unresponsive, not sending");
+            return;
+        }
+
         let mut slow_connections: Vec<ServiceMessageConnection> = vec![];
 
         {
@@ -140,6 +253,7 @@ impl ServiceEventDispatcher {
 
         if connections.is_empty() {
             map.remove(&svc_eid);
+            self.health.write().unwrap().remove(&svc_eid);
         }
     }
 
@@ -153,6 +267,7 @@ impl ServiceEventDispatcher {
 
         if connections.is_empty() {
             map.remove(&svc_eid);
+            self.health.write().unwrap().remove(&svc_eid);
         }
     }
 }
@@ -0,0 +1,188 @@
+//! Detection of a skewed clock on the local node.
+//!
+//! Every node periodically broadcasts the time of its clock over the cluster bus,
+//! and compares the times broadcast by the other nodes with its own clock.
+//! The local clock is considered skewed when it's off by more than the allowed drift from a majority of the other nodes,
+//! so that a single skewed node doesn't get the rest of the cluster flagged.
+//! A skewed node stops serving requests until its clock agrees with the cluster again.
+//!
+//! With only one other node there's no majority to tell which of the two clocks is wrong,
+//! so the drift is only reported, and both nodes keep serving.
+//!
+//! The delivery delay of the broadcast counts as clock offset, so the allowed drift should be well above it.
+
+use std::{
+    collections::BTreeMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use http::{header::RETRY_AFTER, StatusCode};
+use serde::Serialize;
+use time::OffsetDateTime;
+use tracing::warn;
+
+use crate::{
+    bus::{BusError, ClusterMessage},
+    ctx::{ClusterBus, GetClock, GetClockSkew},
+};
+
+/// How often nodes broadcast the time of their clock
+pub const CLOCK_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Observations older than five check intervals are forgotten, e.g. after a node left the cluster
+const OBSERVATION_TTL: Duration = Duration::from_secs(5 * CLOCK_CHECK_INTERVAL.as_secs());
+
+/// The default of the largest allowed clock offset to the other nodes
+const DEFAULT_MAX_DRIFT: Duration = Duration::from_secs(30);
+
+/// Requests to the standard gRPC health service are still answered by a skewed node, reporting it as not serving
+const HEALTH_SERVICE_PATH: &str = "/grpc.health.v1.Health/";
+
+/// How the local clock compares to the clocks of the other nodes
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ClockSkew {
+    /// The local clock agrees with a majority of the other nodes, or the check is disabled
+    InSync,
+    /// The local clock and the only other node of the cluster disagree, without telling which one is off
+    Disputed,
+    /// The local clock is off from a majority of at least two other nodes
+    Skewed,
+}
+
+/// Tracks the clock offsets of the other nodes in the cluster, relative to the local clock
+pub struct ClockSkewMonitor {
+    /// Identifies the clock checks of the local node, which are also delivered to itself
+    node: u64,
+    max_drift: Option<time::Duration>,
+    observations: Mutex<BTreeMap<u64, Observation>>,
+}
+
+struct Observation {
+    offset: time::Duration,
+    observed_at: Instant,
+}
+
+impl Default for ClockSkewMonitor {
+    fn default() -> Self {
+        Self {
+            node: rand::random(),
+            max_drift: Some(DEFAULT_MAX_DRIFT.try_into().unwrap()),
+            observations: Mutex::new(BTreeMap::new()),
+        }
+    }
+}
+
+impl ClockSkewMonitor {
+    /// Set the largest allowed clock offset to the other nodes, `None` disables the check
+    pub fn with_max_drift(mut self, max_drift: Option<Duration>) -> Self {
+        self.max_drift = max_drift.map(|max_drift| max_drift.try_into().unwrap());
+        self
+    }
+
+    /// The message broadcasting the time of the local clock
+    pub fn clock_check(&self, now: OffsetDateTime) -> ClusterMessage {
+        ClusterMessage::ClockCheck {
+            node: self.node,
+            sent_at: now,
+        }
+    }
+
+    /// Record the time broadcast by a node, as received at `now` by the local clock
+    pub fn record(&self, node: u64, sent_at: OffsetDateTime, now: OffsetDateTime) {
+        if node == self.node {
+            return;
+        }
+
+        self.observations.lock().unwrap().insert(
+            node,
+            Observation {
+                offset: sent_at - now,
+                observed_at: Instant::now(),
+            },
+        );
+    }
+
+    /// Compare the local clock to the most recent clock checks of the other nodes
+    pub fn clock_skew(&self) -> ClockSkew {
+        let Some(max_drift) = self.max_drift else {
+            return ClockSkew::InSync;
+        };
+
+        let mut observations = self.observations.lock().unwrap();
+        observations.retain(|_, observation| observation.observed_at.elapsed() < OBSERVATION_TTL);
+
+        let drifted = observations
+            .values()
+            .filter(|observation| observation.offset.abs() > max_drift)
+            .count();
+
+        match observations.len() {
+            // a single node, or two nodes that agree
+            _ if drifted == 0 => ClockSkew::InSync,
+            1 => ClockSkew::Disputed,
+            len if drifted * 2 > len => ClockSkew::Skewed,
+            _ => ClockSkew::InSync,
+        }
+    }
+
+    /// Whether the local clock is off from a majority of the other nodes, then the node must not serve requests
+    pub fn is_skewed(&self) -> bool {
+        self.clock_skew() == ClockSkew::Skewed
+    }
+
+    /// Reject the requests to a router while the local clock is skewed.
+    ///
+    /// The gRPC health service stays reachable, so that the node is reported as not serving.
+    pub fn layer<Ctx>(ctx: Ctx, router: axum::Router) -> axum::Router
+    where
+        Ctx: GetClockSkew + Clone + Send + Sync + 'static,
+    {
+        router.layer(axum::middleware::from_fn_with_state(
+            ctx,
+            reject_while_skewed::<Ctx>,
+        ))
+    }
+}
+
+async fn reject_while_skewed<Ctx: GetClockSkew>(
+    State(ctx): State<Ctx>,
+    req: Request,
+    next: Next,
+) -> Response {
+    if ctx.get_clock_skew().is_skewed() && !req.uri().path().starts_with(HEALTH_SERVICE_PATH) {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            [(RETRY_AFTER, "30")],
+            "clock skewed",
+        )
+            .into_response();
+    }
+
+    next.run(req).await
+}
+
+/// Broadcast the time of the local clock to the cluster, warning if the local clock is skewed
+pub async fn broadcast_clock_check(
+    deps: &(impl ClusterBus + GetClockSkew + GetClock),
+) -> Result<(), BusError> {
+    let monitor = deps.get_clock_skew();
+    match monitor.clock_skew() {
+        ClockSkew::InSync => {}
+        ClockSkew::Disputed => {
+            warn!("the local clock disagrees with the other node of the cluster, one of them has drifted");
+        }
+        ClockSkew::Skewed => {
+            warn!("the local clock has drifted from the other nodes of the cluster, the node is not serving");
+        }
+    }
+
+    deps.broadcast_to_cluster(monitor.clock_check(deps.get_clock().now()))
+        .await
+}
@@ -12,11 +12,23 @@ use webauthn_rs::{
 };
 
 use crate::{
+    audit::AuditQueue,
     builtins::Builtins,
-    bus::{service_events::ServiceEventDispatcher, BusError, ClusterMessage},
+    bus::{
+        entity_events::EntityEventNotifier, service_events::ServiceEventDispatcher, BusError,
+        ClusterMessage,
+    },
+    clock::Clock,
+    clock_skew::ClockSkewMonitor,
     directory::PersonaDirectory,
     encryption::DecryptedDeks,
+    id_generator::IdGenerator,
     instance::AuthlyInstance,
+    metadata_cache::ServiceMetadataCache,
+    policy_cache::PolicyEngineCache,
+    session_cache::SessionCache,
+    settings::Settings,
+    stats::{AuthlyStats, RaftRole},
     webauthn::WebauthnError,
 };
 
@@ -33,6 +45,16 @@ pub trait GetBuiltins {
     fn get_builtins(&self) -> &Builtins;
 }
 
+/// Trait for getting the strategy used for generating new IDs
+pub trait GetIdGenerator {
+    fn get_id_generator(&self) -> &dyn IdGenerator;
+}
+
+/// Trait for getting the source of the current time
+pub trait GetClock {
+    fn get_clock(&self) -> &dyn Clock;
+}
+
 pub trait GetInstance {
     // Gets cheap read guard for the AuthlyInstance
     fn get_instance(&self) -> arc_swap::Guard<Arc<AuthlyInstance>>;
@@ -43,6 +65,11 @@ pub trait LoadInstance {
     fn load_instance(&self) -> Arc<AuthlyInstance>;
 }
 
+pub trait GetSettings {
+    // Gets cheap read guard for the current Settings
+    fn get_settings(&self) -> arc_swap::Guard<Arc<Settings>>;
+}
+
 pub trait SetInstance {
     // Sets a new AuthlyInstance
     fn set_instance(&self, instance: AuthlyInstance);
@@ -66,6 +93,26 @@ pub trait ServiceBus {
     fn service_event_dispatcher(&self) -> &ServiceEventDispatcher;
 }
 
+pub trait GetSessionCache {
+    fn get_session_cache(&self) -> &dyn SessionCache;
+}
+
+pub trait GetMetadataCache {
+    fn get_metadata_cache(&self) -> &ServiceMetadataCache;
+}
+
+pub trait GetPolicyEngineCache {
+    fn get_policy_engine_cache(&self) -> &PolicyEngineCache;
+}
+
+pub trait EntityEventBus {
+    fn entity_event_notifier(&self) -> &EntityEventNotifier;
+}
+
+pub trait GetAuditQueue {
+    fn get_audit_queue(&self) -> &AuditQueue;
+}
+
 pub trait GetHttpClient {
     fn get_internet_http_client(&self) -> reqwest::Client;
 }
@@ -95,6 +142,27 @@ pub trait KubernetesConfig {
     fn authly_local_k8s_namespace(&self) -> &str;
 }
 
+/// Trait for getting the clock offsets of the other nodes of the cluster
+pub trait GetClockSkew {
+    fn get_clock_skew(&self) -> &ClockSkewMonitor;
+}
+
+pub trait GetStats {
+    fn get_stats(&self) -> &AuthlyStats;
+
+    /// The role of the local node in the database cluster
+    fn raft_role(&self) -> impl Future<Output = RaftRole> + Send;
+
+    /// Whether the local node rejects writes, because the database cluster has lost quorum
+    fn is_read_only(&self) -> bool;
+
+    /// Whether at-rest encryption is disabled by the secrets backend
+    fn is_insecure_mode(&self) -> bool;
+
+    /// Number of database statements that exceeded the slow query threshold
+    fn slow_query_count(&self) -> u64;
+}
+
 pub trait WebAuthn {
     /// Get the Webauthn "site".
     fn get_webauthn(&self, public_uri: &Uri) -> Result<Arc<Webauthn>, WebauthnError>;
@@ -4,16 +4,18 @@ use authly_common::id::DirectoryId;
 use authly_db::{Db, DbError, FromRow, Row};
 use indexmap::IndexMap;
 use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::warn;
 
 use crate::{
     audit::Actor,
-    bus::{BusError, ClusterMessage},
-    ctx::{ClusterBus, Directories, GetDb, GetDecryptedDeks},
+    bus::{admin_events::AdminEventKind, BusError, ClusterMessage},
+    ctx::{ClusterBus, Directories, GetClock, GetDb, GetDecryptedDeks},
     document::compiled_document::CompiledDocument,
     encryption::{CryptoError, DecryptedDeks},
     id::BuiltinProp,
     repo::{
-        crypto_repo,
+        admin_event_repo, crypto_repo,
         directory_repo::DbDirectory,
         document_repo::{DocumentDbTxnError, DocumentTransaction},
         oauth_repo::{self, OAuthRow},
@@ -42,6 +44,8 @@ impl FromRow for DirForeignKey {
 pub enum DirectoryKind {
     Document,
     Persona,
+    /// Users and groups provisioned by SCIM clients
+    Scim,
 }
 
 impl Display for DirectoryKind {
@@ -80,6 +84,87 @@ pub struct OAuthDirectory {
     pub user_res_email_path: Option<String>,
 }
 
+/// Just-in-time provisioning of personas that log in through an OAuth directory
+/// but have no local entity yet.
+#[derive(Clone, PartialEq, Eq, Default, Debug)]
+pub struct JitProvisioning {
+    pub enabled: bool,
+
+    /// Email domains allowed to be provisioned. All domains are allowed when empty.
+    pub allowed_domains: Vec<String>,
+
+    /// User response path to the username identity
+    pub user_res_username_path: Option<String>,
+
+    /// User response claims mapped to entity properties.
+    /// The claim values are attribute labels of the property.
+    pub claim_attributes: Vec<ClaimAttributeMapping>,
+}
+
+impl JitProvisioning {
+    /// The provisioning of a directory without stored settings, provisioning any persona on its first login
+    pub fn unrestricted() -> Self {
+        Self {
+            enabled: true,
+            ..Default::default()
+        }
+    }
+
+    /// Whether a persona with the given email address may be provisioned
+    pub fn allows_email(&self, email: &str) -> bool {
+        if self.allowed_domains.is_empty() {
+            return true;
+        }
+
+        let Some((_, domain)) = email.rsplit_once('@') else {
+            return false;
+        };
+
+        self.allowed_domains
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(domain))
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ClaimAttributeMapping {
+    pub claim_path: String,
+    pub namespace: String,
+    pub property: String,
+}
+
+impl ClaimAttributeMapping {
+    /// Parse a comma-separated list of `claim.path=namespace:property`, skipping malformed items
+    pub fn parse_list(value: &str) -> Vec<Self> {
+        value
+            .split(',')
+            .filter_map(|item| {
+                let (claim_path, target) = item.split_once('=')?;
+                let (namespace, property) = target.trim().split_once(':')?;
+
+                Some(Self {
+                    claim_path: claim_path.trim().to_string(),
+                    namespace: namespace.to_string(),
+                    property: property.to_string(),
+                })
+            })
+            .collect()
+    }
+
+    pub fn format_list(mappings: &[Self]) -> String {
+        mappings
+            .iter()
+            .map(|mapping| {
+                format!(
+                    "{}={}:{}",
+                    mapping.claim_path, mapping.namespace, mapping.property
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+}
+
 #[derive(thiserror::Error, Debug)]
 pub enum DirectoryError {
     // #[error("db error: {0}")]
@@ -102,7 +187,7 @@ pub enum DirectoryError {
 
 /// Apply (write or overwrite) a document directory, publish change message
 pub async fn apply_document(
-    deps: &(impl GetDb + GetDecryptedDeks + ClusterBus + Directories),
+    deps: &(impl GetDb + GetDecryptedDeks + ClusterBus + Directories + GetClock),
     compiled_doc: CompiledDocument,
     actor: Actor,
 ) -> Result<(), DirectoryError> {
@@ -111,13 +196,27 @@ pub async fn apply_document(
     let service_ids: Vec<_> = compiled_doc.data.services.keys().copied().collect();
 
     let deks = deps.load_decrypted_deks();
+    let actor_eid = actor.0;
+    let now = deps.get_clock().now();
 
     DocumentTransaction::new(compiled_doc, actor)
-        .execute(deps.get_db(), &deks)
+        .execute(deps.get_db(), &deks, now)
         .await?;
 
     deps.handle_service_tls_reexport_to_file(service_ids);
 
+    // subscribers are woken up when the change message is handled
+    if let Err(err) = admin_event_repo::insert_admin_event(
+        deps.get_db(),
+        AdminEventKind::DirectoryChanged,
+        &json!({ "dirId": dir_id, "actor": actor_eid }),
+        now,
+    )
+    .await
+    {
+        warn!(?err, "unable to record directory event");
+    }
+
     deps.broadcast_to_cluster(ClusterMessage::DirectoryChanged { dir_id })
         .await?;
 
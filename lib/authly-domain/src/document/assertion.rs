@@ -0,0 +1,247 @@
+//! Policy assertions embedded in documents.
+//!
+//! A `[[policy-assertion]]` table states the expected outcome of an access control request:
+//!
+//! ```toml
+//! [[policy-assertion]]
+//! subject = "me"
+//! subject-attributes = ["svc:role:root"]
+//! resource-attributes = ["svc:kind:trousers"]
+//! expect = "allow"
+//! ```
+//!
+//! The assertions are evaluated against the policies of the document when it's compiled,
+//! and a document with a failing assertion is not applied.
+//!
+//! Assertion tables are not part of the document schema, and neither are `[[default-policy-binding]]` tables,
+//! `[[attribute-inheritance]]` tables or the `[defaults]` table.
+//! They are extracted from the source before the rest of it is parsed as a [Document].
+
+use std::{fmt, ops::Range};
+
+use anyhow::anyhow;
+use authly_common::{
+    document::{Document, PolicyBinding},
+    property::QualifiedAttributeName,
+};
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_spanned::Spanned;
+
+use crate::repo::service_repo::Inheritance;
+
+const ASSERTION_HEADER: &str = "[[policy-assertion]]";
+const DEFAULT_POLICY_BINDING_HEADER: &str = "[[default-policy-binding]]";
+const ATTRIBUTE_INHERITANCE_HEADER: &str = "[[attribute-inheritance]]";
+const DEFAULTS_HEADER: &str = "[defaults]";
+
+/// The tables of a document source which are not part of the document schema
+#[derive(Default)]
+pub struct DocumentExtensions {
+    pub policy_assertions: Vec<Spanned<PolicyAssertion>>,
+
+    /// Policy bindings applying to the services of the directory that have no policy bindings of their own
+    pub default_policy_bindings: Vec<Spanned<PolicyBinding>>,
+
+    /// The inheritance directions of entity properties defined by the document
+    pub attribute_inheritance: Vec<Spanned<AttributeInheritance>>,
+
+    pub defaults: Option<Spanned<DocumentDefaults>>,
+}
+
+/// Defaults for the rest of the document
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct DocumentDefaults {
+    /// The namespace of unqualified property and attribute references in policies,
+    /// like `Subject.role contains role:admin`
+    pub namespace: Option<Spanned<String>>,
+}
+
+/// How the attributes of an entity property are inherited along membership relations
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct AttributeInheritance {
+    pub namespace: Spanned<String>,
+    pub property: Spanned<String>,
+    pub direction: Inheritance,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct PolicyAssertion {
+    /// Label or ID of the subject entity
+    pub subject: Option<Spanned<String>>,
+
+    #[serde(default)]
+    pub subject_attributes: Vec<Spanned<QualifiedAttributeName>>,
+
+    #[serde(default)]
+    pub resource_attributes: Vec<Spanned<QualifiedAttributeName>>,
+
+    pub expect: Spanned<AssertionOutcome>,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum AssertionOutcome {
+    Allow,
+    Deny,
+}
+
+impl fmt::Display for AssertionOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Allow => write!(f, "allow"),
+            Self::Deny => write!(f, "deny"),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct AssertionTable {
+    #[serde(rename = "policy-assertion")]
+    policy_assertion: Vec<PolicyAssertion>,
+}
+
+#[derive(Deserialize)]
+struct DefaultPolicyBindingTable {
+    #[serde(rename = "default-policy-binding")]
+    default_policy_binding: Vec<PolicyBinding>,
+}
+
+#[derive(Deserialize)]
+struct AttributeInheritanceTable {
+    #[serde(rename = "attribute-inheritance")]
+    attribute_inheritance: Vec<AttributeInheritance>,
+}
+
+#[derive(Deserialize)]
+struct DefaultsTable {
+    defaults: DocumentDefaults,
+}
+
+/// Parse a document source, along with its extension tables.
+///
+/// Every extension is spanned by its whole table in the source.
+pub fn parse_document(source: &str) -> anyhow::Result<(Document, DocumentExtensions)> {
+    let assertion_blocks = table_blocks(source, ASSERTION_HEADER);
+    let default_binding_blocks = table_blocks(source, DEFAULT_POLICY_BINDING_HEADER);
+    let inheritance_blocks = table_blocks(source, ATTRIBUTE_INHERITANCE_HEADER);
+    let defaults_blocks = table_blocks(source, DEFAULTS_HEADER);
+    if defaults_blocks.len() > 1 {
+        return Err(anyhow!("`{DEFAULTS_HEADER}` is defined more than once"));
+    }
+
+    let extensions = DocumentExtensions {
+        policy_assertions: parse_blocks(source, &assertion_blocks, |table: AssertionTable| {
+            table.policy_assertion
+        })
+        .map_err(|err| anyhow!("invalid policy assertion: {err}"))?,
+        default_policy_bindings: parse_blocks(
+            source,
+            &default_binding_blocks,
+            |table: DefaultPolicyBindingTable| table.default_policy_binding,
+        )
+        .map_err(|err| anyhow!("invalid default policy binding: {err}"))?,
+        attribute_inheritance: parse_blocks(
+            source,
+            &inheritance_blocks,
+            |table: AttributeInheritanceTable| table.attribute_inheritance,
+        )
+        .map_err(|err| anyhow!("invalid attribute inheritance: {err}"))?,
+        defaults: parse_blocks(source, &defaults_blocks, |table: DefaultsTable| {
+            vec![table.defaults]
+        })
+        .map_err(|err| anyhow!("invalid defaults: {err}"))?
+        .pop(),
+    };
+
+    let mut document_source = source.to_string();
+    for block in assertion_blocks
+        .iter()
+        .chain(&default_binding_blocks)
+        .chain(&inheritance_blocks)
+        .chain(&defaults_blocks)
+    {
+        blank(&mut document_source, block);
+    }
+
+    let document = Document::from_toml(&document_source)?;
+
+    Ok((document, extensions))
+}
+
+pub(super) fn parse_blocks<T: DeserializeOwned, V>(
+    source: &str,
+    blocks: &[Range<usize>],
+    values: impl Fn(T) -> Vec<V>,
+) -> Result<Vec<Spanned<V>>, toml::de::Error> {
+    let mut parsed = Vec::with_capacity(blocks.len());
+
+    for block in blocks {
+        // Parse each block in place of the whole source, so that the spans of its values are kept
+        let table: T = toml::from_str(&blank_except(source, block))?;
+        let value = values(table).pop().expect("one table per block");
+        parsed.push(Spanned::new(block.clone(), value));
+    }
+
+    Ok(parsed)
+}
+
+/// Find the tables of the source with the given header.
+///
+/// A table lasts until the next table header, trailing blank lines and comments excluded.
+pub(super) fn table_blocks(source: &str, header: &str) -> Vec<Range<usize>> {
+    let mut blocks = vec![];
+    let mut current: Option<Range<usize>> = None;
+    let mut offset = 0;
+
+    for line in source.split_inclusive('\n') {
+        let line_start = offset;
+        offset += line.len();
+
+        let trimmed = line.trim();
+        let content = trimmed.split('#').next().unwrap_or_default().trim_end();
+
+        if is_table_header(content) {
+            blocks.extend(current.take());
+
+            if content == header {
+                let start = line_start + (line.len() - line.trim_start().len());
+                current = Some(start..start + content.len());
+            }
+        } else if let Some(block) = &mut current {
+            if !content.is_empty() {
+                block.end = line_start + line.trim_end().len();
+            }
+        }
+    }
+
+    blocks.extend(current);
+    blocks
+}
+
+fn is_table_header(content: &str) -> bool {
+    content.starts_with('[') && content.ends_with(']') && !content.contains('=')
+}
+
+/// Replace the given range of the source with whitespace, keeping line breaks and byte offsets
+pub(super) fn blank(source: &mut String, range: &Range<usize>) {
+    let blanked: String = source[range.clone()]
+        .chars()
+        .map(|c| match c {
+            '\n' => "\n".to_string(),
+            c => " ".repeat(c.len_utf8()),
+        })
+        .collect();
+
+    source.replace_range(range.clone(), &blanked);
+}
+
+/// A copy of the source where everything outside the range is blanked
+fn blank_except(source: &str, range: &Range<usize>) -> String {
+    let mut copy = source.to_string();
+    blank(&mut copy, &(range.end..source.len()));
+    blank(&mut copy, &(0..range.start));
+    copy
+}
@@ -1,12 +1,17 @@
 use std::{collections::BTreeMap, ops::Range};
 
-use authly_common::id::{
-    AnyId, AttrId, DirectoryId, DomainId, EntityId, PersonaId, PolicyId, PropId, ServiceId,
+use authly_common::{
+    id::{AnyId, AttrId, DirectoryId, DomainId, EntityId, PersonaId, PolicyId, PropId, ServiceId},
+    policy::code::OpCode,
 };
 
 use crate::{
     id::BuiltinProp,
-    repo::{policy_repo, service_repo::PropertyKind, Identified},
+    repo::{
+        policy_repo,
+        service_repo::{Inheritance, PropertyKind},
+        Identified,
+    },
     settings::Setting,
 };
 
@@ -45,7 +50,12 @@ pub struct CompiledDocumentData {
     pub domain_props: Vec<CompiledProperty>,
 
     pub policies: Vec<Identified<PolicyId, policy_repo::DbPolicy>>,
+    /// The opcodes compiled from each policy.
+    /// Only the policy expression is stored, this is kept for inspecting the compiler output.
+    pub policy_opcodes: BTreeMap<PolicyId, Vec<OpCode>>,
     pub policy_bindings: Vec<policy_repo::DbPolicyBinding>,
+    /// Bindings for the services of the directory that have no policy bindings of their own
+    pub default_policy_bindings: Vec<policy_repo::DbPolicyBinding>,
 }
 
 #[derive(Debug)]
@@ -79,7 +89,7 @@ pub struct EntityPassword {
     pub hash: String,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct CompiledEntityAttributeAssignment {
     pub eid: EntityId,
     pub attrid: AttrId,
@@ -98,6 +108,7 @@ pub struct CompiledProperty {
     pub ns_id: AnyId,
     pub kind: PropertyKind,
     pub label: String,
+    pub inheritance: Inheritance,
 
     pub attributes: Vec<CompiledAttribute>,
 }
@@ -118,6 +129,16 @@ impl CompiledDocumentData {
         self.domain_props.iter().find(|prop| prop.id == prop_id)
     }
 
+    /// The opcodes compiled from the policy with the given label
+    pub fn find_policy_opcodes(&self, label: &str) -> Option<&[OpCode]> {
+        let Identified(policy_id, _) = self
+            .policies
+            .iter()
+            .find(|policy| policy.1.label == label)?;
+
+        self.policy_opcodes.get(policy_id).map(Vec::as_slice)
+    }
+
     pub fn find_attribute_by_label(
         &self,
         prop_id: PropId,
@@ -0,0 +1,139 @@
+//! Machine-readable diagnostics of document errors.
+//!
+//! A [Diagnostic] is a [DocError] resolved against the source it was found in,
+//! serialized in a shape that editors and CI tools can consume directly.
+//! Positions follow the Language Server Protocol: zero-based lines, and columns counted in UTF-16 code units.
+
+use std::{fmt, ops::Range};
+
+use serde::Serialize;
+use serde_spanned::Spanned;
+
+use super::error::DocError;
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Serialize, Clone, PartialEq, Eq, Debug)]
+pub struct Diagnostic {
+    /// Stable code of the error, see [DocError::code]
+    pub code: &'static str,
+    pub severity: Severity,
+    /// Human readable message
+    pub message: String,
+    pub location: Location,
+    /// Other locations involved in the error, like the first definition of a duplicated name
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub related: Vec<RelatedLocation>,
+}
+
+#[derive(Serialize, Clone, PartialEq, Eq, Debug)]
+pub struct Location {
+    /// Byte range in the source
+    pub span: Range<usize>,
+    pub start: Position,
+    pub end: Position,
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Position {
+    pub line: usize,
+    pub character: usize,
+}
+
+#[derive(Serialize, Clone, PartialEq, Eq, Debug)]
+pub struct RelatedLocation {
+    pub message: &'static str,
+    pub location: Location,
+}
+
+impl Diagnostic {
+    pub fn new(source: &str, error: &Spanned<DocError>) -> Self {
+        let related = match error.get_ref() {
+            DocError::NameDefinedMultipleTimes(previous, _) => vec![RelatedLocation {
+                message: "previously defined here",
+                location: Location::new(source, previous.clone()),
+            }],
+            _ => vec![],
+        };
+
+        Self {
+            code: error.get_ref().code(),
+            severity: Severity::Error,
+            message: error.get_ref().to_string(),
+            location: Location::new(source, error.span()),
+            related,
+        }
+    }
+}
+
+/// The diagnostics of all the errors of a source
+pub fn diagnostics(source: &str, errors: &[Spanned<DocError>]) -> Vec<Diagnostic> {
+    errors
+        .iter()
+        .map(|error| Diagnostic::new(source, error))
+        .collect()
+}
+
+/// Human rendering, with one-based line and column numbers
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(
+            f,
+            "{severity}[{}]: {} at {}",
+            self.code, self.message, self.location.start
+        )?;
+
+        for related in &self.related {
+            write!(
+                f,
+                "\n  note: {} at {}",
+                related.message, related.location.start
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Location {
+    pub fn new(source: &str, span: Range<usize>) -> Self {
+        Self {
+            start: Position::new(source, span.start),
+            end: Position::new(source, span.end),
+            span,
+        }
+    }
+}
+
+impl Position {
+    /// The position of a byte offset, clamped to the source
+    pub fn new(source: &str, offset: usize) -> Self {
+        let mut offset = offset.min(source.len());
+        while !source.is_char_boundary(offset) {
+            offset -= 1;
+        }
+
+        let before = &source[..offset];
+        let line_start = before.rfind('\n').map(|idx| idx + 1).unwrap_or(0);
+
+        Self {
+            line: before.matches('\n').count(),
+            character: before[line_start..].encode_utf16().count(),
+        }
+    }
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.line + 1, self.character + 1)
+    }
+}
@@ -7,7 +7,10 @@ use std::{collections::HashMap, ops::Range};
 use authly_common::{
     document,
     id::{AnyId, AttrId, DirectoryId, DomainId, EntityId, PolicyId, PropId, ServiceId},
-    policy::code::PolicyValue,
+    policy::{
+        code::PolicyValue,
+        engine::{AccessControlParams, NoOpPolicyTracer, PolicyEngine},
+    },
     property::QualifiedAttributeName,
 };
 use authly_db::{Db, DbError};
@@ -16,21 +19,30 @@ use serde::Deserialize;
 use serde_spanned::Spanned;
 use tracing::debug;
 
-use crate::ctx::{GetDb, KubernetesConfig};
+use crate::ctx::{GetDb, GetIdGenerator, GetSettings, KubernetesConfig};
 use crate::directory::DirKey;
 use crate::document::compiled_document::{
     CompiledEntityAttributeAssignment, CompiledService, ObjectIdent, ObjectTextAttr,
 };
 use crate::error::{HandleError, ResultExt};
 use crate::id::BuiltinProp;
+use crate::id_generator::IdGenerator;
 use crate::policy::compiler::PolicyCompiler;
 use crate::repo::directory_repo::{
     self, query_dir_key, DbDirectoryNamespaceLabel, DbDirectoryPolicy,
 };
 use crate::repo::policy_repo::{self, DbPolicy};
-use crate::repo::{service_repo, Identified};
+use crate::repo::{
+    service_repo::{self, Inheritance},
+    Identified,
+};
+use crate::serde_util::to_canonical_json;
+use crate::service::{is_valid_k8s_service_account, normalize_hostname};
 use crate::settings::{Setting, Settings};
 
+use super::assertion::{
+    AssertionOutcome, AttributeInheritance, DocumentExtensions, PolicyAssertion,
+};
 use super::compiled_document::{
     CompiledAttribute, CompiledDocument, CompiledDocumentData, CompiledEntityRelation,
     CompiledProperty, DocumentMeta,
@@ -88,10 +100,13 @@ pub enum NamespaceKind {
     Policy(PolicyId),
 }
 
-struct CompileCtx {
+struct CompileCtx<'a> {
     dir_key: DirKey,
     dir_id: DirectoryId,
 
+    /// Generates the IDs of new namespaces, properties, attributes and policies
+    ids: &'a dyn IdGenerator,
+
     namespaces: Namespaces,
 
     prop_cache: HashMap<AnyId, Vec<service_repo::NamespaceProperty>>,
@@ -112,11 +127,14 @@ struct Errors {
 }
 
 pub async fn compile_doc(
-    deps: &(impl GetDb + KubernetesConfig),
+    deps: &(impl GetDb + GetSettings + GetIdGenerator + KubernetesConfig),
     mut doc: document::Document,
+    extensions: DocumentExtensions,
     meta: DocumentMeta,
 ) -> Result<CompiledDocument, Vec<Spanned<DocError>>> {
     let db = deps.get_db();
+    let ident_normalization = deps.get_settings().ident_normalization;
+    let username_policy = deps.get_settings().username_policy.clone();
     let dir_id = DirectoryId::from_uint(doc.authly_document.id.get_ref().as_u128());
     let dir_key = query_dir_key(db, dir_id)
         .await
@@ -126,6 +144,7 @@ pub async fn compile_doc(
     let mut comp = CompileCtx {
         dir_key,
         dir_id,
+        ids: deps.get_id_generator(),
         namespaces: Default::default(),
         prop_cache: Default::default(),
         policy_cache: Default::default(),
@@ -176,7 +195,7 @@ pub async fn compile_doc(
                 .get(domain.label.as_ref())
                 .copied()
                 .and_then(|id| DomainId::try_from(id).ok())
-                .unwrap_or_else(DomainId::random);
+                .unwrap_or_else(|| comp.ids.generate());
 
             comp.ns_add(&domain.label, NamespaceKind::Domain(id));
 
@@ -191,7 +210,7 @@ pub async fn compile_doc(
                     ObjectTextAttr {
                         obj_id: id.upcast(),
                         prop_id: PropId::from(BuiltinProp::Metadata),
-                        value: serde_json::to_string(&metadata).expect("already valid json"),
+                        value: to_canonical_json(&metadata).expect("already valid json"),
                     },
                     span,
                 ));
@@ -204,11 +223,17 @@ pub async fn compile_doc(
     for entity in &mut doc.entity {
         if let Some(username) = entity.username.take() {
             let span = username.span();
+            if username_policy.is_reserved(username.get_ref()) {
+                comp.errors.push(span.clone(), DocError::ReservedUsername);
+            }
+
             data.entity_ident.push((
                 ObjectIdent {
                     obj_id: entity.eid.as_ref().upcast(),
                     prop_id: BuiltinProp::Username.into(),
-                    ident: username.into_inner(),
+                    ident: ident_normalization
+                        .normalize(BuiltinProp::Username.into(), username.get_ref())
+                        .into_owned(),
                 },
                 span,
             ));
@@ -233,6 +258,21 @@ pub async fn compile_doc(
         }
 
         if let Some(k8s) = mem::take(&mut entity.kubernetes_account) {
+            if !is_valid_k8s_service_account(k8s.namespace.as_deref(), &k8s.name) {
+                let span = match &entity.label {
+                    Some(label) => label.span(),
+                    None => entity.eid.span(),
+                };
+                comp.errors.push(
+                    span,
+                    DocError::InvalidKubernetesAccount(format!(
+                        "{namespace}/{account}",
+                        namespace = k8s.namespace.as_deref().unwrap_or_default(),
+                        account = k8s.name
+                    )),
+                );
+            }
+
             data.obj_text_attrs.push((
                 ObjectTextAttr {
                     obj_id: svc_eid.upcast(),
@@ -262,9 +302,25 @@ pub async fn compile_doc(
             ));
         }
 
-        let service = CompiledService {
-            hosts: mem::take(&mut entity.hosts),
-        };
+        let mut hosts: Vec<String> = vec![];
+        for host in mem::take(&mut entity.hosts) {
+            match normalize_hostname(&host) {
+                Some(host) => {
+                    if !hosts.contains(&host) {
+                        hosts.push(host);
+                    }
+                }
+                None => {
+                    let span = match &entity.label {
+                        Some(label) => label.span(),
+                        None => entity.eid.span(),
+                    };
+                    comp.errors.push(span, DocError::InvalidHost(host));
+                }
+            }
+        }
+
+        let service = CompiledService { hosts };
 
         data.services.insert(svc_eid, service);
 
@@ -274,7 +330,7 @@ pub async fn compile_doc(
                 ObjectTextAttr {
                     obj_id: svc_eid.upcast(),
                     prop_id: PropId::from(BuiltinProp::Metadata),
-                    value: serde_json::to_string(&metadata).expect("already valid json"),
+                    value: to_canonical_json(&metadata).expect("already valid json"),
                 },
                 span,
             ));
@@ -291,12 +347,16 @@ pub async fn compile_doc(
             ObjectIdent {
                 obj_id: eid.upcast(),
                 prop_id: BuiltinProp::Email.into(),
-                ident: email.value.into_inner(),
+                ident: ident_normalization
+                    .normalize(BuiltinProp::Email.into(), email.value.get_ref())
+                    .into_owned(),
             },
             span,
         ));
     }
 
+    check_identity_conflicts(&data.entity_ident, &mut comp.errors);
+
     for hash in mem::take(&mut doc.password_hash) {
         let Some(eid) = comp.ns_entity_lookup(&hash.entity) else {
             continue;
@@ -323,9 +383,41 @@ pub async fn compile_doc(
     )
     .await;
 
+    process_attribute_inheritance(extensions.attribute_inheritance, &mut data, &mut comp);
+
     process_attribute_assignments(&mut doc, &mut data, &mut comp);
-    process_policies(doc.policy, &mut data, &mut comp, db).await;
-    process_policy_bindings(doc.policy_binding, &mut data, &mut comp);
+
+    let default_namespace = extensions
+        .defaults
+        .and_then(|defaults| defaults.into_inner().namespace)
+        .filter(|namespace| {
+            let exists = comp.namespaces.get_namespace(namespace.get_ref()).is_some();
+            if !exists {
+                comp.errors
+                    .push(namespace.span(), DocError::UnresolvedNamespace);
+            }
+            exists
+        });
+
+    process_policies(
+        doc.policy,
+        default_namespace
+            .as_ref()
+            .map(|namespace| namespace.get_ref().as_str()),
+        &mut data,
+        &mut comp,
+        db,
+    )
+    .await;
+    data.policy_bindings = compile_policy_bindings(doc.policy_binding, &data, &mut comp);
+    data.default_policy_bindings = compile_policy_bindings(
+        extensions
+            .default_policy_bindings
+            .into_iter()
+            .map(Spanned::into_inner),
+        &data,
+        &mut comp,
+    );
 
     process_entity_attribute_assignments(
         mem::take(&mut doc.entity_attribute_assignment),
@@ -335,22 +427,19 @@ pub async fn compile_doc(
     .await;
 
     for doc_svc_domain in doc.service_domain {
-        if let (Some(svc_eid), Some(dom_id)) = (
+        if let (Some(svc_eid), Some(domain_id)) = (
             comp.ns_service_lookup(&doc_svc_domain.service),
-            comp.ns_dyn_namespace_lookup(&doc_svc_domain.domain),
+            comp.ns_domain_lookup(&doc_svc_domain.domain),
         ) {
-            match DomainId::try_from(dom_id) {
-                Ok(domain_id) => {
-                    data.service_domains.push((svc_eid, domain_id));
-                }
-                Err(_) => {
-                    comp.errors
-                        .push(doc_svc_domain.domain.span(), DocError::UnresolvedDomain);
-                }
-            }
+            data.service_domains.push((svc_eid, domain_id));
         }
     }
 
+    // assertions are only meaningful when the policies compiled
+    if comp.errors.errors.is_empty() {
+        check_policy_assertions(extensions.policy_assertions, &data, &mut comp);
+    }
+
     if !comp.errors.errors.is_empty() {
         Err(comp.errors.errors)
     } else {
@@ -362,7 +451,112 @@ pub async fn compile_doc(
     }
 }
 
-fn seed_namespace(doc: &document::Document, comp: &mut CompileCtx) {
+/// Evaluate the policy assertions of the document against its own policies and bindings.
+///
+/// Like for a service, the default policy bindings are used when the document has no policy bindings.
+fn check_policy_assertions(
+    assertions: Vec<Spanned<PolicyAssertion>>,
+    data: &CompiledDocumentData,
+    comp: &mut CompileCtx<'_>,
+) {
+    if assertions.is_empty() {
+        return;
+    }
+
+    let mut engine = PolicyEngine::default();
+
+    for Identified(id, db_policy) in &data.policies {
+        engine.add_policy(
+            *id,
+            match db_policy.policy.class {
+                PolicyValue::Allow => PolicyValue::Allow,
+                _ => PolicyValue::Deny,
+            },
+            db_policy.policy.to_bytecode(),
+        );
+    }
+
+    let bindings = if data.policy_bindings.is_empty() {
+        &data.default_policy_bindings
+    } else {
+        &data.policy_bindings
+    };
+
+    for binding in bindings {
+        engine.add_trigger(binding.attr_matcher.clone(), binding.policies.clone());
+    }
+
+    for assertion in assertions {
+        let span = assertion.span();
+        let assertion = assertion.into_inner();
+        let mut params = AccessControlParams::default();
+        let error_count = comp.errors.errors.len();
+
+        if let Some(subject) = &assertion.subject {
+            if let Some(eid) = comp.ns_entity_lookup(subject) {
+                params.subject_eids.insert(BuiltinProp::Entity.into(), eid);
+            }
+        }
+
+        for (attributes, param_attrs) in [
+            (&assertion.subject_attributes, &mut params.subject_attrs),
+            (&assertion.resource_attributes, &mut params.resource_attrs),
+        ] {
+            for spanned_qattr in attributes {
+                if let Some(attr_id) = qualified_attribute_lookup(spanned_qattr, data, comp) {
+                    param_attrs.insert(attr_id);
+                }
+            }
+        }
+
+        if comp.errors.errors.len() > error_count {
+            continue;
+        }
+
+        let outcome = match engine.eval(&params, &mut NoOpPolicyTracer) {
+            Ok(PolicyValue::Allow) => AssertionOutcome::Allow,
+            _ => AssertionOutcome::Deny,
+        };
+
+        if outcome != *assertion.expect.get_ref() {
+            comp.errors.push(
+                span,
+                DocError::PolicyAssertionFailed {
+                    expected: *assertion.expect.get_ref(),
+                },
+            );
+        }
+    }
+}
+
+/// Report identities assigned to more than one entity within the document.
+///
+/// Conflicts with entities from other directories are detected when the document is applied.
+fn check_identity_conflicts(entity_ident: &[(ObjectIdent, Range<usize>)], errors: &mut Errors) {
+    let mut owners: HashMap<(PropId, &str), AnyId> = HashMap::new();
+
+    for (ident, span) in entity_ident {
+        match owners.entry((ident.prop_id, ident.ident.as_str())) {
+            Entry::Vacant(vacant) => {
+                vacant.insert(ident.obj_id);
+            }
+            Entry::Occupied(occupied) => {
+                if *occupied.get() != ident.obj_id {
+                    errors.push(
+                        span.clone(),
+                        DocError::IdentityConflict {
+                            prop_id: ident.prop_id,
+                            entity: ident.obj_id,
+                            owner: *occupied.get(),
+                        },
+                    );
+                }
+            }
+        }
+    }
+}
+
+fn seed_namespace(doc: &document::Document, comp: &mut CompileCtx<'_>) {
     comp.namespaces.table.insert(
         "authly".to_string(),
         Spanned::new(
@@ -396,7 +590,7 @@ fn seed_namespace(doc: &document::Document, comp: &mut CompileCtx) {
 fn process_members(
     members_list: Vec<document::Members>,
     data: &mut CompiledDocumentData,
-    comp: &mut CompileCtx,
+    comp: &mut CompileCtx<'_>,
 ) {
     for members in members_list {
         let Some(subject_eid) = comp.ns_entity_lookup(&members.entity) else {
@@ -418,7 +612,7 @@ fn process_members(
 async fn process_entity_attribute_assignments(
     assignments: Vec<document::EntityAttributeAssignment>,
     data: &mut CompiledDocumentData,
-    comp: &mut CompileCtx,
+    comp: &mut CompileCtx<'_>,
 ) {
     for binding in assignments {
         let Some(eid) = comp.ns_entity_lookup(&binding.entity) else {
@@ -455,7 +649,7 @@ async fn process_service_properties(
     entity_properties: Vec<document::EntityProperty>,
     resource_properties: Vec<document::ResourceProperty>,
     data: &mut CompiledDocumentData,
-    comp: &mut CompileCtx,
+    comp: &mut CompileCtx<'_>,
     db: &impl Db,
 ) {
     for doc_eprop in entity_properties {
@@ -505,9 +699,10 @@ async fn compile_ns_property(
     property_kind: service_repo::PropertyKind,
     doc_property_label: &Spanned<String>,
     doc_attributes: Vec<Spanned<String>>,
-    comp: &mut CompileCtx,
+    comp: &mut CompileCtx<'_>,
     db: &impl Db,
 ) -> Option<CompiledProperty> {
+    let ids = comp.ids;
     let db_props_cached = comp.db_namespace_properties_cached(ns_id, db).await?;
 
     let db_eprop = db_props_cached.iter().find(|db_prop| {
@@ -518,10 +713,11 @@ async fn compile_ns_property(
         id: db_eprop
             .as_ref()
             .map(|db_prop| db_prop.id)
-            .unwrap_or_else(PropId::random),
+            .unwrap_or_else(|| ids.generate()),
         ns_id,
         kind: property_kind,
         label: doc_property_label.as_ref().to_string(),
+        inheritance: Inheritance::default(),
         attributes: vec![],
     };
 
@@ -537,7 +733,7 @@ async fn compile_ns_property(
             id: db_attr
                 .as_ref()
                 .map(|attr| attr.0)
-                .unwrap_or_else(AttrId::random),
+                .unwrap_or_else(|| ids.generate()),
             label: doc_attribute.into_inner(),
         });
     }
@@ -551,11 +747,38 @@ async fn compile_ns_property(
     Some(compiled_property)
 }
 
+/// Set the inheritance direction of the entity properties defined by the document
+fn process_attribute_inheritance(
+    inheritance_list: Vec<Spanned<AttributeInheritance>>,
+    data: &mut CompiledDocumentData,
+    comp: &mut CompileCtx<'_>,
+) {
+    for inheritance in inheritance_list {
+        let inheritance = inheritance.into_inner();
+        let Some(prop_id) = comp.ns_property_lookup(&inheritance.namespace, &inheritance.property)
+        else {
+            continue;
+        };
+
+        match data.domain_props.iter_mut().find(|property| {
+            property.id == prop_id && property.kind == service_repo::PropertyKind::Entity
+        }) {
+            Some(property) => {
+                property.inheritance = inheritance.direction;
+            }
+            None => {
+                comp.errors
+                    .push(inheritance.property.span(), DocError::UnresolvedProperty);
+            }
+        }
+    }
+}
+
 /// Assign attributes to entities
 fn process_attribute_assignments(
     doc: &mut document::Document,
     data: &mut CompiledDocumentData,
-    comp: &mut CompileCtx,
+    comp: &mut CompileCtx<'_>,
 ) {
     let mut assignments: Vec<(EntityId, Spanned<QualifiedAttributeName>)> = vec![];
 
@@ -594,8 +817,9 @@ fn process_attribute_assignments(
 
 async fn process_policies(
     policies: Vec<document::Policy>,
+    default_namespace: Option<&str>,
     data: &mut CompiledDocumentData,
-    comp: &mut CompileCtx,
+    comp: &mut CompileCtx<'_>,
     db: &impl Db,
 ) {
     for policy in policies {
@@ -616,9 +840,10 @@ async fn process_policies(
             }
         };
 
-        let mut policy_compiler = PolicyCompiler::new(&comp.namespaces, data);
+        let mut policy_compiler =
+            PolicyCompiler::new(&comp.namespaces, data).with_default_namespace(default_namespace);
 
-        let (expr, _bytecode) = match policy_compiler.compile(src.as_ref()) {
+        let (expr, opcodes) = match policy_compiler.compile(src.as_ref()) {
             Ok(compiled_policy) => compiled_policy,
             Err(errors) => {
                 for error in errors {
@@ -656,7 +881,7 @@ async fn process_policies(
                 )
             } else {
                 Identified(
-                    PolicyId::random(),
+                    comp.ids.generate(),
                     policy_repo::DbPolicy {
                         label: policy.label.into_inner(),
                         policy: policy_postcard,
@@ -675,15 +900,18 @@ async fn process_policies(
             ),
         );
 
+        data.policy_opcodes.insert(*service_policy.id(), opcodes);
         data.policies.push(service_policy);
     }
 }
 
-fn process_policy_bindings(
-    policy_bindings: Vec<document::PolicyBinding>,
-    data: &mut CompiledDocumentData,
-    comp: &mut CompileCtx,
-) {
+fn compile_policy_bindings(
+    policy_bindings: impl IntoIterator<Item = document::PolicyBinding>,
+    data: &CompiledDocumentData,
+    comp: &mut CompileCtx<'_>,
+) -> Vec<policy_repo::DbPolicyBinding> {
+    let mut compiled = vec![];
+
     for binding in policy_bindings {
         let mut policy_binding = policy_repo::DbPolicyBinding {
             attr_matcher: Default::default(),
@@ -691,23 +919,10 @@ fn process_policy_bindings(
         };
 
         for spanned_qattr in binding.attributes {
-            let Some(prop_id) = comp.ns_property_lookup(
-                &Spanned::new(spanned_qattr.span(), &spanned_qattr.as_ref().namespace),
-                &Spanned::new(spanned_qattr.span(), &spanned_qattr.as_ref().property),
-            ) else {
+            let Some(attr_id) = qualified_attribute_lookup(&spanned_qattr, data, comp) else {
                 continue;
             };
 
-            let attr_id =
-                match data.find_attribute_by_label(prop_id, &spanned_qattr.get_ref().attribute) {
-                    Ok(attr_id) => attr_id,
-                    Err(_) => {
-                        comp.errors
-                            .push(spanned_qattr.span(), DocError::UnresolvedAttribute);
-                        continue;
-                    }
-                };
-
             policy_binding.attr_matcher.insert(attr_id);
         }
 
@@ -719,11 +934,33 @@ fn process_policy_bindings(
             policy_binding.policies.insert(policy_id);
         }
 
-        data.policy_bindings.push(policy_binding);
+        compiled.push(policy_binding);
     }
+
+    compiled
 }
 
-impl CompileCtx {
+fn qualified_attribute_lookup(
+    spanned_qattr: &Spanned<QualifiedAttributeName>,
+    data: &CompiledDocumentData,
+    comp: &mut CompileCtx<'_>,
+) -> Option<AttrId> {
+    let prop_id = comp.ns_property_lookup(
+        &Spanned::new(spanned_qattr.span(), &spanned_qattr.as_ref().namespace),
+        &Spanned::new(spanned_qattr.span(), &spanned_qattr.as_ref().property),
+    )?;
+
+    match data.find_attribute_by_label(prop_id, &spanned_qattr.get_ref().attribute) {
+        Ok(attr_id) => Some(attr_id),
+        Err(_) => {
+            comp.errors
+                .push(spanned_qattr.span(), DocError::UnresolvedAttribute);
+            None
+        }
+    }
+}
+
+impl CompileCtx<'_> {
     fn ns_add(&mut self, namespace: &Spanned<String>, kind: NamespaceKind) -> bool {
         if let Some(entry) = self.namespaces.table.insert(
             namespace.get_ref().to_string(),
@@ -800,6 +1037,17 @@ impl CompileCtx {
         }
     }
 
+    /// Look up a domain by label. Other kinds of namespaces are not domains, even if they resolve.
+    fn ns_domain_lookup(&mut self, key: &Spanned<impl AsRef<str>>) -> Option<DomainId> {
+        match self.ns_lookup_kind(key, DocError::UnresolvedDomain)? {
+            NamespaceKind::Domain(domain_id) => Some(*domain_id),
+            _ => {
+                self.errors.push(key.span(), DocError::UnresolvedDomain);
+                None
+            }
+        }
+    }
+
     fn ns_dyn_namespace_lookup(&mut self, key: &Spanned<impl AsRef<str>>) -> Option<AnyId> {
         if let Ok(entity_id) = EntityId::from_str(key.as_ref().as_ref()) {
             return Some(entity_id.upcast());
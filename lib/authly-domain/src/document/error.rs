@@ -1,35 +1,118 @@
 use std::ops::Range;
 
+use authly_common::id::{AnyId, PropId};
 use authly_db::DbError;
+use thiserror::Error;
 
 use crate::policy::error::PolicyCompileErrorKind;
 
+use super::assertion::AssertionOutcome;
+
 /// DocError includes problems related with the document contents,
 /// as well as problems with writing it to the database.
-#[derive(Debug)]
+#[derive(Error, Debug)]
 pub enum DocError {
+    /// The source is not a valid TOML document
+    #[error("invalid syntax: {0}")]
+    Syntax(String),
+    /// A key that is not part of the document schema, reported in strict mode
+    #[error("unknown field `{0}`")]
+    UnknownField(String),
+    #[error("unknown local setting")]
     LocalSettingNotFound,
+    #[error("invalid setting value: {0}")]
     InvalidSettingValue(String),
+    #[error("`{1}` is defined more than once")]
     NameDefinedMultipleTimes(Range<usize>, String),
+    #[error("unresolved domain")]
     UnresolvedDomain,
+    #[error("unresolved namespace")]
     UnresolvedNamespace,
+    #[error("unresolved entity")]
     UnresolvedEntity,
+    #[error("unresolved profile")]
     UnresolvedProfile,
+    #[error("unresolved group")]
     UnresolvedGroup,
+    #[error("unresolved service")]
     UnresolvedService,
+    #[error("unresolved property")]
     UnresolvedProperty,
+    #[error("unresolved attribute")]
     UnresolvedAttribute,
+    #[error("unresolved policy")]
     UnresolvedPolicy,
+    #[error("must be a service ID")]
     MustBeAServiceId,
+    #[error("the policy has neither `allow` nor `deny`")]
     PolicyBodyMissing,
+    #[error("the policy has both `allow` and `deny`")]
     AmbiguousPolicyOutcome,
+    #[error("metadata is not supported here")]
     MetadataNotSupported,
+    /// A service host is not a valid DNS name
+    #[error("invalid host: {0}")]
+    InvalidHost(String),
+    /// A Kubernetes service account reference is not a valid `namespace/name`
+    #[error("invalid Kubernetes service account: {0}")]
+    InvalidKubernetesAccount(String),
+    /// The username is reserved by the `RESERVED_USERNAMES` setting
+    #[error("the username is reserved")]
+    ReservedUsername,
+    #[error("{0}")]
     Policy(PolicyCompileErrorKind),
+    /// A policy assertion of the document doesn't hold for the policies of the document
+    #[error("policy assertion failed, expected {expected}")]
+    PolicyAssertionFailed { expected: AssertionOutcome },
+    /// An identity (username or email address) is assigned to more than one entity.
+    /// Identities are unique per identity property, across all directories.
+    #[error("the identity is already assigned to another entity")]
+    IdentityConflict {
+        prop_id: PropId,
+        entity: AnyId,
+        owner: AnyId,
+    },
     /// Error from transaction:
+    #[error("constraint violation")]
     ConstraintViolation,
+    #[error("database error: {0}")]
     Db(String),
 }
 
+impl DocError {
+    /// A stable code identifying the kind of error, for programs
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Syntax(_) => "syntax",
+            Self::UnknownField(_) => "unknown-field",
+            Self::LocalSettingNotFound => "local-setting-not-found",
+            Self::InvalidSettingValue(_) => "invalid-setting-value",
+            Self::NameDefinedMultipleTimes(..) => "name-defined-multiple-times",
+            Self::UnresolvedDomain => "unresolved-domain",
+            Self::UnresolvedNamespace => "unresolved-namespace",
+            Self::UnresolvedEntity => "unresolved-entity",
+            Self::UnresolvedProfile => "unresolved-profile",
+            Self::UnresolvedGroup => "unresolved-group",
+            Self::UnresolvedService => "unresolved-service",
+            Self::UnresolvedProperty => "unresolved-property",
+            Self::UnresolvedAttribute => "unresolved-attribute",
+            Self::UnresolvedPolicy => "unresolved-policy",
+            Self::MustBeAServiceId => "must-be-a-service-id",
+            Self::PolicyBodyMissing => "policy-body-missing",
+            Self::AmbiguousPolicyOutcome => "ambiguous-policy-outcome",
+            Self::MetadataNotSupported => "metadata-not-supported",
+            Self::InvalidHost(_) => "invalid-host",
+            Self::InvalidKubernetesAccount(_) => "invalid-kubernetes-account",
+            Self::ReservedUsername => "reserved-username",
+            Self::Policy(kind) => kind.code(),
+            Self::PolicyAssertionFailed { .. } => "policy-assertion-failed",
+            Self::IdentityConflict { .. } => "identity-conflict",
+            Self::ConstraintViolation => "constraint-violation",
+            Self::Db(_) => "database",
+        }
+    }
+}
+
 impl From<DbError> for DocError {
     fn from(value: DbError) -> Self {
         Self::Db(value.to_string())
@@ -0,0 +1,186 @@
+//! Composition of documents from several files.
+//!
+//! A document can import fragments of other files with `[[import]]` tables:
+//!
+//! ```toml
+//! [[import]]
+//! path = "common/properties.toml"
+//! ```
+//!
+//! Paths are relative to the importing file, and must stay within the directory of the document:
+//! absolute paths, and relative paths leading out of the directory with `..`, are rejected.
+//! A fragment has the same tables as a document,
+//! except `[authly-document]`, and may import other fragments in turn.
+//! The imported fragments are appended to the source of the document before it is parsed,
+//! and a fragment imported more than once is only included the first time.
+//!
+//! Spans of the parsed document point into the composed source, [ComposedSource::locate] finds the file they belong to.
+
+use std::{
+    ops::Range,
+    path::{Component, Path, PathBuf},
+};
+
+use serde::Deserialize;
+
+use super::assertion::{blank, parse_blocks, table_blocks};
+
+const IMPORT_HEADER: &str = "[[import]]";
+const DOCUMENT_HEADER: &str = "[authly-document]";
+
+#[derive(thiserror::Error, Debug)]
+pub enum ImportError {
+    #[error("{0:?} could not be read: {1}")]
+    Read(PathBuf, anyhow::Error),
+
+    #[error("invalid import in {0:?}: {1}")]
+    Invalid(PathBuf, toml::de::Error),
+
+    #[error("fragment {0:?} must not have an `{DOCUMENT_HEADER}` table")]
+    DocumentHeader(PathBuf),
+
+    #[error("import {1:?} in {0:?} is outside of the document directory")]
+    OutsideRoot(PathBuf, PathBuf),
+
+    #[error("import cycle: {}", .0.iter().map(|path| format!("{path:?}")).collect::<Vec<_>>().join(" -> "))]
+    Cycle(Vec<PathBuf>),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Import {
+    path: PathBuf,
+}
+
+#[derive(Deserialize)]
+struct ImportTable {
+    import: Vec<Import>,
+}
+
+/// The source of a document with its imports resolved
+#[derive(Debug)]
+pub struct ComposedSource {
+    pub source: String,
+
+    /// The files the source is made of, in order, with their range in the source
+    pub files: Vec<(PathBuf, Range<usize>)>,
+}
+
+impl ComposedSource {
+    /// Find the file a span of the composed source points into, and the span within that file
+    pub fn locate(&self, span: Range<usize>) -> Option<(&Path, Range<usize>)> {
+        self.files
+            .iter()
+            .find(|(_, range)| range.start <= span.start && span.end <= range.end)
+            .map(|(path, range)| {
+                (
+                    path.as_path(),
+                    span.start - range.start..span.end - range.start,
+                )
+            })
+    }
+}
+
+/// Compose the document at `path` with its imports, reading files with `read`.
+///
+/// Imports are resolved lexically, `read` should check that the files it reads don't lead out of the document directory through symlinks.
+pub fn compose(
+    path: &Path,
+    mut read: impl FnMut(&Path) -> anyhow::Result<String>,
+) -> Result<ComposedSource, ImportError> {
+    let mut composed = ComposedSource {
+        source: String::new(),
+        files: vec![],
+    };
+    let mut stack = vec![];
+    let root = normalize(path)
+        .parent()
+        .map(Path::to_path_buf)
+        .unwrap_or_default();
+
+    append_file(path, &root, true, &mut read, &mut stack, &mut composed)?;
+
+    Ok(composed)
+}
+
+fn append_file(
+    path: &Path,
+    root: &Path,
+    is_document: bool,
+    read: &mut impl FnMut(&Path) -> anyhow::Result<String>,
+    stack: &mut Vec<PathBuf>,
+    composed: &mut ComposedSource,
+) -> Result<(), ImportError> {
+    let path = normalize(path);
+
+    if stack.contains(&path) {
+        let mut cycle = stack.clone();
+        cycle.push(path);
+        return Err(ImportError::Cycle(cycle));
+    }
+    if composed.files.iter().any(|(included, _)| included == &path) {
+        return Ok(());
+    }
+
+    let mut source = read(&path).map_err(|err| ImportError::Read(path.clone(), err))?;
+
+    if !is_document && !table_blocks(&source, DOCUMENT_HEADER).is_empty() {
+        return Err(ImportError::DocumentHeader(path));
+    }
+
+    let import_blocks = table_blocks(&source, IMPORT_HEADER);
+    let imports = parse_blocks(&source, &import_blocks, |table: ImportTable| table.import)
+        .map_err(|err| ImportError::Invalid(path.clone(), err))?;
+    for block in &import_blocks {
+        blank(&mut source, block);
+    }
+
+    // every file starts on a new line, so that its first table header is recognized
+    if !composed.source.is_empty() && !composed.source.ends_with('\n') {
+        composed.source.push('\n');
+    }
+    let start = composed.source.len();
+    composed.source.push_str(&source);
+    composed
+        .files
+        .push((path.clone(), start..composed.source.len()));
+
+    let dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+    stack.push(path);
+    for import in imports {
+        let import_path = &import.get_ref().path;
+        let resolved = normalize(&dir.join(import_path));
+
+        if import_path.is_absolute()
+            || !resolved.starts_with(root)
+            || resolved
+                .components()
+                .any(|component| component == Component::ParentDir)
+        {
+            let importer = stack.last().cloned().unwrap_or_default();
+            return Err(ImportError::OutsideRoot(importer, import_path.clone()));
+        }
+
+        append_file(&resolved, root, false, read, stack, composed)?;
+    }
+    stack.pop();
+
+    Ok(())
+}
+
+/// Resolve `.` and `..` components, so that a file has one path regardless of how it's imported
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !normalized.pop() {
+                    normalized.push("..");
+                }
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
@@ -0,0 +1,141 @@
+//! Environment variable interpolation in document sources.
+//!
+//! A document can refer to an environment variable as `${AUTHLY_DOC_NAME}`, only variables with the `AUTHLY_DOC_` prefix are interpolated.
+//! Interpolation happens on the source text before parsing, so spans of errors refer to the interpolated document.
+//! Only string values are interpolated, references in comments and elsewhere are left as they are.
+//! Values must not escape the TOML string they are placed in, and the tables of policies and their bindings are left alone,
+//! so the meaning of a policy can't change with the environment.
+
+use std::ops::Range;
+
+use serde_spanned::Spanned;
+use toml::de::{DeTable, DeValue};
+
+/// The prefix of environment variables available to documents
+pub const ENV_PREFIX: &str = "AUTHLY_DOC_";
+
+/// Tables of policies and their bindings, which must not be interpolated
+const POLICY_TABLES: &[&str] = &["policy", "policy-binding", "default-policy-binding"];
+
+#[derive(thiserror::Error, PartialEq, Eq, Debug)]
+pub enum InterpolationError {
+    #[error("undefined environment variable `{name}` on line {line}")]
+    Undefined { name: String, line: usize },
+
+    #[error("environment variable `{name}` on line {line} contains quotes, backslashes or control characters")]
+    UnsafeValue { name: String, line: usize },
+
+    #[error("environment variable `{name}` can't be used in the policy on line {line}")]
+    PolicyExpression { name: String, line: usize },
+}
+
+/// Interpolate `AUTHLY_DOC_` environment variables of the process into the document source
+pub fn interpolate_env(source: &str) -> Result<String, InterpolationError> {
+    interpolate(source, |name| std::env::var(name).ok())
+}
+
+/// Interpolate `AUTHLY_DOC_` variables into the document source, looking up their values with `lookup`
+pub fn interpolate(
+    source: &str,
+    lookup: impl Fn(&str) -> Option<String>,
+) -> Result<String, InterpolationError> {
+    // syntax errors are left to the parser of the document
+    let Ok(root) = DeTable::parse(source) else {
+        return Ok(source.to_string());
+    };
+
+    let mut strings = vec![];
+    for (key, value) in root.get_ref() {
+        let in_policy = POLICY_TABLES
+            .iter()
+            .any(|table| *table == key.get_ref().as_ref());
+        collect_strings(value, in_policy, &mut strings);
+    }
+    strings.sort_by_key(|(span, _)| span.start);
+
+    let mut output = String::with_capacity(source.len());
+    let mut copied = 0;
+
+    for (span, in_policy) in strings {
+        let mut offset = span.start;
+
+        while let Some((name, start, end)) = find_variable(&source[offset..span.end]) {
+            let (start, end) = (offset + start, offset + end);
+            let line = source[..start].matches('\n').count() + 1;
+
+            if in_policy {
+                return Err(InterpolationError::PolicyExpression {
+                    name: name.to_string(),
+                    line,
+                });
+            }
+
+            let value = lookup(name).ok_or_else(|| InterpolationError::Undefined {
+                name: name.to_string(),
+                line,
+            })?;
+            if value
+                .chars()
+                .any(|c| c.is_control() || matches!(c, '"' | '\'' | '\\'))
+            {
+                return Err(InterpolationError::UnsafeValue {
+                    name: name.to_string(),
+                    line,
+                });
+            }
+
+            output.push_str(&source[copied..start]);
+            output.push_str(&value);
+            copied = end;
+            offset = end;
+        }
+    }
+
+    output.push_str(&source[copied..]);
+
+    Ok(output)
+}
+
+/// Collect the spans of the string values within `value`, and whether they belong to a policy table
+fn collect_strings(
+    value: &Spanned<DeValue>,
+    in_policy: bool,
+    strings: &mut Vec<(Range<usize>, bool)>,
+) {
+    match value.get_ref() {
+        DeValue::String(_) => strings.push((value.span(), in_policy)),
+        DeValue::Array(array) => {
+            for item in array {
+                collect_strings(item, in_policy, strings);
+            }
+        }
+        DeValue::Table(table) => {
+            for (_, value) in table {
+                collect_strings(value, in_policy, strings);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Find the next `${AUTHLY_DOC_*}` reference, returning the variable name and the byte range of the reference
+fn find_variable(text: &str) -> Option<(&str, usize, usize)> {
+    let mut offset = 0;
+
+    while let Some(pos) = text[offset..].find("${") {
+        let start = offset + pos;
+        let name_start = start + 2;
+        let name_len = text[name_start..]
+            .find(|c: char| !(c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_'))
+            .unwrap_or(text.len() - name_start);
+        let name = &text[name_start..name_start + name_len];
+
+        if name.starts_with(ENV_PREFIX) && text[name_start + name_len..].starts_with('}') {
+            return Some((name, start, name_start + name_len + 1));
+        }
+
+        offset = name_start;
+    }
+
+    None
+}
@@ -1,3 +1,9 @@
+pub mod assertion;
 pub mod compiled_document;
+pub mod diagnostic;
 pub mod doc_compiler;
 pub mod error;
+pub mod import;
+pub mod interpolate;
+pub mod standalone;
+pub mod strict;
@@ -0,0 +1,118 @@
+//! Compilation of document sources outside of a running Authly, e.g. in editors.
+//!
+//! The database is only read, to reuse the IDs of an existing directory and to resolve references to it.
+//! Pass a snapshot of the cluster database to see the document as it would be applied,
+//! or an empty, migrated database to check the document on its own.
+
+use std::sync::Arc;
+
+use arc_swap::ArcSwap;
+use authly_db::Db;
+use serde_spanned::Spanned;
+
+use crate::{
+    ctx::{GetDb, GetIdGenerator, GetSettings, KubernetesConfig},
+    id_generator::{IdGenerator, RandomIdGenerator},
+    settings::Settings,
+};
+
+use super::{
+    assertion::parse_document,
+    compiled_document::{CompiledDocument, DocumentMeta},
+    diagnostic::{diagnostics, Diagnostic},
+    doc_compiler::compile_doc,
+    error::DocError,
+    strict::check_unknown_fields,
+};
+
+/// Options of [compile_source]
+pub struct CompileOptions {
+    /// The settings the document is compiled with, like identity normalization
+    pub settings: Settings,
+
+    /// The Kubernetes namespace of Authly, which service accounts without a namespace belong to
+    pub k8s_namespace: String,
+
+    /// Report keys that are not part of the document schema, see [super::strict]
+    pub strict: bool,
+
+    pub meta: DocumentMeta,
+}
+
+impl Default for CompileOptions {
+    fn default() -> Self {
+        Self {
+            settings: Settings::default(),
+            k8s_namespace: "default".to_string(),
+            strict: true,
+            meta: DocumentMeta::default(),
+        }
+    }
+}
+
+struct SourceCompileCtx<'a, D> {
+    db: &'a D,
+    settings: ArcSwap<Settings>,
+    k8s_namespace: String,
+}
+
+impl<D: Db> GetDb for SourceCompileCtx<'_, D> {
+    type Db = D;
+
+    fn get_db(&self) -> &Self::Db {
+        self.db
+    }
+}
+
+impl<D> GetSettings for SourceCompileCtx<'_, D> {
+    fn get_settings(&self) -> arc_swap::Guard<Arc<Settings>> {
+        self.settings.load()
+    }
+}
+
+impl<D> GetIdGenerator for SourceCompileCtx<'_, D> {
+    fn get_id_generator(&self) -> &dyn IdGenerator {
+        &RandomIdGenerator
+    }
+}
+
+impl<D> KubernetesConfig for SourceCompileCtx<'_, D> {
+    fn authly_local_k8s_namespace(&self) -> &str {
+        &self.k8s_namespace
+    }
+}
+
+/// Compile a document from its source, without applying it.
+///
+/// All problems of the document are returned as diagnostics, with locations in `source`.
+pub async fn compile_source(
+    db: &impl Db,
+    source: &str,
+    options: CompileOptions,
+) -> Result<CompiledDocument, Vec<Diagnostic>> {
+    if options.strict {
+        check_unknown_fields(source).map_err(|errors| diagnostics(source, &errors))?;
+    }
+
+    let (doc, extensions) = parse_document(source).map_err(|err| {
+        let span = err
+            .downcast_ref::<toml::de::Error>()
+            .and_then(toml::de::Error::span)
+            .unwrap_or(0..0);
+
+        diagnostics(
+            source,
+            &[Spanned::new(span, DocError::Syntax(err.to_string()))],
+        )
+    })?;
+
+    let ctx = SourceCompileCtx {
+        db,
+        settings: ArcSwap::new(Arc::new(options.settings)),
+        k8s_namespace: options.k8s_namespace,
+    };
+
+    compile_doc(&ctx, doc, extensions, options.meta)
+        .await
+        .map_err(|errors| diagnostics(source, &errors))
+}
@@ -0,0 +1,167 @@
+//! Strict checking of document sources.
+//!
+//! The document schema silently ignores keys it doesn't know, so a misspelled key has no effect.
+//! In strict mode, every key of the source is checked against the known tables and their fields,
+//! and unknown keys are reported with the span of the key itself.
+
+use serde_spanned::Spanned;
+use toml::de::{DeTable, DeValue};
+
+use super::error::DocError;
+
+/// The expected shape of a value in the source
+enum Schema {
+    /// A value which is not checked any further, like free-form metadata
+    Value,
+    /// A table, or an array of tables, with known fields
+    Table(&'static [(&'static str, Schema)]),
+}
+
+const KUBERNETES_ACCOUNT: Schema =
+    Schema::Table(&[("name", Schema::Value), ("namespace", Schema::Value)]);
+const POLICY_BINDING: Schema =
+    Schema::Table(&[("attributes", Schema::Value), ("policies", Schema::Value)]);
+const PROPERTY: Schema = Schema::Table(&[
+    ("namespace", Schema::Value),
+    ("label", Schema::Value),
+    ("attributes", Schema::Value),
+]);
+
+/// The tables of a document, including the tables of [super::assertion::DocumentExtensions]
+const DOCUMENT: Schema = Schema::Table(&[
+    ("authly-document", Schema::Table(&[("id", Schema::Value)])),
+    // setting names are checked by the compiler
+    ("local-settings", Schema::Value),
+    (
+        "entity",
+        Schema::Table(&[
+            ("eid", Schema::Value),
+            ("label", Schema::Value),
+            ("attributes", Schema::Value),
+            ("username", Schema::Value),
+            ("email", Schema::Value),
+            ("password-hash", Schema::Value),
+            ("metadata", Schema::Value),
+        ]),
+    ),
+    (
+        "service-entity",
+        Schema::Table(&[
+            ("eid", Schema::Value),
+            ("label", Schema::Value),
+            ("attributes", Schema::Value),
+            ("metadata", Schema::Value),
+            ("hosts", Schema::Value),
+            ("kubernetes-account", KUBERNETES_ACCOUNT),
+        ]),
+    ),
+    (
+        "email",
+        Schema::Table(&[("entity", Schema::Value), ("value", Schema::Value)]),
+    ),
+    (
+        "password-hash",
+        Schema::Table(&[("entity", Schema::Value), ("hash", Schema::Value)]),
+    ),
+    (
+        "members",
+        Schema::Table(&[("entity", Schema::Value), ("members", Schema::Value)]),
+    ),
+    (
+        "domain",
+        Schema::Table(&[("label", Schema::Value), ("metadata", Schema::Value)]),
+    ),
+    (
+        "service-domain",
+        Schema::Table(&[("service", Schema::Value), ("domain", Schema::Value)]),
+    ),
+    ("entity-property", PROPERTY),
+    ("resource-property", PROPERTY),
+    (
+        "attribute-inheritance",
+        Schema::Table(&[
+            ("namespace", Schema::Value),
+            ("property", Schema::Value),
+            ("direction", Schema::Value),
+        ]),
+    ),
+    (
+        "entity-attribute-assignment",
+        Schema::Table(&[("entity", Schema::Value), ("attributes", Schema::Value)]),
+    ),
+    (
+        "policy",
+        Schema::Table(&[
+            ("label", Schema::Value),
+            ("allow", Schema::Value),
+            ("deny", Schema::Value),
+        ]),
+    ),
+    ("policy-binding", POLICY_BINDING),
+    ("default-policy-binding", POLICY_BINDING),
+    (
+        "policy-assertion",
+        Schema::Table(&[
+            ("subject", Schema::Value),
+            ("subject-attributes", Schema::Value),
+            ("resource-attributes", Schema::Value),
+            ("expect", Schema::Value),
+        ]),
+    ),
+    ("defaults", Schema::Table(&[("namespace", Schema::Value)])),
+]);
+
+/// Check that the source only uses keys of the document schema.
+///
+/// Syntax errors are not reported here, they are left to the parser of the document.
+pub fn check_unknown_fields(source: &str) -> Result<(), Vec<Spanned<DocError>>> {
+    let Ok(root) = DeTable::parse(source) else {
+        return Ok(());
+    };
+
+    let mut errors = vec![];
+    check_table(root.get_ref(), &DOCUMENT, "", &mut errors);
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+fn check_table(table: &DeTable, schema: &Schema, path: &str, errors: &mut Vec<Spanned<DocError>>) {
+    let Schema::Table(fields) = schema else {
+        return;
+    };
+
+    for (key, value) in table {
+        let key_path = if path.is_empty() {
+            key.get_ref().to_string()
+        } else {
+            format!("{path}.{}", key.get_ref())
+        };
+
+        match fields
+            .iter()
+            .find(|(field, _)| *field == key.get_ref().as_ref())
+        {
+            Some((_, schema)) => check_value(value.get_ref(), schema, &key_path, errors),
+            None => errors.push(Spanned::new(key.span(), DocError::UnknownField(key_path))),
+        }
+    }
+}
+
+fn check_value(value: &DeValue, schema: &Schema, path: &str, errors: &mut Vec<Spanned<DocError>>) {
+    match value {
+        DeValue::Table(table) => check_table(table, schema, path, errors),
+        DeValue::Array(array) => {
+            for item in array {
+                if let DeValue::Table(table) = item.get_ref() {
+                    check_table(table, schema, path, errors);
+                }
+            }
+        }
+        // the type of the value is checked by the parser of the document
+        _ => {}
+    }
+}
@@ -1,3 +1,18 @@
+//! Encryption at rest.
+//!
+//! Every encrypted builtin property ([BuiltinProp::is_encrypted]) has its own Data Encryption Key (DEK),
+//! which is itself encrypted by the master key held by the secrets backend.
+//!
+//! Encrypted columns:
+//! - `obj_ident`: usernames, emails and OAuth client secrets, looked up through a keyed `fingerprint`
+//! - `authly_instance`: the instance private key
+//! - `token_signing_key`: access token signing keys
+//! - `ent_passkey`: WebAuthn passkeys (`pk_nonce`, `pk_ciph`)
+//!
+//! The DEK of [BuiltinProp::AuditChain] encrypts nothing, it keys the hash chain of the access control audit records.
+//!
+//! Password hashes are not encrypted, as they are already one-way.
+
 use std::{collections::HashMap, fmt::Debug};
 
 use aes_gcm_siv::{
@@ -46,6 +61,33 @@ impl DecryptedDeks {
             .get(&id)
             .ok_or_else(|| anyhow!("no DEK present for {id}"))
     }
+
+    /// Encrypt a value using the DEK of the given property, with a fresh nonce
+    pub fn encrypt(
+        &self,
+        id: PropId,
+        plaintext: &[u8],
+    ) -> anyhow::Result<(Nonce<Aes256GcmSiv>, Vec<u8>)> {
+        let nonce = random_nonce();
+        let ciph = self
+            .get(id)?
+            .aes()
+            .encrypt(&nonce, plaintext)
+            .map_err(|err| anyhow!("encryption failed: {err}"))?;
+
+        Ok((nonce, ciph))
+    }
+
+    /// Decrypt a value encrypted by [Self::encrypt]
+    pub fn decrypt(&self, id: PropId, nonce: &[u8], ciph: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let nonce = Nonce::<Aes256GcmSiv>::from_exact_iter(nonce.iter().copied())
+            .ok_or_else(|| anyhow!("invalid nonce length"))?;
+
+        self.get(id)?
+            .aes()
+            .decrypt(&nonce, ciph)
+            .map_err(|err| anyhow!("decryption failed: {err}"))
+    }
 }
 
 #[derive(Clone)]
@@ -105,6 +147,16 @@ impl AesKey {
         hasher.update(data);
         *hasher.finalize().as_bytes()
     }
+
+    /// A blake3 hasher keyed by this key, for hashes that can't be recomputed without it
+    pub fn keyed_hasher(&self) -> blake3::Hasher {
+        blake3::Hasher::new_keyed(
+            self.key
+                .as_slice()
+                .try_into()
+                .expect("AES-256 key is 32 bytes"),
+        )
+    }
 }
 
 impl Drop for AesKey {
@@ -13,7 +13,8 @@ use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
 use crate::{
     access_control::{authorize_peer_service, VerifyAuthlyRole},
     access_token::{create_access_token_claims, VerifiedAccessToken},
-    ctx::{GetDb, GetInstance},
+    api_error::ApiError,
+    ctx::{GetClock, GetDb, GetInstance, GetSessionCache, GetSettings},
     dev::IsDev,
     repo::entity_repo,
     session::{authenticate_session_cookie, SESSION_COOKIE_NAME},
@@ -37,21 +38,25 @@ pub struct WebAuth<R: VerifyAuthlyRole> {
 
 impl<Ctx, R: VerifyAuthlyRole> axum::extract::FromRequestParts<Ctx> for ApiAuth<R>
 where
-    Ctx: GetDb + GetInstance + Send + Sync,
+    Ctx: GetDb + GetInstance + GetSettings + GetSessionCache + GetClock + Send + Sync,
 {
-    type Rejection = (StatusCode, &'static str);
+    type Rejection = ApiError;
 
     async fn from_request_parts(parts: &mut Parts, ctx: &Ctx) -> Result<Self, Self::Rejection> {
-        verify::<R>(parts, ctx).await.map(|claims| Self {
-            claims,
-            _phantom: PhantomData,
-        })
+        match verify::<R>(parts, ctx).await {
+            Ok(claims) => Ok(Self {
+                claims,
+                _phantom: PhantomData,
+            }),
+            Err((StatusCode::FORBIDDEN, msg)) => Err(ApiError::forbidden(msg)),
+            Err((_, msg)) => Err(ApiError::unauthorized(msg)),
+        }
     }
 }
 
 impl<Ctx, R: VerifyAuthlyRole> axum::extract::FromRequestParts<Ctx> for WebAuth<R>
 where
-    Ctx: GetDb + GetInstance + Send + Sync,
+    Ctx: GetDb + GetInstance + GetSettings + GetSessionCache + GetClock + Send + Sync,
 {
     type Rejection = axum::response::Response;
 
@@ -77,7 +82,7 @@ where
 
 async fn verify<R: VerifyAuthlyRole>(
     parts: &mut Parts,
-    ctx: &(impl GetDb + GetInstance + Send + Sync),
+    ctx: &(impl GetDb + GetInstance + GetSettings + GetSessionCache + GetClock + Send + Sync),
 ) -> Result<AuthlyAccessTokenClaims, (StatusCode, &'static str)> {
     let Extension(peer_svc_eid) = parts
         .extract::<Extension<PeerServiceEntity>>()
@@ -94,11 +99,11 @@ async fn verify<R: VerifyAuthlyRole>(
         let session = authenticate_session_cookie(ctx, session_cookie)
             .await
             .map_err(|err| (StatusCode::UNAUTHORIZED, err))?;
-        let user_attributes = entity_repo::list_entity_attrs(ctx.get_db(), session.eid)
+        let user_attributes = entity_repo::list_resolved_entity_attrs(ctx.get_db(), session.eid)
             .await
             .map_err(|_err| (StatusCode::UNAUTHORIZED, "db error"))?;
 
-        create_access_token_claims(&session, user_attributes)
+        create_access_token_claims(&session, user_attributes, ctx.get_clock().now())
     } else {
         // production mode
 
@@ -0,0 +1,68 @@
+//! Health of the local Authly node, shared by the health checks of the different protocols.
+
+use std::time::Duration;
+
+use authly_db::{params, Db, FromRow, Row};
+use serde::Serialize;
+
+use crate::{
+    clock_skew::ClockSkew,
+    ctx::{GetClockSkew, GetDb, GetStats},
+    stats::RaftRole,
+};
+
+/// How long the database may take to answer before it's considered unhealthy
+const DB_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The outcome of a health check of the local node
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Debug)]
+pub struct Health {
+    /// Whether the database answered a query in time
+    pub db_healthy: bool,
+    pub raft_role: RaftRole,
+    /// Whether the node only serves reads, because the cluster has lost quorum
+    pub read_only: bool,
+    /// How the local clock compares to the other nodes of the cluster
+    pub clock_skew: ClockSkew,
+}
+
+impl Health {
+    /// Whether the node is able to serve requests.
+    ///
+    /// Besides a working database, the clock of the node must agree with the other nodes for tokens to expire at the same time everywhere.
+    /// A node that has lost its leader still serves reads in read-only mode, so it's serving too.
+    pub fn is_serving(&self) -> bool {
+        self.db_healthy
+            && self.raft_role != RaftRole::Shutdown
+            && self.clock_skew != ClockSkew::Skewed
+    }
+}
+
+/// Check the health of the local node
+pub async fn check_health(deps: &(impl GetDb + GetStats + GetClockSkew)) -> Health {
+    Health {
+        db_healthy: ping_db(deps.get_db()).await,
+        raft_role: deps.raft_role().await,
+        read_only: deps.is_read_only(),
+        clock_skew: deps.get_clock_skew().clock_skew(),
+    }
+}
+
+async fn ping_db(db: &impl Db) -> bool {
+    struct One;
+
+    impl FromRow for One {
+        fn from_row(_row: &mut impl Row) -> Self {
+            Self
+        }
+    }
+
+    matches!(
+        tokio::time::timeout(
+            DB_TIMEOUT,
+            db.query_map::<One>("SELECT 1".into(), params!())
+        )
+        .await,
+        Ok(Ok(rows)) if !rows.is_empty()
+    )
+}
@@ -1,5 +1,35 @@
-use authly_common::id::{AttrId, PropId};
+use std::str::FromStr;
+
+use authly_common::id::{kind::IdKind, AttrId, Id128, PropId};
 use int_enum::IntEnum;
+use serde::Serialize;
+
+/// The highest ID in the range reserved for builtins.
+///
+/// Builtin entities, properties and attributes have IDs within this range,
+/// while generated IDs and IDs supplied through APIs are always above it.
+pub const RESERVED_ID_MAX: u128 = u16::MAX as u128;
+
+/// The namespace that builtin properties and attributes are referenced through in documents
+pub const BUILTIN_NAMESPACE: &str = "authly";
+
+/// Whether an ID is in the range reserved for builtins
+pub const fn is_reserved_id(id: u128) -> bool {
+    id <= RESERVED_ID_MAX
+}
+
+/// Parse an ID supplied from outside Authly, rejecting IDs in the reserved range
+pub fn parse_unreserved_id<K: IdKind>(input: &str) -> Option<Id128<K>>
+where
+    Id128<K>: FromStr,
+{
+    let id = Id128::<K>::from_str(input).ok()?;
+    if is_reserved_id(u128::from_be_bytes(id.to_raw_array())) {
+        return None;
+    }
+
+    Some(id)
+}
 
 #[derive(Clone, Copy, Eq, PartialEq, Hash, IntEnum, Debug)]
 #[repr(u32)]
@@ -35,6 +65,16 @@ pub enum BuiltinProp {
     /// The namespace will be resolved to authly's namespace if it's a wildcard in configuration.
     K8sLocalServiceAccount = 9,
     OAuthClientSecret = 10,
+    /// The property used for encrypting stored WebAuthn passkeys
+    Passkey = 11,
+    /// The hour of the day of an access control request, an environment property used by schedule conditions
+    TimeHour = 12,
+    /// The day of the week of an access control request, an environment property used by schedule conditions
+    TimeWeekday = 13,
+    /// The IP address of the subject of an access control request, an environment property used by network conditions
+    SubjectIp = 14,
+    /// The property whose DEK keys the hash chain of the access control audit records
+    AuditChain = 15,
 }
 
 #[derive(Clone, Copy, Eq, PartialEq, Hash, IntEnum, Debug)]
@@ -48,6 +88,14 @@ pub enum BuiltinAttr {
     AuthlyRoleApplyDocument = 2,
     /// A user role for granting mandates to authority
     AuthlyRoleGrantMandate = 3,
+    /// A user role for administering and monitoring Authly
+    AuthlyRoleAdmin = 4,
+    /// A service role for receiving the reason behind access control decisions
+    AuthlyRoleAccessControlDetails = 5,
+    /// A service role for having its certificates signed by Authly
+    AuthlyRoleSignCertificate = 6,
+    /// A service role for receiving messages from Authly, like cache invalidations
+    AuthlyRoleReceiveMessages = 7,
 }
 
 impl From<BuiltinProp> for PropId {
@@ -76,6 +124,9 @@ impl BuiltinProp {
             Self::RelEntityMembership => None,
             Self::Metadata => None,
             Self::OAuthClientSecret => None,
+            Self::Passkey => None,
+            Self::AuditChain => None,
+            Self::TimeHour | Self::TimeWeekday | Self::SubjectIp => None,
         }
     }
 
@@ -83,11 +134,14 @@ impl BuiltinProp {
         match self {
             Self::Entity | Self::AuthlyRole | Self::RelEntityMembership | Self::Metadata => false,
             Self::PasswordHash => false,
+            Self::TimeHour | Self::TimeWeekday | Self::SubjectIp => false,
             Self::K8sConfiguredServiceAccount | Self::K8sLocalServiceAccount => false,
             Self::Username => true,
             Self::Email => true,
             Self::AuthlyInstance => true,
             Self::OAuthClientSecret => true,
+            Self::Passkey => true,
+            Self::AuditChain => true,
         }
     }
 
@@ -98,6 +152,10 @@ impl BuiltinProp {
                 BuiltinAttr::AuthlyRoleAuthenticate,
                 BuiltinAttr::AuthlyRoleApplyDocument,
                 BuiltinAttr::AuthlyRoleGrantMandate,
+                BuiltinAttr::AuthlyRoleAdmin,
+                BuiltinAttr::AuthlyRoleAccessControlDetails,
+                BuiltinAttr::AuthlyRoleSignCertificate,
+                BuiltinAttr::AuthlyRoleReceiveMessages,
             ],
             _ => &[],
         }
@@ -123,6 +181,79 @@ impl BuiltinAttr {
             Self::AuthlyRoleAuthenticate => Some("authenticate"),
             Self::AuthlyRoleApplyDocument => Some("apply_document"),
             Self::AuthlyRoleGrantMandate => Some("grant_mandate"),
+            Self::AuthlyRoleAdmin => Some("admin"),
+            Self::AuthlyRoleAccessControlDetails => Some("access_control_details"),
+            Self::AuthlyRoleSignCertificate => Some("sign_certificate"),
+            Self::AuthlyRoleReceiveMessages => Some("receive_messages"),
         }
     }
 }
+
+/// A listing of the builtin properties and their attributes, like the `authly:role` roles.
+///
+/// Tooling can reference builtins through this listing instead of hardcoding their IDs and labels.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct BuiltinCatalog {
+    pub properties: Vec<BuiltinPropEntry>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct BuiltinPropEntry {
+    /// Stable name of the builtin, like `AuthlyRole`
+    pub name: String,
+    pub id: PropId,
+    /// The label in the `authly` namespace, if the property can be referenced from documents
+    pub label: Option<&'static str>,
+    /// How documents and policies reference the property, like `authly:role`
+    pub reference: Option<String>,
+    pub attributes: Vec<BuiltinAttrEntry>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct BuiltinAttrEntry {
+    /// Stable name of the builtin, like `AuthlyRoleAdmin`
+    pub name: String,
+    pub id: AttrId,
+    pub label: Option<&'static str>,
+    /// How documents and policies reference the attribute, like `authly:role:admin`
+    pub reference: Option<String>,
+}
+
+impl BuiltinCatalog {
+    pub fn new() -> Self {
+        let properties = BuiltinProp::iter()
+            .map(|prop| {
+                let prop_label = prop.label();
+                BuiltinPropEntry {
+                    name: format!("{prop:?}"),
+                    id: prop.into(),
+                    label: prop_label,
+                    reference: prop_label.map(|label| format!("{BUILTIN_NAMESPACE}:{label}")),
+                    attributes: prop
+                        .attributes()
+                        .iter()
+                        .map(|attr| BuiltinAttrEntry {
+                            name: format!("{attr:?}"),
+                            id: (*attr).into(),
+                            label: attr.label(),
+                            reference: prop_label.zip(attr.label()).map(|(prop_label, label)| {
+                                format!("{BUILTIN_NAMESPACE}:{prop_label}:{label}")
+                            }),
+                        })
+                        .collect(),
+                }
+            })
+            .collect();
+
+        Self { properties }
+    }
+}
+
+impl Default for BuiltinCatalog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,118 @@
+//! Generation of the IDs of new entities and objects.
+//!
+//! IDs up to [RESERVED_ID_MAX] are reserved for builtins, no generator returns them.
+
+use std::{
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use authly_common::id::{kind::IdKind, Id128};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::id::{is_reserved_id, RESERVED_ID_MAX};
+
+/// A strategy for generating new IDs
+pub trait IdGenerator: Send + Sync {
+    /// The next ID, outside the reserved range
+    fn next_id(&self) -> u128;
+}
+
+impl dyn IdGenerator + '_ {
+    /// Generate a new typed ID
+    pub fn generate<K: IdKind>(&self) -> Id128<K> {
+        Id128::from_uint(self.next_id())
+    }
+}
+
+/// The ID generation strategies selectable by configuration
+#[derive(Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum IdStrategy {
+    #[default]
+    Random,
+    TimeOrdered,
+}
+
+impl IdStrategy {
+    pub fn generator(self) -> Box<dyn IdGenerator> {
+        match self {
+            Self::Random => Box::new(RandomIdGenerator),
+            Self::TimeOrdered => Box::new(TimeOrderedIdGenerator::default()),
+        }
+    }
+}
+
+/// Uniformly random IDs, the default
+#[derive(Default)]
+pub struct RandomIdGenerator;
+
+impl IdGenerator for RandomIdGenerator {
+    fn next_id(&self) -> u128 {
+        loop {
+            let id = rand::random::<u128>();
+            if !is_reserved_id(id) {
+                return id;
+            }
+        }
+    }
+}
+
+/// Time-ordered IDs, like ULIDs.
+///
+/// The upper 48 bits are the Unix time in milliseconds and the lower 80 bits are random.
+/// IDs generated by the same generator are strictly increasing, also within the same millisecond.
+#[derive(Default)]
+pub struct TimeOrderedIdGenerator {
+    last: Mutex<u128>,
+}
+
+impl IdGenerator for TimeOrderedIdGenerator {
+    fn next_id(&self) -> u128 {
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_millis())
+            .unwrap_or_default()
+            & ((1 << 48) - 1);
+        let random = rand::random::<u128>() & ((1 << 80) - 1);
+        let id = (millis << 80) | random;
+
+        let mut last = self.last.lock().unwrap();
+        *last = if id > *last { id } else { *last + 1 };
+
+        if is_reserved_id(*last) {
+            // only before the epoch: keep the order, but out of the reserved range
+            *last += RESERVED_ID_MAX + 1;
+        }
+
+        *last
+    }
+}
+
+/// IDs supplied from outside Authly.
+///
+/// Reserved IDs returned by the supplier are replaced by random IDs.
+pub struct ExternalIdGenerator {
+    supply: Box<dyn Fn() -> u128 + Send + Sync>,
+}
+
+impl ExternalIdGenerator {
+    pub fn new(supply: impl Fn() -> u128 + Send + Sync + 'static) -> Self {
+        Self {
+            supply: Box::new(supply),
+        }
+    }
+}
+
+impl IdGenerator for ExternalIdGenerator {
+    fn next_id(&self) -> u128 {
+        let id = (self.supply)();
+        if is_reserved_id(id) {
+            warn!(id, "externally supplied ID is reserved, using a random ID");
+            return RandomIdGenerator.next_id();
+        }
+
+        id
+    }
+}
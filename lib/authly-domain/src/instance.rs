@@ -1,25 +1,42 @@
-use std::ops::Deref;
+use std::{ops::Deref, sync::Arc};
 
 use authly_common::id::ServiceId;
-use rcgen::{Issuer, KeyPair, PublicKeyData};
+use rcgen::{CertificateParams, CertificateSigningRequestParams, PublicKeyData};
+use rustls::pki_types::{CertificateDer, CertificateSigningRequestDer};
+use serde::Serialize;
+use time::OffsetDateTime;
 
 use crate::{
     cert::{Cert, SigningRequest},
+    signer::{self, Signer},
     tls::{AuthlyCert, AuthlyCertKind},
+    token_signing::{JwkSet, TokenSigningKey, TokenSigningKeys},
 };
 
 /// Instance data, related to this installation of Authly
 pub struct AuthlyInstance {
     authly_id: AuthlyId,
+    key_source: InstanceKeySource,
     certs: Vec<AuthlyCert>,
     local_jwt_decoding_key: jsonwebtoken::DecodingKey,
+    token_signing_keys: TokenSigningKeys,
 }
 
 /// IDs of the authly instance.
-/// Consists of Entity ID and key pair.
+/// Consists of Entity ID and the signer of the instance key.
 pub struct AuthlyId {
     pub eid: ServiceId,
-    pub private_key: KeyPair,
+    pub signer: Arc<dyn Signer>,
+}
+
+/// Where the instance key is held
+#[derive(Clone, Default)]
+pub enum InstanceKeySource {
+    /// The key is generated by Authly, and stored encrypted in the database
+    #[default]
+    Database,
+    /// The key is held by an external signer, such as an HSM, and never leaves it
+    External(Arc<dyn Signer>),
 }
 
 impl AuthlyInstance {
@@ -48,13 +65,50 @@ impl AuthlyInstance {
             jsonwebtoken::DecodingKey::from_ec_der(&x509_cert.public_key().subject_public_key.data)
         };
 
+        // compatibility mode: sign access tokens with the instance key until a dedicated key is provided
+        let token_signing_keys = TokenSigningKeys::compat(TokenSigningKey::from_signer(
+            id.signer.clone(),
+            OffsetDateTime::UNIX_EPOCH,
+        ));
+
         Self {
             authly_id: id,
+            key_source: InstanceKeySource::Database,
             certs,
             local_jwt_decoding_key,
+            token_signing_keys,
         }
     }
 
+    /// Record where the instance key is held, so that reloading the instance finds it again
+    pub fn with_key_source(mut self, key_source: InstanceKeySource) -> Self {
+        self.key_source = key_source;
+        self
+    }
+
+    pub fn key_source(&self) -> &InstanceKeySource {
+        &self.key_source
+    }
+
+    /// Use dedicated keys for signing access tokens, independent of the CA.
+    ///
+    /// The keys are ordered with the current key first. An empty list means compatibility mode.
+    pub fn with_token_signing_keys(mut self, keys: Vec<TokenSigningKey>) -> Self {
+        self.token_signing_keys = self.token_signing_keys.with_dedicated(keys);
+        self
+    }
+
+    /// Sign new access tokens with a key held outside the database, such as in a KMS
+    pub fn with_external_token_signing_key(mut self, key: TokenSigningKey) -> Self {
+        self.token_signing_keys = self.token_signing_keys.with_external(key);
+        self
+    }
+
+    /// The externally held token signing key, which must be kept when reloading the instance
+    pub fn external_token_signing_key(&self) -> Option<&TokenSigningKey> {
+        self.token_signing_keys.external()
+    }
+
     pub fn authly_eid(&self) -> ServiceId {
         self.authly_id.eid
     }
@@ -63,12 +117,37 @@ impl AuthlyInstance {
         &self.local_jwt_decoding_key
     }
 
-    pub fn local_jwt_encoding_key(&self) -> jsonwebtoken::EncodingKey {
-        jsonwebtoken::EncodingKey::from_ec_der(self.private_key().serialized_der())
+    /// Sign a JWT with the instance key, to be verified with [Self::local_jwt_decoding_key]
+    pub async fn sign_local_jwt(
+        &self,
+        header: &jsonwebtoken::Header,
+        claims: &impl Serialize,
+    ) -> anyhow::Result<String> {
+        signer::encode_jwt(header, claims, self.signer()).await
+    }
+
+    /// The key used for signing new access tokens
+    pub fn token_signing_key(&self) -> &TokenSigningKey {
+        self.token_signing_keys.current()
     }
 
-    pub fn private_key(&self) -> &KeyPair {
-        &self.authly_id.private_key
+    pub fn token_signing_keys(&self) -> &TokenSigningKeys {
+        &self.token_signing_keys
+    }
+
+    /// Whether access tokens are signed with the instance key, for compatibility with older installations
+    pub fn is_token_signing_compat(&self) -> bool {
+        self.token_signing_keys.is_compat()
+    }
+
+    /// The public keys for verifying access tokens
+    pub fn jwks(&self) -> JwkSet {
+        self.token_signing_keys.jwks(OffsetDateTime::now_utc())
+    }
+
+    /// The instance key, which may be held outside the process
+    pub fn signer(&self) -> &dyn Signer {
+        self.authly_id.signer.as_ref()
     }
 
     /// FIXME: sort this by following Eid pointers
@@ -97,22 +176,43 @@ impl AuthlyInstance {
     }
 
     /// NB: local CA might be an intermediate cert
-    pub fn sign_with_local_ca<'a, K: PublicKeyData>(
+    pub async fn sign_with_local_ca<'a, K: PublicKeyData>(
         &self,
         request: SigningRequest<'a, K>,
-    ) -> Cert<'a, K> {
-        let local_ca = self.local_ca();
-        let issuer = Issuer::new(local_ca.params.clone(), self.private_key());
-
-        let certificate = request
-            .params
-            .signed_by(request.key.deref(), &issuer)
-            .unwrap();
-
-        Cert {
-            params: request.params.clone(),
-            der: certificate.der().clone(),
+    ) -> anyhow::Result<Cert<'a, K>> {
+        let der = self
+            .sign_certificate(&request.params, request.key.deref())
+            .await?;
+
+        Ok(Cert {
+            params: request.params,
+            der,
             key: request.key,
-        }
+        })
+    }
+
+    /// Issue a certificate for `public_key` from the local CA
+    pub async fn sign_certificate(
+        &self,
+        params: &CertificateParams,
+        public_key: &impl PublicKeyData,
+    ) -> anyhow::Result<CertificateDer<'static>> {
+        signer::sign_certificate(self.signer(), &self.local_ca().params, params, public_key).await
+    }
+
+    /// Issue the certificate requested by a CSR from the local CA
+    pub async fn sign_csr(
+        &self,
+        csr: &CertificateSigningRequestParams,
+    ) -> anyhow::Result<CertificateDer<'static>> {
+        signer::sign_csr(self.signer(), &self.local_ca().params, csr).await
+    }
+
+    /// Make a certificate signing request for the instance key
+    pub async fn serialize_request(
+        &self,
+        params: &CertificateParams,
+    ) -> anyhow::Result<CertificateSigningRequestDer<'static>> {
+        signer::serialize_request(self.signer(), params).await
     }
 }
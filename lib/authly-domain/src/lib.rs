@@ -2,10 +2,14 @@ use serde::{Deserialize, Serialize};
 
 pub mod access_control;
 pub mod access_token;
+pub mod api_error;
 pub mod audit;
+pub mod aws_kms;
 pub mod builtins;
 pub mod bus;
 pub mod cert;
+pub mod clock;
+pub mod clock_skew;
 pub mod ctx;
 pub mod dev;
 pub mod directory;
@@ -13,20 +17,34 @@ pub mod document;
 pub mod encryption;
 pub mod error;
 pub mod extract;
+pub mod health;
 pub mod id;
+pub mod id_generator;
 pub mod instance;
+pub mod load_shed;
 pub mod login;
 pub mod login_session;
+pub mod metadata_cache;
 pub mod migration;
+pub mod password;
 pub mod persona_directory;
 pub mod policy;
+pub mod policy_cache;
+pub mod readiness;
+pub mod redirect;
 pub mod remote_addr;
 pub mod repo;
+pub mod request_id;
+pub mod scim;
 pub mod serde_util;
 pub mod service;
 pub mod session;
+pub mod session_cache;
 pub mod settings;
+pub mod signer;
+pub mod stats;
 pub mod tls;
+pub mod token_signing;
 pub mod webauthn;
 
 #[derive(Clone, Copy, Serialize, Deserialize, Debug)]
@@ -0,0 +1,318 @@
+//! Backpressure for the main service.
+//!
+//! Requests beyond the concurrency limit are answered immediately with `503 Service Unavailable`,
+//! instead of queueing up until the node runs out of memory or file descriptors.
+//! The same goes for requests beyond the concurrency limit of a single connection,
+//! while connections beyond the connection limit are closed before their TLS handshake.
+//!
+//! The request limit also tracks the requests in flight when the node shuts down and drains.
+
+use std::{future::Future, net::SocketAddr, sync::Arc, time::Duration};
+
+use arc_swap::ArcSwap;
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use futures_util::{future::poll_fn, pin_mut, stream::BoxStream, StreamExt};
+use http::{header::RETRY_AFTER, StatusCode};
+use hyper::body::Incoming;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use rustls::ServerConfig;
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::{OwnedSemaphorePermit, Semaphore},
+};
+use tokio_rustls::TlsAcceptor;
+use tokio_util::sync::CancellationToken;
+use tower::Service;
+use tower_server::tls::TlsConnectionMiddleware;
+use tracing::{debug, warn};
+
+use crate::remote_addr::RemoteAddr;
+
+/// A limit on the number of requests processed concurrently
+#[derive(Clone)]
+pub struct RequestLimit {
+    max: u32,
+    permits: Arc<Semaphore>,
+}
+
+impl RequestLimit {
+    pub fn new(max_concurrent_requests: usize) -> anyhow::Result<Self> {
+        let Ok(max) = u32::try_from(max_concurrent_requests) else {
+            return Err(anyhow::anyhow!(
+                "max concurrent requests must be at most {}",
+                u32::MAX
+            ));
+        };
+
+        Ok(Self {
+            max,
+            permits: Arc::new(Semaphore::new(max_concurrent_requests)),
+        })
+    }
+
+    /// The number of requests currently being processed
+    pub fn in_flight(&self) -> usize {
+        self.max as usize - self.permits.available_permits()
+    }
+
+    /// Wait until no requests are in flight.
+    ///
+    /// Requests arriving meanwhile are shed, so the node is able to drain while under load.
+    pub async fn drain(&self) {
+        let _all = self.permits.acquire_many(self.max).await;
+    }
+
+    /// Apply the limit to a router.
+    ///
+    /// Routers layered by the same `RequestLimit` share one limit.
+    pub fn layer(&self, router: axum::Router) -> axum::Router {
+        router.layer(axum::middleware::from_fn_with_state(self.clone(), shed))
+    }
+}
+
+/// Drain the in-flight requests on shutdown and then run `finish`, all within the time `budget`.
+///
+/// Returns `false` when the budget ran out first, then the remaining work is abandoned.
+pub async fn drain_within(
+    limit: &RequestLimit,
+    budget: Duration,
+    finish: impl Future<Output = ()>,
+) -> bool {
+    tokio::time::timeout(budget, async {
+        limit.drain().await;
+        finish.await;
+    })
+    .await
+    .is_ok()
+}
+
+/// The time an admitted connection has to complete its TLS handshake
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A limit on the number of open TLS connections, and on the requests processed concurrently on each of them.
+///
+/// Connections beyond the limit are closed as soon as they are accepted, see [ConnectionLimit::serve_tls].
+#[derive(Clone)]
+pub struct ConnectionLimit {
+    max_connections: usize,
+    max_requests_per_connection: usize,
+    permits: Arc<Semaphore>,
+}
+
+impl ConnectionLimit {
+    pub fn new(max_connections: usize, max_requests_per_connection: usize) -> Self {
+        Self {
+            max_connections,
+            max_requests_per_connection,
+            permits: Arc::new(Semaphore::new(max_connections)),
+        }
+    }
+
+    /// The number of connections currently admitted
+    pub fn open(&self) -> usize {
+        self.max_connections - self.permits.available_permits()
+    }
+
+    /// Serve `service` over TLS on `listener`, until `cancel` is cancelled.
+    ///
+    /// A connection beyond the limit is closed right after it's accepted, before the TLS handshake,
+    /// so excess connections cost neither a handshake nor a task.
+    /// The requests of an admitted connection pass through the TLS connection `middleware`,
+    /// and are shed by the [RequestLimit] layer beyond the concurrency limit of the connection.
+    ///
+    /// Each handshake uses the latest config produced by `tls_configs`, so certificates can be rotated.
+    pub async fn serve_tls<M, S>(
+        self,
+        listener: TcpListener,
+        mut tls_configs: BoxStream<'static, Arc<ServerConfig>>,
+        middleware: M,
+        service: S,
+        cancel: CancellationToken,
+    ) where
+        M: TlsConnectionMiddleware + Clone + Send + Sync + 'static,
+        M::Data: Send + Sync + 'static,
+        S: Service<
+                http::Request<Incoming>,
+                Response = Response,
+                Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+                Future: Send + 'static,
+            > + Clone
+            + Send
+            + 'static,
+    {
+        let Some(initial) = tls_configs.next().await else {
+            warn!("no TLS config, not serving");
+            return;
+        };
+        let tls_config = Arc::new(ArcSwap::new(initial));
+        tokio::spawn({
+            let tls_config = tls_config.clone();
+            let cancel = cancel.clone();
+            async move {
+                loop {
+                    tokio::select! {
+                        config = tls_configs.next() => match config {
+                            Some(config) => tls_config.store(config),
+                            None => return,
+                        },
+                        _ = cancel.cancelled() => return,
+                    }
+                }
+            }
+        });
+
+        loop {
+            let (stream, addr) = tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok(accepted) => accepted,
+                    Err(err) => {
+                        // e.g. out of file descriptors, which takes a while to recover from
+                        warn!(?err, "accept error");
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                        continue;
+                    }
+                },
+                _ = cancel.cancelled() => return,
+            };
+
+            let Ok(permit) = self.permits.clone().try_acquire_owned() else {
+                warn!(
+                    max = self.max_connections,
+                    ?addr,
+                    "connection limit reached, refusing connection"
+                );
+                drop(stream);
+                continue;
+            };
+
+            let slot = ConnectionSlot {
+                limit: self.clone(),
+                requests: Arc::new(Semaphore::new(self.max_requests_per_connection)),
+                _permit: Arc::new(permit),
+            };
+
+            tokio::spawn(serve_tls_connection(
+                stream,
+                addr,
+                tls_config.load_full(),
+                slot,
+                middleware.clone(),
+                service.clone(),
+                cancel.clone(),
+            ));
+        }
+    }
+}
+
+/// Serve one admitted TLS connection, which releases its share of the [ConnectionLimit] when it closes
+async fn serve_tls_connection<M, S>(
+    stream: TcpStream,
+    addr: SocketAddr,
+    tls_config: Arc<ServerConfig>,
+    slot: ConnectionSlot,
+    middleware: M,
+    service: S,
+    cancel: CancellationToken,
+) where
+    M: TlsConnectionMiddleware,
+    S: Service<
+            http::Request<Incoming>,
+            Response = Response,
+            Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+            Future: Send + 'static,
+        > + Clone
+        + Send
+        + 'static,
+{
+    // a connection that never completes its handshake must not hold on to its share forever
+    let handshake = tokio::time::timeout(
+        TLS_HANDSHAKE_TIMEOUT,
+        TlsAcceptor::from(tls_config).accept(stream),
+    );
+    let tls_stream = match handshake.await {
+        Ok(Ok(tls_stream)) => tls_stream,
+        Ok(Err(err)) => {
+            debug!(?err, ?addr, "TLS accept error");
+            return;
+        }
+        Err(_) => {
+            debug!(?addr, "TLS handshake timed out");
+            return;
+        }
+    };
+
+    let middleware_data = middleware.data(tls_stream.get_ref().1);
+
+    let connection_builder = hyper_util::server::conn::auto::Builder::new(TokioExecutor::new());
+    let connection = connection_builder.serve_connection_with_upgrades(
+        TokioIo::new(tls_stream),
+        hyper::service::service_fn(move |mut req: http::Request<Incoming>| {
+            req.extensions_mut().insert(RemoteAddr(addr));
+            req.extensions_mut().insert(slot.clone());
+            middleware.call(&mut req, &middleware_data);
+            let mut service = service.clone();
+
+            async move {
+                poll_fn(|cx| service.poll_ready(cx)).await?;
+                service.call(req).await
+            }
+        }),
+    );
+
+    pin_mut!(connection);
+
+    tokio::select! {
+        _ = connection.as_mut() => return,
+        _ = cancel.cancelled() => connection.as_mut().graceful_shutdown(),
+    }
+
+    let _ = connection.await;
+}
+
+/// The share of a TLS connection in the [ConnectionLimit], attached to each of its requests.
+///
+/// The connection releases its share when it closes.
+#[derive(Clone)]
+pub struct ConnectionSlot {
+    limit: ConnectionLimit,
+    requests: Arc<Semaphore>,
+    _permit: Arc<OwnedSemaphorePermit>,
+}
+
+async fn shed(State(limit): State<RequestLimit>, req: Request, next: Next) -> Response {
+    let _connection_permit = match req.extensions().get::<ConnectionSlot>() {
+        Some(slot) => {
+            let Ok(permit) = slot.requests.clone().try_acquire_owned() else {
+                warn!(
+                    max = slot.limit.max_requests_per_connection,
+                    "connection request limit reached, shedding request"
+                );
+                return overloaded();
+            };
+
+            Some(permit)
+        }
+        // not served over a limited TLS connection
+        None => None,
+    };
+
+    let Ok(_permit) = limit.permits.clone().try_acquire_owned() else {
+        warn!(max = limit.max, "request limit reached, shedding request");
+        return overloaded();
+    };
+
+    next.run(req).await
+}
+
+fn overloaded() -> Response {
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        [(RETRY_AFTER, "1")],
+        "server overloaded",
+    )
+        .into_response()
+}
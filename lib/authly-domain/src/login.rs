@@ -1,17 +1,28 @@
 //! traditional username/password login
 
 use argon2::Argon2;
-use authly_common::{id::PersonaId, mtls_server::PeerServiceEntity};
+use authly_common::{
+    id::{AttrId, PersonaId, ServiceId},
+    mtls_server::PeerServiceEntity,
+};
 use authly_db::DbError;
-use tracing::warn;
+use serde::Serialize;
+use serde_json::json;
+use tracing::{info, warn};
 
 use crate::{
     access_control::{authorize_peer_service, SvcAccessControlError},
-    ctx::{GetBuiltins, GetDb, GetDecryptedDeks},
+    access_token::{self, AccessTokenClaims, TokenAttributes},
+    audit::Actor,
+    bus::admin_events::{record_admin_event, AdminEventKind},
+    ctx::{ClusterBus, GetBuiltins, GetClock, GetDb, GetDecryptedDeks, GetSettings, GetStats},
     dev::IsDev,
     id::{BuiltinAttr, BuiltinProp},
-    repo::entity_repo::{self, EntityPasswordHash},
-    session::{init_session, Session},
+    repo::{
+        entity_repo::{self, EntityPasswordHash},
+        scim_repo,
+    },
+    session::{init_session, AuthClass, Session, SessionKind},
 };
 
 pub enum LoginError {
@@ -38,6 +49,7 @@ impl From<SvcAccessControlError> for LoginError {
 #[derive(Default)]
 pub struct LoginOptions {
     disable_peer_service_auth: bool,
+    session_kind: SessionKind,
 }
 
 impl LoginOptions {
@@ -47,10 +59,18 @@ impl LoginOptions {
         }
         self
     }
+
+    /// Issue a persistent session instead of the default short-lived one
+    pub fn remember_me(mut self, remember_me: bool) -> Self {
+        if remember_me {
+            self.session_kind = SessionKind::Persistent;
+        }
+        self
+    }
 }
 
 pub async fn try_username_password_login(
-    deps: &(impl GetDb + GetBuiltins + GetDecryptedDeks),
+    deps: &(impl GetDb + GetBuiltins + GetDecryptedDeks + GetSettings + GetStats + GetClock),
     PeerServiceEntity(peer_svc_eid): PeerServiceEntity,
     username: String,
     password: String,
@@ -60,26 +80,177 @@ pub async fn try_username_password_login(
         authorize_peer_service(deps, peer_svc_eid, &[BuiltinAttr::AuthlyRoleAuthenticate]).await?;
     }
 
+    let ehash = find_username_password_hash(deps, &username)
+        .await?
+        .ok_or_else(|| LoginError::Credentials)
+        .inspect_err(|_| deps.get_stats().record_failed_authentication())?;
+
+    let persona_id = verify_secret(ehash, password)
+        .await
+        .inspect_err(|_| deps.get_stats().record_failed_authentication())?;
+
+    // personas deactivated by a SCIM client can't log in, even with a valid password
+    if scim_repo::is_deactivated(deps.get_db(), persona_id.upcast()).await? {
+        deps.get_stats().record_failed_authentication();
+        return Err(LoginError::Credentials);
+    }
+
+    let session = init_session(
+        deps,
+        persona_id.upcast(),
+        options.session_kind,
+        AuthClass::Password,
+    )
+    .await?;
+
+    Ok((persona_id, session))
+}
+
+/// The outcome of a simulated login, for inspection by an operator.
+///
+/// Nothing in it can be used as a credential: no session is stored and the token claims are not signed.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SimulatedLogin {
+    pub persona_id: PersonaId,
+    pub service_id: ServiceId,
+    /// All attributes of the persona, before projection for the service
+    pub entity_attributes: Vec<AttrId>,
+    /// The claims of an access token issued to the service for this login
+    pub token_claims: AccessTokenClaims,
+    /// The steps taken to arrive at the result
+    pub trace: Vec<String>,
+}
+
+/// Simulate a username/password login followed by an access token request from the given service,
+/// without knowing the password of the user.
+///
+/// Every simulation is recorded as an admin event before anything is resolved,
+/// and the simulation is refused if it can't be recorded.
+pub async fn simulate_login(
+    deps: &(impl GetDb + GetBuiltins + GetDecryptedDeks + GetSettings + GetClock + ClusterBus),
+    actor: Actor,
+    username: &str,
+    svc_eid: ServiceId,
+) -> Result<SimulatedLogin, LoginError> {
+    let ehash = find_username_password_hash(deps, username).await?;
+    let persona_id = ehash.map(|ehash| ehash.eid);
+
+    record_admin_event(
+        deps,
+        AdminEventKind::SimulatedLogin,
+        json!({
+            "actorId": actor.0,
+            "personaId": persona_id,
+            "serviceId": svc_eid,
+        }),
+    )
+    .await?;
+    info!(actor = %actor.0, ?persona_id, %svc_eid, "simulated login");
+
+    let persona_id = persona_id.ok_or(LoginError::Credentials)?;
+    let mut trace = vec![format!("username resolved to persona {persona_id}")];
+
+    let user_attrs =
+        entity_repo::list_resolved_entity_attrs(deps.get_db(), persona_id.upcast()).await?;
+    let mut entity_attributes: Vec<_> = user_attrs.iter().copied().collect();
+    entity_attributes.sort();
+    trace.push(format!(
+        "persona has {} attributes",
+        entity_attributes.len()
+    ));
+
+    let projected =
+        access_token::project_token_attributes(deps, svc_eid, user_attrs.clone()).await?;
+    if projected.len() < user_attrs.len() {
+        trace.push(format!(
+            "{} attributes projected for service {svc_eid}",
+            projected.len()
+        ));
+    }
+
+    let now = deps.get_clock().now();
+    let token_claims =
+        match access_token::select_token_attributes(deps, persona_id.upcast(), svc_eid, user_attrs)
+            .await?
+        {
+            TokenAttributes::Embedded(attributes) => {
+                trace.push("attributes embedded in the access token".to_string());
+                AccessTokenClaims {
+                    claims: access_token::entity_access_token_claims(
+                        persona_id.upcast(),
+                        attributes,
+                        now,
+                    ),
+                    authly_attributes_ref: None,
+                }
+            }
+            TokenAttributes::Referenced(attributes_ref, _) => {
+                trace.push(
+                    "attributes referenced from the access token, exceeding the embed limit"
+                        .to_string(),
+                );
+                AccessTokenClaims {
+                    claims: access_token::entity_access_token_claims(
+                        persona_id.upcast(),
+                        Default::default(),
+                        now,
+                    ),
+                    authly_attributes_ref: Some(attributes_ref),
+                }
+            }
+        };
+
+    Ok(SimulatedLogin {
+        persona_id,
+        service_id: svc_eid,
+        entity_attributes,
+        token_claims,
+        trace,
+    })
+}
+
+/// Verify the password of an already identified persona, e.g. for step-up authentication
+pub async fn verify_persona_password(
+    deps: &(impl GetDb + GetBuiltins + GetStats),
+    persona_id: PersonaId,
+    password: String,
+) -> Result<(), LoginError> {
+    let ehash =
+        entity_repo::find_entity_password_hash(deps.get_db(), persona_id, deps.get_builtins())
+            .await?
+            .ok_or_else(|| LoginError::Credentials)
+            .inspect_err(|_| deps.get_stats().record_failed_authentication())?;
+
+    verify_secret(ehash, password)
+        .await
+        .inspect_err(|_| deps.get_stats().record_failed_authentication())?;
+
+    Ok(())
+}
+
+async fn find_username_password_hash(
+    deps: &(impl GetDb + GetBuiltins + GetDecryptedDeks + GetSettings),
+    username: &str,
+) -> Result<Option<EntityPasswordHash>, DbError> {
     let ident_fingerprint = {
+        let username = deps
+            .get_settings()
+            .ident_normalization
+            .normalize(BuiltinProp::Username.into(), username);
         let deks = deps.get_decrypted_deks();
         let dek = deks.get(BuiltinProp::Username.into()).unwrap();
 
         dek.fingerprint(username.as_bytes())
     };
 
-    let ehash = entity_repo::find_local_directory_entity_password_hash_by_entity_ident(
+    entity_repo::find_local_directory_entity_password_hash_by_entity_ident(
         deps.get_db(),
         BuiltinProp::Username.into(),
         &ident_fingerprint,
         deps.get_builtins(),
     )
-    .await?
-    .ok_or_else(|| LoginError::Credentials)?;
-
-    let persona_id = verify_secret(ehash, password).await?;
-    let session = init_session(deps, persona_id.upcast()).await?;
-
-    Ok((persona_id, session))
+    .await
 }
 
 async fn verify_secret(ehash: EntityPasswordHash, secret: String) -> Result<PersonaId, LoginError> {
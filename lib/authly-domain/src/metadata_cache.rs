@@ -0,0 +1,83 @@
+//! Caching of the metadata and configuration served to services.
+//!
+//! Services tend to poll for these, while they only change when a directory changes.
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+use authly_common::{id::ServiceId, proto::service as proto};
+
+/// Computed `get_metadata` and `get_configuration` responses, per service.
+///
+/// The responses are keyed by the service alone, because everything else they depend on
+/// is either directory data or fixed for the lifetime of the node.
+/// The whole cache must be invalidated whenever a directory changes.
+#[derive(Default)]
+pub struct ServiceMetadataCache {
+    generation: AtomicU64,
+    metadata: Mutex<HashMap<ServiceId, proto::ServiceMetadata>>,
+    configuration: Mutex<HashMap<ServiceId, proto::ServiceConfiguration>>,
+}
+
+/// The state of the cache before computing a response.
+///
+/// A response computed from stale data is not cached if the cache was invalidated in the meantime.
+#[derive(Clone, Copy)]
+pub struct CacheGeneration(pub(crate) u64);
+
+impl ServiceMetadataCache {
+    pub fn generation(&self) -> CacheGeneration {
+        CacheGeneration(self.generation.load(Ordering::SeqCst))
+    }
+
+    pub fn get_metadata(&self, svc_eid: ServiceId) -> Option<proto::ServiceMetadata> {
+        self.metadata.lock().unwrap().get(&svc_eid).cloned()
+    }
+
+    pub fn insert_metadata(
+        &self,
+        generation: CacheGeneration,
+        svc_eid: ServiceId,
+        metadata: proto::ServiceMetadata,
+    ) {
+        let mut entries = self.metadata.lock().unwrap();
+        if self.is_current(generation) {
+            entries.insert(svc_eid, metadata);
+        }
+    }
+
+    pub fn get_configuration(&self, svc_eid: ServiceId) -> Option<proto::ServiceConfiguration> {
+        self.configuration.lock().unwrap().get(&svc_eid).cloned()
+    }
+
+    pub fn insert_configuration(
+        &self,
+        generation: CacheGeneration,
+        svc_eid: ServiceId,
+        configuration: proto::ServiceConfiguration,
+    ) {
+        let mut entries = self.configuration.lock().unwrap();
+        if self.is_current(generation) {
+            entries.insert(svc_eid, configuration);
+        }
+    }
+
+    /// Evict all cached responses
+    pub fn invalidate_all(&self) {
+        let mut metadata = self.metadata.lock().unwrap();
+        let mut configuration = self.configuration.lock().unwrap();
+
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        metadata.clear();
+        configuration.clear();
+    }
+
+    fn is_current(&self, generation: CacheGeneration) -> bool {
+        self.generation.load(Ordering::SeqCst) == generation.0
+    }
+}
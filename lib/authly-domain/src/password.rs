@@ -0,0 +1,138 @@
+//! Password policy.
+//!
+//! Passwords are checked against the configured policy when they are set, before they are hashed.
+//! The breach check uses a k-anonymity range API: only the first five characters of the SHA-1 digest
+//! of the password leave Authly, and the matching suffixes are compared locally.
+
+use std::{fmt::Debug, time::Duration};
+
+use argon2::{password_hash::SaltString, Argon2};
+use sha1::{Digest, Sha1};
+use tracing::warn;
+
+use crate::{
+    ctx::{GetHttpClient, GetSettings},
+    settings::PasswordPolicy,
+};
+
+const BREACH_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A plaintext password, which is never printed
+#[derive(Clone, PartialEq, Eq)]
+pub struct Password(pub String);
+
+impl Debug for Password {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Password(***)")
+    }
+}
+
+/// The reason a password was rejected. The messages are meant for the user choosing the password.
+#[derive(thiserror::Error, PartialEq, Eq, Debug)]
+pub enum PasswordPolicyError {
+    #[error("password must be at least {0} characters long")]
+    TooShort(usize),
+
+    #[error(
+        "password must contain characters from at least {0} of: lowercase letters, uppercase letters, digits and symbols"
+    )]
+    TooFewCharacterClasses(u8),
+
+    #[error("password has appeared in a data breach, choose another password")]
+    Breached,
+}
+
+/// Check a password against the configured password policy, including the breach check
+pub async fn check_password(
+    deps: &(impl GetSettings + GetHttpClient),
+    password: &Password,
+) -> Result<(), PasswordPolicyError> {
+    let policy = deps.get_settings().password_policy.clone();
+    check_complexity(&policy, password)?;
+
+    if let Some(url) = &policy.breach_check {
+        if is_breached(deps, url, password).await {
+            return Err(PasswordPolicyError::Breached);
+        }
+    }
+
+    Ok(())
+}
+
+/// Check the length and the character classes of a password
+pub fn check_complexity(
+    policy: &PasswordPolicy,
+    password: &Password,
+) -> Result<(), PasswordPolicyError> {
+    if password.0.chars().count() < policy.min_length {
+        return Err(PasswordPolicyError::TooShort(policy.min_length));
+    }
+
+    let classes: [fn(char) -> bool; 4] = [
+        char::is_lowercase,
+        char::is_uppercase,
+        |c| c.is_numeric(),
+        |c| !c.is_alphanumeric(),
+    ];
+    let character_classes = classes
+        .into_iter()
+        .filter(|class| password.0.chars().any(class))
+        .count();
+
+    if character_classes < policy.character_classes as usize {
+        return Err(PasswordPolicyError::TooFewCharacterClasses(
+            policy.character_classes,
+        ));
+    }
+
+    Ok(())
+}
+
+/// Look the password up in the breach range API.
+///
+/// When the API is unavailable the password is let through, a third party outage must not block password changes.
+async fn is_breached(deps: &impl GetHttpClient, url: &reqwest::Url, password: &Password) -> bool {
+    let digest = hexhex::hex(Sha1::digest(password.0.as_bytes()))
+        .to_string()
+        .to_ascii_uppercase();
+    let (prefix, suffix) = digest.split_at(5);
+
+    let result = async {
+        deps.get_internet_http_client()
+            .get(format!("{url}{prefix}"))
+            .timeout(BREACH_CHECK_TIMEOUT)
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await
+    }
+    .await;
+
+    match result {
+        Ok(body) => body.lines().any(|line| {
+            let Some((line_suffix, count)) = line.trim().split_once(':') else {
+                return false;
+            };
+            // padding entries have a count of zero
+            line_suffix.eq_ignore_ascii_case(suffix) && count.trim() != "0"
+        }),
+        Err(err) => {
+            warn!(?err, %url, "password breach check unavailable");
+            false
+        }
+    }
+}
+
+/// Hash a password for storage
+pub async fn hash_password(password: Password) -> anyhow::Result<String> {
+    tokio::task::spawn_blocking(move || -> anyhow::Result<String> {
+        let salt = SaltString::generate(rand::thread_rng());
+        Ok(
+            argon2::PasswordHash::generate(Argon2::default(), password.0, &salt)
+                .map_err(|e| anyhow::anyhow!("failed to generate password hash: {}", e))?
+                .to_string(),
+        )
+    })
+    .await?
+}
@@ -1,15 +1,16 @@
 use authly_common::id::PersonaId;
-use authly_db::{DbError, DidInsert};
-use tracing::info;
+use authly_db::{Db, DbError, DidInsert};
+use tracing::{info, warn};
 
 use crate::{
-    ctx::{GetDb, GetDecryptedDeks},
-    directory::DirKey,
-    encryption::EncryptedObjIdent,
+    ctx::{GetClock, GetDb, GetDecryptedDeks, GetIdGenerator, GetSettings},
+    directory::{DirKey, JitProvisioning},
+    encryption::{CryptoError, EncryptedObjIdent},
     id::BuiltinProp,
     repo::{
+        crypto_repo, directory_repo,
         entity_repo::{self, OverwritePersonaId},
-        object_repo,
+        object_repo, scim_repo,
     },
 };
 
@@ -19,6 +20,15 @@ pub struct ForeignPersona {
     pub email: String,
 }
 
+/// Claims of a foreign persona used when provisioning it just-in-time
+#[derive(Default)]
+pub struct ForeignClaims {
+    pub username: Option<String>,
+
+    /// Attribute labels, keyed by (namespace, property)
+    pub attributes: Vec<((String, String), String)>,
+}
+
 #[derive(thiserror::Error, Debug)]
 pub enum ForeignLinkError {
     #[error("db error: {0}")]
@@ -27,23 +37,242 @@ pub enum ForeignLinkError {
     #[error("encryption error: {0}")]
     Encryption(anyhow::Error),
 
+    #[error("crypto error: {0}")]
+    Crypto(#[from] CryptoError),
+
+    #[error("persona is not provisioned")]
+    NotProvisioned,
+
+    #[error("email domain not allowed")]
+    DomainNotAllowed,
+
+    #[error("foreign identity is already linked")]
+    AlreadyLinked,
+
+    #[error("persona is deactivated")]
+    Deactivated,
+
+    #[error("foreign identity was unlinked from its persona")]
+    Unlinked,
+
     #[error("other: {0}")]
     Other(&'static str),
 }
 
+/// Log in a foreign persona from an OAuth directory.
+///
+/// Identities explicitly linked by a persona resolve to that persona.
+/// Identities unlinked by their persona are refused, until linked explicitly again.
+/// Personas already linked to the directory, or owning the email address, are linked as usual.
+/// Otherwise the persona is provisioned just-in-time if the directory allows it.
+pub async fn login_foreign_persona(
+    deps: &(impl GetDb + GetDecryptedDeks + GetSettings + GetIdGenerator + GetClock),
+    dir_key: DirKey,
+    jit: &JitProvisioning,
+    foreign: ForeignPersona,
+    claims: ForeignClaims,
+) -> Result<PersonaId, ForeignLinkError> {
+    let linked =
+        entity_repo::find_foreign_persona_link(deps.get_db(), dir_key, &foreign.foreign_id).await?;
+
+    if let Some(link) = linked.as_ref().filter(|link| link.explicit) {
+        deny_deactivated(deps, link.persona_id).await?;
+        return Ok(link.persona_id);
+    }
+
+    // the email address must not link an unlinked identity back, neither to its owner nor through JIT provisioning
+    if linked.is_none()
+        && entity_repo::is_foreign_id_unlinked(deps.get_db(), dir_key, &foreign.foreign_id).await?
+    {
+        info!("login of unlinked foreign identity refused");
+        return Err(ForeignLinkError::Unlinked);
+    }
+
+    let email_owner =
+        crypto_repo::lookup_obj_ident(deps, BuiltinProp::Email.into(), &foreign.email).await?;
+
+    // a deactivated persona must not be logged in, nor be linked through its email address
+    for persona_id in linked
+        .iter()
+        .map(|link| link.persona_id)
+        .chain(email_owner.and_then(|owner| PersonaId::try_from(owner).ok()))
+    {
+        deny_deactivated(deps, persona_id).await?;
+    }
+
+    if linked.is_some() || email_owner.is_some() {
+        let (persona_id, _) = link_foreign_persona(deps, dir_key, foreign).await?;
+        return Ok(persona_id);
+    }
+
+    if !jit.enabled {
+        return Err(ForeignLinkError::NotProvisioned);
+    }
+
+    if !jit.allows_email(&foreign.email) {
+        info!(
+            email = %foreign.email,
+            "JIT provisioning rejected by domain allowlist"
+        );
+        return Err(ForeignLinkError::DomainNotAllowed);
+    }
+
+    let (persona_id, _) = link_foreign_persona(deps, dir_key, foreign).await?;
+
+    provision_claims(deps, dir_key, persona_id, claims).await?;
+
+    info!(?persona_id, "provisioned persona just-in-time");
+
+    Ok(persona_id)
+}
+
+/// Link a foreign identity to an existing persona, on behalf of that persona.
+///
+/// An identity can only be linked to one persona, and a persona can only link one identity from each directory.
+pub async fn link_foreign_identity(
+    deps: &(impl GetDb + GetClock),
+    dir_key: DirKey,
+    persona_id: PersonaId,
+    foreign_id: Vec<u8>,
+) -> Result<(), ForeignLinkError> {
+    let db = deps.get_db();
+
+    if let Some(link) = entity_repo::find_foreign_persona_link(db, dir_key, &foreign_id).await? {
+        if link.persona_id != persona_id {
+            return Err(ForeignLinkError::AlreadyLinked);
+        }
+    } else if entity_repo::list_foreign_persona_link_dirs(db, persona_id)
+        .await?
+        .contains(&dir_key)
+    {
+        return Err(ForeignLinkError::AlreadyLinked);
+    }
+
+    let now = deps.get_clock().now();
+    if !entity_repo::try_link_foreign_persona_explicit(db, dir_key, persona_id, foreign_id, now)
+        .await?
+    {
+        return Err(ForeignLinkError::AlreadyLinked);
+    }
+
+    info!(?persona_id, "foreign identity linked");
+
+    Ok(())
+}
+
+/// Unlink the foreign identity of a directory from a persona.
+///
+/// The identity is then no longer linked at login through the email address of the persona.
+///
+/// Returns whether there was an identity to unlink.
+pub async fn unlink_foreign_identity(
+    deps: &(impl GetDb + GetClock),
+    dir_key: DirKey,
+    persona_id: PersonaId,
+) -> Result<bool, ForeignLinkError> {
+    let deleted = entity_repo::delete_foreign_persona_links(
+        deps.get_db(),
+        dir_key,
+        persona_id,
+        deps.get_clock().now(),
+    )
+    .await?;
+    if deleted > 0 {
+        info!(?persona_id, "foreign identity unlinked");
+    }
+
+    Ok(deleted > 0)
+}
+
+/// Refuse personas that have been deactivated by a SCIM client
+async fn deny_deactivated(
+    deps: &impl GetDb,
+    persona_id: PersonaId,
+) -> Result<(), ForeignLinkError> {
+    if scim_repo::is_deactivated(deps.get_db(), persona_id.upcast()).await? {
+        info!(?persona_id, "foreign login of deactivated persona refused");
+        return Err(ForeignLinkError::Deactivated);
+    }
+
+    Ok(())
+}
+
+/// Write the username identity and attributes of a freshly provisioned persona, and audit it
+async fn provision_claims(
+    deps: &(impl GetDb + GetDecryptedDeks + GetSettings + GetClock),
+    dir_key: DirKey,
+    persona_id: PersonaId,
+    claims: ForeignClaims,
+) -> Result<(), ForeignLinkError> {
+    let db = deps.get_db();
+    let now = deps.get_clock().now();
+    let mut stmts = vec![directory_repo::insert_directory_audit_stmt(
+        dir_key,
+        persona_id.upcast(),
+        now,
+    )];
+
+    if let Some(username) = claims.username.map(|username| {
+        deps.get_settings()
+            .ident_normalization
+            .normalize(BuiltinProp::Username.into(), &username)
+            .into_owned()
+    }) {
+        if crypto_repo::lookup_obj_ident(deps, BuiltinProp::Username.into(), &username)
+            .await?
+            .is_some()
+        {
+            warn!(%username, "username already taken, not provisioned");
+        } else {
+            let ident = EncryptedObjIdent::encrypt(
+                BuiltinProp::Username.into(),
+                &username,
+                &deps.get_decrypted_deks(),
+            )
+            .map_err(ForeignLinkError::Encryption)?;
+            stmts.push(ident.insert_stmt(dir_key.0, persona_id.upcast(), now.unix_timestamp()));
+        }
+    }
+
+    for ((namespace, property), label) in claims.attributes {
+        let attrs = scim_repo::list_labeled_property_attrs(db, &namespace, &property).await?;
+        match attrs
+            .into_iter()
+            .find(|(_, attr_label)| *attr_label == label)
+        {
+            Some((attr_id, _)) => stmts.push(scim_repo::insert_ent_attr_stmt(
+                dir_key,
+                persona_id.upcast(),
+                attr_id,
+                now,
+            )),
+            None => warn!(%namespace, %property, %label, "unknown attribute claim"),
+        }
+    }
+
+    for result in db.transact(stmts).await? {
+        result?;
+    }
+
+    Ok(())
+}
+
 /// Link or re-link a foreign persona to get an Authly PersonaId
 pub async fn link_foreign_persona(
-    deps: &(impl GetDb + GetDecryptedDeks),
+    deps: &(impl GetDb + GetDecryptedDeks + GetSettings + GetIdGenerator + GetClock),
     persona_dir_key: DirKey,
     foreign: ForeignPersona,
 ) -> Result<(PersonaId, DidInsert), ForeignLinkError> {
     let email = EncryptedObjIdent::encrypt(
         BuiltinProp::Email.into(),
-        &foreign.email,
+        &deps
+            .get_settings()
+            .ident_normalization
+            .normalize(BuiltinProp::Email.into(), &foreign.email),
         &deps.get_decrypted_deks(),
     )
     .map_err(ForeignLinkError::Encryption)?;
-    let now = time::OffsetDateTime::now_utc();
+    let now = deps.get_clock().now();
 
     info!("email fingerprint: {}", hexhex::hex(&email.fingerprint));
 
@@ -56,7 +285,7 @@ pub async fn link_foreign_persona(
     let (persona_id, did_insert) = entity_repo::upsert_link_foreign_persona(
         deps.get_db(),
         persona_dir_key,
-        PersonaId::random(),
+        deps.get_id_generator().generate(),
         OverwritePersonaId(false),
         foreign.foreign_id.clone(),
         now,
@@ -6,13 +6,14 @@ use pest::{
     error::InputLocation,
     iterators::Pair,
     pratt_parser::{Assoc, Op, PrattParser},
-    Parser,
+    Parser, RuleType,
 };
 
 use crate::document::{compiled_document::CompiledDocumentData, doc_compiler::Namespaces};
 
 use super::error::{PolicyCompileError, PolicyCompileErrorKind};
 
+pub mod cedar;
 pub mod expr;
 
 mod codegen;
@@ -23,6 +24,9 @@ pub struct PolicyCompiler<'a> {
     namespace: &'a Namespaces,
     doc_data: &'a CompiledDocumentData,
 
+    /// The namespace of unqualified property and attribute references
+    default_namespace: Option<&'a str>,
+
     errors: Vec<PolicyCompileError>,
 }
 
@@ -36,10 +40,16 @@ impl<'a> PolicyCompiler<'a> {
         Self {
             namespace,
             doc_data,
+            default_namespace: None,
             errors: vec![],
         }
     }
 
+    pub fn with_default_namespace(mut self, default_namespace: Option<&'a str>) -> Self {
+        self.default_namespace = default_namespace;
+        self
+    }
+
     pub fn expr_to_opcodes(expr: &Expr) -> Vec<OpCode> {
         let mut codegen = Codegen::default();
         codegen.codegen_expr_root(expr);
@@ -96,7 +106,7 @@ fn pest_parse_policy_as_expr(input: &str) -> Result<Pair<'_, Rule>, Vec<PolicyCo
     Ok(expr_root)
 }
 
-fn parse_error(error: pest::error::Error<Rule>) -> PolicyCompileError {
+fn parse_error<R: RuleType>(error: pest::error::Error<R>) -> PolicyCompileError {
     let span = match error.location {
         InputLocation::Pos(pos) => pos..(pos + 1),
         InputLocation::Span((start, end)) => start..end,
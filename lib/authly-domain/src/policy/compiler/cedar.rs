@@ -0,0 +1,336 @@
+//! Transpiler from a subset of the Cedar policy language to Authly policies.
+//!
+//! Authly has no actions and resources are only described by attributes, so Cedar is mapped as follows:
+//!
+//! - `principal == Type::"label"` compares the subject entity with the entity or service with that label.
+//!   The entity type is not checked.
+//! - `principal in namespace::property::"attribute"` checks that the subject has the attribute.
+//! - `resource in namespace::property::"attribute"` checks that the resource has the attribute.
+//! - `when` and `unless` conditions may combine these with `&&`, `||` and `!`.
+//!
+//! The `action` must be left unconstrained, and `context` can't be used.
+
+use authly_common::{
+    id::{kind::Kind, PropId},
+    policy::code::{OpCode, PolicyValue},
+};
+use pest::{
+    iterators::Pair,
+    pratt_parser::{Assoc, Op, PrattParser},
+    Parser, Span,
+};
+use pest_derive::Parser;
+
+use crate::{
+    document::doc_compiler::{NamespaceEntry, NamespaceKind, NsLookupErr},
+    id::BuiltinProp,
+    policy::error::{PolicyCompileError, PolicyCompileErrorKind},
+};
+
+use super::{
+    codegen::Codegen,
+    expr::{Expr, Global, Label128, Term},
+    parse_error, PolicyCompiler,
+};
+
+/// The Cedar policy language parser
+#[derive(Parser)]
+#[grammar = "../grammar/cedar.pest"]
+struct CedarParser;
+
+/// A Cedar policy lowered to an Authly policy
+#[derive(Debug)]
+pub struct CedarPolicy {
+    /// `Allow` for `permit` policies, `Deny` for `forbid` policies
+    pub class: PolicyValue,
+    pub expr: Expr,
+    pub opcodes: Vec<OpCode>,
+}
+
+/// An entity reference, with its type path split into segments
+struct EntityRef<'i> {
+    span: Span<'i>,
+    type_path: Vec<&'i str>,
+    id: &'i str,
+}
+
+impl PolicyCompiler<'_> {
+    /// Transpile Cedar policies, returning one Authly policy per Cedar policy
+    pub fn compile_cedar(
+        &mut self,
+        input: &str,
+    ) -> Result<Vec<CedarPolicy>, Vec<PolicyCompileError>> {
+        let root = match CedarParser::parse(Rule::policies, input) {
+            Ok(mut pairs) => pairs.next().unwrap(),
+            Err(error) => return Err(vec![parse_error(error)]),
+        };
+        let pratt = PrattParser::<Rule>::new()
+            .op(Op::infix(Rule::infix_or, Assoc::Left))
+            .op(Op::infix(Rule::infix_and, Assoc::Left))
+            .op(Op::prefix(Rule::unary_not));
+
+        let mut policies = vec![];
+
+        for pair in root.into_inner() {
+            if pair.as_rule() != Rule::cedar_policy {
+                continue;
+            }
+
+            let span = pair.as_span();
+            let mut pairs = pair.into_inner();
+            let class = match pairs.next().unwrap().as_str() {
+                "permit" => PolicyValue::Allow,
+                _ => PolicyValue::Deny,
+            };
+
+            let mut clauses = self.cedar_scope(pairs.next().unwrap());
+
+            for condition in pairs {
+                let mut pairs = condition.into_inner();
+                let kind = pairs.next().unwrap().as_str();
+                let expr = self.cedar_expr(pairs.next().unwrap(), &pratt);
+
+                clauses.push(match kind {
+                    "when" => expr,
+                    _ => Expr::Not(Box::new(expr)),
+                });
+            }
+
+            let Some(expr) = clauses
+                .into_iter()
+                .reduce(|lhs, rhs| Expr::And(Box::new(lhs), Box::new(rhs)))
+            else {
+                self.cedar_unsupported(span, "policies without any constraints");
+                continue;
+            };
+
+            let mut codegen = Codegen::default();
+            codegen.codegen_expr_root(&expr);
+
+            policies.push(CedarPolicy {
+                class,
+                expr,
+                opcodes: codegen.ops,
+            });
+        }
+
+        if !self.errors.is_empty() {
+            Err(std::mem::take(&mut self.errors))
+        } else {
+            Ok(policies)
+        }
+    }
+
+    /// Lower the policy scope to the constraints it puts on the principal and resource
+    fn cedar_scope(&mut self, pair: Pair<Rule>) -> Vec<Expr> {
+        let mut clauses = vec![];
+
+        for scope in pair.into_inner() {
+            let rule = scope.as_rule();
+            let span = scope.as_span();
+            let mut pairs = scope.into_inner();
+            let Some(op) = pairs.next() else {
+                continue;
+            };
+
+            match rule {
+                Rule::principal_scope => {
+                    let target = pairs.next().unwrap();
+                    clauses.push(self.cedar_relation(Global::Subject, op, target));
+                }
+                Rule::resource_scope => {
+                    let target = pairs.next().unwrap();
+                    clauses.push(self.cedar_relation(Global::Resource, op, target));
+                }
+                _ => {
+                    self.cedar_unsupported(span, "action constraints, Authly has no actions");
+                }
+            }
+        }
+
+        clauses
+    }
+
+    fn cedar_expr(&mut self, pair: Pair<Rule>, pratt: &PrattParser<Rule>) -> Expr {
+        match pair.as_rule() {
+            Rule::expr => pratt
+                .map_primary(|atom| self.cedar_expr(atom, pratt))
+                .map_prefix(|op, rhs| match op.as_rule() {
+                    Rule::unary_not => Expr::Not(Box::new(rhs)),
+                    // `unary_prefix` in cedar.pest only matches `unary_not`
+                    _ => unreachable!("prefix {op:?}"),
+                })
+                .map_infix(|lhs, op, rhs| match op.as_rule() {
+                    Rule::infix_and => Expr::And(Box::new(lhs), Box::new(rhs)),
+                    Rule::infix_or => Expr::Or(Box::new(lhs), Box::new(rhs)),
+                    // `infix` in cedar.pest only matches `infix_and` and `infix_or`
+                    _ => unreachable!("infix {op:?}"),
+                })
+                .parse(pair.into_inner()),
+            Rule::expr_relation => {
+                let mut pairs = pair.into_inner();
+                let variable = pairs.next().unwrap();
+                let op = pairs.next().unwrap();
+                let target = pairs.next().unwrap();
+
+                match variable.as_str() {
+                    "principal" => self.cedar_relation(Global::Subject, op, target),
+                    "resource" => self.cedar_relation(Global::Resource, op, target),
+                    "action" => {
+                        self.cedar_unsupported(
+                            variable.as_span(),
+                            "actions, Authly has no actions",
+                        );
+                        Expr::Error
+                    }
+                    _ => {
+                        self.cedar_unsupported(variable.as_span(), "context");
+                        Expr::Error
+                    }
+                }
+            }
+            _ => {
+                self.pest_error(
+                    pair.as_span(),
+                    PolicyCompileErrorKind::Misc("unhandled syntax"),
+                );
+                Expr::Error
+            }
+        }
+    }
+
+    /// Lower `principal` or `resource` related to an entity reference with `==` or `in`
+    fn cedar_relation(&mut self, global: Global, op: Pair<Rule>, target: Pair<Rule>) -> Expr {
+        let entity_ref = cedar_entity_ref(target);
+
+        match (op.as_str(), &global, entity_ref.type_path.as_slice()) {
+            ("==", Global::Subject, [_type]) => {
+                let Some(entity) = self.cedar_entity_label(&entity_ref) else {
+                    return Expr::Error;
+                };
+
+                Expr::Equals(
+                    Term::Field(
+                        Global::Subject,
+                        Label128(PropId::from(BuiltinProp::Entity).to_raw_array()),
+                    ),
+                    entity,
+                )
+            }
+            ("in", _, [namespace, property]) => {
+                let Some((property_label, attr_label)) =
+                    self.cedar_attribute_label(&entity_ref, namespace, property)
+                else {
+                    return Expr::Error;
+                };
+
+                Expr::Contains(
+                    Term::Field(global.clone(), property_label.clone()),
+                    Term::Attr(property_label, attr_label),
+                )
+            }
+            ("==", Global::Resource, _) => {
+                self.cedar_unsupported(
+                    entity_ref.span,
+                    "resource entities, use `resource in namespace::property::\"attribute\"`",
+                );
+                Expr::Error
+            }
+            ("==", ..) => {
+                self.cedar_unsupported(
+                    entity_ref.span,
+                    "equality with attributes, use `in` to test for an attribute",
+                );
+                Expr::Error
+            }
+            _ => {
+                self.cedar_unsupported(
+                    entity_ref.span,
+                    "membership in entities, use `namespace::property::\"attribute\"`",
+                );
+                Expr::Error
+            }
+        }
+    }
+
+    fn cedar_entity_label(&mut self, entity_ref: &EntityRef) -> Option<Term> {
+        match self
+            .namespace
+            .get_namespace(entity_ref.id)
+            .map(|namespace| &namespace.kind)
+        {
+            Some(NamespaceKind::Entity(id)) => {
+                Some(Term::Entity(id.kind(), Label128(id.to_raw_array())))
+            }
+            Some(NamespaceKind::Service(id)) => {
+                Some(Term::Entity(Kind::Service, Label128(id.to_raw_array())))
+            }
+            _ => {
+                self.pest_error(
+                    entity_ref.span,
+                    PolicyCompileErrorKind::UnknownLabel(entity_ref.id.to_string()),
+                );
+                None
+            }
+        }
+    }
+
+    fn cedar_attribute_label(
+        &mut self,
+        entity_ref: &EntityRef,
+        namespace: &str,
+        property: &str,
+    ) -> Option<(Label128, Label128)> {
+        let property_label = match self.namespace.get_entry(namespace, property) {
+            Ok(NamespaceEntry::PropertyLabel(id)) => Label128(id.to_raw_array()),
+            Err(NsLookupErr::Namespace) => {
+                self.pest_error(
+                    entity_ref.span,
+                    PolicyCompileErrorKind::UnknownNamespace(namespace.to_string()),
+                );
+                return None;
+            }
+            Err(NsLookupErr::Entry) => {
+                self.pest_error(
+                    entity_ref.span,
+                    PolicyCompileErrorKind::UnknownProperty(property.to_string()),
+                );
+                return None;
+            }
+        };
+
+        match self
+            .doc_data
+            .find_attribute_by_label(property_label.0.into(), entity_ref.id)
+        {
+            Ok(id) => Some((property_label, Label128(id.to_raw_array()))),
+            Err(_) => {
+                self.pest_error(
+                    entity_ref.span,
+                    PolicyCompileErrorKind::UnknownAttribute(
+                        property.to_string(),
+                        entity_ref.id.to_string(),
+                    ),
+                );
+                None
+            }
+        }
+    }
+
+    fn cedar_unsupported(&mut self, span: Span, construct: &'static str) {
+        self.pest_error(span, PolicyCompileErrorKind::UnsupportedCedar(construct));
+    }
+}
+
+fn cedar_entity_ref(pair: Pair<'_, Rule>) -> EntityRef<'_> {
+    let span = pair.as_span();
+    let mut pairs = pair.into_inner();
+    let type_path = pairs.next().unwrap().as_str().split("::").collect();
+    let id = pairs.next().unwrap().into_inner().next().unwrap().as_str();
+
+    EntityRef {
+        span,
+        type_path,
+        id,
+    }
+}
@@ -71,6 +71,10 @@ impl Codegen {
                 Global::Resource => {
                     self.ops.push(OpCode::LoadResourceAttrs);
                 }
+                // environment attributes are passed to the VM as part of the subject attributes
+                Global::Environment => {
+                    self.ops.push(OpCode::LoadSubjectAttrs);
+                }
             },
             Term::Attr(_prop, attr) => self.ops.push(OpCode::LoadConstAttrId(AttrId::from(attr.0))),
             Term::Error => {}
@@ -41,6 +41,8 @@ pub struct Label128(pub [u8; 16]);
 pub enum Global {
     Subject,
     Resource,
+    /// The environment of the request, like the time it was made
+    Environment,
 }
 
 #[cfg(test)]
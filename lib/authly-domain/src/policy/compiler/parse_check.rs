@@ -1,12 +1,20 @@
 //! This module takes the pest parse tree and transforms it into Expr,
 //! with basic type checking
 
-use authly_common::id::kind::Kind;
+use std::cmp::Ordering;
+
+use authly_common::id::{kind::Kind, PropId};
 use pest::{iterators::Pair, Span};
 
 use crate::{
     document::doc_compiler::{NamespaceEntry, NamespaceKind, NsLookupErr},
-    policy::error::{PolicyCompileError, PolicyCompileErrorKind},
+    id::BuiltinProp,
+    policy::{
+        error::{PolicyCompileError, PolicyCompileErrorKind},
+        network::network_attr,
+        schedule::TimeUnit,
+    },
+    remote_addr::IpCidr,
 };
 
 use super::{
@@ -47,6 +55,8 @@ impl PolicyCompiler<'_> {
 
                 Expr::Contains(lhs, rhs)
             }
+            Rule::expr_schedule => self.pest_schedule(pair),
+            Rule::expr_network => self.pest_network(pair),
             _ => {
                 self.pest_error(
                     pair.as_span(),
@@ -57,6 +67,111 @@ impl PolicyCompiler<'_> {
         }
     }
 
+    /// Lower a schedule condition to a disjunction of the time attributes within the range.
+    ///
+    /// A range whose end is before its start wraps around, like `Time.hour in 22..6`.
+    fn pest_schedule(&mut self, pair: Pair<Rule>) -> Expr {
+        let mut pairs = pair.into_inner();
+        let unit = match pairs.next().unwrap().as_str() {
+            "hour" => TimeUnit::Hour,
+            _ => TimeUnit::Weekday,
+        };
+        let mut range = pairs.next().unwrap().into_inner();
+
+        let Some(start) = self.pest_time_value(unit, range.next().unwrap(), false) else {
+            return Expr::Error;
+        };
+        let values: Vec<u8> = match (range.next(), range.next()) {
+            (Some(op), Some(end)) => {
+                let inclusive = op.as_str() == "..=";
+                let Some(end) = self.pest_time_value(unit, end, !inclusive) else {
+                    return Expr::Error;
+                };
+                let end = if inclusive { end + 1 } else { end };
+                let len = match end.cmp(&start) {
+                    Ordering::Greater => end - start,
+                    Ordering::Less => end + unit.cycle() - start,
+                    Ordering::Equal if inclusive => unit.cycle(),
+                    Ordering::Equal => 0,
+                };
+
+                if len == 0 {
+                    self.pest_error(
+                        op.as_span(),
+                        PolicyCompileErrorKind::InvalidSchedule("empty time range"),
+                    );
+                    return Expr::Error;
+                }
+
+                (0..len)
+                    .map(|offset| (start + offset) % unit.cycle())
+                    .collect()
+            }
+            _ => vec![start],
+        };
+
+        let prop_label = Label128(PropId::from(unit.prop()).to_raw_array());
+
+        values
+            .into_iter()
+            .map(|value| {
+                Expr::Contains(
+                    Term::Field(Global::Environment, prop_label.clone()),
+                    Term::Attr(
+                        prop_label.clone(),
+                        Label128(unit.attr(value).to_raw_array()),
+                    ),
+                )
+            })
+            .reduce(|lhs, rhs| Expr::Or(Box::new(lhs), Box::new(rhs)))
+            .unwrap_or(Expr::Error)
+    }
+
+    /// Lower a network condition to a test of the network attribute of the subject
+    fn pest_network(&mut self, pair: Pair<Rule>) -> Expr {
+        let cidr_pair = pair.into_inner().next().unwrap();
+        let cidr = match cidr_pair.as_str().parse::<IpCidr>() {
+            Ok(cidr) => cidr,
+            Err(err) => {
+                self.pest_error(
+                    cidr_pair.as_span(),
+                    PolicyCompileErrorKind::InvalidNetwork(err.to_string()),
+                );
+                return Expr::Error;
+            }
+        };
+
+        let prop_label = Label128(PropId::from(BuiltinProp::SubjectIp).to_raw_array());
+
+        Expr::Contains(
+            Term::Field(Global::Environment, prop_label.clone()),
+            Term::Attr(prop_label, Label128(network_attr(&cidr).to_raw_array())),
+        )
+    }
+
+    /// Parse a time value. An exclusive range end may be one past the last value, like `Time.hour in 18..24`.
+    fn pest_time_value(&mut self, unit: TimeUnit, pair: Pair<Rule>, range_end: bool) -> Option<u8> {
+        let max = if range_end && unit == TimeUnit::Hour {
+            unit.cycle()
+        } else {
+            unit.cycle() - 1
+        };
+
+        match unit.parse_value(pair.as_str()) {
+            Some(value) if value <= max => Some(value),
+            _ => {
+                self.pest_error(
+                    pair.as_span(),
+                    PolicyCompileErrorKind::InvalidSchedule(match unit {
+                        TimeUnit::Hour => "expected an hour from 0 to 23",
+                        TimeUnit::Weekday => "expected a weekday, like `mon`",
+                    }),
+                );
+                None
+            }
+        }
+    }
+
     fn pest_term(&mut self, pair: Pair<Rule>) -> Term {
         match pair.as_rule() {
             Rule::label => {
@@ -69,8 +184,12 @@ impl PolicyCompiler<'_> {
             Rule::term_field => {
                 let mut pairs = pair.into_inner();
                 let global = self.pest_global(pairs.next().unwrap());
-                let namespace = pairs.next().unwrap();
-                let property = pairs.next().unwrap();
+                let labels: Vec<_> = pairs.collect();
+                let (namespace, property) = match labels.as_slice() {
+                    [namespace, property] => (Some(namespace.clone()), property.clone()),
+                    [property] => (None, property.clone()),
+                    _ => unreachable!("field labels"),
+                };
                 let Some(label) = self.pest_property_label(namespace, property) else {
                     return Term::Error;
                 };
@@ -79,15 +198,35 @@ impl PolicyCompiler<'_> {
             }
             Rule::term_attr => {
                 let span = pair.as_span();
-                let mut pairs = pair.into_inner();
-                let namespace = pairs.next().unwrap();
-                let property = pairs.next().unwrap();
+                let labels: Vec<_> = pair.into_inner().collect();
+                let (namespace, property, attribute) = match labels.as_slice() {
+                    [namespace, property, attribute] => {
+                        (Some(namespace.clone()), property.clone(), attribute.clone())
+                    }
+                    [property, attribute] => {
+                        // `a:b` could be mistaken for a reference into the namespace `a`
+                        if self.default_namespace.is_some()
+                            && self.namespace.get_namespace(property.as_str()).is_some()
+                        {
+                            self.pest_error(
+                                span,
+                                PolicyCompileErrorKind::AmbiguousReference(
+                                    span.as_str().to_string(),
+                                ),
+                            );
+                            return Term::Error;
+                        }
+
+                        (None, property.clone(), attribute.clone())
+                    }
+                    _ => unreachable!("attribute labels"),
+                };
                 let Some(property_label) = self.pest_property_label(namespace, property.clone())
                 else {
                     return Term::Error;
                 };
 
-                let attr_label_str = pairs.next().unwrap().as_str();
+                let attr_label_str = attribute.as_str();
 
                 let attr_label = match self
                     .doc_data
@@ -137,14 +276,32 @@ impl PolicyCompiler<'_> {
         }
     }
 
-    fn pest_property_label(&mut self, namespace: Pair<Rule>, prop: Pair<Rule>) -> Option<Label128> {
-        let ns_label = namespace.as_str();
+    /// Look up a property, in the default namespace if the namespace is left out
+    fn pest_property_label(
+        &mut self,
+        namespace: Option<Pair<Rule>>,
+        prop: Pair<Rule>,
+    ) -> Option<Label128> {
         let prop_label = prop.as_str();
+        let (ns_label, ns_span) = match &namespace {
+            Some(namespace) => (namespace.as_str(), namespace.as_span()),
+            None => match self.default_namespace {
+                Some(default_namespace) => (default_namespace, prop.as_span()),
+                None => {
+                    self.pest_error(
+                        prop.as_span(),
+                        PolicyCompileErrorKind::NoDefaultNamespace(prop_label.to_string()),
+                    );
+                    return None;
+                }
+            },
+        };
+
         match self.namespace.get_entry(ns_label, prop_label) {
             Ok(NamespaceEntry::PropertyLabel(id)) => Some(Label128(id.to_raw_array())),
             Err(NsLookupErr::Namespace) => {
                 self.pest_error(
-                    namespace.as_span(),
+                    ns_span,
                     PolicyCompileErrorKind::UnknownNamespace(ns_label.to_string()),
                 );
                 None
@@ -170,7 +327,7 @@ impl PolicyCompiler<'_> {
         }
     }
 
-    fn pest_error(&mut self, span: Span, kind: PolicyCompileErrorKind) {
+    pub(super) fn pest_error(&mut self, span: Span, kind: PolicyCompileErrorKind) {
         self.errors.push(PolicyCompileError {
             span: span.start()..span.end(),
             kind,
@@ -32,6 +32,12 @@ mod policy_tests {
         parse_policy_ok("Subject.a:role contains foo:bar:baz");
     }
 
+    #[test]
+    fn policy_unqualified() {
+        parse_policy_ok("Subject.role contains role:admin");
+        parse_policy_ok("Subject.entity == testservice");
+    }
+
     #[test]
     fn policy_conjunction() {
         parse_policy_ok("Subject.a:role contains a:b:c and Resource.a:name == foo");
@@ -64,6 +70,18 @@ mod policy_tests {
         );
     }
 
+    #[test]
+    fn policy_schedule() {
+        parse_policy_ok("Time.hour in 9..17 and Time.weekday in mon..=fri");
+        parse_policy_ok("Time.weekday in sat or not Time.hour in 22..6");
+    }
+
+    #[test]
+    fn policy_network() {
+        parse_policy_ok("Subject.ip in 10.0.0.0/8 or Subject.ip in fd00::/8");
+        parse_policy_ok("not Subject.ip in 192.168.1.1");
+    }
+
     #[test]
     fn policy_print_tree() {
         let foo = parse_policy_ok("(not Subject.a:role contains a:b:c) and (not a == b)")
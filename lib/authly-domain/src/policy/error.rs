@@ -25,6 +25,40 @@ pub enum PolicyCompileErrorKind {
     #[error("no attribute {1} in {0}")]
     UnknownAttribute(String, String),
 
+    #[error("no default namespace for unqualified reference: {0}")]
+    NoDefaultNamespace(String),
+
+    #[error("ambiguous reference {0}, qualify it with a namespace")]
+    AmbiguousReference(String),
+
+    #[error("invalid schedule: {0}")]
+    InvalidSchedule(&'static str),
+
+    #[error("invalid network: {0}")]
+    InvalidNetwork(String),
+
+    #[error("unsupported Cedar construct: {0}")]
+    UnsupportedCedar(&'static str),
+
     #[error("compile error: {0}")]
     Misc(&'static str),
 }
+
+impl PolicyCompileErrorKind {
+    /// A stable code identifying the kind of error, for programs
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Parse(_) => "policy-parse",
+            Self::UnknownLabel(_) => "policy-unknown-label",
+            Self::UnknownNamespace(_) => "policy-unknown-namespace",
+            Self::UnknownProperty(_) => "policy-unknown-property",
+            Self::UnknownAttribute(..) => "policy-unknown-attribute",
+            Self::NoDefaultNamespace(_) => "policy-no-default-namespace",
+            Self::AmbiguousReference(_) => "policy-ambiguous-reference",
+            Self::InvalidSchedule(_) => "policy-invalid-schedule",
+            Self::InvalidNetwork(_) => "policy-invalid-network",
+            Self::UnsupportedCedar(_) => "policy-unsupported-cedar",
+            Self::Misc(_) => "policy-compile",
+        }
+    }
+}
@@ -1,5 +1,13 @@
 pub mod compiler;
 pub mod error;
+pub mod network;
+pub mod schedule;
 
+#[cfg(test)]
+mod test_cedar;
 #[cfg(test)]
 mod test_compile;
+#[cfg(test)]
+mod test_network;
+#[cfg(test)]
+mod test_schedule;
@@ -0,0 +1,55 @@
+//! Network conditions in policies.
+//!
+//! The policy VM can only test membership in attribute sets, so the IP address of the subject is passed to it
+//! as environment attributes: one for each network prefix of the address, from `/0` to the full address.
+//! A network condition like `Subject.ip in 10.0.0.0/8` is lowered to a membership test of the attribute for that network.
+
+use std::net::IpAddr;
+
+use authly_common::{id::AttrId, policy::engine::AccessControlParams};
+
+use crate::remote_addr::{mask, max_prefix_len, IpCidr};
+
+/// Network attribute IDs start with these bytes
+const NETWORK_ATTR_MARKER: [u8; 8] = *b"authlyip";
+
+/// The attribute representing a network
+pub fn network_attr(cidr: &IpCidr) -> AttrId {
+    prefix_attr(cidr.network(), cidr.prefix_len())
+}
+
+fn prefix_attr(network: IpAddr, prefix_len: u8) -> AttrId {
+    let mut hasher = blake3::Hasher::new();
+    match network {
+        IpAddr::V4(addr) => hasher.update(&[4]).update(&addr.octets()),
+        IpAddr::V6(addr) => hasher.update(&[6]).update(&addr.octets()),
+    };
+    hasher.update(&[prefix_len]);
+
+    let mut array = [0; 16];
+    array[..8].copy_from_slice(&NETWORK_ATTR_MARKER);
+    array[8..].copy_from_slice(&hasher.finalize().as_bytes()[..8]);
+
+    AttrId::from_raw_array(array)
+}
+
+/// Whether the attribute is one of the network attributes
+pub fn is_network_attr(attr: AttrId) -> bool {
+    attr.to_raw_array()[..8] == NETWORK_ATTR_MARKER
+}
+
+/// The network attributes of an address
+pub fn network_attrs(addr: IpAddr) -> impl Iterator<Item = AttrId> {
+    let addr = addr.to_canonical();
+
+    (0..=max_prefix_len(addr))
+        .map(move |prefix_len| prefix_attr(mask(addr, prefix_len), prefix_len))
+}
+
+/// Set the IP address of the subject as environment attributes of the access control parameters.
+///
+/// Network attributes supplied by the caller are removed first, the address can't be chosen by the requester.
+pub fn set_subject_ip(params: &mut AccessControlParams, addr: IpAddr) {
+    params.subject_attrs.retain(|attr| !is_network_attr(*attr));
+    params.subject_attrs.extend(network_attrs(addr));
+}
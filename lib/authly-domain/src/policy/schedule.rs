@@ -0,0 +1,105 @@
+//! Schedule conditions in policies.
+//!
+//! The policy VM has no notion of time, so the time of an access control request is passed to it
+//! as environment attributes: one for the hour of the day and one for the day of the week.
+//! A schedule condition like `Time.hour in 9..17` is lowered to membership tests of these attributes.
+//!
+//! The time is the timestamp of the request, so that every node in a cluster reaches the same decision for it.
+
+use authly_common::{id::AttrId, policy::engine::AccessControlParams};
+use time::{OffsetDateTime, UtcOffset, Weekday};
+
+use crate::id::{is_reserved_id, BuiltinProp};
+
+/// The attribute IDs below are reserved for the hours of the day
+const HOUR_ATTR_BASE: u128 = 0x7400;
+
+/// The attribute IDs below are reserved for the days of the week
+const WEEKDAY_ATTR_BASE: u128 = 0x7500;
+
+// the schedule attributes must not collide with generated attribute IDs
+const _: () = assert!(is_reserved_id(HOUR_ATTR_BASE + 23) && is_reserved_id(WEEKDAY_ATTR_BASE + 6));
+
+/// The unit of a schedule condition
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TimeUnit {
+    Hour,
+    Weekday,
+}
+
+impl TimeUnit {
+    /// The environment property holding the time in this unit
+    pub const fn prop(self) -> BuiltinProp {
+        match self {
+            Self::Hour => BuiltinProp::TimeHour,
+            Self::Weekday => BuiltinProp::TimeWeekday,
+        }
+    }
+
+    /// The number of distinct values, a full cycle
+    pub const fn cycle(self) -> u8 {
+        match self {
+            Self::Hour => 24,
+            Self::Weekday => 7,
+        }
+    }
+
+    /// Parse a value in this unit. Hours are numbers from 0, weekdays are abbreviated names.
+    pub fn parse_value(self, value: &str) -> Option<u8> {
+        match self {
+            Self::Hour => value.parse().ok(),
+            Self::Weekday => match value {
+                "mon" => Some(0),
+                "tue" => Some(1),
+                "wed" => Some(2),
+                "thu" => Some(3),
+                "fri" => Some(4),
+                "sat" => Some(5),
+                "sun" => Some(6),
+                _ => None,
+            },
+        }
+    }
+
+    /// The attribute representing a value in this unit
+    pub const fn attr(self, value: u8) -> AttrId {
+        match self {
+            Self::Hour => AttrId::from_uint(HOUR_ATTR_BASE + value as u128),
+            Self::Weekday => AttrId::from_uint(WEEKDAY_ATTR_BASE + value as u128),
+        }
+    }
+}
+
+/// Whether the attribute is one of the time attributes
+pub fn is_time_attr(attr: AttrId) -> bool {
+    [TimeUnit::Hour, TimeUnit::Weekday]
+        .into_iter()
+        .any(|unit| (0..unit.cycle()).any(|value| unit.attr(value) == attr))
+}
+
+/// The time attributes for the time of a request, in the given timezone
+pub fn time_attrs(time: OffsetDateTime, offset: UtcOffset) -> [AttrId; 2] {
+    let time = time.to_offset(offset);
+    let weekday = match time.weekday() {
+        Weekday::Monday => 0,
+        Weekday::Tuesday => 1,
+        Weekday::Wednesday => 2,
+        Weekday::Thursday => 3,
+        Weekday::Friday => 4,
+        Weekday::Saturday => 5,
+        Weekday::Sunday => 6,
+    };
+
+    [
+        TimeUnit::Hour.attr(time.hour()),
+        TimeUnit::Weekday.attr(weekday),
+    ]
+}
+
+/// Set the time of the request as environment attributes of the access control parameters.
+///
+/// Time attributes supplied by the caller are removed first, the time can't be chosen by the requester.
+pub fn set_request_time(params: &mut AccessControlParams, time: OffsetDateTime, offset: UtcOffset) {
+    params.subject_attrs.retain(|attr| !is_time_attr(*attr));
+    params.subject_attrs.extend(time_attrs(time, offset));
+}
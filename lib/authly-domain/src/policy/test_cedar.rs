@@ -0,0 +1,284 @@
+use std::collections::BTreeSet;
+
+use authly_common::{
+    id::{kind::Kind, AttrId, PolicyId, PropId, ServiceId},
+    policy::{
+        code::{to_bytecode, OpCode, PolicyValue},
+        engine::{AccessControlParams, NoOpPolicyTracer, PolicyEngine},
+    },
+};
+
+use crate::{
+    document::{
+        compiled_document::{CompiledAttribute, CompiledDocumentData, CompiledProperty},
+        doc_compiler::{NamespaceEntry, NamespaceKind, Namespaces},
+    },
+    id::BuiltinProp,
+    repo::service_repo::PropertyKind,
+};
+
+use super::{
+    compiler::{
+        cedar::CedarPolicy,
+        expr::{Expr, Global, Label128, Term},
+        PolicyCompiler,
+    },
+    error::PolicyCompileErrorKind,
+};
+
+const SVC: ServiceId = ServiceId::from_uint(42);
+const ROLE: PropId = PropId::from_uint(1337);
+const ROLE_ROOT: AttrId = AttrId::from_uint(1338);
+const ROLE_GUEST: AttrId = AttrId::from_uint(1339);
+const KIND: PropId = PropId::from_uint(1340);
+const KIND_TROUSERS: AttrId = AttrId::from_uint(1341);
+
+fn test_env() -> (Namespaces, CompiledDocumentData) {
+    let namespace = Namespaces::from_iter([
+        (
+            "a".to_string(),
+            NamespaceKind::Service(SVC),
+            vec![(
+                "entity".to_string(),
+                NamespaceEntry::PropertyLabel(BuiltinProp::Entity.into()),
+            )],
+        ),
+        (
+            "svc".to_string(),
+            NamespaceKind::Service(SVC),
+            vec![
+                ("role".to_string(), NamespaceEntry::PropertyLabel(ROLE)),
+                ("kind".to_string(), NamespaceEntry::PropertyLabel(KIND)),
+            ],
+        ),
+    ]);
+    let mut doc_data = CompiledDocumentData::default();
+    doc_data.domain_props.push(CompiledProperty {
+        id: ROLE,
+        ns_id: SVC.upcast(),
+        kind: PropertyKind::Entity,
+        label: "role".to_string(),
+        inheritance: Default::default(),
+        attributes: vec![
+            CompiledAttribute {
+                id: ROLE_ROOT,
+                label: "root".to_string(),
+            },
+            CompiledAttribute {
+                id: ROLE_GUEST,
+                label: "guest".to_string(),
+            },
+        ],
+    });
+    doc_data.domain_props.push(CompiledProperty {
+        id: KIND,
+        ns_id: SVC.upcast(),
+        kind: PropertyKind::Resource,
+        label: "kind".to_string(),
+        inheritance: Default::default(),
+        attributes: vec![CompiledAttribute {
+            id: KIND_TROUSERS,
+            label: "trousers".to_string(),
+        }],
+    });
+
+    (namespace, doc_data)
+}
+
+#[track_caller]
+fn cedar(src: &str) -> Vec<CedarPolicy> {
+    let (namespace, doc_data) = test_env();
+    PolicyCompiler::new(&namespace, &doc_data)
+        .compile_cedar(src)
+        .unwrap()
+}
+
+#[track_caller]
+fn cedar_one(src: &str) -> CedarPolicy {
+    let mut policies = cedar(src);
+    assert_eq!(policies.len(), 1);
+    policies.pop().unwrap()
+}
+
+#[track_caller]
+fn cedar_error(src: &str) -> PolicyCompileErrorKind {
+    let (namespace, doc_data) = test_env();
+    PolicyCompiler::new(&namespace, &doc_data)
+        .compile_cedar(src)
+        .unwrap_err()
+        .into_iter()
+        .next()
+        .unwrap()
+        .kind
+}
+
+#[track_caller]
+fn authly(src: &str) -> (Expr, Vec<OpCode>) {
+    let (namespace, doc_data) = test_env();
+    PolicyCompiler::new(&namespace, &doc_data)
+        .compile(src)
+        .unwrap()
+}
+
+/// Evaluate a single policy triggered by the trousers resource attribute
+fn eval(class: &PolicyValue, opcodes: &[OpCode], subject_attrs: &[AttrId]) -> PolicyValue {
+    let class = match class {
+        PolicyValue::Allow => PolicyValue::Allow,
+        _ => PolicyValue::Deny,
+    };
+    let policy_id = PolicyId::from_uint(1);
+    let mut engine = PolicyEngine::default();
+    engine.add_policy(policy_id, class, to_bytecode(opcodes));
+    engine.add_trigger(BTreeSet::from([KIND_TROUSERS]), BTreeSet::from([policy_id]));
+
+    engine
+        .eval(
+            &AccessControlParams {
+                resource_attrs: FromIterator::from_iter([KIND_TROUSERS]),
+                subject_attrs: FromIterator::from_iter(subject_attrs.iter().copied()),
+                ..Default::default()
+            },
+            &mut NoOpPolicyTracer,
+        )
+        .unwrap()
+}
+
+/// Assert that the Cedar policy evaluates like the Authly policy, for a few subjects
+#[track_caller]
+fn assert_equivalent(cedar_src: &str, class: PolicyValue, authly_src: &str) {
+    let policy = cedar_one(cedar_src);
+    let (expr, opcodes) = authly(authly_src);
+
+    assert_eq!(policy.class, class);
+    assert_eq!(policy.expr, expr);
+    assert_eq!(policy.opcodes, opcodes);
+
+    for subject_attrs in [
+        &[][..],
+        &[ROLE_ROOT][..],
+        &[ROLE_GUEST][..],
+        &[ROLE_ROOT, ROLE_GUEST][..],
+    ] {
+        assert_eq!(
+            eval(&policy.class, &policy.opcodes, subject_attrs),
+            eval(&class, &opcodes, subject_attrs),
+            "subject attributes: {subject_attrs:?}"
+        );
+    }
+}
+
+#[test]
+fn test_cedar_permit_principal_in_attribute() {
+    assert_equivalent(
+        r#"permit(principal in svc::role::"root", action, resource);"#,
+        PolicyValue::Allow,
+        "Subject.svc:role contains svc:role:root",
+    );
+}
+
+#[test]
+fn test_cedar_forbid_unless() {
+    assert_equivalent(
+        r#"
+        forbid(principal, action, resource in svc::kind::"trousers")
+        unless { principal in svc::role::"root" };
+        "#,
+        PolicyValue::Deny,
+        "Resource.svc:kind contains svc:kind:trousers and not Subject.svc:role contains svc:role:root",
+    );
+}
+
+#[test]
+fn test_cedar_when_logic() {
+    assert_equivalent(
+        r#"
+        permit(principal, action, resource)
+        when { principal in svc::role::"root" || principal in svc::role::"guest" && !(principal in svc::role::"root") };
+        "#,
+        PolicyValue::Allow,
+        "Subject.svc:role contains svc:role:root or Subject.svc:role contains svc:role:guest and not (Subject.svc:role contains svc:role:root)",
+    );
+}
+
+#[test]
+fn test_cedar_principal_equals_entity() {
+    let policy = cedar_one(r#"permit(principal == Service::"svc", action, resource);"#);
+
+    assert_eq!(
+        policy.expr,
+        Expr::Equals(
+            Term::Field(
+                Global::Subject,
+                Label128(PropId::from(BuiltinProp::Entity).to_raw_array()),
+            ),
+            Term::Entity(Kind::Service, Label128(SVC.to_raw_array())),
+        )
+    );
+    assert_eq!(policy.opcodes, authly("Subject.a:entity == svc").1);
+}
+
+#[test]
+fn test_cedar_multiple_policies() {
+    let policies = cedar(
+        r#"
+        // guests may look
+        permit(principal in svc::role::"guest", action, resource);
+
+        forbid(principal, action, resource) when { principal in svc::role::"guest" };
+        "#,
+    );
+
+    assert_eq!(policies.len(), 2);
+    assert_eq!(policies[0].class, PolicyValue::Allow);
+    assert_eq!(policies[1].class, PolicyValue::Deny);
+}
+
+#[test]
+fn test_cedar_unsupported() {
+    assert!(matches!(
+        cedar_error(r#"permit(principal, action == Action::"read", resource);"#),
+        PolicyCompileErrorKind::UnsupportedCedar(_)
+    ));
+    assert!(matches!(
+        cedar_error(r#"permit(principal, action, resource == Document::"doc");"#),
+        PolicyCompileErrorKind::UnsupportedCedar(_)
+    ));
+    assert!(matches!(
+        cedar_error(r#"permit(principal in Group::"admins", action, resource);"#),
+        PolicyCompileErrorKind::UnsupportedCedar(_)
+    ));
+    assert!(matches!(
+        cedar_error(r#"permit(principal, action, resource);"#),
+        PolicyCompileErrorKind::UnsupportedCedar(_)
+    ));
+    assert!(matches!(
+        cedar_error(
+            r#"permit(principal, action, resource) when { context in svc::role::"root" };"#
+        ),
+        PolicyCompileErrorKind::UnsupportedCedar(_)
+    ));
+}
+
+#[test]
+fn test_cedar_unknown_labels() {
+    assert!(matches!(
+        cedar_error(r#"permit(principal in svc::role::"superuser", action, resource);"#),
+        PolicyCompileErrorKind::UnknownAttribute(..)
+    ));
+    assert!(matches!(
+        cedar_error(r#"permit(principal == Service::"nobody", action, resource);"#),
+        PolicyCompileErrorKind::UnknownLabel(_)
+    ));
+    assert!(matches!(
+        cedar_error(r#"permit(principal in svc::rank::"root", action, resource);"#),
+        PolicyCompileErrorKind::UnknownProperty(_)
+    ));
+}
+
+#[test]
+fn test_cedar_parse_error() {
+    assert!(matches!(
+        cedar_error("permit(principal, action, resource) when { principal.role == 1 };"),
+        PolicyCompileErrorKind::Parse(_)
+    ));
+}
@@ -13,6 +13,7 @@ use crate::{
         doc_compiler::{NamespaceEntry, NamespaceKind, Namespaces},
     },
     id::BuiltinProp,
+    policy::error::PolicyCompileErrorKind,
     repo::service_repo::PropertyKind,
 };
 
@@ -42,6 +43,7 @@ fn test_env() -> (Namespaces, CompiledDocumentData) {
         ns_id: SVC.upcast(),
         kind: PropertyKind::Entity,
         label: "role".to_string(),
+        inheritance: Default::default(),
         attributes: vec![CompiledAttribute {
             id: ROLE_ROOT,
             label: "root".to_string(),
@@ -69,6 +71,19 @@ fn to_opcodes(src: &str) -> Vec<OpCode> {
         .1
 }
 
+#[track_caller]
+fn compile_error(default_namespace: Option<&str>, src: &str) -> PolicyCompileErrorKind {
+    let (namespace, doc_data) = test_env();
+    PolicyCompiler::new(&namespace, &doc_data)
+        .with_default_namespace(default_namespace)
+        .compile(src)
+        .unwrap_err()
+        .into_iter()
+        .next()
+        .unwrap()
+        .kind
+}
+
 fn subject_entity_equals_svc() -> Expr {
     Expr::Equals(
         Term::Field(
@@ -101,6 +116,45 @@ fn test_expr_field_attribute() {
     );
 }
 
+#[test]
+fn test_expr_default_namespace() {
+    let (namespace, doc_data) = test_env();
+    let mut compiler =
+        PolicyCompiler::new(&namespace, &doc_data).with_default_namespace(Some("svc"));
+
+    assert_eq!(
+        to_expr("Subject.svc:role contains svc:role:root"),
+        compiler
+            .compile("Subject.role contains role:root")
+            .unwrap()
+            .0
+    );
+    // qualified references still resolve to their own namespace
+    assert_eq!(
+        to_expr("Subject.a:entity == svc"),
+        compiler.compile("Subject.a:entity == svc").unwrap().0
+    );
+}
+
+#[test]
+fn test_expr_default_namespace_errors() {
+    assert!(matches!(
+        compile_error(None, "Subject.role contains role:root"),
+        PolicyCompileErrorKind::NoDefaultNamespace(label) if label == "role"
+    ));
+
+    // `svc:role` could be the unqualified `svc` property, or the `role` property of the `svc` namespace
+    assert!(matches!(
+        compile_error(Some("svc"), "Subject.role contains svc:role"),
+        PolicyCompileErrorKind::AmbiguousReference(reference) if reference == "svc:role"
+    ));
+
+    assert!(matches!(
+        compile_error(Some("a"), "Subject.role contains role:root"),
+        PolicyCompileErrorKind::UnknownProperty(label) if label == "role"
+    ));
+}
+
 #[test]
 fn test_expr_not() {
     assert_eq!(
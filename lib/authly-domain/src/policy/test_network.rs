@@ -0,0 +1,180 @@
+use std::{borrow::Cow, collections::BTreeSet, net::IpAddr};
+
+use authly_common::{
+    id::{AttrId, PolicyId},
+    policy::{
+        code::{to_bytecode, PolicyValue},
+        engine::{AccessControlParams, NoOpPolicyTracer, PolicyEngine},
+    },
+};
+
+use crate::{
+    document::{compiled_document::CompiledDocumentData, doc_compiler::Namespaces},
+    remote_addr::{forwarded_client_ip, IpCidr},
+    settings::{Setting, Settings},
+};
+
+use super::{
+    compiler::PolicyCompiler,
+    error::PolicyCompileErrorKind,
+    network::{self, network_attr},
+};
+
+const TROUSERS: AttrId = AttrId::from_uint(1341);
+
+fn ip(addr: &str) -> IpAddr {
+    addr.parse().unwrap()
+}
+
+fn cidr(cidr: &str) -> IpCidr {
+    cidr.parse().unwrap()
+}
+
+#[track_caller]
+fn compile_error(src: &str) -> PolicyCompileErrorKind {
+    let (namespace, doc_data) = (Namespaces::default(), CompiledDocumentData::default());
+    PolicyCompiler::new(&namespace, &doc_data)
+        .compile(src)
+        .unwrap_err()
+        .into_iter()
+        .next()
+        .unwrap()
+        .kind
+}
+
+/// Evaluate an allow policy triggered by the trousers resource attribute, for a subject at the given address
+#[track_caller]
+fn is_allowed(src: &str, addr: &str) -> bool {
+    let (namespace, doc_data) = (Namespaces::default(), CompiledDocumentData::default());
+    let (_, opcodes) = PolicyCompiler::new(&namespace, &doc_data)
+        .compile(src)
+        .unwrap();
+
+    let policy_id = PolicyId::from_uint(1);
+    let mut engine = PolicyEngine::default();
+    engine.add_policy(policy_id, PolicyValue::Allow, to_bytecode(&opcodes));
+    engine.add_trigger(BTreeSet::from([TROUSERS]), BTreeSet::from([policy_id]));
+
+    let mut params = AccessControlParams {
+        resource_attrs: FromIterator::from_iter([TROUSERS]),
+        ..Default::default()
+    };
+    network::set_subject_ip(&mut params, ip(addr));
+
+    matches!(
+        engine.eval(&params, &mut NoOpPolicyTracer).unwrap(),
+        PolicyValue::Allow
+    )
+}
+
+#[test]
+fn test_cidr() {
+    assert!(cidr("10.0.0.0/8").contains(ip("10.1.2.3")));
+    assert!(!cidr("10.0.0.0/8").contains(ip("11.0.0.0")));
+    assert!(cidr("0.0.0.0/0").contains(ip("192.168.1.1")));
+    assert!(!cidr("0.0.0.0/0").contains(ip("::1")));
+    assert!(cidr("192.168.1.1").contains(ip("192.168.1.1")));
+    assert!(!cidr("192.168.1.1").contains(ip("192.168.1.2")));
+    assert!(cidr("fd00::/8").contains(ip("fd12:3456::1")));
+    assert!(!cidr("fd00::/8").contains(ip("fe80::1")));
+
+    // IPv4-mapped IPv6 addresses are IPv4 addresses
+    assert!(cidr("10.0.0.0/8").contains(ip("::ffff:10.0.0.1")));
+
+    assert_eq!(cidr("10.1.2.3/8").to_string(), "10.0.0.0/8");
+    assert!("10.0.0.0/33".parse::<IpCidr>().is_err());
+    assert!("fd00::/129".parse::<IpCidr>().is_err());
+    assert!("10.0.0/8".parse::<IpCidr>().is_err());
+}
+
+#[test]
+fn test_network_policy_ipv4() {
+    let policy = "Subject.ip in 10.0.0.0/8";
+
+    assert!(is_allowed(policy, "10.0.0.1"));
+    assert!(is_allowed(policy, "10.255.255.255"));
+    assert!(is_allowed(policy, "::ffff:10.0.0.1"));
+    assert!(!is_allowed(policy, "11.0.0.1"));
+    assert!(!is_allowed(policy, "192.168.0.1"));
+
+    assert!(is_allowed("Subject.ip in 192.168.1.17", "192.168.1.17"));
+    assert!(!is_allowed("Subject.ip in 192.168.1.17", "192.168.1.18"));
+}
+
+#[test]
+fn test_network_policy_ipv6() {
+    let policy = "Subject.ip in fd00::/8 and not Subject.ip in fd00:bad::/32";
+
+    assert!(is_allowed(policy, "fd12::1"));
+    assert!(!is_allowed(policy, "fd00:bad::1"));
+    assert!(!is_allowed(policy, "fe80::1"));
+
+    // the IPv4 network with the same bits is another network
+    assert!(!is_allowed("Subject.ip in 0.0.0.0/0", "::1"));
+    assert!(is_allowed("Subject.ip in ::/0", "::1"));
+}
+
+#[test]
+fn test_network_attrs_cannot_be_supplied() {
+    let mut params = AccessControlParams::default();
+    params
+        .subject_attrs
+        .insert(network_attr(&cidr("10.0.0.0/8")));
+    params.subject_attrs.insert(TROUSERS);
+
+    network::set_subject_ip(&mut params, ip("192.168.0.1"));
+
+    assert!(!params
+        .subject_attrs
+        .contains(&network_attr(&cidr("10.0.0.0/8"))));
+    assert!(params
+        .subject_attrs
+        .contains(&network_attr(&cidr("192.168.0.0/16"))));
+    assert!(params.subject_attrs.contains(&TROUSERS));
+}
+
+#[test]
+fn test_forwarded_client_ip() {
+    let mut settings = Settings::default();
+    settings
+        .try_set(
+            Setting::TrustedProxies,
+            Cow::Borrowed("10.0.0.0/8, fd00::/8"),
+        )
+        .unwrap();
+    let trusted = &settings.trusted_proxies;
+
+    assert_eq!(forwarded_client_ip("1.2.3.4", trusted), Some(ip("1.2.3.4")));
+
+    // trusted proxies are skipped
+    assert_eq!(
+        forwarded_client_ip("1.2.3.4, 10.0.0.1, fd00::2", trusted),
+        Some(ip("1.2.3.4"))
+    );
+
+    // a client can't spoof the address by prepending to the chain
+    assert_eq!(
+        forwarded_client_ip("6.6.6.6, 1.2.3.4, 10.0.0.1", trusted),
+        Some(ip("1.2.3.4"))
+    );
+
+    // addresses may include ports
+    assert_eq!(
+        forwarded_client_ip("[2001:db8::1]:443, 10.0.0.1:8080", trusted),
+        Some(ip("2001:db8::1"))
+    );
+
+    assert_eq!(forwarded_client_ip("1.2.3.4, garbage", trusted), None);
+}
+
+#[test]
+fn test_network_invalid() {
+    assert!(matches!(
+        compile_error("Subject.ip in 10.0.0.0/33"),
+        PolicyCompileErrorKind::InvalidNetwork(_)
+    ));
+    assert!(matches!(
+        compile_error("Subject.ip in 10.0.0/8"),
+        PolicyCompileErrorKind::InvalidNetwork(_)
+    ));
+}
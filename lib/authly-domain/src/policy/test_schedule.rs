@@ -0,0 +1,171 @@
+use std::{borrow::Cow, collections::BTreeSet};
+
+use authly_common::{
+    id::{AttrId, PolicyId},
+    policy::{
+        code::{to_bytecode, PolicyValue},
+        engine::{AccessControlParams, NoOpPolicyTracer, PolicyEngine},
+    },
+};
+use time::{Date, Month, OffsetDateTime, UtcOffset};
+
+use crate::{
+    document::{compiled_document::CompiledDocumentData, doc_compiler::Namespaces},
+    settings::{Setting, Settings},
+};
+
+use super::{
+    compiler::PolicyCompiler,
+    error::PolicyCompileErrorKind,
+    schedule::{self, TimeUnit},
+};
+
+const TROUSERS: AttrId = AttrId::from_uint(1341);
+
+/// A frozen clock, at the given hour of a day in the first week of 2024, which started on a Monday
+fn at(day: u8, hour: u8) -> OffsetDateTime {
+    Date::from_calendar_date(2024, Month::January, day)
+        .unwrap()
+        .with_hms(hour, 30, 0)
+        .unwrap()
+        .assume_utc()
+}
+
+#[track_caller]
+fn compile_error(src: &str) -> PolicyCompileErrorKind {
+    let (namespace, doc_data) = (Namespaces::default(), CompiledDocumentData::default());
+    PolicyCompiler::new(&namespace, &doc_data)
+        .compile(src)
+        .unwrap_err()
+        .into_iter()
+        .next()
+        .unwrap()
+        .kind
+}
+
+/// Evaluate an allow policy triggered by the trousers resource attribute, at the given time
+#[track_caller]
+fn eval_at(src: &str, time: OffsetDateTime, offset: UtcOffset) -> PolicyValue {
+    let (namespace, doc_data) = (Namespaces::default(), CompiledDocumentData::default());
+    let (_, opcodes) = PolicyCompiler::new(&namespace, &doc_data)
+        .compile(src)
+        .unwrap();
+
+    let policy_id = PolicyId::from_uint(1);
+    let mut engine = PolicyEngine::default();
+    engine.add_policy(policy_id, PolicyValue::Allow, to_bytecode(&opcodes));
+    engine.add_trigger(BTreeSet::from([TROUSERS]), BTreeSet::from([policy_id]));
+
+    let mut params = AccessControlParams {
+        resource_attrs: FromIterator::from_iter([TROUSERS]),
+        ..Default::default()
+    };
+    schedule::set_request_time(&mut params, time, offset);
+
+    engine.eval(&params, &mut NoOpPolicyTracer).unwrap()
+}
+
+fn is_allowed(src: &str, time: OffsetDateTime) -> bool {
+    matches!(eval_at(src, time, UtcOffset::UTC), PolicyValue::Allow)
+}
+
+#[test]
+fn test_schedule_business_hours() {
+    let business_hours = "Time.hour in 9..17 and Time.weekday in mon..=fri";
+
+    assert!(is_allowed(business_hours, at(1, 9)));
+    assert!(is_allowed(business_hours, at(3, 16)));
+    assert!(is_allowed(business_hours, at(5, 12)));
+
+    assert!(!is_allowed(business_hours, at(1, 8)));
+    assert!(!is_allowed(business_hours, at(3, 17)));
+    assert!(!is_allowed(business_hours, at(6, 12)));
+    assert!(!is_allowed(business_hours, at(7, 12)));
+}
+
+#[test]
+fn test_schedule_wrapping_range() {
+    let night = "Time.hour in 22..6";
+
+    assert!(is_allowed(night, at(1, 22)));
+    assert!(is_allowed(night, at(1, 0)));
+    assert!(is_allowed(night, at(1, 5)));
+    assert!(!is_allowed(night, at(1, 6)));
+    assert!(!is_allowed(night, at(1, 21)));
+
+    let weekend = "Time.weekday in sat..=sun";
+
+    assert!(is_allowed(weekend, at(6, 12)));
+    assert!(is_allowed(weekend, at(7, 12)));
+    assert!(!is_allowed(weekend, at(8, 12)));
+}
+
+#[test]
+fn test_schedule_single_value() {
+    assert!(is_allowed("Time.weekday in wed", at(3, 12)));
+    assert!(!is_allowed("Time.weekday in wed", at(4, 12)));
+    assert!(is_allowed("not Time.hour in 12", at(4, 13)));
+}
+
+#[test]
+fn test_schedule_timezone() {
+    let mut settings = Settings::default();
+    settings
+        .try_set(Setting::PolicyTimezone, Cow::Borrowed("+02:00"))
+        .unwrap();
+
+    // 16:30 UTC is 18:30 in the policy timezone
+    assert!(matches!(
+        eval_at("Time.hour in 9..17", at(1, 16), UtcOffset::UTC),
+        PolicyValue::Allow
+    ));
+    assert!(matches!(
+        eval_at("Time.hour in 9..17", at(1, 16), settings.policy_timezone),
+        PolicyValue::Deny
+    ));
+
+    // 23:30 UTC on Friday is Saturday in the policy timezone
+    assert!(matches!(
+        eval_at("Time.weekday in sat", at(5, 23), settings.policy_timezone),
+        PolicyValue::Allow
+    ));
+}
+
+#[test]
+fn test_schedule_time_attrs_cannot_be_supplied() {
+    let mut params = AccessControlParams::default();
+    params.subject_attrs.insert(TimeUnit::Hour.attr(10));
+    params.subject_attrs.insert(TROUSERS);
+
+    schedule::set_request_time(&mut params, at(1, 20), UtcOffset::UTC);
+
+    assert!(!params.subject_attrs.contains(&TimeUnit::Hour.attr(10)));
+    assert!(params.subject_attrs.contains(&TimeUnit::Hour.attr(20)));
+    assert!(params.subject_attrs.contains(&TimeUnit::Weekday.attr(0)));
+    assert!(params.subject_attrs.contains(&TROUSERS));
+}
+
+#[test]
+fn test_schedule_invalid() {
+    for src in [
+        "Time.hour in 9..25",
+        "Time.hour in 24",
+        "Time.hour in 9..=24",
+        "Time.weekday in monday",
+        "Time.weekday in 1..5",
+        "Time.hour in 9..9",
+    ] {
+        assert!(
+            matches!(
+                compile_error(src),
+                PolicyCompileErrorKind::InvalidSchedule(_)
+            ),
+            "{src}"
+        );
+    }
+
+    assert!(matches!(
+        compile_error("Time.minute in 0..30"),
+        PolicyCompileErrorKind::Parse(_)
+    ));
+}
@@ -0,0 +1,170 @@
+//! Caching of the policy engines of services.
+//!
+//! The engines are swapped atomically: an access control evaluation holds on to the engine it started with,
+//! and never observes a policy reload happening in the meantime.
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use arc_swap::ArcSwap;
+use authly_common::{
+    id::{AttrId, EntityId, PropId, ServiceId},
+    policy::engine::{AccessControlParams, PolicyEngine},
+};
+use time::OffsetDateTime;
+
+use crate::{
+    access_control::DecisionReason, metadata_cache::CacheGeneration, settings::DecisionCache,
+};
+
+type EngineMap = HashMap<ServiceId, Arc<PolicyEngine>>;
+
+/// The inputs of a local access control evaluation.
+///
+/// The inputs themselves are the key, rather than a digest of them,
+/// so that a hash collision can never return the decision of another request.
+#[derive(PartialEq, Eq, Hash)]
+struct DecisionKey {
+    generation: u64,
+    svc_eid: ServiceId,
+    subject_eids: Vec<(PropId, EntityId)>,
+    subject_attrs: Vec<AttrId>,
+    resource_attrs: Vec<AttrId>,
+}
+
+impl DecisionKey {
+    fn new(generation: CacheGeneration, svc_eid: ServiceId, params: &AccessControlParams) -> Self {
+        let mut subject_eids: Vec<_> = params
+            .subject_eids
+            .iter()
+            .map(|(prop_id, eid)| (*prop_id, *eid))
+            .collect();
+        subject_eids.sort();
+        let mut subject_attrs: Vec<_> = params.subject_attrs.iter().copied().collect();
+        subject_attrs.sort();
+        let mut resource_attrs: Vec<_> = params.resource_attrs.iter().copied().collect();
+        resource_attrs.sort();
+
+        Self {
+            generation: generation.0,
+            svc_eid,
+            subject_eids,
+            subject_attrs,
+            resource_attrs,
+        }
+    }
+}
+
+struct CachedDecision {
+    reason: DecisionReason,
+    expires_at: OffsetDateTime,
+}
+
+/// Loaded policy engines, per service.
+///
+/// Readers never block, a reload replaces the whole map of engines in one swap.
+/// The whole cache must be invalidated whenever a directory changes.
+#[derive(Default)]
+pub struct PolicyEngineCache {
+    generation: AtomicU64,
+    engines: ArcSwap<EngineMap>,
+    decisions: Mutex<HashMap<DecisionKey, CachedDecision>>,
+    /// Serializes writers, so that an engine loaded before an invalidation can't be cached after it
+    write_lock: Mutex<()>,
+}
+
+impl PolicyEngineCache {
+    pub fn generation(&self) -> CacheGeneration {
+        CacheGeneration(self.generation.load(Ordering::SeqCst))
+    }
+
+    pub fn get(&self, svc_eid: ServiceId) -> Option<Arc<PolicyEngine>> {
+        self.engines.load().get(&svc_eid).cloned()
+    }
+
+    pub fn insert(
+        &self,
+        generation: CacheGeneration,
+        svc_eid: ServiceId,
+        engine: Arc<PolicyEngine>,
+    ) {
+        let _write_lock = self.write_lock.lock().unwrap();
+        if self.generation.load(Ordering::SeqCst) != generation.0 {
+            return;
+        }
+
+        let mut engines = EngineMap::clone(&self.engines.load());
+        engines.insert(svc_eid, engine);
+        self.engines.store(Arc::new(engines));
+    }
+
+    /// Evict all engines
+    pub fn invalidate_all(&self) {
+        let _write_lock = self.write_lock.lock().unwrap();
+
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        self.engines.store(Default::default());
+        self.decisions.lock().unwrap().clear();
+    }
+
+    /// A decision made for the same inputs by an engine of the given generation, unless it has expired
+    pub fn get_decision(
+        &self,
+        generation: CacheGeneration,
+        svc_eid: ServiceId,
+        params: &AccessControlParams,
+        now: OffsetDateTime,
+    ) -> Option<DecisionReason> {
+        let key = DecisionKey::new(generation, svc_eid, params);
+        self.decisions
+            .lock()
+            .unwrap()
+            .get(&key)
+            .filter(|cached| cached.expires_at > now)
+            .map(|cached| cached.reason)
+    }
+
+    /// Cache a decision for the TTL of the decision cache, if it is enabled.
+    ///
+    /// The `generation` must be read before the engine making the decision was loaded.
+    /// A full cache first drops its expired decisions, and admits no more decisions until some expire.
+    pub fn insert_decision(
+        &self,
+        generation: CacheGeneration,
+        svc_eid: ServiceId,
+        params: &AccessControlParams,
+        reason: DecisionReason,
+        now: OffsetDateTime,
+        config: DecisionCache,
+    ) {
+        let Some(ttl) = config.ttl else {
+            return;
+        };
+
+        let _write_lock = self.write_lock.lock().unwrap();
+        if self.generation.load(Ordering::SeqCst) != generation.0 {
+            return;
+        }
+
+        let mut decisions = self.decisions.lock().unwrap();
+        if decisions.len() >= config.capacity {
+            decisions.retain(|_, cached| cached.expires_at > now);
+            if decisions.len() >= config.capacity {
+                return;
+            }
+        }
+
+        decisions.insert(
+            DecisionKey::new(generation, svc_eid, params),
+            CachedDecision {
+                reason,
+                expires_at: now + ttl,
+            },
+        );
+    }
+}
@@ -0,0 +1,99 @@
+//! Readiness of the local node to serve requests.
+//!
+//! A node is not ready before the documents in its document path are applied to the directory.
+//! On a follower that means replicated from the leader, so services never connect to an incomplete directory.
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use authly_common::id::DirectoryId;
+use axum::{extract::State, response::IntoResponse, Json};
+use http::StatusCode;
+use serde_json::json;
+use tracing::{info, warn};
+
+use crate::{ctx::GetDb, directory::DirectoryKind, repo::directory_repo::DbDirectory};
+
+/// The readiness gate of the node, closed until the node is ready
+#[derive(Clone, Default)]
+pub struct Readiness {
+    ready: Arc<AtomicBool>,
+}
+
+impl Readiness {
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::Relaxed)
+    }
+
+    pub fn set_ready(&self) {
+        self.ready.store(true, Ordering::Relaxed);
+    }
+}
+
+/// The readiness probe at `/health/readiness`, answering `503 Service Unavailable` until the node is ready
+pub fn router(readiness: Readiness) -> axum::Router {
+    axum::Router::new()
+        .route("/health/readiness", axum::routing::get(readiness_probe))
+        .with_state(readiness)
+}
+
+async fn readiness_probe(State(readiness): State<Readiness>) -> impl IntoResponse {
+    if readiness.is_ready() {
+        (StatusCode::OK, Json(json!({ "status": "UP" })))
+    } else {
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(json!({ "status": "DOWN" })),
+        )
+    }
+}
+
+/// A document that has to be applied before the node is ready
+pub struct ExpectedDocument {
+    pub dir_id: DirectoryId,
+    /// The hash of the document source
+    pub hash: [u8; 32],
+}
+
+/// Wait until every expected document is applied, in the version with the expected hash, then open the gate.
+///
+/// The directory is polled, since documents may be applied by the leader of the cluster.
+pub async fn open_when_documents_applied(
+    deps: &impl GetDb,
+    readiness: &Readiness,
+    expected: &[ExpectedDocument],
+    poll_interval: Duration,
+) {
+    loop {
+        match DbDirectory::query_by_kind(deps.get_db(), DirectoryKind::Document).await {
+            Ok(applied) => {
+                let pending = expected
+                    .iter()
+                    .filter(|doc| {
+                        !applied
+                            .iter()
+                            .any(|dir| dir.id == doc.dir_id && dir.hash == doc.hash)
+                    })
+                    .count();
+
+                if pending == 0 {
+                    info!("documents applied, ready");
+                    readiness.set_ready();
+                    return;
+                }
+
+                info!(pending, "waiting for documents to be applied");
+            }
+            Err(err) => {
+                warn!(?err, "unable to check applied documents");
+            }
+        }
+
+        tokio::time::sleep(poll_interval).await;
+    }
+}
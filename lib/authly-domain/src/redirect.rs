@@ -0,0 +1,227 @@
+//! Validation of the redirect targets of login flows.
+//!
+//! After a successful login the user is sent to the `next` target supplied with the login page.
+//! Since anyone can craft a login link, the target is checked against the `LOGIN_REDIRECT_ALLOWLIST` setting,
+//! so that Authly can't be used to send users to other sites.
+//! Absolute targets on the site Authly is served from are treated like relative ones.
+
+use std::str::FromStr;
+
+use reqwest::Url;
+
+/// The base used for resolving relative targets, it never leaves this module
+const RELATIVE_BASE: &str = "http://relative.invalid";
+
+/// An allowed redirect target.
+///
+/// Written either as a path prefix like `/app`, matching relative targets on the same site,
+/// or as a URL like `https://app.example.com/home`, matching targets with the same origin and path prefix.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct AllowedRedirect {
+    /// The origin of absolute targets, `None` for relative targets
+    origin: Option<Origin>,
+    path_prefix: String,
+}
+
+impl AllowedRedirect {
+    /// Whether the parsed target matches this entry
+    fn matches(&self, target: &Target, site: Option<&Origin>) -> bool {
+        let origin_matches = match (&self.origin, target) {
+            (None, Target::Relative(_)) => true,
+            (None, Target::Absolute(url)) => site == Some(&Origin::of(url)),
+            (Some(origin), Target::Absolute(url)) => &Origin::of(url) == origin,
+            (Some(_), Target::Relative(_)) => false,
+        };
+
+        origin_matches && path_has_prefix(target.url().path(), &self.path_prefix)
+    }
+}
+
+impl FromStr for AllowedRedirect {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match parse_target(s).ok_or_else(|| anyhow::anyhow!("invalid redirect target `{s}`"))? {
+            Target::Relative(url) => Ok(Self {
+                origin: None,
+                path_prefix: url.path().to_string(),
+            }),
+            Target::Absolute(url) => Ok(Self {
+                origin: Some(Origin::of(&url)),
+                path_prefix: url.path().to_string(),
+            }),
+        }
+    }
+}
+
+/// The scheme, host and port of a URL
+#[derive(Clone, PartialEq, Eq, Debug)]
+struct Origin(String, String, Option<u16>);
+
+impl Origin {
+    fn of(url: &Url) -> Self {
+        Self(
+            url.scheme().to_string(),
+            url.host_str().unwrap_or_default().to_string(),
+            url.port_or_known_default(),
+        )
+    }
+}
+
+enum Target {
+    Relative(Url),
+    Absolute(Url),
+}
+
+impl Target {
+    fn url(&self) -> &Url {
+        match self {
+            Self::Relative(url) | Self::Absolute(url) => url,
+        }
+    }
+}
+
+fn parse_target(target: &str) -> Option<Target> {
+    // browsers ignore some characters inside URLs, and treat backslashes as slashes
+    if target.is_empty() || target.chars().any(|c| c.is_control() || c == '\\') {
+        return None;
+    }
+
+    if target.starts_with('/') {
+        // protocol-relative targets, like `//evil.com`, are other sites
+        if target.starts_with("//") {
+            return None;
+        }
+
+        return Url::parse(RELATIVE_BASE)
+            .ok()?
+            .join(target)
+            .ok()
+            .map(Target::Relative);
+    }
+
+    let url = Url::parse(target).ok()?;
+    if !matches!(url.scheme(), "http" | "https") || !url.username().is_empty() {
+        return None;
+    }
+
+    Some(Target::Absolute(url))
+}
+
+/// Whether the path is the prefix itself or lies below it
+fn path_has_prefix(path: &str, prefix: &str) -> bool {
+    let prefix = prefix.trim_end_matches('/');
+    match path.strip_prefix(prefix) {
+        Some(rest) => rest.is_empty() || rest.starts_with('/') || prefix.is_empty(),
+        None => false,
+    }
+}
+
+/// Whether a redirect target is allowed by the allowlist.
+///
+/// `site` is the public base URL of Authly, when known.
+pub fn is_allowed_redirect(
+    allowlist: &[AllowedRedirect],
+    site: Option<&str>,
+    target: &str,
+) -> bool {
+    let Some(target) = parse_target(target) else {
+        return false;
+    };
+    let site = site
+        .and_then(|site| Url::parse(site).ok())
+        .map(|site| Origin::of(&site));
+
+    allowlist
+        .iter()
+        .any(|allowed| allowed.matches(&target, site.as_ref()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SITE: Option<&str> = Some("https://authly.example.com/");
+
+    fn allowlist(entries: &[&str]) -> Vec<AllowedRedirect> {
+        entries.iter().map(|entry| entry.parse().unwrap()).collect()
+    }
+
+    fn allowlist_default() -> Vec<AllowedRedirect> {
+        crate::settings::Settings::default().login_redirect_allowlist
+    }
+
+    #[test]
+    fn test_allowed_redirects() {
+        let allowlist = allowlist(&["/app", "https://shop.example.com/checkout"]);
+
+        assert!(is_allowed_redirect(&allowlist, SITE, "/app"));
+        assert!(is_allowed_redirect(&allowlist, SITE, "/app/page?x=1#top"));
+        assert!(is_allowed_redirect(
+            &allowlist,
+            SITE,
+            "https://authly.example.com/app/page"
+        ));
+        assert!(is_allowed_redirect(
+            &allowlist,
+            SITE,
+            "https://shop.example.com/checkout/cart"
+        ));
+        assert!(is_allowed_redirect(
+            &allowlist,
+            SITE,
+            "https://shop.example.com:443/checkout"
+        ));
+
+        // defaults to any path on the same site
+        let default = allowlist_default();
+        assert!(is_allowed_redirect(&default, SITE, "/anywhere"));
+        assert!(is_allowed_redirect(
+            &default,
+            SITE,
+            "https://authly.example.com/anywhere"
+        ));
+    }
+
+    #[test]
+    fn test_rejected_redirects() {
+        let allowlist = allowlist(&["/app", "https://shop.example.com/checkout"]);
+
+        for target in [
+            "",
+            "https://evil.com/app",
+            "//evil.com/app",
+            "/\\evil.com/app",
+            "\\\\evil.com",
+            "javascript:alert(1)",
+            "/application",
+            "/app/../admin",
+            "/admin",
+            "http://shop.example.com/checkout",
+            "https://shop.example.com.evil.com/checkout",
+            "https://shop.example.com@evil.com/checkout",
+            "https://shop.example.com/",
+            "https://authly.example.com.evil.com/app",
+            "/app\n/x",
+        ] {
+            assert!(
+                !is_allowed_redirect(&allowlist, SITE, target),
+                "{target:?} was allowed"
+            );
+        }
+
+        // absolute targets on the same site are only allowed when the site is known
+        assert!(!is_allowed_redirect(
+            &allowlist,
+            None,
+            "https://authly.example.com/app"
+        ));
+    }
+
+    #[test]
+    fn test_invalid_allowlist_entries() {
+        for entry in ["", "//evil.com", "app", "ftp://files.example.com"] {
+            assert!(entry.parse::<AllowedRedirect>().is_err(), "{entry:?}");
+        }
+    }
+}
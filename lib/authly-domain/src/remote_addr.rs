@@ -1,4 +1,8 @@
-use std::net::SocketAddr;
+use std::{
+    fmt::Display,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    str::FromStr,
+};
 
 #[derive(Clone, Debug)]
 pub struct RemoteAddr(pub SocketAddr);
@@ -6,3 +10,101 @@ pub struct RemoteAddr(pub SocketAddr);
 pub fn remote_addr_middleware<B>(req: &mut http::Request<B>, addr: SocketAddr) {
     req.extensions_mut().insert(RemoteAddr(addr));
 }
+
+/// An IP network in CIDR notation, like `10.0.0.0/8` or `fd00::/8`
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct IpCidr {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl IpCidr {
+    /// The address of the network, with the host bits cleared
+    pub fn network(&self) -> IpAddr {
+        mask(self.addr, self.prefix_len)
+    }
+
+    pub fn prefix_len(&self) -> u8 {
+        self.prefix_len
+    }
+
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        let addr = addr.to_canonical();
+        addr.is_ipv4() == self.addr.is_ipv4() && mask(addr, self.prefix_len) == self.network()
+    }
+}
+
+impl FromStr for IpCidr {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        let (addr, prefix_len) = match value.trim().split_once('/') {
+            Some((addr, prefix_len)) => (addr.parse::<IpAddr>()?, Some(prefix_len.parse::<u8>()?)),
+            None => (value.trim().parse::<IpAddr>()?, None),
+        };
+        let addr = addr.to_canonical();
+        let max_len = max_prefix_len(addr);
+        let prefix_len = prefix_len.unwrap_or(max_len);
+
+        if prefix_len > max_len {
+            return Err(anyhow::anyhow!("prefix length must be at most {max_len}"));
+        }
+
+        Ok(Self { addr, prefix_len })
+    }
+}
+
+impl Display for IpCidr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.network(), self.prefix_len)
+    }
+}
+
+/// The number of bits in an address
+pub fn max_prefix_len(addr: IpAddr) -> u8 {
+    match addr {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    }
+}
+
+/// Clear the host bits of an address
+pub fn mask(addr: IpAddr, prefix_len: u8) -> IpAddr {
+    match addr {
+        IpAddr::V4(addr) => {
+            let bits = u32::MAX.checked_shl(32 - prefix_len as u32).unwrap_or(0);
+            IpAddr::V4(Ipv4Addr::from(u32::from(addr) & bits))
+        }
+        IpAddr::V6(addr) => {
+            let bits = u128::MAX.checked_shl(128 - prefix_len as u32).unwrap_or(0);
+            IpAddr::V6(Ipv6Addr::from(u128::from(addr) & bits))
+        }
+    }
+}
+
+/// Find the address of the client behind a chain of proxies.
+///
+/// `forwarded_for` is an `x-forwarded-for` value, where each proxy appended the address it received the request from.
+/// The chain is followed backwards from the nearest proxy, skipping trusted proxies, and the first untrusted address is the client.
+/// The caller must trust the sender of the value to have appended to it correctly.
+/// Returns `None` if the value is malformed.
+pub fn forwarded_client_ip(forwarded_for: &str, trusted_proxies: &[IpCidr]) -> Option<IpAddr> {
+    let mut client_ip = None;
+
+    for entry in forwarded_for.rsplit(',') {
+        let entry = entry.trim();
+        let addr = entry
+            .parse::<IpAddr>()
+            .or_else(|_| entry.parse::<SocketAddr>().map(|addr| addr.ip()))
+            .ok()?
+            .to_canonical();
+
+        client_ip = Some(addr);
+
+        if !trusted_proxies.iter().any(|proxy| proxy.contains(addr)) {
+            break;
+        }
+    }
+
+    client_ip
+}
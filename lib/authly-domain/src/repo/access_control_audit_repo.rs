@@ -0,0 +1,240 @@
+use authly_common::id::{AttrId, EntityId, Id128DynamicArrayConv, PolicyId, ServiceId};
+use authly_db::{param::ToBlob, params, Db, DbError, DbResult, FromRow, Row, TryFromRow};
+use indoc::{formatdoc, indoc};
+
+use crate::encryption::AesKey;
+
+/// How many times appending a record is retried when another record was appended concurrently
+const APPEND_ATTEMPTS: usize = 10;
+
+const COLUMNS: &str =
+    "id, created_at, svc_eid, subject_eid, resource_attrs, allowed, reason, policy_id, prev_hash, hash";
+
+/// A recorded access control decision
+#[derive(Debug)]
+pub struct DbAccessControlAudit {
+    /// Assigned by the database, in the order records are written
+    pub id: i64,
+    pub created_at: time::OffsetDateTime,
+    pub svc_eid: ServiceId,
+    pub subject_eid: Option<EntityId>,
+    pub resource_attrs: Vec<AttrId>,
+    pub allowed: bool,
+    /// The [crate::access_control::DecisionReason] code
+    pub reason: String,
+    /// The policy that decided the outcome, if any
+    pub policy_id: Option<PolicyId>,
+    /// The `hash` of the previous record, assigned when appended
+    pub prev_hash: Vec<u8>,
+    /// The hash of this record and `prev_hash`, assigned when appended.
+    /// Empty for records written before the chain was introduced.
+    pub hash: Vec<u8>,
+}
+
+impl TryFromRow for DbAccessControlAudit {
+    type Error = DbError;
+
+    fn try_from_row(row: &mut impl Row) -> Result<Self, Self::Error> {
+        Ok(Self {
+            id: row.get_int("id"),
+            created_at: row.get_datetime("created_at")?,
+            svc_eid: row.get_id("svc_eid"),
+            subject_eid: EntityId::try_from_bytes_dynamic(&row.get_blob("subject_eid")),
+            resource_attrs: row.get_ids_concatenated("resource_attrs").collect(),
+            allowed: row.get_int("allowed") != 0,
+            reason: row.get_text("reason"),
+            policy_id: PolicyId::try_from_bytes_dynamic(&row.get_blob("policy_id")),
+            prev_hash: row.get_blob("prev_hash"),
+            hash: row.get_blob("hash"),
+        })
+    }
+}
+
+impl DbAccessControlAudit {
+    /// List the most recent records, optionally for one service, newest first
+    pub async fn query_recent(
+        deps: &impl Db,
+        svc_eid: Option<ServiceId>,
+        limit: usize,
+    ) -> DbResult<Vec<Self>> {
+        let (filter, params) = match svc_eid {
+            Some(svc_eid) => (
+                "WHERE svc_eid = $2",
+                params!(limit as i64, svc_eid.to_blob()),
+            ),
+            None => ("", params!(limit as i64)),
+        };
+
+        deps.query_filter_map(
+            formatdoc! {
+                "
+                SELECT {COLUMNS}
+                FROM access_control_audit
+                {filter}
+                ORDER BY id DESC
+                LIMIT $1
+                "
+            }
+            .into(),
+            params,
+        )
+        .await
+    }
+
+    /// List records after the given `id`, oldest first
+    pub async fn query_after(deps: &impl Db, after_id: i64, limit: usize) -> DbResult<Vec<Self>> {
+        deps.query_filter_map(
+            formatdoc! {
+                "
+                SELECT {COLUMNS}
+                FROM access_control_audit
+                WHERE id > $1
+                ORDER BY id
+                LIMIT $2
+                "
+            }
+            .into(),
+            params!(after_id, limit as i64),
+        )
+        .await
+    }
+
+    /// Append the record to the end of the chain, the `id` and hashes are assigned here.
+    pub async fn append(&self, deps: &impl Db, key: &AesKey) -> DbResult<()> {
+        Self::append_all(deps, key, std::slice::from_ref(self)).await
+    }
+
+    /// Append the records to the end of the chain in one transaction, in the given order.
+    ///
+    /// The records are only written if the chain has not grown since its last hash was read,
+    /// so concurrent writers, also on other nodes, never fork the chain.
+    pub async fn append_all(deps: &impl Db, key: &AesKey, records: &[Self]) -> DbResult<()> {
+        struct LastHash(Vec<u8>);
+
+        impl FromRow for LastHash {
+            fn from_row(row: &mut impl Row) -> Self {
+                Self(row.get_blob("hash"))
+            }
+        }
+
+        if records.is_empty() {
+            return Ok(());
+        }
+
+        for _ in 0..APPEND_ATTEMPTS {
+            let mut prev_hash = deps
+                .query_map_opt::<LastHash>(
+                    "SELECT hash FROM access_control_audit ORDER BY id DESC LIMIT 1".into(),
+                    params!(),
+                )
+                .await?
+                .map(|last| last.0)
+                .unwrap_or_default();
+
+            let mut statements = Vec::with_capacity(records.len());
+            for record in records {
+                let hash = record.chain_hash(key, &prev_hash).to_vec();
+                let resource_attrs: Vec<u8> = record
+                    .resource_attrs
+                    .iter()
+                    .flat_map(|attr| attr.to_blob())
+                    .collect();
+
+                // each insert links to the one before it, so if the first is skipped, they all are
+                statements.push((
+                    indoc! {
+                        "
+                        INSERT INTO access_control_audit
+                            (created_at, svc_eid, subject_eid, resource_attrs, allowed, reason, policy_id, prev_hash, hash)
+                        SELECT $1, $2, $3, $4, $5, $6, $7, $8, $9
+                        WHERE COALESCE((SELECT hash FROM access_control_audit ORDER BY id DESC LIMIT 1), X'') = $8
+                        "
+                    }
+                    .into(),
+                    params!(
+                        record.created_at.unix_timestamp(),
+                        record.svc_eid.to_blob(),
+                        record.subject_eid.map(|eid| eid.to_blob()).unwrap_or_default(),
+                        resource_attrs,
+                        record.allowed as i64,
+                        record.reason.clone(),
+                        record.policy_id.map(|id| id.to_blob()).unwrap_or_default(),
+                        std::mem::replace(&mut prev_hash, hash.clone()),
+                        hash
+                    ),
+                ));
+            }
+
+            let appended: usize = deps
+                .transact(statements)
+                .await?
+                .into_result()?
+                .into_iter()
+                .sum();
+            if appended > 0 {
+                return Ok(());
+            }
+        }
+
+        Err(DbError::Other("audit chain contention".into()))
+    }
+
+    /// The hash linking this record to the chain, covering every field except the `id`.
+    ///
+    /// The hash is keyed, so that a modified record can't be given a matching hash without the key.
+    pub fn chain_hash(&self, key: &AesKey, prev_hash: &[u8]) -> [u8; 32] {
+        let mut hasher = key.keyed_hasher();
+        let mut field = |bytes: &[u8]| {
+            hasher.update(&(bytes.len() as u64).to_le_bytes());
+            hasher.update(bytes);
+        };
+
+        field(prev_hash);
+        field(&self.created_at.unix_timestamp().to_le_bytes());
+        field(&self.svc_eid.to_blob());
+        field(
+            &self
+                .subject_eid
+                .map(|eid| eid.to_blob())
+                .unwrap_or_default(),
+        );
+        field(
+            &self
+                .resource_attrs
+                .iter()
+                .flat_map(|attr| attr.to_blob())
+                .collect::<Vec<u8>>(),
+        );
+        field(&[self.allowed as u8]);
+        field(self.reason.as_bytes());
+        field(&self.policy_id.map(|id| id.to_blob()).unwrap_or_default());
+
+        hasher.finalize().into()
+    }
+}
+
+/// Delete audit records, of access control decisions and of directory changes, written before `before`.
+///
+/// Both logs are purged in one transaction, together with the admin events written in the same period.
+/// Returns the number of deleted audit records, not counting the admin events.
+pub async fn purge_audit_before(deps: &impl Db, before: time::OffsetDateTime) -> DbResult<usize> {
+    let deleted = deps
+        .transact(vec![
+            (
+                "DELETE FROM access_control_audit WHERE created_at < $1".into(),
+                params!(before.unix_timestamp()),
+            ),
+            (
+                "DELETE FROM directory_audit WHERE upd < $1".into(),
+                params!(before.unix_timestamp()),
+            ),
+            (
+                "DELETE FROM admin_event WHERE created_at < $1".into(),
+                params!(before.unix_timestamp()),
+            ),
+        ])
+        .await?
+        .into_result()?;
+
+    Ok(deleted.into_iter().take(2).sum())
+}
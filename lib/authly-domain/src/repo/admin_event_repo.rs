@@ -0,0 +1,59 @@
+use authly_db::{params, Db, DbError, DbResult, Row, TryFromRow};
+use indoc::indoc;
+
+use crate::bus::admin_events::{AdminEvent, AdminEventKind};
+
+impl TryFromRow for AdminEvent {
+    type Error = DbError;
+
+    fn try_from_row(row: &mut impl Row) -> Result<Self, Self::Error> {
+        Ok(Self {
+            seq: row.get_int("seq"),
+            kind: row.get_text("kind"),
+            detail: serde_json::from_str(&row.get_text("detail"))
+                .map_err(|err| DbError::Other(format!("invalid event detail: {err}")))?,
+            created_at: row.get_datetime("created_at")?,
+        })
+    }
+}
+
+/// Append an event to the outbox
+pub async fn insert_admin_event(
+    deps: &impl Db,
+    kind: AdminEventKind,
+    detail: &serde_json::Value,
+    now: time::OffsetDateTime,
+) -> DbResult<()> {
+    deps.execute(
+        "INSERT INTO admin_event (created_at, kind, detail) VALUES ($1, $2, $3)".into(),
+        params!(
+            now.unix_timestamp(),
+            kind.code().to_string(),
+            detail.to_string()
+        ),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// List outbox events after the given sequence number, oldest first
+pub async fn list_admin_events(
+    deps: &impl Db,
+    after_seq: i64,
+    limit: usize,
+) -> DbResult<Vec<AdminEvent>> {
+    deps.query_filter_map(
+        indoc! {
+            "
+            SELECT seq, created_at, kind, detail FROM admin_event
+            WHERE seq > $1
+            ORDER BY seq
+            LIMIT $2
+            "
+        }
+        .into(),
+        params!(after_seq, limit as i64),
+    )
+    .await
+}
@@ -0,0 +1,114 @@
+use std::borrow::Cow;
+
+use authly_db::{params, Db, DbResult, FromRow, Row};
+use indoc::indoc;
+use tracing::info;
+
+/// Write a consistent snapshot of the whole database into a new SQLite file at `path`.
+///
+/// The snapshot is taken with `VACUUM INTO`, which only reads the source database,
+/// so it runs against the local node and does not go through the write path.
+pub async fn snapshot_into(deps: &impl Db, path: &str) -> DbResult<()> {
+    struct NoRow;
+
+    impl FromRow for NoRow {
+        fn from_row(_row: &mut impl Row) -> Self {
+            Self
+        }
+    }
+
+    deps.query_map::<NoRow>("VACUUM INTO $1".into(), params!(path))
+        .await?;
+
+    Ok(())
+}
+
+/// Tables that are populated by triggers on other tables
+const TRIGGERED_TABLES: &[&str] = &["admin_event", "ent_attr_event"];
+
+/// List the Authly tables of the local database schema, excluding SQLite and migration bookkeeping tables.
+pub async fn list_tables(deps: &impl Db) -> DbResult<Vec<String>> {
+    struct TableName(String);
+
+    impl FromRow for TableName {
+        fn from_row(row: &mut impl Row) -> Self {
+            Self(row.get_text("name"))
+        }
+    }
+
+    Ok(deps
+        .query_map::<TableName>(
+            indoc! {
+                r"
+                SELECT name FROM sqlite_schema
+                WHERE type = 'table' AND name NOT LIKE 'sqlite\_%' ESCAPE '\' AND name NOT LIKE '\_%' ESCAPE '\'
+                ORDER BY name
+                "
+            }
+            .into(),
+            params!(),
+        )
+        .await?
+        .into_iter()
+        .map(|name| name.0)
+        .collect())
+}
+
+/// Replace the contents of every Authly table with the contents of the snapshot at `path`.
+///
+/// The snapshot must have been produced by the same schema version as the local database.
+/// All rows are replaced in one transaction with deferred foreign keys, so table order doesn't matter.
+///
+/// The snapshot is attached to the database connection, so `deps` must be a node-local database
+/// with a single connection. A replicated database would attach the file on other nodes,
+/// and a connection pool could run the transaction on a connection without the attachment.
+pub async fn restore_from(deps: &impl Db, path: &str) -> DbResult<()> {
+    let tables = list_tables(deps).await?;
+
+    deps.execute("ATTACH DATABASE $1 AS backup".into(), params!(path))
+        .await?;
+
+    let result = restore_attached(deps, &tables).await;
+
+    deps.execute("DETACH DATABASE backup".into(), params!())
+        .await?;
+
+    result
+}
+
+async fn restore_attached(deps: &impl Db, tables: &[String]) -> DbResult<()> {
+    let mut stmts: Vec<(Cow<'static, str>, _)> =
+        vec![("PRAGMA defer_foreign_keys = ON".into(), params!())];
+
+    for table in tables {
+        stmts.push((format!("DELETE FROM main.{table}").into(), params!()));
+    }
+
+    // Tables written by triggers go last, replacing whatever the triggers wrote during the restore
+    let (triggered, tables): (Vec<_>, Vec<_>) = tables
+        .iter()
+        .partition(|table| TRIGGERED_TABLES.contains(&table.as_str()));
+
+    for table in &tables {
+        stmts.push((
+            format!("INSERT INTO main.{table} SELECT * FROM backup.{table}").into(),
+            params!(),
+        ));
+    }
+
+    for table in triggered {
+        stmts.push((format!("DELETE FROM main.{table}").into(), params!()));
+        stmts.push((
+            format!("INSERT INTO main.{table} SELECT * FROM backup.{table}").into(),
+            params!(),
+        ));
+    }
+
+    for result in deps.transact(stmts).await? {
+        result?;
+    }
+
+    info!(tables = tables.len(), "restored tables from backup");
+
+    Ok(())
+}
@@ -1,4 +1,4 @@
-use std::{borrow::Cow, collections::HashMap, time::Duration};
+use std::{borrow::Cow, collections::HashMap, sync::Arc, time::Duration};
 
 use aes_gcm_siv::{
     aead::{Aead, Nonce},
@@ -14,13 +14,17 @@ use tracing::{debug, info};
 
 use crate::{
     cert::{authly_ca, client_cert, key_pair},
-    ctx::{GetDb, GetDecryptedDeks},
+    ctx::{GetDb, GetDecryptedDeks, GetSettings},
     encryption::{
         random_nonce, CryptoError, DecryptedDeks, EncryptedDek, EncryptedObjIdent, MasterVersion,
     },
     id::BuiltinProp,
-    instance::{AuthlyId, AuthlyInstance},
+    id_generator::IdGenerator,
+    instance::{AuthlyId, AuthlyInstance, InstanceKeySource},
+    repo::token_signing_repo,
+    signer::{self, InMemorySigner},
     tls::{AuthlyCert, AuthlyCertKind},
+    token_signing::TokenSigningKey,
     IsLeaderDb,
 };
 
@@ -63,8 +67,11 @@ pub async fn load_authly_instance(
     is_leader: IsLeaderDb,
     db: &impl Db,
     deks: &DecryptedDeks,
+    key_source: InstanceKeySource,
+    id_generator: &dyn IdGenerator,
 ) -> Result<AuthlyInstance, CryptoError> {
-    let authly_id = load_or_generate_authly_id(is_leader, db, deks).await?;
+    let authly_id =
+        load_or_generate_authly_id(is_leader, db, deks, &key_source, id_generator).await?;
     let mut certs = load_certs(db).await?;
 
     let missing_certs = check_missing_certs(&authly_id, &certs);
@@ -74,29 +81,38 @@ pub async fn load_authly_instance(
             for kind in missing_certs {
                 match kind {
                     AuthlyCertKind::Ca => {
-                        let certificate = authly_ca().self_signed(&authly_id.private_key).unwrap();
+                        let certificate =
+                            signer::self_sign(authly_id.signer.as_ref(), &authly_ca())
+                                .await
+                                .map_err(CryptoError::Crypto)?;
                         let cert = AuthlyCert {
                             kind: AuthlyCertKind::Ca,
                             certifies: authly_id.eid,
                             signed_by: authly_id.eid,
                             params: authly_ca(), // Use the original CA params
-                            der: certificate.der().clone(),
+                            der: certificate,
                         };
 
                         save_tls_cert(&cert, db).await?;
                         certs.push(cert);
                     }
                     AuthlyCertKind::Identity => {
-                        let certificate =
-                            client_cert("authly", authly_id.eid, time::Duration::days(365 * 100))
-                                .self_signed(&authly_id.private_key)
-                                .unwrap();
+                        let certificate = signer::self_sign(
+                            authly_id.signer.as_ref(),
+                            &client_cert("authly", authly_id.eid, time::Duration::days(365 * 100)),
+                        )
+                        .await
+                        .map_err(CryptoError::Crypto)?;
                         let cert = AuthlyCert {
                             kind: AuthlyCertKind::Identity,
                             certifies: authly_id.eid,
                             signed_by: authly_id.eid,
-                            params: client_cert("authly", authly_id.eid, time::Duration::days(365 * 100)),
-                            der: certificate.der().clone(),
+                            params: client_cert(
+                                "authly",
+                                authly_id.eid,
+                                time::Duration::days(365 * 100),
+                            ),
+                            der: certificate,
                         };
 
                         save_tls_cert(&cert, db).await?;
@@ -117,32 +133,61 @@ pub async fn load_authly_instance(
         }
     }
 
-    Ok(AuthlyInstance::new(authly_id, certs))
+    // Without a dedicated token signing key, the instance runs in compatibility mode
+    Ok(AuthlyInstance::new(authly_id, certs)
+        .with_key_source(key_source)
+        .with_token_signing_keys(token_signing_repo::load_token_signing_keys(db, deks).await?))
 }
 
 async fn load_or_generate_authly_id(
     is_leader: IsLeaderDb,
     db: &impl Db,
     deks: &DecryptedDeks,
+    key_source: &InstanceKeySource,
+    id_generator: &dyn IdGenerator,
 ) -> Result<AuthlyId, CryptoError> {
-    match try_load_authly_id(db, deks).await? {
+    match try_load_authly_id(db, deks, key_source).await? {
         Some(authly_id) => Ok(authly_id),
         None => {
             if is_leader.0 {
-                let eid = ServiceId::random();
-                let private_key = key_pair();
+                let eid: ServiceId = id_generator.generate();
 
                 debug!("initializing new authly ID");
 
-                save_instance(eid, &private_key, db, deks).await?;
+                // New installations get a token signing key separate from the CA.
+                // It's saved first, so that followers waiting for the Authly ID also find it.
+                token_signing_repo::save_token_signing_key(
+                    db,
+                    &TokenSigningKey::generate(time::OffsetDateTime::now_utc()),
+                    deks,
+                )
+                .await?;
+
+                match key_source {
+                    InstanceKeySource::Database => {
+                        let private_key = key_pair();
+                        save_instance(eid, &private_key, db, deks).await?;
+
+                        Ok(AuthlyId {
+                            eid,
+                            signer: Arc::new(InMemorySigner::new(private_key)),
+                        })
+                    }
+                    InstanceKeySource::External(signer) => {
+                        save_external_instance(eid, db).await?;
 
-                Ok(AuthlyId { eid, private_key })
+                        Ok(AuthlyId {
+                            eid,
+                            signer: signer.clone(),
+                        })
+                    }
+                }
             } else {
                 loop {
                     info!("waiting for leader to generate Authly ID");
                     tokio::time::sleep(Duration::from_secs(1)).await;
 
-                    if let Some(local_ca) = try_load_authly_id(db, deks).await? {
+                    if let Some(local_ca) = try_load_authly_id(db, deks, key_source).await? {
                         return Ok(local_ca);
                     }
                 }
@@ -154,14 +199,15 @@ async fn load_or_generate_authly_id(
 async fn try_load_authly_id(
     deps: &impl Db,
     deks: &DecryptedDeks,
+    key_source: &InstanceKeySource,
 ) -> Result<Option<AuthlyId>, CryptoError> {
-    struct Output(ServiceId, [u8; 12], Vec<u8>);
+    struct Output(ServiceId, Vec<u8>, Vec<u8>);
 
     impl FromRow for Output {
         fn from_row(row: &mut impl Row) -> Self {
             Self(
                 row.get_id("eid"),
-                row.get_blob_array("private_key_nonce"),
+                row.get_blob("private_key_nonce"),
                 row.get_blob("private_key_ciph"),
             )
         }
@@ -177,13 +223,37 @@ async fn try_load_authly_id(
         return Ok(None);
     };
 
+    // An empty private key means the key is held outside the database
+    match (private_key_ciph.is_empty(), key_source) {
+        (true, InstanceKeySource::External(signer)) => {
+            return Ok(Some(AuthlyId {
+                eid,
+                signer: signer.clone(),
+            }));
+        }
+        (true, InstanceKeySource::Database) => {
+            return Err(CryptoError::Crypto(anyhow!(
+                "FATAL: The instance key is held externally, but no external signer is configured"
+            )));
+        }
+        (false, InstanceKeySource::External(_)) => {
+            return Err(CryptoError::Crypto(anyhow!(
+                "FATAL: The instance key is stored in the database, moving it to an external signer is not supported"
+            )));
+        }
+        (false, InstanceKeySource::Database) => {}
+    }
+
+    let nonce = Nonce::<Aes256GcmSiv>::from_exact_iter(nonce)
+        .ok_or_else(|| CryptoError::Crypto(anyhow!("invalid instance key nonce")))?;
+
     let dek = deks
         .get(BuiltinProp::AuthlyInstance.into())
         .map_err(CryptoError::Crypto)?;
 
     let private_key_plaintext = dek
         .aes()
-        .decrypt(&nonce.into(), private_key_ciph.as_ref())
+        .decrypt(&nonce, private_key_ciph.as_ref())
         .context("FATAL: Encryption key has changed, unable to decrypt private key")
         .map_err(CryptoError::Crypto)?;
 
@@ -192,7 +262,10 @@ async fn try_load_authly_id(
 
     Ok(Some(AuthlyId {
         eid,
-        private_key: KeyPair::from_der_and_sign_algo(&private_key_der, &PKCS_ECDSA_P256_SHA256)?,
+        signer: Arc::new(InMemorySigner::new(KeyPair::from_der_and_sign_algo(
+            &private_key_der,
+            &PKCS_ECDSA_P256_SHA256,
+        )?)),
     }))
 }
 
@@ -229,6 +302,24 @@ pub async fn save_instance(
     Ok(())
 }
 
+/// Save an instance whose private key is held by an external signer
+async fn save_external_instance(eid: ServiceId, db: &impl Db) -> Result<(), CryptoError> {
+    db.execute(
+        indoc! {
+            "
+            INSERT INTO authly_instance (key, eid, private_key_nonce, private_key_ciph)
+            VALUES ('self', $1, x'', x'')
+            ON CONFLICT DO UPDATE SET eid = $1
+            "
+        }
+        .into(),
+        params!(eid.to_blob()),
+    )
+    .await?;
+
+    Ok(())
+}
+
 async fn load_certs(db: &impl Db) -> Result<Vec<AuthlyCert>, CryptoError> {
     Ok(db
         .query_filter_map(
@@ -439,8 +530,9 @@ impl EncryptedObjIdent {
     }
 }
 
+/// Look up the object owning an identity, after normalizing it
 pub async fn lookup_obj_ident(
-    deps: &(impl GetDb + GetDecryptedDeks),
+    deps: &(impl GetDb + GetDecryptedDeks + GetSettings),
     prop_id: PropId,
     ident: &str,
 ) -> Result<Option<AnyId>, CryptoError> {
@@ -453,6 +545,10 @@ pub async fn lookup_obj_ident(
     }
 
     let ident_fingerprint = {
+        let ident = deps
+            .get_settings()
+            .ident_normalization
+            .normalize(prop_id, ident);
         let deks = deps.get_decrypted_deks();
         let dek = deks.get(prop_id).map_err(CryptoError::Crypto)?;
 
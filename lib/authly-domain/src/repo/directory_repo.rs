@@ -1,7 +1,7 @@
-use std::collections::HashMap;
+use std::{borrow::Cow, collections::HashMap};
 
-use authly_common::id::{AnyId, AttrId, DirectoryId, PolicyId, PropId, ServiceId};
-use authly_db::{param::ToBlob, params, Db, DbResult, FromRow, Row, TryFromRow};
+use authly_common::id::{AnyId, AttrId, DirectoryId, EntityId, PolicyId, PropId, ServiceId};
+use authly_db::{param::ToBlob, params, Db, DbError, DbResult, FromRow, Params, Row, TryFromRow};
 use indoc::indoc;
 use serde::{de::value::StringDeserializer, Deserialize};
 
@@ -132,6 +132,58 @@ impl DbDirectoryPolicy {
     }
 }
 
+/// An entry in the directory audit log
+pub struct DbDirectoryAudit {
+    pub dir_id: DirectoryId,
+    pub dir_label: Option<String>,
+    pub upd: time::OffsetDateTime,
+    pub updated_by: EntityId,
+}
+
+impl TryFromRow for DbDirectoryAudit {
+    type Error = DbError;
+
+    fn try_from_row(row: &mut impl Row) -> Result<Self, Self::Error> {
+        Ok(Self {
+            dir_id: row.get_id("id"),
+            dir_label: row.get_opt_text("label"),
+            upd: row.get_datetime("upd")?,
+            updated_by: row.get_id("updated_by_eid"),
+        })
+    }
+}
+
+impl DbDirectoryAudit {
+    /// List the most recent audit entries across all directories, newest first
+    pub async fn query_recent(deps: &impl Db, limit: usize) -> DbResult<Vec<Self>> {
+        deps.query_filter_map(
+            indoc! {
+                "
+                SELECT d.id, d.label, a.upd, a.updated_by_eid
+                FROM directory_audit a
+                JOIN directory d ON d.key = a.dir_key
+                ORDER BY a.upd DESC
+                LIMIT $1
+                "
+            }
+            .into(),
+            params!(limit as i64),
+        )
+        .await
+    }
+}
+
+pub fn insert_directory_audit_stmt<D: Db>(
+    dir_key: DirKey,
+    updated_by: EntityId,
+    now: time::OffsetDateTime,
+) -> (Cow<'static, str>, Params<D>) {
+    (
+        "INSERT INTO directory_audit (dir_key, upd, updated_by_eid) VALUES ($1, $2, $3)".into(),
+        params!(dir_key.0, now.unix_timestamp(), updated_by.to_blob()),
+    )
+}
+
 pub async fn list_namespace_properties(
     deps: &impl Db,
     dir_key: DirKey,
@@ -2,7 +2,7 @@ use std::{borrow::Cow, ops::Range};
 
 use authly_common::id::{AnyId, AttrId, DirectoryId, PolicyId, PropId, ServiceId};
 use authly_db::{literal::Literal, param::ToBlob, params, Db, DbError};
-use indoc::indoc;
+use indoc::{formatdoc, indoc};
 use itertools::Itertools;
 use serde_spanned::Spanned;
 use tracing::info;
@@ -17,7 +17,11 @@ use crate::{
         error::DocError,
     },
     encryption::{DecryptedDeks, EncryptedObjIdent},
-    repo::{service_repo::PropertyKind, Identified},
+    repo::{
+        entity_event_repo, object_repo,
+        service_repo::{Inheritance, PropertyKind},
+        Identified,
+    },
     settings::Setting,
 };
 
@@ -48,8 +52,9 @@ impl DocumentTransaction {
         mut self,
         db: &D,
         deks: &DecryptedDeks,
+        now: time::OffsetDateTime,
     ) -> Result<(), DocumentDbTxnError> {
-        let now = time::OffsetDateTime::now_utc().unix_timestamp();
+        let now = now.unix_timestamp();
         let db_statements: Vec<_> = self
             .stmts
             .iter()
@@ -72,7 +77,21 @@ impl DocumentTransaction {
                     let stmt = self.stmts.swap_remove(index);
                     let span = self.spans.swap_remove(index);
 
-                    let doc_error = txn_error_to_doc_error(stmt, err);
+                    let conflict = match (&stmt, &err) {
+                        (Stmt::ObjIdentWrite(ident), DbError::Sql(_)) => {
+                            find_ident_owner(db, ident, deks)
+                                .await?
+                                .filter(|owner| *owner != ident.obj_id)
+                                .map(|owner| DocError::IdentityConflict {
+                                    prop_id: ident.prop_id,
+                                    entity: ident.obj_id,
+                                    owner,
+                                })
+                        }
+                        _ => None,
+                    };
+
+                    let doc_error = conflict.unwrap_or_else(|| txn_error_to_doc_error(stmt, err));
 
                     errors.push(Spanned::new(span, doc_error));
                 }
@@ -125,13 +144,15 @@ pub enum Stmt {
         ns_id: AnyId,
         kind: PropertyKind,
         label: String,
+        inheritance: Inheritance,
     },
     NsAttrWrite {
         prop_stmt: usize,
         id: AttrId,
         label: String,
     },
-    EntAttrAssignmentGc,
+    EntAttrAssignmentRemovedEvents(Vec<CompiledEntityAttributeAssignment>),
+    EntAttrAssignmentGc(Vec<CompiledEntityAttributeAssignment>),
     EntAttrAssignmentWrite(CompiledEntityAttributeAssignment),
     PolicyGc(Vec<PolicyId>),
     PolicyWrite {
@@ -140,7 +161,9 @@ pub enum Stmt {
         policy_pc: Vec<u8>,
     },
     PolBindGc,
-    PolBindWrite,
+    PolBindWrite {
+        dir_default: bool,
+    },
     PolBindAttrMatchWrite(usize, AttrId),
     PolBindPolicyWrite(usize, PolicyId),
 }
@@ -229,6 +252,18 @@ fn mk_document_transaction(document: CompiledDocument, actor: Actor) -> Document
         }
     }
 
+    // Only assignments that are no longer present are removed, so that the change events reflect actual changes.
+    // This must run before the attribute GC, while the removed attributes still exist,
+    // and the removals are recorded right before.
+    txn.push(
+        Stmt::EntAttrAssignmentRemovedEvents(data.entity_attribute_assignments.clone()),
+        NO_SPAN,
+    );
+    txn.push(
+        Stmt::EntAttrAssignmentGc(data.entity_attribute_assignments.clone()),
+        NO_SPAN,
+    );
+
     // namespaced properties
     {
         txn.push(
@@ -253,6 +288,7 @@ fn mk_document_transaction(document: CompiledDocument, actor: Actor) -> Document
                     kind: prop.kind,
                     ns_id: prop.ns_id,
                     label: prop.label,
+                    inheritance: prop.inheritance,
                 },
                 NO_SPAN,
             );
@@ -272,9 +308,6 @@ fn mk_document_transaction(document: CompiledDocument, actor: Actor) -> Document
 
     // entity attribute assignment
     {
-        // not sure how to "GC" this?
-        txn.push(Stmt::EntAttrAssignmentGc, NO_SPAN);
-
         for assignment in data.entity_attribute_assignments {
             txn.push(Stmt::EntAttrAssignmentWrite(assignment), NO_SPAN);
         }
@@ -303,8 +336,18 @@ fn mk_document_transaction(document: CompiledDocument, actor: Actor) -> Document
     {
         txn.push(Stmt::PolBindGc, NO_SPAN);
 
-        for binding in data.policy_bindings {
-            let parent_stmt = txn.push(Stmt::PolBindWrite, NO_SPAN);
+        let bindings = data
+            .policy_bindings
+            .into_iter()
+            .map(|binding| (binding, false))
+            .chain(
+                data.default_policy_bindings
+                    .into_iter()
+                    .map(|binding| (binding, true)),
+            );
+
+        for (binding, dir_default) in bindings {
+            let parent_stmt = txn.push(Stmt::PolBindWrite { dir_default }, NO_SPAN);
 
             for attr_id in binding.attr_matcher {
                 txn.push(Stmt::PolBindAttrMatchWrite(parent_stmt, attr_id), NO_SPAN);
@@ -399,10 +442,14 @@ fn stmt_to_db_stmt<D: Db>(
             "INSERT INTO svc_namespace (dir_key, upd, svc_eid, ns_key) VALUES ($1, $2, $3, (SELECT key FROM namespace WHERE id = $4))".into(),
             params!(dir_key, now, svc_id.to_blob(), ns_id.to_blob()),
         ),
-        Stmt::EntAttrAssignmentGc => (
-            "DELETE FROM ent_attr WHERE dir_key = $1".into(),
-            params!(dir_key),
-        ),
+        Stmt::EntAttrAssignmentRemovedEvents(keep) => {
+            let (filter, params) = ent_attr_gc_filter::<D>(dir_key, keep);
+            entity_event_repo::removed_entity_attribute_events_stmt::<D>(&filter, params, now)
+        }
+        Stmt::EntAttrAssignmentGc(keep) => {
+            let (filter, params) = ent_attr_gc_filter::<D>(dir_key, keep);
+            (format!("DELETE FROM ent_attr WHERE {filter}").into(), params)
+        }
         Stmt::EntAttrAssignmentWrite(assignment) => (
             "INSERT INTO ent_attr (dir_key, upd, eid, attr_key) VALUES ($1, $2, $3, (SELECT key FROM attr WHERE id = $4)) ON CONFLICT DO NOTHING".into(),
             params!(dir_key, now, assignment.eid.to_blob(), assignment.attrid.to_blob()),
@@ -417,16 +464,16 @@ fn stmt_to_db_stmt<D: Db>(
             NotIn("id", ids.iter().copied()),
             dir_key,
         ),
-        Stmt::NsPropWrite { id, ns_id, kind, label } => (
+        Stmt::NsPropWrite { id, ns_id, kind, label, inheritance } => (
             indoc! {
                 "
-                INSERT INTO prop (dir_key, ns_key, upd, id, kind, label)
-                VALUES ($1, (SELECT key FROM namespace WHERE id = $2), $3, $4, $5, $6)
-                ON CONFLICT DO UPDATE SET upd = $3, kind = $5, label = $6
+                INSERT INTO prop (dir_key, ns_key, upd, id, kind, label, inheritance)
+                VALUES ($1, (SELECT key FROM namespace WHERE id = $2), $3, $4, $5, $6, $7)
+                ON CONFLICT DO UPDATE SET upd = $3, kind = $5, label = $6, inheritance = $7
                 RETURNING key
                 "
             }.into(),
-            params!(dir_key, ns_id.to_blob(), now, id.to_blob(), format!("{kind}"), label.clone()),
+            params!(dir_key, ns_id.to_blob(), now, id.to_blob(), format!("{kind}"), label.clone(), format!("{inheritance}")),
         ),
         Stmt::NsAttrWrite { prop_stmt, id, label } => (
             indoc! {
@@ -457,9 +504,9 @@ fn stmt_to_db_stmt<D: Db>(
             "DELETE FROM polbind WHERE dir_key = $1".into(),
             params!(dir_key)
         ),
-        Stmt::PolBindWrite => (
-            "INSERT INTO polbind (dir_key, upd) VALUES ($1, $2) RETURNING key".into(),
-            params!(dir_key, now)
+        Stmt::PolBindWrite { dir_default } => (
+            "INSERT INTO polbind (dir_key, upd, dir_default) VALUES ($1, $2, $3) RETURNING key".into(),
+            params!(dir_key, now, *dir_default as i64)
         ),
         Stmt::PolBindAttrMatchWrite(parent_stmt, attr_id) => (
             "INSERT INTO polbind_attr_match (polbind_key, attr_key) VALUES ($1, (SELECT key FROM attr WHERE id = $2))"
@@ -476,6 +523,38 @@ fn stmt_to_db_stmt<D: Db>(
     Ok(output)
 }
 
+/// The condition on `ent_attr` matching the assignments of the directory that are not kept
+fn ent_attr_gc_filter<D: Db>(
+    dir_key: D::Param,
+    keep: &[CompiledEntityAttributeAssignment],
+) -> (String, Vec<<D as Db>::Param>) {
+    if keep.is_empty() {
+        return ("dir_key = $1".to_string(), params!(dir_key));
+    }
+
+    let mut params: Vec<D::Param> = params!(dir_key);
+    let mut values = Vec::with_capacity(keep.len());
+
+    for assignment in keep {
+        values.push(format!("(${}, ${})", params.len() + 1, params.len() + 2));
+        params.push(assignment.eid.to_blob().into());
+        params.push(assignment.attrid.to_blob().into());
+    }
+
+    (
+        formatdoc! {
+            "
+            dir_key = $1 AND (eid, attr_key) NOT IN (
+                SELECT keep.column1, attr.key FROM (VALUES {values}) AS keep
+                JOIN attr ON attr.id = keep.column2
+            )
+            ",
+            values = values.iter().format(", ")
+        },
+        params,
+    )
+}
+
 struct NotIn<'a, I>(&'a str, I);
 
 fn gc<D: Db>(
@@ -493,6 +572,20 @@ fn gc<D: Db>(
     )
 }
 
+/// Find the current owner of an identity that could not be written
+async fn find_ident_owner<D: Db>(
+    db: &D,
+    ident: &ObjectIdent,
+    deks: &DecryptedDeks,
+) -> Result<Option<AnyId>, DocumentDbTxnError> {
+    let fingerprint = deks
+        .get(ident.prop_id)
+        .map_err(DocumentDbTxnError::Encryption)?
+        .fingerprint(ident.ident.as_bytes());
+
+    Ok(object_repo::find_obj_id_by_ident_fingerprint(db, ident.prop_id, &fingerprint).await?)
+}
+
 fn txn_error_to_doc_error(stmt: Stmt, db_error: DbError) -> DocError {
     info!(?stmt, "doc transaction error");
     match db_error {
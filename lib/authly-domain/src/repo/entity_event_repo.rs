@@ -0,0 +1,139 @@
+use std::borrow::Cow;
+
+use authly_common::id::ServiceId;
+use authly_db::{param::ToBlob, params, Db, DbError, DbResult, Params, Row, TryFromRow};
+use indoc::{formatdoc, indoc};
+
+use crate::bus::entity_events::{AttributeChange, EntityAttributeEvent};
+
+impl TryFromRow for EntityAttributeEvent {
+    type Error = DbError;
+
+    fn try_from_row(row: &mut impl Row) -> Result<Self, Self::Error> {
+        Ok(Self {
+            seq: row.get_int("seq"),
+            eid: row.get_id("eid"),
+            attr_id: row.get_id("attr_id"),
+            change: if row.get_int("added") != 0 {
+                AttributeChange::Added
+            } else {
+                AttributeChange::Removed
+            },
+            created_at: row.get_datetime("created_at")?,
+        })
+    }
+}
+
+/// Statement recording the removal of the entity attributes matched by `filter`, to run right before deleting them.
+///
+/// `filter` is a condition on `ent_attr` using the given `params`, the time of the removal is appended as the last parameter.
+pub fn removed_entity_attribute_events_stmt<D: Db>(
+    filter: &str,
+    mut params: Params<D>,
+    now: i64,
+) -> (Cow<'static, str>, Params<D>) {
+    let now_param = params.len() + 1;
+    params.push(now.into());
+
+    (
+        formatdoc! {
+            "
+            INSERT INTO ent_attr_event (eid, attr_id, added, created_at)
+            SELECT ent_attr.eid, (SELECT attr.id FROM attr WHERE attr.key = ent_attr.attr_key), 0, ${now_param}
+            FROM ent_attr WHERE {filter}
+            "
+        }
+        .into(),
+        params,
+    )
+}
+
+/// List outbox events after the given sequence number, oldest first
+pub async fn list_entity_attribute_events(
+    deps: &impl Db,
+    after_seq: i64,
+    limit: usize,
+) -> DbResult<Vec<EntityAttributeEvent>> {
+    deps.query_filter_map(
+        indoc! {
+            "
+            SELECT seq, eid, attr_id, added, created_at FROM ent_attr_event
+            WHERE seq > $1
+            ORDER BY seq
+            LIMIT $2
+            "
+        }
+        .into(),
+        params!(after_seq, limit as i64),
+    )
+    .await
+}
+
+/// Record the last event a subscriber has processed, never moving its cursor backwards
+pub async fn acknowledge_entity_attribute_events(
+    deps: &impl Db,
+    svc_eid: ServiceId,
+    acked_seq: i64,
+    now: time::OffsetDateTime,
+) -> DbResult<()> {
+    deps.execute(
+        indoc! {
+            "
+            INSERT INTO ent_attr_event_subscriber (svc_eid, acked_seq, upd)
+            VALUES ($1, $2, $3)
+            ON CONFLICT DO UPDATE SET acked_seq = MAX(acked_seq, $2), upd = $3
+            "
+        }
+        .into(),
+        params!(svc_eid.to_blob(), acked_seq, now.unix_timestamp()),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Delete the events that every subscriber has acknowledged
+pub async fn delete_acknowledged_entity_attribute_events(deps: &impl Db) -> DbResult<usize> {
+    deps.execute(
+        indoc! {
+            "
+            DELETE FROM ent_attr_event
+            WHERE seq <= (SELECT MIN(acked_seq) FROM ent_attr_event_subscriber)
+            "
+        }
+        .into(),
+        params!(),
+    )
+    .await
+}
+
+/// Forget the subscribers that haven't acknowledged any events since `before`,
+/// and delete the events written before `before` along with the events every remaining subscriber has acknowledged.
+///
+/// Returns the number of deleted events.
+pub async fn purge_entity_attribute_events_before(
+    deps: &impl Db,
+    before: time::OffsetDateTime,
+) -> DbResult<usize> {
+    let deleted = deps
+        .transact(vec![
+            (
+                "DELETE FROM ent_attr_event_subscriber WHERE upd < $1".into(),
+                params!(before.unix_timestamp()),
+            ),
+            (
+                indoc! {
+                    "
+                    DELETE FROM ent_attr_event
+                    WHERE created_at < $1 OR seq <= (SELECT MIN(acked_seq) FROM ent_attr_event_subscriber)
+                    "
+                }
+                .into(),
+                params!(before.unix_timestamp()),
+            ),
+        ])
+        .await?
+        .into_result()?;
+
+    Ok(deleted.into_iter().nth(1).unwrap_or_default())
+}
@@ -1,10 +1,14 @@
-use argon2::{password_hash::SaltString, Argon2};
 use authly_common::id::{AttrId, EntityId, PersonaId, PropId};
 use authly_db::{param::ToBlob, params, Db, DbResult, DidInsert, FromRow, Row};
 use fnv::FnvHashSet;
 use indoc::indoc;
 
-use crate::{builtins::Builtins, directory::DirKey, id::BuiltinProp};
+use crate::{
+    builtins::Builtins,
+    directory::{DirForeignKey, DirKey},
+    id::BuiltinProp,
+    password::{self, Password},
+};
 
 pub struct EntityPasswordHash {
     pub eid: PersonaId,
@@ -13,6 +17,7 @@ pub struct EntityPasswordHash {
 
 pub struct EntityAttrs(pub FnvHashSet<AttrId>);
 
+/// List the attributes assigned directly to an entity
 pub async fn list_entity_attrs(deps: &impl Db, eid: EntityId) -> DbResult<FnvHashSet<AttrId>> {
     struct EntityAttr(AttrId);
 
@@ -40,6 +45,61 @@ pub async fn list_entity_attrs(deps: &impl Db, eid: EntityId) -> DbResult<FnvHas
         .collect())
 }
 
+/// List the attributes of an entity, including the attributes it inherits along membership relations.
+///
+/// The inheritance direction of each property decides whether its attributes are inherited
+/// from the entities the entity is a member of (`down`), from the members of the entity (`up`), or both.
+/// Inheritance is transitive.
+pub async fn list_resolved_entity_attrs(
+    deps: &impl Db,
+    eid: EntityId,
+) -> DbResult<FnvHashSet<AttrId>> {
+    struct EntityAttr(AttrId);
+
+    impl FromRow for EntityAttr {
+        fn from_row(row: &mut impl Row) -> Self {
+            Self(row.get_id("attrid"))
+        }
+    }
+
+    let membership: PropId = BuiltinProp::RelEntityMembership.into();
+
+    Ok(deps
+        .query_map::<EntityAttr>(
+            indoc! {
+                "
+                WITH RECURSIVE
+                    parent(eid) AS (
+                        SELECT $1
+                        UNION
+                        SELECT ent_rel.subject_eid FROM ent_rel
+                        JOIN parent ON ent_rel.object_eid = parent.eid
+                        WHERE ent_rel.prop_key = (SELECT key FROM prop WHERE id = $2)
+                    ),
+                    member(eid) AS (
+                        SELECT $1
+                        UNION
+                        SELECT ent_rel.object_eid FROM ent_rel
+                        JOIN member ON ent_rel.subject_eid = member.eid
+                        WHERE ent_rel.prop_key = (SELECT key FROM prop WHERE id = $2)
+                    )
+                SELECT DISTINCT attr.id AS attrid
+                FROM ent_attr
+                JOIN attr ON attr.key = ent_attr.attr_key
+                JOIN prop ON prop.key = attr.prop_key
+                WHERE ent_attr.eid = $1
+                    OR (prop.inheritance IN ('down', 'both') AND ent_attr.eid IN (SELECT eid FROM parent))
+                    OR (prop.inheritance IN ('up', 'both') AND ent_attr.eid IN (SELECT eid FROM member))"
+            }
+            .into(),
+            params!(eid.to_blob(), membership.to_blob()),
+        )
+        .await?
+        .into_iter()
+        .map(|attr| attr.0)
+        .collect())
+}
+
 impl FromRow for EntityPasswordHash {
     fn from_row(row: &mut impl Row) -> Self {
         Self {
@@ -75,6 +135,21 @@ pub async fn find_local_directory_entity_password_hash_by_entity_ident(
     .await
 }
 
+pub async fn find_entity_password_hash(
+    deps: &impl Db,
+    persona_id: PersonaId,
+    builtins: &Builtins,
+) -> DbResult<Option<EntityPasswordHash>> {
+    deps.query_map_opt(
+        "SELECT obj_id, value FROM obj_text_attr WHERE obj_id = $1 AND prop_key = $2".into(),
+        params!(
+            persona_id.to_blob(),
+            builtins.prop_key(BuiltinProp::PasswordHash)
+        ),
+    )
+    .await
+}
+
 #[expect(unused)]
 pub async fn try_insert_entity_credentials(
     deps: &impl Db,
@@ -83,15 +158,7 @@ pub async fn try_insert_entity_credentials(
     ident: String,
     secret: String,
 ) -> anyhow::Result<PersonaId> {
-    let secret_hash = tokio::task::spawn_blocking(move || -> anyhow::Result<String> {
-        let salt = SaltString::generate(rand::thread_rng());
-        Ok(
-            argon2::PasswordHash::generate(Argon2::default(), secret, &salt)
-                .map_err(|e| anyhow::anyhow!("failed to generate password hash: {}", e))?
-                .to_string(),
-        )
-    })
-    .await??;
+    let secret_hash = password::hash_password(Password(secret)).await?;
 
     deps
         .execute(
@@ -165,3 +232,146 @@ pub async fn upsert_link_foreign_persona(
 
     Ok((row.id, DidInsert(!row.overwritten)))
 }
+
+/// A link between a foreign id in a persona directory and a persona
+pub struct ForeignPersonaLink {
+    pub persona_id: PersonaId,
+
+    /// Whether the link was made by the persona itself
+    pub explicit: bool,
+}
+
+impl FromRow for ForeignPersonaLink {
+    fn from_row(row: &mut impl Row) -> Self {
+        Self {
+            persona_id: row.get_id("obj_id"),
+            explicit: row.get_int("explicit") != 0,
+        }
+    }
+}
+
+/// Find the persona previously linked to a foreign id in a persona directory
+pub async fn find_foreign_persona_link(
+    deps: &impl Db,
+    dir_key: DirKey,
+    foreign_id: &[u8],
+) -> DbResult<Option<ForeignPersonaLink>> {
+    deps.query_map_opt::<ForeignPersonaLink>(
+        "SELECT obj_id, explicit FROM obj_foreign_dir_link WHERE dir_key = $1 AND foreign_id = $2"
+            .into(),
+        params!(dir_key.0, foreign_id.to_vec()),
+    )
+    .await
+}
+
+/// List the persona directories where the persona is linked to a foreign id
+pub async fn list_foreign_persona_link_dirs(
+    deps: &impl Db,
+    persona_id: PersonaId,
+) -> DbResult<Vec<DirKey>> {
+    Ok(deps
+        .query_map::<DirForeignKey>(
+            "SELECT DISTINCT dir_key FROM obj_foreign_dir_link WHERE obj_id = $1".into(),
+            params!(persona_id.to_blob()),
+        )
+        .await?
+        .into_iter()
+        .map(|key| key.0)
+        .collect())
+}
+
+/// Explicitly link a foreign id to a persona, which clears any previous unlinking of the foreign id.
+///
+/// Returns `false` when the foreign id is already linked to another persona, which is left untouched.
+pub async fn try_link_foreign_persona_explicit(
+    deps: &impl Db,
+    dir_key: DirKey,
+    persona_id: PersonaId,
+    foreign_id: Vec<u8>,
+    now: time::OffsetDateTime,
+) -> DbResult<bool> {
+    let rows = deps
+        .execute_map::<ForeignPersonaLink>(
+            indoc! {
+                "
+                INSERT INTO obj_foreign_dir_link (dir_key, upd, overwritten, foreign_id, obj_id, explicit)
+                VALUES ($1, $2, 0, $3, $4, 1)
+                ON CONFLICT DO UPDATE SET upd = $2, explicit = 1 WHERE obj_id = $4
+                RETURNING obj_id, explicit
+                "
+            }
+            .into(),
+            params!(dir_key.0, now.unix_timestamp(), foreign_id.clone(), persona_id.to_blob()),
+        )
+        .await?;
+
+    if rows.is_empty() {
+        return Ok(false);
+    }
+
+    deps.execute(
+        "DELETE FROM obj_foreign_dir_unlink WHERE dir_key = $1 AND foreign_id = $2".into(),
+        params!(dir_key.0, foreign_id),
+    )
+    .await?;
+
+    Ok(true)
+}
+
+/// Remove the links between a persona and the foreign ids of a persona directory,
+/// recording the unlinked foreign ids so they are not linked again implicitly.
+///
+/// Returns the number of removed links.
+pub async fn delete_foreign_persona_links(
+    deps: &impl Db,
+    dir_key: DirKey,
+    persona_id: PersonaId,
+    now: time::OffsetDateTime,
+) -> DbResult<usize> {
+    let rows = deps
+        .transact(vec![
+            (
+                indoc! {
+                    "
+                    INSERT INTO obj_foreign_dir_unlink (dir_key, foreign_id, obj_id, upd)
+                    SELECT dir_key, foreign_id, obj_id, $3 FROM obj_foreign_dir_link
+                    WHERE dir_key = $1 AND obj_id = $2
+                    ON CONFLICT DO UPDATE SET obj_id = excluded.obj_id, upd = excluded.upd
+                    "
+                }
+                .into(),
+                params!(dir_key.0, persona_id.to_blob(), now.unix_timestamp()),
+            ),
+            (
+                "DELETE FROM obj_foreign_dir_link WHERE dir_key = $1 AND obj_id = $2".into(),
+                params!(dir_key.0, persona_id.to_blob()),
+            ),
+        ])
+        .await?
+        .into_result()?;
+
+    Ok(rows[1])
+}
+
+/// Whether a foreign id of a persona directory was unlinked from its persona, and not explicitly linked since
+pub async fn is_foreign_id_unlinked(
+    deps: &impl Db,
+    dir_key: DirKey,
+    foreign_id: &[u8],
+) -> DbResult<bool> {
+    struct Unlinked;
+
+    impl FromRow for Unlinked {
+        fn from_row(_row: &mut impl Row) -> Self {
+            Self
+        }
+    }
+
+    Ok(deps
+        .query_map_opt::<Unlinked>(
+            "SELECT 1 FROM obj_foreign_dir_unlink WHERE dir_key = $1 AND foreign_id = $2".into(),
+            params!(dir_key.0, foreign_id.to_vec()),
+        )
+        .await?
+        .is_some())
+}
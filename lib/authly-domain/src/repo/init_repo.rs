@@ -116,7 +116,7 @@ async fn write_builtins<D: Db>(deps: &D, missing: Vec<Missing>) -> DbResult<()>
         }
     }
 
-    deps.transact(stmts).await?;
+    deps.transact(stmts).await?.into_result()?;
 
     Ok(())
 }
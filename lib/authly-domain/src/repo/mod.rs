@@ -1,14 +1,23 @@
+pub mod access_control_audit_repo;
+pub mod admin_event_repo;
+pub mod backup_repo;
 pub mod crypto_repo;
 pub mod directory_repo;
 pub mod document_repo;
+pub mod entity_event_repo;
 pub mod entity_repo;
 pub mod init_repo;
 pub mod oauth_repo;
 pub mod object_repo;
 pub mod policy_repo;
+pub mod quota_repo;
+pub mod scim_repo;
 pub mod service_repo;
 pub mod session_repo;
 pub mod settings_repo;
+pub mod stats_repo;
+pub mod token_attrs_repo;
+pub mod token_signing_repo;
 pub mod webauthn_repo;
 
 #[derive(Debug)]
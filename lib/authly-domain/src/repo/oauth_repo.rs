@@ -5,7 +5,7 @@ use authly_db::{param::ToBlob, params, Db, DbResult, FromRow, Params};
 use indoc::indoc;
 
 use crate::{
-    directory::{DirKey, OAuthDirectory},
+    directory::{ClaimAttributeMapping, DirKey, JitProvisioning, OAuthDirectory},
     encryption::{CryptoError, DecryptedDeks, EncryptedObjIdent},
     id::BuiltinProp,
 };
@@ -63,9 +63,35 @@ impl FromRow for OAuthRow {
     }
 }
 
+struct JitRow(JitProvisioning);
+
+impl FromRow for JitRow {
+    fn from_row(row: &mut impl authly_db::Row) -> Self {
+        Self(JitProvisioning {
+            enabled: row.get_int("jit_enabled") != 0,
+            allowed_domains: row
+                .get_opt_text("jit_allowed_domains")
+                .map(|domains| {
+                    domains
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|domain| !domain.is_empty())
+                        .map(str::to_string)
+                        .collect()
+                })
+                .unwrap_or_default(),
+            user_res_username_path: row.get_opt_text("jit_username_path"),
+            claim_attributes: row
+                .get_opt_text("jit_claim_attributes")
+                .map(|mappings| ClaimAttributeMapping::parse_list(&mappings))
+                .unwrap_or_default(),
+        })
+    }
+}
+
 pub async fn oauth_query(deps: &impl Db) -> DbResult<Vec<OAuthRow>> {
     deps.query_map(
-        "SELECT dir_oauth.*, directory.id AS dir_id FROM dir_oauth JOIN directory".into(),
+        "SELECT dir_oauth.*, directory.id AS dir_id FROM dir_oauth JOIN directory ON directory.key = dir_oauth.dir_key".into(),
         params!(),
     )
     .await
@@ -124,6 +150,59 @@ pub fn oauth_upsert_params<D: Db>(dir: OAuthDirectory, now: i64) -> Params<D> {
     )
 }
 
+/// Find the just-in-time provisioning settings of an OAuth directory.
+///
+/// Returns `None` when the directory has no stored settings.
+pub async fn find_jit_provisioning(
+    deps: &impl Db,
+    dir_key: DirKey,
+) -> DbResult<Option<JitProvisioning>> {
+    Ok(deps
+        .query_map_opt::<JitRow>(
+            indoc! {
+                "
+                SELECT jit_enabled, jit_allowed_domains, jit_username_path, jit_claim_attributes
+                FROM dir_oauth WHERE dir_key = $1
+                "
+            }
+            .into(),
+            params!(dir_key.0),
+        )
+        .await?
+        .map(|row| row.0))
+}
+
+pub fn oauth_update_jit_stmt() -> Cow<'static, str> {
+    indoc! {
+        "
+        UPDATE dir_oauth SET
+            upd = $2,
+            jit_enabled = $3,
+            jit_allowed_domains = $4,
+            jit_username_path = $5,
+            jit_claim_attributes = $6
+        WHERE dir_key = $1
+        "
+    }
+    .into()
+}
+
+pub fn oauth_update_jit_params<D: Db>(
+    dir_key: DirKey,
+    jit: JitProvisioning,
+    now: i64,
+) -> Params<D> {
+    params!(
+        dir_key.0,
+        now,
+        jit.enabled as i64,
+        (!jit.allowed_domains.is_empty()).then(|| jit.allowed_domains.join(",")),
+        jit.user_res_username_path,
+        (!jit.claim_attributes.is_empty())
+            .then(|| ClaimAttributeMapping::format_list(&jit.claim_attributes))
+    )
+}
+
 pub fn oauth_upsert_secret_stmt<D: Db>(
     dir: &OAuthDirectory,
     parent_dir_key: DirKey,
@@ -1,4 +1,4 @@
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
 
 use authly_common::{
     id::{AttrId, PolicyId, ServiceId},
@@ -51,8 +51,7 @@ pub async fn load_svc_policy_engine(deps: &impl Db, svc_eid: ServiceId) -> DbRes
     let mut policy_engine = PolicyEngine::default();
 
     for Identified(id, policy_pc) in policy_data.policies {
-        let opcodes = PolicyCompiler::expr_to_opcodes(&policy_pc.expr);
-        let bytecode = to_bytecode(&opcodes);
+        let bytecode = policy_pc.to_bytecode();
 
         policy_engine.add_policy(id, policy_pc.class, bytecode);
     }
@@ -68,6 +67,13 @@ pub async fn load_svc_policy_engine(deps: &impl Db, svc_eid: ServiceId) -> DbRes
     Ok(policy_engine)
 }
 
+impl PolicyPostcard {
+    /// Compile the policy expression to the bytecode evaluated by the policy engine
+    pub fn to_bytecode(&self) -> Vec<u8> {
+        to_bytecode(&PolicyCompiler::expr_to_opcodes(&self.expr))
+    }
+}
+
 impl TryFromRow for Identified<PolicyId, PolicyPostcard> {
     type Error = postcard::Error;
 
@@ -79,11 +85,17 @@ impl TryFromRow for Identified<PolicyId, PolicyPostcard> {
     }
 }
 
+/// Load the policies and policy bindings applying to a service.
+///
+/// A service without policy bindings of its own gets the default policy bindings of its directory.
 pub async fn load_svc_policies_with_bindings(
     deps: &impl Db,
     svc_id: ServiceId,
 ) -> DbResult<PoliciesWithBindings> {
-    let bindings = list_svc_implied_policy_bindings(deps, svc_id).await?;
+    let mut bindings = list_svc_implied_policy_bindings(deps, svc_id).await?;
+    if bindings.is_empty() {
+        bindings = list_svc_directory_default_policy_bindings(deps, svc_id).await?;
+    }
 
     let policy_ids = BTreeSet::<PolicyId>::from_iter(
         bindings
@@ -105,6 +117,36 @@ pub async fn load_svc_policies_with_bindings(
     Ok(PoliciesWithBindings { bindings, policies })
 }
 
+/// Look up the labels of the given policies
+pub async fn list_policy_labels(
+    deps: &impl Db,
+    policy_ids: impl IntoIterator<Item = PolicyId>,
+) -> DbResult<BTreeMap<PolicyId, String>> {
+    struct PolicyLabel(PolicyId, String);
+
+    impl FromRow for PolicyLabel {
+        fn from_row(row: &mut impl Row) -> Self {
+            Self(row.get_id("id"), row.get_text("label"))
+        }
+    }
+
+    let labels: Vec<PolicyLabel> = deps
+        .query_map(
+            format!(
+                "SELECT id, label FROM policy WHERE id IN ({})",
+                policy_ids.into_iter().map(|id| id.literal()).format(", ")
+            )
+            .into(),
+            params!(),
+        )
+        .await?;
+
+    Ok(labels
+        .into_iter()
+        .map(|PolicyLabel(id, label)| (id, label))
+        .collect())
+}
+
 impl FromRow for DbPolicyBinding {
     fn from_row(row: &mut impl Row) -> Self {
         Self {
@@ -125,11 +167,38 @@ async fn list_svc_implied_policy_bindings(
                 CAST(group_concat(attr.id, '') AS BLOB) attr_matcher,
                 CAST(group_concat(pb_pol.policy_id, '') AS BLOB) policies
             FROM polbind_policy pb_pol
+            JOIN polbind pb ON pb.key = pb_pol.polbind_key
             JOIN polbind_attr_match pb_am ON pb_am.polbind_key = pb_pol.polbind_key
             JOIN attr ON attr.key = pb_am.attr_key
             JOIN prop ON prop.key = attr.prop_key
             JOIN svc_namespace sdom ON sdom.ns_key = prop.ns_key
-            WHERE sdom.svc_eid = $1
+            WHERE sdom.svc_eid = $1 AND pb.dir_default = 0
+            GROUP BY pb_pol.polbind_key
+            "
+        }
+        .into(),
+        params!(svc_id.to_blob()),
+    )
+    .await
+}
+
+/// The default policy bindings of the directory the service belongs to
+async fn list_svc_directory_default_policy_bindings(
+    deps: &impl Db,
+    svc_id: ServiceId,
+) -> DbResult<Vec<DbPolicyBinding>> {
+    deps.query_map(
+        indoc! {
+            "
+            SELECT
+                CAST(group_concat(attr.id, '') AS BLOB) attr_matcher,
+                CAST(group_concat(pb_pol.policy_id, '') AS BLOB) policies
+            FROM polbind_policy pb_pol
+            JOIN polbind pb ON pb.key = pb_pol.polbind_key
+            JOIN svc ON svc.dir_key = pb.dir_key
+            JOIN polbind_attr_match pb_am ON pb_am.polbind_key = pb_pol.polbind_key
+            JOIN attr ON attr.key = pb_am.attr_key
+            WHERE svc.svc_eid = $1 AND pb.dir_default = 1
             GROUP BY pb_pol.polbind_key
             "
         }
@@ -138,3 +207,64 @@ async fn list_svc_implied_policy_bindings(
     )
     .await
 }
+
+/// Policy binding coverage of one resource attribute
+#[derive(Debug)]
+pub struct ResourceAttrCoverage {
+    pub namespace: String,
+    pub property: String,
+    pub attr_id: AttrId,
+    pub attribute: String,
+    /// The number of policy bindings matching the attribute
+    pub bindings: usize,
+}
+
+impl ResourceAttrCoverage {
+    /// Whether the attribute triggers at least one policy.
+    /// Access to uncovered resources falls back to the default decision.
+    pub fn is_covered(&self) -> bool {
+        self.bindings > 0
+    }
+}
+
+impl FromRow for ResourceAttrCoverage {
+    fn from_row(row: &mut impl Row) -> Self {
+        Self {
+            namespace: row.get_text("ns"),
+            property: row.get_text("plabel"),
+            attr_id: row.get_id("attrid"),
+            attribute: row.get_text("alabel"),
+            bindings: row.get_int("bindings") as usize,
+        }
+    }
+}
+
+/// Analyze which resource attributes of a service are covered by a policy binding
+pub async fn list_svc_resource_coverage(
+    deps: &impl Db,
+    svc_id: ServiceId,
+) -> DbResult<Vec<ResourceAttrCoverage>> {
+    deps.query_map(
+        indoc! {
+            "
+            SELECT
+                ns.label ns,
+                prop.label plabel,
+                attr.id attrid,
+                attr.label alabel,
+                count(pb_am.polbind_key) bindings
+            FROM prop
+            JOIN attr ON attr.prop_key = prop.key
+            JOIN svc_namespace sdom ON sdom.ns_key = prop.ns_key
+            JOIN namespace ns ON ns.key = prop.ns_key
+            LEFT JOIN polbind_attr_match pb_am ON pb_am.attr_key = attr.key
+            WHERE sdom.svc_eid = $1 AND prop.kind = 'res'
+            GROUP BY attr.key
+            ORDER BY ns.label, prop.label, attr.label
+            "
+        }
+        .into(),
+        params!(svc_id.to_blob()),
+    )
+    .await
+}
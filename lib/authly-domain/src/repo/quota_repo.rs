@@ -0,0 +1,90 @@
+use authly_common::id::{AttrId, EntityId};
+use authly_db::{param::ToBlob, params, Db, DbResult, FromRow, Row};
+use indoc::indoc;
+
+/// The quota of a subject for an attribute, in the window starting at `window_start`
+pub struct QuotaWindow {
+    pub attr_id: AttrId,
+    pub window_start: i64,
+    pub limit: u32,
+}
+
+/// Find the attribute a quota applies to, by its labels
+pub async fn find_quota_attr_id(
+    deps: &impl Db,
+    namespace: &str,
+    property: &str,
+    attribute: &str,
+) -> DbResult<Option<AttrId>> {
+    struct TypedRow(AttrId);
+
+    impl FromRow for TypedRow {
+        fn from_row(row: &mut impl Row) -> Self {
+            Self(row.get_id("id"))
+        }
+    }
+
+    Ok(deps
+        .query_map_opt::<TypedRow>(
+            indoc! {
+                "
+                SELECT attr.id FROM attr
+                JOIN prop ON prop.key = attr.prop_key
+                JOIN namespace ON namespace.key = prop.ns_key
+                WHERE namespace.label = $1 AND prop.label = $2 AND attr.label = $3
+                "
+            }
+            .into(),
+            params!(namespace, property, attribute),
+        )
+        .await?
+        .map(|row| row.0))
+}
+
+/// Consume one unit of each of a subject's quotas, or none of them.
+///
+/// Counters are reset when a new window has started.
+/// Returns `false`, leaving every counter untouched, when `limit` units of any quota are already consumed in its window.
+/// The quotas are consumed in one transaction, where a used up quota fails its statement
+/// by setting a `NULL` count, so concurrent requests never exceed a limit.
+pub async fn try_consume_all(
+    deps: &impl Db,
+    subject_eid: EntityId,
+    quotas: &[QuotaWindow],
+) -> DbResult<bool> {
+    if quotas.iter().any(|quota| quota.limit == 0) {
+        return Ok(false);
+    }
+    if quotas.is_empty() {
+        return Ok(true);
+    }
+
+    let statements = quotas
+        .iter()
+        .map(|quota| {
+            (
+                indoc! {
+                    "
+                    INSERT INTO quota_counter (subject_eid, attr_id, window_start, count)
+                    VALUES ($1, $2, $3, 1)
+                    ON CONFLICT DO UPDATE SET
+                        count = CASE
+                            WHEN window_start != $3 THEN 1
+                            WHEN count < $4 THEN count + 1
+                        END,
+                        window_start = $3
+                    "
+                }
+                .into(),
+                params!(
+                    subject_eid.to_blob(),
+                    quota.attr_id.to_blob(),
+                    quota.window_start,
+                    quota.limit as i64
+                ),
+            )
+        })
+        .collect();
+
+    Ok(deps.transact(statements).await?.is_committed())
+}
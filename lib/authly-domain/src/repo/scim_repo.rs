@@ -0,0 +1,499 @@
+use std::borrow::Cow;
+
+use authly_common::id::{AttrId, DirectoryId, EntityId, Id128DynamicArrayConv, ServiceId};
+use authly_db::{param::ToBlob, params, Db, DbError, DbResult, FromRow, Params, Row, TryFromRow};
+use indoc::indoc;
+
+use crate::{directory::DirKey, repo::entity_event_repo};
+
+/// The SCIM resource type of a provisioned entity
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ScimResourceType {
+    User,
+    Group,
+}
+
+impl ScimResourceType {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::User => "User",
+            Self::Group => "Group",
+        }
+    }
+}
+
+pub struct ScimResourceRow<T> {
+    pub id: T,
+    pub external_id: Option<String>,
+    pub display_name: Option<String>,
+    pub active: bool,
+    pub created_at: time::OffsetDateTime,
+    pub upd: time::OffsetDateTime,
+}
+
+impl<T: Id128DynamicArrayConv> TryFromRow for ScimResourceRow<T> {
+    type Error = DbError;
+
+    fn try_from_row(row: &mut impl Row) -> Result<Self, Self::Error> {
+        Ok(Self {
+            id: row.get_id("eid"),
+            external_id: row.get_opt_text("external_id"),
+            display_name: row.get_opt_text("display_name"),
+            active: row.get_int("active") != 0,
+            created_at: row.get_datetime("created_at")?,
+            upd: row.get_datetime("upd")?,
+        })
+    }
+}
+
+/// Find the SCIM directory, creating it with `new_id` on first use
+pub async fn get_or_create_scim_directory(
+    deps: &impl Db,
+    new_id: DirectoryId,
+) -> DbResult<(DirKey, DirectoryId)> {
+    struct TypedRow(DirKey, DirectoryId);
+
+    impl FromRow for TypedRow {
+        fn from_row(row: &mut impl Row) -> Self {
+            Self(DirKey(row.get_int("key")), row.get_id("id"))
+        }
+    }
+
+    deps.execute(
+        indoc! {
+            "
+            INSERT INTO directory (id, kind, url, hash, label)
+            VALUES ($1, 'scim', '', $2, 'scim')
+            ON CONFLICT DO NOTHING
+            "
+        }
+        .into(),
+        params!(new_id.to_blob(), vec![0u8; 32]),
+    )
+    .await?;
+
+    let row = deps
+        .query_map_opt::<TypedRow>(
+            "SELECT key, id FROM directory WHERE kind = 'scim'".into(),
+            params!(),
+        )
+        .await?
+        .ok_or_else(|| DbError::Other("scim directory missing".into()))?;
+
+    Ok((row.0, row.1))
+}
+
+pub async fn insert_scim_token(
+    deps: &impl Db,
+    token_hash: [u8; 32],
+    svc_eid: ServiceId,
+    now: time::OffsetDateTime,
+    expires_at: time::OffsetDateTime,
+) -> DbResult<()> {
+    deps.execute(
+        "INSERT INTO scim_token (token_hash, svc_eid, created_at, expires_at) VALUES ($1, $2, $3, $4)"
+            .into(),
+        params!(
+            token_hash.to_vec(),
+            svc_eid.to_blob(),
+            now.unix_timestamp(),
+            expires_at.unix_timestamp()
+        ),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Find the service of a token that is neither expired nor revoked
+pub async fn find_scim_token_service(
+    deps: &impl Db,
+    token_hash: [u8; 32],
+    now: time::OffsetDateTime,
+) -> DbResult<Option<ServiceId>> {
+    struct TypedRow(ServiceId);
+
+    impl FromRow for TypedRow {
+        fn from_row(row: &mut impl Row) -> Self {
+            Self(row.get_id("svc_eid"))
+        }
+    }
+
+    Ok(deps
+        .query_map_opt::<TypedRow>(
+            indoc! {
+                "
+                SELECT svc_eid FROM scim_token
+                WHERE token_hash = $1 AND expires_at > $2 AND revoked_at IS NULL
+                "
+            }
+            .into(),
+            params!(token_hash.to_vec(), now.unix_timestamp()),
+        )
+        .await?
+        .map(|row| row.0))
+}
+
+/// Revoke all the unrevoked tokens issued to a service
+pub async fn revoke_scim_tokens(
+    deps: &impl Db,
+    svc_eid: ServiceId,
+    now: time::OffsetDateTime,
+) -> DbResult<usize> {
+    deps.execute(
+        "UPDATE scim_token SET revoked_at = $2 WHERE svc_eid = $1 AND revoked_at IS NULL".into(),
+        params!(svc_eid.to_blob(), now.unix_timestamp()),
+    )
+    .await
+}
+
+/// Whether the entity is a SCIM resource that has been deactivated
+pub async fn is_deactivated(deps: &impl Db, eid: EntityId) -> DbResult<bool> {
+    struct Exists(bool);
+
+    impl FromRow for Exists {
+        fn from_row(row: &mut impl Row) -> Self {
+            Self(row.get_int("present") != 0)
+        }
+    }
+
+    Ok(deps
+        .query_map_opt::<Exists>(
+            "SELECT EXISTS (SELECT 1 FROM scim_resource WHERE eid = $1 AND active = 0) AS present"
+                .into(),
+            params!(eid.to_blob()),
+        )
+        .await?
+        .is_some_and(|exists| exists.0))
+}
+
+pub async fn get_scim_resource<T: Id128DynamicArrayConv + Send + 'static>(
+    deps: &impl Db,
+    resource_type: ScimResourceType,
+    eid: EntityId,
+) -> DbResult<Option<ScimResourceRow<T>>> {
+    Ok(deps
+        .query_filter_map(
+            indoc! {
+                "
+                SELECT eid, external_id, display_name, active, created_at, upd
+                FROM scim_resource
+                WHERE resource_type = $1 AND eid = $2
+                "
+            }
+            .into(),
+            params!(resource_type.as_str(), eid.to_blob()),
+        )
+        .await?
+        .into_iter()
+        .next())
+}
+
+pub async fn list_scim_resources<T: Id128DynamicArrayConv + Send + 'static>(
+    deps: &impl Db,
+    resource_type: ScimResourceType,
+) -> DbResult<Vec<ScimResourceRow<T>>> {
+    deps.query_filter_map(
+        indoc! {
+            "
+            SELECT eid, external_id, display_name, active, created_at, upd
+            FROM scim_resource
+            WHERE resource_type = $1
+            ORDER BY created_at, eid
+            "
+        }
+        .into(),
+        params!(resource_type.as_str()),
+    )
+    .await
+}
+
+/// List all attributes of a property, identified by namespace and property labels
+pub async fn list_labeled_property_attrs(
+    deps: &impl Db,
+    namespace: &str,
+    property: &str,
+) -> DbResult<Vec<(AttrId, String)>> {
+    struct TypedRow(AttrId, String);
+
+    impl FromRow for TypedRow {
+        fn from_row(row: &mut impl Row) -> Self {
+            Self(row.get_id("id"), row.get_text("label"))
+        }
+    }
+
+    Ok(deps
+        .query_map::<TypedRow>(
+            indoc! {
+                "
+                SELECT attr.id, attr.label FROM attr
+                JOIN prop ON prop.key = attr.prop_key
+                JOIN namespace ON namespace.key = prop.ns_key
+                WHERE namespace.label = $1 AND prop.label = $2 AND attr.label IS NOT NULL
+                "
+            }
+            .into(),
+            params!(namespace, property),
+        )
+        .await?
+        .into_iter()
+        .map(|row| (row.0, row.1))
+        .collect())
+}
+
+pub async fn list_group_members(
+    deps: &impl Db,
+    membership_prop_key: i64,
+    group_eid: EntityId,
+) -> DbResult<Vec<EntityId>> {
+    struct TypedRow(EntityId);
+
+    impl FromRow for TypedRow {
+        fn from_row(row: &mut impl Row) -> Self {
+            Self(row.get_id("object_eid"))
+        }
+    }
+
+    Ok(deps
+        .query_map::<TypedRow>(
+            indoc! {
+                "
+                SELECT object_eid FROM ent_rel
+                WHERE prop_key = $1 AND subject_eid = $2
+                ORDER BY object_eid
+                "
+            }
+            .into(),
+            params!(membership_prop_key, group_eid.to_blob()),
+        )
+        .await?
+        .into_iter()
+        .map(|row| row.0)
+        .collect())
+}
+
+pub fn upsert_scim_resource_stmt<D: Db>(
+    dir_key: DirKey,
+    resource_type: ScimResourceType,
+    eid: EntityId,
+    external_id: Option<&str>,
+    display_name: Option<&str>,
+    active: bool,
+    now: time::OffsetDateTime,
+) -> (Cow<'static, str>, Params<D>) {
+    (
+        indoc! {
+            "
+            INSERT INTO scim_resource (dir_key, eid, resource_type, external_id, display_name, active, created_at, upd)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $7)
+            ON CONFLICT DO UPDATE SET external_id = $4, display_name = $5, active = $6, upd = $7
+            "
+        }
+        .into(),
+        params!(
+            dir_key.0,
+            eid.to_blob(),
+            resource_type.as_str(),
+            external_id,
+            display_name,
+            active as i64,
+            now.unix_timestamp()
+        ),
+    )
+}
+
+/// When an identity of an object was last set
+pub async fn find_obj_ident_upd(
+    deps: &impl Db,
+    obj_id: EntityId,
+    prop_key: i64,
+) -> DbResult<Option<time::OffsetDateTime>> {
+    struct TypedRow(time::OffsetDateTime);
+
+    impl TryFromRow for TypedRow {
+        type Error = DbError;
+
+        fn try_from_row(row: &mut impl Row) -> Result<Self, Self::Error> {
+            Ok(Self(row.get_datetime("upd")?))
+        }
+    }
+
+    Ok(deps
+        .query_filter_map::<TypedRow>(
+            "SELECT upd FROM obj_ident WHERE obj_id = $1 AND prop_key = $2".into(),
+            params!(obj_id.to_blob(), prop_key),
+        )
+        .await?
+        .into_iter()
+        .next()
+        .map(|row| row.0))
+}
+
+pub fn delete_obj_ident_stmt<D: Db>(
+    eid: EntityId,
+    prop_key: i64,
+) -> (Cow<'static, str>, Params<D>) {
+    (
+        "DELETE FROM obj_ident WHERE obj_id = $1 AND prop_key = $2".into(),
+        params!(eid.to_blob(), prop_key),
+    )
+}
+
+pub fn upsert_obj_text_attr_stmt<D: Db>(
+    dir_key: DirKey,
+    eid: EntityId,
+    prop_key: i64,
+    value: String,
+    now: time::OffsetDateTime,
+) -> (Cow<'static, str>, Params<D>) {
+    (
+        indoc! {
+            "
+            INSERT INTO obj_text_attr (dir_key, obj_id, prop_key, upd, value)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT DO UPDATE SET upd = $4, value = $5
+            "
+        }
+        .into(),
+        params!(
+            dir_key.0,
+            eid.to_blob(),
+            prop_key,
+            now.unix_timestamp(),
+            value
+        ),
+    )
+}
+
+pub fn insert_ent_attr_stmt<D: Db>(
+    dir_key: DirKey,
+    eid: EntityId,
+    attr_id: AttrId,
+    now: time::OffsetDateTime,
+) -> (Cow<'static, str>, Params<D>) {
+    (
+        indoc! {
+            "
+            INSERT INTO ent_attr (dir_key, eid, attr_key, upd)
+            VALUES ($1, $2, (SELECT key FROM attr WHERE id = $3), $4)
+            ON CONFLICT DO NOTHING
+            "
+        }
+        .into(),
+        params!(
+            dir_key.0,
+            eid.to_blob(),
+            attr_id.to_blob(),
+            now.unix_timestamp()
+        ),
+    )
+}
+
+/// Statements removing an attribute from an entity, recording the removal in the outbox first
+pub fn delete_ent_attr_stmts<D: Db>(
+    dir_key: DirKey,
+    eid: EntityId,
+    attr_id: AttrId,
+    now: time::OffsetDateTime,
+) -> [(Cow<'static, str>, Params<D>); 2] {
+    const FILTER: &str =
+        "dir_key = $1 AND eid = $2 AND attr_key = (SELECT key FROM attr WHERE id = $3)";
+
+    [
+        entity_event_repo::removed_entity_attribute_events_stmt::<D>(
+            FILTER,
+            params!(dir_key.0, eid.to_blob(), attr_id.to_blob()),
+            now.unix_timestamp(),
+        ),
+        (
+            format!("DELETE FROM ent_attr WHERE {FILTER}").into(),
+            params!(dir_key.0, eid.to_blob(), attr_id.to_blob()),
+        ),
+    ]
+}
+
+pub fn delete_group_members_stmt<D: Db>(
+    dir_key: DirKey,
+    membership_prop_key: i64,
+    group_eid: EntityId,
+) -> (Cow<'static, str>, Params<D>) {
+    (
+        "DELETE FROM ent_rel WHERE dir_key = $1 AND prop_key = $2 AND subject_eid = $3".into(),
+        params!(dir_key.0, membership_prop_key, group_eid.to_blob()),
+    )
+}
+
+pub fn insert_group_member_stmt<D: Db>(
+    dir_key: DirKey,
+    membership_prop_key: i64,
+    group_eid: EntityId,
+    member_eid: EntityId,
+    now: time::OffsetDateTime,
+) -> (Cow<'static, str>, Params<D>) {
+    (
+        indoc! {
+            "
+            INSERT INTO ent_rel (dir_key, prop_key, subject_eid, object_eid, upd)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT DO NOTHING
+            "
+        }
+        .into(),
+        params!(
+            dir_key.0,
+            membership_prop_key,
+            group_eid.to_blob(),
+            member_eid.to_blob(),
+            now.unix_timestamp()
+        ),
+    )
+}
+
+pub fn delete_entity_sessions_stmt<D: Db>(eid: EntityId) -> (Cow<'static, str>, Params<D>) {
+    (
+        "DELETE FROM session WHERE eid = $1".into(),
+        params!(eid.to_blob()),
+    )
+}
+
+/// Statements removing every trace of a SCIM-provisioned entity
+pub fn delete_scim_entity_stmts<D: Db>(
+    eid: EntityId,
+    now: time::OffsetDateTime,
+) -> Vec<(Cow<'static, str>, Params<D>)> {
+    vec![
+        (
+            "DELETE FROM obj_ident WHERE obj_id = $1".into(),
+            params!(eid.to_blob()),
+        ),
+        (
+            "DELETE FROM obj_text_attr WHERE obj_id = $1".into(),
+            params!(eid.to_blob()),
+        ),
+        entity_event_repo::removed_entity_attribute_events_stmt::<D>(
+            "eid = $1",
+            params!(eid.to_blob()),
+            now.unix_timestamp(),
+        ),
+        (
+            "DELETE FROM ent_attr WHERE eid = $1".into(),
+            params!(eid.to_blob()),
+        ),
+        (
+            "DELETE FROM ent_rel WHERE subject_eid = $1 OR object_eid = $1".into(),
+            params!(eid.to_blob()),
+        ),
+        (
+            "DELETE FROM ent_passkey WHERE eid = $1".into(),
+            params!(eid.to_blob()),
+        ),
+        (
+            "DELETE FROM session WHERE eid = $1".into(),
+            params!(eid.to_blob()),
+        ),
+        (
+            "DELETE FROM scim_resource WHERE eid = $1".into(),
+            params!(eid.to_blob()),
+        ),
+    ]
+}
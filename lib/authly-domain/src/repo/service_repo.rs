@@ -33,6 +33,26 @@ impl Display for PropertyKind {
     }
 }
 
+/// The direction entity attributes of a property are inherited in along membership relations
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Inheritance {
+    /// Members inherit the attributes of the entities they are members of
+    Down,
+    /// Entities inherit the attributes of their members
+    Up,
+    Both,
+    /// The attributes only apply to the entity they are assigned to
+    #[default]
+    None,
+}
+
+impl Display for Inheritance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.serialize(f)
+    }
+}
+
 pub async fn find_service_label_by_eid(deps: &impl Db, eid: ServiceId) -> DbResult<Option<String>> {
     struct SvcLabel(String);
 
@@ -117,6 +137,33 @@ pub async fn get_svc_local_k8s_account_name(
         }))
 }
 
+/// List the local Kubernetes service accounts of all services, as `(service, namespace, account)`
+pub async fn list_local_k8s_service_accounts(
+    deps: &impl Db,
+    builtins: &Builtins,
+) -> DbResult<Vec<(ServiceId, String, String)>> {
+    struct SvcK8sAccount(ServiceId, String);
+
+    impl FromRow for SvcK8sAccount {
+        fn from_row(row: &mut impl Row) -> Self {
+            Self(row.get_id("obj_id"), row.get_text("value"))
+        }
+    }
+
+    Ok(deps
+        .query_map::<SvcK8sAccount>(
+            "SELECT obj_id, value FROM obj_text_attr WHERE prop_key = $1".into(),
+            params!(builtins.prop_key(BuiltinProp::K8sLocalServiceAccount)),
+        )
+        .await?
+        .into_iter()
+        .filter_map(|SvcK8sAccount(svc_eid, account)| {
+            let (namespace, account) = account.split_once('/')?;
+            Some((svc_eid, namespace.to_string(), account.to_string()))
+        })
+        .collect())
+}
+
 pub async fn get_service_property_mapping(
     deps: &impl Db,
     svc_eid: ServiceId,
@@ -164,6 +211,37 @@ pub async fn get_service_property_mapping(
     Ok(mapping)
 }
 
+/// Find the label of the namespace a resource attribute belongs to, whichever services have access to it
+pub async fn find_resource_attr_namespace_label(
+    deps: &impl Db,
+    attr_id: AttrId,
+) -> DbResult<Option<String>> {
+    struct NsLabel(String);
+
+    impl FromRow for NsLabel {
+        fn from_row(row: &mut impl Row) -> Self {
+            Self(row.get_text("label"))
+        }
+    }
+
+    Ok(deps
+        .query_map_opt::<NsLabel>(
+            indoc! {
+                "
+                SELECT ns.label label
+                FROM attr a
+                JOIN prop p ON p.key = a.prop_key
+                JOIN namespace ns ON ns.key = p.ns_key
+                WHERE a.id = $1 AND p.kind = $2
+                "
+            }
+            .into(),
+            params!(attr_id.to_blob(), format!("{}", PropertyKind::Resource)),
+        )
+        .await?
+        .map(|label| label.0))
+}
+
 pub struct SvcNamespaceWithMetadata {
     pub id: AnyId,
     pub label: String,
@@ -237,3 +315,32 @@ pub async fn list_service_hosts(deps: &impl Db, svc_eid: ServiceId) -> DbResult<
 
     Ok(serde_json::from_str(&row.hosts_json).unwrap_or_default())
 }
+
+/// List all services along with their labels, ordered by label
+pub async fn list_service_labels(deps: &impl Db) -> DbResult<Vec<(ServiceId, String)>> {
+    struct TypedRow(ServiceId, String);
+
+    impl FromRow for TypedRow {
+        fn from_row(row: &mut impl Row) -> Self {
+            Self(row.get_id("svc_eid"), row.get_text("label"))
+        }
+    }
+
+    Ok(deps
+        .query_map::<TypedRow>(
+            indoc! {
+                "
+                SELECT svc.svc_eid, ns.label
+                FROM svc
+                JOIN namespace ns ON ns.id = svc.svc_eid
+                ORDER BY ns.label
+                "
+            }
+            .into(),
+            params!(),
+        )
+        .await?
+        .into_iter()
+        .map(|TypedRow(svc_eid, label)| (svc_eid, label))
+        .collect())
+}
@@ -1,16 +1,30 @@
 use authly_common::id::EntityId;
 use authly_db::{param::ToBlob, params, Db, DbError, DbResult, Row, TryFromRow};
+use indoc::indoc;
 use time::OffsetDateTime;
 
-use crate::session::{Session, SessionToken};
+use crate::session::{AuthClass, Session, SessionKind, SessionToken};
 
 pub async fn store_session(deps: &impl Db, session: &Session) -> DbResult<()> {
     deps.execute(
-        "INSERT INTO session (token, eid, expires_at) VALUES ($1, $2, $3)".into(),
+        indoc! {
+            "
+            INSERT INTO session (
+                token, eid, expires_at, persistent, created_at, last_seen, auth_class, authenticated_at
+            )
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            "
+        }
+        .into(),
         params!(
             session.token.0.clone(),
             session.eid.to_blob(),
-            session.expires_at.unix_timestamp()
+            session.expires_at.unix_timestamp(),
+            (session.kind == SessionKind::Persistent) as i64,
+            session.created_at.unix_timestamp(),
+            session.last_seen.unix_timestamp(),
+            session.auth_class as i64,
+            session.authenticated_at.unix_timestamp()
         ),
     )
     .await?;
@@ -18,32 +32,105 @@ pub async fn store_session(deps: &impl Db, session: &Session) -> DbResult<()> {
     Ok(())
 }
 
-pub async fn get_session(deps: &impl Db, token: SessionToken) -> DbResult<Option<Session>> {
-    struct SessionData(EntityId, OffsetDateTime);
-
-    impl TryFromRow for SessionData {
-        type Error = DbError;
+impl TryFromRow for Session {
+    type Error = DbError;
 
-        fn try_from_row(row: &mut impl Row) -> Result<Self, Self::Error> {
-            Ok(Self(row.get_id("eid"), row.get_datetime("expires_at")?))
-        }
+    fn try_from_row(row: &mut impl Row) -> Result<Self, Self::Error> {
+        Ok(Self {
+            token: SessionToken(row.get_blob("token")),
+            eid: row.get_id("eid"),
+            expires_at: row.get_datetime("expires_at")?,
+            kind: if row.get_int("persistent") != 0 {
+                SessionKind::Persistent
+            } else {
+                SessionKind::Default
+            },
+            created_at: row.get_datetime("created_at")?,
+            last_seen: row.get_datetime("last_seen")?,
+            auth_class: AuthClass::try_from(row.get_int("auth_class") as u8)
+                .map_err(|_| DbError::Other("invalid session auth class".into()))?,
+            authenticated_at: row.get_datetime("authenticated_at")?,
+        })
     }
+}
 
-    let Some(SessionData(eid, expires_at)) = deps
-        .query_filter_map::<SessionData>(
-            "SELECT eid, expires_at FROM session WHERE token = $1".into(),
-            params!(token.0.clone()),
+pub async fn get_session(deps: &impl Db, token: SessionToken) -> DbResult<Option<Session>> {
+    Ok(deps
+        .query_filter_map::<Session>(
+            indoc! {
+                "
+                SELECT token, eid, expires_at, persistent, created_at, last_seen, auth_class, authenticated_at
+                FROM session
+                WHERE token = $1
+                "
+            }
+            .into(),
+            params!(token.0),
         )
         .await?
         .into_iter()
-        .next()
-    else {
-        return Ok(None);
-    };
-
-    Ok(Some(Session {
-        token,
-        eid,
-        expires_at,
-    }))
+        .next())
+}
+
+pub async fn list_entity_sessions(
+    deps: &impl Db,
+    eid: EntityId,
+    now: OffsetDateTime,
+) -> DbResult<Vec<Session>> {
+    deps.query_filter_map(
+        indoc! {
+            "
+            SELECT token, eid, expires_at, persistent, created_at, last_seen, auth_class, authenticated_at
+            FROM session
+            WHERE eid = $1 AND expires_at > $2
+            ORDER BY expires_at DESC
+            "
+        }
+        .into(),
+        params!(eid.to_blob(), now.unix_timestamp()),
+    )
+    .await
+}
+
+pub async fn update_session_last_seen(
+    deps: &impl Db,
+    token: &SessionToken,
+    last_seen: OffsetDateTime,
+) -> DbResult<()> {
+    deps.execute(
+        "UPDATE session SET last_seen = $1 WHERE token = $2".into(),
+        params!(last_seen.unix_timestamp(), token.0.clone()),
+    )
+    .await?;
+
+    Ok(())
+}
+
+pub async fn update_session_auth(
+    deps: &impl Db,
+    token: &SessionToken,
+    auth_class: AuthClass,
+    authenticated_at: OffsetDateTime,
+) -> DbResult<()> {
+    deps.execute(
+        "UPDATE session SET auth_class = $1, authenticated_at = $2 WHERE token = $3".into(),
+        params!(
+            auth_class as i64,
+            authenticated_at.unix_timestamp(),
+            token.0.clone()
+        ),
+    )
+    .await?;
+
+    Ok(())
+}
+
+pub async fn delete_session(deps: &impl Db, token: &SessionToken) -> DbResult<()> {
+    deps.execute(
+        "DELETE FROM session WHERE token = $1".into(),
+        params!(token.0.clone()),
+    )
+    .await?;
+
+    Ok(())
 }
@@ -0,0 +1,69 @@
+use authly_common::id::{kind::IdKind, GroupId, Id128, PersonaId};
+use authly_db::{param::ToBlob, params, Db, DbResult, FromRow, Row};
+use indoc::indoc;
+use time::OffsetDateTime;
+
+use crate::stats::DirectoryStats;
+
+struct Count(u64);
+
+impl FromRow for Count {
+    fn from_row(row: &mut impl Row) -> Self {
+        Self(row.get_int("count") as u64)
+    }
+}
+
+pub async fn count_active_sessions(deps: &impl Db, now: OffsetDateTime) -> DbResult<u64> {
+    Ok(deps
+        .query_map_opt::<Count>(
+            "SELECT COUNT(*) AS count FROM session WHERE expires_at > $1".into(),
+            params!(now.unix_timestamp()),
+        )
+        .await?
+        .map(|count| count.0)
+        .unwrap_or(0))
+}
+
+/// The leading kind byte of the stored form of an ID
+fn kind_prefix<K: IdKind>() -> Vec<u8> {
+    Id128::<K>::from_raw_array([0; 16]).to_blob()[..1].to_vec()
+}
+
+pub async fn directory_stats(deps: &impl Db) -> DbResult<DirectoryStats> {
+    impl FromRow for DirectoryStats {
+        fn from_row(row: &mut impl Row) -> Self {
+            Self {
+                directories: row.get_int("directories") as u64,
+                personas: row.get_int("personas") as u64,
+                groups: row.get_int("groups") as u64,
+                services: row.get_int("services") as u64,
+                policies: row.get_int("policies") as u64,
+            }
+        }
+    }
+
+    Ok(deps
+        .query_map_opt::<DirectoryStats>(
+            indoc! {
+                "
+                WITH ent(eid) AS (
+                    SELECT obj_id FROM obj_ident
+                    UNION SELECT obj_id FROM obj_text_attr
+                    UNION SELECT eid FROM ent_attr
+                    UNION SELECT subject_eid FROM ent_rel
+                    UNION SELECT object_eid FROM ent_rel
+                )
+                SELECT
+                    (SELECT COUNT(*) FROM directory) AS directories,
+                    (SELECT COUNT(*) FROM ent WHERE substr(eid, 1, 1) = $1) AS personas,
+                    (SELECT COUNT(*) FROM ent WHERE substr(eid, 1, 1) = $2) AS groups,
+                    (SELECT COUNT(*) FROM svc) AS services,
+                    (SELECT COUNT(*) FROM policy) AS policies
+                "
+            }
+            .into(),
+            params!(kind_prefix::<PersonaId>(), kind_prefix::<GroupId>()),
+        )
+        .await?
+        .unwrap_or_default())
+}
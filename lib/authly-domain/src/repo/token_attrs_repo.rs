@@ -0,0 +1,82 @@
+use authly_common::id::{AttrId, EntityId, Id128DynamicArrayConv, ServiceId};
+use authly_db::{param::ToBlob, params, Db, DbResult, FromRow, Row};
+use fnv::FnvHashSet;
+use indoc::indoc;
+
+/// Store the attribute set left out of an access token under its reference.
+///
+/// Storing the same reference again extends its lifetime.
+/// Expired attribute sets are purged along the way.
+pub async fn upsert_token_attrs_ref(
+    deps: &impl Db,
+    eid: EntityId,
+    id: &str,
+    svc_eid: ServiceId,
+    attrs: &FnvHashSet<AttrId>,
+    expires_at: time::OffsetDateTime,
+    now: time::OffsetDateTime,
+) -> DbResult<()> {
+    let attrs: Vec<u8> = attrs
+        .iter()
+        .flat_map(|attr_id| attr_id.to_array_dynamic())
+        .collect();
+
+    deps.transact(vec![
+        (
+            "DELETE FROM token_attrs_ref WHERE expires_at < $1".into(),
+            params!(now.unix_timestamp()),
+        ),
+        (
+            indoc! {
+                "
+                INSERT INTO token_attrs_ref (eid, id, svc_eid, attrs, expires_at)
+                VALUES ($1, $2, $3, $4, $5)
+                ON CONFLICT DO UPDATE SET expires_at = max(expires_at, $5)
+                "
+            }
+            .into(),
+            params!(
+                eid.to_blob(),
+                id,
+                svc_eid.to_blob(),
+                attrs,
+                expires_at.unix_timestamp()
+            ),
+        ),
+    ])
+    .await?
+    .into_result()?;
+
+    Ok(())
+}
+
+/// Find the attribute set stored under a reference, unless it has expired
+pub async fn find_token_attrs_ref(
+    deps: &impl Db,
+    eid: EntityId,
+    id: &str,
+    svc_eid: ServiceId,
+    now: time::OffsetDateTime,
+) -> DbResult<Option<FnvHashSet<AttrId>>> {
+    struct TokenAttrs(FnvHashSet<AttrId>);
+
+    impl FromRow for TokenAttrs {
+        fn from_row(row: &mut impl Row) -> Self {
+            Self(row.get_ids_concatenated("attrs").collect())
+        }
+    }
+
+    Ok(deps
+        .query_map_opt::<TokenAttrs>(
+            indoc! {
+                "
+                SELECT attrs FROM token_attrs_ref
+                WHERE eid = $1 AND id = $2 AND svc_eid = $3 AND expires_at > $4
+                "
+            }
+            .into(),
+            params!(eid.to_blob(), id, svc_eid.to_blob(), now.unix_timestamp()),
+        )
+        .await?
+        .map(|attrs| attrs.0))
+}
@@ -0,0 +1,160 @@
+use aes_gcm_siv::aead::Aead;
+use anyhow::{anyhow, Context};
+use authly_db::{params, Db, DbResult, FromRow, Row};
+use indoc::indoc;
+use rcgen::{KeyPair, PKCS_ECDSA_P256_SHA256};
+use rustls::pki_types::PrivateKeyDer;
+
+use crate::{
+    encryption::{random_nonce, CryptoError, DecryptedDeks},
+    id::BuiltinProp,
+    token_signing::TokenSigningKey,
+};
+
+/// Load the dedicated token signing keys, the current key first
+pub async fn load_token_signing_keys(
+    deps: &impl Db,
+    deks: &DecryptedDeks,
+) -> Result<Vec<TokenSigningKey>, CryptoError> {
+    struct Output(i64, bool, [u8; 12], Vec<u8>);
+
+    impl FromRow for Output {
+        fn from_row(row: &mut impl Row) -> Self {
+            Self(
+                row.get_int("created_at"),
+                row.get_int("supersedes_instance") != 0,
+                row.get_blob_array("private_key_nonce"),
+                row.get_blob("private_key_ciph"),
+            )
+        }
+    }
+
+    let rows: Vec<Output> = deps
+        .query_map(
+            indoc! {
+                "
+                SELECT created_at, supersedes_instance, private_key_nonce, private_key_ciph
+                FROM token_signing_key
+                ORDER BY created_at DESC, rowid DESC
+                "
+            }
+            .into(),
+            params!(),
+        )
+        .await?;
+
+    if rows.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let dek = deks
+        .get(BuiltinProp::AuthlyInstance.into())
+        .map_err(CryptoError::Crypto)?;
+
+    let mut keys = Vec::with_capacity(rows.len());
+
+    for Output(created_at, supersedes_instance, nonce, private_key_ciph) in rows {
+        let private_key_plaintext = dek
+            .aes()
+            .decrypt(&nonce.into(), private_key_ciph.as_ref())
+            .context("FATAL: Encryption key has changed, unable to decrypt token signing key")
+            .map_err(CryptoError::Crypto)?;
+
+        let private_key_der = PrivateKeyDer::try_from(private_key_plaintext)
+            .map_err(|msg| CryptoError::Crypto(anyhow!("token signing key: {msg}")))?;
+
+        let created_at = time::OffsetDateTime::from_unix_timestamp(created_at)
+            .map_err(|err| CryptoError::Crypto(err.into()))?;
+
+        keys.push(
+            TokenSigningKey::new(
+                KeyPair::from_der_and_sign_algo(&private_key_der, &PKCS_ECDSA_P256_SHA256)?,
+                created_at,
+            )
+            .with_supersedes_instance(supersedes_instance),
+        );
+    }
+
+    Ok(keys)
+}
+
+pub async fn save_token_signing_key(
+    deps: &impl Db,
+    signing_key: &TokenSigningKey,
+    deks: &DecryptedDeks,
+) -> Result<(), CryptoError> {
+    let private_key_der = signing_key
+        .signer()
+        .private_key_der()
+        .ok_or_else(|| CryptoError::Crypto(anyhow!("token signing key is not exportable")))?;
+
+    let dek = deks
+        .get(BuiltinProp::AuthlyInstance.into())
+        .map_err(CryptoError::Crypto)?;
+    let nonce = random_nonce();
+    let key_ciph = dek
+        .aes()
+        .encrypt(&nonce, private_key_der.as_ref())
+        .map_err(|err| CryptoError::Crypto(err.into()))?;
+
+    deps.execute(
+        indoc! {
+            "
+            INSERT INTO token_signing_key (kid, created_at, supersedes_instance, private_key_nonce, private_key_ciph)
+            VALUES ($1, $2, $3, $4, $5)
+            "
+        }
+        .into(),
+        params!(
+            signing_key.kid().to_string(),
+            signing_key.created_at().unix_timestamp(),
+            signing_key.supersedes_instance() as i64,
+            nonce.to_vec(),
+            key_ciph
+        ),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Whether any dedicated token signing key is stored, i.e. the instance is not in compatibility mode
+pub async fn has_token_signing_keys(deps: &impl Db) -> DbResult<bool> {
+    struct Exists(bool);
+
+    impl FromRow for Exists {
+        fn from_row(row: &mut impl Row) -> Self {
+            Self(row.get_int("present") != 0)
+        }
+    }
+
+    Ok(deps
+        .query_map_opt::<Exists>(
+            "SELECT EXISTS (SELECT 1 FROM token_signing_key) AS present".into(),
+            params!(),
+        )
+        .await?
+        .is_some_and(|exists| exists.0))
+}
+
+/// Delete keys that were superseded before the given time, so no unexpired tokens signed by them can exist
+pub async fn delete_retired_token_signing_keys(
+    deps: &impl Db,
+    superseded_before: time::OffsetDateTime,
+) -> DbResult<usize> {
+    deps.execute(
+        indoc! {
+            "
+            DELETE FROM token_signing_key
+            WHERE EXISTS (
+                SELECT 1 FROM token_signing_key AS newer
+                WHERE newer.created_at > token_signing_key.created_at
+                AND newer.created_at < $1
+            )
+            "
+        }
+        .into(),
+        params!(superseded_before.unix_timestamp()),
+    )
+    .await
+}
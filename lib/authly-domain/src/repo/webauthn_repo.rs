@@ -1,8 +1,13 @@
+use aes_gcm_siv::{aead::Nonce, Aes256GcmSiv};
 use authly_common::id::{PersonaId, PropId};
 use authly_db::{param::ToBlob, params, Db, DbError, DbResult, TryFromRow};
-use indoc::indoc;
+use hexhex::Hex;
+use indoc::formatdoc;
+use tracing::warn;
 use webauthn_rs::prelude::{CredentialID, Passkey};
 
+use crate::{encryption::DecryptedDeks, id::BuiltinProp};
+
 pub struct PasskeyRow {
     pub eid: PersonaId,
     pub passkey: Passkey,
@@ -10,48 +15,121 @@ pub struct PasskeyRow {
     pub last_used: Option<time::OffsetDateTime>,
 }
 
-impl TryFromRow for PasskeyRow {
+/// A passkey row as stored, before decryption
+struct StoredPasskeyRow {
+    eid: PersonaId,
+    cred_id: Vec<u8>,
+    pk_json: String,
+    pk_nonce: Vec<u8>,
+    pk_ciph: Vec<u8>,
+    created: time::OffsetDateTime,
+    last_used: Option<time::OffsetDateTime>,
+}
+
+impl TryFromRow for StoredPasskeyRow {
     type Error = anyhow::Error;
 
     fn try_from_row(row: &mut impl authly_db::Row) -> Result<Self, Self::Error> {
-        Ok(PasskeyRow {
+        Ok(StoredPasskeyRow {
             eid: row.get_id("eid"),
-            passkey: serde_json::from_str(&row.get_text("pk_json"))?,
+            cred_id: row.get_blob("cred_id"),
+            pk_json: row.get_text("pk_json"),
+            pk_nonce: row.get_blob("pk_nonce"),
+            pk_ciph: row.get_blob("pk_ciph"),
             created: row.get_datetime("created_at")?,
             last_used: row.get_opt_datetime("last_used")?,
         })
     }
 }
 
+impl StoredPasskeyRow {
+    /// Legacy rows written before passkey encryption have no nonce
+    fn is_plaintext(&self) -> bool {
+        self.pk_nonce.is_empty()
+    }
+
+    fn decrypt(self, deks: &DecryptedDeks) -> anyhow::Result<PasskeyRow> {
+        let passkey = if self.is_plaintext() {
+            serde_json::from_str(&self.pk_json)?
+        } else {
+            let json = deks.decrypt(BuiltinProp::Passkey.into(), &self.pk_nonce, &self.pk_ciph)?;
+            serde_json::from_slice(&json)?
+        };
+
+        Ok(PasskeyRow {
+            eid: self.eid,
+            passkey,
+            created: self.created,
+            last_used: self.last_used,
+        })
+    }
+}
+
+const SELECT_COLUMNS: &str = "pk.eid, pk.cred_id, pk.pk_json, coalesce(pk.pk_nonce, x'') pk_nonce, coalesce(pk.pk_ciph, x'') pk_ciph, pk.created_at, pk.last_used";
+
+fn decrypt_rows(rows: Vec<StoredPasskeyRow>, deks: &DecryptedDeks) -> Vec<PasskeyRow> {
+    rows.into_iter()
+        .filter_map(|row| {
+            let cred_id = Hex::new(&row.cred_id).to_string();
+            match row.decrypt(deks) {
+                Ok(row) => Some(row),
+                Err(err) => {
+                    warn!(?err, %cred_id, "unable to decrypt passkey");
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+fn encrypt_passkey(
+    passkey: &Passkey,
+    deks: &DecryptedDeks,
+) -> DbResult<(Nonce<Aes256GcmSiv>, Vec<u8>)> {
+    let json =
+        serde_json::to_vec(passkey).map_err(|err| DbError::Other(format!("{err:?}").into()))?;
+
+    deks.encrypt(BuiltinProp::Passkey.into(), &json)
+        .map_err(|err| DbError::Other(format!("{err:?}").into()))
+}
+
 pub async fn list_passkeys_by_entity_ident(
     deps: &impl Db,
     ident_prop_id: PropId,
     ident_fingerprint: &[u8],
+    deks: &DecryptedDeks,
 ) -> DbResult<Vec<PasskeyRow>> {
-    deps.query_filter_map::<PasskeyRow>(
-        indoc! {
-            "
-            SELECT pk.eid, pk.pk_json, pk.created_at, pk.last_used FROM ent_passkey pk
-            JOIN obj_ident i ON i.obj_id = pk.eid
-            WHERE i.prop_key = (SELECT key FROM prop WHERE id = $1)
-                AND i.fingerprint = $2
-            ",
-        }
-        .into(),
-        params!(ident_prop_id.to_blob(), ident_fingerprint.to_blob()),
-    )
-    .await
+    let rows = deps
+        .query_filter_map::<StoredPasskeyRow>(
+            formatdoc! {
+                "
+                SELECT {SELECT_COLUMNS} FROM ent_passkey pk
+                JOIN obj_ident i ON i.obj_id = pk.eid
+                WHERE i.prop_key = (SELECT key FROM prop WHERE id = $1)
+                    AND i.fingerprint = $2
+                ",
+            }
+            .into(),
+            params!(ident_prop_id.to_blob(), ident_fingerprint.to_blob()),
+        )
+        .await?;
+
+    Ok(decrypt_rows(rows, deks))
 }
 
 pub async fn list_passkeys_by_entity_id(
     deps: &impl Db,
     eid: PersonaId,
+    deks: &DecryptedDeks,
 ) -> DbResult<Vec<PasskeyRow>> {
-    deps.query_filter_map::<PasskeyRow>(
-        "SELECT eid, pk_json, created_at, last_used FROM ent_passkey pk WHERE pk.eid = $1".into(),
-        params!(eid.to_blob()),
-    )
-    .await
+    let rows = deps
+        .query_filter_map::<StoredPasskeyRow>(
+            format!("SELECT {SELECT_COLUMNS} FROM ent_passkey pk WHERE pk.eid = $1").into(),
+            params!(eid.to_blob()),
+        )
+        .await?;
+
+    Ok(decrypt_rows(rows, deks))
 }
 
 pub async fn insert_passkey(
@@ -59,14 +137,17 @@ pub async fn insert_passkey(
     persona_id: PersonaId,
     passkey: &Passkey,
     now: time::OffsetDateTime,
+    deks: &DecryptedDeks,
 ) -> DbResult<()> {
+    let (nonce, ciph) = encrypt_passkey(passkey, deks)?;
+
     deps.execute(
-        "INSERT INTO ent_passkey (eid, cred_id, pk_json, created_at, last_used) VALUES ($1, $2, $3, $4, $4)".into(),
+        "INSERT INTO ent_passkey (eid, cred_id, pk_json, pk_nonce, pk_ciph, created_at, last_used) VALUES ($1, $2, '', $3, $4, $5, $5)".into(),
         params!(
             persona_id.to_blob(),
             passkey.cred_id().to_vec(),
-            serde_json::to_string(passkey)
-                .map_err(|err| { DbError::Other(format!("{err:?}").into()) })?,
+            nonce.to_vec(),
+            ciph,
             now.unix_timestamp()
         ),
     )
@@ -79,14 +160,17 @@ pub async fn update_passkey(
     persona_id: PersonaId,
     passkey: &Passkey,
     now: time::OffsetDateTime,
+    deks: &DecryptedDeks,
 ) -> DbResult<()> {
+    let (nonce, ciph) = encrypt_passkey(passkey, deks)?;
+
     let row_count = deps
         .execute(
-            "UPDATE ent_passkey SET pk_json = $1, last_used = $2 WHERE eid = $3 AND cred_id = $4"
+            "UPDATE ent_passkey SET pk_json = '', pk_nonce = $1, pk_ciph = $2, last_used = $3 WHERE eid = $4 AND cred_id = $5"
                 .into(),
             params!(
-                serde_json::to_string(passkey)
-                    .map_err(|err| { DbError::Other(format!("{err:?}").into()) })?,
+                nonce.to_vec(),
+                ciph,
                 now.unix_timestamp(),
                 persona_id.to_blob(),
                 passkey.cred_id().to_vec()
@@ -103,6 +187,42 @@ pub async fn update_passkey(
     Ok(())
 }
 
+/// Encrypt passkeys that were stored in plaintext before passkey encryption was introduced.
+///
+/// Returns the number of passkeys that were encrypted.
+pub async fn encrypt_plaintext_passkeys(deps: &impl Db, deks: &DecryptedDeks) -> DbResult<usize> {
+    let rows = deps
+        .query_filter_map::<StoredPasskeyRow>(
+            format!("SELECT {SELECT_COLUMNS} FROM ent_passkey pk WHERE pk.pk_nonce IS NULL").into(),
+            params!(),
+        )
+        .await?;
+
+    let mut count = 0;
+
+    for row in rows {
+        let eid = row.eid;
+        let cred_id = row.cred_id.clone();
+        let passkey = match row.decrypt(deks) {
+            Ok(row) => row.passkey,
+            Err(err) => {
+                warn!(?err, cred_id = %Hex::new(&cred_id), "unable to parse plaintext passkey");
+                continue;
+            }
+        };
+        let (nonce, ciph) = encrypt_passkey(&passkey, deks)?;
+
+        count += deps
+            .execute(
+                "UPDATE ent_passkey SET pk_json = '', pk_nonce = $1, pk_ciph = $2 WHERE eid = $3 AND cred_id = $4 AND pk_nonce IS NULL".into(),
+                params!(nonce.to_vec(), ciph, eid.to_blob(), cred_id),
+            )
+            .await?;
+    }
+
+    Ok(count)
+}
+
 pub async fn update_passkey_last_used(
     deps: &impl Db,
     persona_id: PersonaId,
@@ -0,0 +1,73 @@
+//! Request IDs for correlating API responses with server logs.
+//!
+//! A request keeps the `x-request-id` it arrived with, if it has a usable one, otherwise a new ID is generated.
+//! The ID is echoed in the response header, and included in [ApiError](crate::api_error::ApiError) bodies.
+
+use std::future::Future;
+
+use axum::{extract::Request, middleware::Next, response::Response};
+use http::{HeaderMap, HeaderName, HeaderValue};
+use tracing::Instrument;
+
+pub const X_REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+const MAX_LEN: usize = 128;
+
+tokio::task_local! {
+    static REQUEST_ID: RequestId;
+}
+
+#[derive(Clone, Debug)]
+pub struct RequestId(pub String);
+
+impl RequestId {
+    /// The ID sent by the client if it is usable, otherwise a new one
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        headers
+            .get(&X_REQUEST_ID)
+            .and_then(Self::from_header)
+            .unwrap_or_else(Self::generate)
+    }
+
+    fn from_header(value: &HeaderValue) -> Option<Self> {
+        let value = value.to_str().ok()?;
+        if value.is_empty() || value.len() > MAX_LEN {
+            return None;
+        }
+
+        Some(Self(value.to_string()))
+    }
+
+    fn generate() -> Self {
+        Self(hexhex::hex(rand::random::<[u8; 16]>()).to_string())
+    }
+
+    /// The ID of the request currently being handled
+    pub fn current() -> Option<Self> {
+        REQUEST_ID.try_with(Clone::clone).ok()
+    }
+
+    /// Handle a request with this as the current request ID
+    pub async fn scope<F: Future>(self, fut: F) -> F::Output {
+        REQUEST_ID.scope(self, fut).await
+    }
+}
+
+/// Assign a request ID to every request
+pub async fn request_id_middleware(mut req: Request, next: Next) -> Response {
+    let request_id = RequestId::from_headers(req.headers());
+
+    req.extensions_mut().insert(request_id.clone());
+    let span = tracing::info_span!("request", request_id = request_id.0);
+
+    let mut response = request_id
+        .clone()
+        .scope(next.run(req).instrument(span))
+        .await;
+
+    if let Ok(value) = HeaderValue::from_str(&request_id.0) {
+        response.headers_mut().insert(X_REQUEST_ID, value);
+    }
+
+    response
+}
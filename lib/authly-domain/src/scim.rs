@@ -0,0 +1,730 @@
+//! Provisioning of users and groups by SCIM clients.
+//!
+//! SCIM resources live in a dedicated directory, which is created when first used.
+//! The SCIM protocol itself is implemented by the service crate, this module maps resources onto entities.
+
+use std::{borrow::Cow, collections::BTreeMap};
+
+use authly_common::id::{AnyId, AttrId, EntityId, GroupId, PersonaId, ServiceId};
+use authly_db::{Db, DbError, DbResult, Params};
+use fnv::FnvHashSet;
+use rand::Rng;
+
+use crate::{
+    bus::{BusError, ClusterMessage},
+    ctx::{
+        ClusterBus, GetBuiltins, GetClock, GetDb, GetDecryptedDeks, GetHttpClient, GetIdGenerator,
+        GetSettings,
+    },
+    directory::DirKey,
+    encryption::{CryptoError, EncryptedObjIdent},
+    id::BuiltinProp,
+    password::{self, Password, PasswordPolicyError},
+    repo::{
+        crypto_repo, entity_repo,
+        scim_repo::{self, ScimResourceRow, ScimResourceType},
+        service_repo,
+    },
+};
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ScimUserData {
+    pub user_name: String,
+    pub external_id: Option<String>,
+    pub display_name: Option<String>,
+    pub email: Option<String>,
+    pub active: bool,
+    /// A new password for the user. Passwords are write-only, this is never loaded.
+    pub password: Option<Password>,
+    /// Attribute labels of the mapped SCIM attributes, keyed by SCIM attribute name
+    pub attributes: BTreeMap<String, Vec<String>>,
+}
+
+#[derive(Debug)]
+pub struct ScimUser {
+    pub id: PersonaId,
+    pub data: ScimUserData,
+    pub created_at: time::OffsetDateTime,
+    pub updated_at: time::OffsetDateTime,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ScimGroupData {
+    pub display_name: String,
+    pub external_id: Option<String>,
+    pub members: Vec<EntityId>,
+}
+
+#[derive(Debug)]
+pub struct ScimGroup {
+    pub id: GroupId,
+    pub data: ScimGroupData,
+    pub created_at: time::OffsetDateTime,
+    pub updated_at: time::OffsetDateTime,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ScimError {
+    #[error("resource not found")]
+    NotFound,
+
+    #[error("{0} is already in use")]
+    Uniqueness(&'static str),
+
+    #[error("invalid value: {0}")]
+    InvalidValue(String),
+
+    #[error("{0}")]
+    Password(#[from] PasswordPolicyError),
+
+    #[error("password hashing error: {0}")]
+    PasswordHash(anyhow::Error),
+
+    #[error("db error: {0}")]
+    Db(#[from] DbError),
+
+    #[error("cryptography error: {0}")]
+    Crypto(#[from] CryptoError),
+
+    #[error("bus error: {0}")]
+    Bus(#[from] BusError),
+}
+
+/// Issue a new bearer token for a SCIM client acting on behalf of the given service.
+///
+/// Only the hash of the token is stored. The token expires after the configured SCIM token validity.
+pub async fn issue_scim_token(
+    deps: &(impl GetDb + GetSettings + GetClock),
+    svc_eid: ServiceId,
+) -> Result<String, ScimError> {
+    if service_repo::find_service_label_by_eid(deps.get_db(), svc_eid)
+        .await?
+        .is_none()
+    {
+        return Err(ScimError::InvalidValue(format!(
+            "unknown service {svc_eid}"
+        )));
+    }
+
+    let now = deps.get_clock().now();
+    let expires_at = time::Duration::try_from(deps.get_settings().scim_token_validity)
+        .ok()
+        .and_then(|validity| now.checked_add(validity))
+        .ok_or_else(|| ScimError::InvalidValue("invalid token validity".to_string()))?;
+
+    let token_bytes: [u8; 32] = rand::thread_rng().r#gen();
+    let token = hexhex::hex(&token_bytes).to_string();
+
+    scim_repo::insert_scim_token(deps.get_db(), token_hash(&token), svc_eid, now, expires_at)
+        .await?;
+
+    Ok(token)
+}
+
+/// Authenticate a SCIM bearer token, returning the service it was issued to.
+///
+/// Expired and revoked tokens are not accepted.
+pub async fn authenticate_scim_token(
+    deps: &(impl GetDb + GetClock),
+    token: &str,
+) -> DbResult<Option<ServiceId>> {
+    scim_repo::find_scim_token_service(deps.get_db(), token_hash(token), deps.get_clock().now())
+        .await
+}
+
+/// Revoke all the tokens issued to SCIM clients acting on behalf of the given service
+pub async fn revoke_scim_tokens(
+    deps: &(impl GetDb + GetClock),
+    svc_eid: ServiceId,
+) -> DbResult<usize> {
+    scim_repo::revoke_scim_tokens(deps.get_db(), svc_eid, deps.get_clock().now()).await
+}
+
+fn token_hash(token: &str) -> [u8; 32] {
+    blake3::hash(token.as_bytes()).into()
+}
+
+pub async fn list_users(
+    deps: &(impl GetDb + GetDecryptedDeks + GetSettings),
+) -> Result<Vec<ScimUser>, ScimError> {
+    let mut users = vec![];
+
+    for row in scim_repo::list_scim_resources(deps.get_db(), ScimResourceType::User).await? {
+        users.push(load_user(deps, row).await?);
+    }
+
+    Ok(users)
+}
+
+pub async fn get_user(
+    deps: &(impl GetDb + GetDecryptedDeks + GetSettings),
+    id: PersonaId,
+) -> Result<ScimUser, ScimError> {
+    let row = scim_repo::get_scim_resource(deps.get_db(), ScimResourceType::User, id.upcast())
+        .await?
+        .ok_or(ScimError::NotFound)?;
+
+    load_user(deps, row).await
+}
+
+pub async fn create_user(
+    deps: &(impl GetDb
+          + GetBuiltins
+          + GetDecryptedDeks
+          + GetSettings
+          + GetHttpClient
+          + GetIdGenerator
+          + GetClock
+          + ClusterBus),
+    data: ScimUserData,
+) -> Result<ScimUser, ScimError> {
+    let id: PersonaId = deps.get_id_generator().generate();
+    write_user(deps, id, data).await?;
+    get_user(deps, id).await
+}
+
+/// Replace all the data of an existing user
+pub async fn replace_user(
+    deps: &(impl GetDb
+          + GetBuiltins
+          + GetDecryptedDeks
+          + GetSettings
+          + GetHttpClient
+          + GetIdGenerator
+          + GetClock
+          + ClusterBus),
+    id: PersonaId,
+    data: ScimUserData,
+) -> Result<ScimUser, ScimError> {
+    scim_repo::get_scim_resource::<PersonaId>(deps.get_db(), ScimResourceType::User, id.upcast())
+        .await?
+        .ok_or(ScimError::NotFound)?;
+
+    write_user(deps, id, data).await?;
+    get_user(deps, id).await
+}
+
+/// Delete a user, including its identities, attributes, memberships and sessions
+pub async fn delete_user(
+    deps: &(impl GetDb + GetIdGenerator + GetClock + ClusterBus),
+    id: PersonaId,
+) -> Result<(), ScimError> {
+    delete_resource(deps, ScimResourceType::User, id.upcast()).await
+}
+
+pub async fn list_groups(deps: &(impl GetDb + GetBuiltins)) -> Result<Vec<ScimGroup>, ScimError> {
+    let mut groups = vec![];
+
+    for row in scim_repo::list_scim_resources(deps.get_db(), ScimResourceType::Group).await? {
+        groups.push(load_group(deps, row).await?);
+    }
+
+    Ok(groups)
+}
+
+pub async fn get_group(
+    deps: &(impl GetDb + GetBuiltins),
+    id: GroupId,
+) -> Result<ScimGroup, ScimError> {
+    let row = scim_repo::get_scim_resource(deps.get_db(), ScimResourceType::Group, id.upcast())
+        .await?
+        .ok_or(ScimError::NotFound)?;
+
+    load_group(deps, row).await
+}
+
+pub async fn create_group(
+    deps: &(impl GetDb + GetBuiltins + GetIdGenerator + GetClock + ClusterBus),
+    data: ScimGroupData,
+) -> Result<ScimGroup, ScimError> {
+    let id: GroupId = deps.get_id_generator().generate();
+    write_group(deps, id, data).await?;
+    get_group(deps, id).await
+}
+
+/// Replace all the data of an existing group, including its members
+pub async fn replace_group(
+    deps: &(impl GetDb + GetBuiltins + GetIdGenerator + GetClock + ClusterBus),
+    id: GroupId,
+    data: ScimGroupData,
+) -> Result<ScimGroup, ScimError> {
+    scim_repo::get_scim_resource::<GroupId>(deps.get_db(), ScimResourceType::Group, id.upcast())
+        .await?
+        .ok_or(ScimError::NotFound)?;
+
+    write_group(deps, id, data).await?;
+    get_group(deps, id).await
+}
+
+pub async fn delete_group(
+    deps: &(impl GetDb + GetIdGenerator + GetClock + ClusterBus),
+    id: GroupId,
+) -> Result<(), ScimError> {
+    delete_resource(deps, ScimResourceType::Group, id.upcast()).await
+}
+
+async fn load_user(
+    deps: &(impl GetDb + GetDecryptedDeks + GetSettings),
+    row: ScimResourceRow<PersonaId>,
+) -> Result<ScimUser, ScimError> {
+    let deks = deps.load_decrypted_deks();
+    let obj_id: AnyId = row.id.upcast();
+
+    let user_name = crypto_repo::load_decrypt_obj_ident(
+        deps.get_db(),
+        obj_id,
+        BuiltinProp::Username.into(),
+        &deks,
+    )
+    .await?
+    .unwrap_or_default();
+    let email = crypto_repo::load_decrypt_obj_ident(
+        deps.get_db(),
+        obj_id,
+        BuiltinProp::Email.into(),
+        &deks,
+    )
+    .await?;
+
+    let entity_attrs = entity_repo::list_entity_attrs(deps.get_db(), row.id.upcast()).await?;
+    let mappings = deps.get_settings().scim_attribute_mapping.clone();
+    let mut attributes = BTreeMap::new();
+
+    for mapping in mappings {
+        let labels: Vec<String> = scim_repo::list_labeled_property_attrs(
+            deps.get_db(),
+            &mapping.namespace,
+            &mapping.property,
+        )
+        .await?
+        .into_iter()
+        .filter(|(attr_id, _)| entity_attrs.contains(attr_id))
+        .map(|(_, label)| label)
+        .collect();
+
+        if !labels.is_empty() {
+            attributes.insert(mapping.scim_attribute, labels);
+        }
+    }
+
+    Ok(ScimUser {
+        id: row.id,
+        data: ScimUserData {
+            user_name,
+            external_id: row.external_id,
+            display_name: row.display_name,
+            email,
+            active: row.active,
+            password: None,
+            attributes,
+        },
+        created_at: row.created_at,
+        updated_at: row.upd,
+    })
+}
+
+async fn load_group(
+    deps: &(impl GetDb + GetBuiltins),
+    row: ScimResourceRow<GroupId>,
+) -> Result<ScimGroup, ScimError> {
+    let members = scim_repo::list_group_members(
+        deps.get_db(),
+        deps.get_builtins()
+            .prop_key(BuiltinProp::RelEntityMembership),
+        row.id.upcast(),
+    )
+    .await?;
+
+    Ok(ScimGroup {
+        id: row.id,
+        data: ScimGroupData {
+            display_name: row.display_name.unwrap_or_default(),
+            external_id: row.external_id,
+            members,
+        },
+        created_at: row.created_at,
+        updated_at: row.upd,
+    })
+}
+
+async fn write_user(
+    deps: &(impl GetDb
+          + GetBuiltins
+          + GetDecryptedDeks
+          + GetSettings
+          + GetHttpClient
+          + GetIdGenerator
+          + GetClock
+          + ClusterBus),
+    id: PersonaId,
+    mut data: ScimUserData,
+) -> Result<(), ScimError> {
+    let now = deps.get_clock().now();
+    let ident_normalization = deps.get_settings().ident_normalization;
+    data.user_name = ident_normalization
+        .normalize(BuiltinProp::Username.into(), &data.user_name)
+        .into_owned();
+    data.email = data.email.map(|email| {
+        ident_normalization
+            .normalize(BuiltinProp::Email.into(), &email)
+            .into_owned()
+    });
+
+    let (dir_key, dir_id) =
+        scim_repo::get_or_create_scim_directory(deps.get_db(), deps.get_id_generator().generate())
+            .await?;
+
+    check_ident_available(deps, BuiltinProp::Username, "userName", &data.user_name, id).await?;
+    let user_name_changed = check_username_change(deps, id, &data.user_name, now).await?;
+    if let Some(email) = &data.email {
+        check_ident_available(deps, BuiltinProp::Email, "email", email, id).await?;
+    }
+
+    let attr_diff = diff_mapped_attributes(deps, id.upcast(), &data.attributes).await?;
+
+    let password_hash = match data.password.take() {
+        Some(password) => {
+            password::check_password(deps, &password).await?;
+            Some(
+                password::hash_password(password)
+                    .await
+                    .map_err(ScimError::PasswordHash)?,
+            )
+        }
+        None => None,
+    };
+
+    let (user_name, email) = {
+        let deks = deps.get_decrypted_deks();
+        let encrypt = |prop: BuiltinProp, value: &str| {
+            EncryptedObjIdent::encrypt(prop.into(), value, &deks).map_err(CryptoError::Crypto)
+        };
+
+        (
+            user_name_changed
+                .then(|| encrypt(BuiltinProp::Username, &data.user_name))
+                .transpose()?,
+            data.email
+                .as_deref()
+                .map(|email| encrypt(BuiltinProp::Email, email))
+                .transpose()?,
+        )
+    };
+
+    let stmts = user_txn_statements(
+        deps.get_db(),
+        UserTxn {
+            dir_key,
+            id,
+            data: &data,
+            user_name,
+            email,
+            email_prop_key: deps.get_builtins().prop_key(BuiltinProp::Email),
+            password_hash,
+            password_hash_prop_key: deps.get_builtins().prop_key(BuiltinProp::PasswordHash),
+            attr_diff,
+            now,
+        },
+    );
+
+    transact(deps.get_db(), stmts).await?;
+
+    deps.broadcast_to_cluster(ClusterMessage::DirectoryChanged { dir_id })
+        .await?;
+
+    if !data.active {
+        deps.broadcast_to_cluster(ClusterMessage::SessionsRevoked { eid: id.upcast() })
+            .await?;
+    }
+
+    Ok(())
+}
+
+async fn write_group(
+    deps: &(impl GetDb + GetBuiltins + GetIdGenerator + GetClock + ClusterBus),
+    id: GroupId,
+    data: ScimGroupData,
+) -> Result<(), ScimError> {
+    let now = deps.get_clock().now();
+    let (dir_key, dir_id) =
+        scim_repo::get_or_create_scim_directory(deps.get_db(), deps.get_id_generator().generate())
+            .await?;
+    let membership_prop_key = deps
+        .get_builtins()
+        .prop_key(BuiltinProp::RelEntityMembership);
+
+    let stmts = group_txn_statements(deps.get_db(), dir_key, id, &data, membership_prop_key, now);
+
+    transact(deps.get_db(), stmts).await?;
+
+    deps.broadcast_to_cluster(ClusterMessage::DirectoryChanged { dir_id })
+        .await?;
+
+    Ok(())
+}
+
+async fn delete_resource(
+    deps: &(impl GetDb + GetIdGenerator + GetClock + ClusterBus),
+    resource_type: ScimResourceType,
+    eid: EntityId,
+) -> Result<(), ScimError> {
+    let Some(row) =
+        scim_repo::get_scim_resource::<EntityId>(deps.get_db(), resource_type, eid).await?
+    else {
+        return Err(ScimError::NotFound);
+    };
+
+    let (_, dir_id) =
+        scim_repo::get_or_create_scim_directory(deps.get_db(), deps.get_id_generator().generate())
+            .await?;
+
+    transact(
+        deps.get_db(),
+        scim_repo::delete_scim_entity_stmts(row.id, deps.get_clock().now()),
+    )
+    .await?;
+
+    deps.broadcast_to_cluster(ClusterMessage::DirectoryChanged { dir_id })
+        .await?;
+
+    if resource_type == ScimResourceType::User {
+        deps.broadcast_to_cluster(ClusterMessage::SessionsRevoked { eid: row.id })
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Fail if the identity is already owned by another object
+async fn check_ident_available(
+    deps: &(impl GetDb + GetDecryptedDeks + GetSettings),
+    prop: BuiltinProp,
+    scim_attribute: &'static str,
+    ident: &str,
+    id: PersonaId,
+) -> Result<(), ScimError> {
+    match crypto_repo::lookup_obj_ident(deps, prop.into(), ident).await? {
+        Some(owner) if owner != id.upcast() => Err(ScimError::Uniqueness(scim_attribute)),
+        _ => Ok(()),
+    }
+}
+
+/// Check that the user may be given the username, returning whether it's a change.
+///
+/// Reserved usernames can't be taken, and the `USERNAME_CHANGE_INTERVAL` must have passed since the username was last set.
+async fn check_username_change(
+    deps: &(impl GetDb + GetBuiltins + GetDecryptedDeks + GetSettings),
+    id: PersonaId,
+    user_name: &str,
+    now: time::OffsetDateTime,
+) -> Result<bool, ScimError> {
+    let current = crypto_repo::load_decrypt_obj_ident(
+        deps.get_db(),
+        id.upcast(),
+        BuiltinProp::Username.into(),
+        &deps.load_decrypted_deks(),
+    )
+    .await?;
+
+    if current.as_deref() == Some(user_name) {
+        return Ok(false);
+    }
+
+    let username_policy = deps.get_settings().username_policy.clone();
+    if username_policy.is_reserved(user_name) {
+        return Err(ScimError::InvalidValue(format!(
+            "userName `{user_name}` is reserved"
+        )));
+    }
+
+    if let (Some(_), Some(change_interval)) = (current, username_policy.change_interval) {
+        let last_changed = scim_repo::find_obj_ident_upd(
+            deps.get_db(),
+            id.upcast(),
+            deps.get_builtins().prop_key(BuiltinProp::Username),
+        )
+        .await?;
+
+        if let Some(next_change) = last_changed.map(|upd| upd + change_interval) {
+            if now < next_change {
+                return Err(ScimError::InvalidValue(format!(
+                    "userName can't be changed again until {next_change}"
+                )));
+            }
+        }
+    }
+
+    Ok(true)
+}
+
+#[derive(Default)]
+struct AttrDiff {
+    added: Vec<AttrId>,
+    removed: Vec<AttrId>,
+}
+
+/// Compute the attribute assignments needed to make the mapped properties match the SCIM data
+async fn diff_mapped_attributes(
+    deps: &(impl GetDb + GetSettings),
+    eid: EntityId,
+    attributes: &BTreeMap<String, Vec<String>>,
+) -> Result<AttrDiff, ScimError> {
+    let current = entity_repo::list_entity_attrs(deps.get_db(), eid).await?;
+    let mappings = deps.get_settings().scim_attribute_mapping.clone();
+    let mut diff = AttrDiff::default();
+
+    for mapping in mappings {
+        let property_attrs = scim_repo::list_labeled_property_attrs(
+            deps.get_db(),
+            &mapping.namespace,
+            &mapping.property,
+        )
+        .await?;
+
+        let mut desired: FnvHashSet<AttrId> = Default::default();
+
+        for label in attributes
+            .get(&mapping.scim_attribute)
+            .into_iter()
+            .flatten()
+        {
+            let Some((attr_id, _)) = property_attrs.iter().find(|(_, l)| l == label) else {
+                return Err(ScimError::InvalidValue(format!(
+                    "unknown {} `{label}`",
+                    mapping.scim_attribute
+                )));
+            };
+            desired.insert(*attr_id);
+        }
+
+        for (attr_id, _) in property_attrs {
+            match (current.contains(&attr_id), desired.contains(&attr_id)) {
+                (false, true) => diff.added.push(attr_id),
+                (true, false) => diff.removed.push(attr_id),
+                _ => {}
+            }
+        }
+    }
+
+    Ok(diff)
+}
+
+struct UserTxn<'a> {
+    dir_key: DirKey,
+    id: PersonaId,
+    data: &'a ScimUserData,
+    /// Only written when the username changed
+    user_name: Option<EncryptedObjIdent>,
+    email: Option<EncryptedObjIdent>,
+    email_prop_key: i64,
+    password_hash: Option<String>,
+    password_hash_prop_key: i64,
+    attr_diff: AttrDiff,
+    now: time::OffsetDateTime,
+}
+
+fn user_txn_statements<D: Db>(_db: &D, txn: UserTxn) -> Vec<(Cow<'static, str>, Params<D>)> {
+    let eid: EntityId = txn.id.upcast();
+    let mut stmts = vec![scim_repo::upsert_scim_resource_stmt::<D>(
+        txn.dir_key,
+        ScimResourceType::User,
+        eid,
+        txn.data.external_id.as_deref(),
+        txn.data.display_name.as_deref(),
+        txn.data.active,
+        txn.now,
+    )];
+
+    if let Some(user_name) = txn.user_name {
+        stmts.push(user_name.upsert_stmt::<D>(
+            txn.dir_key.0,
+            txn.id.upcast(),
+            txn.now.unix_timestamp(),
+        ));
+    }
+
+    stmts.push(match txn.email {
+        Some(email) => {
+            email.upsert_stmt::<D>(txn.dir_key.0, txn.id.upcast(), txn.now.unix_timestamp())
+        }
+        None => scim_repo::delete_obj_ident_stmt::<D>(eid, txn.email_prop_key),
+    });
+
+    if let Some(password_hash) = txn.password_hash {
+        stmts.push(scim_repo::upsert_obj_text_attr_stmt::<D>(
+            txn.dir_key,
+            eid,
+            txn.password_hash_prop_key,
+            password_hash,
+            txn.now,
+        ));
+    }
+
+    // deactivated users are logged out
+    if !txn.data.active {
+        stmts.push(scim_repo::delete_entity_sessions_stmt::<D>(eid));
+    }
+
+    for attr_id in txn.attr_diff.removed {
+        stmts.extend(scim_repo::delete_ent_attr_stmts::<D>(
+            txn.dir_key,
+            eid,
+            attr_id,
+            txn.now,
+        ));
+    }
+
+    for attr_id in txn.attr_diff.added {
+        stmts.push(scim_repo::insert_ent_attr_stmt::<D>(
+            txn.dir_key,
+            eid,
+            attr_id,
+            txn.now,
+        ));
+    }
+
+    stmts
+}
+
+fn group_txn_statements<D: Db>(
+    _db: &D,
+    dir_key: DirKey,
+    id: GroupId,
+    data: &ScimGroupData,
+    membership_prop_key: i64,
+    now: time::OffsetDateTime,
+) -> Vec<(Cow<'static, str>, Params<D>)> {
+    let eid: EntityId = id.upcast();
+    let mut stmts = vec![
+        scim_repo::upsert_scim_resource_stmt::<D>(
+            dir_key,
+            ScimResourceType::Group,
+            eid,
+            data.external_id.as_deref(),
+            Some(&data.display_name),
+            true,
+            now,
+        ),
+        scim_repo::delete_group_members_stmt::<D>(dir_key, membership_prop_key, eid),
+    ];
+
+    for member in &data.members {
+        stmts.push(scim_repo::insert_group_member_stmt::<D>(
+            dir_key,
+            membership_prop_key,
+            eid,
+            *member,
+            now,
+        ));
+    }
+
+    stmts
+}
+
+async fn transact<D: Db>(db: &D, stmts: Vec<(Cow<'static, str>, Params<D>)>) -> DbResult<()> {
+    for result in db.transact(stmts).await? {
+        result?;
+    }
+
+    Ok(())
+}
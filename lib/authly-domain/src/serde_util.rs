@@ -60,3 +60,79 @@ impl<'de> Deserialize<'de> for UrlSafeBase64 {
         deserializer.deserialize_str(Visit)
     }
 }
+
+/// Serialize to JSON in a canonical form, with object keys sorted and numbers normalized.
+///
+/// Equivalent values produce the same string, regardless of the key order or number formatting of their source,
+/// so that stored JSON only changes when the value does.
+pub fn to_canonical_json(value: &impl Serialize) -> serde_json::Result<String> {
+    let mut out = String::new();
+    write_canonical_json(&serde_json::to_value(value)?, &mut out)?;
+    Ok(out)
+}
+
+fn write_canonical_json(value: &serde_json::Value, out: &mut String) -> serde_json::Result<()> {
+    use serde_json::Value;
+
+    match value {
+        Value::Object(map) => {
+            let mut entries: Vec<_> = map.iter().collect();
+            entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+            out.push('{');
+            for (idx, (key, value)) in entries.into_iter().enumerate() {
+                if idx > 0 {
+                    out.push(',');
+                }
+                out.push_str(&serde_json::to_string(key)?);
+                out.push(':');
+                write_canonical_json(value, out)?;
+            }
+            out.push('}');
+        }
+        Value::Array(items) => {
+            out.push('[');
+            for (idx, item) in items.iter().enumerate() {
+                if idx > 0 {
+                    out.push(',');
+                }
+                write_canonical_json(item, out)?;
+            }
+            out.push(']');
+        }
+        Value::Number(number) => match number.as_f64() {
+            // integral floats are written as integers, as long as they're exactly representable
+            Some(float)
+                if number.is_f64() && float.fract() == 0.0 && float.abs() < 2f64.powi(53) =>
+            {
+                out.push_str(&(float as i64).to_string());
+            }
+            _ => out.push_str(&number.to_string()),
+        },
+        Value::Null | Value::Bool(_) | Value::String(_) => {
+            out.push_str(&serde_json::to_string(value)?);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::to_canonical_json;
+
+    #[test]
+    fn test_canonical_json() {
+        assert_eq!(
+            to_canonical_json(&json!({ "b": [1.0, -0.0, 1.5], "a": { "y": null, "x": "\"" } }))
+                .unwrap(),
+            r#"{"a":{"x":"\"","y":null},"b":[1,0,1.5]}"#
+        );
+        assert_eq!(
+            to_canonical_json(&json!({ "z": 1, "a": 2.0 })).unwrap(),
+            to_canonical_json(&json!({ "a": 2, "z": 1.0 })).unwrap()
+        );
+    }
+}
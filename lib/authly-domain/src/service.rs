@@ -2,13 +2,112 @@
 
 use authly_common::id::ServiceId;
 use authly_db::DbError;
+use rcgen::{CertificateParams, SanType};
+use reqwest::Url;
+use time::OffsetDateTime;
 use tracing::info;
 
 use crate::{
-    ctx::{GetBuiltins, GetDb, HostsConfig},
+    ctx::{GetBuiltins, GetDb, GetStats, HostsConfig},
     repo::service_repo,
+    settings::ServiceCertPolicy,
 };
 
+/// The maximum length of a DNS name, excluding the trailing dot
+const MAX_HOSTNAME_LEN: usize = 253;
+
+/// The maximum length of one label of a DNS name
+const MAX_LABEL_LEN: usize = 63;
+
+/// How long before being issued a service certificate is valid, to tolerate clock skew between Authly and the service
+const CERT_BACKDATE: time::Duration = time::Duration::days(1);
+
+/// Normalize a service hostname to the DNS name used in its certificates.
+///
+/// Unicode names are converted to punycode, and the name is lowercased.
+/// Returns `None` if the hostname is not a valid DNS name.
+pub fn normalize_hostname(host: &str) -> Option<String> {
+    let host = host.strip_suffix('.').unwrap_or(host);
+
+    // everything else that the URL parser accepts in a host, like percent encoding, isn't valid in DNS names
+    if host
+        .chars()
+        .any(|c| c.is_ascii() && !(c.is_ascii_alphanumeric() || c == '-' || c == '.'))
+    {
+        return None;
+    }
+
+    // IDNA mapping and punycode conversion, IP addresses are not hostnames
+    let url = Url::parse(&format!("https://{host}/")).ok()?;
+    let domain = url.domain()?;
+
+    let valid = domain.len() <= MAX_HOSTNAME_LEN
+        && domain.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= MAX_LABEL_LEN
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+        });
+
+    valid.then(|| domain.to_string())
+}
+
+/// Whether a Kubernetes service account reference is well formed, like Kubernetes requires.
+///
+/// The namespace must be a DNS label and the account name a DNS subdomain, as defined by RFC 1123.
+pub fn is_valid_k8s_service_account(namespace: Option<&str>, name: &str) -> bool {
+    namespace.is_none_or(is_dns_label)
+        && name.len() <= MAX_HOSTNAME_LEN
+        && name.split('.').all(is_dns_label)
+}
+
+fn is_dns_label(label: &str) -> bool {
+    !label.is_empty()
+        && label.len() <= MAX_LABEL_LEN
+        && label
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+        && !label.starts_with('-')
+        && !label.ends_with('-')
+}
+
+/// The namespace statistics of Kubernetes tokens that could not be verified are recorded under
+pub const K8S_UNVERIFIED_NAMESPACE: &str = "unknown";
+
+#[derive(thiserror::Error, Debug)]
+pub enum K8sAuthenticationError {
+    #[error("kubernetes service account not known by authly")]
+    NotFound,
+
+    #[error("db error: {0}")]
+    Db(#[from] DbError),
+}
+
+/// Authenticate a service by the Kubernetes service account of a verified token.
+///
+/// The outcome is recorded in the node statistics under the namespace of the account.
+#[tracing::instrument(skip(deps))]
+pub async fn authenticate_k8s_service_account(
+    deps: &(impl GetDb + GetBuiltins + GetStats),
+    namespace: &str,
+    account_name: &str,
+) -> Result<ServiceId, K8sAuthenticationError> {
+    let result = service_repo::find_service_eid_by_k8s_local_service_account_name(
+        deps.get_db(),
+        namespace,
+        account_name,
+        deps.get_builtins(),
+    )
+    .await
+    .map_err(K8sAuthenticationError::from)
+    .and_then(|eid| eid.ok_or(K8sAuthenticationError::NotFound));
+
+    deps.get_stats()
+        .record_k8s_authentication(namespace, result.is_ok());
+
+    result
+}
+
 pub async fn get_service_hosts(
     deps: &(impl GetDb + GetBuiltins + HostsConfig),
     svc_eid: ServiceId,
@@ -40,3 +139,63 @@ pub async fn get_service_hosts(
 
     Ok(hosts)
 }
+
+/// An alt name in a service certificate signing request that the service may not use
+#[derive(thiserror::Error, Debug)]
+#[error("invalid alt name: {0}")]
+pub struct InvalidAltName(pub String);
+
+/// Verify the alt names of a service certificate signing request.
+///
+/// DNS names must be hosts of the service, URI and IP names must be permitted by the certificate policy.
+/// Other types of alt names are never accepted.
+pub fn verify_cert_alt_names(
+    alt_names: &[SanType],
+    valid_hosts: &[String],
+    policy: &ServiceCertPolicy,
+) -> Result<(), InvalidAltName> {
+    for alt_name in alt_names {
+        match alt_name {
+            SanType::DnsName(name) => {
+                // DNS names are case insensitive, the service hosts are normalized to lowercase
+                if !valid_hosts
+                    .iter()
+                    .any(|valid| valid.eq_ignore_ascii_case(name.as_str()))
+                {
+                    return Err(InvalidAltName(name.as_str().to_string()));
+                }
+            }
+            SanType::URI(uri) => {
+                if !policy.permits_uri(uri.as_str()) {
+                    return Err(InvalidAltName(uri.as_str().to_string()));
+                }
+            }
+            SanType::IpAddress(addr) => {
+                if !policy.permits_ip(*addr) {
+                    return Err(InvalidAltName(addr.to_string()));
+                }
+            }
+            _ => return Err(InvalidAltName("unsupported type".to_string())),
+        }
+    }
+
+    Ok(())
+}
+
+/// Limit the validity of a service certificate to the longest validity of the certificate policy, counted from `now`.
+///
+/// Certificate signing requests don't carry a validity, so a parsed request has a practically unbounded one.
+pub fn limit_cert_validity(
+    params: &mut CertificateParams,
+    policy: &ServiceCertPolicy,
+    now: OffsetDateTime,
+) {
+    params.not_before = params.not_before.clamp(now - CERT_BACKDATE, now);
+
+    if let Some(max_not_after) = time::Duration::try_from(policy.max_validity)
+        .ok()
+        .and_then(|max_validity| now.checked_add(max_validity))
+    {
+        params.not_after = params.not_after.min(max_not_after);
+    }
+}
@@ -3,25 +3,117 @@ use std::time::Duration;
 use authly_common::id::EntityId;
 use authly_db::DbResult;
 use cookie::{Cookie, Expiration, SameSite};
+use int_enum::IntEnum;
 use rand::Rng;
 use time::OffsetDateTime;
 use tracing::warn;
 
-use crate::{ctx::GetDb, repo::session_repo};
+use crate::{
+    bus::ClusterMessage,
+    ctx::{ClusterBus, GetClock, GetDb, GetSessionCache, GetSettings, GetStats},
+    repo::session_repo,
+    settings::Settings,
+};
 
 pub const TOKEN_WIDTH: usize = 20;
-pub const SESSION_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// The lifetime of a "remember me" session
+pub const PERSISTENT_SESSION_TTL: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// How long a strong authentication is considered fresh enough for sensitive operations
+pub const STEP_UP_MAX_AGE: Duration = Duration::from_secs(5 * 60);
+
+/// How often the last-seen timestamp of a session is written back to the database
+const LAST_SEEN_RESOLUTION: Duration = Duration::from_secs(60);
 
 pub const SESSION_COOKIE_NAME: &str = "session-cookie";
 
+#[derive(Clone)]
 pub struct Session {
     pub token: SessionToken,
     pub eid: EntityId,
     pub expires_at: time::OffsetDateTime,
+    pub kind: SessionKind,
+    pub created_at: time::OffsetDateTime,
+    pub last_seen: time::OffsetDateTime,
+    /// How the session was most recently authenticated
+    pub auth_class: AuthClass,
+    /// When the session was most recently authenticated, either by login or by step-up
+    pub authenticated_at: time::OffsetDateTime,
+}
+
+/// The authentication context class of a session
+#[repr(u8)]
+#[derive(Clone, Copy, PartialEq, Eq, IntEnum, Debug)]
+pub enum AuthClass {
+    /// Authenticated with username and password
+    Password = 0,
+    /// Authenticated with a WebAuthn passkey
+    WebAuthn = 1,
+    /// Authenticated by a foreign identity provider
+    Federated = 2,
+}
+
+impl AuthClass {
+    /// Whether Authly itself verified the credentials
+    pub fn is_strong(self) -> bool {
+        matches!(self, Self::Password | Self::WebAuthn)
+    }
+}
+
+/// The kind of session, which decides its lifetime
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum SessionKind {
+    /// A short-lived session, subject to the idle and absolute timeout settings
+    #[default]
+    Default,
+    /// A long-lived session requested through "remember me", which is not subject to idle timeout.
+    ///
+    /// Its absolute lifetime is [PERSISTENT_SESSION_TTL] rather than the absolute timeout setting,
+    /// as a lifetime of hours would defeat the purpose of remembering the login.
+    Persistent,
+}
+
+impl SessionKind {
+    pub fn ttl(self, settings: &Settings) -> Duration {
+        match self {
+            Self::Default => settings.session_absolute_timeout,
+            Self::Persistent => PERSISTENT_SESSION_TTL,
+        }
+    }
 }
 
 impl Session {
-    pub fn to_cookie(&self) -> Cookie<'static> {
+    /// A public identifier for the session, which does not reveal the token
+    pub fn handle(&self) -> String {
+        self.token.handle()
+    }
+
+    /// Check whether the session has expired, either by its absolute lifetime or by inactivity
+    pub fn expiry_reason(&self, settings: &Settings, now: OffsetDateTime) -> Option<&'static str> {
+        if self.expires_at < now {
+            return Some("session expired");
+        }
+
+        if self.created_at + self.kind.ttl(settings) < now {
+            return Some("session expired");
+        }
+
+        if self.kind == SessionKind::Default && self.last_seen + settings.session_idle_timeout < now
+        {
+            return Some("session idle timeout");
+        }
+
+        None
+    }
+
+    /// Whether the session was recently authenticated strongly enough for sensitive operations
+    pub fn is_stepped_up(&self, now: OffsetDateTime) -> bool {
+        self.auth_class.is_strong() && now - self.authenticated_at <= STEP_UP_MAX_AGE
+    }
+
+    /// The session cookie, its max age counting from `now`
+    pub fn to_cookie(&self, now: OffsetDateTime) -> Cookie<'static> {
         let mut cookie = Cookie::new(
             SESSION_COOKIE_NAME,
             format!("{}", hexhex::hex(&self.token.0)),
@@ -30,55 +122,158 @@ impl Session {
         // cookie.set_secure(true);
         cookie.set_http_only(true);
         cookie.set_expires(Expiration::DateTime(self.expires_at));
+        cookie.set_max_age((self.expires_at - now).max(time::Duration::ZERO));
         cookie.set_same_site(SameSite::Strict);
         cookie
     }
 }
 
+/// Authenticate a session cookie, refreshing the last-seen timestamp of the session.
+///
+/// The session cache is consulted before the database.
 pub async fn authenticate_session_cookie(
-    deps: &impl GetDb,
+    deps: &(impl GetDb + GetSettings + GetSessionCache + GetClock),
     session_cookie: &Cookie<'_>,
 ) -> Result<Session, &'static str> {
-    let now = OffsetDateTime::now_utc();
+    let now = deps.get_clock().now();
 
     let token_hex = session_cookie.value();
     let token = SessionToken(hexhex::decode(token_hex).map_err(|_| "invalid session cookie")?);
 
-    let session = session_repo::get_session(deps.get_db(), token)
-        .await
-        .map_err(|err| {
-            warn!(?err, "session lookup error");
-            "internal error"
-        })?
-        .ok_or("no session")?;
-
-    if session.expires_at < now {
-        return Err("session expired");
+    let cached = deps.get_session_cache().get(&token);
+    let is_cached = cached.is_some();
+    let mut session = match cached {
+        Some(session) => session,
+        None => session_repo::get_session(deps.get_db(), token)
+            .await
+            .map_err(|err| {
+                warn!(?err, "session lookup error");
+                "internal error"
+            })?
+            .ok_or("no session")?,
+    };
+
+    if let Some(reason) = session.expiry_reason(&deps.get_settings(), now) {
+        deps.get_session_cache().invalidate(&session.token);
+        return Err(reason);
+    }
+
+    if now - session.last_seen >= LAST_SEEN_RESOLUTION {
+        if let Err(err) =
+            session_repo::update_session_last_seen(deps.get_db(), &session.token, now).await
+        {
+            warn!(?err, "failed to update session last seen");
+        }
+        session.last_seen = now;
+        deps.get_session_cache().insert(&session);
+    } else if !is_cached {
+        deps.get_session_cache().insert(&session);
     }
 
     Ok(session)
 }
 
+#[derive(Clone)]
 pub struct SessionToken(pub Vec<u8>);
 
 impl SessionToken {
     pub fn new_random() -> Self {
         Self(rand::thread_rng().r#gen::<[u8; TOKEN_WIDTH]>().to_vec())
     }
+
+    fn handle(&self) -> String {
+        let hash = blake3::hash(&self.0);
+        hexhex::hex(&hash.as_bytes()[..16]).to_string()
+    }
 }
 
-pub async fn init_session(deps: &impl GetDb, eid: EntityId) -> DbResult<Session> {
+pub async fn init_session(
+    deps: &(impl GetDb + GetSettings + GetStats + GetClock),
+    eid: EntityId,
+    kind: SessionKind,
+    auth_class: AuthClass,
+) -> DbResult<Session> {
+    let now = deps.get_clock().now();
+    let ttl = kind.ttl(&deps.get_settings());
     let session = Session {
         token: SessionToken::new_random(),
         eid,
-        expires_at: time::OffsetDateTime::now_utc() + SESSION_TTL,
+        expires_at: now + ttl,
+        kind,
+        created_at: now,
+        last_seen: now,
+        auth_class,
+        authenticated_at: now,
     };
 
     session_repo::store_session(deps.get_db(), &session).await?;
+    deps.get_stats().record_authentication();
 
     Ok(session)
 }
 
+/// Record a fresh re-authentication of an existing session
+pub async fn step_up_session(
+    deps: &(impl GetDb + GetSessionCache + GetClock),
+    session: &mut Session,
+    auth_class: AuthClass,
+) -> DbResult<()> {
+    let now = deps.get_clock().now();
+
+    session_repo::update_session_auth(deps.get_db(), &session.token, auth_class, now).await?;
+
+    session.auth_class = auth_class;
+    session.authenticated_at = now;
+    deps.get_session_cache().insert(session);
+
+    Ok(())
+}
+
+/// List the unexpired sessions of an entity
+pub async fn list_sessions(
+    deps: &(impl GetDb + GetSettings + GetClock),
+    eid: EntityId,
+) -> DbResult<Vec<Session>> {
+    let now = deps.get_clock().now();
+    let sessions = session_repo::list_entity_sessions(deps.get_db(), eid, now).await?;
+    let settings = deps.get_settings();
+
+    Ok(sessions
+        .into_iter()
+        .filter(|session| session.expiry_reason(&settings, now).is_none())
+        .collect())
+}
+
+/// Revoke a session of the given entity by its [Session::handle].
+///
+/// Returns whether a session was revoked.
+pub async fn revoke_session(
+    deps: &(impl GetDb + GetSettings + GetSessionCache + GetClock + ClusterBus),
+    eid: EntityId,
+    handle: &str,
+) -> DbResult<bool> {
+    let Some(session) = list_sessions(deps, eid)
+        .await?
+        .into_iter()
+        .find(|session| session.handle() == handle)
+    else {
+        return Ok(false);
+    };
+
+    session_repo::delete_session(deps.get_db(), &session.token).await?;
+
+    // The session must not be served from the cache of any cluster node
+    deps.get_session_cache().invalidate(&session.token);
+    if let Err(err) = deps
+        .broadcast_to_cluster(ClusterMessage::SessionsRevoked { eid })
+        .await
+    {
+        warn!(?err, "failed to broadcast session revocation");
+    }
+
+    Ok(true)
+}
+
 pub fn find_session_cookie<'a>(
     cookie_headers: impl Iterator<Item = &'a str>,
 ) -> Result<Cookie<'a>, &'static str> {
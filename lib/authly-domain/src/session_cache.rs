@@ -0,0 +1,102 @@
+//! Caching of authenticated sessions, in front of the session table.
+
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use authly_common::id::EntityId;
+use hashlink::LruCache;
+
+use crate::session::{Session, SessionToken};
+
+/// A secondary index of sessions, consulted before the database when authenticating a session cookie.
+///
+/// Cached sessions must be invalidated whenever the underlying session is revoked.
+pub trait SessionCache: Send + Sync {
+    /// Look up a cached session
+    fn get(&self, token: &SessionToken) -> Option<Session>;
+
+    /// Insert or refresh a session in the cache
+    fn insert(&self, session: &Session);
+
+    /// Evict a single session
+    fn invalidate(&self, token: &SessionToken);
+
+    /// Evict all the sessions of the given entity
+    fn invalidate_entity(&self, eid: EntityId);
+}
+
+/// An in-memory, size bounded session cache with a short time-to-live.
+///
+/// Cache keys are hashes of the session token, so the token itself is not kept in the index.
+pub struct LruSessionCache {
+    ttl: Duration,
+    entries: Mutex<LruCache<[u8; 32], CachedSession>>,
+}
+
+struct CachedSession {
+    session: Session,
+    cached_at: Instant,
+}
+
+impl LruSessionCache {
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: Mutex::new(LruCache::new(capacity)),
+        }
+    }
+}
+
+impl Default for LruSessionCache {
+    fn default() -> Self {
+        Self::new(10_000, Duration::from_secs(10))
+    }
+}
+
+impl SessionCache for LruSessionCache {
+    fn get(&self, token: &SessionToken) -> Option<Session> {
+        let key = cache_key(token);
+        let mut entries = self.entries.lock().unwrap();
+
+        let cached = entries.get(&key)?;
+        if cached.cached_at.elapsed() > self.ttl {
+            entries.remove(&key);
+            return None;
+        }
+
+        Some(cached.session.clone())
+    }
+
+    fn insert(&self, session: &Session) {
+        self.entries.lock().unwrap().insert(
+            cache_key(&session.token),
+            CachedSession {
+                session: session.clone(),
+                cached_at: Instant::now(),
+            },
+        );
+    }
+
+    fn invalidate(&self, token: &SessionToken) {
+        self.entries.lock().unwrap().remove(&cache_key(token));
+    }
+
+    fn invalidate_entity(&self, eid: EntityId) {
+        let mut entries = self.entries.lock().unwrap();
+        let keys: Vec<_> = entries
+            .iter()
+            .filter(|(_, cached)| cached.session.eid == eid)
+            .map(|(key, _)| *key)
+            .collect();
+
+        for key in keys {
+            entries.remove(&key);
+        }
+    }
+}
+
+fn cache_key(token: &SessionToken) -> [u8; 32] {
+    *blake3::hash(&token.0).as_bytes()
+}
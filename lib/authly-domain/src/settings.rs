@@ -1,9 +1,14 @@
 //! Settings are runtime-managable dynamic configurations stored in the database.
 
-use std::{borrow::Cow, time::Duration};
+use std::{borrow::Cow, net::IpAddr, time::Duration};
 
+use authly_common::id::PropId;
 use int_enum::IntEnum;
 use serde::{Deserialize, Serialize};
+use time::UtcOffset;
+use unicode_normalization::{is_nfc, UnicodeNormalization};
+
+use crate::{id::BuiltinProp, redirect::AllowedRedirect, remote_addr::IpCidr};
 
 const SECONDS_PER_DAY: u64 = 60 * 60 * 24;
 
@@ -13,18 +18,622 @@ const SECONDS_PER_DAY: u64 = 60 * 60 * 24;
 pub enum Setting {
     /// How often to rotate server certificates, in seconds
     ServerCertRotationRate = 0,
+    /// How long a session may stay unused before it expires
+    SessionIdleTimeout = 1,
+    /// The maximum lifetime of a session, regardless of activity.
+    /// Remember-me sessions instead have a fixed lifetime of 30 days.
+    SessionAbsoluteTimeout = 2,
+    /// Mapping of SCIM attributes to entity properties,
+    /// written as comma-separated `{scim_attribute}={namespace}:{property}` pairs
+    ScimAttributeMapping = 3,
+    /// Whether usernames and email addresses are case-folded before being stored or looked up.
+    /// Off by default, since identities stored before it's turned on are only found by their exact spelling
+    /// until they are stored again, e.g. by re-applying their document.
+    IdentCaseFold = 4,
+    /// Whether usernames and email addresses are converted to Unicode NFC before being stored or looked up.
+    /// Off by default, for the same reason as `IDENT_CASE_FOLD`.
+    IdentUnicodeNfc = 5,
+    /// Whether the `+tag` subaddress of email addresses is removed before being stored or looked up
+    EmailStripSubaddress = 6,
+    /// Entity properties whose attributes are projected into access tokens issued to a service,
+    /// written as comma-separated `{service}={namespace}:{property}` pairs.
+    /// Services without any pairs receive all attributes.
+    TokenAttributeProjection = 7,
+    /// The largest attribute set embedded in access tokens issued to a service,
+    /// written as comma-separated `{service}={limit}` pairs.
+    /// Larger sets are replaced by a reference, resolved through the `authly_token.AuthlyToken/GetEntityAttributes` gRPC method.
+    TokenAttributeEmbedLimit = 8,
+    /// Which access control decisions are recorded in the audit log: `off`, `denied` or `all`.
+    /// Access is denied when an allowing decision can't be recorded.
+    AccessControlAudit = 9,
+    /// The fraction of access control decisions recorded in the audit log, between 0 and 1
+    AccessControlAuditSampleRate = 10,
+    /// How long audit records are kept before they are purged, or `forever`
+    AuditRetention = 11,
+    /// External policy decision points that access control for a service is delegated to,
+    /// written as comma-separated `{service}={url}` pairs
+    ExternalDecisionPoint = 12,
+    /// How long to wait for an external policy decision point before applying the fail mode
+    ExternalDecisionTimeout = 13,
+    /// What to do when an external policy decision point fails or times out:
+    /// `local` evaluates the local policies, `open` allows and `closed` denies
+    ExternalDecisionFailMode = 14,
+    /// The timezone of schedule conditions in policies, written as a UTC offset like `+02:00`
+    PolicyTimezone = 15,
+    /// Networks of proxies trusted to forward the address of a client in `x-forwarded-for`,
+    /// written as comma-separated CIDRs like `10.0.0.0/8`
+    TrustedProxies = 16,
+    /// Quotas on how often a subject entity may be allowed access to resources with an attribute,
+    /// written as comma-separated `{namespace}:{property}:{attribute}={limit}/{window}` entries like `svc:action:deploy=100/1d`
+    AccessControlQuota = 17,
+    /// The minimum number of characters in a password
+    PasswordMinLength = 18,
+    /// How many character classes a password must mix: lowercase letters, uppercase letters, digits and symbols
+    PasswordCharacterClasses = 19,
+    /// A k-anonymity range API that passwords are checked against for breaches,
+    /// like `https://api.pwnedpasswords.com/range/`, or `off`
+    PasswordBreachCheck = 20,
+    /// Usernames that can't be given to entities, written as a comma-separated list
+    ReservedUsernames = 21,
+    /// The shortest time allowed between two changes of the username of an entity, or `off`
+    UsernameChangeInterval = 22,
+    /// Where users may be sent after logging in, written as comma-separated path prefixes like `/app`
+    /// or URLs like `https://app.example.com/`. Other redirect targets are rejected.
+    LoginRedirectAllowlist = 23,
+    /// How often connected services are pinged
+    ServicePingInterval = 24,
+    /// How many pings in a row a service may leave unanswered before it's considered unresponsive, or `off`.
+    /// Unresponsive services are not sent notifications until they answer a ping again.
+    ServicePingMaxMissed = 25,
+    /// How long a local access control decision is reused for requests with the same inputs, or `off`
+    DecisionCacheTtl = 26,
+    /// The largest number of access control decisions kept in the decision cache
+    DecisionCacheCapacity = 27,
+    /// The largest number of subject attributes a service may send in one access control request
+    AccessControlMaxSubjectAttributes = 28,
+    /// The largest number of resource attributes, required and optional, a service may send in one access control request
+    AccessControlMaxResourceAttributes = 29,
+    /// The longest validity of certificates issued to services. Longer requested lifetimes are shortened.
+    ServiceCertMaxValidity = 30,
+    /// URI prefixes that URI alt names in service certificates must start with, like `spiffe://example.org/`,
+    /// written as a comma-separated list. URI alt names are rejected when the list is empty.
+    ServiceCertUriPrefixes = 31,
+    /// Networks that IP alt names in service certificates must be within, written as comma-separated CIDRs.
+    /// IP alt names are rejected when the list is empty.
+    ServiceCertIpNetworks = 32,
+    /// How long bearer tokens issued to SCIM clients are valid
+    ScimTokenValidity = 33,
+    /// How long entity attribute events are kept, even when not every subscriber has acknowledged them.
+    /// Subscribers that haven't acknowledged any events for this long are forgotten.
+    EntityEventRetention = 34,
 }
 
 /// The deserialized version of the full collection of settings
 #[derive(Debug)]
 pub struct Settings {
     pub server_cert_rotation_rate: Duration,
+    pub session_idle_timeout: Duration,
+    pub session_absolute_timeout: Duration,
+    pub scim_attribute_mapping: Vec<ScimAttributeMapping>,
+    pub ident_normalization: IdentNormalization,
+    pub token_attribute_projection: Vec<TokenAttributeProjection>,
+    pub token_attribute_embed_limit: Vec<TokenAttributeEmbedLimit>,
+    pub access_control_audit: AccessControlAudit,
+    pub audit_retention: Option<Duration>,
+    pub external_decision: ExternalDecision,
+    pub policy_timezone: UtcOffset,
+    pub trusted_proxies: Vec<IpCidr>,
+    pub access_control_quotas: Vec<AccessControlQuota>,
+    pub password_policy: PasswordPolicy,
+    pub username_policy: UsernamePolicy,
+    pub login_redirect_allowlist: Vec<AllowedRedirect>,
+    pub service_ping: ServicePing,
+    pub decision_cache: DecisionCache,
+    pub access_control_limits: AccessControlLimits,
+    pub service_cert: ServiceCertPolicy,
+    pub scim_token_validity: Duration,
+    pub entity_event_retention: Duration,
+}
+
+/// Recording of access control decisions in the audit log
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct AccessControlAudit {
+    pub decisions: AuditedDecisions,
+    pub sample_rate: f64,
+}
+
+impl Default for AccessControlAudit {
+    fn default() -> Self {
+        Self {
+            decisions: AuditedDecisions::Off,
+            sample_rate: 1.0,
+        }
+    }
+}
+
+impl AccessControlAudit {
+    /// Whether a decision should be recorded, before sampling
+    pub fn is_audited(&self, allowed: bool) -> bool {
+        match self.decisions {
+            AuditedDecisions::Off => false,
+            AuditedDecisions::Denied => !allowed,
+            AuditedDecisions::All => true,
+        }
+    }
+}
+
+/// Caching of local access control decisions, see [crate::policy_cache::PolicyEngineCache]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct DecisionCache {
+    /// `None` disables the cache
+    pub ttl: Option<Duration>,
+    pub capacity: usize,
+}
+
+impl Default for DecisionCache {
+    fn default() -> Self {
+        Self {
+            ttl: None,
+            capacity: 10_000,
+        }
+    }
+}
+
+/// Limits on the size of access control requests, checked before anything in the request is resolved
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct AccessControlLimits {
+    pub max_subject_attributes: usize,
+    pub max_resource_attributes: usize,
+}
+
+impl Default for AccessControlLimits {
+    fn default() -> Self {
+        Self {
+            max_subject_attributes: 1024,
+            max_resource_attributes: 256,
+        }
+    }
+}
+
+/// Restrictions on the certificates issued to services
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ServiceCertPolicy {
+    pub max_validity: Duration,
+    pub uri_prefixes: Vec<String>,
+    pub ip_networks: Vec<IpCidr>,
+}
+
+impl Default for ServiceCertPolicy {
+    fn default() -> Self {
+        Self {
+            max_validity: Duration::from_secs(365 * SECONDS_PER_DAY),
+            uri_prefixes: vec![],
+            ip_networks: vec![],
+        }
+    }
+}
+
+impl ServiceCertPolicy {
+    /// Whether a URI alt name is permitted.
+    ///
+    /// The URI must be in normal form, so that dot segments can't be used to escape a prefix.
+    pub fn permits_uri(&self, uri: &str) -> bool {
+        let Ok(url) = reqwest::Url::parse(uri) else {
+            return false;
+        };
+
+        url.as_str() == uri
+            && self
+                .uri_prefixes
+                .iter()
+                .any(|prefix| uri.starts_with(prefix.as_str()))
+    }
+
+    /// Whether an IP alt name is permitted
+    pub fn permits_ip(&self, addr: IpAddr) -> bool {
+        self.ip_networks
+            .iter()
+            .any(|network| network.contains(addr))
+    }
+
+    fn parse_uri_prefixes(value: &str) -> anyhow::Result<Vec<String>> {
+        value
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(|item| {
+                let url = reqwest::Url::parse(item)?;
+                // a prefix without a path boundary would also match other authorities, like `spiffe://example.org.evil`
+                if !url.has_host() || !url.as_str().ends_with('/') {
+                    return Err(anyhow::anyhow!(
+                        "expected a URI prefix with a host, ending with `/`"
+                    ));
+                }
+                Ok(url.to_string())
+            })
+            .collect()
+    }
+}
+
+/// Delegation of access control decisions to external policy decision points
+#[derive(Clone, PartialEq, Debug)]
+pub struct ExternalDecision {
+    pub points: Vec<ExternalDecisionPoint>,
+    pub timeout: Duration,
+    pub fail_mode: ExternalDecisionFailMode,
+}
+
+impl Default for ExternalDecision {
+    fn default() -> Self {
+        Self {
+            points: vec![],
+            timeout: Duration::from_secs(1),
+            fail_mode: ExternalDecisionFailMode::Local,
+        }
+    }
+}
+
+/// An external policy decision point making access control decisions for a service
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ExternalDecisionPoint {
+    pub service: String,
+    pub url: reqwest::Url,
+}
+
+impl ExternalDecisionPoint {
+    fn parse_list(value: &str) -> anyhow::Result<Vec<Self>> {
+        value
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(|item| {
+                let (service, url) = item
+                    .split_once('=')
+                    .ok_or_else(|| anyhow::anyhow!("expected `service=url`"))?;
+
+                Ok(Self {
+                    service: service.trim().to_string(),
+                    url: reqwest::Url::parse(url.trim())?,
+                })
+            })
+            .collect()
+    }
+}
+
+/// The decision made when an external policy decision point is unavailable
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ExternalDecisionFailMode {
+    Local,
+    Open,
+    Closed,
+}
+
+impl std::str::FromStr for ExternalDecisionFailMode {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        match value.trim() {
+            "local" => Ok(Self::Local),
+            "open" => Ok(Self::Open),
+            "closed" => Ok(Self::Closed),
+            _ => Err(anyhow::anyhow!("expected `local`, `open` or `closed`")),
+        }
+    }
+}
+
+/// Parse a UTC offset like `+02:00`, `-05` or `UTC`
+fn parse_utc_offset(value: &str) -> anyhow::Result<UtcOffset> {
+    let value = value.trim();
+    if matches!(value, "UTC" | "Z") {
+        return Ok(UtcOffset::UTC);
+    }
+
+    let (sign, offset) = if let Some(offset) = value.strip_prefix('+') {
+        (1, offset)
+    } else if let Some(offset) = value.strip_prefix('-') {
+        (-1, offset)
+    } else {
+        return Err(anyhow::anyhow!("expected a UTC offset like `+02:00`"));
+    };
+    let (hours, minutes) = offset.split_once(':').unwrap_or((offset, "0"));
+    let (hours, minutes): (i8, i8) = (hours.parse()?, minutes.parse()?);
+
+    Ok(UtcOffset::from_hms(sign * hours, sign * minutes, 0)?)
+}
+
+/// A limit on how many times a subject entity is allowed access to resources with an attribute, within a fixed window of time
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct AccessControlQuota {
+    pub namespace: String,
+    pub property: String,
+    pub attribute: String,
+    pub limit: u32,
+    pub window: Duration,
+}
+
+impl AccessControlQuota {
+    fn parse_list(value: &str) -> anyhow::Result<Vec<Self>> {
+        value
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(|item| {
+                let (target, quota) = item.split_once('=').ok_or_else(|| {
+                    anyhow::anyhow!("expected `namespace:property:attribute=limit/window`")
+                })?;
+                let mut target = target.trim().splitn(3, ':');
+                let (Some(namespace), Some(property), Some(attribute)) =
+                    (target.next(), target.next(), target.next())
+                else {
+                    return Err(anyhow::anyhow!("expected `namespace:property:attribute`"));
+                };
+                let (limit, window) = quota
+                    .split_once('/')
+                    .ok_or_else(|| anyhow::anyhow!("expected `limit/window`"))?;
+                let window = humantime::parse_duration(window.trim())?;
+                if window.as_secs() == 0 {
+                    return Err(anyhow::anyhow!("quota window must be at least one second"));
+                }
+
+                Ok(Self {
+                    namespace: namespace.to_string(),
+                    property: property.to_string(),
+                    attribute: attribute.to_string(),
+                    limit: limit.trim().parse()?,
+                    window,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Requirements for passwords, enforced when a password is set
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    pub character_classes: u8,
+    /// The range API checked for breached passwords, if any
+    pub breach_check: Option<reqwest::Url>,
+}
+
+/// Restrictions on the usernames of entities
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct UsernamePolicy {
+    pub reserved: Vec<String>,
+    pub change_interval: Option<Duration>,
+}
+
+impl UsernamePolicy {
+    /// Whether a username is reserved. Reserved names are matched regardless of case.
+    pub fn is_reserved(&self, username: &str) -> bool {
+        self.reserved
+            .iter()
+            .any(|reserved| reserved.to_lowercase() == username.to_lowercase())
+    }
+}
+
+impl Default for UsernamePolicy {
+    fn default() -> Self {
+        Self {
+            reserved: ["admin", "root", "authly"].map(str::to_string).to_vec(),
+            change_interval: None,
+        }
+    }
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self {
+            min_length: 8,
+            character_classes: 1,
+            breach_check: None,
+        }
+    }
+}
+
+/// Active health checking of connected services
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ServicePing {
+    pub interval: Duration,
+    /// Unanswered pings in a row before a service is unresponsive, if unresponsive services are left out of notifications
+    pub max_missed: Option<u32>,
+}
+
+impl Default for ServicePing {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(5 * 60),
+            max_missed: None,
+        }
+    }
+}
+
+/// Which access control decisions are recorded in the audit log
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AuditedDecisions {
+    Off,
+    Denied,
+    All,
+}
+
+impl std::str::FromStr for AuditedDecisions {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        match value.trim() {
+            "off" => Ok(Self::Off),
+            "denied" => Ok(Self::Denied),
+            "all" => Ok(Self::All),
+            _ => Err(anyhow::anyhow!("expected `off`, `denied` or `all`")),
+        }
+    }
+}
+
+/// An entity property whose attributes are included in access tokens issued to a service
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct TokenAttributeProjection {
+    pub service: String,
+    pub namespace: String,
+    pub property: String,
+}
+
+impl TokenAttributeProjection {
+    fn parse_list(value: &str) -> anyhow::Result<Vec<Self>> {
+        value
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(|item| {
+                let (service, target) = item
+                    .split_once('=')
+                    .ok_or_else(|| anyhow::anyhow!("expected `service=namespace:property`"))?;
+                let (namespace, property) = target
+                    .trim()
+                    .split_once(':')
+                    .ok_or_else(|| anyhow::anyhow!("expected `namespace:property`"))?;
+
+                Ok(Self {
+                    service: service.trim().to_string(),
+                    namespace: namespace.to_string(),
+                    property: property.to_string(),
+                })
+            })
+            .collect()
+    }
+}
+
+/// The largest attribute set embedded in access tokens issued to a service
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct TokenAttributeEmbedLimit {
+    pub service: String,
+    pub limit: usize,
+}
+
+impl TokenAttributeEmbedLimit {
+    fn parse_list(value: &str) -> anyhow::Result<Vec<Self>> {
+        value
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(|item| {
+                let (service, limit) = item
+                    .split_once('=')
+                    .ok_or_else(|| anyhow::anyhow!("expected `service=limit`"))?;
+
+                Ok(Self {
+                    service: service.trim().to_string(),
+                    limit: limit.trim().parse()?,
+                })
+            })
+            .collect()
+    }
+}
+
+/// A SCIM attribute whose values are labels of attributes of an entity property
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ScimAttributeMapping {
+    pub scim_attribute: String,
+    pub namespace: String,
+    pub property: String,
+}
+
+impl ScimAttributeMapping {
+    fn parse_list(value: &str) -> anyhow::Result<Vec<Self>> {
+        value
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(|item| {
+                let (scim_attribute, target) = item
+                    .split_once('=')
+                    .ok_or_else(|| anyhow::anyhow!("expected `attribute=namespace:property`"))?;
+                let (namespace, property) = target
+                    .trim()
+                    .split_once(':')
+                    .ok_or_else(|| anyhow::anyhow!("expected `namespace:property`"))?;
+
+                Ok(Self {
+                    scim_attribute: scim_attribute.trim().to_string(),
+                    namespace: namespace.to_string(),
+                    property: property.to_string(),
+                })
+            })
+            .collect()
+    }
+}
+
+/// Normalization of usernames and email addresses.
+///
+/// The same normalization has to be applied when an identity is written and when it is looked up,
+/// since identities are only comparable through their fingerprints.
+/// Nothing is normalized by default, so turning normalization on is a decision to re-store existing identities.
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub struct IdentNormalization {
+    pub case_fold: bool,
+    pub unicode_nfc: bool,
+    pub email_strip_subaddress: bool,
+}
+
+impl IdentNormalization {
+    /// Normalize an identity value. Properties other than username and email are left untouched.
+    pub fn normalize<'a>(&self, prop_id: PropId, value: &'a str) -> Cow<'a, str> {
+        let (email, username): (PropId, PropId) =
+            (BuiltinProp::Email.into(), BuiltinProp::Username.into());
+        let is_email = prop_id == email;
+        if !is_email && prop_id != username {
+            return Cow::Borrowed(value);
+        }
+
+        let mut value = Cow::Borrowed(value);
+
+        if self.unicode_nfc && !is_nfc(&value) {
+            value = Cow::Owned(value.nfc().collect());
+        }
+
+        if self.case_fold {
+            let folded = value.to_lowercase();
+            if folded != *value {
+                value = Cow::Owned(folded);
+            }
+        }
+
+        if is_email && self.email_strip_subaddress {
+            if let Some((local, domain)) = value.rsplit_once('@') {
+                if let Some((local, _tag)) = local.split_once('+') {
+                    value = Cow::Owned(format!("{local}@{domain}"));
+                }
+            }
+        }
+
+        value
+    }
 }
 
 impl Default for Settings {
     fn default() -> Self {
         Settings {
             server_cert_rotation_rate: Duration::from_secs(7 * SECONDS_PER_DAY),
+            session_idle_timeout: Duration::from_secs(60 * 60),
+            session_absolute_timeout: Duration::from_secs(12 * 60 * 60),
+            scim_attribute_mapping: vec![],
+            ident_normalization: IdentNormalization::default(),
+            token_attribute_projection: vec![],
+            token_attribute_embed_limit: vec![],
+            access_control_audit: AccessControlAudit::default(),
+            audit_retention: None,
+            external_decision: ExternalDecision::default(),
+            policy_timezone: UtcOffset::UTC,
+            trusted_proxies: vec![],
+            access_control_quotas: vec![],
+            password_policy: PasswordPolicy::default(),
+            username_policy: UsernamePolicy::default(),
+            login_redirect_allowlist: vec!["/".parse().expect("valid redirect target")],
+            service_ping: ServicePing::default(),
+            decision_cache: DecisionCache::default(),
+            access_control_limits: AccessControlLimits::default(),
+            service_cert: ServiceCertPolicy::default(),
+            scim_token_validity: Duration::from_secs(90 * SECONDS_PER_DAY),
+            entity_event_retention: Duration::from_secs(30 * SECONDS_PER_DAY),
         }
     }
 }
@@ -35,6 +644,167 @@ impl Settings {
             Setting::ServerCertRotationRate => {
                 self.server_cert_rotation_rate = humantime::parse_duration(&value)?;
             }
+            Setting::SessionIdleTimeout => {
+                self.session_idle_timeout = humantime::parse_duration(&value)?;
+            }
+            Setting::SessionAbsoluteTimeout => {
+                self.session_absolute_timeout = humantime::parse_duration(&value)?;
+            }
+            Setting::ScimAttributeMapping => {
+                self.scim_attribute_mapping = ScimAttributeMapping::parse_list(&value)?;
+            }
+            Setting::IdentCaseFold => {
+                self.ident_normalization.case_fold = value.parse()?;
+            }
+            Setting::IdentUnicodeNfc => {
+                self.ident_normalization.unicode_nfc = value.parse()?;
+            }
+            Setting::EmailStripSubaddress => {
+                self.ident_normalization.email_strip_subaddress = value.parse()?;
+            }
+            Setting::TokenAttributeProjection => {
+                self.token_attribute_projection = TokenAttributeProjection::parse_list(&value)?;
+            }
+            Setting::TokenAttributeEmbedLimit => {
+                self.token_attribute_embed_limit = TokenAttributeEmbedLimit::parse_list(&value)?;
+            }
+            Setting::AccessControlAudit => {
+                self.access_control_audit.decisions = value.parse()?;
+            }
+            Setting::AccessControlAuditSampleRate => {
+                let sample_rate: f64 = value.trim().parse()?;
+                if !(0.0..=1.0).contains(&sample_rate) {
+                    return Err(anyhow::anyhow!("sample rate must be between 0 and 1"));
+                }
+                self.access_control_audit.sample_rate = sample_rate;
+            }
+            Setting::AuditRetention => {
+                self.audit_retention = match value.trim() {
+                    "forever" => None,
+                    value => Some(humantime::parse_duration(value)?),
+                };
+            }
+            Setting::ExternalDecisionPoint => {
+                self.external_decision.points = ExternalDecisionPoint::parse_list(&value)?;
+            }
+            Setting::ExternalDecisionTimeout => {
+                self.external_decision.timeout = humantime::parse_duration(&value)?;
+            }
+            Setting::ExternalDecisionFailMode => {
+                self.external_decision.fail_mode = value.parse()?;
+            }
+            Setting::PolicyTimezone => {
+                self.policy_timezone = parse_utc_offset(&value)?;
+            }
+            Setting::TrustedProxies => {
+                self.trusted_proxies = value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|item| !item.is_empty())
+                    .map(str::parse)
+                    .collect::<anyhow::Result<_>>()?;
+            }
+            Setting::AccessControlQuota => {
+                self.access_control_quotas = AccessControlQuota::parse_list(&value)?;
+            }
+            Setting::PasswordMinLength => {
+                self.password_policy.min_length = value.trim().parse()?;
+            }
+            Setting::PasswordCharacterClasses => {
+                let character_classes: u8 = value.trim().parse()?;
+                if !(1..=4).contains(&character_classes) {
+                    return Err(anyhow::anyhow!("character classes must be between 1 and 4"));
+                }
+                self.password_policy.character_classes = character_classes;
+            }
+            Setting::PasswordBreachCheck => {
+                self.password_policy.breach_check = match value.trim() {
+                    "off" => None,
+                    url => Some(reqwest::Url::parse(url)?),
+                };
+            }
+            Setting::ReservedUsernames => {
+                self.username_policy.reserved = value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|name| !name.is_empty())
+                    .map(str::to_string)
+                    .collect();
+            }
+            Setting::UsernameChangeInterval => {
+                self.username_policy.change_interval = match value.trim() {
+                    "off" => None,
+                    value => Some(humantime::parse_duration(value)?),
+                };
+            }
+            Setting::LoginRedirectAllowlist => {
+                self.login_redirect_allowlist = value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|item| !item.is_empty())
+                    .map(str::parse)
+                    .collect::<anyhow::Result<_>>()?;
+            }
+            Setting::ServicePingInterval => {
+                let interval = humantime::parse_duration(&value)?;
+                if interval.is_zero() {
+                    return Err(anyhow::anyhow!("ping interval must be positive"));
+                }
+                self.service_ping.interval = interval;
+            }
+            Setting::ServicePingMaxMissed => {
+                self.service_ping.max_missed = match value.trim() {
+                    "off" => None,
+                    value => Some(value.parse()?),
+                };
+            }
+            Setting::DecisionCacheTtl => {
+                self.decision_cache.ttl = match value.trim() {
+                    "off" => None,
+                    value => Some(humantime::parse_duration(value)?),
+                };
+            }
+            Setting::DecisionCacheCapacity => {
+                let capacity: usize = value.trim().parse()?;
+                if capacity == 0 {
+                    return Err(anyhow::anyhow!("decision cache capacity must be positive"));
+                }
+                self.decision_cache.capacity = capacity;
+            }
+            Setting::AccessControlMaxSubjectAttributes => {
+                self.access_control_limits.max_subject_attributes = value.trim().parse()?;
+            }
+            Setting::AccessControlMaxResourceAttributes => {
+                self.access_control_limits.max_resource_attributes = value.trim().parse()?;
+            }
+            Setting::ServiceCertMaxValidity => {
+                let max_validity = humantime::parse_duration(&value)?;
+                if max_validity.is_zero() {
+                    return Err(anyhow::anyhow!("certificate validity must be positive"));
+                }
+                self.service_cert.max_validity = max_validity;
+            }
+            Setting::ServiceCertUriPrefixes => {
+                self.service_cert.uri_prefixes = ServiceCertPolicy::parse_uri_prefixes(&value)?;
+            }
+            Setting::ServiceCertIpNetworks => {
+                self.service_cert.ip_networks = value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|item| !item.is_empty())
+                    .map(str::parse)
+                    .collect::<anyhow::Result<_>>()?;
+            }
+            Setting::ScimTokenValidity => {
+                let validity = humantime::parse_duration(&value)?;
+                if validity.is_zero() {
+                    return Err(anyhow::anyhow!("token validity must be positive"));
+                }
+                self.scim_token_validity = validity;
+            }
+            Setting::EntityEventRetention => {
+                self.entity_event_retention = humantime::parse_duration(&value)?;
+            }
         }
 
         Ok(())
@@ -0,0 +1,364 @@
+//! Signing with keys that may live outside the process.
+//!
+//! The instance key signs certificates issued by the local CA, and access tokens in compatibility mode.
+//! It's either an [InMemorySigner], or a key held by an external device or service such as an HSM,
+//! in which case the private key is never available to Authly.
+//!
+//! Signing is asynchronous, since remote signers need a round trip for every signature.
+//! rcgen only supports synchronous signing keys, so certificates and CSRs are first encoded
+//! by rcgen with a placeholder signature, and then re-assembled with the real signature.
+
+use std::sync::Mutex;
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use rcgen::{
+    CertificateParams, CertificateSigningRequestParams, Issuer, KeyPair, PublicKeyData,
+    SignatureAlgorithm, SigningKey,
+};
+use rustls::pki_types::{CertificateDer, CertificateSigningRequestDer};
+use serde::Serialize;
+
+/// A private key which can sign messages
+#[async_trait]
+pub trait Signer: Send + Sync {
+    /// The signature algorithm of the key
+    fn algorithm(&self) -> &'static SignatureAlgorithm;
+
+    /// The raw public key, which for EC keys is the uncompressed point
+    fn public_key_raw(&self) -> &[u8];
+
+    /// Sign a message. ECDSA signatures are ASN.1 DER encoded, as used in X.509.
+    async fn sign(&self, msg: &[u8]) -> anyhow::Result<Vec<u8>>;
+
+    /// The private key in PKCS#8 DER format, if it may leave the signer
+    fn private_key_der(&self) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// Whether every signature is a request to a remote service, like a KMS
+    fn is_remote(&self) -> bool {
+        false
+    }
+}
+
+/// A signer backed by a key pair in process memory
+pub struct InMemorySigner(KeyPair);
+
+impl InMemorySigner {
+    pub fn new(key_pair: KeyPair) -> Self {
+        Self(key_pair)
+    }
+
+    pub fn key_pair(&self) -> &KeyPair {
+        &self.0
+    }
+}
+
+#[async_trait]
+impl Signer for InMemorySigner {
+    fn algorithm(&self) -> &'static SignatureAlgorithm {
+        PublicKeyData::algorithm(&self.0)
+    }
+
+    fn public_key_raw(&self) -> &[u8] {
+        PublicKeyData::der_bytes(&self.0)
+    }
+
+    async fn sign(&self, msg: &[u8]) -> anyhow::Result<Vec<u8>> {
+        Ok(SigningKey::sign(&self.0, msg)?)
+    }
+
+    fn private_key_der(&self) -> Option<Vec<u8>> {
+        Some(self.0.serialize_der())
+    }
+}
+
+/// Sign a certificate for `public_key`, issued by the CA with `issuer_params` whose key is held by `signer`
+pub async fn sign_certificate(
+    signer: &dyn Signer,
+    issuer_params: &CertificateParams,
+    params: &CertificateParams,
+    public_key: &impl PublicKeyData,
+) -> anyhow::Result<CertificateDer<'static>> {
+    let capture = CaptureKey::new(signer);
+    let der = {
+        let issuer = Issuer::new(issuer_params.clone(), &capture);
+        params.signed_by(public_key, &issuer)?.der().to_vec()
+    };
+
+    Ok(capture.resign(&der).await?.into())
+}
+
+/// Sign the certificate requested in a CSR, issued by the CA with `issuer_params` whose key is held by `signer`
+pub async fn sign_csr(
+    signer: &dyn Signer,
+    issuer_params: &CertificateParams,
+    csr: &CertificateSigningRequestParams,
+) -> anyhow::Result<CertificateDer<'static>> {
+    let capture = CaptureKey::new(signer);
+    let der = {
+        let issuer = Issuer::new(issuer_params.clone(), &capture);
+        csr.signed_by(&issuer)?.der().to_vec()
+    };
+
+    Ok(capture.resign(&der).await?.into())
+}
+
+/// Make a self-signed certificate for the key held by `signer`
+pub async fn self_sign(
+    signer: &dyn Signer,
+    params: &CertificateParams,
+) -> anyhow::Result<CertificateDer<'static>> {
+    let capture = CaptureKey::new(signer);
+    let der = params.self_signed(&capture)?.der().to_vec();
+
+    Ok(capture.resign(&der).await?.into())
+}
+
+/// Make a certificate signing request for the key held by `signer`
+pub async fn serialize_request(
+    signer: &dyn Signer,
+    params: &CertificateParams,
+) -> anyhow::Result<CertificateSigningRequestDer<'static>> {
+    let capture = CaptureKey::new(signer);
+    let der = params.serialize_request(&capture)?.der().to_vec();
+
+    Ok(capture.resign(&der).await?.into())
+}
+
+/// Encode and sign an ES256 JSON Web Token
+pub async fn encode_jwt(
+    header: &jsonwebtoken::Header,
+    claims: &impl Serialize,
+    signer: &dyn Signer,
+) -> anyhow::Result<String> {
+    let header = URL_SAFE_NO_PAD.encode(serde_json::to_vec(header)?);
+    let claims = URL_SAFE_NO_PAD.encode(serde_json::to_vec(claims)?);
+    let message = format!("{header}.{claims}");
+
+    let signature = signer
+        .sign(message.as_bytes())
+        .await
+        .map_err(|err| anyhow!("signing failed: {err}"))?;
+    let signature = ecdsa_der_to_fixed(&signature, 32)?;
+
+    Ok(format!("{message}.{}", URL_SAFE_NO_PAD.encode(signature)))
+}
+
+/// Convert an ASN.1 DER ECDSA signature to the fixed size `r || s` format used by JWS
+pub fn ecdsa_der_to_fixed(der: &[u8], scalar_len: usize) -> anyhow::Result<Vec<u8>> {
+    let mut reader = DerReader(der);
+    let mut sequence = DerReader(reader.read(0x30)?);
+
+    let mut fixed = Vec::with_capacity(scalar_len * 2);
+    for _ in 0..2 {
+        let int = sequence.read(0x02)?;
+        let int = &int[int.iter().take_while(|byte| **byte == 0).count()..];
+        if int.len() > scalar_len {
+            return Err(anyhow!("ECDSA signature scalar too large"));
+        }
+        fixed.extend(std::iter::repeat_n(0, scalar_len - int.len()));
+        fixed.extend_from_slice(int);
+    }
+
+    Ok(fixed)
+}
+
+/// Convert a fixed size `r || s` ECDSA signature to the ASN.1 DER format used by X.509
+pub fn ecdsa_fixed_to_der(fixed: &[u8]) -> Vec<u8> {
+    let (r, s) = fixed.split_at(fixed.len() / 2);
+
+    let mut sequence = vec![];
+    for int in [r, s] {
+        let int = &int[int.iter().take_while(|byte| **byte == 0).count()..];
+        let mut value = vec![];
+        // an empty or negative-looking integer needs a leading zero
+        if int.first().is_none_or(|byte| byte & 0x80 != 0) {
+            value.push(0);
+        }
+        value.extend_from_slice(int);
+        sequence.extend(encode_tlv(0x02, &value));
+    }
+
+    encode_tlv(0x30, &sequence)
+}
+
+/// An rcgen signing key which records the message to sign, and returns a placeholder signature.
+///
+/// The signed structure is then re-assembled with a signature from the real signer.
+struct CaptureKey<'s> {
+    signer: &'s dyn Signer,
+    tbs: Mutex<Option<Vec<u8>>>,
+}
+
+impl<'s> CaptureKey<'s> {
+    fn new(signer: &'s dyn Signer) -> Self {
+        Self {
+            signer,
+            tbs: Mutex::new(None),
+        }
+    }
+
+    /// Replace the placeholder signature of a certificate or CSR encoded with this key
+    async fn resign(self, der: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let tbs = self
+            .tbs
+            .into_inner()
+            .unwrap()
+            .ok_or_else(|| anyhow!("nothing was signed"))?;
+        let signature = self.signer.sign(&tbs).await?;
+
+        replace_signature(der, &tbs, &signature)
+    }
+}
+
+impl PublicKeyData for CaptureKey<'_> {
+    fn algorithm(&self) -> &'static SignatureAlgorithm {
+        self.signer.algorithm()
+    }
+
+    fn der_bytes(&self) -> &[u8] {
+        self.signer.public_key_raw()
+    }
+}
+
+impl SigningKey for CaptureKey<'_> {
+    fn sign(&self, msg: &[u8]) -> Result<Vec<u8>, rcgen::Error> {
+        *self.tbs.lock().unwrap() = Some(msg.to_vec());
+
+        // ECDSA-Sig-Value { r: 1, s: 1 }
+        Ok(vec![0x30, 0x06, 0x02, 0x01, 0x01, 0x02, 0x01, 0x01])
+    }
+}
+
+/// Replace the signature of a signed X.509 structure (certificate or CSR), which is
+/// `SEQUENCE { tbs, signatureAlgorithm, signature BIT STRING }`
+fn replace_signature(der: &[u8], tbs: &[u8], signature: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut reader = DerReader(der);
+    let mut signed = DerReader(reader.read(0x30)?);
+
+    let (_, _, signed_tbs) = signed.read_tlv()?;
+    let (_, _, algorithm) = signed.read_tlv()?;
+    signed.read(0x03)?;
+
+    if signed_tbs != tbs {
+        return Err(anyhow!("signed data does not match the encoded structure"));
+    }
+
+    let mut bit_string = vec![0];
+    bit_string.extend_from_slice(signature);
+
+    let mut value = vec![];
+    value.extend_from_slice(signed_tbs);
+    value.extend_from_slice(algorithm);
+    value.extend(encode_tlv(0x03, &bit_string));
+
+    Ok(encode_tlv(0x30, &value))
+}
+
+fn encode_tlv(tag: u8, value: &[u8]) -> Vec<u8> {
+    let mut der = vec![tag];
+    if value.len() < 0x80 {
+        der.push(value.len() as u8);
+    } else {
+        let len = value.len().to_be_bytes();
+        let len = &len[len.iter().take_while(|byte| **byte == 0).count()..];
+        der.push(0x80 | len.len() as u8);
+        der.extend_from_slice(len);
+    }
+    der.extend_from_slice(value);
+    der
+}
+
+struct DerReader<'a>(&'a [u8]);
+
+impl<'a> DerReader<'a> {
+    /// Read the value of the next element, which must have the given tag
+    fn read(&mut self, tag: u8) -> anyhow::Result<&'a [u8]> {
+        let (actual_tag, header_len, tlv) = self.read_tlv()?;
+        if actual_tag != tag {
+            return Err(anyhow!("invalid DER: unexpected tag"));
+        }
+
+        Ok(&tlv[header_len..])
+    }
+
+    /// Read the next element, returning its tag, header length and complete encoding
+    fn read_tlv(&mut self) -> anyhow::Result<(u8, usize, &'a [u8])> {
+        let invalid = || anyhow!("invalid DER");
+
+        let [tag, len, rest @ ..] = self.0 else {
+            return Err(invalid());
+        };
+
+        let (len, header_len) = match *len {
+            len if len < 0x80 => (len as usize, 2),
+            len @ 0x81..=0x84 => {
+                let octets = (len & 0x7f) as usize;
+                if rest.len() < octets {
+                    return Err(invalid());
+                }
+                let len = rest[..octets]
+                    .iter()
+                    .fold(0, |len, byte| (len << 8) | *byte as usize);
+                (len, 2 + octets)
+            }
+            _ => return Err(invalid()),
+        };
+        if self.0.len() - header_len < len {
+            return Err(invalid());
+        }
+
+        let (tlv, rest) = self.0.split_at(header_len + len);
+        self.0 = rest;
+        Ok((*tag, header_len, tlv))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ecdsa_signature_roundtrip() {
+        let key_pair = crate::cert::key_pair();
+        let der = SigningKey::sign(&key_pair, b"message").unwrap();
+
+        let fixed = ecdsa_der_to_fixed(&der, 32).unwrap();
+        assert_eq!(fixed.len(), 64);
+        assert_eq!(ecdsa_fixed_to_der(&fixed), der);
+    }
+
+    #[test]
+    fn ecdsa_fixed_to_der_pads_negative_scalars() {
+        let mut fixed = vec![0xff; 32];
+        fixed.extend([0x01; 32]);
+
+        let der = ecdsa_fixed_to_der(&fixed);
+        assert_eq!(&der[..5], &[0x30, 69, 0x02, 33, 0x00]);
+        assert_eq!(ecdsa_der_to_fixed(&der, 32).unwrap(), fixed);
+    }
+
+    #[test]
+    fn replace_signature_reencodes_certificate() {
+        let key_pair = crate::cert::key_pair();
+        let der = crate::cert::authly_ca()
+            .self_signed(&key_pair)
+            .unwrap()
+            .der()
+            .to_vec();
+
+        let mut reader = DerReader(&der);
+        let mut signed = DerReader(reader.read(0x30).unwrap());
+        let (_, _, tbs) = signed.read_tlv().unwrap();
+        signed.read_tlv().unwrap();
+        let signature = &signed.read(0x03).unwrap()[1..];
+
+        // certificates are longer than 255 bytes, exercising long form lengths
+        assert!(der.len() > 0x100);
+        assert_eq!(replace_signature(&der, tbs, signature).unwrap(), der);
+        assert!(replace_signature(&der, b"other", signature).is_err());
+    }
+}
@@ -0,0 +1,203 @@
+//! Runtime statistics for embedding in dashboards.
+//!
+//! Counters are kept in memory per node, while object counts are read from the database.
+//! State owned by other components, like the secrets backend or the database, is read from its owner.
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use authly_db::DbResult;
+use serde::Serialize;
+
+use crate::{
+    clock_skew::ClockSkew,
+    ctx::{GetClockSkew, GetDb, GetStats, ServiceBus},
+    repo::stats_repo,
+};
+
+/// The window used for computing the authentication rate
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+/// In-memory counters for the local Authly node
+pub struct AuthlyStats {
+    started_at: Instant,
+    authentications: AtomicU64,
+    failed_authentications: AtomicU64,
+    recent_authentications: Mutex<VecDeque<Instant>>,
+    k8s_authentications: Mutex<BTreeMap<String, K8sAuthenticationStats>>,
+    secrets_healthy: AtomicBool,
+}
+
+impl Default for AuthlyStats {
+    fn default() -> Self {
+        Self {
+            started_at: Instant::now(),
+            authentications: AtomicU64::new(0),
+            failed_authentications: AtomicU64::new(0),
+            recent_authentications: Mutex::new(VecDeque::new()),
+            k8s_authentications: Mutex::new(BTreeMap::new()),
+            secrets_healthy: AtomicBool::new(true),
+        }
+    }
+}
+
+impl AuthlyStats {
+    /// Record a successful authentication
+    pub fn record_authentication(&self) {
+        self.authentications.fetch_add(1, Ordering::Relaxed);
+
+        let now = Instant::now();
+        let mut recent = self.recent_authentications.lock().unwrap();
+        recent.push_back(now);
+        Self::trim(&mut recent, now);
+    }
+
+    /// Record an authentication attempt that failed because of invalid credentials
+    pub fn record_failed_authentication(&self) {
+        self.failed_authentications.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record the outcome of a Kubernetes service account authentication, per namespace
+    pub fn record_k8s_authentication(&self, namespace: &str, success: bool) {
+        let mut k8s_authentications = self.k8s_authentications.lock().unwrap();
+        let namespace_stats = k8s_authentications
+            .entry(namespace.to_string())
+            .or_default();
+
+        if success {
+            namespace_stats.succeeded += 1;
+        } else {
+            namespace_stats.failed += 1;
+        }
+    }
+
+    /// Record the outcome of the latest secrets backend health probe
+    pub fn record_secrets_health(&self, healthy: bool) {
+        self.secrets_healthy.store(healthy, Ordering::Relaxed);
+    }
+
+    /// The number of successful authentications within the last minute
+    pub fn authentications_per_minute(&self) -> usize {
+        let mut recent = self.recent_authentications.lock().unwrap();
+        Self::trim(&mut recent, Instant::now());
+        recent.len()
+    }
+
+    fn trim(recent: &mut VecDeque<Instant>, now: Instant) {
+        while let Some(oldest) = recent.front() {
+            if now.duration_since(*oldest) > RATE_WINDOW {
+                recent.pop_front();
+            } else {
+                break;
+            }
+        }
+    }
+}
+
+/// The role of the local node in the database cluster
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum RaftRole {
+    Leader,
+    Follower,
+    Candidate,
+    Learner,
+    Shutdown,
+}
+
+/// Serializable snapshot of the node statistics
+#[derive(Serialize, Debug)]
+pub struct StatsSnapshot {
+    pub uptime_secs: u64,
+    /// Whether at-rest encryption is disabled, monitoring should alert on this
+    pub insecure_mode: bool,
+    /// Whether the secrets backend answered the latest health probe
+    pub secrets_healthy: bool,
+    /// How the clock of the node compares to the other nodes of the cluster
+    pub clock_skew: ClockSkew,
+    pub raft_role: RaftRole,
+    /// Whether the node rejects writes and only serves reads, because the cluster has lost quorum
+    pub read_only: bool,
+    /// Number of database statements that exceeded the slow query threshold
+    pub slow_queries: u64,
+    pub authentications: AuthenticationStats,
+    /// Service account authentications through the Kubernetes auth server, by namespace
+    pub k8s_authentications: BTreeMap<String, K8sAuthenticationStats>,
+    pub active_sessions: u64,
+    pub connected_services: ConnectedServiceStats,
+    pub directories: DirectoryStats,
+}
+
+#[derive(Serialize, Debug)]
+pub struct AuthenticationStats {
+    pub total: u64,
+    pub failed: u64,
+    pub per_minute: usize,
+}
+
+#[derive(Serialize, Clone, Default, Debug)]
+pub struct K8sAuthenticationStats {
+    pub succeeded: u64,
+    pub failed: u64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ConnectedServiceStats {
+    /// Number of distinct services with at least one message connection
+    pub services: usize,
+    /// Total number of message connections
+    pub connections: usize,
+    /// Number of services left out of notifications for not answering pings
+    pub unresponsive: usize,
+}
+
+#[derive(Serialize, Default, Debug)]
+pub struct DirectoryStats {
+    pub directories: u64,
+    pub personas: u64,
+    pub groups: u64,
+    pub services: u64,
+    pub policies: u64,
+}
+
+/// Collect a statistics snapshot of the local node
+pub async fn collect_stats(
+    deps: &(impl GetDb + GetStats + GetClockSkew + ServiceBus),
+) -> DbResult<StatsSnapshot> {
+    let now = time::OffsetDateTime::now_utc();
+    let stats = deps.get_stats();
+    let service_connections = deps.service_event_dispatcher().statistics();
+    let service_health = deps.service_event_dispatcher().health();
+
+    Ok(StatsSnapshot {
+        uptime_secs: stats.started_at.elapsed().as_secs(),
+        insecure_mode: deps.is_insecure_mode(),
+        secrets_healthy: stats.secrets_healthy.load(Ordering::Relaxed),
+        clock_skew: deps.get_clock_skew().clock_skew(),
+        raft_role: deps.raft_role().await,
+        read_only: deps.is_read_only(),
+        slow_queries: deps.slow_query_count(),
+        authentications: AuthenticationStats {
+            total: stats.authentications.load(Ordering::Relaxed),
+            failed: stats.failed_authentications.load(Ordering::Relaxed),
+            per_minute: stats.authentications_per_minute(),
+        },
+        k8s_authentications: stats.k8s_authentications.lock().unwrap().clone(),
+        active_sessions: stats_repo::count_active_sessions(deps.get_db(), now).await?,
+        connected_services: ConnectedServiceStats {
+            services: service_connections.len(),
+            connections: service_connections.values().sum(),
+            unresponsive: service_health
+                .values()
+                .filter(|health| !health.responsive)
+                .count(),
+        },
+        directories: stats_repo::directory_stats(deps.get_db()).await?,
+    })
+}
@@ -1,11 +1,15 @@
-use std::{fmt::Display, str::FromStr};
+use std::{fmt::Display, str::FromStr, sync::Arc};
 
-use anyhow::anyhow;
+use anyhow::{anyhow, Context};
 use authly_common::id::ServiceId;
 use authly_db::{Row, TryFromRow};
 use pem::{EncodeConfig, Pem};
 use rcgen::CertificateParams;
-use rustls::pki_types::CertificateDer;
+use rustls::{
+    crypto::CryptoProvider, pki_types::CertificateDer, ConfigBuilder, ProtocolVersion,
+    ServerConfig, WantsVerifier,
+};
+use serde::{Deserialize, Serialize};
 
 #[derive(Clone, Debug)]
 pub struct AuthlyCert {
@@ -70,3 +74,159 @@ impl TryFromRow for AuthlyCert {
         })
     }
 }
+
+/// The oldest TLS protocol version a server accepts
+#[derive(Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, Debug)]
+pub enum TlsVersion {
+    #[default]
+    #[serde(rename = "1.2")]
+    Tls12,
+    #[serde(rename = "1.3")]
+    Tls13,
+}
+
+impl TlsVersion {
+    fn accepts(self, version: ProtocolVersion) -> bool {
+        match self {
+            Self::Tls12 => matches!(version, ProtocolVersion::TLSv1_2 | ProtocolVersion::TLSv1_3),
+            Self::Tls13 => matches!(version, ProtocolVersion::TLSv1_3),
+        }
+    }
+}
+
+/// An application protocol negotiated through ALPN
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Debug)]
+pub enum AlpnProtocol {
+    #[serde(rename = "h2")]
+    H2,
+    #[serde(rename = "http/1.1")]
+    Http11,
+    #[serde(rename = "http/1.0")]
+    Http10,
+}
+
+impl AlpnProtocol {
+    /// The protocols offered by Authly servers unless configured otherwise
+    pub const DEFAULT: &[Self] = &[Self::H2, Self::Http11];
+
+    /// The registered ALPN protocol ID
+    pub fn id(self) -> &'static [u8] {
+        match self {
+            Self::H2 => b"h2",
+            Self::Http11 => b"http/1.1",
+            Self::Http10 => b"http/1.0",
+        }
+    }
+}
+
+/// ALPN protocol IDs in order of server preference, as used in [ServerConfig::alpn_protocols]
+pub fn alpn_protocol_ids(protocols: &[AlpnProtocol]) -> Vec<Vec<u8>> {
+    protocols
+        .iter()
+        .map(|protocol| protocol.id().to_vec())
+        .collect()
+}
+
+/// Restrictions on the TLS connections a server accepts.
+///
+/// Cipher suites and named groups use their IANA names, e.g. `TLS13_AES_256_GCM_SHA384` and `secp384r1`.
+/// An empty list leaves the crypto provider defaults in place.
+#[derive(Clone, Debug)]
+pub struct TlsPolicy {
+    pub min_version: TlsVersion,
+    pub cipher_suites: Vec<String>,
+    pub named_groups: Vec<String>,
+
+    /// Protocols offered through ALPN, clients that offer none of them are rejected
+    pub alpn_protocols: Vec<AlpnProtocol>,
+}
+
+impl Default for TlsPolicy {
+    fn default() -> Self {
+        Self {
+            min_version: TlsVersion::default(),
+            cipher_suites: vec![],
+            named_groups: vec![],
+            alpn_protocols: AlpnProtocol::DEFAULT.to_vec(),
+        }
+    }
+}
+
+impl TlsPolicy {
+    /// Start building a server config restricted by this policy, based on the process-default crypto provider
+    pub fn server_config_builder(
+        &self,
+    ) -> anyhow::Result<ConfigBuilder<ServerConfig, WantsVerifier>> {
+        let provider = self.crypto_provider(
+            CryptoProvider::get_default().context("no default TLS crypto provider installed")?,
+        )?;
+        let versions: Vec<_> = rustls::ALL_VERSIONS
+            .iter()
+            .copied()
+            .filter(|version| self.min_version.accepts(version.version))
+            .collect();
+
+        Ok(ServerConfig::builder_with_provider(Arc::new(provider))
+            .with_protocol_versions(&versions)?)
+    }
+
+    /// Apply the ALPN part of the policy to a built server config
+    pub fn apply_alpn(&self, config: &mut ServerConfig) {
+        config.alpn_protocols = alpn_protocol_ids(&self.alpn_protocols);
+    }
+
+    fn crypto_provider(&self, base: &CryptoProvider) -> anyhow::Result<CryptoProvider> {
+        let mut provider = base.clone();
+
+        if !self.cipher_suites.is_empty() {
+            provider.cipher_suites = select(
+                "cipher suite",
+                &self.cipher_suites,
+                &base.cipher_suites,
+                |suite| suite.suite().as_str(),
+            )?;
+        }
+
+        if !self.named_groups.is_empty() {
+            provider.kx_groups = select(
+                "named group",
+                &self.named_groups,
+                &base.kx_groups,
+                |group| group.name().as_str(),
+            )?;
+        }
+
+        Ok(provider)
+    }
+}
+
+/// Select the supported items named in `names`, keeping the preference order of the provider
+fn select<T: Clone>(
+    kind: &str,
+    names: &[String],
+    supported: &[T],
+    name_of: impl Fn(&T) -> Option<&'static str>,
+) -> anyhow::Result<Vec<T>> {
+    for name in names {
+        if !supported
+            .iter()
+            .any(|item| name_of(item) == Some(name.as_str()))
+        {
+            let supported: Vec<_> = supported.iter().filter_map(&name_of).collect();
+            return Err(anyhow!(
+                "unsupported TLS {kind} `{name}`, supported: {}",
+                supported.join(", ")
+            ));
+        }
+    }
+
+    Ok(supported
+        .iter()
+        .filter(|item| {
+            names
+                .iter()
+                .any(|name| name_of(item) == Some(name.as_str()))
+        })
+        .cloned()
+        .collect())
+}
@@ -0,0 +1,344 @@
+//! Keys for signing access tokens.
+//!
+//! The token signing key is managed independently of the key backing the Authly CA,
+//! so that one can be rotated without affecting the other.
+//! Installations without a dedicated signing key sign tokens with the instance key (compatibility mode).
+//! The instance key stays valid for verification only when a dedicated key took over from it,
+//! new installations never sign tokens with it.
+//!
+//! Several keys can be valid for verification at the same time. Tokens carry the `kid` of their signing key,
+//! and a key that has been superseded stays valid until the tokens it signed have expired.
+
+use std::sync::{Arc, Mutex};
+
+use authly_db::DbError;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hashlink::LruCache;
+use rcgen::KeyPair;
+use serde::Serialize;
+use time::OffsetDateTime;
+use tracing::info;
+
+use crate::{
+    access_token,
+    bus::ClusterMessage,
+    cert::key_pair,
+    ctx::{ClusterBus, GetClock, GetDb, GetDecryptedDeks, GetInstance},
+    encryption::CryptoError,
+    repo::token_signing_repo,
+    signer::{self, InMemorySigner, Signer},
+};
+
+/// How long an access token signed by a remote signer is handed out again for the same claims
+const REMOTE_SIGNED_TOKEN_REUSE: time::Duration = time::Duration::minutes(5);
+
+/// The largest number of access tokens kept for reuse per remote signing key
+const REMOTE_SIGNED_TOKEN_CAPACITY: usize = 10_000;
+
+#[derive(Clone)]
+pub struct TokenSigningKey {
+    kid: String,
+    created_at: OffsetDateTime,
+    supersedes_instance: bool,
+    signer: Arc<dyn Signer>,
+    decoding_key: jsonwebtoken::DecodingKey,
+    /// Recently signed tokens, when signing is a remote request
+    signed_tokens: Option<Arc<Mutex<LruCache<[u8; 32], SignedToken>>>>,
+}
+
+struct SignedToken {
+    token: String,
+    signed_at: OffsetDateTime,
+}
+
+/// Thrown when rotating the token signing key, if it's not managed by Authly
+#[derive(thiserror::Error, Debug)]
+pub enum RotationError {
+    #[error("access tokens are signed by an external key, which must be rotated where it's held")]
+    ExternalKey,
+
+    #[error("db error: {0}")]
+    Db(#[from] DbError),
+
+    #[error("{0}")]
+    Crypto(#[from] CryptoError),
+}
+
+impl TokenSigningKey {
+    pub fn new(key_pair: KeyPair, created_at: OffsetDateTime) -> Self {
+        Self::from_signer(Arc::new(InMemorySigner::new(key_pair)), created_at)
+    }
+
+    /// A signing key which might be held outside the process
+    pub fn from_signer(signer: Arc<dyn Signer>, created_at: OffsetDateTime) -> Self {
+        let public_key = signer.public_key_raw();
+        let kid = hexhex::hex(&blake3::hash(public_key).as_bytes()[..8]).to_string();
+
+        // Assume that EC is always used
+        let decoding_key = jsonwebtoken::DecodingKey::from_ec_der(public_key);
+
+        let signed_tokens = signer
+            .is_remote()
+            .then(|| Arc::new(Mutex::new(LruCache::new(REMOTE_SIGNED_TOKEN_CAPACITY))));
+
+        Self {
+            kid,
+            created_at,
+            supersedes_instance: false,
+            signer,
+            decoding_key,
+            signed_tokens,
+        }
+    }
+
+    /// Mark the key as taking over signing from the instance key, ending compatibility mode
+    pub fn with_supersedes_instance(mut self, supersedes_instance: bool) -> Self {
+        self.supersedes_instance = supersedes_instance;
+        self
+    }
+
+    /// Generate a new key, created at `now`
+    pub fn generate(now: OffsetDateTime) -> Self {
+        Self::new(key_pair(), now)
+    }
+
+    /// The key ID, derived from the public key
+    pub fn kid(&self) -> &str {
+        &self.kid
+    }
+
+    pub fn created_at(&self) -> OffsetDateTime {
+        self.created_at
+    }
+
+    /// Whether the instance key signed tokens before this key
+    pub fn supersedes_instance(&self) -> bool {
+        self.supersedes_instance
+    }
+
+    pub fn signer(&self) -> &dyn Signer {
+        self.signer.as_ref()
+    }
+
+    /// Encode and sign a JWT with this key
+    pub async fn encode_jwt(
+        &self,
+        header: &jsonwebtoken::Header,
+        claims: &impl Serialize,
+    ) -> anyhow::Result<String> {
+        signer::encode_jwt(header, claims, self.signer.as_ref()).await
+    }
+
+    /// Encode and sign an access token with this key.
+    ///
+    /// Signing with a remote key costs a request, so a token signed by it within the last
+    /// few minutes for the same `claims_key` is returned instead of signing a new one.
+    /// The `claims_key` must identify the claims apart from their timestamps.
+    pub async fn encode_access_token(
+        &self,
+        header: &jsonwebtoken::Header,
+        claims: &impl Serialize,
+        claims_key: [u8; 32],
+        now: OffsetDateTime,
+    ) -> anyhow::Result<String> {
+        let Some(signed_tokens) = &self.signed_tokens else {
+            return self.encode_jwt(header, claims).await;
+        };
+
+        if let Some(signed) = signed_tokens.lock().unwrap().get(&claims_key) {
+            if now - signed.signed_at < REMOTE_SIGNED_TOKEN_REUSE {
+                return Ok(signed.token.clone());
+            }
+        }
+
+        let token = self.encode_jwt(header, claims).await?;
+        signed_tokens.lock().unwrap().insert(
+            claims_key,
+            SignedToken {
+                token: token.clone(),
+                signed_at: now,
+            },
+        );
+
+        Ok(token)
+    }
+
+    pub fn decoding_key(&self) -> &jsonwebtoken::DecodingKey {
+        &self.decoding_key
+    }
+
+    /// The public key as a JSON Web Key
+    pub fn to_jwk(&self) -> Jwk {
+        // uncompressed EC point: 0x04 || x || y
+        let point = self.signer.public_key_raw();
+        let (x, y) = point[1..].split_at(point.len() / 2);
+
+        Jwk {
+            kty: "EC",
+            crv: "P-256",
+            alg: "ES256",
+            key_use: "sig",
+            kid: self.kid.clone(),
+            x: URL_SAFE_NO_PAD.encode(x),
+            y: URL_SAFE_NO_PAD.encode(y),
+        }
+    }
+}
+
+/// The set of keys for signing and verifying access tokens
+pub struct TokenSigningKeys {
+    /// A key held outside the database, e.g. in a KMS, which takes precedence for signing
+    external: Option<TokenSigningKey>,
+    /// Dedicated signing keys, the current one first
+    dedicated: Vec<TokenSigningKey>,
+    /// The instance key, which signs tokens in compatibility mode
+    instance: TokenSigningKey,
+}
+
+impl TokenSigningKeys {
+    /// Keys in compatibility mode, where tokens are signed with the instance key
+    pub fn compat(instance: TokenSigningKey) -> Self {
+        Self {
+            external: None,
+            dedicated: vec![],
+            instance,
+        }
+    }
+
+    /// Use dedicated keys, ordered with the current key first
+    pub fn with_dedicated(mut self, dedicated: Vec<TokenSigningKey>) -> Self {
+        self.dedicated = dedicated;
+        self
+    }
+
+    /// Sign new tokens with an external key.
+    /// Dedicated keys remain valid for verifying the tokens they signed.
+    pub fn with_external(mut self, external: TokenSigningKey) -> Self {
+        self.external = Some(external);
+        self
+    }
+
+    pub fn external(&self) -> Option<&TokenSigningKey> {
+        self.external.as_ref()
+    }
+
+    pub fn is_compat(&self) -> bool {
+        self.external.is_none() && self.dedicated.is_empty()
+    }
+
+    /// The key used for signing new tokens
+    pub fn current(&self) -> &TokenSigningKey {
+        self.external
+            .as_ref()
+            .or(self.dedicated.first())
+            .unwrap_or(&self.instance)
+    }
+
+    /// The keys that may have signed tokens which have not yet expired
+    pub fn verification_keys(&self, now: OffsetDateTime) -> impl Iterator<Item = &TokenSigningKey> {
+        // A key stops signing when its successor is created,
+        // after which its tokens live for at most one expiration period.
+        let dedicated = self
+            .dedicated
+            .iter()
+            .enumerate()
+            .filter(move |(idx, _)| {
+                *idx == 0 || self.dedicated[idx - 1].created_at + access_token::EXPIRATION > now
+            })
+            .map(|(_, key)| key);
+
+        let instance = match self.dedicated.last() {
+            None => Some(&self.instance),
+            Some(oldest)
+                if oldest.supersedes_instance
+                    && oldest.created_at + access_token::EXPIRATION > now =>
+            {
+                Some(&self.instance)
+            }
+            Some(_) => None,
+        };
+
+        self.external.iter().chain(dedicated).chain(instance)
+    }
+
+    /// Find the verification key for a token.
+    ///
+    /// Tokens without a `kid` were signed with the instance key, before key IDs were introduced.
+    pub fn find(&self, kid: Option<&str>, now: OffsetDateTime) -> Option<&TokenSigningKey> {
+        let kid = kid.unwrap_or(&self.instance.kid);
+        self.verification_keys(now).find(|key| key.kid == kid)
+    }
+
+    /// The public keys for verifying access tokens
+    pub fn jwks(&self, now: OffsetDateTime) -> JwkSet {
+        JwkSet {
+            keys: self
+                .verification_keys(now)
+                .map(|key| key.to_jwk())
+                .collect(),
+        }
+    }
+}
+
+/// A public JSON Web Key (RFC 7517)
+#[derive(Serialize, Debug)]
+pub struct Jwk {
+    pub kty: &'static str,
+    pub crv: &'static str,
+    pub alg: &'static str,
+    #[serde(rename = "use")]
+    pub key_use: &'static str,
+    pub kid: String,
+    pub x: String,
+    pub y: String,
+}
+
+/// A JSON Web Key Set, as served by the JWKS endpoint
+#[derive(Serialize, Debug)]
+pub struct JwkSet {
+    pub keys: Vec<Jwk>,
+}
+
+/// Generate and store a new token signing key, and make the cluster start using it.
+///
+/// This also ends compatibility mode, if the instance key was used for signing tokens.
+/// Previous keys remain valid for verification until the tokens they signed have expired,
+/// after which they are deleted.
+///
+/// An external token signing key, like one held in a KMS, takes precedence over stored keys,
+/// so rotation is refused while one is configured.
+pub async fn rotate_token_signing_key(
+    deps: &(impl GetDb + GetDecryptedDeks + GetInstance + GetClock + ClusterBus),
+) -> Result<String, RotationError> {
+    if deps.get_instance().external_token_signing_key().is_some() {
+        return Err(RotationError::ExternalKey);
+    }
+
+    let supersedes_instance = !token_signing_repo::has_token_signing_keys(deps.get_db()).await?;
+    let signing_key = TokenSigningKey::generate(deps.get_clock().now())
+        .with_supersedes_instance(supersedes_instance);
+
+    token_signing_repo::save_token_signing_key(
+        deps.get_db(),
+        &signing_key,
+        &deps.get_decrypted_deks(),
+    )
+    .await?;
+
+    let retired = token_signing_repo::delete_retired_token_signing_keys(
+        deps.get_db(),
+        signing_key.created_at() - access_token::EXPIRATION,
+    )
+    .await?;
+    if retired > 0 {
+        info!(retired, "deleted retired token signing keys");
+    }
+
+    info!(kid = signing_key.kid(), "rotated token signing key");
+
+    deps.broadcast_to_cluster(ClusterMessage::TokenSigningKeyChanged)
+        .await
+        .map_err(|err| CryptoError::Crypto(err.into()))?;
+
+    Ok(signing_key.kid)
+}
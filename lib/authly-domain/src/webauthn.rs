@@ -13,11 +13,11 @@ pub use webauthn_rs::prelude::{
 };
 
 use crate::{
-    ctx::{GetDb, GetDecryptedDeks, WebAuthn},
+    ctx::{GetClock, GetDb, GetDecryptedDeks, GetSettings, GetStats, WebAuthn},
     encryption::CryptoError,
     id::BuiltinProp,
-    repo::{crypto_repo, webauthn_repo},
-    session::{init_session, Session},
+    repo::{crypto_repo, scim_repo, webauthn_repo},
+    session::{init_session, AuthClass, Session, SessionKind},
 };
 
 #[derive(Error, Debug)]
@@ -29,6 +29,9 @@ pub enum WebauthnError {
     /// NB: This should not be directly exposed in the Auth UI
     #[error("Username not found")]
     UsernameNotFound,
+    /// NB: This should not be directly exposed in the Auth UI
+    #[error("Persona is deactivated")]
+    Deactivated,
     #[error("db")]
     Db(#[from] DbError),
     #[error("webauthn")]
@@ -71,7 +74,7 @@ pub async fn webauthn_start_registration(
 }
 
 pub async fn webauthn_finish_registration(
-    deps: &(impl GetDb + WebAuthn),
+    deps: &(impl GetDb + WebAuthn + GetDecryptedDeks + GetClock),
     public_uri: &Uri,
     persona_id: PersonaId,
     body: RegisterPublicKeyCredential,
@@ -88,7 +91,8 @@ pub async fn webauthn_finish_registration(
         deps.get_db(),
         persona_id,
         &passkey,
-        time::OffsetDateTime::now_utc(),
+        deps.get_clock().now(),
+        &deps.load_decrypted_deks(),
     )
     .await?;
 
@@ -96,13 +100,17 @@ pub async fn webauthn_finish_registration(
 }
 
 pub async fn webauthn_start_authentication(
-    deps: &(impl GetDb + WebAuthn + GetDecryptedDeks),
+    deps: &(impl GetDb + WebAuthn + GetDecryptedDeks + GetSettings),
     public_uri: &Uri,
     login_session_id: Uuid,
     username: &str,
     session_ttl: Duration,
 ) -> Result<RequestChallengeResponse, WebauthnError> {
     let ident_fingerprint = {
+        let username = deps
+            .get_settings()
+            .ident_normalization
+            .normalize(BuiltinProp::Username.into(), &username);
         let deks = deps.get_decrypted_deks();
         let dek = deks.get(BuiltinProp::Username.into()).unwrap();
 
@@ -113,6 +121,7 @@ pub async fn webauthn_start_authentication(
         deps.get_db(),
         BuiltinProp::Username.into(),
         &ident_fingerprint,
+        &deps.load_decrypted_deks(),
     )
     .await?;
 
@@ -138,10 +147,11 @@ pub async fn webauthn_start_authentication(
 }
 
 pub async fn webauthn_finish_authentication(
-    deps: &(impl GetDb + WebAuthn),
+    deps: &(impl GetDb + GetDecryptedDeks + GetSettings + GetStats + GetClock + WebAuthn),
     public_uri: &Uri,
     login_session_id: Uuid,
     credential: PublicKeyCredential,
+    session_kind: SessionKind,
 ) -> Result<(PersonaId, Session), WebauthnError> {
     let Some((persona_id, passkey_authentication)) =
         deps.yank_passkey_authentication(login_session_id).await
@@ -153,7 +163,16 @@ pub async fn webauthn_finish_authentication(
         .get_webauthn(public_uri)?
         .finish_passkey_authentication(&credential, &passkey_authentication)?;
 
-    for mut row in webauthn_repo::list_passkeys_by_entity_id(deps.get_db(), persona_id).await? {
+    if scim_repo::is_deactivated(deps.get_db(), persona_id.upcast()).await? {
+        deps.get_stats().record_failed_authentication();
+        return Err(WebauthnError::Deactivated);
+    }
+
+    let deks = deps.load_decrypted_deks();
+
+    for mut row in
+        webauthn_repo::list_passkeys_by_entity_id(deps.get_db(), persona_id, &deks).await?
+    {
         match row.passkey.update_credential(&auth_result) {
             Some(true) => {
                 info!(
@@ -164,7 +183,8 @@ pub async fn webauthn_finish_authentication(
                     deps.get_db(),
                     persona_id,
                     &row.passkey,
-                    time::OffsetDateTime::now_utc(),
+                    deps.get_clock().now(),
+                    &deks,
                 )
                 .await?;
             }
@@ -177,7 +197,7 @@ pub async fn webauthn_finish_authentication(
                     deps.get_db(),
                     persona_id,
                     row.passkey.cred_id(),
-                    time::OffsetDateTime::now_utc(),
+                    deps.get_clock().now(),
                 )
                 .await?;
             }
@@ -185,7 +205,8 @@ pub async fn webauthn_finish_authentication(
         }
     }
 
-    let session = init_session(deps, persona_id.upcast()).await?;
+    let session =
+        init_session(deps, persona_id.upcast(), session_kind, AuthClass::WebAuthn).await?;
 
     Ok((persona_id, session))
 }
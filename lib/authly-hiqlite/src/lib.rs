@@ -1,17 +1,163 @@
-use std::{borrow::Cow, fmt::Debug, ops::Deref};
+use std::{
+    borrow::Cow,
+    fmt::Debug,
+    future::Future,
+    ops::Deref,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
-use authly_db::{Db, DbError, FromRow, Row, TryFromRow};
+use authly_db::{slow_query::SlowQueryLog, Db, DbError, FromRow, Row, TryFromRow, TxnResults};
 use bytemuck::{TransparentWrapper, TransparentWrapperAlloc};
 use hiqlite::{Params, StmtIndex};
+use openraft::{RaftMetrics, ServerState};
+use tokio::sync::watch;
+use tracing::{debug, info, warn};
+
+/// How many times a write is retried while the cluster leader is changing
+const NOT_LEADER_RETRIES: u32 = 5;
+
+/// Backoff before the first retry, doubled for each following retry
+const NOT_LEADER_BACKOFF: Duration = Duration::from_millis(50);
+
+/// How long a leader may go without being acknowledged by a quorum before the quorum is considered lost
+const QUORUM_ACK_TIMEOUT: Duration = Duration::from_secs(3);
 
 #[derive(Clone)]
 pub struct HiqliteClient {
     client: hiqlite::Client,
+    slow_query_log: SlowQueryLog,
+    /// Set while the cluster has lost quorum, writes are rejected meanwhile
+    read_only: Arc<AtomicBool>,
+    /// The leadership of the local node, as of the last quorum check
+    leadership: Arc<watch::Sender<Leadership>>,
+}
+
+/// The leadership of the local node in the raft cluster
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub struct Leadership {
+    /// The ID of the local node
+    pub node_id: u64,
+    /// The current raft term
+    pub term: u64,
+    /// Whether the local node is the leader in the current term
+    pub is_leader: bool,
 }
 
 impl HiqliteClient {
-    pub const fn new(client: hiqlite::Client) -> Self {
-        Self { client }
+    pub fn new(client: hiqlite::Client) -> Self {
+        Self {
+            client,
+            slow_query_log: SlowQueryLog::default(),
+            read_only: Arc::new(AtomicBool::new(false)),
+            leadership: Arc::new(watch::channel(Leadership::default()).0),
+        }
+    }
+
+    pub fn with_slow_query_log(mut self, slow_query_log: SlowQueryLog) -> Self {
+        self.slow_query_log = slow_query_log;
+        self
+    }
+
+    pub fn slow_query_log(&self) -> &SlowQueryLog {
+        &self.slow_query_log
+    }
+
+    /// Whether the local node is part of a cluster with quorum:
+    /// either a follower of a known leader, or a leader recently acknowledged by a quorum.
+    pub async fn has_quorum(&self) -> bool {
+        match self.client.metrics_db().await {
+            Ok(metrics) => metrics_have_quorum(&metrics),
+            Err(_) => false,
+        }
+    }
+
+    /// Whether the node is in read-only mode, rejecting writes because the cluster has lost quorum
+    pub fn is_read_only(&self) -> bool {
+        self.read_only.load(Ordering::Relaxed)
+    }
+
+    /// Subscribe to changes of the leadership of the local node.
+    ///
+    /// The leadership is updated by [Self::update_read_only].
+    pub fn subscribe_leadership(&self) -> watch::Receiver<Leadership> {
+        self.leadership.subscribe()
+    }
+
+    /// Enter read-only mode when the cluster has lost quorum, and leave it when quorum is regained.
+    /// Leadership subscribers are notified when the leadership of the local node changed.
+    ///
+    /// Returns whether the node is in read-only mode.
+    pub async fn update_read_only(&self) -> bool {
+        let metrics = self.client.metrics_db().await.ok();
+        let read_only = !metrics.as_ref().is_some_and(metrics_have_quorum);
+        let was_read_only = self.read_only.swap(read_only, Ordering::Relaxed);
+
+        match (was_read_only, read_only) {
+            (false, true) => warn!("cluster quorum lost, entering read-only mode"),
+            (true, false) => info!("cluster quorum regained, leaving read-only mode"),
+            _ => {}
+        }
+
+        if let Some(metrics) = metrics {
+            let leadership = Leadership {
+                node_id: metrics.id,
+                term: metrics.current_term,
+                is_leader: metrics.state == ServerState::Leader,
+            };
+            self.leadership.send_if_modified(|current| {
+                let modified = *current != leadership;
+                *current = leadership;
+                modified
+            });
+        }
+
+        read_only
+    }
+
+    /// Run a write, retrying with bounded backoff while it fails because this node is not the leader.
+    ///
+    /// Each attempt is forwarded by the hiqlite client to the leader it currently knows about,
+    /// so a retry succeeds as soon as an election has settled.
+    /// In read-only mode, the write is rejected without being attempted.
+    async fn write_leader<T, F, Fut>(&self, mut write: F) -> Result<T, DbError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, hiqlite::Error>>,
+    {
+        if self.is_read_only() {
+            return Err(DbError::ReadOnly);
+        }
+
+        let mut backoff = NOT_LEADER_BACKOFF;
+        let mut retries = 0;
+
+        loop {
+            match write().await.map_err(hql_err) {
+                Err(DbError::NotLeader { leader }) if retries < NOT_LEADER_RETRIES => {
+                    debug!(?leader, retries, "write did not reach the leader, retrying");
+                    tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                    retries += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// Whether the metrics describe a follower of a known leader, or a leader recently acknowledged by a quorum
+fn metrics_have_quorum(metrics: &RaftMetrics<u64, hiqlite::Node>) -> bool {
+    match metrics.state {
+        ServerState::Leader => !matches!(
+            metrics.millis_since_quorum_ack,
+            Some(millis) if millis > QUORUM_ACK_TIMEOUT.as_millis() as u64
+        ),
+        ServerState::Follower => metrics.current_leader.is_some(),
+        ServerState::Candidate | ServerState::Learner | ServerState::Shutdown => false,
     }
 }
 
@@ -30,7 +176,12 @@ impl Db for HiqliteClient {
     where
         T: crate::FromRow + Send + 'static,
     {
-        let values = hiqlite::Client::query_map::<HiqliteWrapper<T>, _>(self, stmt, params)
+        let values = self
+            .slow_query_log
+            .observe(
+                &stmt,
+                hiqlite::Client::query_map::<HiqliteWrapper<T>, _>(self, stmt.clone(), params),
+            )
             .await
             .map_err(hql_err)?;
         Ok(TransparentWrapperAlloc::<T>::peel_vec(values))
@@ -44,12 +195,14 @@ impl Db for HiqliteClient {
     where
         T: FromRow + Send + 'static,
     {
-        Ok(
-            hiqlite::Client::query_map_optional::<HiqliteWrapper<T>, _>(self, stmt, params)
-                .await
-                .map_err(hql_err)?
-                .map(|wrapper| wrapper.0),
-        )
+        let value =
+            hiqlite::Client::query_map_optional::<HiqliteWrapper<T>, _>(self, stmt.clone(), params);
+        Ok(self
+            .slow_query_log
+            .observe(&stmt, value)
+            .await
+            .map_err(hql_err)?
+            .map(|wrapper| wrapper.0))
     }
 
     async fn query_try_map_opt<T>(
@@ -60,14 +213,17 @@ impl Db for HiqliteClient {
     where
         T: TryFromRow + Send + 'static,
     {
-        Ok(
-            hiqlite::Client::query_map_optional::<HiqliteTryWrapper<Result<T, T::Error>>, _>(
-                self, stmt, params,
-            )
+        let value = hiqlite::Client::query_map_optional::<HiqliteTryWrapper<Result<T, T::Error>>, _>(
+            self,
+            stmt.clone(),
+            params,
+        );
+        Ok(self
+            .slow_query_log
+            .observe(&stmt, value)
             .await
             .map_err(hql_err)?
-            .map(|wrapper| wrapper.0),
-        )
+            .map(|wrapper| wrapper.0))
     }
 
     async fn query_filter_map<T>(
@@ -80,10 +236,15 @@ impl Db for HiqliteClient {
         <T as TryFromRow>::Error: Debug,
     {
         let values = hiqlite::Client::query_map::<HiqliteTryWrapper<Result<T, T::Error>>, _>(
-            self, stmt, params,
-        )
-        .await
-        .map_err(hql_err)?;
+            self,
+            stmt.clone(),
+            params,
+        );
+        let values = self
+            .slow_query_log
+            .observe(&stmt, values)
+            .await
+            .map_err(hql_err)?;
         Ok(values
             .into_iter()
             .filter_map(|HiqliteTryWrapper(result)| match result {
@@ -97,9 +258,9 @@ impl Db for HiqliteClient {
     }
 
     async fn execute(&self, sql: Cow<'static, str>, params: Params) -> Result<usize, DbError> {
-        hiqlite::Client::execute(self, sql, params)
-            .await
-            .map_err(hql_err)
+        let write =
+            self.write_leader(|| hiqlite::Client::execute(self, sql.clone(), params.clone()));
+        self.slow_query_log.observe(&sql, write).await
     }
 
     async fn execute_map<T>(
@@ -110,10 +271,14 @@ impl Db for HiqliteClient {
     where
         T: FromRow + Send + 'static,
     {
-        let values =
-            hiqlite::Client::execute_returning_map::<_, HiqliteWrapper<T>>(self, sql, params)
-                .await
-                .map_err(hql_err)?;
+        let write = self.write_leader(|| {
+            hiqlite::Client::execute_returning_map::<_, HiqliteWrapper<T>>(
+                self,
+                sql.clone(),
+                params.clone(),
+            )
+        });
+        let values = self.slow_query_log.observe(&sql, write).await?;
         Ok(values
             .into_iter()
             .map(|result| result.map(|wrapper| wrapper.0).map_err(hql_err))
@@ -124,16 +289,25 @@ impl Db for HiqliteClient {
         StmtIndex(stmt_index).column(column_index).into()
     }
 
-    async fn transact(
-        &self,
-        sql: Vec<(Cow<'static, str>, Params)>,
-    ) -> Result<Vec<Result<usize, DbError>>, DbError> {
-        Ok(hiqlite::Client::txn(self, sql)
-            .await
-            .map_err(hql_err)?
+    async fn transact(&self, sql: Vec<(Cow<'static, str>, Params)>) -> Result<TxnResults, DbError> {
+        let stmt_count = sql.len();
+        let log_sql = sql
+            .iter()
+            .map(|(sql, _)| sql.as_ref())
+            .collect::<Vec<_>>()
+            .join(";\n");
+        let write = self.write_leader(|| hiqlite::Client::txn(self, sql.clone()));
+        let results = self
+            .slow_query_log
+            .observe(&log_sql, write)
+            .await?
             .into_iter()
             .map(|result| result.map_err(hql_err))
-            .collect())
+            .collect();
+
+        // hiqlite does not report the outcome itself,
+        // it is derived from the statement results in the same way as for sqlite
+        Ok(TxnResults::new(stmt_count, results))
     }
 }
 
@@ -188,6 +362,10 @@ unsafe impl<T> TransparentWrapper<T> for HiqliteWrapper<T> {}
 unsafe impl<T> TransparentWrapper<T> for HiqliteTryWrapper<T> {}
 
 fn hql_err(err: hiqlite::Error) -> DbError {
+    if let Some((leader, _)) = err.is_forward_to_leader() {
+        return DbError::NotLeader { leader };
+    }
+
     match err {
         hiqlite::Error::Sqlite(msg) => DbError::Sql(msg),
         err => DbError::Other(format!("{err:?}").into()),
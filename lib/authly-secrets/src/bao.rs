@@ -195,6 +195,19 @@ impl AuthlySecrets for BaoBackend {
         "bao"
     }
 
+    async fn health(&self) -> anyhow::Result<()> {
+        let url = &self.url;
+
+        // responds with an error status when sealed or uninitialized
+        self.client
+            .get(format!("{url}/v1/sys/health"))
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(())
+    }
+
     async fn gen_versioned(&self, name: &str) -> anyhow::Result<(Version, Secret)> {
         Ok(self.gen_secret(name).await?)
     }
@@ -0,0 +1,255 @@
+//! A secrets backend trying several backends in order
+
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use secrecy::ExposeSecret;
+use tracing::warn;
+
+use crate::{AuthlySecrets, Secret, Version};
+
+/// How long a secret read from a backend may be served from memory when all the backends fail
+const CACHE_TTL: Duration = Duration::from_secs(15 * 60);
+
+/// Delegates to the first backend that works.
+///
+/// Secrets that have been read recently are kept in memory for [CACHE_TTL],
+/// so a transient outage of all the backends doesn't prevent re-reading them.
+/// Cached secrets are zeroized when they expire, since [Secret] zeroizes its contents on drop.
+///
+/// New secrets are only generated by the primary backend, the others only serve existing secrets.
+pub struct FailoverBackend {
+    backends: Vec<Box<dyn AuthlySecrets>>,
+    cache_ttl: Duration,
+    cache: Mutex<HashMap<(String, Vec<u8>), CachedSecret>>,
+}
+
+struct CachedSecret {
+    secret: Secret,
+    cached_at: Instant,
+}
+
+impl FailoverBackend {
+    pub fn new(backends: Vec<Box<dyn AuthlySecrets>>) -> Self {
+        Self {
+            backends,
+            cache_ttl: CACHE_TTL,
+            cache: Default::default(),
+        }
+    }
+
+    fn cached(&self, name: &str, version: &[u8]) -> Option<Secret> {
+        let mut cache = self.cache.lock().unwrap();
+        self.evict_expired(&mut cache);
+        let cached = cache.get(&(name.to_string(), version.to_vec()))?;
+
+        Some(Secret::init_with(|| *cached.secret.expose_secret()))
+    }
+
+    fn put_cache(&self, name: &str, version: &[u8], secret: &Secret) {
+        let mut cache = self.cache.lock().unwrap();
+        self.evict_expired(&mut cache);
+        cache.insert(
+            (name.to_string(), version.to_vec()),
+            CachedSecret {
+                secret: Secret::init_with(|| *secret.expose_secret()),
+                cached_at: Instant::now(),
+            },
+        );
+    }
+
+    fn evict_expired(&self, cache: &mut HashMap<(String, Vec<u8>), CachedSecret>) {
+        cache.retain(|_, cached| cached.cached_at.elapsed() < self.cache_ttl);
+    }
+}
+
+#[async_trait]
+impl AuthlySecrets for FailoverBackend {
+    fn name(&self) -> &'static str {
+        self.backends
+            .first()
+            .map(|backend| backend.name())
+            .unwrap_or("failover")
+    }
+
+    async fn health(&self) -> anyhow::Result<()> {
+        let mut errors = vec![];
+
+        for backend in &self.backends {
+            match backend.health().await {
+                Ok(()) => return Ok(()),
+                Err(err) => errors.push(format!("{}: {err}", backend.name())),
+            }
+        }
+
+        Err(anyhow!("no healthy secrets backend: {}", errors.join(", ")))
+    }
+
+    /// Generate a secret on the primary backend.
+    ///
+    /// A secret generated on a fallback would not be known to the primary once it's back,
+    /// so generation fails instead of failing over.
+    async fn gen_versioned(&self, name: &str) -> anyhow::Result<(Version, Secret)> {
+        let primary = self
+            .backends
+            .first()
+            .ok_or_else(|| anyhow!("no secrets backends configured"))?;
+
+        let (version, secret) = primary.gen_versioned(name).await?;
+        self.put_cache(name, &version.0, &secret);
+
+        Ok((version, secret))
+    }
+
+    async fn get_versioned(&self, name: &str, version: &[u8]) -> anyhow::Result<Secret> {
+        let mut last_err = anyhow!("no secrets backends configured");
+
+        for backend in &self.backends {
+            match backend.get_versioned(name, version).await {
+                Ok(secret) => {
+                    self.put_cache(name, version, &secret);
+                    return Ok(secret);
+                }
+                Err(err) => {
+                    warn!(?err, backend = backend.name(), "failed to get secret");
+                    last_err = err;
+                }
+            }
+        }
+
+        match self.cached(name, version) {
+            Some(secret) => {
+                warn!(name, "all secrets backends failed, using cached secret");
+                Ok(secret)
+            }
+            None => Err(last_err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use anyhow::anyhow;
+    use async_trait::async_trait;
+    use secrecy::ExposeSecret;
+
+    use crate::{AuthlySecrets, Secret, Version};
+
+    use super::FailoverBackend;
+
+    struct MockBackend {
+        healthy: Arc<AtomicBool>,
+        secret: [u8; 32],
+    }
+
+    #[async_trait]
+    impl AuthlySecrets for MockBackend {
+        fn name(&self) -> &'static str {
+            "mock"
+        }
+
+        async fn health(&self) -> anyhow::Result<()> {
+            if self.healthy.load(Ordering::SeqCst) {
+                Ok(())
+            } else {
+                Err(anyhow!("unavailable"))
+            }
+        }
+
+        async fn gen_versioned(&self, name: &str) -> anyhow::Result<(Version, Secret)> {
+            self.health().await?;
+            Ok((
+                Version(name.as_bytes().to_vec()),
+                Secret::init_with(|| self.secret),
+            ))
+        }
+
+        async fn get_versioned(&self, _name: &str, _version: &[u8]) -> anyhow::Result<Secret> {
+            self.health().await?;
+            Ok(Secret::init_with(|| self.secret))
+        }
+    }
+
+    fn mock(secret: u8) -> (Arc<AtomicBool>, Box<dyn AuthlySecrets>) {
+        let healthy = Arc::new(AtomicBool::new(true));
+        let backend = MockBackend {
+            healthy: healthy.clone(),
+            secret: [secret; 32],
+        };
+
+        (healthy, Box::new(backend))
+    }
+
+    #[tokio::test]
+    async fn test_unhealthy_backend_serves_cached_secret() {
+        let (healthy, backend) = mock(1);
+        let secrets = FailoverBackend::new(vec![backend]);
+
+        assert!(secrets.health().await.is_ok());
+        let (version, _) = secrets.gen_versioned("master-key").await.unwrap();
+
+        healthy.store(false, Ordering::SeqCst);
+        assert!(secrets.health().await.is_err());
+
+        let secret = secrets
+            .get_versioned("master-key", &version.0)
+            .await
+            .unwrap();
+        assert_eq!(secret.expose_secret(), &[1; 32]);
+
+        // unknown secrets can't be served from the cache
+        assert!(secrets.get_versioned("other", &version.0).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_cached_secret_expires() {
+        let (healthy, backend) = mock(1);
+        let mut secrets = FailoverBackend::new(vec![backend]);
+        secrets.cache_ttl = Duration::ZERO;
+
+        let (version, _) = secrets.gen_versioned("master-key").await.unwrap();
+
+        healthy.store(false, Ordering::SeqCst);
+        assert!(secrets
+            .get_versioned("master-key", &version.0)
+            .await
+            .is_err());
+        assert!(secrets.cache.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_secrets_are_only_generated_by_primary() {
+        let (primary_healthy, primary) = mock(1);
+        let (_, fallback) = mock(2);
+        let secrets = FailoverBackend::new(vec![primary, fallback]);
+
+        primary_healthy.store(false, Ordering::SeqCst);
+        assert!(secrets.gen_versioned("master-key").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_failover_to_next_backend() {
+        let (primary_healthy, primary) = mock(1);
+        let (_, fallback) = mock(2);
+        let secrets = FailoverBackend::new(vec![primary, fallback]);
+
+        primary_healthy.store(false, Ordering::SeqCst);
+        assert!(secrets.health().await.is_ok());
+
+        let secret = secrets.get_versioned("master-key", b"v").await.unwrap();
+        assert_eq!(secret.expose_secret(), &[2; 32]);
+    }
+}
@@ -1,10 +1,12 @@
 use async_trait::async_trait;
 use bao::BaoBackend;
+use failover::FailoverBackend;
 use local_unencrypted::LocalUnencryptedBackend;
 use secrecy::SecretBox;
-use tracing::warn;
+use tracing::error;
 
 mod bao;
+mod failover;
 mod local_unencrypted;
 
 /// A secret. Currently all secrets are 256 bit.
@@ -22,9 +24,19 @@ pub struct Version(pub Vec<u8>);
 ///
 /// Therefore, this secret backend can't be used for secrets needed before the cluster is up and running.
 #[async_trait]
-pub trait AuthlySecrets {
+pub trait AuthlySecrets: Send + Sync {
     fn name(&self) -> &'static str;
 
+    /// Probe whether the backend is currently able to serve secrets
+    async fn health(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Whether secrets from this backend offer no real protection
+    fn is_insecure(&self) -> bool {
+        false
+    }
+
     /// Generate a new versioned secret
     async fn gen_versioned(&self, name: &str) -> anyhow::Result<(Version, Secret)>;
 
@@ -38,24 +50,43 @@ pub struct AuthlySecretsBuilder {
     pub bao_url: Option<String>,
     pub bao_token: Option<String>,
 
+    /// Bao instances to try in order when the primary `bao_url` fails
+    pub bao_fallback_urls: Vec<String>,
+
     /// A last chance to continue insecurely if none of the real backends are configured successfully
     pub danger_disable_encryption: bool,
+
+    /// Explicit acknowledgment required in addition to `danger_disable_encryption`
+    pub i_understand_this_is_insecure: bool,
 }
 
 impl AuthlySecretsBuilder {
     pub fn build(self, client: reqwest::Client) -> Result<Box<dyn AuthlySecrets>, &'static str> {
         if let Some(bao_url) = self.bao_url {
-            return Ok(Box::new(BaoBackend::new(
-                self.authly_uid,
-                bao_url,
-                self.bao_token,
-                client,
-            )));
+            let backends = std::iter::once(bao_url)
+                .chain(self.bao_fallback_urls)
+                .map(|url| -> Box<dyn AuthlySecrets> {
+                    Box::new(BaoBackend::new(
+                        self.authly_uid,
+                        url,
+                        self.bao_token.clone(),
+                        client.clone(),
+                    ))
+                })
+                .collect();
+
+            return Ok(Box::new(FailoverBackend::new(backends)));
         }
 
         // the last clause:
         if self.danger_disable_encryption {
-            warn!("WARNING: Authly encryption is disabled! This should never be configured in a production system!");
+            if !self.i_understand_this_is_insecure {
+                return Err("encryption is disabled, but the insecure mode is not acknowledged with I_UNDERSTAND_THIS_IS_INSECURE=true");
+            }
+
+            error!("****************************************************************");
+            error!("WARNING: Authly encryption is disabled! This should never be configured in a production system!");
+            error!("****************************************************************");
             return Ok(Box::new(LocalUnencryptedBackend));
         }
 
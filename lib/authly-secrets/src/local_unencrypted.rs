@@ -16,6 +16,10 @@ impl AuthlySecrets for LocalUnencryptedBackend {
         "local UNENCRYPTED secret store (WARNING!)"
     }
 
+    fn is_insecure(&self) -> bool {
+        true
+    }
+
     async fn gen_versioned(&self, _name: &str) -> anyhow::Result<(Version, Secret)> {
         Ok((Version(VERSION.to_vec()), Secret::init_with(|| SECRET)))
     }
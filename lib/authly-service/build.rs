@@ -0,0 +1,16 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    tonic_prost_build::configure()
+        .protoc_arg("--experimental_allow_proto3_optional")
+        .compile_protos(
+            &[
+                "proto/authly_admin.proto",
+                "proto/authly_policy.proto",
+                "proto/authly_token.proto",
+            ],
+            &["proto/"],
+        )?;
+
+    println!("cargo:rerun-if-changed=build.rs");
+
+    Ok(())
+}
@@ -4,12 +4,12 @@ use authly_common::id::ServiceId;
 use authly_domain::{
     audit::Actor,
     cert::authly_ca,
-    ctx::{GetDb, GetInstance},
+    ctx::{GetDb, GetIdGenerator, GetInstance},
     serde_util::UrlSafeBase64,
     tls::{AuthlyCert, AuthlyCertKind},
 };
 use rand::{rngs::OsRng, Rng};
-use rcgen::{CertificateSigningRequestParams, DnValue, Issuer, PublicKeyData};
+use rcgen::{CertificateSigningRequestParams, DnValue, PublicKeyData};
 use tracing::warn;
 
 use crate::repo::authority_mandate_repo::{self, AmDbError};
@@ -22,6 +22,9 @@ pub enum AuthoritySubmissionError {
     #[error("token generation problem: {0}")]
     Token(#[from] jsonwebtoken::errors::Error),
 
+    #[error("token signing problem: {0}")]
+    TokenSigning(anyhow::Error),
+
     #[error("csr missing entity ID")]
     CsrMissingEntityId,
 
@@ -29,7 +32,7 @@ pub enum AuthoritySubmissionError {
     CsrEntityIdMismatch,
 
     #[error("csr error: {0}")]
-    CsrOther(rcgen::Error),
+    CsrOther(anyhow::Error),
 
     #[error("database error")]
     Db(#[from] AmDbError),
@@ -38,7 +41,7 @@ pub enum AuthoritySubmissionError {
 pub struct PreissuedCode(pub Vec<u8>);
 
 pub async fn authority_generate_submission_token(
-    deps: &(impl GetDb + GetInstance),
+    deps: &(impl GetDb + GetInstance + GetIdGenerator),
     self_url: String,
     actor: Actor,
     preissued_code: Option<PreissuedCode>,
@@ -52,7 +55,7 @@ pub async fn authority_generate_submission_token(
     let expiration = now + SUBMISSION_CODE_EXPIRATION;
 
     // Assign new Entity ID to mandate
-    let mandate_entity_id = ServiceId::random();
+    let mandate_entity_id: ServiceId = deps.get_id_generator().generate();
 
     let claims = SubmissionClaims {
         iat: now.unix_timestamp(),
@@ -65,11 +68,10 @@ pub async fn authority_generate_submission_token(
     };
 
     let jwt_header = jsonwebtoken::Header::new(jsonwebtoken::Algorithm::ES256);
-    Ok(jsonwebtoken::encode(
-        &jwt_header,
-        &claims,
-        &deps.get_instance().local_jwt_encoding_key(),
-    )?)
+    deps.get_instance()
+        .sign_local_jwt(&jwt_header, &claims)
+        .await
+        .map_err(AuthoritySubmissionError::TokenSigning)
 }
 
 async fn save_new_submission_code(
@@ -130,9 +132,9 @@ pub async fn authority_fulfill_submission(
         }
     };
 
-    let issuer = Issuer::new(instance.local_ca().params.clone(), instance.private_key());
-    let mandate_local_ca = authly_ca()
-        .signed_by(&csr_params.public_key, &issuer)
+    let mandate_local_ca = instance
+        .sign_certificate(&authly_ca(), &csr_params.public_key)
+        .await
         .map_err(|err| {
             warn!(?err, "unable to sign mandate CA");
             AuthoritySubmissionError::CsrOther(err)
@@ -140,12 +142,10 @@ pub async fn authority_fulfill_submission(
 
     let mandate_public_key = csr_params.public_key.der_bytes().to_vec();
 
-    let mandate_identity = csr_params
-        .signed_by(&issuer)
-        .map_err(|err| {
-            warn!(?err, "unable to sign mandate identity");
-            AuthoritySubmissionError::CsrOther(err)
-        })?;
+    let mandate_identity = instance.sign_csr(&csr_params).await.map_err(|err| {
+        warn!(?err, "unable to sign mandate identity");
+        AuthoritySubmissionError::CsrOther(err)
+    })?;
 
     authority_mandate_repo::insert_authority_mandate(
         deps.get_db(),
@@ -163,14 +163,14 @@ pub async fn authority_fulfill_submission(
             certifies: mandate_eid,
             signed_by: instance.authly_eid(),
             params: csr_params.params.clone(), // Use original CSR params
-            der: mandate_identity.der().clone(),
+            der: mandate_identity,
         },
         mandate_local_ca: AuthlyCert {
             kind: AuthlyCertKind::Ca,
             certifies: mandate_eid,
             signed_by: instance.authly_eid(),
             params: authly_ca(), // Use original CA params
-            der: mandate_local_ca.der().clone(),
+            der: mandate_local_ca,
         },
     })
 }
@@ -12,7 +12,7 @@ use authly_connect::{
     client::new_authly_connect_grpc_client_service, no_trust_verifier::NoTrustVerifier,
     TunnelSecurity,
 };
-use authly_db::{param::ToBlob, params, Db};
+use authly_db::{param::ToBlob, params, Db, TxnResults};
 use authly_domain::{
     bus::{BusError, ClusterMessage},
     cert::client_cert_csr,
@@ -20,8 +20,8 @@ use authly_domain::{
     repo::crypto_repo,
 };
 use bytes::Bytes;
-use rcgen::{CertificateParams, CertificateSigningRequest, DnType, KeyUsagePurpose};
-use rustls::ClientConfig;
+use rcgen::{CertificateParams, DnType, KeyUsagePurpose};
+use rustls::{pki_types::CertificateSigningRequestDer, ClientConfig};
 use tracing::error;
 
 use super::{MandateSubmissionData, SubmissionClaims};
@@ -36,7 +36,7 @@ pub enum MandateSubmissionError {
     Connect(anyhow::Error),
 
     #[error("csr error: {0}")]
-    Csr(rcgen::Error),
+    Csr(anyhow::Error),
 
     #[error("submission protocol error: {0}")]
     Protocol(tonic::Status),
@@ -80,18 +80,20 @@ pub async fn mandate_execute_submission(
     // Ask the authority to sign a identity certificate.
     // The private key never leaves the mandate.
     // The certificate will be used in subsequent communication with the authority.
-    let identity_csr = client_cert_csr(
-        "authly",
-        claims.authly.mandate_entity_id,
-        time::Duration::days(365 * 100),
-    )
-    .serialize_request(deps.get_instance().private_key())
-    .map_err(MandateSubmissionError::Csr)?;
+    let identity_csr = deps
+        .get_instance()
+        .serialize_request(&client_cert_csr(
+            "authly",
+            claims.authly.mandate_entity_id,
+            time::Duration::days(365 * 100),
+        ))
+        .await
+        .map_err(MandateSubmissionError::Csr)?;
 
     let response = submission_grpc_client
         .submit(proto::SubmissionRequest {
             token,
-            identity_csr_der: identity_csr.der().to_vec().into(),
+            identity_csr_der: identity_csr.to_vec().into(),
         })
         .await
         .map_err(MandateSubmissionError::Protocol)?
@@ -100,10 +102,14 @@ pub async fn mandate_execute_submission(
     let mandate_submission_data =
         MandateSubmissionData::try_from(response).map_err(MandateSubmissionError::Protobuf)?;
     let stmts = mandate_fulfill_submission_txn_statements(deps.get_db(), mandate_submission_data);
-    deps.get_db().transact(stmts).await.map_err(|err| {
-        error!(?err, "submission transaction error");
-        MandateSubmissionError::Db
-    })?;
+    deps.get_db()
+        .transact(stmts)
+        .await
+        .and_then(TxnResults::into_result)
+        .map_err(|err| {
+            error!(?err, "submission transaction error");
+            MandateSubmissionError::Db
+        })?;
 
     // notify ourselves and the rest of the cluster
     deps.broadcast_to_cluster(ClusterMessage::InstanceChanged)
@@ -129,10 +135,10 @@ pub fn mandate_decode_submission_token(
     .claims)
 }
 
-pub fn mandate_identity_signing_request(
+pub async fn mandate_identity_signing_request(
     deps: &dyn GetInstance,
     mandate_eid: ServiceId,
-) -> anyhow::Result<CertificateSigningRequest> {
+) -> anyhow::Result<CertificateSigningRequestDer<'static>> {
     let common_name = mandate_eid.to_string();
     let params = {
         let mut params = CertificateParams::new(vec![common_name.to_string()])?;
@@ -155,7 +161,7 @@ pub fn mandate_identity_signing_request(
         params
     };
 
-    Ok(params.serialize_request(deps.get_instance().private_key())?)
+    deps.get_instance().serialize_request(&params).await
 }
 
 pub fn mandate_fulfill_submission_txn_statements<D: Db>(
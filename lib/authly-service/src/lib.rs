@@ -4,3 +4,4 @@ pub mod authority_mandate;
 pub mod openapi;
 pub mod proto;
 pub mod repo;
+pub mod scim;
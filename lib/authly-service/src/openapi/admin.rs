@@ -1,33 +1,52 @@
-use authly_common::document::Document;
+use authly_common::id::ServiceId;
 use authly_domain::{
     access_control,
+    api_error::ApiError,
     audit::Actor,
-    ctx::{ClusterBus, Directories, GetDb, GetDecryptedDeks, GetInstance, KubernetesConfig},
+    ctx::{
+        ClusterBus, Directories, GetBuiltins, GetClock, GetClockSkew, GetDb, GetDecryptedDeks,
+        GetIdGenerator, GetInstance, GetSettings, GetStats, KubernetesConfig, ServiceBus,
+    },
     directory,
-    document::{compiled_document::DocumentMeta, doc_compiler::compile_doc},
+    document::{
+        assertion::parse_document, compiled_document::DocumentMeta, diagnostic::diagnostics,
+        doc_compiler::compile_doc,
+    },
     extract::{auth::ApiAuth, base_uri::ProxiedBaseUri},
+    login::{self, LoginError},
+    scim::{self, ScimError},
+    stats,
+    token_signing::{self, RotationError},
 };
 use axum::{
     extract::State,
     response::{IntoResponse, Response},
+    Json,
 };
 use http::StatusCode;
-use tracing::warn;
+use serde::Deserialize;
+use serde_json::json;
 
 use crate::authority_mandate::submission;
 
-// FIXME: User-friendly document errors
 // TODO: Handle unchanged documents like in load.rs
 pub async fn post_document<Ctx>(
     State(ctx): State<Ctx>,
     auth: ApiAuth<access_control::role::ApplyDocument>,
     body: String,
-) -> Result<Response, Response>
+) -> Result<Response, ApiError>
 where
-    Ctx: GetDb + KubernetesConfig + GetDecryptedDeks + ClusterBus + Directories,
+    Ctx: GetDb
+        + GetSettings
+        + GetIdGenerator
+        + KubernetesConfig
+        + GetDecryptedDeks
+        + ClusterBus
+        + Directories
+        + GetClock,
 {
-    let doc = Document::from_toml(&body)
-        .map_err(|_| (StatusCode::UNPROCESSABLE_ENTITY, "invalid toml").into_response())?;
+    let (doc, extensions) =
+        parse_document(&body).map_err(|_| ApiError::invalid_request("invalid toml"))?;
 
     let meta = DocumentMeta {
         url: format!("admin://user/?entity_id={}", auth.claims.authly.entity_id),
@@ -37,30 +56,31 @@ where
             hasher.finalize().into()
         },
     };
-    let compiled_doc = compile_doc(&ctx, doc, meta)
+    let compiled_doc = compile_doc(&ctx, doc, extensions, meta)
         .await
-        .map_err(|_| (StatusCode::UNPROCESSABLE_ENTITY, "invalid document").into_response())?;
+        .map_err(|errors| {
+            ApiError::new(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "invalid_document",
+                "invalid document",
+            )
+            .with_details(json!({ "diagnostics": diagnostics(&body, &errors) }))
+        })?;
 
     directory::apply_document(&ctx, compiled_doc, Actor(auth.claims.authly.entity_id))
         .await
-        .map_err(|_| {
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "authority application error",
-            )
-                .into_response()
-        })?;
+        .map_err(|err| ApiError::internal("unable to apply document", err))?;
 
-    Ok((StatusCode::OK, "document applied").into_response())
+    Ok("document applied".into_response())
 }
 
 pub async fn post_authority_mandate_submission_token<Ctx>(
     State(ctx): State<Ctx>,
     auth: ApiAuth<access_control::role::GrantMandate>,
     proxied_base_uri: ProxiedBaseUri,
-) -> Result<Response, Response>
+) -> Result<Response, ApiError>
 where
-    Ctx: GetDb + GetInstance,
+    Ctx: GetDb + GetInstance + GetIdGenerator,
 {
     let token = submission::authority::authority_generate_submission_token(
         &ctx,
@@ -69,10 +89,116 @@ where
         None,
     )
     .await
-    .map_err(|err| {
-        warn!(?err, "unable to create submission token");
-        StatusCode::INTERNAL_SERVER_ERROR.into_response()
-    })?;
+    .map_err(|err| ApiError::internal("unable to create submission token", err))?;
 
     Ok(token.into_response())
 }
+
+/// Compact JSON statistics of the local node, for embedding in dashboards
+pub async fn get_stats<Ctx>(
+    State(ctx): State<Ctx>,
+    _auth: ApiAuth<access_control::role::Admin>,
+) -> Result<Response, ApiError>
+where
+    Ctx: GetDb + GetInstance + GetStats + GetClockSkew + ServiceBus,
+{
+    let stats = stats::collect_stats(&ctx)
+        .await
+        .map_err(|err| ApiError::internal("unable to collect stats", err))?;
+
+    Ok(Json(stats).into_response())
+}
+
+/// Rotate the access token signing key, independently of the CA
+pub async fn post_rotate_token_signing_key<Ctx>(
+    State(ctx): State<Ctx>,
+    _auth: ApiAuth<access_control::role::Admin>,
+) -> Result<Response, ApiError>
+where
+    Ctx: GetDb + GetDecryptedDeks + GetInstance + GetClock + ClusterBus,
+{
+    let kid = token_signing::rotate_token_signing_key(&ctx)
+        .await
+        .map_err(|err| match err {
+            RotationError::ExternalKey => {
+                ApiError::new(StatusCode::CONFLICT, "external_key", err.to_string())
+            }
+            err => ApiError::internal("unable to rotate token signing key", err),
+        })?;
+
+    Ok(Json(json!({ "kid": kid })).into_response())
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScimTokenRequest {
+    /// The service the SCIM client acts on behalf of
+    service_id: ServiceId,
+}
+
+/// Issue a bearer token for a SCIM provisioning client
+pub async fn post_scim_token<Ctx>(
+    State(ctx): State<Ctx>,
+    _auth: ApiAuth<access_control::role::Admin>,
+    Json(request): Json<ScimTokenRequest>,
+) -> Result<Response, ApiError>
+where
+    Ctx: GetDb + GetSettings + GetClock,
+{
+    let token = scim::issue_scim_token(&ctx, request.service_id)
+        .await
+        .map_err(|err| match err {
+            ScimError::InvalidValue(msg) => ApiError::invalid_request(msg),
+            err => ApiError::internal("unable to issue scim token", err),
+        })?;
+
+    Ok(Json(json!({ "token": token })).into_response())
+}
+
+/// Revoke all the bearer tokens of the SCIM provisioning clients of a service
+pub async fn post_revoke_scim_tokens<Ctx>(
+    State(ctx): State<Ctx>,
+    _auth: ApiAuth<access_control::role::Admin>,
+    Json(request): Json<ScimTokenRequest>,
+) -> Result<Response, ApiError>
+where
+    Ctx: GetDb + GetClock,
+{
+    let revoked = scim::revoke_scim_tokens(&ctx, request.service_id)
+        .await
+        .map_err(|err| ApiError::internal("unable to revoke scim tokens", err))?;
+
+    Ok(Json(json!({ "revoked": revoked })).into_response())
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SimulateLoginRequest {
+    username: String,
+    /// The service requesting an access token after the login
+    service_id: ServiceId,
+}
+
+/// Show what logging in as a user would result in, without their password and without issuing a session
+pub async fn post_simulate_login<Ctx>(
+    State(ctx): State<Ctx>,
+    auth: ApiAuth<access_control::role::Admin>,
+    Json(request): Json<SimulateLoginRequest>,
+) -> Result<Response, ApiError>
+where
+    Ctx: GetDb + GetBuiltins + GetDecryptedDeks + GetSettings + GetClock + ClusterBus,
+{
+    let simulated = login::simulate_login(
+        &ctx,
+        Actor(auth.claims.authly.entity_id),
+        &request.username,
+        request.service_id,
+    )
+    .await
+    .map_err(|err| match err {
+        LoginError::Db(err) => ApiError::internal("unable to simulate login", err),
+        _ => ApiError::new(StatusCode::NOT_FOUND, "not_found", "no such user"),
+    })?;
+
+    Ok(Json(simulated).into_response())
+}
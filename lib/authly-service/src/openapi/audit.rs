@@ -0,0 +1,46 @@
+use authly_common::id::ServiceId;
+use authly_domain::{
+    access_control, api_error::ApiError, audit::AccessControlAuditEntry, ctx::GetDb,
+    extract::auth::ApiAuth, repo::access_control_audit_repo::DbAccessControlAudit,
+};
+use axum::{
+    extract::{Query, State},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Deserialize;
+
+const DEFAULT_LIMIT: usize = 100;
+
+#[derive(Deserialize)]
+pub struct AccessControlAuditQuery {
+    /// Only list decisions made for this service
+    service: Option<ServiceId>,
+    limit: Option<usize>,
+}
+
+/// List recorded access control decisions, newest first
+pub async fn get_access_control_audit<Ctx>(
+    State(ctx): State<Ctx>,
+    _auth: ApiAuth<access_control::role::Admin>,
+    Query(query): Query<AccessControlAuditQuery>,
+) -> Result<Response, ApiError>
+where
+    Ctx: GetDb,
+{
+    let records = DbAccessControlAudit::query_recent(
+        ctx.get_db(),
+        query.service,
+        query.limit.unwrap_or(DEFAULT_LIMIT),
+    )
+    .await
+    .map_err(|err| ApiError::internal("unable to list access control audit", err))?;
+
+    Ok(Json(
+        records
+            .into_iter()
+            .map(AccessControlAuditEntry::from)
+            .collect::<Vec<_>>(),
+    )
+    .into_response())
+}
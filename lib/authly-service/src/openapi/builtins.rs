@@ -0,0 +1,7 @@
+use authly_domain::id::BuiltinCatalog;
+use axum::Json;
+
+/// The builtin properties and attributes of Authly, with their IDs and labels
+pub async fn get_builtins() -> Json<BuiltinCatalog> {
+    Json(BuiltinCatalog::new())
+}
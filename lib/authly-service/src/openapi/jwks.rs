@@ -0,0 +1,10 @@
+use authly_domain::{ctx::GetInstance, token_signing::JwkSet};
+use axum::{extract::State, Json};
+
+/// The public keys for verifying access tokens, as a JSON Web Key Set
+pub async fn get_jwks<Ctx>(State(ctx): State<Ctx>) -> Json<JwkSet>
+where
+    Ctx: GetInstance,
+{
+    Json(ctx.get_instance().jwks())
+}
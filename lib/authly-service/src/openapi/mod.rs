@@ -5,4 +5,7 @@
 pub mod router;
 
 mod admin;
+mod audit;
+mod builtins;
+mod jwks;
 mod user_auth;
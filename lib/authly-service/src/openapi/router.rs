@@ -1,9 +1,14 @@
 use authly_domain::ctx::{
-    ClusterBus, Directories, GetBuiltins, GetDb, GetDecryptedDeks, GetInstance, KubernetesConfig,
+    ClusterBus, Directories, GetBuiltins, GetClock, GetClockSkew, GetDb, GetDecryptedDeks,
+    GetIdGenerator, GetInstance, GetSessionCache, GetSettings, GetStats, KubernetesConfig,
+    ServiceBus,
+};
+use axum::{
+    routing::{get, post},
+    Router,
 };
-use axum::{routing::post, Router};
 
-use super::{admin, user_auth};
+use super::{admin, audit, builtins, jwks, user_auth};
 
 pub fn router<Ctx>() -> Router<Ctx>
 where
@@ -11,9 +16,16 @@ where
         + GetInstance
         + GetBuiltins
         + GetDecryptedDeks
+        + GetIdGenerator
         + Directories
         + ClusterBus
         + KubernetesConfig
+        + GetSettings
+        + GetSessionCache
+        + GetStats
+        + GetClockSkew
+        + GetClock
+        + ServiceBus
         + Clone
         + Send
         + Sync
@@ -24,9 +36,30 @@ where
             "/api/auth/authenticate",
             post(user_auth::authenticate::<Ctx>),
         )
+        .route("/api/auth/whoami", get(user_auth::get_whoami::<Ctx>))
         .route("/api/admin/document", post(admin::post_document::<Ctx>))
         .route(
             "/api/admin/mandate/submission_token",
             post(admin::post_authority_mandate_submission_token::<Ctx>),
         )
+        .route("/api/admin/stats", get(admin::get_stats::<Ctx>))
+        .route(
+            "/api/admin/token_signing_key/rotate",
+            post(admin::post_rotate_token_signing_key::<Ctx>),
+        )
+        .route("/api/admin/scim/token", post(admin::post_scim_token::<Ctx>))
+        .route(
+            "/api/admin/scim/token/revoke",
+            post(admin::post_revoke_scim_tokens::<Ctx>),
+        )
+        .route(
+            "/api/admin/simulate_login",
+            post(admin::post_simulate_login::<Ctx>),
+        )
+        .route(
+            "/api/admin/audit/access_control",
+            get(audit::get_access_control_audit::<Ctx>),
+        )
+        .route("/api/builtins", get(builtins::get_builtins))
+        .route("/.well-known/jwks.json", get(jwks::get_jwks::<Ctx>))
 }
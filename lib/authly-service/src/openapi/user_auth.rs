@@ -1,12 +1,18 @@
-use authly_common::{id::PersonaId, mtls_server::PeerServiceEntity};
+use authly_common::{
+    id::{AttrId, EntityId, PersonaId},
+    mtls_server::PeerServiceEntity,
+};
 use authly_domain::{
-    ctx::{GetBuiltins, GetDb, GetDecryptedDeks},
+    api_error::ApiError,
+    ctx::{GetBuiltins, GetClock, GetDb, GetDecryptedDeks, GetSessionCache, GetSettings, GetStats},
+    id::BuiltinProp,
     login::{try_username_password_login, LoginError},
+    repo::{crypto_repo, entity_repo},
+    session::{authenticate_session_cookie, AuthClass, SessionKind, SESSION_COOKIE_NAME},
 };
-use axum::{extract::State, http::StatusCode, response::IntoResponse, Extension, Json};
+use axum::{extract::State, response::IntoResponse, Extension, Json};
 use axum_extra::extract::CookieJar;
 use serde::{Deserialize, Serialize};
-use tracing::warn;
 
 pub struct AuthError(LoginError);
 
@@ -19,13 +25,13 @@ impl From<LoginError> for AuthError {
 impl IntoResponse for AuthError {
     fn into_response(self) -> axum::response::Response {
         match self.0 {
-            LoginError::UnprivilegedService => StatusCode::FORBIDDEN.into_response(),
-            LoginError::Credentials => StatusCode::UNAUTHORIZED.into_response(),
-            LoginError::Db(err) => {
-                warn!(?err, "auth db error");
-                StatusCode::UNAUTHORIZED.into_response()
+            LoginError::UnprivilegedService => {
+                ApiError::forbidden("the service may not authenticate users")
             }
+            LoginError::Credentials => ApiError::unauthorized("invalid credentials"),
+            LoginError::Db(err) => ApiError::internal("auth db error", err),
         }
+        .into_response()
     }
 }
 
@@ -57,7 +63,7 @@ pub async fn authenticate<Ctx>(
     Json(body): Json<AuthenticateRequest>,
 ) -> Result<axum::response::Response, AuthError>
 where
-    Ctx: GetDb + GetBuiltins + GetDecryptedDeks,
+    Ctx: GetDb + GetBuiltins + GetDecryptedDeks + GetSettings + GetStats + GetClock,
 {
     // BUG: figure this out:
     let _mfa_needed = false;
@@ -71,7 +77,7 @@ where
     };
 
     Ok((
-        CookieJar::new().add(session.to_cookie()),
+        CookieJar::new().add(session.to_cookie(ctx.get_clock().now())),
         Json(AuthenticateResponse {
             token: session.token.0,
             entity_id: persona_id,
@@ -86,3 +92,84 @@ where
     )
         .into_response())
 }
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WhoamiResponse {
+    entity_id: EntityId,
+    username: Option<String>,
+    email: Option<String>,
+    entity_attributes: Vec<AttrId>,
+    session: WhoamiSession,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WhoamiSession {
+    handle: String,
+    kind: &'static str,
+    auth_class: &'static str,
+    #[serde(with = "time::serde::rfc3339")]
+    created_at: time::OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339")]
+    authenticated_at: time::OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339")]
+    expires: time::OffsetDateTime,
+}
+
+/// The profile of the entity authenticated by the session cookie, for front-ends of the current user
+pub async fn get_whoami<Ctx>(
+    State(ctx): State<Ctx>,
+    jar: CookieJar,
+) -> Result<Json<WhoamiResponse>, ApiError>
+where
+    Ctx: GetDb + GetDecryptedDeks + GetSettings + GetSessionCache + GetClock,
+{
+    let session_cookie = jar
+        .get(SESSION_COOKIE_NAME)
+        .ok_or_else(|| ApiError::unauthorized("no session cookie"))?;
+    let session = authenticate_session_cookie(&ctx, session_cookie)
+        .await
+        .map_err(ApiError::unauthorized)?;
+
+    let deks = ctx.load_decrypted_deks();
+    let load_ident = |prop: BuiltinProp| {
+        crypto_repo::load_decrypt_obj_ident(ctx.get_db(), session.eid.upcast(), prop.into(), &deks)
+    };
+    let username = load_ident(BuiltinProp::Username)
+        .await
+        .map_err(|err| ApiError::internal("whoami identity", err))?;
+    let email = load_ident(BuiltinProp::Email)
+        .await
+        .map_err(|err| ApiError::internal("whoami identity", err))?;
+
+    let mut entity_attributes: Vec<AttrId> =
+        entity_repo::list_resolved_entity_attrs(ctx.get_db(), session.eid)
+            .await
+            .map_err(|err| ApiError::internal("whoami attributes", err))?
+            .into_iter()
+            .collect();
+    entity_attributes.sort();
+
+    Ok(Json(WhoamiResponse {
+        entity_id: session.eid,
+        username,
+        email,
+        entity_attributes,
+        session: WhoamiSession {
+            handle: session.handle(),
+            kind: match session.kind {
+                SessionKind::Default => "default",
+                SessionKind::Persistent => "persistent",
+            },
+            auth_class: match session.auth_class {
+                AuthClass::Password => "password",
+                AuthClass::WebAuthn => "webauthn",
+                AuthClass::Federated => "federated",
+            },
+            created_at: session.created_at,
+            authenticated_at: session.authenticated_at,
+            expires: session.expires_at,
+        },
+    }))
+}
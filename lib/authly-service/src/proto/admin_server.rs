@@ -0,0 +1,189 @@
+//! gRPC services for admin tooling.
+
+use std::future::Future;
+
+use authly_common::mtls_server::PeerServiceEntity;
+use authly_db::DbResult;
+use authly_domain::{
+    bus::{
+        admin_events::{AdminEvent, AdminEventSubscription},
+        entity_events::{self, AttributeChange, EntityAttributeEvent, EntityAttributeSubscription},
+    },
+    ctx::{EntityEventBus, GetClock, GetDb},
+    id::BuiltinAttr,
+};
+use futures_util::{stream::BoxStream, StreamExt};
+use tonic::{Request, Response};
+
+use crate::proto::{
+    grpc_db_err,
+    peer_auth::{MethodRoles, PeerAuth},
+};
+
+pub mod proto {
+    tonic::include_proto!("authly_admin");
+}
+
+use proto::authly_admin_server::{AuthlyAdmin, AuthlyAdminServer};
+
+const BATCH_LIMIT: usize = 100;
+
+/// The roles required for calling each method of the service, enforced by [PeerAuth]
+pub const METHODS: &[MethodRoles] = &[
+    MethodRoles {
+        method: "TailEvents",
+        roles: &[BuiltinAttr::AuthlyRoleAdmin],
+        checked_by_handler: false,
+    },
+    MethodRoles {
+        method: "TailEntityAttributeEvents",
+        roles: &[BuiltinAttr::AuthlyRoleAdmin],
+        checked_by_handler: false,
+    },
+];
+
+pub struct AuthlyAdminServerImpl<Ctx> {
+    ctx: Ctx,
+}
+
+impl<Ctx> AuthlyAdminServerImpl<Ctx> {
+    pub fn new_service(ctx: Ctx) -> PeerAuth<AuthlyAdminServer<Self>, Ctx>
+    where
+        Ctx: Clone,
+    {
+        PeerAuth::new(
+            AuthlyAdminServer::new(Self { ctx: ctx.clone() }),
+            ctx,
+            METHODS,
+        )
+    }
+}
+
+impl From<AdminEvent> for proto::AdminEvent {
+    fn from(event: AdminEvent) -> Self {
+        Self {
+            seq: event.seq,
+            kind: event.kind,
+            detail_json: event.detail.to_string(),
+            created_at: event.created_at.unix_timestamp(),
+        }
+    }
+}
+
+impl From<EntityAttributeEvent> for proto::EntityAttributeEvent {
+    fn from(event: EntityAttributeEvent) -> Self {
+        Self {
+            seq: event.seq,
+            entity_id: event.eid.to_string(),
+            attribute_id: event.attr_id.to_string(),
+            added: event.change == AttributeChange::Added,
+            created_at: event.created_at.unix_timestamp(),
+        }
+    }
+}
+
+/// An outbox subscription, woken up by the cluster message bus when events are written
+trait Subscription: Send + 'static {
+    type Event;
+
+    fn next_batch(
+        &mut self,
+        deps: &(impl GetDb + Sync),
+        limit: usize,
+    ) -> impl Future<Output = DbResult<Vec<Self::Event>>> + Send;
+}
+
+impl Subscription for AdminEventSubscription {
+    type Event = AdminEvent;
+
+    fn next_batch(
+        &mut self,
+        deps: &(impl GetDb + Sync),
+        limit: usize,
+    ) -> impl Future<Output = DbResult<Vec<AdminEvent>>> + Send {
+        AdminEventSubscription::next_batch(self, deps, limit)
+    }
+}
+
+impl Subscription for EntityAttributeSubscription {
+    type Event = EntityAttributeEvent;
+
+    fn next_batch(
+        &mut self,
+        deps: &(impl GetDb + Sync),
+        limit: usize,
+    ) -> impl Future<Output = DbResult<Vec<EntityAttributeEvent>>> + Send {
+        EntityAttributeSubscription::next_batch(self, deps, limit)
+    }
+}
+
+/// Stream the events of a subscription as they are written, until the client goes away
+fn tail<Ctx, S, T>(ctx: Ctx, subscription: S) -> BoxStream<'static, tonic::Result<T>>
+where
+    Ctx: GetDb + Send + Sync + 'static,
+    S: Subscription,
+    S::Event: Into<T> + Send,
+    T: Send + 'static,
+{
+    futures_util::stream::unfold(Some((ctx, subscription)), |state| async move {
+        let (ctx, mut subscription) = state?;
+        match subscription.next_batch(&ctx, BATCH_LIMIT).await {
+            // the notifier is gone, the server is shutting down
+            Ok(events) if events.is_empty() => None,
+            Ok(events) => Some((Ok(events), Some((ctx, subscription)))),
+            Err(err) => Some((Err(grpc_db_err(err)), None)),
+        }
+    })
+    .flat_map(|batch| {
+        let items: Vec<_> = match batch {
+            Ok(events) => events.into_iter().map(|event| Ok(event.into())).collect(),
+            Err(status) => vec![Err(status)],
+        };
+        futures_util::stream::iter(items)
+    })
+    .boxed()
+}
+
+#[tonic::async_trait]
+impl<Ctx> AuthlyAdmin for AuthlyAdminServerImpl<Ctx>
+where
+    Ctx: GetDb + GetClock + EntityEventBus + Clone + Send + Sync + 'static,
+{
+    type TailEventsStream = BoxStream<'static, tonic::Result<proto::AdminEvent>>;
+    type TailEntityAttributeEventsStream =
+        BoxStream<'static, tonic::Result<proto::EntityAttributeEvent>>;
+
+    /// Sends the events after the cursor, then new events as they are written, until the client goes away
+    async fn tail_events(
+        &self,
+        request: Request<proto::TailEventsRequest>,
+    ) -> tonic::Result<Response<Self::TailEventsStream>> {
+        let subscription = AdminEventSubscription::new(&self.ctx, request.into_inner().after);
+
+        Ok(Response::new(tail(self.ctx.clone(), subscription)))
+    }
+
+    /// Acknowledges the events up to the cursor, then streams like [Self::tail_events].
+    ///
+    /// Delivery is at-least-once: subscribers resume from the last cursor they processed.
+    async fn tail_entity_attribute_events(
+        &self,
+        request: Request<proto::TailEventsRequest>,
+    ) -> tonic::Result<Response<Self::TailEntityAttributeEventsStream>> {
+        let peer_svc_eid = request
+            .extensions()
+            .get::<PeerServiceEntity>()
+            .ok_or_else(|| tonic::Status::unauthenticated("invalid service identity"))?
+            .0;
+        let after = request.into_inner().after;
+
+        let subscription = EntityAttributeSubscription::new(&self.ctx, after);
+        if after > 0 {
+            entity_events::acknowledge_events(&self.ctx, peer_svc_eid, after)
+                .await
+                .map_err(grpc_db_err)?;
+        }
+
+        Ok(Response::new(tail(self.ctx.clone(), subscription)))
+    }
+}
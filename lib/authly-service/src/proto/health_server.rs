@@ -0,0 +1,128 @@
+//! The standard gRPC health checking service (`grpc.health.v1`).
+
+use std::time::Duration;
+
+use authly_common::proto::{connect::authly_connect_server, service::authly_service_server};
+use authly_domain::{
+    ctx::{GetClockSkew, GetDb, GetStats},
+    health::{self, Health as NodeHealth},
+};
+use futures_util::{stream::BoxStream, StreamExt};
+use tonic::{metadata::MetadataValue, Request, Response};
+use tonic_health::pb::{
+    health_check_response::ServingStatus,
+    health_server::{Health, HealthServer},
+    HealthCheckRequest, HealthCheckResponse,
+};
+
+/// Response metadata set by `Check` while the node is serving in read-only mode
+pub const READ_ONLY_METADATA: &str = "authly-read-only";
+
+/// How often the health is checked for watchers
+const WATCH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The services reporting health. The empty name is the health of the server as a whole.
+const SERVICES: &[&str] = &[
+    "",
+    authly_service_server::SERVICE_NAME,
+    authly_connect_server::SERVICE_NAME,
+];
+
+pub struct AuthlyHealthServerImpl<Ctx> {
+    ctx: Ctx,
+}
+
+impl<Ctx> AuthlyHealthServerImpl<Ctx> {
+    pub fn new_service(ctx: Ctx) -> HealthServer<Self> {
+        HealthServer::new(Self { ctx })
+    }
+}
+
+impl<Ctx> AuthlyHealthServerImpl<Ctx>
+where
+    Ctx: GetDb + GetStats + GetClockSkew,
+{
+    async fn check_health(&self, service: &str) -> tonic::Result<NodeHealth> {
+        if !SERVICES.contains(&service) {
+            return Err(tonic::Status::not_found(format!(
+                "unknown service: {service}"
+            )));
+        }
+
+        Ok(health::check_health(&self.ctx).await)
+    }
+
+    async fn serving_status(&self, service: &str) -> tonic::Result<ServingStatus> {
+        Ok(serving_status(&self.check_health(service).await?))
+    }
+}
+
+fn serving_status(health: &NodeHealth) -> ServingStatus {
+    if health.is_serving() {
+        ServingStatus::Serving
+    } else {
+        ServingStatus::NotServing
+    }
+}
+
+fn response(status: ServingStatus) -> HealthCheckResponse {
+    HealthCheckResponse {
+        status: status.into(),
+    }
+}
+
+#[tonic::async_trait]
+impl<Ctx> Health for AuthlyHealthServerImpl<Ctx>
+where
+    Ctx: GetDb + GetStats + GetClockSkew + Clone + Send + Sync + 'static,
+{
+    type WatchStream = BoxStream<'static, tonic::Result<HealthCheckResponse>>;
+
+    async fn check(
+        &self,
+        request: Request<HealthCheckRequest>,
+    ) -> tonic::Result<Response<HealthCheckResponse>> {
+        let health = self.check_health(&request.into_inner().service).await?;
+
+        let mut response = Response::new(response(serving_status(&health)));
+        if health.read_only {
+            response
+                .metadata_mut()
+                .insert(READ_ONLY_METADATA, MetadataValue::from_static("true"));
+        }
+
+        Ok(response)
+    }
+
+    /// Sends the current status, then every change of it until the client goes away
+    async fn watch(
+        &self,
+        request: Request<HealthCheckRequest>,
+    ) -> tonic::Result<Response<Self::WatchStream>> {
+        let service = request.into_inner().service;
+        let status = self.serving_status(&service).await?;
+        let server = Self {
+            ctx: self.ctx.clone(),
+        };
+
+        let changes = futures_util::stream::unfold((server, status), move |(server, mut last)| {
+            let service = service.clone();
+            async move {
+                loop {
+                    tokio::time::sleep(WATCH_INTERVAL).await;
+                    let status = server.serving_status(&service).await.ok()?;
+                    if status != last {
+                        last = status;
+                        return Some((Ok(response(status)), (server, last)));
+                    }
+                }
+            }
+        });
+
+        Ok(Response::new(
+            futures_util::stream::once(async move { Ok(response(status)) })
+                .chain(changes)
+                .boxed(),
+        ))
+    }
+}
@@ -1,10 +1,20 @@
 use authly_db::DbError;
 use tracing::warn;
 
+pub mod admin_server;
+pub mod health_server;
 pub mod mandate_submission;
+pub mod peer_auth;
+pub mod policy_server;
+pub mod protocol;
 pub mod service_server;
+pub mod token_server;
 
 fn grpc_db_err(err: DbError) -> tonic::Status {
+    if let DbError::ReadOnly = err {
+        return tonic::Status::unavailable("read-only mode, the cluster has lost quorum");
+    }
+
     warn!(?err, "gRPC DbError");
     tonic::Status::internal("internal error")
 }
@@ -0,0 +1,154 @@
+//! Baseline authentication, authorization and tracing of gRPC calls from services.
+
+use std::task::{Context, Poll};
+
+use authly_common::{id::AttrId, mtls_server::PeerServiceEntity};
+use authly_domain::{
+    access_control::{self, SvcAccessControlError},
+    ctx::GetDb,
+    id::BuiltinAttr,
+    remote_addr::RemoteAddr,
+    request_id::RequestId,
+};
+use fnv::FnvHashSet;
+use tonic::{
+    codegen::{BoxFuture, Service},
+    server::NamedService,
+};
+use tracing::{info_span, Instrument};
+
+use crate::proto::grpc_db_err;
+
+/// The builtin roles a service must have to call a gRPC method
+pub struct MethodRoles {
+    /// The method name, as in the request path `/<service>/<method>`
+    pub method: &'static str,
+    pub roles: &'static [BuiltinAttr],
+    /// The roles are checked by the method handler instead, which may do so concurrently with other work
+    pub checked_by_handler: bool,
+}
+
+impl MethodRoles {
+    pub fn is_allowed(&self, attributes: &FnvHashSet<AttrId>) -> bool {
+        self.roles
+            .iter()
+            .all(|role| attributes.contains(&AttrId::from(*role)))
+    }
+}
+
+/// Wraps a gRPC service, rejecting calls that do not come from an authenticated service.
+///
+/// Calls to a method listed in the method table are also rejected unless the service has the listed roles.
+/// Calls to methods that are not listed are rejected, so a new method is unavailable until it's listed.
+///
+/// Each call runs in a span with the peer service, its remote address and a request ID.
+#[derive(Clone)]
+pub struct PeerAuth<S, Ctx> {
+    inner: S,
+    ctx: Ctx,
+    methods: &'static [MethodRoles],
+}
+
+impl<S, Ctx> PeerAuth<S, Ctx> {
+    pub fn new(inner: S, ctx: Ctx, methods: &'static [MethodRoles]) -> Self {
+        Self {
+            inner,
+            ctx,
+            methods,
+        }
+    }
+}
+
+impl<S, Ctx, ReqBody, ResBody> Service<http::Request<ReqBody>> for PeerAuth<S, Ctx>
+where
+    S: Service<http::Request<ReqBody>, Response = http::Response<ResBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    S::Error: Send + 'static,
+    Ctx: GetDb + Clone + Send + Sync + 'static,
+    ReqBody: Send + 'static,
+    ResBody: Default + Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: http::Request<ReqBody>) -> Self::Future {
+        let Some(PeerServiceEntity(peer_svc_eid)) = req.extensions().get().cloned() else {
+            let response = tonic::Status::unauthenticated("invalid service identity").into_http();
+            return Box::pin(async { Ok(response) });
+        };
+        let remote_addr = req
+            .extensions()
+            .get::<RemoteAddr>()
+            .map(|remote_addr| remote_addr.0);
+        let request_id = RequestId::from_headers(req.headers());
+
+        let path = req.uri().path();
+        let Some(required_roles) = path
+            .rsplit_once('/')
+            .and_then(|(_, method)| self.methods.iter().find(|m| m.method == method))
+            .map(|method| {
+                if !method.checked_by_handler {
+                    method.roles
+                } else {
+                    &[]
+                }
+            })
+        else {
+            let response =
+                tonic::Status::permission_denied("the method is not available to services")
+                    .into_http();
+            return Box::pin(async { Ok(response) });
+        };
+
+        let span = info_span!(
+            "grpc",
+            method = path,
+            peer = %peer_svc_eid,
+            remote_addr = ?remote_addr,
+            request_id = request_id.0,
+        );
+
+        // the service that was polled ready is the one that must be called
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let ctx = self.ctx.clone();
+
+        Box::pin(
+            request_id
+                .scope(async move {
+                    if !required_roles.is_empty() {
+                        match access_control::authorize_peer_service(
+                            &ctx,
+                            peer_svc_eid,
+                            required_roles,
+                        )
+                        .await
+                        {
+                            Ok(_) => {}
+                            Err(SvcAccessControlError::Denied) => {
+                                return Ok(tonic::Status::permission_denied(
+                                    "the service does not have the required role",
+                                )
+                                .into_http());
+                            }
+                            Err(SvcAccessControlError::Db(err)) => {
+                                return Ok(grpc_db_err(err).into_http());
+                            }
+                        }
+                    }
+
+                    inner.call(req).await
+                })
+                .instrument(span),
+        )
+    }
+}
+
+impl<S: NamedService, Ctx> NamedService for PeerAuth<S, Ctx> {
+    const NAME: &'static str = S::NAME;
+}
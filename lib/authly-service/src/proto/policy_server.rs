@@ -0,0 +1,106 @@
+//! gRPC services for services that make local policy decisions.
+
+use authly_common::{mtls_server::PeerServiceEntity, policy::code::PolicyValue};
+use authly_domain::{
+    ctx::GetDb,
+    repo::{policy_repo, Identified},
+};
+use tonic::{Request, Response};
+
+use crate::proto::{
+    grpc_db_err,
+    peer_auth::{MethodRoles, PeerAuth},
+};
+
+pub mod proto {
+    tonic::include_proto!("authly_policy");
+}
+
+use proto::authly_policy_server::{AuthlyPolicy, AuthlyPolicyServer};
+
+/// The roles required for calling each method of the service, enforced by [PeerAuth]
+pub const METHODS: &[MethodRoles] = &[MethodRoles {
+    method: "GetPolicyBindings",
+    roles: &[],
+    checked_by_handler: false,
+}];
+
+pub struct AuthlyPolicyServerImpl<Ctx> {
+    ctx: Ctx,
+}
+
+impl<Ctx> AuthlyPolicyServerImpl<Ctx> {
+    pub fn new_service(ctx: Ctx) -> PeerAuth<AuthlyPolicyServer<Self>, Ctx>
+    where
+        Ctx: Clone,
+    {
+        PeerAuth::new(
+            AuthlyPolicyServer::new(Self { ctx: ctx.clone() }),
+            ctx,
+            METHODS,
+        )
+    }
+}
+
+#[tonic::async_trait]
+impl<Ctx> AuthlyPolicy for AuthlyPolicyServerImpl<Ctx>
+where
+    Ctx: GetDb + Send + Sync + 'static,
+{
+    /// The service is identified by its client certificate, and only ever gets its own bindings
+    async fn get_policy_bindings(
+        &self,
+        request: Request<proto::GetPolicyBindingsRequest>,
+    ) -> tonic::Result<Response<proto::PolicyBindings>> {
+        let peer_svc_eid = request
+            .extensions()
+            .get::<PeerServiceEntity>()
+            .ok_or_else(|| tonic::Status::unauthenticated("invalid service identity"))?
+            .0;
+        let request = request.into_inner();
+
+        let policy_data =
+            policy_repo::load_svc_policies_with_bindings(self.ctx.get_db(), peer_svc_eid)
+                .await
+                .map_err(grpc_db_err)?;
+        let mut labels = policy_repo::list_policy_labels(
+            self.ctx.get_db(),
+            policy_data.policies.iter().map(|policy| *policy.id()),
+        )
+        .await
+        .map_err(grpc_db_err)?;
+
+        Ok(Response::new(proto::PolicyBindings {
+            bindings: policy_data
+                .bindings
+                .into_iter()
+                .map(|binding| proto::PolicyBinding {
+                    attr_matcher: binding
+                        .attr_matcher
+                        .into_iter()
+                        .map(|attr_id| attr_id.to_string())
+                        .collect(),
+                    policy_ids: binding
+                        .policies
+                        .into_iter()
+                        .map(|policy_id| policy_id.to_string())
+                        .collect(),
+                })
+                .collect(),
+            policies: policy_data
+                .policies
+                .into_iter()
+                .map(|Identified(id, policy_pc)| proto::BoundPolicy {
+                    id: id.to_string(),
+                    label: labels.remove(&id).unwrap_or_default(),
+                    class: match policy_pc.class {
+                        PolicyValue::Allow => "allow",
+                        _ => "deny",
+                    }
+                    .to_string(),
+                    bytecode: request.bytecode.then(|| policy_pc.to_bytecode()),
+                })
+                .collect(),
+        }))
+    }
+}
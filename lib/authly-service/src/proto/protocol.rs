@@ -0,0 +1,118 @@
+//! Protocol version and capabilities of the service API.
+//!
+//! The messages of the service API are shared with clients through authly-common and can't express what a server supports.
+//! Instead, the server announces its protocol version and optional capabilities in the response metadata of `get_metadata`.
+//! A client reads them with [ServerProtocol::from_metadata] and uses the basic form of a request when a capability is missing.
+//!
+//! Servers from before protocol versioning announce nothing, which reads as version 0 without capabilities.
+
+use std::collections::BTreeSet;
+
+use tonic::metadata::{MetadataMap, MetadataValue};
+
+/// Response metadata of `get_metadata`, with the protocol version of the server
+pub const PROTOCOL_VERSION: &str = "authly-protocol-version";
+
+/// Response metadata of `get_metadata`, with the comma separated [Capability] names of the server
+pub const CAPABILITIES: &str = "authly-capabilities";
+
+/// The protocol version implemented by this server.
+///
+/// Incremented when the meaning of an existing request or response changes,
+/// new optional features are announced as a [Capability] instead.
+pub const CURRENT_PROTOCOL_VERSION: u32 = 1;
+
+/// An optional feature of the service API
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Capability {
+    /// `get_configuration` and `get_resource_property_mappings` honour `if-none-match`
+    ConditionalConfiguration,
+    /// `access_control` accepts optional resource attributes in request metadata
+    OptionalResourceAttributes,
+    /// `access_control` explains its decisions when asked to
+    AccessControlDetails,
+    /// `get_metadata` lists the methods the service may call
+    AllowedMethods,
+}
+
+impl Capability {
+    pub const ALL: &[Self] = &[
+        Self::ConditionalConfiguration,
+        Self::OptionalResourceAttributes,
+        Self::AccessControlDetails,
+        Self::AllowedMethods,
+    ];
+
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::ConditionalConfiguration => "conditional-configuration",
+            Self::OptionalResourceAttributes => "optional-resource-attributes",
+            Self::AccessControlDetails => "access-control-details",
+            Self::AllowedMethods => "allowed-methods",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|capability| capability.name() == name)
+    }
+}
+
+/// The protocol of a server, as announced in its `get_metadata` response
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ServerProtocol {
+    pub version: u32,
+    pub capabilities: BTreeSet<Capability>,
+}
+
+impl ServerProtocol {
+    /// The protocol of this server
+    pub fn current() -> Self {
+        Self {
+            version: CURRENT_PROTOCOL_VERSION,
+            capabilities: Capability::ALL.iter().copied().collect(),
+        }
+    }
+
+    /// Read the protocol from response metadata.
+    ///
+    /// Capabilities unknown to the client, announced by a newer server, are ignored.
+    pub fn from_metadata(metadata: &MetadataMap) -> Self {
+        let version = metadata
+            .get(PROTOCOL_VERSION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(0);
+
+        let capabilities = metadata
+            .get(CAPABILITIES)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.split(',').filter_map(Capability::from_name).collect())
+            .unwrap_or_default();
+
+        Self {
+            version,
+            capabilities,
+        }
+    }
+
+    pub fn supports(&self, capability: Capability) -> bool {
+        self.capabilities.contains(&capability)
+    }
+
+    pub(crate) fn insert_into(&self, metadata: &mut MetadataMap) {
+        metadata.insert(PROTOCOL_VERSION, MetadataValue::from(self.version));
+
+        let capabilities = self
+            .capabilities
+            .iter()
+            .map(|capability| capability.name())
+            .collect::<Vec<_>>()
+            .join(",");
+        if let Ok(value) = MetadataValue::try_from(capabilities) {
+            metadata.insert(CAPABILITIES, value);
+        }
+    }
+}
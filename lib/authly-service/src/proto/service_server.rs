@@ -1,27 +1,33 @@
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 
 use authly_common::{
     access_token::AuthlyAccessTokenClaims,
-    id::{Id128DynamicArrayConv, ServiceId},
+    id::{AttrId, Id128DynamicArrayConv, ServiceId},
     mtls_server::PeerServiceEntity,
-    policy::{
-        code::PolicyValue,
-        engine::{AccessControlParams, NoOpPolicyTracer},
-    },
+    policy::{code::PolicyValue, engine::AccessControlParams},
     proto::service::{
         self as proto,
         authly_service_server::{AuthlyService, AuthlyServiceServer},
     },
+    service::NamespacePropertyMapping,
 };
 use authly_domain::{
-    access_control::{self, AuthorizedPeerService},
-    access_token,
-    bus::{ServiceMessage, ServiceMessageConnection},
-    ctx::{GetBuiltins, GetDb, GetInstance, HostsConfig, ServiceBus},
+    access_control::{self, AuthorizedPeerService, ResourceAttrError, SvcAccessControlError},
+    access_token::{self, AccessTokenError},
+    bus::{
+        admin_events::{record_admin_event, AdminEventKind},
+        ServiceMessage, ServiceMessageConnection,
+    },
+    ctx::{
+        ClusterBus, GetAuditQueue, GetBuiltins, GetClock, GetDb, GetHttpClient, GetInstance,
+        GetMetadataCache, GetPolicyEngineCache, GetSessionCache, GetSettings, GetStats,
+        HostsConfig, ServiceBus,
+    },
     id::{BuiltinAttr, BuiltinProp},
-    remote_addr::RemoteAddr,
+    policy::{network, schedule},
+    remote_addr::{self, RemoteAddr},
     repo::{
-        entity_repo, policy_repo,
+        entity_repo,
         service_repo::{self, find_service_label_by_eid, PropertyKind},
     },
     service,
@@ -29,30 +35,133 @@ use authly_domain::{
 };
 use futures_util::{stream::BoxStream, StreamExt};
 use http::header::{AUTHORIZATION, COOKIE};
-use rcgen::{CertificateSigningRequestParams, DnType, Issuer, SanType};
+use rcgen::{CertificateSigningRequestParams, DnType};
 use rustls::pki_types::CertificateSigningRequestDer;
+use serde_json::json;
 use tonic::{
-    metadata::{Ascii, MetadataMap},
+    metadata::{Ascii, MetadataMap, MetadataValue},
     Request, Response,
 };
 use tracing::{info, warn};
 
-use crate::proto::grpc_db_err;
+use crate::proto::{
+    grpc_db_err,
+    peer_auth::{MethodRoles, PeerAuth},
+    protocol::ServerProtocol,
+};
+
+/// Request metadata asking for the reason behind an access control decision.
+///
+/// Requires the `authly:role:access_control_details` role.
+pub const ACCESS_CONTROL_DETAILS: &str = "authly-access-control-details";
+
+/// Response metadata with the [access_control::DecisionReason] code
+pub const DECISION_REASON: &str = "authly-decision-reason";
+
+/// Response metadata with a human readable explanation of the decision
+pub const DECISION_MESSAGE: &str = "authly-decision-message";
+
+/// Binary request metadata with an optional resource attribute, may be repeated.
+///
+/// Unlike the `resource_attributes` of the request, an attribute unknown to the service
+/// is ignored instead of failing the request.
+pub const OPTIONAL_RESOURCE_ATTRIBUTE: &str = "authly-optional-resource-attribute-bin";
+
+/// Request metadata with the [ETAG] of a previously received response
+pub const IF_NONE_MATCH: &str = "if-none-match";
+
+/// Response metadata identifying the content of a configuration response
+pub const ETAG: &str = "etag";
+
+/// Response metadata signalling that the content matches [IF_NONE_MATCH].
+///
+/// The response message is left empty, and the client should keep using its copy.
+pub const NOT_MODIFIED: &str = "authly-not-modified";
+
+/// Response metadata of `get_metadata`, with the comma separated names of the methods the service may call
+pub const ALLOWED_METHODS: &str = "authly-allowed-methods";
+
+/// The roles required for calling each method of the service, enforced by [PeerAuth]
+pub const METHODS: &[MethodRoles] = &[
+    MethodRoles {
+        method: "GetConfiguration",
+        roles: &[],
+        checked_by_handler: false,
+    },
+    MethodRoles {
+        method: "GetMetadata",
+        roles: &[],
+        checked_by_handler: false,
+    },
+    MethodRoles {
+        method: "GetAccessToken",
+        roles: &[BuiltinAttr::AuthlyRoleGetAccessToken],
+        checked_by_handler: true,
+    },
+    MethodRoles {
+        method: "GetResourcePropertyMappings",
+        roles: &[],
+        checked_by_handler: false,
+    },
+    MethodRoles {
+        method: "AccessControl",
+        roles: &[],
+        checked_by_handler: false,
+    },
+    MethodRoles {
+        method: "SignCertificate",
+        roles: &[BuiltinAttr::AuthlyRoleSignCertificate],
+        checked_by_handler: false,
+    },
+    MethodRoles {
+        method: "Messages",
+        roles: &[BuiltinAttr::AuthlyRoleReceiveMessages],
+        checked_by_handler: false,
+    },
+    MethodRoles {
+        method: "Pong",
+        roles: &[],
+        checked_by_handler: false,
+    },
+];
 
 pub struct AuthlyServiceServerImpl<Ctx> {
     ctx: Ctx,
 }
 
 impl<Ctx> AuthlyServiceServerImpl<Ctx> {
-    pub fn new_service(ctx: Ctx) -> AuthlyServiceServer<Self> {
-        AuthlyServiceServer::new(Self { ctx })
+    pub fn new_service(ctx: Ctx) -> PeerAuth<AuthlyServiceServer<Self>, Ctx>
+    where
+        Ctx: Clone,
+    {
+        PeerAuth::new(
+            AuthlyServiceServer::new(Self { ctx: ctx.clone() }),
+            ctx,
+            METHODS,
+        )
     }
 }
 
 #[tonic::async_trait]
 impl<Ctx> AuthlyService for AuthlyServiceServerImpl<Ctx>
 where
-    Ctx: GetDb + GetBuiltins + GetInstance + ServiceBus + HostsConfig + Send + Sync + 'static,
+    Ctx: GetDb
+        + GetBuiltins
+        + GetInstance
+        + GetSettings
+        + GetSessionCache
+        + GetMetadataCache
+        + GetPolicyEngineCache
+        + GetHttpClient
+        + GetClock
+        + GetAuditQueue
+        + GetStats
+        + ServiceBus
+        + ClusterBus
+        + HostsConfig
+        + Send
+        + Sync
+        + 'static,
 {
     type MessagesStream = BoxStream<'static, tonic::Result<proto::ServiceMessage>>;
 
@@ -61,45 +170,33 @@ where
         request: Request<proto::Empty>,
     ) -> tonic::Result<Response<proto::ServiceConfiguration>> {
         let peer_svc = svc_mtls_auth(&self.ctx, request.extensions(), &[]).await?;
+        let cache = self.ctx.get_metadata_cache();
+        if let Some(configuration) = cache.get_configuration(peer_svc.eid) {
+            return Ok(conditional_response(request.metadata(), configuration));
+        }
+        let generation = cache.generation();
 
         let hosts = service::get_service_hosts(&self.ctx, peer_svc.eid)
             .await
             .map_err(grpc_db_err)?;
 
-        let property_mapping_namespaces = {
-            let resource_property_mapping = service_repo::get_service_property_mapping(
+        let property_mapping_namespaces = property_mapping_to_proto(
+            service_repo::get_service_property_mapping(
                 self.ctx.get_db(),
                 peer_svc.eid,
                 PropertyKind::Resource,
             )
             .await
-            .map_err(grpc_db_err)?;
-
-            resource_property_mapping
-                .into_iter()
-                .map(|(label, properties)| proto::PropertyMappingNamespace {
-                    label,
-                    properties: properties
-                        .into_iter()
-                        .map(|(label, attributes)| proto::PropertyMapping {
-                            label,
-                            attributes: attributes
-                                .into_iter()
-                                .map(|(label, attr_id)| proto::AttributeMapping {
-                                    label,
-                                    obj_id: attr_id.to_array_dynamic().to_vec().into(),
-                                })
-                                .collect(),
-                        })
-                        .collect(),
-                })
-                .collect()
-        };
+            .map_err(grpc_db_err)?,
+        );
 
-        Ok(Response::new(proto::ServiceConfiguration {
+        let configuration = proto::ServiceConfiguration {
             hosts,
             property_mapping_namespaces,
-        }))
+        };
+        cache.insert_configuration(generation, peer_svc.eid, configuration.clone());
+
+        Ok(conditional_response(request.metadata(), configuration))
     }
 
     async fn get_metadata(
@@ -107,6 +204,12 @@ where
         request: Request<proto::Empty>,
     ) -> tonic::Result<Response<proto::ServiceMetadata>> {
         let peer_svc = svc_mtls_auth(&self.ctx, request.extensions(), &[]).await?;
+        let cache = self.ctx.get_metadata_cache();
+        if let Some(metadata) = cache.get_metadata(peer_svc.eid) {
+            return Ok(metadata_response(metadata, &peer_svc));
+        }
+        let generation = cache.generation();
+
         let label = find_service_label_by_eid(self.ctx.get_db(), peer_svc.eid)
             .await
             .map_err(grpc_db_err)?
@@ -120,11 +223,14 @@ where
         .await
         .map_err(grpc_db_err)?;
 
-        Ok(Response::new(proto::ServiceMetadata {
+        let metadata = proto::ServiceMetadata {
             entity_id: peer_svc.eid.to_array_dynamic().to_vec().into(),
             label,
             namespaces: metadata::namespaces_with_metadata_to_proto(namespaces),
-        }))
+        };
+        cache.insert_metadata(generation, peer_svc.eid, metadata.clone());
+
+        Ok(metadata_response(metadata, &peer_svc))
     }
 
     // TODO: This could use some local caching of both service auth and user auth?
@@ -135,41 +241,71 @@ where
     ) -> tonic::Result<Response<proto::AccessToken>> {
         // let start = Instant::now();
 
-        let (peer_svc_result, access_token_result) = tokio::join!(
-            svc_mtls_auth(
-                &self.ctx,
-                request.extensions(),
-                &[BuiltinAttr::AuthlyRoleGetAccessToken],
-            ),
+        let peer_svc_eid = svc_mtls_auth_trivial(request.extensions())?;
+
+        // the get_access_token role is checked here rather than by PeerAuth, concurrently with the session
+        let (peer_svc_result, token_attrs_result) = tokio::join!(
+            async {
+                access_control::authorize_peer_service(
+                    &self.ctx,
+                    peer_svc_eid,
+                    &[BuiltinAttr::AuthlyRoleGetAccessToken],
+                )
+                .await
+                .map_err(|err| match err {
+                    SvcAccessControlError::Denied => tonic::Status::permission_denied(
+                        "the service does not have the required role",
+                    ),
+                    SvcAccessControlError::Db(err) => grpc_db_err(err),
+                })
+            },
             async {
                 let session = session_auth(&self.ctx, request.metadata())
                     .await
                     .map_err(tonic::Status::unauthenticated)?;
 
-                let user_attrs = entity_repo::list_entity_attrs(self.ctx.get_db(), session.eid)
-                    .await
-                    .map_err(grpc_db_err)?;
-
-                let token = access_token::create_access_token(
-                    &session,
+                let user_attrs =
+                    entity_repo::list_resolved_entity_attrs(self.ctx.get_db(), session.eid)
+                        .await
+                        .map_err(grpc_db_err)?;
+                let token_attrs = access_token::select_token_attributes(
+                    &self.ctx,
+                    session.eid,
+                    peer_svc_eid,
                     user_attrs,
-                    &self.ctx.get_instance(),
                 )
-                .map_err(|_| tonic::Status::internal("access token error"))?;
+                .await
+                .map_err(grpc_db_err)?;
 
-                Result::<_, tonic::Status>::Ok(proto::AccessToken {
-                    token,
-                    entity_id: session.eid.to_array_dynamic().to_vec().into(),
-                })
+                Result::<_, tonic::Status>::Ok((session, token_attrs))
             },
         );
 
         // info!("get_access_token async took {:?}", start.elapsed());
 
         let _peer_svc = peer_svc_result?;
-        let access_token = access_token_result?;
+        let (session, token_attrs) = token_attrs_result?;
+
+        // the token is only issued to an authorized service, as referenced attributes are stored
+        let token = access_token::create_access_token_with(
+            &self.ctx,
+            &session,
+            token_attrs,
+            &self.ctx.get_instance(),
+            self.ctx.get_clock().now(),
+        )
+        .await
+        .map_err(|err| match err {
+            AccessTokenError::Db(err) => grpc_db_err(err),
+            _ => tonic::Status::internal("access token error"),
+        })?;
+
+        // info!("get_access_token took {:?}", start.elapsed());
 
-        Ok(Response::new(access_token))
+        Ok(Response::new(proto::AccessToken {
+            token,
+            entity_id: session.eid.to_array_dynamic().to_vec().into(),
+        }))
     }
 
     async fn get_resource_property_mappings(
@@ -187,45 +323,85 @@ where
         .map_err(grpc_db_err)?;
 
         let response = proto::PropertyMappingsResponse {
-            namespaces: resource_property_mapping
-                .into_iter()
-                .map(|(label, properties)| proto::PropertyMappingNamespace {
-                    label,
-                    properties: properties
-                        .into_iter()
-                        .map(|(label, attributes)| proto::PropertyMapping {
-                            label,
-                            attributes: attributes
-                                .into_iter()
-                                .map(|(label, attr_id)| proto::AttributeMapping {
-                                    label,
-                                    obj_id: attr_id.to_array_dynamic().to_vec().into(),
-                                })
-                                .collect(),
-                        })
-                        .collect(),
-                })
-                .collect(),
+            namespaces: property_mapping_to_proto(resource_property_mapping),
         };
 
-        Ok(Response::new(response))
+        Ok(conditional_response(request.metadata(), response))
     }
 
     async fn access_control(
         &self,
         request: Request<proto::AccessControlRequest>,
     ) -> tonic::Result<Response<proto::AccessControlResponse>> {
+        let request_time = self.ctx.get_clock().now();
         let peer_svc_eid = svc_mtls_auth_trivial(request.extensions())?;
-        let opt_user_claims = get_access_token_opt(&self.ctx, request.metadata())?;
+        let opt_user_claims = get_access_token_opt(&self.ctx, request.metadata()).await?;
+
+        let details = request.metadata().contains_key(ACCESS_CONTROL_DETAILS);
+        if details {
+            svc_mtls_auth(
+                &self.ctx,
+                request.extensions(),
+                &[BuiltinAttr::AuthlyRoleAccessControlDetails],
+            )
+            .await?;
+        }
+
+        access_control::check_attribute_limits(
+            self.ctx.get_settings().access_control_limits,
+            request.get_ref().peer_entity_attributes.len(),
+            request.get_ref().resource_attributes.len()
+                + request
+                    .metadata()
+                    .get_all_bin(OPTIONAL_RESOURCE_ATTRIBUTE)
+                    .iter()
+                    .count(),
+        )
+        .map_err(|err| tonic::Status::invalid_argument(err.to_string()))?;
+
+        let optional_resource_attrs = request
+            .metadata()
+            .get_all_bin(OPTIONAL_RESOURCE_ATTRIBUTE)
+            .iter()
+            .map(|value| {
+                let bytes = value
+                    .to_bytes()
+                    .map_err(|_| tonic::Status::invalid_argument("invalid ID"))?;
+                id_from_proto(&bytes)
+            })
+            .collect::<tonic::Result<Vec<AttrId>>>()?;
+
+        let subject_ip = subject_ip(&self.ctx, &request);
 
         let mut params = AccessControlParams::default();
 
         let request = request.into_inner();
 
         // resource attributes
-        for resource_attr in request.resource_attributes {
-            params.resource_attrs.insert(id_from_proto(&resource_attr)?);
-        }
+        let resource_attrs = request
+            .resource_attributes
+            .iter()
+            .map(|attr| id_from_proto(attr))
+            .collect::<tonic::Result<Vec<AttrId>>>()?;
+        params.resource_attrs = access_control::resolve_resource_attrs(
+            &self.ctx,
+            peer_svc_eid,
+            resource_attrs,
+            optional_resource_attrs,
+        )
+        .await
+        .map_err(|err| match err {
+            ResourceAttrError::Unknown(attr) => {
+                tonic::Status::invalid_argument(format!("unknown resource attribute: {attr}"))
+            }
+            ResourceAttrError::ForeignNamespace { attr, namespace } => {
+                warn!(?peer_svc_eid, ?attr, namespace, "foreign resource attribute");
+                tonic::Status::permission_denied(format!(
+                    "resource attribute {attr} belongs to namespace `{namespace}`, which is not available to the service"
+                ))
+            }
+            ResourceAttrError::Db(err) => grpc_db_err(err),
+        })?;
 
         // user attributes from access token
         if let Some(user_claims) = opt_user_claims {
@@ -244,47 +420,66 @@ where
         }
 
         // resolve attributes of all of the peers of the peer service
-        // TODO: improve concurrency?
-        for subject_entity_id in request.peer_entity_ids {
-            let subject_entity_id: ServiceId = id_from_proto(&subject_entity_id)?;
+        let peer_entity_ids = request
+            .peer_entity_ids
+            .iter()
+            .map(|eid| id_from_proto::<ServiceId>(eid))
+            .collect::<tonic::Result<Vec<_>>>()?;
+        params.subject_attrs.extend(
+            access_control::resolve_peer_entity_attrs(&self.ctx, peer_entity_ids)
+                .await
+                .map_err(grpc_db_err)?,
+        );
 
-            let subject_entity_property_mapping = service_repo::get_service_property_mapping(
-                self.ctx.get_db(),
-                subject_entity_id,
-                PropertyKind::Entity,
-            )
+        let external_decision = access_control::evaluate_external(&self.ctx, peer_svc_eid, &params)
             .await
             .map_err(grpc_db_err)?;
 
-            for (_, properties) in subject_entity_property_mapping {
-                for (_, attributes) in properties {
-                    for (_, attribute) in attributes {
-                        params.subject_attrs.insert(attribute);
-                    }
+        let decision = match external_decision {
+            Some(decision) => decision,
+            None => {
+                // schedule conditions are evaluated at the time of the request
+                schedule::set_request_time(
+                    &mut params,
+                    request_time,
+                    self.ctx.get_settings().policy_timezone,
+                );
+                if let Some(subject_ip) = subject_ip {
+                    network::set_subject_ip(&mut params, subject_ip);
                 }
-            }
-        }
 
-        // TODO: Should definitely cache service policy engine in memory
-        let policy_engine = policy_repo::load_svc_policy_engine(self.ctx.get_db(), peer_svc_eid)
+                access_control::evaluate_local(&self.ctx, peer_svc_eid, &params)
+                    .await
+                    .map_err(grpc_db_err)?
+            }
+        };
+        let decision = access_control::enforce_quotas(&self.ctx, &params, decision, request_time)
             .await
             .map_err(grpc_db_err)?;
-
-        let value = match policy_engine.eval(&params, &mut NoOpPolicyTracer) {
-            Ok(value) => {
-                if matches!(value, PolicyValue::Allow) {
-                    1
-                } else {
-                    0
-                }
-            }
-            Err(err) => {
-                warn!(?err, "policy engine error");
-                0
-            }
+        let decision =
+            access_control::audit_decision(&self.ctx, peer_svc_eid, params, decision, request_time);
+        let value = if matches!(decision.value, PolicyValue::Allow) {
+            1
+        } else {
+            0
         };
 
-        Ok(Response::new(proto::AccessControlResponse { value }))
+        let mut response = Response::new(proto::AccessControlResponse { value });
+
+        // the plain response stays minimal, details are only sent on request
+        if details {
+            let metadata = response.metadata_mut();
+            metadata.insert(
+                DECISION_REASON,
+                MetadataValue::from_static(decision.reason.code()),
+            );
+            metadata.insert(
+                DECISION_MESSAGE,
+                MetadataValue::from_static(decision.reason.message()),
+            );
+        }
+
+        Ok(response)
     }
 
     async fn sign_certificate(
@@ -293,7 +488,7 @@ where
     ) -> tonic::Result<Response<proto::Certificate>> {
         let peer_svc_eid = svc_mtls_auth_trivial(request.extensions())?;
 
-        let csr_params = CertificateSigningRequestParams::from_der(
+        let mut csr_params = CertificateSigningRequestParams::from_der(
             &CertificateSigningRequestDer::from(request.into_inner().der.as_ref()),
         )
         .map_err(|_err| tonic::Status::invalid_argument("invalid Certificate Signing Request"))?;
@@ -326,33 +521,42 @@ where
                 .await
                 .map_err(grpc_db_err)?;
 
-            for alt_name_san in &csr_params.params.subject_alt_names {
-                match alt_name_san {
-                    SanType::DnsName(ia5_string) => {
-                        if !valid_hosts.iter().any(|valid| valid == ia5_string.as_str()) {
-                            return Err(tonic::Status::invalid_argument(format!(
-                                "invalid alt name: {}",
-                                ia5_string.as_str()
-                            )));
-                        }
-                    }
-                    _ => return Err(tonic::Status::invalid_argument("invalid alt name")),
-                }
-            }
+            service::verify_cert_alt_names(
+                &csr_params.params.subject_alt_names,
+                &valid_hosts,
+                &self.ctx.get_settings().service_cert,
+            )
+            .map_err(|err| tonic::Status::invalid_argument(err.to_string()))?;
         }
 
-        let instance = self.ctx.get_instance();
+        service::limit_cert_validity(
+            &mut csr_params.params,
+            &self.ctx.get_settings().service_cert,
+            self.ctx.get_clock().now(),
+        );
 
-        let issuer = Issuer::new(instance.local_ca().params.clone(), instance.private_key());
-        let certificate = csr_params
-            .signed_by(&issuer)
+        let certificate = self
+            .ctx
+            .get_instance()
+            .sign_csr(&csr_params)
+            .await
             .map_err(|err| {
                 warn!(?err, "unable to sign service certificate");
                 tonic::Status::invalid_argument("Certificate signing problem")
             })?;
 
+        if let Err(err) = record_admin_event(
+            &self.ctx,
+            AdminEventKind::CertificateIssued,
+            json!({ "serviceId": peer_svc_eid }),
+        )
+        .await
+        {
+            warn!(?err, "unable to record certificate event");
+        }
+
         Ok(Response::new(proto::Certificate {
-            der: certificate.der().to_vec().into(),
+            der: certificate.to_vec().into(),
         }))
     }
 
@@ -363,7 +567,7 @@ where
         let eid = svc_mtls_auth_trivial(request.extensions())?;
         let remote_addr = svc_remote_addr(request.extensions())?;
 
-        info!(?eid, ?remote_addr, "service subscribing to messages");
+        info!("service subscribing to messages");
 
         let (sender, receiver) = tokio::sync::mpsc::channel(8);
 
@@ -400,16 +604,18 @@ where
         &self,
         request: Request<proto::Empty>,
     ) -> tonic::Result<tonic::Response<proto::Empty>> {
-        let eid = svc_mtls_auth_trivial(request.extensions())?;
-        let remote_addr = svc_remote_addr(request.extensions())?;
+        let svc_eid = svc_mtls_auth_trivial(request.extensions())?;
 
-        info!(?eid, ?remote_addr, "received pong");
+        info!(?svc_eid, "received pong");
+        self.ctx.service_event_dispatcher().record_pong(svc_eid);
 
         Ok(tonic::Response::new(proto::Empty {}))
     }
 }
 
-/// Just extract the peer entity id without checking any required roles
+/// Just extract the peer entity id without checking any required roles.
+///
+/// The peer is already authenticated by [PeerAuth].
 fn svc_mtls_auth_trivial(extensions: &tonic::Extensions) -> tonic::Result<ServiceId> {
     let peer_svc_eid = extensions
         .get::<PeerServiceEntity>()
@@ -418,6 +624,21 @@ fn svc_mtls_auth_trivial(extensions: &tonic::Extensions) -> tonic::Result<Servic
     Ok(peer_svc_eid.0)
 }
 
+/// The IP address of the subject of a request.
+///
+/// The peer service is authenticated, and trusted to forward the address of its own client in `x-forwarded-for`.
+/// Without it, the subject is the peer service itself.
+fn subject_ip<T>(deps: &impl GetSettings, request: &Request<T>) -> Option<IpAddr> {
+    if let Some(forwarded_for) = request.metadata().get("x-forwarded-for") {
+        return forwarded_for.to_str().ok().and_then(|forwarded_for| {
+            remote_addr::forwarded_client_ip(forwarded_for, &deps.get_settings().trusted_proxies)
+        });
+    }
+
+    let remote_addr = request.extensions().get::<RemoteAddr>()?;
+    Some(remote_addr.0.ip().to_canonical())
+}
+
 fn svc_remote_addr(extensions: &tonic::Extensions) -> tonic::Result<SocketAddr> {
     let remote_addr = extensions
         .get::<RemoteAddr>()
@@ -445,7 +666,10 @@ async fn svc_mtls_auth(
     Ok(authorized)
 }
 
-async fn session_auth(deps: &impl GetDb, metadata: &MetadataMap) -> Result<Session, &'static str> {
+async fn session_auth(
+    deps: &(impl GetDb + GetSettings + GetSessionCache + GetClock),
+    metadata: &MetadataMap,
+) -> Result<Session, &'static str> {
     let session_cookie = find_session_cookie(
         metadata
             .get_all(COOKIE.as_str())
@@ -456,20 +680,20 @@ async fn session_auth(deps: &impl GetDb, metadata: &MetadataMap) -> Result<Sessi
     authenticate_session_cookie(deps, &session_cookie).await
 }
 
-fn get_access_token_opt(
-    deps: &dyn GetInstance,
+async fn get_access_token_opt(
+    deps: &(impl GetDb + GetInstance + GetSettings + GetClock),
     metadata: &MetadataMap,
 ) -> tonic::Result<Option<AuthlyAccessTokenClaims>> {
     let Some(authorization) = metadata.get(AUTHORIZATION.as_str()) else {
         return Ok(None);
     };
-    let claims = verify_bearer(deps, authorization)?;
+    let claims = verify_bearer(deps, authorization).await?;
     Ok(Some(claims))
 }
 
 #[expect(unused)]
-fn get_access_token(
-    deps: &dyn GetInstance,
+async fn get_access_token(
+    deps: &(impl GetDb + GetInstance + GetSettings + GetClock),
     metadata: &MetadataMap,
 ) -> tonic::Result<AuthlyAccessTokenClaims> {
     verify_bearer(
@@ -478,10 +702,11 @@ fn get_access_token(
             .get(AUTHORIZATION.as_str())
             .ok_or_else(|| tonic::Status::unauthenticated("access token is missing"))?,
     )
+    .await
 }
 
-fn verify_bearer(
-    deps: &dyn GetInstance,
+async fn verify_bearer(
+    deps: &(impl GetDb + GetInstance + GetSettings + GetClock),
     value: &tonic::metadata::MetadataValue<Ascii>,
 ) -> tonic::Result<AuthlyAccessTokenClaims> {
     let token = value
@@ -490,10 +715,101 @@ fn verify_bearer(
         .and_then(|bearer| bearer.strip_prefix("Bearer "))
         .ok_or_else(|| tonic::Status::unauthenticated("invalid access token encoding"))?;
 
-    access_token::verify_access_token(token, &deps.get_instance())
+    access_token::verify_and_resolve_access_token(token, deps)
+        .await
         .map_err(|_| tonic::Status::unauthenticated("access token not verified"))
 }
 
+/// List the methods the peer service may call in the [ALLOWED_METHODS] response metadata
+/// The `get_metadata` response, announcing the allowed methods and the protocol of the server
+fn metadata_response(
+    metadata: proto::ServiceMetadata,
+    peer_svc: &AuthorizedPeerService,
+) -> Response<proto::ServiceMetadata> {
+    let mut response = with_allowed_methods(Response::new(metadata), peer_svc);
+    ServerProtocol::current().insert_into(response.metadata_mut());
+    response
+}
+
+fn with_allowed_methods<T>(
+    mut response: Response<T>,
+    peer_svc: &AuthorizedPeerService,
+) -> Response<T> {
+    let allowed_methods = METHODS
+        .iter()
+        .filter(|method| method.is_allowed(&peer_svc.attributes))
+        .map(|method| method.method)
+        .collect::<Vec<_>>()
+        .join(",");
+
+    if let Ok(value) = MetadataValue::try_from(allowed_methods) {
+        response.metadata_mut().insert(ALLOWED_METHODS, value);
+    }
+
+    response
+}
+
+/// Respond with the message unless the client already has it, according to its `if-none-match`.
+///
+/// The ETag is a hash of the encoded message, which relies on the message being built deterministically.
+fn conditional_response<T: prost::Message + Default>(
+    request_metadata: &MetadataMap,
+    message: T,
+) -> Response<T> {
+    let etag = format!(
+        "\"{}\"",
+        hexhex::hex(blake3::hash(&message.encode_to_vec()).as_bytes())
+    );
+    let not_modified = request_metadata
+        .get(IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value == etag);
+
+    let mut response = Response::new(if not_modified { T::default() } else { message });
+
+    let metadata = response.metadata_mut();
+    if let Ok(value) = MetadataValue::try_from(etag) {
+        metadata.insert(ETAG, value);
+    }
+    if not_modified {
+        metadata.insert(NOT_MODIFIED, MetadataValue::from_static("true"));
+    }
+
+    response
+}
+
+/// Convert a property mapping to proto, sorted by namespace, property and attribute label
+fn property_mapping_to_proto(
+    property_mapping: NamespacePropertyMapping,
+) -> Vec<proto::PropertyMappingNamespace> {
+    let mut namespaces: Vec<_> = property_mapping
+        .into_iter()
+        .map(|(label, properties)| {
+            let mut properties: Vec<_> = properties
+                .into_iter()
+                .map(|(label, attributes)| {
+                    let mut attributes: Vec<_> = attributes
+                        .into_iter()
+                        .map(|(label, attr_id)| proto::AttributeMapping {
+                            label,
+                            obj_id: attr_id.to_array_dynamic().to_vec().into(),
+                        })
+                        .collect();
+                    attributes.sort_by(|a, b| a.label.cmp(&b.label));
+
+                    proto::PropertyMapping { label, attributes }
+                })
+                .collect();
+            properties.sort_by(|a, b| a.label.cmp(&b.label));
+
+            proto::PropertyMappingNamespace { label, properties }
+        })
+        .collect();
+    namespaces.sort_by(|a, b| a.label.cmp(&b.label));
+
+    namespaces
+}
+
 fn id_from_proto<T: Id128DynamicArrayConv>(bytes: &[u8]) -> tonic::Result<T> {
     T::try_from_bytes_dynamic(bytes).ok_or_else(|| tonic::Status::invalid_argument("invalid ID"))
 }
@@ -0,0 +1,90 @@
+//! gRPC services for resolving access tokens.
+
+use authly_common::{id::EntityId, mtls_server::PeerServiceEntity};
+use authly_domain::{
+    access_token::{self, AccessTokenError, AttributesRef},
+    ctx::{GetClock, GetDb},
+};
+use tonic::{Request, Response};
+
+use crate::proto::{
+    grpc_db_err,
+    peer_auth::{MethodRoles, PeerAuth},
+};
+
+pub mod proto {
+    tonic::include_proto!("authly_token");
+}
+
+use proto::authly_token_server::{AuthlyToken, AuthlyTokenServer};
+
+/// The roles required for calling each method of the service, enforced by [PeerAuth]
+pub const METHODS: &[MethodRoles] = &[MethodRoles {
+    method: "GetEntityAttributes",
+    roles: &[],
+    checked_by_handler: false,
+}];
+
+pub struct AuthlyTokenServerImpl<Ctx> {
+    ctx: Ctx,
+}
+
+impl<Ctx> AuthlyTokenServerImpl<Ctx> {
+    pub fn new_service(ctx: Ctx) -> PeerAuth<AuthlyTokenServer<Self>, Ctx>
+    where
+        Ctx: Clone,
+    {
+        PeerAuth::new(
+            AuthlyTokenServer::new(Self { ctx: ctx.clone() }),
+            ctx,
+            METHODS,
+        )
+    }
+}
+
+#[tonic::async_trait]
+impl<Ctx> AuthlyToken for AuthlyTokenServerImpl<Ctx>
+where
+    Ctx: GetDb + GetClock + Send + Sync + 'static,
+{
+    /// Only the service the access token was issued to resolves its attributes
+    async fn get_entity_attributes(
+        &self,
+        request: Request<proto::GetEntityAttributesRequest>,
+    ) -> tonic::Result<Response<proto::EntityAttributes>> {
+        let peer_svc_eid = request
+            .extensions()
+            .get::<PeerServiceEntity>()
+            .ok_or_else(|| tonic::Status::unauthenticated("invalid service identity"))?
+            .0;
+        let request = request.into_inner();
+        let eid: EntityId = request
+            .entity_id
+            .parse()
+            .map_err(|_| tonic::Status::invalid_argument("invalid entity id"))?;
+        let attributes_ref = AttributesRef {
+            service: peer_svc_eid,
+            id: request.attributes_ref,
+        };
+
+        let attributes = access_token::resolve_attributes_ref(
+            &self.ctx,
+            eid,
+            &attributes_ref,
+            self.ctx.get_clock().now(),
+        )
+        .await
+        .map_err(|err| match err {
+            AccessTokenError::Db(err) => grpc_db_err(err),
+            _ => tonic::Status::not_found("unknown attribute reference"),
+        })?;
+
+        Ok(Response::new(proto::EntityAttributes {
+            entity_id: eid.to_string(),
+            attribute_ids: attributes
+                .into_iter()
+                .map(|attr_id| attr_id.to_string())
+                .collect(),
+        }))
+    }
+}
@@ -0,0 +1,157 @@
+//! A subset of the SCIM filter language: attribute comparisons joined by `and`.
+
+use serde_json::Value;
+
+use super::json_attribute_values;
+
+#[derive(Debug)]
+pub struct Filter(Vec<Comparison>);
+
+#[derive(Debug)]
+struct Comparison {
+    attribute: String,
+    op: Op,
+    value: String,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Op {
+    Eq,
+    Ne,
+    Co,
+    Sw,
+    Ew,
+    Pr,
+}
+
+impl Filter {
+    pub fn parse(input: &str) -> Result<Self, String> {
+        let tokens = tokenize(input)?;
+        let mut tokens = tokens.into_iter();
+        let mut comparisons = vec![];
+
+        loop {
+            let attribute = tokens.next().ok_or("expected attribute")?;
+            let op = match tokens
+                .next()
+                .ok_or("expected operator")?
+                .to_ascii_lowercase()
+                .as_str()
+            {
+                "eq" => Op::Eq,
+                "ne" => Op::Ne,
+                "co" => Op::Co,
+                "sw" => Op::Sw,
+                "ew" => Op::Ew,
+                "pr" => Op::Pr,
+                other => return Err(format!("unsupported operator `{other}`")),
+            };
+            let value = match op {
+                Op::Pr => String::new(),
+                _ => tokens.next().ok_or("expected value")?,
+            };
+
+            comparisons.push(Comparison {
+                attribute,
+                op,
+                value: value.to_lowercase(),
+            });
+
+            match tokens.next() {
+                None => break,
+                Some(token) if token.eq_ignore_ascii_case("and") => continue,
+                Some(token) => return Err(format!("unsupported filter syntax at `{token}`")),
+            }
+        }
+
+        Ok(Self(comparisons))
+    }
+
+    /// Test a resource against the filter. String comparisons are case-insensitive.
+    pub fn matches(&self, resource: &Value) -> bool {
+        self.0.iter().all(|comparison| {
+            let values = json_attribute_values(resource, &comparison.attribute);
+            let expected = comparison.value.as_str();
+
+            match comparison.op {
+                Op::Pr => !values.is_empty(),
+                Op::Ne => values.iter().all(|value| value.to_lowercase() != expected),
+                op => values.iter().any(|value| {
+                    let value = value.to_lowercase();
+                    match op {
+                        Op::Eq => value == expected,
+                        Op::Co => value.contains(expected),
+                        Op::Sw => value.starts_with(expected),
+                        Op::Ew => value.ends_with(expected),
+                        Op::Ne | Op::Pr => unreachable!(),
+                    }
+                }),
+            }
+        })
+    }
+}
+
+/// Split into whitespace-separated tokens, where double-quoted strings form a single token
+fn tokenize(input: &str) -> Result<Vec<String>, String> {
+    let mut tokens = vec![];
+    let mut chars = input.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '"' {
+            chars.next();
+            let mut token = String::new();
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => token.extend(chars.next()),
+                    Some(c) => token.push(c),
+                    None => return Err("unterminated string".to_string()),
+                }
+            }
+            tokens.push(token);
+        } else {
+            let mut token = String::new();
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() {
+                    break;
+                }
+                token.push(c);
+                chars.next();
+            }
+            tokens.push(token);
+        }
+    }
+
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::Filter;
+
+    #[test]
+    fn test_filter() {
+        let user = json!({
+            "userName": "Alice",
+            "emails": [{ "value": "alice@example.com", "primary": true }],
+            "active": true,
+        });
+
+        let matches = |filter: &str| Filter::parse(filter).unwrap().matches(&user);
+
+        assert!(matches(r#"userName eq "alice""#));
+        assert!(matches(r#"USERNAME eq "ALICE""#));
+        assert!(!matches(r#"userName eq "bob""#));
+        assert!(matches(r#"emails.value ew "@example.com""#));
+        assert!(matches(r#"emails co "alice""#));
+        assert!(matches(r#"userName sw "al" and active eq true"#));
+        assert!(matches("externalId ne \"x\""));
+        assert!(!matches("externalId pr"));
+        assert!(Filter::parse(r#"userName eq "alice" or active eq true"#).is_err());
+        assert!(Filter::parse(r#"userName gt "a""#).is_err());
+    }
+}
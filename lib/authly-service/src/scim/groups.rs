@@ -0,0 +1,180 @@
+use authly_common::id::{EntityId, GroupId};
+use authly_domain::{
+    ctx::{ClusterBus, GetBuiltins, GetClock, GetDb, GetIdGenerator},
+    id::parse_unreserved_id,
+    scim::{self, ScimGroup, ScimGroupData},
+};
+use axum::{
+    extract::{Path, Query, State},
+    response::{IntoResponse, Response},
+};
+use http::StatusCode;
+use serde_json::{json, Value};
+
+use super::{
+    format_datetime, list_response,
+    patch::{apply_patch, PatchRequest},
+    scim_json, ListQuery, ScimAuth, ScimErrorResponse, ScimJson, SCHEMA_GROUP,
+};
+
+pub async fn list_groups<Ctx>(
+    State(ctx): State<Ctx>,
+    _auth: ScimAuth,
+    Query(query): Query<ListQuery>,
+) -> Result<Response, ScimErrorResponse>
+where
+    Ctx: GetDb + GetBuiltins,
+{
+    let groups = scim::list_groups(&ctx).await?;
+
+    list_response(groups.into_iter().map(group_to_json).collect(), &query)
+}
+
+pub async fn get_group<Ctx>(
+    State(ctx): State<Ctx>,
+    _auth: ScimAuth,
+    Path(id): Path<String>,
+) -> Result<Response, ScimErrorResponse>
+where
+    Ctx: GetDb + GetBuiltins,
+{
+    let group = scim::get_group(&ctx, parse_id(&id)?).await?;
+
+    Ok(scim_json(StatusCode::OK, group_to_json(group)))
+}
+
+pub async fn create_group<Ctx>(
+    State(ctx): State<Ctx>,
+    _auth: ScimAuth,
+    ScimJson(body): ScimJson<Value>,
+) -> Result<Response, ScimErrorResponse>
+where
+    Ctx: GetDb + GetBuiltins + GetIdGenerator + GetClock + ClusterBus,
+{
+    let group = scim::create_group(&ctx, group_data_from_json(&body)?).await?;
+
+    Ok(scim_json(StatusCode::CREATED, group_to_json(group)))
+}
+
+pub async fn replace_group<Ctx>(
+    State(ctx): State<Ctx>,
+    _auth: ScimAuth,
+    Path(id): Path<String>,
+    ScimJson(body): ScimJson<Value>,
+) -> Result<Response, ScimErrorResponse>
+where
+    Ctx: GetDb + GetBuiltins + GetIdGenerator + GetClock + ClusterBus,
+{
+    let group = scim::replace_group(&ctx, parse_id(&id)?, group_data_from_json(&body)?).await?;
+
+    Ok(scim_json(StatusCode::OK, group_to_json(group)))
+}
+
+pub async fn patch_group<Ctx>(
+    State(ctx): State<Ctx>,
+    _auth: ScimAuth,
+    Path(id): Path<String>,
+    ScimJson(patch): ScimJson<PatchRequest>,
+) -> Result<Response, ScimErrorResponse>
+where
+    Ctx: GetDb + GetBuiltins + GetIdGenerator + GetClock + ClusterBus,
+{
+    let id = parse_id(&id)?;
+
+    let mut resource = group_to_json(scim::get_group(&ctx, id).await?);
+    apply_patch(&mut resource, patch)?;
+
+    let group = scim::replace_group(&ctx, id, group_data_from_json(&resource)?).await?;
+
+    Ok(scim_json(StatusCode::OK, group_to_json(group)))
+}
+
+pub async fn delete_group<Ctx>(
+    State(ctx): State<Ctx>,
+    _auth: ScimAuth,
+    Path(id): Path<String>,
+) -> Result<Response, ScimErrorResponse>
+where
+    Ctx: GetDb + GetIdGenerator + GetClock + ClusterBus,
+{
+    scim::delete_group(&ctx, parse_id(&id)?).await?;
+
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+fn parse_id(id: &str) -> Result<GroupId, ScimErrorResponse> {
+    parse_unreserved_id(id).ok_or_else(ScimErrorResponse::not_found)
+}
+
+fn group_to_json(group: ScimGroup) -> Value {
+    let mut value = json!({
+        "schemas": [SCHEMA_GROUP],
+        "id": group.id.to_string(),
+        "displayName": group.data.display_name,
+        "members": group.data.members
+            .iter()
+            .map(|member| json!({ "value": member.to_string() }))
+            .collect::<Vec<_>>(),
+        "meta": {
+            "resourceType": "Group",
+            "created": format_datetime(group.created_at),
+            "lastModified": format_datetime(group.updated_at),
+        },
+    });
+    if let Some(external_id) = group.data.external_id {
+        value["externalId"] = json!(external_id);
+    }
+
+    value
+}
+
+fn group_data_from_json(body: &Value) -> Result<ScimGroupData, ScimErrorResponse> {
+    let Value::Object(object) = body else {
+        return Err(ScimErrorResponse::bad_request(
+            "invalidSyntax",
+            "expected a JSON object",
+        ));
+    };
+    let get = |attribute: &str| {
+        object
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(attribute))
+            .map(|(_, value)| value)
+            .filter(|value| !value.is_null())
+    };
+
+    let display_name = get("displayName")
+        .and_then(Value::as_str)
+        .filter(|display_name| !display_name.is_empty())
+        .ok_or_else(|| ScimErrorResponse::bad_request("invalidValue", "displayName is required"))?
+        .to_string();
+
+    let members = match get("members") {
+        None => vec![],
+        Some(Value::Array(members)) => members
+            .iter()
+            .map(|member| {
+                member["value"]
+                    .as_str()
+                    .and_then(|value| value.parse::<EntityId>().ok())
+                    .ok_or_else(|| {
+                        ScimErrorResponse::bad_request("invalidValue", "invalid member value")
+                    })
+            })
+            .collect::<Result<_, _>>()?,
+        Some(_) => {
+            return Err(ScimErrorResponse::bad_request(
+                "invalidValue",
+                "members must be an array",
+            ))
+        }
+    };
+
+    Ok(ScimGroupData {
+        display_name,
+        external_id: get("externalId")
+            .and_then(Value::as_str)
+            .map(str::to_string),
+        members,
+    })
+}
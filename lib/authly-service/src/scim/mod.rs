@@ -0,0 +1,295 @@
+//! SCIM 2.0 provisioning API (RFC 7643 and RFC 7644).
+//!
+//! SCIM clients authenticate with bearer tokens issued through the admin API.
+//! The mapping of resources onto entities is done in [authly_domain::scim].
+
+use authly_common::id::ServiceId;
+use authly_domain::{
+    ctx::{
+        ClusterBus, GetBuiltins, GetClock, GetDb, GetDecryptedDeks, GetHttpClient, GetIdGenerator,
+        GetSettings,
+    },
+    scim::{self, ScimError},
+};
+use axum::{
+    body::Bytes,
+    extract::{FromRequest, FromRequestParts, Request},
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use axum_extra::{
+    headers::{authorization::Bearer, Authorization},
+    TypedHeader,
+};
+use http::{header::CONTENT_TYPE, request::Parts, StatusCode};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{json, Value};
+use tracing::warn;
+
+mod filter;
+mod patch;
+
+pub mod groups;
+pub mod users;
+
+pub const SCIM_CONTENT_TYPE: &str = "application/scim+json";
+
+const SCHEMA_USER: &str = "urn:ietf:params:scim:schemas:core:2.0:User";
+const SCHEMA_GROUP: &str = "urn:ietf:params:scim:schemas:core:2.0:Group";
+const SCHEMA_LIST_RESPONSE: &str = "urn:ietf:params:scim:api:messages:2.0:ListResponse";
+const SCHEMA_ERROR: &str = "urn:ietf:params:scim:api:messages:2.0:Error";
+
+pub fn router<Ctx>() -> Router<Ctx>
+where
+    Ctx: GetDb
+        + GetBuiltins
+        + GetDecryptedDeks
+        + GetSettings
+        + GetHttpClient
+        + GetIdGenerator
+        + GetClock
+        + ClusterBus
+        + Clone
+        + Send
+        + Sync
+        + 'static,
+{
+    Router::new()
+        .route(
+            "/scim/v2/Users",
+            get(users::list_users::<Ctx>).post(users::create_user::<Ctx>),
+        )
+        .route(
+            "/scim/v2/Users/{id}",
+            get(users::get_user::<Ctx>)
+                .put(users::replace_user::<Ctx>)
+                .patch(users::patch_user::<Ctx>)
+                .delete(users::delete_user::<Ctx>),
+        )
+        .route(
+            "/scim/v2/Groups",
+            get(groups::list_groups::<Ctx>).post(groups::create_group::<Ctx>),
+        )
+        .route(
+            "/scim/v2/Groups/{id}",
+            get(groups::get_group::<Ctx>)
+                .put(groups::replace_group::<Ctx>)
+                .patch(groups::patch_group::<Ctx>)
+                .delete(groups::delete_group::<Ctx>),
+        )
+}
+
+/// A SCIM client authenticated by bearer token, acting on behalf of a service
+pub struct ScimAuth(pub ServiceId);
+
+impl<Ctx> FromRequestParts<Ctx> for ScimAuth
+where
+    Ctx: GetDb + GetClock + Send + Sync,
+{
+    type Rejection = ScimErrorResponse;
+
+    async fn from_request_parts(parts: &mut Parts, ctx: &Ctx) -> Result<Self, Self::Rejection> {
+        let unauthorized =
+            || ScimErrorResponse::new(StatusCode::UNAUTHORIZED, None, "invalid bearer token");
+
+        let TypedHeader(Authorization(bearer)) =
+            TypedHeader::<Authorization<Bearer>>::from_request_parts(parts, ctx)
+                .await
+                .map_err(|_| unauthorized())?;
+
+        let svc_eid = scim::authenticate_scim_token(ctx, bearer.token())
+            .await
+            .map_err(|err| {
+                warn!(?err, "scim token lookup error");
+                ScimErrorResponse::internal()
+            })?
+            .ok_or_else(unauthorized)?;
+
+        Ok(Self(svc_eid))
+    }
+}
+
+/// JSON request body, accepting both `application/scim+json` and `application/json`
+pub struct ScimJson<T>(pub T);
+
+impl<Ctx, T> FromRequest<Ctx> for ScimJson<T>
+where
+    Ctx: Send + Sync,
+    T: DeserializeOwned,
+{
+    type Rejection = ScimErrorResponse;
+
+    async fn from_request(req: Request, ctx: &Ctx) -> Result<Self, Self::Rejection> {
+        let bytes = Bytes::from_request(req, ctx)
+            .await
+            .map_err(|_| ScimErrorResponse::bad_request("invalidSyntax", "unreadable body"))?;
+
+        serde_json::from_slice(&bytes)
+            .map(Self)
+            .map_err(|err| ScimErrorResponse::bad_request("invalidSyntax", err.to_string()))
+    }
+}
+
+/// An error in the SCIM error response format
+#[derive(Debug)]
+pub struct ScimErrorResponse {
+    pub status: StatusCode,
+    pub scim_type: Option<&'static str>,
+    pub detail: String,
+}
+
+impl ScimErrorResponse {
+    fn new(status: StatusCode, scim_type: Option<&'static str>, detail: impl Into<String>) -> Self {
+        Self {
+            status,
+            scim_type,
+            detail: detail.into(),
+        }
+    }
+
+    fn bad_request(scim_type: &'static str, detail: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, Some(scim_type), detail)
+    }
+
+    fn not_found() -> Self {
+        Self::new(StatusCode::NOT_FOUND, None, "resource not found")
+    }
+
+    fn internal() -> Self {
+        Self::new(StatusCode::INTERNAL_SERVER_ERROR, None, "internal error")
+    }
+}
+
+impl From<ScimError> for ScimErrorResponse {
+    fn from(err: ScimError) -> Self {
+        match err {
+            ScimError::NotFound => Self::not_found(),
+            ScimError::Uniqueness(attribute) => Self::new(
+                StatusCode::CONFLICT,
+                Some("uniqueness"),
+                format!("{attribute} is already in use"),
+            ),
+            ScimError::InvalidValue(detail) => Self::bad_request("invalidValue", detail),
+            ScimError::Password(err) => Self::bad_request("invalidValue", err.to_string()),
+            err => {
+                warn!(?err, "scim error");
+                Self::internal()
+            }
+        }
+    }
+}
+
+impl IntoResponse for ScimErrorResponse {
+    fn into_response(self) -> Response {
+        let mut body = json!({
+            "schemas": [SCHEMA_ERROR],
+            "status": self.status.as_u16().to_string(),
+            "detail": self.detail,
+        });
+        if let Some(scim_type) = self.scim_type {
+            body["scimType"] = json!(scim_type);
+        }
+
+        scim_json(self.status, body)
+    }
+}
+
+fn scim_json(status: StatusCode, body: impl Serialize) -> Response {
+    (
+        status,
+        [(CONTENT_TYPE, SCIM_CONTENT_TYPE)],
+        serde_json::to_string(&body).unwrap_or_default(),
+    )
+        .into_response()
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct ListQuery {
+    pub filter: Option<String>,
+    /// 1-based index of the first result
+    pub start_index: Option<usize>,
+    pub count: Option<usize>,
+}
+
+/// Filter and paginate resources into a list response
+fn list_response(resources: Vec<Value>, query: &ListQuery) -> Result<Response, ScimErrorResponse> {
+    let filter = query
+        .filter
+        .as_deref()
+        .map(filter::Filter::parse)
+        .transpose()
+        .map_err(|err| ScimErrorResponse::bad_request("invalidFilter", err))?;
+
+    let matching: Vec<Value> = resources
+        .into_iter()
+        .filter(|resource| match &filter {
+            Some(filter) => filter.matches(resource),
+            None => true,
+        })
+        .collect();
+
+    let total_results = matching.len();
+    let start_index = query.start_index.unwrap_or(1).max(1);
+    let page: Vec<Value> = matching
+        .into_iter()
+        .skip(start_index - 1)
+        .take(query.count.unwrap_or(usize::MAX))
+        .collect();
+
+    Ok(scim_json(
+        StatusCode::OK,
+        json!({
+            "schemas": [SCHEMA_LIST_RESPONSE],
+            "totalResults": total_results,
+            "startIndex": start_index,
+            "itemsPerPage": page.len(),
+            "Resources": page,
+        }),
+    ))
+}
+
+/// Look up a (possibly dotted) attribute of a JSON resource, case-insensitively.
+///
+/// Multi-valued attributes yield all their values, complex values yield their `value` sub-attribute.
+fn json_attribute_values(resource: &Value, attribute: &str) -> Vec<String> {
+    let mut current = vec![resource];
+
+    for segment in attribute.split('.') {
+        current = current
+            .into_iter()
+            .filter_map(|value| value.as_object())
+            .filter_map(|object| {
+                object
+                    .iter()
+                    .find(|(key, _)| key.eq_ignore_ascii_case(segment))
+                    .map(|(_, value)| value)
+            })
+            .flat_map(|value| match value {
+                Value::Array(items) => items.iter().collect(),
+                value => vec![value],
+            })
+            .collect();
+    }
+
+    current
+        .into_iter()
+        .filter_map(|value| match value {
+            Value::String(string) => Some(string.clone()),
+            Value::Bool(bool) => Some(bool.to_string()),
+            Value::Number(number) => Some(number.to_string()),
+            Value::Object(object) => object
+                .get("value")
+                .and_then(Value::as_str)
+                .map(str::to_string),
+            _ => None,
+        })
+        .collect()
+}
+
+fn format_datetime(datetime: time::OffsetDateTime) -> String {
+    datetime
+        .format(&time::format_description::well_known::Rfc3339)
+        .unwrap_or_default()
+}
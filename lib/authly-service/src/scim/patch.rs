@@ -0,0 +1,201 @@
+//! SCIM PATCH operations, applied to the JSON representation of a resource.
+
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use super::{filter::Filter, ScimErrorResponse};
+
+#[derive(Deserialize)]
+pub struct PatchRequest {
+    #[serde(rename = "Operations")]
+    pub operations: Vec<PatchOperation>,
+}
+
+#[derive(Deserialize)]
+pub struct PatchOperation {
+    pub op: String,
+    pub path: Option<String>,
+    pub value: Option<Value>,
+}
+
+/// A parsed patch path: `attribute`, `attribute.sub` or `attribute[filter].sub`
+struct Path {
+    attribute: String,
+    filter: Option<Filter>,
+    sub_attribute: Option<String>,
+}
+
+impl Path {
+    fn parse(input: &str) -> Result<Self, ScimErrorResponse> {
+        let invalid_path = || ScimErrorResponse::bad_request("invalidPath", input);
+
+        let (attribute, filter, rest) = match input.split_once('[') {
+            Some((attribute, rest)) => {
+                let (filter, rest) = rest.split_once(']').ok_or_else(invalid_path)?;
+                let filter = Filter::parse(filter).map_err(|_| invalid_path())?;
+                (attribute, Some(filter), rest)
+            }
+            None => match input.split_once('.') {
+                Some((attribute, sub_attribute)) => (attribute, None, sub_attribute),
+                None => (input, None, ""),
+            },
+        };
+
+        let sub_attribute = rest.strip_prefix('.').unwrap_or(rest);
+
+        if attribute.is_empty() {
+            return Err(invalid_path());
+        }
+
+        Ok(Self {
+            attribute: attribute.to_string(),
+            filter,
+            sub_attribute: (!sub_attribute.is_empty()).then(|| sub_attribute.to_string()),
+        })
+    }
+}
+
+/// Apply patch operations to a resource
+pub fn apply_patch(resource: &mut Value, request: PatchRequest) -> Result<(), ScimErrorResponse> {
+    let Value::Object(object) = resource else {
+        return Err(ScimErrorResponse::internal());
+    };
+
+    for operation in request.operations {
+        let op = operation.op.to_ascii_lowercase();
+
+        match (op.as_str(), operation.path) {
+            ("add" | "replace", None) => {
+                let Some(Value::Object(values)) = operation.value else {
+                    return Err(ScimErrorResponse::bad_request(
+                        "invalidValue",
+                        "operation without path requires an object value",
+                    ));
+                };
+
+                for (key, value) in values {
+                    set_attribute(object, &key, value, op == "add");
+                }
+            }
+            ("add" | "replace", Some(path)) => {
+                let path = Path::parse(&path)?;
+                let value = operation.value.ok_or_else(|| {
+                    ScimErrorResponse::bad_request("invalidValue", "missing value")
+                })?;
+
+                match (path.filter, path.sub_attribute) {
+                    (None, None) => set_attribute(object, &path.attribute, value, op == "add"),
+                    (None, Some(sub_attribute)) => {
+                        let key = attribute_key(object, &path.attribute);
+                        let entry = object
+                            .entry(key)
+                            .or_insert_with(|| Value::Object(Map::new()));
+                        if let Value::Object(complex) = entry {
+                            set_attribute(complex, &sub_attribute, value, op == "add");
+                        }
+                    }
+                    (Some(filter), sub_attribute) => {
+                        for item in matching_items(object, &path.attribute, &filter)? {
+                            match (&sub_attribute, &mut *item) {
+                                (Some(sub_attribute), Value::Object(complex)) => {
+                                    set_attribute(complex, sub_attribute, value.clone(), false)
+                                }
+                                (None, item) => *item = value.clone(),
+                                _ => {}
+                            }
+                        }
+                    }
+                }
+            }
+            ("remove", Some(path)) => {
+                let path = Path::parse(&path)?;
+                let key = attribute_key(object, &path.attribute);
+
+                match (path.filter, path.sub_attribute) {
+                    (None, None) => {
+                        object.remove(&key);
+                    }
+                    (None, Some(sub_attribute)) => {
+                        if let Some(Value::Object(complex)) = object.get_mut(&key) {
+                            let sub_key = attribute_key(complex, &sub_attribute);
+                            complex.remove(&sub_key);
+                        }
+                    }
+                    (Some(filter), None) => {
+                        if let Some(Value::Array(items)) = object.get_mut(&key) {
+                            items.retain(|item| !filter.matches(item));
+                        }
+                    }
+                    (Some(filter), Some(sub_attribute)) => {
+                        for item in matching_items(object, &path.attribute, &filter)? {
+                            if let Value::Object(complex) = item {
+                                let sub_key = attribute_key(complex, &sub_attribute);
+                                complex.remove(&sub_key);
+                            }
+                        }
+                    }
+                }
+            }
+            ("remove", None) => {
+                return Err(ScimErrorResponse::bad_request(
+                    "noTarget",
+                    "remove operation requires a path",
+                ));
+            }
+            (other, _) => {
+                return Err(ScimErrorResponse::bad_request(
+                    "invalidSyntax",
+                    format!("unsupported operation `{other}`"),
+                ));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Find the existing key of an attribute, attribute names are case-insensitive
+fn attribute_key(object: &Map<String, Value>, attribute: &str) -> String {
+    object
+        .keys()
+        .find(|key| key.eq_ignore_ascii_case(attribute))
+        .cloned()
+        .unwrap_or_else(|| attribute.to_string())
+}
+
+/// Set an attribute. `add` to a multi-valued attribute appends the new values.
+fn set_attribute(object: &mut Map<String, Value>, attribute: &str, value: Value, add: bool) {
+    let key = attribute_key(object, attribute);
+
+    if let (true, Some(Value::Array(items)), Value::Array(new_items)) =
+        (add, object.get_mut(&key), &value)
+    {
+        for item in new_items {
+            if !items.contains(item) {
+                items.push(item.clone());
+            }
+        }
+        return;
+    }
+
+    object.insert(key, value);
+}
+
+fn matching_items<'a>(
+    object: &'a mut Map<String, Value>,
+    attribute: &str,
+    filter: &Filter,
+) -> Result<Vec<&'a mut Value>, ScimErrorResponse> {
+    let key = attribute_key(object, attribute);
+    let Some(Value::Array(items)) = object.get_mut(&key) else {
+        return Err(ScimErrorResponse::bad_request(
+            "noTarget",
+            format!("no values of {attribute}"),
+        ));
+    };
+
+    Ok(items
+        .iter_mut()
+        .filter(|item| filter.matches(item))
+        .collect())
+}
@@ -0,0 +1,304 @@
+use std::collections::BTreeMap;
+
+use authly_common::id::PersonaId;
+use authly_domain::{
+    ctx::{
+        ClusterBus, GetBuiltins, GetClock, GetDb, GetDecryptedDeks, GetHttpClient, GetIdGenerator,
+        GetSettings,
+    },
+    id::parse_unreserved_id,
+    password::Password,
+    scim::{self, ScimUser, ScimUserData},
+    settings::ScimAttributeMapping,
+};
+use axum::{
+    extract::{Path, Query, State},
+    response::{IntoResponse, Response},
+};
+use http::StatusCode;
+use serde_json::{json, Map, Value};
+
+use super::{
+    format_datetime, list_response,
+    patch::{apply_patch, PatchRequest},
+    scim_json, ListQuery, ScimAuth, ScimErrorResponse, ScimJson, SCHEMA_USER,
+};
+
+pub async fn list_users<Ctx>(
+    State(ctx): State<Ctx>,
+    _auth: ScimAuth,
+    Query(query): Query<ListQuery>,
+) -> Result<Response, ScimErrorResponse>
+where
+    Ctx: GetDb + GetDecryptedDeks + GetSettings,
+{
+    let users = scim::list_users(&ctx).await?;
+    let mappings = ctx.get_settings().scim_attribute_mapping.clone();
+
+    list_response(
+        users
+            .into_iter()
+            .map(|user| user_to_json(user, &mappings))
+            .collect(),
+        &query,
+    )
+}
+
+pub async fn get_user<Ctx>(
+    State(ctx): State<Ctx>,
+    _auth: ScimAuth,
+    Path(id): Path<String>,
+) -> Result<Response, ScimErrorResponse>
+where
+    Ctx: GetDb + GetDecryptedDeks + GetSettings,
+{
+    let user = scim::get_user(&ctx, parse_id(&id)?).await?;
+
+    Ok(user_response(&ctx, StatusCode::OK, user))
+}
+
+pub async fn create_user<Ctx>(
+    State(ctx): State<Ctx>,
+    _auth: ScimAuth,
+    ScimJson(body): ScimJson<Value>,
+) -> Result<Response, ScimErrorResponse>
+where
+    Ctx: GetDb
+        + GetBuiltins
+        + GetDecryptedDeks
+        + GetSettings
+        + GetHttpClient
+        + GetIdGenerator
+        + GetClock
+        + ClusterBus,
+{
+    let data = user_data_from_json(&body, &ctx.get_settings().scim_attribute_mapping)?;
+    let user = scim::create_user(&ctx, data).await?;
+
+    Ok(user_response(&ctx, StatusCode::CREATED, user))
+}
+
+pub async fn replace_user<Ctx>(
+    State(ctx): State<Ctx>,
+    _auth: ScimAuth,
+    Path(id): Path<String>,
+    ScimJson(body): ScimJson<Value>,
+) -> Result<Response, ScimErrorResponse>
+where
+    Ctx: GetDb
+        + GetBuiltins
+        + GetDecryptedDeks
+        + GetSettings
+        + GetHttpClient
+        + GetIdGenerator
+        + GetClock
+        + ClusterBus,
+{
+    let data = user_data_from_json(&body, &ctx.get_settings().scim_attribute_mapping)?;
+    let user = scim::replace_user(&ctx, parse_id(&id)?, data).await?;
+
+    Ok(user_response(&ctx, StatusCode::OK, user))
+}
+
+pub async fn patch_user<Ctx>(
+    State(ctx): State<Ctx>,
+    _auth: ScimAuth,
+    Path(id): Path<String>,
+    ScimJson(patch): ScimJson<PatchRequest>,
+) -> Result<Response, ScimErrorResponse>
+where
+    Ctx: GetDb
+        + GetBuiltins
+        + GetDecryptedDeks
+        + GetSettings
+        + GetHttpClient
+        + GetIdGenerator
+        + GetClock
+        + ClusterBus,
+{
+    let id = parse_id(&id)?;
+    let mappings = ctx.get_settings().scim_attribute_mapping.clone();
+
+    let mut resource = user_to_json(scim::get_user(&ctx, id).await?, &mappings);
+    apply_patch(&mut resource, patch)?;
+
+    let data = user_data_from_json(&resource, &mappings)?;
+    let user = scim::replace_user(&ctx, id, data).await?;
+
+    Ok(user_response(&ctx, StatusCode::OK, user))
+}
+
+pub async fn delete_user<Ctx>(
+    State(ctx): State<Ctx>,
+    _auth: ScimAuth,
+    Path(id): Path<String>,
+) -> Result<Response, ScimErrorResponse>
+where
+    Ctx: GetDb + GetIdGenerator + GetClock + ClusterBus,
+{
+    scim::delete_user(&ctx, parse_id(&id)?).await?;
+
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+fn parse_id(id: &str) -> Result<PersonaId, ScimErrorResponse> {
+    parse_unreserved_id(id).ok_or_else(ScimErrorResponse::not_found)
+}
+
+fn user_response(ctx: &impl GetSettings, status: StatusCode, user: ScimUser) -> Response {
+    scim_json(
+        status,
+        user_to_json(user, &ctx.get_settings().scim_attribute_mapping),
+    )
+}
+
+pub fn user_to_json(user: ScimUser, mappings: &[ScimAttributeMapping]) -> Value {
+    let ScimUserData {
+        user_name,
+        external_id,
+        display_name,
+        email,
+        active,
+        password: _,
+        mut attributes,
+    } = user.data;
+
+    let mut object = Map::new();
+    object.insert("schemas".to_string(), json!([SCHEMA_USER]));
+    object.insert("id".to_string(), json!(user.id.to_string()));
+    if let Some(external_id) = external_id {
+        object.insert("externalId".to_string(), json!(external_id));
+    }
+    object.insert("userName".to_string(), json!(user_name));
+    if let Some(display_name) = display_name {
+        object.insert("displayName".to_string(), json!(display_name));
+    }
+    if let Some(email) = email {
+        object.insert(
+            "emails".to_string(),
+            json!([{ "value": email, "primary": true }]),
+        );
+    }
+    object.insert("active".to_string(), json!(active));
+
+    for mapping in mappings {
+        let Some(mut labels) = attributes.remove(&mapping.scim_attribute) else {
+            continue;
+        };
+        let value = if labels.len() == 1 {
+            json!(labels.remove(0))
+        } else {
+            json!(labels)
+        };
+        object.insert(mapping.scim_attribute.clone(), value);
+    }
+
+    object.insert(
+        "meta".to_string(),
+        json!({
+            "resourceType": "User",
+            "created": format_datetime(user.created_at),
+            "lastModified": format_datetime(user.updated_at),
+        }),
+    );
+
+    Value::Object(object)
+}
+
+fn user_data_from_json(
+    body: &Value,
+    mappings: &[ScimAttributeMapping],
+) -> Result<ScimUserData, ScimErrorResponse> {
+    let Value::Object(object) = body else {
+        return Err(ScimErrorResponse::bad_request(
+            "invalidSyntax",
+            "expected a JSON object",
+        ));
+    };
+    let get = |attribute: &str| {
+        object
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(attribute))
+            .map(|(_, value)| value)
+            .filter(|value| !value.is_null())
+    };
+    let get_string = |attribute: &str| -> Result<Option<String>, ScimErrorResponse> {
+        match get(attribute) {
+            None => Ok(None),
+            Some(Value::String(value)) => Ok(Some(value.clone())),
+            Some(_) => Err(ScimErrorResponse::bad_request(
+                "invalidValue",
+                format!("{attribute} must be a string"),
+            )),
+        }
+    };
+
+    let user_name = get_string("userName")?
+        .filter(|user_name| !user_name.is_empty())
+        .ok_or_else(|| ScimErrorResponse::bad_request("invalidValue", "userName is required"))?;
+
+    let email = match get("emails") {
+        None => None,
+        Some(Value::Array(emails)) => emails
+            .iter()
+            .find(|email| email["primary"] == json!(true))
+            .or_else(|| emails.first())
+            .and_then(|email| email["value"].as_str())
+            .map(str::to_string),
+        Some(_) => {
+            return Err(ScimErrorResponse::bad_request(
+                "invalidValue",
+                "emails must be an array",
+            ))
+        }
+    };
+
+    let active = match get("active") {
+        None => true,
+        Some(Value::Bool(active)) => *active,
+        // some clients send booleans as strings
+        Some(Value::String(active)) if active.eq_ignore_ascii_case("true") => true,
+        Some(Value::String(active)) if active.eq_ignore_ascii_case("false") => false,
+        Some(_) => {
+            return Err(ScimErrorResponse::bad_request(
+                "invalidValue",
+                "active must be a boolean",
+            ))
+        }
+    };
+
+    let mut attributes = BTreeMap::new();
+    for mapping in mappings {
+        let labels = match get(&mapping.scim_attribute) {
+            None => continue,
+            Some(Value::String(label)) => vec![label.clone()],
+            Some(Value::Array(labels)) => labels
+                .iter()
+                .map(|label| label.as_str().map(str::to_string))
+                .collect::<Option<Vec<_>>>()
+                .ok_or_else(|| {
+                    ScimErrorResponse::bad_request(
+                        "invalidValue",
+                        format!("{} must be strings", mapping.scim_attribute),
+                    )
+                })?,
+            Some(_) => {
+                return Err(ScimErrorResponse::bad_request(
+                    "invalidValue",
+                    format!("{} must be a string", mapping.scim_attribute),
+                ))
+            }
+        };
+        attributes.insert(mapping.scim_attribute.clone(), labels);
+    }
+
+    Ok(ScimUserData {
+        user_name,
+        external_id: get_string("externalId")?,
+        display_name: get_string("displayName")?,
+        email,
+        active,
+        password: get_string("password")?.map(Password),
+        attributes,
+    })
+}
@@ -1,6 +1,6 @@
 use std::{borrow::Cow, fmt::Debug, path::PathBuf};
 
-use authly_db::{Db, DbError, FromRow, TryFromRow};
+use authly_db::{slow_query::SlowQueryLog, Db, DbError, FromRow, TryFromRow, TxnResults};
 use deadpool::managed::{Object, Pool, PoolConfig};
 use manager::SqlitePoolManager;
 use param::{rusqlite_params, RusqliteParam};
@@ -21,6 +21,7 @@ pub enum Storage {
 #[derive(Clone)]
 pub struct SqlitePool {
     pool: Pool<SqlitePoolManager>,
+    slow_query_log: SlowQueryLog,
 }
 
 impl SqlitePool {
@@ -30,7 +31,15 @@ impl SqlitePool {
             .build()
             .unwrap();
 
-        Self { pool }
+        Self {
+            pool,
+            slow_query_log: SlowQueryLog::default(),
+        }
+    }
+
+    pub fn with_slow_query_log(mut self, slow_query_log: SlowQueryLog) -> Self {
+        self.slow_query_log = slow_query_log;
+        self
     }
 
     pub async fn get(&self) -> Result<Object<SqlitePoolManager>, DbError> {
@@ -53,8 +62,9 @@ impl Db for SqlitePool {
         T: FromRow + Send + 'static,
     {
         let conn = self.get().await?;
+        let log_sql = stmt.clone();
 
-        tokio::task::spawn_blocking(move || {
+        let task = tokio::task::spawn_blocking(move || {
             let mut stmt = conn.prepare_cached(&stmt).map_err(e)?;
             let mut rows = stmt.query(rusqlite_params(params)).map_err(e)?;
 
@@ -65,8 +75,9 @@ impl Db for SqlitePool {
             }
 
             Ok(output)
-        })
-        .await?
+        });
+
+        self.slow_query_log.observe(&log_sql, task).await?
     }
 
     async fn query_map_opt<T>(
@@ -78,8 +89,9 @@ impl Db for SqlitePool {
         T: FromRow + Send + 'static,
     {
         let conn = self.get().await?;
+        let log_sql = stmt.clone();
 
-        tokio::task::spawn_blocking(move || {
+        let task = tokio::task::spawn_blocking(move || {
             let mut stmt = conn.prepare_cached(&stmt).map_err(e)?;
             let mut rows = stmt.query(rusqlite_params(params)).map_err(e)?;
 
@@ -94,8 +106,9 @@ impl Db for SqlitePool {
             }
 
             Ok(output)
-        })
-        .await?
+        });
+
+        self.slow_query_log.observe(&log_sql, task).await?
     }
 
     async fn query_try_map_opt<T>(
@@ -107,8 +120,9 @@ impl Db for SqlitePool {
         T: TryFromRow + Send + 'static,
     {
         let conn = self.get().await?;
+        let log_sql = stmt.clone();
 
-        tokio::task::spawn_blocking(move || {
+        let task = tokio::task::spawn_blocking(move || {
             let mut stmt = conn.prepare_cached(&stmt).map_err(e)?;
             let mut rows = stmt.query(rusqlite_params(params)).map_err(e)?;
 
@@ -123,8 +137,9 @@ impl Db for SqlitePool {
             }
 
             Ok(output)
-        })
-        .await?
+        });
+
+        self.slow_query_log.observe(&log_sql, task).await?
     }
 
     async fn query_filter_map<T>(
@@ -137,8 +152,9 @@ impl Db for SqlitePool {
         <T as TryFromRow>::Error: Debug,
     {
         let conn = self.get().await?;
+        let log_sql = stmt.clone();
 
-        tokio::task::spawn_blocking(move || {
+        let task = tokio::task::spawn_blocking(move || {
             let mut stmt = conn.prepare_cached(&stmt).map_err(e)?;
             let mut rows = stmt.query(rusqlite_params(params)).map_err(e)?;
 
@@ -154,8 +170,9 @@ impl Db for SqlitePool {
             }
 
             Ok(output)
-        })
-        .await?
+        });
+
+        self.slow_query_log.observe(&log_sql, task).await?
     }
 
     async fn execute(
@@ -164,11 +181,13 @@ impl Db for SqlitePool {
         params: Vec<RusqliteParam>,
     ) -> Result<usize, DbError> {
         let conn = self.get().await?;
+        let log_sql = stmt.clone();
 
-        tokio::task::spawn_blocking(move || {
+        let task = tokio::task::spawn_blocking(move || {
             rusqlite::Connection::execute(&conn, &stmt, rusqlite_params(params)).map_err(e)
-        })
-        .await?
+        });
+
+        self.slow_query_log.observe(&log_sql, task).await?
     }
 
     async fn execute_map<T>(
@@ -180,8 +199,9 @@ impl Db for SqlitePool {
         T: FromRow + Send + 'static,
     {
         let conn = self.get().await?;
+        let log_sql = sql.clone();
 
-        tokio::task::spawn_blocking(move || {
+        let task = tokio::task::spawn_blocking(move || {
             let mut stmt = conn.prepare_cached(&sql).map_err(e)?;
             let mut rows = stmt.query(rusqlite_params(params)).map_err(e)?;
 
@@ -192,8 +212,9 @@ impl Db for SqlitePool {
             }
 
             Ok(output)
-        })
-        .await?
+        });
+
+        self.slow_query_log.observe(&log_sql, task).await?
     }
 
     fn stmt_column(stmt_index: usize, column_index: usize) -> Self::Param {
@@ -203,10 +224,12 @@ impl Db for SqlitePool {
     async fn transact(
         &self,
         sql: Vec<(Cow<'static, str>, Vec<RusqliteParam>)>,
-    ) -> Result<Vec<Result<usize, DbError>>, DbError> {
+    ) -> Result<TxnResults, DbError> {
         let mut conn = self.get().await?;
+        let log_sql = txn_sql(&sql);
+        let stmt_count = sql.len();
 
-        tokio::task::spawn_blocking(move || {
+        let task = tokio::task::spawn_blocking(move || {
             let txn = conn.transaction().map_err(e)?;
 
             let mut output = vec![];
@@ -265,18 +288,29 @@ impl Db for SqlitePool {
                 output.push(result);
             }
 
-            if output.iter().any(|result| result.is_err()) {
-                txn.rollback().map_err(e)?;
-            } else {
+            let results = TxnResults::new(stmt_count, output);
+            if results.is_committed() {
                 txn.commit().map_err(e)?;
+            } else {
+                txn.rollback().map_err(e)?;
             }
 
-            Ok(output)
-        })
-        .await?
+            Ok(results)
+        });
+
+        self.slow_query_log.observe(&log_sql, task).await?
     }
 }
 
+/// The statements of a transaction as one loggable string
+fn txn_sql<P>(stmts: &[(Cow<'static, str>, P)]) -> String {
+    stmts
+        .iter()
+        .map(|(sql, _)| sql.as_ref())
+        .collect::<Vec<_>>()
+        .join(";\n")
+}
+
 fn e(err: rusqlite::Error) -> DbError {
     DbError::Sql(format!("{err:?}").into())
 }
@@ -1,31 +1,113 @@
-use authly_common::id::{AttrId, PersonaId};
+use authly_common::id::{AttrId, PersonaId, ServiceId};
 use authly_domain::{
-    access_token,
-    ctx::LoadInstance,
-    session::{Session, SessionToken},
+    access_control, access_token,
+    ctx::{GetDb, LoadInstance},
+    repo::service_repo::{self, PropertyKind},
+    session::{AuthClass, Session, SessionKind, SessionToken},
 };
-use authly_test::test_ctx::TestCtx;
+use authly_test::{test_ctx::TestCtx, util::compile_and_apply_doc};
 use criterion::{criterion_group, criterion_main, Criterion};
 use fnv::FnvHashSet;
+use indoc::{formatdoc, indoc};
 use time::{Duration, OffsetDateTime};
 
 pub fn authly_benchmark(c: &mut Criterion) {
     let ctx = TestCtx::new().lite_instance();
+    let now = OffsetDateTime::now_utc();
     let session = Session {
         token: SessionToken::new_random(),
         eid: PersonaId::random().upcast(),
-        expires_at: OffsetDateTime::now_utc() + Duration::days(42),
+        expires_at: now + Duration::days(42),
+        kind: SessionKind::Default,
+        created_at: now,
+        last_seen: now,
+        auth_class: AuthClass::Password,
+        authenticated_at: now,
     };
     let user_attributes = FnvHashSet::from_iter([AttrId::random(), AttrId::random()]);
     let instance = ctx.load_instance();
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
 
     c.bench_function("generate_access_token", |b| {
         b.iter(|| {
-            access_token::create_access_token(&session, user_attributes.clone(), &instance)
+            runtime
+                .block_on(access_token::create_access_token(
+                    &session,
+                    user_attributes.clone(),
+                    &instance,
+                    OffsetDateTime::now_utc(),
+                ))
                 .unwrap();
         })
     });
 }
 
-criterion_group!(benches, authly_benchmark);
+pub fn peer_entity_benchmark(c: &mut Criterion) {
+    let runtime = tokio::runtime::Builder::new_multi_thread().build().unwrap();
+    let peer_eids: Vec<ServiceId> = (0..16).map(|_| ServiceId::random()).collect();
+    let ctx = runtime.block_on(async {
+        let ctx = TestCtx::new().inmemory_db().await;
+        let mut doc = indoc! {
+            r#"
+            [authly-document]
+            id = "bc9ce588-50c3-47d1-94c1-f88b21eaf299"
+            "#
+        }
+        .to_string();
+        for (idx, eid) in peer_eids.iter().enumerate() {
+            doc.push_str(&formatdoc! {
+                r#"
+                [[service-entity]]
+                eid = "{eid}"
+                label = "svc{idx}"
+
+                [[entity-property]]
+                namespace = "svc{idx}"
+                label = "trait"
+                attributes = ["x", "y"]
+                "#
+            });
+        }
+        compile_and_apply_doc(&doc, &ctx).await.unwrap();
+        ctx
+    });
+
+    c.bench_function("resolve_peer_entity_attrs_serial", |b| {
+        b.iter(|| {
+            runtime.block_on(async {
+                let mut attrs = FnvHashSet::default();
+                for eid in &peer_eids {
+                    let property_mapping = service_repo::get_service_property_mapping(
+                        ctx.get_db(),
+                        *eid,
+                        PropertyKind::Entity,
+                    )
+                    .await
+                    .unwrap();
+                    for (_, properties) in property_mapping {
+                        for (_, attributes) in properties {
+                            attrs.extend(attributes.into_iter().map(|(_, attr)| attr));
+                        }
+                    }
+                }
+                attrs
+            })
+        })
+    });
+
+    c.bench_function("resolve_peer_entity_attrs", |b| {
+        b.iter(|| {
+            runtime
+                .block_on(access_control::resolve_peer_entity_attrs(
+                    &ctx,
+                    peer_eids.iter().copied(),
+                ))
+                .unwrap()
+        })
+    });
+}
+
+criterion_group!(benches, authly_benchmark, peer_entity_benchmark);
 criterion_main!(benches);
@@ -10,22 +10,34 @@ use arc_swap::ArcSwap;
 use authly_common::id::{PersonaId, ServiceId};
 use authly_db::{params, Db, FromRow};
 use authly_domain::{
+    audit::{AuditAppender, AuditQueue},
     builtins::Builtins,
     bus::{
-        handler::authly_node_handle_incoming_message, service_events::ServiceEventDispatcher,
-        BusError, ClusterMessage,
+        entity_events::EntityEventNotifier, handler::authly_node_handle_incoming_message,
+        service_events::ServiceEventDispatcher, BusError, ClusterMessage,
     },
     cert::{authly_ca, client_cert, key_pair},
+    clock::{Clock, SystemClock},
+    clock_skew::ClockSkewMonitor,
     ctx::{
-        ClusterBus, Directories, GetBuiltins, GetDb, GetDecryptedDeks, GetHttpClient, GetInstance,
+        ClusterBus, Directories, EntityEventBus, GetAuditQueue, GetBuiltins, GetClock,
+        GetClockSkew, GetDb, GetDecryptedDeks, GetHttpClient, GetIdGenerator, GetInstance,
+        GetMetadataCache, GetPolicyEngineCache, GetSessionCache, GetSettings, GetStats,
         HostsConfig, KubernetesConfig, LoadInstance, RedistributeCertificates, ServiceBus,
         SetInstance, WebAuthn,
     },
     directory::PersonaDirectory,
     encryption::{gen_prop_deks, DecryptedDeks, DecryptedMaster},
-    instance::{AuthlyId, AuthlyInstance},
+    id_generator::{IdGenerator, RandomIdGenerator},
+    instance::{AuthlyId, AuthlyInstance, InstanceKeySource},
+    metadata_cache::ServiceMetadataCache,
     migration::Migrations,
+    policy_cache::PolicyEngineCache,
     repo::{crypto_repo, init_repo},
+    session_cache::{LruSessionCache, SessionCache},
+    settings::Settings,
+    signer::{InMemorySigner, Signer},
+    stats::{AuthlyStats, RaftRole},
     tls::{AuthlyCert, AuthlyCertKind},
     webauthn::{PasskeyAuthentication, PasskeyRegistration, Webauthn, WebauthnError},
     IsLeaderDb,
@@ -48,7 +60,18 @@ pub struct TestCtx {
     builtins: Option<Arc<Builtins>>,
     instance: Option<Arc<ArcSwap<AuthlyInstance>>>,
     deks: Arc<ArcSwap<DecryptedDeks>>,
+    settings: Arc<ArcSwap<Settings>>,
     svc_event_dispatcher: ServiceEventDispatcher,
+    entity_event_notifier: EntityEventNotifier,
+    audit_queue: AuditQueue,
+    audit_appender: Arc<tokio::sync::Mutex<AuditAppender>>,
+    session_cache: Arc<LruSessionCache>,
+    metadata_cache: Arc<ServiceMetadataCache>,
+    policy_engine_cache: Arc<PolicyEngineCache>,
+    stats: Arc<AuthlyStats>,
+    id_generator: Arc<dyn IdGenerator>,
+    clock: Arc<dyn Clock>,
+    clock_skew: Arc<ClockSkewMonitor>,
     persona_directories: IndexMap<String, PersonaDirectory>,
     webauthn: Option<Arc<Webauthn>>,
 
@@ -65,13 +88,25 @@ impl TestCtx {
     #[expect(clippy::new_without_default)]
     pub fn new() -> Self {
         let cancel = CancellationToken::new();
+        let (audit_queue, audit_appender) = AuditQueue::new();
 
         Self {
             db: None,
             builtins: None,
             instance: None,
             deks: Default::default(),
+            settings: Default::default(),
             svc_event_dispatcher: ServiceEventDispatcher::new(cancel.clone()),
+            entity_event_notifier: Default::default(),
+            audit_queue,
+            audit_appender: Arc::new(tokio::sync::Mutex::new(audit_appender)),
+            session_cache: Default::default(),
+            metadata_cache: Default::default(),
+            policy_engine_cache: Default::default(),
+            stats: Default::default(),
+            id_generator: Arc::new(RandomIdGenerator),
+            clock: Arc::new(SystemClock),
+            clock_skew: Default::default(),
             persona_directories: Default::default(),
             cache: Arc::new(Mutex::new(HashMap::new())),
             webauthn: None,
@@ -136,35 +171,37 @@ impl TestCtx {
 
     /// With AuthlyInstance that doesn't use the database
     pub fn lite_instance(mut self) -> Self {
-        let authly_id = AuthlyId {
-            eid: ServiceId::random(),
-            private_key: key_pair(),
-        };
+        // The certificates are signed directly with the key pair, which keeps this constructor synchronous
+        let key_pair = key_pair();
+        let eid = ServiceId::random();
         let certs = vec![
             {
-                let certificate = authly_ca().self_signed(&authly_id.private_key).unwrap();
+                let certificate = authly_ca().self_signed(&key_pair).unwrap();
                 AuthlyCert {
                     kind: AuthlyCertKind::Ca,
-                    certifies: authly_id.eid,
-                    signed_by: authly_id.eid,
+                    certifies: eid,
+                    signed_by: eid,
                     params: authly_ca(), // Use original CA params
                     der: certificate.der().clone(),
                 }
             },
             {
-                let certificate =
-                    client_cert("authly", authly_id.eid, time::Duration::days(365 * 100))
-                        .self_signed(&authly_id.private_key)
-                        .unwrap();
+                let certificate = client_cert("authly", eid, time::Duration::days(365 * 100))
+                    .self_signed(&key_pair)
+                    .unwrap();
                 AuthlyCert {
                     kind: AuthlyCertKind::Identity,
-                    certifies: authly_id.eid,
-                    signed_by: authly_id.eid,
-                    params: client_cert("authly", authly_id.eid, time::Duration::days(365 * 100)),
+                    certifies: eid,
+                    signed_by: eid,
+                    params: client_cert("authly", eid, time::Duration::days(365 * 100)),
                     der: certificate.der().clone(),
                 }
             },
         ];
+        let authly_id = AuthlyId {
+            eid,
+            signer: Arc::new(InMemorySigner::new(key_pair)),
+        };
         self.instance = Some(Arc::new(ArcSwap::new(Arc::new(AuthlyInstance::new(
             authly_id, certs,
         )))));
@@ -172,7 +209,18 @@ impl TestCtx {
     }
 
     /// Make a supreme instance with cryptographic keys and certificates
-    pub async fn supreme_instance(mut self) -> Self {
+    pub async fn supreme_instance(self) -> Self {
+        self.supreme_instance_with_key_source(InstanceKeySource::Database)
+            .await
+    }
+
+    /// Make a supreme instance whose instance key is held by an external signer
+    pub async fn supreme_instance_with_signer(self, signer: Arc<dyn Signer>) -> Self {
+        self.supreme_instance_with_key_source(InstanceKeySource::External(signer))
+            .await
+    }
+
+    async fn supreme_instance_with_key_source(mut self, key_source: InstanceKeySource) -> Self {
         let db = self.db.unwrap();
 
         let decrypted_master = DecryptedMaster::fake_for_test();
@@ -182,9 +230,15 @@ impl TestCtx {
                 .unwrap(),
         );
 
-        let instance = crypto_repo::load_authly_instance(IsLeaderDb(true), &db, &decrypted_deks)
-            .await
-            .unwrap();
+        let instance = crypto_repo::load_authly_instance(
+            IsLeaderDb(true),
+            &db,
+            &decrypted_deks,
+            key_source,
+            self.id_generator.as_ref(),
+        )
+        .await
+        .unwrap();
 
         self.db = Some(db);
         self.deks = Arc::new(ArcSwap::new(Arc::new(decrypted_deks)));
@@ -215,6 +269,28 @@ impl TestCtx {
         self
     }
 
+    pub fn with_settings(self, settings: Settings) -> Self {
+        self.settings.store(Arc::new(settings));
+        self
+    }
+
+    pub fn with_id_generator(mut self, id_generator: impl IdGenerator + 'static) -> Self {
+        self.id_generator = Arc::new(id_generator);
+        self
+    }
+
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    pub fn with_audit_queue_capacity(mut self, capacity: usize) -> Self {
+        let (audit_queue, audit_appender) = AuditQueue::with_capacity(capacity);
+        self.audit_queue = audit_queue;
+        self.audit_appender = Arc::new(tokio::sync::Mutex::new(audit_appender));
+        self
+    }
+
     pub fn with_webauthn(mut self, webauthn: Webauthn) -> Self {
         self.webauthn = Some(Arc::new(webauthn));
         self
@@ -228,6 +304,15 @@ impl TestCtx {
         self.cluster_message_log.lock().unwrap().clear();
     }
 
+    pub fn clone_cluster_message_log(&self) -> Vec<ClusterMessage> {
+        self.cluster_message_log.lock().unwrap().clone()
+    }
+
+    /// Record the access control decisions queued for auditing so far
+    pub async fn flush_audit(&self) {
+        self.audit_appender.lock().await.flush(self).await;
+    }
+
     #[track_caller]
     fn instance(&self) -> &ArcSwap<AuthlyInstance> {
         self.instance.as_ref().expect("TestCtx has no instance")
@@ -296,6 +381,12 @@ impl SetInstance for TestCtx {
     }
 }
 
+impl GetSettings for TestCtx {
+    fn get_settings(&self) -> arc_swap::Guard<Arc<Settings>> {
+        self.settings.load()
+    }
+}
+
 impl GetDecryptedDeks for TestCtx {
     #[track_caller]
     fn get_decrypted_deks(&self) -> arc_swap::Guard<Arc<DecryptedDeks>> {
@@ -345,6 +436,76 @@ impl ServiceBus for TestCtx {
     }
 }
 
+impl GetSessionCache for TestCtx {
+    fn get_session_cache(&self) -> &dyn SessionCache {
+        self.session_cache.as_ref()
+    }
+}
+
+impl GetMetadataCache for TestCtx {
+    fn get_metadata_cache(&self) -> &ServiceMetadataCache {
+        &self.metadata_cache
+    }
+}
+
+impl GetPolicyEngineCache for TestCtx {
+    fn get_policy_engine_cache(&self) -> &PolicyEngineCache {
+        &self.policy_engine_cache
+    }
+}
+
+impl EntityEventBus for TestCtx {
+    fn entity_event_notifier(&self) -> &EntityEventNotifier {
+        &self.entity_event_notifier
+    }
+}
+
+impl GetAuditQueue for TestCtx {
+    fn get_audit_queue(&self) -> &AuditQueue {
+        &self.audit_queue
+    }
+}
+
+impl GetStats for TestCtx {
+    fn get_stats(&self) -> &AuthlyStats {
+        &self.stats
+    }
+
+    async fn raft_role(&self) -> RaftRole {
+        RaftRole::Leader
+    }
+
+    fn is_read_only(&self) -> bool {
+        false
+    }
+
+    fn is_insecure_mode(&self) -> bool {
+        false
+    }
+
+    fn slow_query_count(&self) -> u64 {
+        0
+    }
+}
+
+impl GetClock for TestCtx {
+    fn get_clock(&self) -> &dyn Clock {
+        self.clock.as_ref()
+    }
+}
+
+impl GetClockSkew for TestCtx {
+    fn get_clock_skew(&self) -> &ClockSkewMonitor {
+        &self.clock_skew
+    }
+}
+
+impl GetIdGenerator for TestCtx {
+    fn get_id_generator(&self) -> &dyn IdGenerator {
+        self.id_generator.as_ref()
+    }
+}
+
 impl RedistributeCertificates for TestCtx {
     async fn redistribute_certificates_if_leader(&self) {
         info!("TestCtx redistribute certificates: ignored");
@@ -415,9 +576,19 @@ impl WebAuthn for TestCtx {
     }
 }
 
-async fn sqlite_migrate_naive<T: rust_embed::RustEmbed>(conn: &mut rusqlite::Connection) {
+/// Migration files in the order they are applied, by the numeric prefix of `{id}_{name}.sql`
+fn migration_files<T: rust_embed::RustEmbed>() -> Vec<std::borrow::Cow<'static, str>> {
     let mut files: Vec<_> = T::iter().collect();
-    files.sort();
+    files.sort_by_key(|file| {
+        file.split_once('_')
+            .and_then(|(id, _)| id.parse::<u32>().ok())
+            .unwrap_or(u32::MAX)
+    });
+    files
+}
+
+async fn sqlite_migrate_naive<T: rust_embed::RustEmbed>(conn: &mut rusqlite::Connection) {
+    let files = migration_files::<T>();
 
     let txn = conn.transaction().unwrap();
 
@@ -431,8 +602,7 @@ async fn sqlite_migrate_naive<T: rust_embed::RustEmbed>(conn: &mut rusqlite::Con
 }
 
 async fn sqlite_migrate_persistent<T: rust_embed::RustEmbed>(pool: &SqlitePool) {
-    let mut files: Vec<_> = T::iter().collect();
-    files.sort();
+    let files = migration_files::<T>();
 
     pool.execute(
         indoc! {
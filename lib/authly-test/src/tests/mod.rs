@@ -1,15 +1,65 @@
 mod end2end;
 mod test_access_control;
+mod test_access_control_audit;
+mod test_access_control_quota;
+mod test_admin_events;
+mod test_api_error;
+mod test_attribute_inheritance;
 mod test_authly_connect;
 mod test_authority_mandate;
+mod test_aws_kms;
+mod test_backup;
+mod test_builtin_catalog;
+mod test_clock;
+mod test_clock_skew;
+mod test_compile_source;
+mod test_db_transaction;
+mod test_decision_cache;
+mod test_default_policy_bindings;
 mod test_demo;
+mod test_doc_compiler_properties;
+mod test_doc_diagnostics;
 mod test_docs_clause_examples;
 mod test_docs_full_example;
 mod test_document;
+mod test_document_import;
+mod test_document_interpolation;
+mod test_document_strict;
+mod test_entity_events;
+mod test_external_decision;
+mod test_grpc_health;
+mod test_grpc_peer_auth;
+mod test_hiqlite_leader;
+mod test_id_encoding;
+mod test_id_generator;
+mod test_ident_normalization;
+mod test_instance_signer;
+mod test_k8s_service_account;
+mod test_load_shed;
 mod test_metadata;
+mod test_password_policy;
+mod test_policy_assertions;
+mod test_policy_bindings;
+mod test_policy_opcodes;
+mod test_policy_reload;
+mod test_protocol;
+mod test_readiness;
+mod test_scim;
+mod test_service_cert;
+mod test_service_hosts;
+mod test_service_ping;
+mod test_session;
+mod test_simulate_login;
+mod test_slow_query;
+mod test_stats;
 mod test_tls;
+mod test_token_compaction;
+mod test_token_projection;
+mod test_token_signing;
 mod test_ultradb;
+mod test_username_policy;
 mod test_webauthn;
+mod test_whoami;
 
 #[test]
 fn directory_changed_serde() {
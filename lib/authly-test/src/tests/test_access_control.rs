@@ -1,20 +1,34 @@
+use std::borrow::Cow;
+
 use authly_common::{
-    id::{AttrId, ServiceId},
+    id::{AttrId, Id128DynamicArrayConv, ServiceId},
     policy::{
         code::PolicyValue,
         engine::{AccessControlParams, NoOpPolicyTracer},
     },
+    proto::service::{self as proto, authly_service_client::AuthlyServiceClient},
 };
 use authly_domain::{
+    access_control,
     ctx::GetDb,
-    repo::policy_repo::{self, load_svc_policies_with_bindings},
+    repo::{
+        policy_repo::{self, load_svc_policies_with_bindings},
+        service_repo::{self, PropertyKind},
+    },
+    settings::{Setting, Settings},
+};
+use authly_service::proto::service_server::{
+    AuthlyServiceServerImpl, ACCESS_CONTROL_DETAILS, DECISION_MESSAGE, DECISION_REASON,
+    OPTIONAL_RESOURCE_ATTRIBUTE,
 };
+use fnv::FnvHashSet;
 use hexhex::hex_literal;
-use indoc::indoc;
+use indoc::{formatdoc, indoc};
+use tonic::metadata::MetadataValue;
 
 use crate::{
     test_ctx::TestCtx,
-    util::{compile_and_apply_doc, ServiceProperties},
+    util::{compile_and_apply_doc, tonic_request, ServiceProperties},
 };
 
 const SVC_A: ServiceId =
@@ -244,3 +258,633 @@ async fn test_svc_domain_implied_policies() {
         .unwrap();
     assert_eq!(pol_b.policies.len(), 2);
 }
+
+#[test_log::test(tokio::test)]
+async fn test_policy_binding_coverage() {
+    let ctx = TestCtx::new().inmemory_db().await;
+    let doc = indoc! {
+        r#"
+        [authly-document]
+        id = "bc9ce588-50c3-47d1-94c1-f88b21eaf299"
+
+        [[service-entity]]
+        eid = "s.e5462a0d22b54d9f9ca37bd96e9b9d8b"
+        label = "svc_a"
+
+        [[resource-property]]
+        namespace = "svc_a"
+        label = "verb"
+        attributes = ["read", "write"]
+
+        [[policy]]
+        label = "allow for main service"
+        allow = "Subject.authly:entity == svc_a"
+
+        [[policy-binding]]
+        attributes = ["svc_a:verb:read"]
+        policies = ["allow for main service"]
+        "#
+    };
+    compile_and_apply_doc(doc, &ctx).await.unwrap();
+
+    let coverage = policy_repo::list_svc_resource_coverage(ctx.get_db(), SVC_A)
+        .await
+        .unwrap();
+    let summary: Vec<_> = coverage
+        .iter()
+        .map(|attr| {
+            (
+                attr.property.as_str(),
+                attr.attribute.as_str(),
+                attr.is_covered(),
+            )
+        })
+        .collect();
+
+    assert_eq!(
+        summary,
+        vec![("verb", "read", true), ("verb", "write", false)]
+    );
+}
+
+fn attrs_to_proto<B: From<Vec<u8>>>(attrs: impl IntoIterator<Item = AttrId>) -> Vec<B> {
+    attrs
+        .into_iter()
+        .map(|attr| attr.to_array_dynamic().to_vec().into())
+        .collect()
+}
+
+#[test_log::test(tokio::test)]
+async fn test_access_control_details() {
+    let ctx = TestCtx::new().inmemory_db().await;
+    let doc = indoc! {
+        r#"
+        [authly-document]
+        id = "bc9ce588-50c3-47d1-94c1-f88b21eaf299"
+
+        [[service-entity]]
+        eid = "s.e5462a0d22b54d9f9ca37bd96e9b9d8b"
+        label = "svc_a"
+        attributes = ["authly:role:access_control_details"]
+
+        [[service-entity]]
+        eid = "s.015362d6655447c6b7f44865bd111c70"
+        label = "svc_b"
+
+        [[entity-property]]
+        namespace = "svc_a"
+        label = "trait"
+        attributes = ["has_legs", "has_wings"]
+
+        [[resource-property]]
+        namespace = "svc_a"
+        label = "kind"
+        attributes = ["trousers"]
+
+        [[policy]]
+        label = "allow for legged creatures"
+        allow = "Subject.svc_a:trait == svc_a:trait:has_legs"
+
+        [[policy-binding]]
+        attributes = ["svc_a:kind:trousers"]
+        policies = ["allow for legged creatures"]
+        "#
+    };
+
+    compile_and_apply_doc(doc, &ctx).await.unwrap();
+
+    let props = ServiceProperties::load(SVC_A, ctx.get_db()).await;
+    let mut client = AuthlyServiceClient::new(AuthlyServiceServerImpl::new_service(ctx.clone()));
+
+    let request = |eid: ServiceId, subject: &[(&str, &str, &str)], details: bool| {
+        let mut request = tonic_request(
+            proto::AccessControlRequest {
+                resource_attributes: attrs_to_proto(
+                    props.resource.translate([("svc_a", "kind", "trousers")]),
+                ),
+                peer_entity_attributes: attrs_to_proto(
+                    props.entity.translate(subject.iter().copied()),
+                ),
+                ..Default::default()
+            },
+            eid,
+        );
+        if details {
+            request
+                .metadata_mut()
+                .insert(ACCESS_CONTROL_DETAILS, "1".parse().unwrap());
+        }
+        request
+    };
+
+    let detailed = |response: tonic::Response<proto::AccessControlResponse>| {
+        let reason = response.metadata().get(DECISION_REASON).cloned();
+        let message = response.metadata().get(DECISION_MESSAGE).cloned();
+        (
+            response.into_inner().value,
+            reason.map(|reason| reason.to_str().unwrap().to_string()),
+            message.map(|message| message.to_str().unwrap().to_string()),
+        )
+    };
+
+    let (value, reason, message) = detailed(
+        client
+            .access_control(request(SVC_A, &[("svc_a", "trait", "has_legs")], true))
+            .await
+            .unwrap(),
+    );
+    assert_eq!(value, 1);
+    assert_eq!(reason.as_deref(), Some("allowed"));
+    assert_eq!(message.as_deref(), Some("access allowed by policy"));
+
+    let (value, reason, message) = detailed(
+        client
+            .access_control(request(SVC_A, &[("svc_a", "trait", "has_wings")], true))
+            .await
+            .unwrap(),
+    );
+    assert_eq!(value, 0);
+    assert_eq!(reason.as_deref(), Some("policy_denied"));
+    assert_eq!(message.as_deref(), Some("access denied by policy"));
+
+    let (value, reason, _) = detailed(
+        client
+            .access_control(request(SVC_A, &[], true))
+            .await
+            .unwrap(),
+    );
+    assert_eq!(value, 0);
+    assert_eq!(reason.as_deref(), Some("no_subject"));
+
+    // the plain response carries no details
+    let (value, reason, message) = detailed(
+        client
+            .access_control(request(SVC_A, &[("svc_a", "trait", "has_wings")], false))
+            .await
+            .unwrap(),
+    );
+    assert_eq!(value, 0);
+    assert_eq!(reason, None);
+    assert_eq!(message, None);
+
+    // details require the role
+    let status = client
+        .access_control(request(SVC_B, &[("svc_a", "trait", "has_legs")], true))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::Unauthenticated);
+}
+
+#[test_log::test(tokio::test)]
+async fn test_resolve_peer_entity_attrs() {
+    let ctx = TestCtx::new().inmemory_db().await;
+    let mut doc = indoc! {
+        r#"
+        [authly-document]
+        id = "bc9ce588-50c3-47d1-94c1-f88b21eaf299"
+
+        [[domain]]
+        label = "shared"
+
+        [[entity-property]]
+        namespace = "shared"
+        label = "role"
+        attributes = ["a", "b"]
+        "#
+    }
+    .to_string();
+    let peer_eids: Vec<ServiceId> = (0..12).map(|_| ServiceId::random()).collect();
+    for (idx, eid) in peer_eids.iter().enumerate() {
+        doc.push_str(&formatdoc! {
+            r#"
+            [[service-entity]]
+            eid = "{eid}"
+            label = "svc{idx}"
+
+            [[service-domain]]
+            service = "svc{idx}"
+            domain = "shared"
+
+            [[entity-property]]
+            namespace = "svc{idx}"
+            label = "trait"
+            attributes = ["x", "y"]
+            "#
+        });
+    }
+
+    compile_and_apply_doc(&doc, &ctx).await.unwrap();
+
+    let mut expected = FnvHashSet::default();
+    for eid in &peer_eids {
+        let property_mapping =
+            service_repo::get_service_property_mapping(ctx.get_db(), *eid, PropertyKind::Entity)
+                .await
+                .unwrap();
+        for (_, properties) in property_mapping {
+            for (_, attributes) in properties {
+                expected.extend(attributes.into_iter().map(|(_, attr)| attr));
+            }
+        }
+    }
+    // two shared attributes, and two of each service
+    assert_eq!(expected.len(), 2 + 2 * peer_eids.len());
+
+    let attrs = access_control::resolve_peer_entity_attrs(&ctx, peer_eids)
+        .await
+        .unwrap();
+    assert_eq!(attrs, expected);
+}
+
+#[test_log::test(tokio::test)]
+async fn test_access_control_unknown_resource_attribute() {
+    let ctx = TestCtx::new().inmemory_db().await;
+    let doc = indoc! {
+        r#"
+        [authly-document]
+        id = "bc9ce588-50c3-47d1-94c1-f88b21eaf299"
+
+        [[service-entity]]
+        eid = "s.e5462a0d22b54d9f9ca37bd96e9b9d8b"
+        label = "svc_a"
+
+        [[service-entity]]
+        eid = "s.015362d6655447c6b7f44865bd111c70"
+        label = "svc_b"
+
+        [[entity-property]]
+        namespace = "svc_a"
+        label = "trait"
+        attributes = ["has_legs"]
+
+        [[resource-property]]
+        namespace = "svc_a"
+        label = "kind"
+        attributes = ["trousers", "hat"]
+
+        [[resource-property]]
+        namespace = "svc_b"
+        label = "kind"
+        attributes = ["wings"]
+
+        [[policy]]
+        label = "allow for legged creatures"
+        allow = "Subject.svc_a:trait == svc_a:trait:has_legs"
+
+        [[policy-binding]]
+        attributes = ["svc_a:kind:trousers"]
+        policies = ["allow for legged creatures"]
+        "#
+    };
+
+    compile_and_apply_doc(doc, &ctx).await.unwrap();
+
+    let props = ServiceProperties::load(SVC_A, ctx.get_db()).await;
+    let props_b = ServiceProperties::load(SVC_B, ctx.get_db()).await;
+    let unknown = props_b.resource.translate([("svc_b", "kind", "wings")]);
+    let mut client = AuthlyServiceClient::new(AuthlyServiceServerImpl::new_service(ctx.clone()));
+
+    let request = |required: FnvHashSet<AttrId>, optional: FnvHashSet<AttrId>| {
+        let mut request = tonic_request(
+            proto::AccessControlRequest {
+                resource_attributes: attrs_to_proto(required),
+                peer_entity_attributes: attrs_to_proto(
+                    props.entity.translate([("svc_a", "trait", "has_legs")]),
+                ),
+                ..Default::default()
+            },
+            SVC_A,
+        );
+        for attr in optional {
+            request.metadata_mut().append_bin(
+                OPTIONAL_RESOURCE_ATTRIBUTE,
+                MetadataValue::from_bytes(&attr.to_array_dynamic()),
+            );
+        }
+        request
+    };
+
+    // a required attribute from the namespace of another service is rejected
+    let status = client
+        .access_control(request(
+            props
+                .resource
+                .translate([("svc_a", "kind", "trousers")])
+                .union(&unknown)
+                .copied()
+                .collect(),
+            Default::default(),
+        ))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::PermissionDenied);
+
+    // an unknown required attribute is a client error
+    let status = client
+        .access_control(request(
+            FnvHashSet::from_iter([AttrId::random()]),
+            Default::default(),
+        ))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::InvalidArgument);
+
+    // an unknown optional attribute is ignored
+    let response = client
+        .access_control(request(
+            props.resource.translate([("svc_a", "kind", "trousers")]),
+            unknown.clone(),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.into_inner().value, 1);
+
+    // optional attributes known to the service are used
+    let response = client
+        .access_control(request(
+            Default::default(),
+            props
+                .resource
+                .translate([("svc_a", "kind", "trousers")])
+                .union(&unknown)
+                .copied()
+                .collect(),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.into_inner().value, 1);
+
+    // a valid attribute the resource does not have is policy input, not an error
+    let response = client
+        .access_control(request(
+            props.resource.translate([("svc_a", "kind", "hat")]),
+            Default::default(),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.into_inner().value, 0);
+}
+
+#[test_log::test(tokio::test)]
+async fn test_access_control_resource_namespace_isolation() {
+    let ctx = TestCtx::new().inmemory_db().await;
+    let doc = indoc! {
+        r#"
+        [authly-document]
+        id = "bc9ce588-50c3-47d1-94c1-f88b21eaf299"
+
+        [[domain]]
+        label = "shared"
+
+        [[service-entity]]
+        eid = "s.e5462a0d22b54d9f9ca37bd96e9b9d8b"
+        label = "svc_a"
+
+        [[service-entity]]
+        eid = "s.015362d6655447c6b7f44865bd111c70"
+        label = "svc_b"
+
+        [[service-domain]]
+        service = "svc_a"
+        domain = "shared"
+
+        [[entity-property]]
+        namespace = "svc_a"
+        label = "trait"
+        attributes = ["has_legs"]
+
+        [[resource-property]]
+        namespace = "svc_a"
+        label = "kind"
+        attributes = ["trousers"]
+
+        [[resource-property]]
+        namespace = "shared"
+        label = "kind"
+        attributes = ["document"]
+
+        [[policy]]
+        label = "allow for legged creatures"
+        allow = "Subject.svc_a:trait == svc_a:trait:has_legs"
+
+        [[policy-binding]]
+        attributes = ["svc_a:kind:trousers"]
+        policies = ["allow for legged creatures"]
+        "#
+    };
+
+    compile_and_apply_doc(doc, &ctx).await.unwrap();
+
+    let props = ServiceProperties::load(SVC_A, ctx.get_db()).await;
+    let own = props.resource.translate([("svc_a", "kind", "trousers")]);
+    let granted = props.resource.translate([("shared", "kind", "document")]);
+    let mut client = AuthlyServiceClient::new(AuthlyServiceServerImpl::new_service(ctx.clone()));
+
+    let request = |resource: &FnvHashSet<AttrId>, svc_eid: ServiceId| {
+        tonic_request(
+            proto::AccessControlRequest {
+                resource_attributes: attrs_to_proto(resource.iter().copied()),
+                peer_entity_attributes: attrs_to_proto(
+                    props.entity.translate([("svc_a", "trait", "has_legs")]),
+                ),
+                ..Default::default()
+            },
+            svc_eid,
+        )
+    };
+
+    // attributes of the service's own namespace, and of domains granted to it
+    let response = client.access_control(request(&own, SVC_A)).await.unwrap();
+    assert_eq!(response.into_inner().value, 1);
+    client
+        .access_control(request(&granted, SVC_A))
+        .await
+        .unwrap();
+
+    // svc_b can't submit attributes of svc_a's namespace, nor of a domain it has not been granted
+    for foreign in [&own, &granted] {
+        let status = client
+            .access_control(request(foreign, SVC_B))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::PermissionDenied);
+    }
+}
+
+#[test_log::test(tokio::test)]
+async fn test_access_control_subject_network() {
+    let mut settings = Settings::default();
+    settings
+        .try_set(Setting::TrustedProxies, Cow::Borrowed("10.0.0.0/8"))
+        .unwrap();
+    let ctx = TestCtx::new().inmemory_db().await.with_settings(settings);
+    let doc = indoc! {
+        r#"
+        [authly-document]
+        id = "bc9ce588-50c3-47d1-94c1-f88b21eaf299"
+
+        [[service-entity]]
+        eid = "s.e5462a0d22b54d9f9ca37bd96e9b9d8b"
+        label = "svc_a"
+
+        [[resource-property]]
+        namespace = "svc_a"
+        label = "kind"
+        attributes = ["trousers"]
+
+        [[policy]]
+        label = "allow from the office"
+        allow = "Subject.ip in 192.168.0.0/16 or Subject.ip in fd00::/8"
+
+        [[policy-binding]]
+        attributes = ["svc_a:kind:trousers"]
+        policies = ["allow from the office"]
+        "#
+    };
+
+    compile_and_apply_doc(doc, &ctx).await.unwrap();
+
+    let props = ServiceProperties::load(SVC_A, ctx.get_db()).await;
+    let mut client = AuthlyServiceClient::new(AuthlyServiceServerImpl::new_service(ctx.clone()));
+
+    let request = |forwarded_for: Option<&'static str>| {
+        let mut request = tonic_request(
+            proto::AccessControlRequest {
+                resource_attributes: attrs_to_proto(
+                    props.resource.translate([("svc_a", "kind", "trousers")]),
+                ),
+                ..Default::default()
+            },
+            SVC_A,
+        );
+        if let Some(forwarded_for) = forwarded_for {
+            request
+                .metadata_mut()
+                .insert("x-forwarded-for", MetadataValue::from_static(forwarded_for));
+        }
+        request
+    };
+
+    for (forwarded_for, value) in [
+        (Some("192.168.1.7"), 1),
+        (Some("10.0.0.1"), 0),
+        (Some("fd00::1"), 1),
+        (Some("2001:db8::1"), 0),
+        // behind a trusted proxy
+        (Some("192.168.1.7, 10.0.0.1"), 1),
+        // the subject spoofing its address to an untrusted proxy
+        (Some("192.168.1.7, 172.16.0.1"), 0),
+        // without a forwarded address, the subject is the service at 127.0.0.1
+        (None, 0),
+    ] {
+        let response = client.access_control(request(forwarded_for)).await.unwrap();
+        assert_eq!(response.into_inner().value, value, "{forwarded_for:?}");
+    }
+}
+
+#[test_log::test(tokio::test)]
+async fn test_access_control_attribute_limits() {
+    let mut settings = Settings::default();
+    for (setting, value) in [
+        (Setting::AccessControlMaxSubjectAttributes, "2"),
+        (Setting::AccessControlMaxResourceAttributes, "2"),
+    ] {
+        settings.try_set(setting, Cow::Borrowed(value)).unwrap();
+    }
+    let ctx = TestCtx::new().inmemory_db().await.with_settings(settings);
+    let doc = indoc! {
+        r#"
+        [authly-document]
+        id = "bc9ce588-50c3-47d1-94c1-f88b21eaf299"
+
+        [[service-entity]]
+        eid = "s.e5462a0d22b54d9f9ca37bd96e9b9d8b"
+        label = "svc_a"
+
+        [[entity-property]]
+        namespace = "svc_a"
+        label = "trait"
+        attributes = ["has_legs", "has_arms", "has_wings"]
+
+        [[resource-property]]
+        namespace = "svc_a"
+        label = "kind"
+        attributes = ["trousers", "hat", "gloves"]
+
+        [[policy]]
+        label = "allow for legged creatures"
+        allow = "Subject.svc_a:trait == svc_a:trait:has_legs"
+
+        [[policy-binding]]
+        attributes = ["svc_a:kind:trousers"]
+        policies = ["allow for legged creatures"]
+        "#
+    };
+
+    compile_and_apply_doc(doc, &ctx).await.unwrap();
+
+    let props = ServiceProperties::load(SVC_A, ctx.get_db()).await;
+    let mut client = AuthlyServiceClient::new(AuthlyServiceServerImpl::new_service(ctx.clone()));
+
+    let request = |traits: &[&str], kinds: &[&str], optional_kinds: &[&str]| {
+        let mut request = tonic_request(
+            proto::AccessControlRequest {
+                resource_attributes: attrs_to_proto(
+                    props
+                        .resource
+                        .translate(kinds.iter().map(|kind| ("svc_a", "kind", *kind))),
+                ),
+                peer_entity_attributes: attrs_to_proto(
+                    props
+                        .entity
+                        .translate(traits.iter().map(|t| ("svc_a", "trait", *t))),
+                ),
+                ..Default::default()
+            },
+            SVC_A,
+        );
+        for attr in props
+            .resource
+            .translate(optional_kinds.iter().map(|kind| ("svc_a", "kind", *kind)))
+        {
+            request.metadata_mut().append_bin(
+                OPTIONAL_RESOURCE_ATTRIBUTE,
+                MetadataValue::from_bytes(&attr.to_array_dynamic()),
+            );
+        }
+        request
+    };
+
+    // at the limits
+    let response = client
+        .access_control(request(&["has_legs", "has_arms"], &["trousers"], &["hat"]))
+        .await
+        .unwrap();
+    assert_eq!(response.into_inner().value, 1);
+
+    // over the subject limit
+    let status = client
+        .access_control(request(
+            &["has_legs", "has_arms", "has_wings"],
+            &["trousers"],
+            &[],
+        ))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    assert_eq!(
+        status.message(),
+        "too many subject attributes in request: 3, at most 2 are allowed"
+    );
+
+    // optional resource attributes count towards the resource limit
+    let status = client
+        .access_control(request(&["has_legs"], &["trousers", "hat"], &["gloves"]))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    assert_eq!(
+        status.message(),
+        "too many resource attributes in request: 3, at most 2 are allowed"
+    );
+}
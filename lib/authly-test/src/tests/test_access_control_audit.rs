@@ -0,0 +1,539 @@
+use std::borrow::Cow;
+
+use authly_common::{
+    id::{AttrId, Id128DynamicArrayConv, PersonaId, ServiceId},
+    proto::service::{self as proto, authly_service_client::AuthlyServiceClient},
+};
+use authly_db::{params, Db};
+use authly_domain::{
+    audit::{self, AuditChainError, AuditExportFormat},
+    bus::ClusterMessage,
+    ctx::GetDb,
+    repo::{access_control_audit_repo::DbAccessControlAudit, admin_event_repo, policy_repo},
+    settings::{Setting, Settings},
+    IsLeaderDb,
+};
+use authly_service::proto::service_server::AuthlyServiceServerImpl;
+use hexhex::hex_literal;
+use indoc::indoc;
+use time::{Duration, OffsetDateTime};
+
+use crate::{
+    test_ctx::TestCtx,
+    util::{compile_and_apply_doc, tonic_request, ServiceProperties},
+};
+
+const SVC: ServiceId = ServiceId::from_raw_array(hex_literal!("e5462a0d22b54d9f9ca37bd96e9b9d8b"));
+
+const DOC: &str = indoc! {
+    r#"
+    [authly-document]
+    id = "bc9ce588-50c3-47d1-94c1-f88b21eaf299"
+
+    [[service-entity]]
+    eid = "s.e5462a0d22b54d9f9ca37bd96e9b9d8b"
+    label = "svc"
+
+    [[entity-property]]
+    namespace = "svc"
+    label = "trait"
+    attributes = ["has_legs", "has_wings"]
+
+    [[resource-property]]
+    namespace = "svc"
+    label = "kind"
+    attributes = ["trousers"]
+
+    [[policy]]
+    label = "allow for legged creatures"
+    allow = "Subject.svc:trait == svc:trait:has_legs"
+
+    [[policy]]
+    label = "deny for winged creatures"
+    deny = "Subject.svc:trait == svc:trait:has_wings"
+
+    [[policy-binding]]
+    attributes = ["svc:kind:trousers"]
+    policies = ["allow for legged creatures", "deny for winged creatures"]
+    "#
+};
+
+/// Ask for trousers on behalf of a subject with the given trait, returning the decision value
+async fn access_control(ctx: &TestCtx, subject_trait: &str) -> i32 {
+    let props = ServiceProperties::load(SVC, ctx.get_db()).await;
+
+    AuthlyServiceClient::new(AuthlyServiceServerImpl::new_service(ctx.clone()))
+        .access_control(tonic_request(
+            proto::AccessControlRequest {
+                resource_attributes: attrs_to_proto(
+                    props.resource.translate([("svc", "kind", "trousers")]),
+                ),
+                peer_entity_attributes: attrs_to_proto(props.entity.translate([(
+                    "svc",
+                    "trait",
+                    subject_trait,
+                )])),
+                ..Default::default()
+            },
+            SVC,
+        ))
+        .await
+        .unwrap()
+        .into_inner()
+        .value
+}
+
+fn attrs_to_proto<B: From<Vec<u8>>>(attrs: impl IntoIterator<Item = AttrId>) -> Vec<B> {
+    attrs
+        .into_iter()
+        .map(|attr| attr.to_array_dynamic().to_vec().into())
+        .collect()
+}
+
+async fn audit_settings(value: &'static str) -> TestCtx {
+    let mut settings = Settings::default();
+    settings
+        .try_set(Setting::AccessControlAudit, Cow::Borrowed(value))
+        .unwrap();
+
+    let ctx = TestCtx::new()
+        .inmemory_db()
+        .await
+        .supreme_instance()
+        .await
+        .with_settings(settings);
+    compile_and_apply_doc(DOC, &ctx).await.unwrap();
+    ctx
+}
+
+#[test_log::test(tokio::test)]
+async fn test_denied_decision_is_audited() {
+    let ctx = audit_settings("denied").await;
+
+    assert_eq!(access_control(&ctx, "has_legs").await, 1);
+    assert_eq!(access_control(&ctx, "has_wings").await, 0);
+    ctx.flush_audit().await;
+
+    // only the denial is recorded
+    let records = DbAccessControlAudit::query_recent(ctx.get_db(), Some(SVC), 10)
+        .await
+        .unwrap();
+    assert_eq!(records.len(), 1);
+
+    let record = &records[0];
+    assert!(!record.allowed);
+    assert_eq!(record.reason, "policy_denied");
+    assert_eq!(record.subject_eid, None);
+    assert_eq!(record.resource_attrs.len(), 1);
+
+    let labels = policy_repo::list_policy_labels(ctx.get_db(), record.policy_id)
+        .await
+        .unwrap();
+    assert_eq!(
+        labels.into_values().collect::<Vec<_>>(),
+        vec!["deny for winged creatures".to_string()]
+    );
+}
+
+#[test_log::test(tokio::test)]
+async fn test_all_decisions_audited() {
+    let ctx = audit_settings("all").await;
+
+    assert_eq!(access_control(&ctx, "has_legs").await, 1);
+    ctx.flush_audit().await;
+
+    let records = DbAccessControlAudit::query_recent(ctx.get_db(), None, 10)
+        .await
+        .unwrap();
+    assert_eq!(records.len(), 1);
+    assert!(records[0].allowed);
+    assert_eq!(records[0].reason, "allowed");
+    assert!(records[0].policy_id.is_some());
+}
+
+#[test_log::test(tokio::test)]
+async fn test_no_audit_by_default() {
+    let ctx = TestCtx::new().inmemory_db().await.supreme_instance().await;
+    compile_and_apply_doc(DOC, &ctx).await.unwrap();
+
+    assert_eq!(access_control(&ctx, "has_legs").await, 1);
+    assert_eq!(access_control(&ctx, "has_wings").await, 0);
+    ctx.flush_audit().await;
+
+    let records = DbAccessControlAudit::query_recent(ctx.get_db(), None, 10)
+        .await
+        .unwrap();
+    assert!(records.is_empty());
+}
+
+#[test_log::test(tokio::test)]
+async fn test_audit_sample_rate() {
+    let mut settings = Settings::default();
+    settings
+        .try_set(Setting::AccessControlAudit, Cow::Borrowed("denied"))
+        .unwrap();
+    settings
+        .try_set(Setting::AccessControlAuditSampleRate, Cow::Borrowed("0"))
+        .unwrap();
+    assert!(settings
+        .try_set(Setting::AccessControlAuditSampleRate, Cow::Borrowed("1.5"))
+        .is_err());
+
+    let ctx = TestCtx::new()
+        .inmemory_db()
+        .await
+        .supreme_instance()
+        .await
+        .with_settings(settings);
+    compile_and_apply_doc(DOC, &ctx).await.unwrap();
+
+    assert_eq!(access_control(&ctx, "has_wings").await, 0);
+    ctx.flush_audit().await;
+
+    let records = DbAccessControlAudit::query_recent(ctx.get_db(), None, 10)
+        .await
+        .unwrap();
+    assert!(records.is_empty());
+}
+
+fn record(created_at: OffsetDateTime, reason: &str) -> DbAccessControlAudit {
+    DbAccessControlAudit {
+        id: 0,
+        created_at,
+        svc_eid: SVC,
+        subject_eid: Some(
+            PersonaId::from_raw_array(hex_literal!("0fbcd73e1a884424a1615c3c3fdeebec")).upcast(),
+        ),
+        resource_attrs: vec![
+            AttrId::from_raw_array(hex_literal!("8b1e3a0f2c7d4e5f9a6b1c2d3e4f5a6b")),
+            AttrId::from_raw_array(hex_literal!("9c2f4b1a3d8e4f6a0b7c2d3e4f5a6b7c")),
+        ],
+        allowed: false,
+        reason: reason.to_string(),
+        policy_id: None,
+        prev_hash: vec![],
+        hash: vec![],
+    }
+}
+
+/// Append a record to the chain, keyed by the audit chain key of the test instance
+async fn append(ctx: &TestCtx, record: DbAccessControlAudit) {
+    let deks = ctx.get_decrypted_deks();
+    record
+        .append(ctx.get_db(), audit::audit_chain_key(&deks).unwrap())
+        .await
+        .unwrap();
+}
+
+#[test_log::test(tokio::test)]
+async fn test_audit_retention_purge() {
+    let mut settings = Settings::default();
+    settings
+        .try_set(Setting::AuditRetention, Cow::Borrowed("30 days"))
+        .unwrap();
+    let ctx = TestCtx::new()
+        .inmemory_db()
+        .await
+        .with_db_instance()
+        .await
+        .with_settings(settings);
+
+    let now = OffsetDateTime::now_utc();
+    append(&ctx, record(now - Duration::days(31), "policy_denied")).await;
+    append(&ctx, record(now - Duration::days(1), "no_subject")).await;
+
+    // followers leave purging to the leader
+    assert_eq!(
+        audit::purge_expired_audit(&ctx, IsLeaderDb(false), now)
+            .await
+            .unwrap(),
+        0
+    );
+
+    assert_eq!(
+        audit::purge_expired_audit(&ctx, IsLeaderDb(true), now)
+            .await
+            .unwrap(),
+        1
+    );
+
+    let records = DbAccessControlAudit::query_recent(ctx.get_db(), None, 10)
+        .await
+        .unwrap();
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].reason, "no_subject");
+
+    // the admin events of the purged records are purged too
+    let events = admin_event_repo::list_admin_events(ctx.get_db(), 0, 10)
+        .await
+        .unwrap();
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].created_at, records[0].created_at);
+}
+
+#[test_log::test(tokio::test)]
+async fn test_audit_kept_forever_by_default() {
+    let ctx = TestCtx::new().inmemory_db().await.with_db_instance().await;
+
+    let now = OffsetDateTime::now_utc();
+    append(&ctx, record(now - Duration::days(10000), "policy_denied")).await;
+
+    assert_eq!(
+        audit::purge_expired_audit(&ctx, IsLeaderDb(true), now)
+            .await
+            .unwrap(),
+        0
+    );
+}
+
+#[test_log::test(tokio::test)]
+async fn test_audit_export() {
+    let ctx = TestCtx::new().inmemory_db().await.with_db_instance().await;
+
+    let created_at = OffsetDateTime::from_unix_timestamp(1_700_000_000).unwrap();
+    append(&ctx, record(created_at, "policy_denied")).await;
+    append(&ctx, record(created_at, "no_resource")).await;
+
+    let mut jsonl = vec![];
+    let count =
+        audit::export_access_control_audit(ctx.get_db(), AuditExportFormat::Jsonl, &mut jsonl)
+            .await
+            .unwrap();
+    assert_eq!(count, 2);
+
+    let lines: Vec<serde_json::Value> = String::from_utf8(jsonl)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0]["reason"], "policy_denied");
+    assert_eq!(lines[1]["reason"], "no_resource");
+    assert_eq!(lines[0]["createdAt"], "2023-11-14T22:13:20Z");
+    assert_eq!(lines[0]["allowed"], false);
+    assert_eq!(lines[0]["serviceId"], serde_json::to_value(SVC).unwrap());
+    assert_eq!(lines[0]["resourceAttributes"].as_array().unwrap().len(), 2);
+    assert_eq!(lines[0]["policyId"], serde_json::Value::Null);
+
+    let mut csv = vec![];
+    audit::export_access_control_audit(ctx.get_db(), AuditExportFormat::Csv, &mut csv)
+        .await
+        .unwrap();
+
+    let csv = String::from_utf8(csv).unwrap();
+    let rows: Vec<Vec<&str>> = csv.lines().map(|line| line.split(',').collect()).collect();
+    assert_eq!(
+        rows[0],
+        vec![
+            "id",
+            "created_at",
+            "service_id",
+            "subject_id",
+            "resource_attributes",
+            "allowed",
+            "reason",
+            "policy_id"
+        ]
+    );
+    assert_eq!(rows.len(), 3);
+
+    let row = &rows[1];
+    assert_eq!(row[1], "2023-11-14T22:13:20Z");
+    assert_eq!(row[2], SVC.to_string());
+    assert_eq!(row[4].split(' ').count(), 2);
+    assert_eq!(row[5], "false");
+    assert_eq!(row[6], "policy_denied");
+    assert_eq!(row[7], "");
+    assert_eq!(rows[2][6], "no_resource");
+}
+
+/// Append three chained records, returning their IDs
+async fn append_chain(ctx: &TestCtx) -> Vec<i64> {
+    let now = OffsetDateTime::now_utc();
+    for reason in ["policy_denied", "no_subject", "no_resource"] {
+        append(ctx, record(now, reason)).await;
+    }
+
+    DbAccessControlAudit::query_after(ctx.get_db(), 0, 10)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|record| record.id)
+        .collect()
+}
+
+#[test_log::test(tokio::test)]
+async fn test_audit_chain_verifies() {
+    let ctx = TestCtx::new().inmemory_db().await.with_db_instance().await;
+    let ids = append_chain(&ctx).await;
+
+    let records = DbAccessControlAudit::query_after(ctx.get_db(), 0, 10)
+        .await
+        .unwrap();
+    assert!(records[0].prev_hash.is_empty());
+    assert_eq!(records[1].prev_hash, records[0].hash);
+    assert_eq!(records[2].prev_hash, records[1].hash);
+
+    assert_eq!(
+        audit::verify_access_control_audit_chain(ctx.get_db(), &ctx.get_decrypted_deks())
+            .await
+            .unwrap(),
+        ids.len()
+    );
+}
+
+#[test_log::test(tokio::test)]
+async fn test_audit_chain_detects_modified_record() {
+    let ctx = TestCtx::new().inmemory_db().await.with_db_instance().await;
+    let ids = append_chain(&ctx).await;
+
+    ctx.get_db()
+        .execute(
+            "UPDATE access_control_audit SET reason = 'allowed' WHERE id = $1".into(),
+            params!(ids[1]),
+        )
+        .await
+        .unwrap();
+
+    let err = audit::verify_access_control_audit_chain(ctx.get_db(), &ctx.get_decrypted_deks())
+        .await
+        .unwrap_err();
+    assert!(matches!(err, AuditChainError::Modified(id) if id == ids[1]));
+}
+
+#[test_log::test(tokio::test)]
+async fn test_audit_chain_detects_removed_record() {
+    let ctx = TestCtx::new().inmemory_db().await.with_db_instance().await;
+    let ids = append_chain(&ctx).await;
+
+    ctx.get_db()
+        .execute(
+            "DELETE FROM access_control_audit WHERE id = $1".into(),
+            params!(ids[1]),
+        )
+        .await
+        .unwrap();
+
+    let err = audit::verify_access_control_audit_chain(ctx.get_db(), &ctx.get_decrypted_deks())
+        .await
+        .unwrap_err();
+    assert!(matches!(err, AuditChainError::Unlinked(id) if id == ids[2]));
+}
+
+#[test_log::test(tokio::test)]
+async fn test_audit_chain_survives_purge() {
+    let mut settings = Settings::default();
+    settings
+        .try_set(Setting::AuditRetention, Cow::Borrowed("30 days"))
+        .unwrap();
+    let ctx = TestCtx::new()
+        .inmemory_db()
+        .await
+        .with_db_instance()
+        .await
+        .with_settings(settings);
+
+    let now = OffsetDateTime::now_utc();
+    append(&ctx, record(now - Duration::days(31), "policy_denied")).await;
+    append(&ctx, record(now, "no_subject")).await;
+    audit::purge_expired_audit(&ctx, IsLeaderDb(true), now)
+        .await
+        .unwrap();
+
+    assert_eq!(
+        audit::verify_access_control_audit_chain(ctx.get_db(), &ctx.get_decrypted_deks())
+            .await
+            .unwrap(),
+        1
+    );
+}
+
+#[test_log::test(tokio::test)]
+async fn test_queued_decisions_are_appended_in_one_batch() {
+    let ctx = audit_settings("all").await;
+
+    for _ in 0..3 {
+        assert_eq!(access_control(&ctx, "has_legs").await, 1);
+    }
+
+    // decisions are only queued on the request path
+    let records = DbAccessControlAudit::query_recent(ctx.get_db(), None, 10)
+        .await
+        .unwrap();
+    assert!(records.is_empty());
+
+    ctx.clear_cluster_message_log();
+    ctx.flush_audit().await;
+
+    let records = DbAccessControlAudit::query_recent(ctx.get_db(), None, 10)
+        .await
+        .unwrap();
+    assert_eq!(records.len(), 3);
+
+    let announcements = ctx
+        .clone_cluster_message_log()
+        .into_iter()
+        .filter(|message| matches!(message, ClusterMessage::EventsAppended))
+        .count();
+    assert_eq!(announcements, 1);
+}
+
+#[test_log::test(tokio::test)]
+async fn test_unqueued_decision_is_denied() {
+    let mut settings = Settings::default();
+    settings
+        .try_set(Setting::AccessControlAudit, Cow::Borrowed("all"))
+        .unwrap();
+    let ctx = TestCtx::new()
+        .inmemory_db()
+        .await
+        .supreme_instance()
+        .await
+        .with_settings(settings)
+        .with_audit_queue_capacity(1);
+    compile_and_apply_doc(DOC, &ctx).await.unwrap();
+
+    assert_eq!(access_control(&ctx, "has_legs").await, 1);
+
+    // the queue is full, so access is not allowed without an audit record
+    assert_eq!(access_control(&ctx, "has_legs").await, 0);
+
+    ctx.flush_audit().await;
+    assert_eq!(access_control(&ctx, "has_legs").await, 1);
+}
+
+#[test_log::test(tokio::test)]
+async fn test_audit_chain_detects_rehashed_record() {
+    let ctx = TestCtx::new().inmemory_db().await.with_db_instance().await;
+    let ids = append_chain(&ctx).await;
+
+    // a record modified and rehashed without the chain key does not verify
+    let mut records = DbAccessControlAudit::query_after(ctx.get_db(), 0, 10)
+        .await
+        .unwrap();
+    let mut modified = records.remove(1);
+    modified.reason = "allowed".to_string();
+    let other_deks = TestCtx::new()
+        .inmemory_db()
+        .await
+        .with_db_instance()
+        .await
+        .get_decrypted_deks();
+    let forged_hash = modified.chain_hash(
+        audit::audit_chain_key(&other_deks).unwrap(),
+        &modified.prev_hash,
+    );
+
+    ctx.get_db()
+        .execute(
+            "UPDATE access_control_audit SET reason = 'allowed', hash = $2 WHERE id = $1".into(),
+            params!(ids[1], forged_hash.to_vec()),
+        )
+        .await
+        .unwrap();
+
+    let err = audit::verify_access_control_audit_chain(ctx.get_db(), &ctx.get_decrypted_deks())
+        .await
+        .unwrap_err();
+    assert!(matches!(err, AuditChainError::Modified(id) if id == ids[1]));
+}
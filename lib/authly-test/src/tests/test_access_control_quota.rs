@@ -0,0 +1,251 @@
+use std::borrow::Cow;
+
+use authly_common::{
+    id::{EntityId, PersonaId, ServiceId},
+    policy::{code::PolicyValue, engine::AccessControlParams},
+};
+use authly_domain::{
+    access_control::{self, DecisionReason},
+    ctx::GetDb,
+    id::BuiltinProp,
+    repo::policy_repo,
+    settings::{Setting, Settings},
+};
+use hexhex::hex_literal;
+use indoc::indoc;
+use time::{Duration, OffsetDateTime};
+
+use crate::{
+    test_ctx::TestCtx,
+    util::{compile_and_apply_doc, ServiceProperties},
+};
+
+const SVC: ServiceId = ServiceId::from_raw_array(hex_literal!("e5462a0d22b54d9f9ca37bd96e9b9d8b"));
+const ME: PersonaId = PersonaId::from_raw_array(hex_literal!("0fbcd73e1a884424a1615c3c3fdeebec"));
+const YOU: PersonaId = PersonaId::from_raw_array(hex_literal!("8a1f3cf2a1e54ae8b1e4e4a3d1c3f0a7"));
+
+const DOC: &str = indoc! {
+    r#"
+    [authly-document]
+    id = "bc9ce588-50c3-47d1-94c1-f88b21eaf299"
+
+    [[service-entity]]
+    eid = "s.e5462a0d22b54d9f9ca37bd96e9b9d8b"
+    label = "svc"
+
+    [[entity-property]]
+    namespace = "svc"
+    label = "role"
+    attributes = ["deployer", "intern"]
+
+    [[resource-property]]
+    namespace = "svc"
+    label = "action"
+    attributes = ["deploy", "inspect"]
+
+    [[policy]]
+    label = "allow for deployers"
+    allow = "Subject.svc:role == svc:role:deployer"
+
+    [[policy-binding]]
+    attributes = ["svc:action:deploy"]
+    policies = ["allow for deployers"]
+
+    [[policy-binding]]
+    attributes = ["svc:action:inspect"]
+    policies = ["allow for deployers"]
+    "#
+};
+
+/// A fixed point in time, at the start of a quota window
+fn t0() -> OffsetDateTime {
+    OffsetDateTime::from_unix_timestamp(1_700_006_400).unwrap()
+}
+
+async fn quota_ctx(quota: &str) -> TestCtx {
+    let mut settings = Settings::default();
+    settings
+        .try_set(Setting::AccessControlQuota, Cow::Borrowed(quota))
+        .unwrap();
+
+    let ctx = TestCtx::new().inmemory_db().await.with_settings(settings);
+    compile_and_apply_doc(DOC, &ctx).await.unwrap();
+    ctx
+}
+
+/// Evaluate an access control request for an action with quotas enforced, as of `now`, returning whether it was allowed
+async fn access_control(
+    ctx: &TestCtx,
+    subject: EntityId,
+    role: &str,
+    action: &str,
+    now: OffsetDateTime,
+) -> (bool, DecisionReason) {
+    access_control_actions(ctx, subject, role, &[action], now).await
+}
+
+/// Evaluate an access control request for a resource with several actions
+async fn access_control_actions(
+    ctx: &TestCtx,
+    subject: EntityId,
+    role: &str,
+    actions: &[&str],
+    now: OffsetDateTime,
+) -> (bool, DecisionReason) {
+    let props = ServiceProperties::load(SVC, ctx.get_db()).await;
+    let mut params = AccessControlParams {
+        resource_attrs: props
+            .resource
+            .translate(actions.iter().map(|action| ("svc", "action", *action))),
+        subject_attrs: props.entity.translate([("svc", "role", role)]),
+        ..Default::default()
+    };
+    params
+        .subject_eids
+        .insert(BuiltinProp::Entity.into(), subject);
+
+    let engine = policy_repo::load_svc_policy_engine(ctx.get_db(), SVC)
+        .await
+        .unwrap();
+    let decision = access_control::evaluate(&engine, &params);
+    let decision = access_control::enforce_quotas(ctx, &params, decision, now)
+        .await
+        .unwrap();
+
+    (
+        matches!(decision.value, PolicyValue::Allow),
+        decision.reason,
+    )
+}
+
+#[test_log::test(tokio::test)]
+async fn test_quota_exceeded_and_reset() {
+    let ctx = quota_ctx("svc:action:deploy=3/1h").await;
+    let me = ME.upcast();
+
+    for minute in 0..3 {
+        assert_eq!(
+            access_control(
+                &ctx,
+                me,
+                "deployer",
+                "deploy",
+                t0() + Duration::minutes(minute)
+            )
+            .await,
+            (true, DecisionReason::Allowed)
+        );
+    }
+
+    assert_eq!(
+        access_control(&ctx, me, "deployer", "deploy", t0() + Duration::minutes(10)).await,
+        (false, DecisionReason::QuotaExceeded)
+    );
+
+    // other resources and other subjects are unaffected
+    assert_eq!(
+        access_control(
+            &ctx,
+            me,
+            "deployer",
+            "inspect",
+            t0() + Duration::minutes(10)
+        )
+        .await,
+        (true, DecisionReason::Allowed)
+    );
+    assert_eq!(
+        access_control(&ctx, YOU.upcast(), "deployer", "deploy", t0()).await,
+        (true, DecisionReason::Allowed)
+    );
+
+    // the quota resets in the next window
+    assert_eq!(
+        access_control(&ctx, me, "deployer", "deploy", t0() + Duration::hours(1)).await,
+        (true, DecisionReason::Allowed)
+    );
+}
+
+#[test_log::test(tokio::test)]
+async fn test_quota_not_consumed_when_denied() {
+    let ctx = quota_ctx("svc:action:deploy=1/1d").await;
+    let me = ME.upcast();
+
+    for _ in 0..3 {
+        assert_eq!(
+            access_control(&ctx, me, "intern", "deploy", t0()).await,
+            (false, DecisionReason::PolicyDenied)
+        );
+    }
+
+    assert_eq!(
+        access_control(&ctx, me, "deployer", "deploy", t0()).await,
+        (true, DecisionReason::Allowed)
+    );
+    assert_eq!(
+        access_control(&ctx, me, "deployer", "deploy", t0()).await,
+        (false, DecisionReason::QuotaExceeded)
+    );
+}
+
+#[test_log::test(tokio::test)]
+async fn test_exceeded_quota_consumes_no_other_quota() {
+    let ctx = quota_ctx("svc:action:deploy=1/1d, svc:action:inspect=2/1d").await;
+    let me = ME.upcast();
+
+    assert_eq!(
+        access_control(&ctx, me, "deployer", "deploy", t0()).await,
+        (true, DecisionReason::Allowed)
+    );
+
+    // the deploy quota is used up, so the inspect quota is left untouched
+    for _ in 0..3 {
+        assert_eq!(
+            access_control_actions(&ctx, me, "deployer", &["inspect", "deploy"], t0()).await,
+            (false, DecisionReason::QuotaExceeded)
+        );
+    }
+
+    for _ in 0..2 {
+        assert_eq!(
+            access_control(&ctx, me, "deployer", "inspect", t0()).await,
+            (true, DecisionReason::Allowed)
+        );
+    }
+    assert_eq!(
+        access_control(&ctx, me, "deployer", "inspect", t0()).await,
+        (false, DecisionReason::QuotaExceeded)
+    );
+}
+
+#[test]
+fn test_quota_setting() {
+    let mut settings = Settings::default();
+
+    for invalid in [
+        "svc:action=3/1h",
+        "svc:action:deploy=3",
+        "svc:action:deploy=many/1h",
+        "svc:action:deploy=3/0s",
+    ] {
+        assert!(
+            settings
+                .try_set(Setting::AccessControlQuota, Cow::Borrowed(invalid))
+                .is_err(),
+            "{invalid}"
+        );
+    }
+
+    settings
+        .try_set(
+            Setting::AccessControlQuota,
+            Cow::Borrowed("svc:action:deploy=100/1d, svc:action:inspect=10/1m"),
+        )
+        .unwrap();
+    assert_eq!(settings.access_control_quotas.len(), 2);
+    assert_eq!(settings.access_control_quotas[0].limit, 100);
+    assert_eq!(
+        settings.access_control_quotas[1].window,
+        std::time::Duration::from_secs(60)
+    );
+}
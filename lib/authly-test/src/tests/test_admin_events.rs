@@ -0,0 +1,108 @@
+use authly_common::id::ServiceId;
+use authly_domain::bus::admin_events::{record_admin_event, AdminEventKind};
+use authly_service::proto::admin_server::{
+    proto::{self, authly_admin_client::AuthlyAdminClient},
+    AuthlyAdminServerImpl,
+};
+use hexhex::hex_literal;
+use indoc::indoc;
+use serde_json::{json, Value};
+use tonic::Code;
+
+use crate::{
+    test_ctx::TestCtx,
+    util::{compile_and_apply_doc, tonic_request},
+};
+
+const SVC_ADMIN: ServiceId =
+    ServiceId::from_raw_array(hex_literal!("e5462a0d22b54d9f9ca37bd96e9b9d8b"));
+const SVC_B: ServiceId =
+    ServiceId::from_raw_array(hex_literal!("015362d6655447c6b7f44865bd111c70"));
+
+const DOC: &str = indoc! {
+    r#"
+    [authly-document]
+    id = "bc9ce588-50c3-47d1-94c1-f88b21eaf299"
+
+    [[service-entity]]
+    eid = "s.e5462a0d22b54d9f9ca37bd96e9b9d8b"
+    label = "svc_admin"
+    attributes = ["authly:role:admin"]
+
+    [[service-entity]]
+    eid = "s.015362d6655447c6b7f44865bd111c70"
+    label = "svc_b"
+    "#
+};
+
+fn detail(event: &proto::AdminEvent) -> Value {
+    serde_json::from_str(&event.detail_json).unwrap()
+}
+
+#[test_log::test(tokio::test)]
+async fn test_tail_events() {
+    let ctx = TestCtx::new().inmemory_db().await.supreme_instance().await;
+    compile_and_apply_doc(DOC, &ctx).await.unwrap();
+
+    let mut client = AuthlyAdminClient::new(AuthlyAdminServerImpl::new_service(ctx.clone()));
+    let mut stream = client
+        .tail_events(tonic_request(
+            proto::TailEventsRequest { after: 0 },
+            SVC_ADMIN,
+        ))
+        .await
+        .unwrap()
+        .into_inner();
+
+    // events written before subscribing are replayed
+    let applied = stream.message().await.unwrap().unwrap();
+    assert_eq!(applied.kind, "directory_changed");
+    assert!(detail(&applied)["dirId"].is_string());
+
+    // new events are received as they are written
+    record_admin_event(
+        &ctx,
+        AdminEventKind::CertificateIssued,
+        json!({ "serviceId": SVC_B }),
+    )
+    .await
+    .unwrap();
+
+    let issued = stream.message().await.unwrap().unwrap();
+    assert_eq!(issued.kind, "certificate_issued");
+    assert_eq!(detail(&issued), json!({ "serviceId": SVC_B }));
+    assert!(issued.seq > applied.seq);
+    drop(stream);
+
+    // events written while away are received when resuming from the cursor
+    record_admin_event(&ctx, AdminEventKind::LeaderChanged, json!({ "nodeId": 1 }))
+        .await
+        .unwrap();
+
+    let mut stream = client
+        .tail_events(tonic_request(
+            proto::TailEventsRequest { after: issued.seq },
+            SVC_ADMIN,
+        ))
+        .await
+        .unwrap()
+        .into_inner();
+
+    let leader = stream.message().await.unwrap().unwrap();
+    assert_eq!(leader.kind, "leader_changed");
+    assert_eq!(detail(&leader), json!({ "nodeId": 1 }));
+}
+
+#[test_log::test(tokio::test)]
+async fn test_tail_events_requires_admin_role() {
+    let ctx = TestCtx::new().inmemory_db().await.supreme_instance().await;
+    compile_and_apply_doc(DOC, &ctx).await.unwrap();
+
+    let mut client = AuthlyAdminClient::new(AuthlyAdminServerImpl::new_service(ctx.clone()));
+    let status = client
+        .tail_events(tonic_request(proto::TailEventsRequest { after: 0 }, SVC_B))
+        .await
+        .unwrap_err();
+
+    assert_eq!(status.code(), Code::PermissionDenied);
+}
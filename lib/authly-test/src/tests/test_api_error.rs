@@ -0,0 +1,170 @@
+use authly_common::{
+    id::{PersonaId, ServiceId},
+    mtls_server::PeerServiceEntity,
+};
+use authly_domain::{
+    access_token::create_access_token,
+    ctx::GetInstance,
+    id::BuiltinAttr,
+    request_id::request_id_middleware,
+    session::{init_session, AuthClass, SessionKind},
+};
+use axum::Extension;
+use http::StatusCode;
+use serde_json::{json, Value};
+use time::OffsetDateTime;
+
+use crate::test_ctx::TestCtx;
+
+const SVC: ServiceId = ServiceId::from_raw_array([7; 16]);
+
+/// Serve the HTTP API on a local port, as if called by `SVC`
+async fn spawn_api(ctx: &TestCtx) -> String {
+    let router = authly_service::openapi::router::router()
+        .layer(Extension(PeerServiceEntity(SVC)))
+        .layer(axum::middleware::from_fn(request_id_middleware))
+        .with_state(ctx.clone());
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let base_url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, router).await });
+
+    base_url
+}
+
+async fn access_token(ctx: &TestCtx, roles: &[BuiltinAttr]) -> String {
+    let session = init_session(
+        ctx,
+        PersonaId::random().upcast(),
+        SessionKind::Default,
+        AuthClass::Password,
+    )
+    .await
+    .unwrap();
+
+    create_access_token(
+        &session,
+        roles.iter().map(|role| (*role).into()).collect(),
+        &ctx.get_instance(),
+        OffsetDateTime::now_utc(),
+    )
+    .await
+    .unwrap()
+}
+
+/// Check that the response is an error envelope with the given code, returning the body
+async fn assert_error(response: reqwest::Response, status: StatusCode, code: &str) -> Value {
+    assert_eq!(response.status(), status);
+    let header_request_id = response.headers()["x-request-id"]
+        .to_str()
+        .unwrap()
+        .to_string();
+
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["code"], json!(code));
+    assert!(body["message"].as_str().is_some_and(|msg| !msg.is_empty()));
+    assert_eq!(body["request_id"], json!(header_request_id));
+
+    body
+}
+
+#[test_log::test(tokio::test)]
+async fn test_api_error_envelope() {
+    let ctx = TestCtx::new().inmemory_db().await.supreme_instance().await;
+    let base_url = spawn_api(&ctx).await;
+    let client = reqwest::Client::new();
+
+    // auth
+    assert_error(
+        client
+            .post(format!("{base_url}/api/auth/authenticate"))
+            .json(&json!({ "username": "nobody", "password": "secret" }))
+            .send()
+            .await
+            .unwrap(),
+        StatusCode::UNAUTHORIZED,
+        "unauthorized",
+    )
+    .await;
+
+    // admin, without and with insufficient privileges
+    assert_error(
+        client
+            .get(format!("{base_url}/api/admin/stats"))
+            .send()
+            .await
+            .unwrap(),
+        StatusCode::UNAUTHORIZED,
+        "unauthorized",
+    )
+    .await;
+    assert_error(
+        client
+            .get(format!("{base_url}/api/admin/stats"))
+            .bearer_auth(access_token(&ctx, &[]).await)
+            .send()
+            .await
+            .unwrap(),
+        StatusCode::FORBIDDEN,
+        "forbidden",
+    )
+    .await;
+
+    // invalid input
+    assert_error(
+        client
+            .post(format!("{base_url}/api/admin/document"))
+            .bearer_auth(access_token(&ctx, &[BuiltinAttr::AuthlyRoleApplyDocument]).await)
+            .body("not [toml")
+            .send()
+            .await
+            .unwrap(),
+        StatusCode::UNPROCESSABLE_ENTITY,
+        "invalid_request",
+    )
+    .await;
+}
+
+#[test_log::test(tokio::test)]
+async fn test_api_error_request_id() {
+    let ctx = TestCtx::new().inmemory_db().await.supreme_instance().await;
+    let base_url = spawn_api(&ctx).await;
+    let client = reqwest::Client::new();
+
+    // a request ID from the caller is kept
+    let body = assert_error(
+        client
+            .get(format!("{base_url}/api/admin/stats"))
+            .header("x-request-id", "trace-1234")
+            .send()
+            .await
+            .unwrap(),
+        StatusCode::UNAUTHORIZED,
+        "unauthorized",
+    )
+    .await;
+    assert_eq!(body["request_id"], json!("trace-1234"));
+
+    // otherwise every request gets a new one
+    let first = assert_error(
+        client
+            .get(format!("{base_url}/api/admin/stats"))
+            .send()
+            .await
+            .unwrap(),
+        StatusCode::UNAUTHORIZED,
+        "unauthorized",
+    )
+    .await;
+    let second = assert_error(
+        client
+            .get(format!("{base_url}/api/admin/stats"))
+            .send()
+            .await
+            .unwrap(),
+        StatusCode::UNAUTHORIZED,
+        "unauthorized",
+    )
+    .await;
+    assert_ne!(first["request_id"], second["request_id"]);
+}
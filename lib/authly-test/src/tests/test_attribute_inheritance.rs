@@ -0,0 +1,278 @@
+use authly_common::id::{AttrId, EntityId, GroupId, PersonaId, ServiceId};
+use authly_domain::{
+    access_control::{self, SvcAccessControlError},
+    ctx::GetDb,
+    document::error::DocError,
+    id::BuiltinAttr,
+    repo::entity_repo,
+};
+use fnv::FnvHashSet;
+use hexhex::hex_literal;
+use indoc::indoc;
+
+use crate::{
+    test_ctx::TestCtx,
+    util::{compile_and_apply_doc, ServiceProperties, TestDocError},
+};
+
+const SVC: ServiceId = ServiceId::from_raw_array(hex_literal!("e5462a0d22b54d9f9ca37bd96e9b9d8b"));
+const STAFF: GroupId = GroupId::from_raw_array(hex_literal!("5a1f0e6fbb1d4a0f9d8e0f7c1e2b3c4d"));
+const ADMINS: GroupId = GroupId::from_raw_array(hex_literal!("81dc1da0fa644142bad35043a9c3b025"));
+const ALICE: PersonaId =
+    PersonaId::from_raw_array(hex_literal!("96bf83f88cbf455fa356553f7fca1b9e"));
+
+/// `alice` is a member of `admins`, which is a member of `staff`.
+/// Roles are inherited down, while clearances roll up and projects aren't inherited.
+/// The inheritance of roles is opted into, the inheritance of projects is spelled out.
+const DOC: &str = indoc! {
+    r#"
+    [authly-document]
+    id = "bc9ce588-50c3-47d1-94c1-f88b21eaf299"
+
+    [[service-entity]]
+    eid = "s.e5462a0d22b54d9f9ca37bd96e9b9d8b"
+    label = "svc"
+
+    [[entity]]
+    eid = "g.5a1f0e6fbb1d4a0f9d8e0f7c1e2b3c4d"
+    label = "staff"
+
+    [[entity]]
+    eid = "g.81dc1da0fa644142bad35043a9c3b025"
+    label = "admins"
+
+    [[entity]]
+    eid = "p.96bf83f88cbf455fa356553f7fca1b9e"
+    label = "alice"
+
+    [[members]]
+    entity = "staff"
+    members = ["admins"]
+
+    [[members]]
+    entity = "admins"
+    members = ["alice"]
+
+    [[entity-property]]
+    namespace = "svc"
+    label = "role"
+    attributes = ["employee", "admin", "user"]
+
+    [[entity-property]]
+    namespace = "svc"
+    label = "clearance"
+    attributes = ["public", "secret"]
+
+    [[entity-property]]
+    namespace = "svc"
+    label = "project"
+    attributes = ["apollo"]
+
+    [[attribute-inheritance]]
+    namespace = "svc"
+    property = "role"
+    direction = "down"
+
+    [[attribute-inheritance]]
+    namespace = "svc"
+    property = "clearance"
+    direction = "up"
+
+    [[attribute-inheritance]]
+    namespace = "svc"
+    property = "project"
+    direction = "none"
+
+    [[entity-attribute-assignment]]
+    entity = "staff"
+    attributes = ["svc:role:employee", "svc:clearance:public"]
+
+    [[entity-attribute-assignment]]
+    entity = "admins"
+    attributes = ["svc:role:admin", "svc:project:apollo"]
+
+    [[entity-attribute-assignment]]
+    entity = "alice"
+    attributes = ["svc:role:user", "svc:clearance:secret"]
+    "#
+};
+
+async fn resolved_attrs(ctx: &TestCtx, eid: EntityId) -> FnvHashSet<AttrId> {
+    entity_repo::list_resolved_entity_attrs(ctx.get_db(), eid)
+        .await
+        .unwrap()
+}
+
+async fn attrs(ctx: &TestCtx, labels: &[(&str, &str)]) -> FnvHashSet<AttrId> {
+    let props = ServiceProperties::load(SVC, ctx.get_db()).await;
+    props
+        .entity
+        .translate(labels.iter().map(|(prop, attr)| ("svc", *prop, *attr)))
+        .into_iter()
+        .collect()
+}
+
+#[test_log::test(tokio::test)]
+async fn test_role_is_inherited_down() {
+    let ctx = TestCtx::new().inmemory_db().await.supreme_instance().await;
+    compile_and_apply_doc(DOC, &ctx).await.unwrap();
+
+    let alice = resolved_attrs(&ctx, ALICE.upcast()).await;
+    assert!(alice.is_superset(
+        &attrs(
+            &ctx,
+            &[("role", "user"), ("role", "admin"), ("role", "employee")]
+        )
+        .await
+    ));
+
+    // roles of members don't roll up
+    let staff = resolved_attrs(&ctx, STAFF.upcast()).await;
+    assert!(staff.is_disjoint(&attrs(&ctx, &[("role", "user"), ("role", "admin")]).await));
+
+    // only direct assignments are listed as the attributes of the entity itself
+    let direct = entity_repo::list_entity_attrs(ctx.get_db(), ALICE.upcast())
+        .await
+        .unwrap();
+    assert_eq!(
+        direct,
+        attrs(&ctx, &[("role", "user"), ("clearance", "secret")]).await
+    );
+}
+
+#[test_log::test(tokio::test)]
+async fn test_rolled_up_property_is_not_inherited_down() {
+    let ctx = TestCtx::new().inmemory_db().await.supreme_instance().await;
+    compile_and_apply_doc(DOC, &ctx).await.unwrap();
+
+    let secret = attrs(&ctx, &[("clearance", "secret")]).await;
+    let public = attrs(&ctx, &[("clearance", "public")]).await;
+
+    // the clearance of alice rolls up through admins to staff
+    assert!(resolved_attrs(&ctx, ADMINS.upcast())
+        .await
+        .is_superset(&secret));
+    assert!(resolved_attrs(&ctx, STAFF.upcast())
+        .await
+        .is_superset(&secret));
+
+    // but the clearance of staff is not inherited by its members
+    assert!(resolved_attrs(&ctx, ALICE.upcast())
+        .await
+        .is_disjoint(&public));
+    assert!(resolved_attrs(&ctx, ADMINS.upcast())
+        .await
+        .is_disjoint(&public));
+}
+
+#[test_log::test(tokio::test)]
+async fn test_property_without_inheritance() {
+    let ctx = TestCtx::new().inmemory_db().await.supreme_instance().await;
+    compile_and_apply_doc(DOC, &ctx).await.unwrap();
+
+    let apollo = attrs(&ctx, &[("project", "apollo")]).await;
+    assert!(resolved_attrs(&ctx, ADMINS.upcast())
+        .await
+        .is_superset(&apollo));
+    assert!(resolved_attrs(&ctx, ALICE.upcast())
+        .await
+        .is_disjoint(&apollo));
+    assert!(resolved_attrs(&ctx, STAFF.upcast())
+        .await
+        .is_disjoint(&apollo));
+}
+
+#[test_log::test(tokio::test)]
+async fn test_attributes_are_not_inherited_by_default() {
+    let ctx = TestCtx::new().inmemory_db().await.supreme_instance().await;
+    let doc = indoc! {
+        r#"
+        [authly-document]
+        id = "bc9ce588-50c3-47d1-94c1-f88b21eaf299"
+
+        [[service-entity]]
+        eid = "s.e5462a0d22b54d9f9ca37bd96e9b9d8b"
+        label = "svc"
+
+        [[service-entity]]
+        eid = "s.2c7e1f4a9b3d4e5f8a6b7c8d9e0f1a2b"
+        label = "member_svc"
+
+        [[entity]]
+        eid = "g.81dc1da0fa644142bad35043a9c3b025"
+        label = "admins"
+
+        [[entity]]
+        eid = "p.96bf83f88cbf455fa356553f7fca1b9e"
+        label = "alice"
+
+        [[members]]
+        entity = "admins"
+        members = ["alice", "member_svc"]
+
+        [[entity-property]]
+        namespace = "svc"
+        label = "role"
+        attributes = ["admin"]
+
+        [[entity-attribute-assignment]]
+        entity = "admins"
+        attributes = ["svc:role:admin", "authly:role:admin"]
+        "#
+    };
+    compile_and_apply_doc(doc, &ctx).await.unwrap();
+
+    let admin = attrs(&ctx, &[("role", "admin")]).await;
+    let authly_admin = AttrId::from(BuiltinAttr::AuthlyRoleAdmin);
+
+    let admins = resolved_attrs(&ctx, ADMINS.upcast()).await;
+    assert!(admins.is_superset(&admin));
+    assert!(admins.contains(&authly_admin));
+
+    let alice = resolved_attrs(&ctx, ALICE.upcast()).await;
+    assert!(alice.is_disjoint(&admin));
+    assert!(!alice.contains(&authly_admin));
+
+    // a service in a group with an authly role is not granted that role
+    let member_svc = ServiceId::from_raw_array(hex_literal!("2c7e1f4a9b3d4e5f8a6b7c8d9e0f1a2b"));
+    let result =
+        access_control::authorize_peer_service(&ctx, member_svc, &[BuiltinAttr::AuthlyRoleAdmin])
+            .await;
+    assert!(matches!(result, Err(SvcAccessControlError::Denied)));
+}
+
+#[test_log::test(tokio::test)]
+async fn test_inheritance_of_resource_property_is_unresolved() {
+    let ctx = TestCtx::new().inmemory_db().await.supreme_instance().await;
+    let doc = indoc! {
+        r#"
+        [authly-document]
+        id = "bc9ce588-50c3-47d1-94c1-f88b21eaf299"
+
+        [[service-entity]]
+        eid = "s.e5462a0d22b54d9f9ca37bd96e9b9d8b"
+        label = "svc"
+
+        [[resource-property]]
+        namespace = "svc"
+        label = "kind"
+        attributes = ["trousers"]
+
+        [[attribute-inheritance]]
+        namespace = "svc"
+        property = "kind"
+        direction = "up"
+        "#
+    };
+
+    let TestDocError::Doc(errors) = compile_and_apply_doc(doc, &ctx).await.unwrap_err() else {
+        panic!()
+    };
+    let spanned_error = errors.into_iter().next().unwrap();
+
+    assert!(
+        matches!(spanned_error.as_ref(), DocError::UnresolvedProperty),
+        "unexpected error: {spanned_error:?}"
+    );
+    assert_eq!("\"kind\"", &doc[spanned_error.span()]);
+}
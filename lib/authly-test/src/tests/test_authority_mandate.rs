@@ -43,12 +43,13 @@ async fn test_mandate_registration_failure() {
     .unwrap();
     let submission_claims = mandate_decode_submission_token(&m_ctx, &token).unwrap();
     let csr = mandate_identity_signing_request(&m_ctx, submission_claims.authly.mandate_entity_id)
+        .await
         .unwrap();
 
     assert!(authority_fulfill_submission(
         &a_ctx,
         &token,
-        CertificateSigningRequestParams::from_der(csr.der()).unwrap(),
+        CertificateSigningRequestParams::from_der(&csr).unwrap(),
     )
     .await
     .is_err());
@@ -64,15 +65,19 @@ async fn test_mandate_registration() {
     ];
 
     let (server_connect_uri, _drop) = spawn_test_connect_server(
-        rustls_server_config_no_client_auth(&[&authority_ctx.get_instance().sign_with_local_ca(
-            server_cert(
-                "authly",
-                vec!["localhost".to_string()],
-                time::Duration::hours(1),
+        rustls_server_config_no_client_auth(&[&authority_ctx
+            .get_instance()
+            .sign_with_local_ca(
+                server_cert(
+                    "authly",
+                    vec!["localhost".to_string()],
+                    time::Duration::hours(1),
+                )
+                .unwrap()
+                .with_new_key_pair(),
             )
-            .unwrap()
-            .with_new_key_pair(),
-        )])
+            .await
+            .unwrap()])
         .unwrap(),
         TunnelSecurity::Secure,
         tonic::service::Routes::default()
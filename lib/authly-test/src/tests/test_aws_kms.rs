@@ -0,0 +1,314 @@
+use std::sync::Arc;
+
+use authly_common::{access_token::AuthlyAccessTokenClaims, id::PersonaId};
+use authly_domain::{
+    access_token::{create_access_token, verify_access_token},
+    aws_kms::{AwsCredentials, AwsKmsSigner},
+    cert::{server_cert, CertificateParamsExt},
+    ctx::{GetDb, GetDecryptedDeks, GetIdGenerator, GetInstance, SetInstance},
+    instance::InstanceKeySource,
+    repo::crypto_repo,
+    session::{init_session, AuthClass, SessionKind},
+    signer::Signer,
+    token_signing::{rotate_token_signing_key, RotationError, TokenSigningKey},
+    IsLeaderDb,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use openssl::{
+    bn::BigNumContext,
+    ec::{EcGroup, EcKey, PointConversionForm},
+    ecdsa::EcdsaSig,
+    nid::Nid,
+    pkey::{PKey, Private},
+    sha::sha256,
+};
+use serde_json::json;
+use time::{Duration, OffsetDateTime};
+use wiremock::{
+    matchers::{header, method, path},
+    Mock, MockServer, Request, Respond, ResponseTemplate,
+};
+
+use crate::test_ctx::TestCtx;
+
+use super::test_instance_signer::verify_localhost_cert;
+
+const KEY_ID: &str = "arn:aws:kms:eu-north-1:111122223333:key/authly-test";
+
+struct GetPublicKey(EcKey<Private>);
+
+impl Respond for GetPublicKey {
+    fn respond(&self, _request: &Request) -> ResponseTemplate {
+        let spki = PKey::from_ec_key(self.0.clone())
+            .unwrap()
+            .public_key_to_der()
+            .unwrap();
+
+        ResponseTemplate::new(200).set_body_json(json!({
+            "KeyId": KEY_ID,
+            "KeySpec": "ECC_NIST_P256",
+            "KeyUsage": "SIGN_VERIFY",
+            "PublicKey": STANDARD.encode(spki),
+            "SigningAlgorithms": ["ECDSA_SHA_256"],
+        }))
+    }
+}
+
+struct Sign(EcKey<Private>);
+
+impl Respond for Sign {
+    fn respond(&self, request: &Request) -> ResponseTemplate {
+        let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+        let digest = STANDARD.decode(body["Message"].as_str().unwrap()).unwrap();
+        let signature = EcdsaSig::sign(&digest, &self.0).unwrap().to_der().unwrap();
+
+        ResponseTemplate::new(200).set_body_json(json!({
+            "KeyId": KEY_ID,
+            "Signature": STANDARD.encode(signature),
+            "SigningAlgorithm": "ECDSA_SHA_256",
+        }))
+    }
+}
+
+/// A KMS server holding a single P-256 key
+async fn mock_kms() -> (MockServer, EcKey<Private>) {
+    let key = EcKey::generate(&EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap()).unwrap();
+    let server = MockServer::start().await;
+
+    Mock::given(method("POST"))
+        .and(path("/"))
+        .and(header("x-amz-target", "TrentService.GetPublicKey"))
+        .respond_with(GetPublicKey(key.clone()))
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/"))
+        .and(header("x-amz-target", "TrentService.Sign"))
+        .respond_with(Sign(key.clone()))
+        .mount(&server)
+        .await;
+
+    (server, key)
+}
+
+async fn connect(server: &MockServer) -> AwsKmsSigner {
+    AwsKmsSigner::connect(
+        reqwest::Client::new(),
+        "eu-north-1".to_string(),
+        Some(&server.uri()),
+        KEY_ID.to_string(),
+        AwsCredentials {
+            access_key_id: "AKIDEXAMPLE".to_string(),
+            secret_access_key: "secret".to_string(),
+            session_token: Some("session".to_string()),
+        },
+    )
+    .await
+    .unwrap()
+}
+
+fn header_str<'r>(request: &'r Request, name: &str) -> &'r str {
+    request.headers.get(name).unwrap().to_str().unwrap()
+}
+
+#[test_log::test(tokio::test)]
+async fn test_kms_sign_request() {
+    let (server, key) = mock_kms().await;
+    let signer = connect(&server).await;
+
+    let mut bn_ctx = BigNumContext::new().unwrap();
+    let public_key_raw = key
+        .public_key()
+        .to_bytes(key.group(), PointConversionForm::UNCOMPRESSED, &mut bn_ctx)
+        .unwrap();
+    assert_eq!(signer.public_key_raw(), public_key_raw);
+
+    let signature = signer.sign(b"message").await.unwrap();
+    assert!(EcdsaSig::from_der(&signature)
+        .unwrap()
+        .verify(&sha256(b"message"), &key)
+        .unwrap());
+
+    let requests = server.received_requests().await.unwrap();
+    let sign_request = requests
+        .iter()
+        .find(|request| header_str(request, "x-amz-target") == "TrentService.Sign")
+        .unwrap();
+
+    assert_eq!(
+        header_str(sign_request, "content-type"),
+        "application/x-amz-json-1.1"
+    );
+    assert_eq!(header_str(sign_request, "x-amz-security-token"), "session");
+
+    let authorization = header_str(sign_request, "authorization");
+    assert!(authorization.starts_with("AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/"));
+    assert!(authorization.contains(
+        "/eu-north-1/kms/aws4_request, \
+         SignedHeaders=content-type;host;x-amz-date;x-amz-security-token;x-amz-target, \
+         Signature="
+    ));
+
+    // only the digest of the message is sent to KMS
+    let body: serde_json::Value = serde_json::from_slice(&sign_request.body).unwrap();
+    assert_eq!(
+        body,
+        json!({
+            "KeyId": KEY_ID,
+            "Message": STANDARD.encode(sha256(b"message")),
+            "MessageType": "DIGEST",
+            "SigningAlgorithm": "ECDSA_SHA_256",
+        })
+    );
+}
+
+/// A context signing access tokens with a KMS key, returning the `kid` of the key
+async fn kms_token_signing_ctx(server: &MockServer) -> (TestCtx, String) {
+    let ctx = TestCtx::new().inmemory_db().await.supreme_instance().await;
+
+    let kms_key =
+        TokenSigningKey::from_signer(Arc::new(connect(server).await), OffsetDateTime::UNIX_EPOCH);
+    let kid = kms_key.kid().to_string();
+    let instance = crypto_repo::load_authly_instance(
+        IsLeaderDb(true),
+        ctx.get_db(),
+        &ctx.get_decrypted_deks(),
+        InstanceKeySource::Database,
+        ctx.get_id_generator(),
+    )
+    .await
+    .unwrap()
+    .with_external_token_signing_key(kms_key);
+    ctx.set_instance(instance);
+
+    (ctx, kid)
+}
+
+async fn sign_request_count(server: &MockServer) -> usize {
+    server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .filter(|request| header_str(request, "x-amz-target") == "TrentService.Sign")
+        .count()
+}
+
+#[test_log::test(tokio::test)]
+async fn test_kms_token_signing_key() {
+    let (server, _key) = mock_kms().await;
+    let (ctx, kid) = kms_token_signing_ctx(&server).await;
+
+    let session = init_session(
+        &ctx,
+        PersonaId::random().upcast(),
+        SessionKind::Default,
+        AuthClass::Password,
+    )
+    .await
+    .unwrap();
+    let token = create_access_token(
+        &session,
+        Default::default(),
+        &ctx.get_instance(),
+        OffsetDateTime::now_utc(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        jsonwebtoken::decode_header(&token).unwrap().kid,
+        Some(kid.clone())
+    );
+    assert!(verify_access_token(&token, &ctx.get_instance(), OffsetDateTime::now_utc()).is_ok());
+
+    // the token validates against the public key published in the JWKS
+    let jwks = serde_json::to_value(ctx.get_instance().jwks()).unwrap();
+    assert_eq!(jwks["keys"][0]["kid"], kid.as_str());
+    let decoding_key = jsonwebtoken::DecodingKey::from_ec_components(
+        jwks["keys"][0]["x"].as_str().unwrap(),
+        jwks["keys"][0]["y"].as_str().unwrap(),
+    )
+    .unwrap();
+    let claims = jsonwebtoken::decode::<AuthlyAccessTokenClaims>(
+        &token,
+        &decoding_key,
+        &jsonwebtoken::Validation::new(jsonwebtoken::Algorithm::ES256),
+    )
+    .unwrap()
+    .claims;
+    assert_eq!(claims.authly.entity_id, session.eid);
+}
+
+#[test_log::test(tokio::test)]
+async fn test_kms_signed_tokens_are_reused() {
+    let (server, _key) = mock_kms().await;
+    let (ctx, _) = kms_token_signing_ctx(&server).await;
+    let session = init_session(
+        &ctx,
+        PersonaId::random().upcast(),
+        SessionKind::Default,
+        AuthClass::Password,
+    )
+    .await
+    .unwrap();
+    let now = OffsetDateTime::now_utc();
+
+    let instance = ctx.get_instance();
+    let create = |now| create_access_token(&session, Default::default(), &instance, now);
+    let first = create(now).await.unwrap();
+    let second = create(now + Duration::seconds(30)).await.unwrap();
+    assert_eq!(first, second);
+    assert_eq!(sign_request_count(&server).await, 1);
+
+    // a token signed too long ago is not handed out again
+    let third = create(now + Duration::minutes(10)).await.unwrap();
+    assert_ne!(first, third);
+    assert_eq!(sign_request_count(&server).await, 2);
+}
+
+#[test_log::test(tokio::test)]
+async fn test_kms_token_signing_key_is_not_rotated() {
+    let (server, _key) = mock_kms().await;
+    let (ctx, kid) = kms_token_signing_ctx(&server).await;
+
+    assert!(matches!(
+        rotate_token_signing_key(&ctx).await,
+        Err(RotationError::ExternalKey)
+    ));
+    assert_eq!(ctx.get_instance().token_signing_key().kid(), kid);
+}
+
+#[test_log::test(tokio::test)]
+async fn test_kms_instance_key() {
+    let (server, _key) = mock_kms().await;
+    let signer = Arc::new(connect(&server).await);
+    let ctx = TestCtx::new()
+        .inmemory_db()
+        .await
+        .supreme_instance_with_signer(signer.clone())
+        .await;
+
+    // the CA published by the instance certifies the KMS key
+    let (_, ca) =
+        x509_parser::parse_x509_certificate(&ctx.get_instance().trust_root_ca().der).unwrap();
+    assert_eq!(
+        ca.public_key().subject_public_key.data.as_ref(),
+        signer.public_key_raw()
+    );
+
+    let server_cert = ctx
+        .get_instance()
+        .sign_with_local_ca(
+            server_cert(
+                "authly",
+                vec!["localhost".to_string()],
+                time::Duration::hours(1),
+            )
+            .unwrap()
+            .with_new_key_pair(),
+        )
+        .await
+        .unwrap();
+
+    verify_localhost_cert(&ctx.get_instance().trust_root_ca().der, &server_cert.der);
+}
@@ -0,0 +1,96 @@
+use authly_common::id::{PersonaId, ServiceId};
+use authly_domain::{
+    access_token::{create_access_token, verify_access_token},
+    ctx::{GetDb, GetInstance},
+    repo::{backup_repo, policy_repo, service_repo, session_repo},
+    session::{init_session, AuthClass, SessionKind},
+};
+use hexhex::hex_literal;
+use time::OffsetDateTime;
+
+use crate::{test_ctx::TestCtx, util::compile_and_apply_doc_dir};
+
+const PERSONA_ME: PersonaId =
+    PersonaId::from_raw_array(hex_literal!("0fbcd73e1a884424a1615c3c3fdeebec"));
+const TESTSERVICE: ServiceId =
+    ServiceId::from_raw_array(hex_literal!("f3e799137c034e1eb4cd3e4f65705932"));
+
+#[test_log::test(tokio::test)]
+async fn test_backup_wipe_restore() {
+    let backup_path =
+        std::env::temp_dir().join(format!("authly-backup-{}.db", ServiceId::random()));
+    let backup_path_str = backup_path.to_str().unwrap();
+
+    let source = TestCtx::new().inmemory_db().await.supreme_instance().await;
+    compile_and_apply_doc_dir("../../examples/demo".into(), &source)
+        .await
+        .unwrap();
+
+    let session = init_session(
+        &source,
+        PERSONA_ME.upcast(),
+        SessionKind::Default,
+        AuthClass::Password,
+    )
+    .await
+    .unwrap();
+    let access_token = create_access_token(
+        &session,
+        Default::default(),
+        &source.get_instance(),
+        OffsetDateTime::now_utc(),
+    )
+    .await
+    .unwrap();
+    let policy_count = policy_repo::load_svc_policy_engine(source.get_db(), TESTSERVICE)
+        .await
+        .unwrap()
+        .get_policy_count();
+    let local_ca_der = source.get_instance().local_ca().der.clone();
+
+    backup_repo::snapshot_into(source.get_db(), backup_path_str)
+        .await
+        .unwrap();
+
+    // wipe the original node
+    drop(source);
+
+    let target = TestCtx::new().inmemory_db().await;
+    backup_repo::restore_from(target.get_db(), backup_path_str)
+        .await
+        .unwrap();
+
+    // the restored DEKs are decrypted with the same (fake) master key
+    let target = target.supreme_instance().await;
+
+    std::fs::remove_file(&backup_path).unwrap();
+
+    assert_eq!(
+        Some("testservice"),
+        service_repo::find_service_label_by_eid(target.get_db(), TESTSERVICE)
+            .await
+            .unwrap()
+            .as_deref()
+    );
+    assert!(policy_count > 0);
+    assert_eq!(
+        policy_count,
+        policy_repo::load_svc_policy_engine(target.get_db(), TESTSERVICE)
+            .await
+            .unwrap()
+            .get_policy_count()
+    );
+    assert_eq!(local_ca_der, target.get_instance().local_ca().der);
+    assert!(session_repo::get_session(target.get_db(), session.token)
+        .await
+        .unwrap()
+        .is_some());
+
+    let claims = verify_access_token(
+        &access_token,
+        &target.get_instance(),
+        OffsetDateTime::now_utc(),
+    )
+    .unwrap();
+    assert_eq!(claims.authly.entity_id, PERSONA_ME.upcast());
+}
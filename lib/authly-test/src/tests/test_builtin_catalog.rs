@@ -0,0 +1,87 @@
+use authly_common::id::{AttrId, PropId};
+use authly_domain::id::{BuiltinAttr, BuiltinProp};
+use http::StatusCode;
+use serde_json::{json, Value};
+
+use crate::test_ctx::TestCtx;
+
+async fn get_catalog() -> Value {
+    let ctx = TestCtx::new().inmemory_db().await;
+    let router = authly_service::openapi::router::router().with_state(ctx);
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let base_url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, router).await });
+
+    let response = reqwest::get(format!("{base_url}/api/builtins"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    response.json().await.unwrap()
+}
+
+#[test_log::test(tokio::test)]
+async fn test_every_builtin_is_listed() {
+    let catalog = get_catalog().await;
+    let properties = catalog["properties"].as_array().unwrap();
+    assert_eq!(properties.len(), BuiltinProp::iter().count());
+
+    for prop in BuiltinProp::iter() {
+        let entry = properties
+            .iter()
+            .find(|entry| entry["name"] == format!("{prop:?}"))
+            .unwrap_or_else(|| panic!("{prop:?} not listed"));
+        assert_eq!(entry["id"], json!(PropId::from(prop)), "{prop:?}");
+        assert_eq!(entry["label"], json!(prop.label()), "{prop:?}");
+    }
+
+    for attr in BuiltinAttr::iter() {
+        let entry = properties
+            .iter()
+            .flat_map(|entry| entry["attributes"].as_array().unwrap())
+            .find(|entry| entry["name"] == format!("{attr:?}"))
+            .unwrap_or_else(|| panic!("{attr:?} not listed"));
+        assert_eq!(entry["id"], json!(AttrId::from(attr)), "{attr:?}");
+        assert_eq!(entry["label"], json!(attr.label()), "{attr:?}");
+    }
+}
+
+#[test_log::test(tokio::test)]
+async fn test_roles_are_referenced_through_the_authly_namespace() {
+    let catalog = get_catalog().await;
+    let role = catalog["properties"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|entry| entry["name"] == "AuthlyRole")
+        .unwrap();
+
+    assert_eq!(role["reference"], "authly:role");
+    assert_eq!(
+        role["attributes"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|attr| attr["reference"].as_str().unwrap())
+            .collect::<Vec<_>>(),
+        vec![
+            "authly:role:get_access_token",
+            "authly:role:authenticate",
+            "authly:role:apply_document",
+            "authly:role:grant_mandate",
+            "authly:role:admin",
+            "authly:role:access_control_details",
+            "authly:role:sign_certificate",
+            "authly:role:receive_messages",
+        ]
+    );
+
+    // properties without a label can't be referenced from documents
+    let username = catalog["properties"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|entry| entry["name"] == "Username")
+        .unwrap();
+    assert_eq!(username["reference"], Value::Null);
+}
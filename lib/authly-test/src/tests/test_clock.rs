@@ -0,0 +1,109 @@
+use std::time::Duration;
+
+use authly_common::id::PersonaId;
+use authly_domain::{
+    access_token::{self, verify_and_resolve_access_token, EXPIRATION},
+    clock::ManualClock,
+    ctx::{GetClock, GetInstance},
+    session::{authenticate_session_cookie, init_session, AuthClass, SessionKind},
+    settings::Settings,
+};
+use hexhex::hex_literal;
+use time::OffsetDateTime;
+
+use crate::test_ctx::TestCtx;
+
+const PERSONA_ME: PersonaId =
+    PersonaId::from_raw_array(hex_literal!("0fbcd73e1a884424a1615c3c3fdeebec"));
+
+async fn clock_ctx(clock: &ManualClock) -> TestCtx {
+    TestCtx::new()
+        .inmemory_db()
+        .await
+        .supreme_instance()
+        .await
+        .with_settings(Settings {
+            session_idle_timeout: Duration::from_secs(10 * 60),
+            ..Default::default()
+        })
+        .with_clock(clock.clone())
+}
+
+#[test_log::test(tokio::test)]
+async fn test_access_token_expiry() {
+    let clock = ManualClock::new(OffsetDateTime::now_utc());
+    let ctx = clock_ctx(&clock).await;
+    let session = init_session(
+        &ctx,
+        PERSONA_ME.upcast(),
+        SessionKind::Default,
+        AuthClass::Password,
+    )
+    .await
+    .unwrap();
+    let token = access_token::create_access_token(
+        &session,
+        Default::default(),
+        &ctx.get_instance(),
+        ctx.get_clock().now(),
+    )
+    .await
+    .unwrap();
+
+    assert!(verify_and_resolve_access_token(&token, &ctx).await.is_ok());
+
+    // just before expiry
+    clock.advance(EXPIRATION - time::Duration::minutes(1));
+    assert!(verify_and_resolve_access_token(&token, &ctx).await.is_ok());
+
+    // expired, and past the leeway for clock skew
+    clock.advance(time::Duration::minutes(5));
+    assert!(verify_and_resolve_access_token(&token, &ctx).await.is_err());
+}
+
+#[test_log::test(tokio::test)]
+async fn test_session_idle_timeout_with_clock() {
+    let clock = ManualClock::new(OffsetDateTime::now_utc());
+    let ctx = clock_ctx(&clock).await;
+    let session = init_session(
+        &ctx,
+        PERSONA_ME.upcast(),
+        SessionKind::Default,
+        AuthClass::Password,
+    )
+    .await
+    .unwrap();
+    let cookie = session.to_cookie(ctx.get_clock().now());
+
+    clock.advance(time::Duration::minutes(9));
+    assert!(authenticate_session_cookie(&ctx, &cookie).await.is_ok());
+
+    // the activity above refreshed the idle window
+    clock.advance(time::Duration::minutes(9));
+    assert!(authenticate_session_cookie(&ctx, &cookie).await.is_ok());
+
+    clock.advance(time::Duration::minutes(11));
+    let result = authenticate_session_cookie(&ctx, &cookie).await;
+    assert_eq!(result.err(), Some("session idle timeout"));
+}
+
+#[test_log::test(tokio::test)]
+async fn test_session_cookie_max_age_with_clock() {
+    let clock = ManualClock::new(OffsetDateTime::now_utc());
+    let ctx = clock_ctx(&clock).await;
+    let session = init_session(
+        &ctx,
+        PERSONA_ME.upcast(),
+        SessionKind::Default,
+        AuthClass::Password,
+    )
+    .await
+    .unwrap();
+    let max_age = session.to_cookie(ctx.get_clock().now()).max_age().unwrap();
+
+    clock.advance(time::Duration::hours(1));
+    assert_eq!(
+        session.to_cookie(ctx.get_clock().now()).max_age().unwrap(),
+        max_age - time::Duration::hours(1)
+    );
+}
@@ -0,0 +1,135 @@
+use authly_domain::{
+    bus::{handler::authly_node_handle_incoming_message, ClusterMessage},
+    clock::ManualClock,
+    clock_skew::{self, ClockSkew, ClockSkewMonitor},
+    ctx::GetClockSkew,
+    health::check_health,
+    stats::collect_stats,
+};
+use axum::routing::get;
+use http::StatusCode;
+use time::{Duration, OffsetDateTime};
+
+use crate::test_ctx::TestCtx;
+
+/// Deliver the clock checks of other nodes, sent at the given times
+async fn receive_clock_checks(ctx: &TestCtx, sent_at: &[OffsetDateTime]) {
+    for (node, sent_at) in sent_at.iter().enumerate() {
+        authly_node_handle_incoming_message(
+            ctx,
+            ClusterMessage::ClockCheck {
+                node: node as u64,
+                sent_at: *sent_at,
+            },
+        )
+        .await
+        .unwrap();
+    }
+}
+
+#[test_log::test(tokio::test)]
+async fn test_skewed_node_is_not_serving() {
+    let now = OffsetDateTime::now_utc();
+    let ctx = TestCtx::new()
+        .inmemory_db()
+        .await
+        .with_clock(ManualClock::new(now + Duration::minutes(5)));
+    assert!(check_health(&ctx).await.is_serving());
+
+    receive_clock_checks(&ctx, &[now, now + Duration::seconds(1)]).await;
+
+    let health = check_health(&ctx).await;
+    assert_eq!(health.clock_skew, ClockSkew::Skewed);
+    assert!(!health.is_serving());
+    assert_eq!(
+        collect_stats(&ctx).await.unwrap().clock_skew,
+        ClockSkew::Skewed
+    );
+}
+
+#[test_log::test(tokio::test)]
+async fn test_two_node_cluster_keeps_serving() {
+    let now = OffsetDateTime::now_utc();
+    let ctx = TestCtx::new()
+        .inmemory_db()
+        .await
+        .with_clock(ManualClock::new(now + Duration::minutes(5)));
+
+    // with one other node, there's no telling which of the two clocks is off
+    receive_clock_checks(&ctx, &[now]).await;
+
+    let health = check_health(&ctx).await;
+    assert_eq!(health.clock_skew, ClockSkew::Disputed);
+    assert!(health.is_serving());
+}
+
+#[test_log::test(tokio::test)]
+async fn test_single_skewed_peer_does_not_flag_the_node() {
+    let now = OffsetDateTime::now_utc();
+    let ctx = TestCtx::new()
+        .inmemory_db()
+        .await
+        .with_clock(ManualClock::new(now));
+
+    receive_clock_checks(
+        &ctx,
+        &[now + Duration::minutes(5), now, now - Duration::seconds(2)],
+    )
+    .await;
+
+    let health = check_health(&ctx).await;
+    assert_eq!(health.clock_skew, ClockSkew::InSync);
+    assert!(health.is_serving());
+}
+
+#[test_log::test(tokio::test)]
+async fn test_own_clock_check_is_ignored() {
+    let ctx = TestCtx::new()
+        .inmemory_db()
+        .await
+        .with_clock(ManualClock::new(
+            OffsetDateTime::now_utc() + Duration::minutes(5),
+        ));
+
+    // the broadcast is delivered to the sending node as well
+    clock_skew::broadcast_clock_check(&ctx).await.unwrap();
+    assert!(ctx
+        .clone_cluster_message_log()
+        .iter()
+        .any(|message| matches!(message, ClusterMessage::ClockCheck { .. })));
+
+    assert_eq!(check_health(&ctx).await.clock_skew, ClockSkew::InSync);
+}
+
+#[test_log::test(tokio::test)]
+async fn test_skewed_node_rejects_requests() {
+    let now = OffsetDateTime::now_utc();
+    let ctx = TestCtx::new()
+        .inmemory_db()
+        .await
+        .with_clock(ManualClock::new(now + Duration::minutes(5)));
+
+    let router = ClockSkewMonitor::layer(
+        ctx.clone(),
+        axum::Router::new()
+            .route("/", get(|| async { "done" }))
+            .route("/grpc.health.v1.Health/Check", get(|| async { "done" })),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, router).await });
+
+    let client = reqwest::Client::new();
+    let status = |path: &'static str| {
+        let request = client.get(format!("{url}{path}"));
+        async move { request.send().await.unwrap().status() }
+    };
+    assert_eq!(status("/").await, StatusCode::OK);
+
+    receive_clock_checks(&ctx, &[now, now + Duration::seconds(1)]).await;
+    assert!(ctx.get_clock_skew().is_skewed());
+
+    assert_eq!(status("/").await, StatusCode::SERVICE_UNAVAILABLE);
+    // the health check reports the node as not serving
+    assert_eq!(status("/grpc.health.v1.Health/Check").await, StatusCode::OK);
+}
@@ -0,0 +1,116 @@
+use authly_domain::{
+    ctx::GetDb,
+    document::standalone::{compile_source, CompileOptions},
+};
+use indoc::indoc;
+
+use crate::{test_ctx::TestCtx, util::compile_and_apply_doc};
+
+const DOC: &str = indoc! {
+    r#"
+    [authly-document]
+    id = "bc9ce588-50c3-47d1-94c1-f88b21eaf299"
+
+    [[service-entity]]
+    eid = "s.e5462a0d22b54d9f9ca37bd96e9b9d8b"
+    label = "svc"
+
+    [[resource-property]]
+    namespace = "svc"
+    label = "action"
+    attributes = ["read", "write"]
+
+    [[policy]]
+    label = "allow svc"
+    allow = "Subject.authly:entity == svc"
+
+    [[policy-binding]]
+    attributes = ["svc:action:read"]
+    policies = ["allow svc"]
+    "#
+};
+
+/// The codes of the diagnostics of a source, with the text they point at
+async fn diagnostics(ctx: &TestCtx, source: &str) -> Vec<(&'static str, String)> {
+    compile_source(ctx.get_db(), source, CompileOptions::default())
+        .await
+        .unwrap_err()
+        .into_iter()
+        .map(|diagnostic| {
+            (
+                diagnostic.code,
+                source[diagnostic.location.span].to_string(),
+            )
+        })
+        .collect()
+}
+
+#[test_log::test(tokio::test)]
+async fn test_compile_valid_source() {
+    let ctx = TestCtx::new().inmemory_db().await;
+
+    let compiled = compile_source(ctx.get_db(), DOC, CompileOptions::default())
+        .await
+        .unwrap();
+    assert_eq!(compiled.data.domain_props.len(), 1);
+    assert_eq!(compiled.data.policies.len(), 1);
+    assert_eq!(compiled.data.policy_bindings.len(), 1);
+
+    // compiling does not apply the document, it can still be applied
+    compile_and_apply_doc(DOC, &ctx).await.unwrap();
+
+    // against a database containing the document, its IDs are reused
+    let first = compile_source(ctx.get_db(), DOC, CompileOptions::default())
+        .await
+        .unwrap();
+    let second = compile_source(ctx.get_db(), DOC, CompileOptions::default())
+        .await
+        .unwrap();
+    assert_eq!(
+        first.data.domain_props[0].id,
+        second.data.domain_props[0].id
+    );
+    assert_eq!(first.data.policies[0].0, second.data.policies[0].0);
+}
+
+#[test_log::test(tokio::test)]
+async fn test_compile_invalid_source() {
+    let ctx = TestCtx::new().inmemory_db().await;
+
+    // unresolved references
+    let source = DOC.replace(r#"policies = ["allow svc"]"#, r#"policies = ["deny svc"]"#);
+    assert_eq!(
+        diagnostics(&ctx, &source).await,
+        vec![("unresolved-policy", r#""deny svc""#.to_string())]
+    );
+
+    // unknown keys
+    let source = DOC.replace("attributes = [\"read\"", "atributes = [\"read\"");
+    assert_eq!(
+        diagnostics(&ctx, &source).await,
+        vec![("unknown-field", "atributes".to_string())]
+    );
+
+    // unknown keys are ignored when not strict
+    let result = compile_source(
+        ctx.get_db(),
+        &DOC.replace(
+            "label = \"allow svc\"",
+            "label = \"allow svc\"\nnote = \"x\"",
+        ),
+        CompileOptions {
+            strict: false,
+            ..Default::default()
+        },
+    )
+    .await;
+    assert!(result.is_ok());
+
+    // invalid TOML
+    let source = format!("{DOC}\n[[policy]\n");
+    let diagnostics = compile_source(ctx.get_db(), &source, CompileOptions::default())
+        .await
+        .unwrap_err();
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].code, "syntax");
+}
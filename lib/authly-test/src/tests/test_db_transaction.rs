@@ -0,0 +1,94 @@
+use authly_db::{params, Db, FromRow, TxnOutcome};
+use authly_domain::ctx::GetDb;
+
+use crate::test_ctx::TestCtx;
+
+struct Value(String);
+
+impl FromRow for Value {
+    fn from_row(row: &mut impl authly_db::Row) -> Self {
+        Self(row.get_text("value"))
+    }
+}
+
+async fn ctx_with_table() -> TestCtx {
+    let ctx = TestCtx::new().inmemory_db().await;
+    ctx.get_db()
+        .execute(
+            "CREATE TABLE txn_test (value TEXT NOT NULL UNIQUE)".into(),
+            params!(),
+        )
+        .await
+        .unwrap();
+    ctx
+}
+
+async fn values(ctx: &TestCtx) -> Vec<String> {
+    ctx.get_db()
+        .query_map::<Value>(
+            "SELECT value FROM txn_test ORDER BY value".into(),
+            params!(),
+        )
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|value| value.0)
+        .collect()
+}
+
+#[test_log::test(tokio::test)]
+async fn test_transact_commit() {
+    let ctx = ctx_with_table().await;
+
+    let results = ctx
+        .get_db()
+        .transact(vec![
+            (
+                "INSERT INTO txn_test (value) VALUES ($1)".into(),
+                params!("a"),
+            ),
+            (
+                "INSERT INTO txn_test (value) VALUES ($1)".into(),
+                params!("b"),
+            ),
+        ])
+        .await
+        .unwrap();
+
+    assert_eq!(results.outcome, TxnOutcome::Committed);
+    assert_eq!(results.into_result().unwrap(), vec![1, 1]);
+    assert_eq!(values(&ctx).await, vec!["a", "b"]);
+}
+
+#[test_log::test(tokio::test)]
+async fn test_transact_failure_rolls_back() {
+    let ctx = ctx_with_table().await;
+
+    let results = ctx
+        .get_db()
+        .transact(vec![
+            (
+                "INSERT INTO txn_test (value) VALUES ($1)".into(),
+                params!("a"),
+            ),
+            (
+                "INSERT INTO txn_test (value) VALUES ($1)".into(),
+                params!("a"),
+            ),
+            (
+                "INSERT INTO txn_test (value) VALUES ($1)".into(),
+                params!("b"),
+            ),
+        ])
+        .await
+        .unwrap();
+
+    assert_eq!(results.outcome, TxnOutcome::RolledBack);
+    assert_eq!(results.results.len(), 3);
+    assert!(results.results[0].is_ok());
+    assert!(results.results[1].is_err());
+    assert!(results.into_result().is_err());
+
+    // neither the statement before nor after the failing one took effect
+    assert!(values(&ctx).await.is_empty());
+}
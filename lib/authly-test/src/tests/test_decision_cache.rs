@@ -0,0 +1,288 @@
+use std::{borrow::Cow, collections::BTreeSet, sync::Arc, time::Duration};
+
+use authly_common::{
+    id::{AttrId, PersonaId, PolicyId, ServiceId},
+    policy::{
+        code::{to_bytecode, OpCode, PolicyValue},
+        engine::{AccessControlParams, PolicyEngine},
+    },
+};
+use authly_domain::{
+    access_control::{self, DecisionReason},
+    ctx::{GetClock, GetPolicyEngineCache, GetSettings},
+    id::BuiltinProp,
+    settings::{DecisionCache, Setting, Settings},
+};
+
+use crate::test_ctx::TestCtx;
+
+const SVC: ServiceId = ServiceId::from_raw_array([7; 16]);
+const OTHER_SVC: ServiceId = ServiceId::from_raw_array([8; 16]);
+const READ: AttrId = AttrId::from_uint(1);
+const WRITE: AttrId = AttrId::from_uint(2);
+const ROLE: AttrId = AttrId::from_uint(3);
+
+/// An engine with a single policy, allowing access to resources with the given attribute
+fn engine(resource_attr: AttrId) -> Arc<PolicyEngine> {
+    let policy_id = PolicyId::from_uint(1);
+    let mut engine = PolicyEngine::default();
+    engine.add_policy(
+        policy_id,
+        PolicyValue::Allow,
+        to_bytecode(&[
+            OpCode::LoadConstAttrId(resource_attr),
+            OpCode::LoadResourceAttrs,
+            OpCode::IdSetContains,
+            OpCode::Return,
+        ]),
+    );
+    engine.add_trigger(BTreeSet::from([resource_attr]), BTreeSet::from([policy_id]));
+    Arc::new(engine)
+}
+
+fn params(resource_attr: AttrId) -> AccessControlParams {
+    AccessControlParams {
+        resource_attrs: FromIterator::from_iter([resource_attr]),
+        subject_attrs: FromIterator::from_iter([ROLE]),
+        ..Default::default()
+    }
+}
+
+async fn cache_ctx(ttl: &str) -> TestCtx {
+    let mut settings = Settings::default();
+    settings
+        .try_set(Setting::DecisionCacheTtl, Cow::Borrowed(ttl))
+        .unwrap();
+
+    TestCtx::new()
+        .inmemory_db()
+        .await
+        .supreme_instance()
+        .await
+        .with_settings(settings)
+}
+
+async fn evaluate(
+    ctx: &TestCtx,
+    svc_eid: ServiceId,
+    params: &AccessControlParams,
+) -> DecisionReason {
+    access_control::evaluate_local(ctx, svc_eid, params)
+        .await
+        .unwrap()
+        .reason
+}
+
+#[test_log::test(tokio::test)]
+async fn test_repeated_request_is_cached() {
+    let ctx = cache_ctx("1m").await;
+    let cache = ctx.get_policy_engine_cache();
+    cache.insert(cache.generation(), SVC, engine(READ));
+
+    assert_eq!(
+        evaluate(&ctx, SVC, &params(READ)).await,
+        DecisionReason::Allowed
+    );
+    assert_eq!(
+        cache.get_decision(
+            cache.generation(),
+            SVC,
+            &params(READ),
+            ctx.get_clock().now()
+        ),
+        Some(DecisionReason::Allowed)
+    );
+
+    // replace the engine without a new generation, the cached decision is still served
+    cache.insert(cache.generation(), SVC, engine(WRITE));
+    assert_eq!(
+        evaluate(&ctx, SVC, &params(READ)).await,
+        DecisionReason::Allowed
+    );
+}
+
+#[test_log::test(tokio::test)]
+async fn test_changed_inputs_miss_the_cache() {
+    let ctx = cache_ctx("1m").await;
+    let cache = ctx.get_policy_engine_cache();
+    cache.insert(cache.generation(), SVC, engine(READ));
+    evaluate(&ctx, SVC, &params(READ)).await;
+
+    let generation = cache.generation();
+    let now = ctx.get_clock().now();
+    assert!(cache
+        .get_decision(generation, SVC, &params(READ), now)
+        .is_some());
+
+    let mut other_subject_attrs = params(READ);
+    other_subject_attrs
+        .subject_attrs
+        .insert(AttrId::from_uint(4));
+
+    let mut other_subject_eids = params(READ);
+    other_subject_eids
+        .subject_eids
+        .insert(BuiltinProp::Entity.into(), PersonaId::from_uint(5).upcast());
+
+    let mut other_resource_attrs = params(READ);
+    other_resource_attrs.resource_attrs.insert(WRITE);
+
+    for params in [
+        other_subject_attrs,
+        other_subject_eids,
+        other_resource_attrs,
+        params(WRITE),
+    ] {
+        assert!(cache.get_decision(generation, SVC, &params, now).is_none());
+    }
+    assert!(cache
+        .get_decision(generation, OTHER_SVC, &params(READ), now)
+        .is_none());
+}
+
+#[test_log::test(tokio::test)]
+async fn test_engine_swap_misses_the_cache() {
+    let ctx = cache_ctx("1m").await;
+    let cache = ctx.get_policy_engine_cache();
+    cache.insert(cache.generation(), SVC, engine(READ));
+    assert_eq!(
+        evaluate(&ctx, SVC, &params(READ)).await,
+        DecisionReason::Allowed
+    );
+
+    let stale = cache.generation();
+    cache.invalidate_all();
+    cache.insert(cache.generation(), SVC, engine(WRITE));
+
+    assert!(cache
+        .get_decision(stale, SVC, &params(READ), ctx.get_clock().now())
+        .is_none());
+    assert_eq!(
+        evaluate(&ctx, SVC, &params(READ)).await,
+        DecisionReason::PolicyDenied
+    );
+
+    // a decision made by an engine loaded before the swap is not cached
+    cache.insert_decision(
+        stale,
+        SVC,
+        &params(WRITE),
+        DecisionReason::PolicyDenied,
+        ctx.get_clock().now(),
+        ctx.get_settings().decision_cache,
+    );
+    assert!(cache
+        .get_decision(stale, SVC, &params(WRITE), ctx.get_clock().now())
+        .is_none());
+}
+
+#[test_log::test(tokio::test)]
+async fn test_decision_cache_disabled_by_default() {
+    let ctx = cache_ctx("off").await;
+    let cache = ctx.get_policy_engine_cache();
+    cache.insert(cache.generation(), SVC, engine(READ));
+
+    assert_eq!(
+        evaluate(&ctx, SVC, &params(READ)).await,
+        DecisionReason::Allowed
+    );
+    assert!(cache
+        .get_decision(
+            cache.generation(),
+            SVC,
+            &params(READ),
+            ctx.get_clock().now()
+        )
+        .is_none());
+
+    cache.insert(cache.generation(), SVC, engine(WRITE));
+    assert_eq!(
+        evaluate(&ctx, SVC, &params(READ)).await,
+        DecisionReason::PolicyDenied
+    );
+}
+
+#[test_log::test(tokio::test)]
+async fn test_decision_expiry_and_capacity() {
+    let ctx = TestCtx::new().inmemory_db().await;
+    let cache = ctx.get_policy_engine_cache();
+    let generation = cache.generation();
+    let config = DecisionCache {
+        ttl: Some(Duration::from_secs(10)),
+        capacity: 1,
+    };
+    let t0 = ctx.get_clock().now();
+
+    cache.insert_decision(
+        generation,
+        SVC,
+        &params(READ),
+        DecisionReason::Allowed,
+        t0,
+        config,
+    );
+    assert!(cache
+        .get_decision(generation, SVC, &params(READ), t0 + Duration::from_secs(9))
+        .is_some());
+    assert!(cache
+        .get_decision(generation, SVC, &params(READ), t0 + Duration::from_secs(10))
+        .is_none());
+
+    // the cache is full until the first decision expires
+    cache.insert_decision(
+        generation,
+        SVC,
+        &params(WRITE),
+        DecisionReason::PolicyDenied,
+        t0 + Duration::from_secs(1),
+        config,
+    );
+    assert!(cache
+        .get_decision(generation, SVC, &params(WRITE), t0 + Duration::from_secs(2))
+        .is_none());
+
+    cache.insert_decision(
+        generation,
+        SVC,
+        &params(WRITE),
+        DecisionReason::PolicyDenied,
+        t0 + Duration::from_secs(10),
+        config,
+    );
+    assert_eq!(
+        cache.get_decision(
+            generation,
+            SVC,
+            &params(WRITE),
+            t0 + Duration::from_secs(11)
+        ),
+        Some(DecisionReason::PolicyDenied)
+    );
+}
+
+#[test]
+fn test_decision_cache_settings() {
+    let mut settings = Settings::default();
+    assert_eq!(settings.decision_cache.ttl, None);
+
+    for (setting, value) in [
+        (Setting::DecisionCacheTtl, "soon"),
+        (Setting::DecisionCacheCapacity, "0"),
+    ] {
+        assert!(settings.try_set(setting, Cow::Borrowed(value)).is_err());
+    }
+
+    settings
+        .try_set(Setting::DecisionCacheTtl, Cow::Borrowed("500ms"))
+        .unwrap();
+    settings
+        .try_set(Setting::DecisionCacheCapacity, Cow::Borrowed("100"))
+        .unwrap();
+    assert_eq!(
+        settings.decision_cache,
+        DecisionCache {
+            ttl: Some(Duration::from_millis(500)),
+            capacity: 100,
+        }
+    );
+}
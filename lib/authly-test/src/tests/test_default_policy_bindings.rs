@@ -0,0 +1,141 @@
+use authly_common::{
+    id::ServiceId,
+    policy::{
+        code::PolicyValue,
+        engine::{AccessControlParams, NoOpPolicyTracer},
+    },
+};
+use authly_domain::{ctx::GetDb, repo::policy_repo};
+use hexhex::hex_literal;
+use indoc::indoc;
+
+use crate::{
+    test_ctx::TestCtx,
+    util::{compile_and_apply_doc, ServiceProperties},
+};
+
+const SVC_A: ServiceId =
+    ServiceId::from_raw_array(hex_literal!("e5462a0d22b54d9f9ca37bd96e9b9d8b"));
+const SVC_B: ServiceId =
+    ServiceId::from_raw_array(hex_literal!("015362d6655447c6b7f44865bd111c70"));
+
+const DOC: &str = indoc! {
+    r#"
+    [authly-document]
+    id = "bc9ce588-50c3-47d1-94c1-f88b21eaf299"
+
+    [[domain]]
+    label = "shared"
+
+    [[service-entity]]
+    eid = "s.e5462a0d22b54d9f9ca37bd96e9b9d8b"
+    label = "svc_a"
+
+    [[service-entity]]
+    eid = "s.015362d6655447c6b7f44865bd111c70"
+    label = "svc_b"
+
+    [[service-domain]]
+    service = "svc_a"
+    domain = "shared"
+
+    [[service-domain]]
+    service = "svc_b"
+    domain = "shared"
+
+    [[entity-property]]
+    namespace = "shared"
+    label = "role"
+    attributes = ["user", "admin"]
+
+    [[resource-property]]
+    namespace = "shared"
+    label = "action"
+    attributes = ["read"]
+
+    [[resource-property]]
+    namespace = "svc_b"
+    label = "kind"
+    attributes = ["secret"]
+
+    [[policy]]
+    label = "allow for users"
+    allow = "Subject.shared:role == shared:role:user"
+
+    [[policy]]
+    label = "allow for admins"
+    allow = "Subject.shared:role == shared:role:admin"
+
+    [[default-policy-binding]]
+    attributes = ["shared:action:read"]
+    policies = ["allow for users"]
+
+    [[policy-binding]]
+    attributes = ["svc_b:kind:secret"]
+    policies = ["allow for admins"]
+    "#
+};
+
+async fn eval_user_read(ctx: &TestCtx, svc_eid: ServiceId) -> PolicyValue {
+    let engine = policy_repo::load_svc_policy_engine(ctx.get_db(), svc_eid)
+        .await
+        .unwrap();
+    let props = ServiceProperties::load(svc_eid, ctx.get_db()).await;
+
+    engine
+        .eval(
+            &AccessControlParams {
+                resource_attrs: props.resource.translate([("shared", "action", "read")]),
+                subject_attrs: props.entity.translate([("shared", "role", "user")]),
+                ..Default::default()
+            },
+            &mut NoOpPolicyTracer,
+        )
+        .unwrap()
+}
+
+#[test_log::test(tokio::test)]
+async fn test_default_policy_binding_inherited() {
+    let ctx = TestCtx::new().inmemory_db().await;
+    compile_and_apply_doc(DOC, &ctx).await.unwrap();
+
+    // svc_a has no policy bindings of its own
+    let pol_a = policy_repo::load_svc_policies_with_bindings(ctx.get_db(), SVC_A)
+        .await
+        .unwrap();
+    assert_eq!(pol_a.policies.len(), 1);
+    assert_eq!(pol_a.bindings.len(), 1);
+
+    assert_eq!(eval_user_read(&ctx, SVC_A).await, PolicyValue::Allow);
+}
+
+#[test_log::test(tokio::test)]
+async fn test_default_policy_binding_overridden() {
+    let ctx = TestCtx::new().inmemory_db().await;
+    compile_and_apply_doc(DOC, &ctx).await.unwrap();
+
+    // svc_b only gets its own binding
+    let pol_b = policy_repo::load_svc_policies_with_bindings(ctx.get_db(), SVC_B)
+        .await
+        .unwrap();
+    assert_eq!(pol_b.policies.len(), 1);
+    assert_eq!(pol_b.bindings.len(), 1);
+
+    assert_eq!(eval_user_read(&ctx, SVC_B).await, PolicyValue::Deny);
+}
+
+#[test_log::test(tokio::test)]
+async fn test_default_policy_binding_replaced_on_reapply() {
+    let ctx = TestCtx::new().inmemory_db().await;
+    compile_and_apply_doc(DOC, &ctx).await.unwrap();
+
+    let without_default = DOC.replace("[[default-policy-binding]]", "[[policy-binding]]");
+    compile_and_apply_doc(&without_default, &ctx).await.unwrap();
+
+    // the former default binding is now an ordinary binding in the shared namespace, implied for both services
+    let pol_b = policy_repo::load_svc_policies_with_bindings(ctx.get_db(), SVC_B)
+        .await
+        .unwrap();
+    assert_eq!(pol_b.policies.len(), 2);
+    assert_eq!(eval_user_read(&ctx, SVC_B).await, PolicyValue::Allow);
+}
@@ -0,0 +1,373 @@
+//! Property tests of document compilation.
+//!
+//! Documents are generated from a small model that only produces valid cross-references,
+//! so every generated document is expected to compile.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write,
+};
+
+use authly_domain::{
+    document::{
+        assertion::parse_document,
+        compiled_document::{CompiledDocument, DocumentMeta},
+        doc_compiler::compile_doc,
+    },
+    id::is_reserved_id,
+    repo::service_repo::PropertyKind,
+};
+use proptest::prelude::*;
+
+use crate::{test_ctx::TestCtx, util::compile_and_apply_doc};
+
+#[derive(Clone, Debug)]
+struct DocModel {
+    services: usize,
+    personas: usize,
+    groups: Vec<Vec<usize>>,
+    properties: Vec<PropertyModel>,
+    assignments: Vec<(usize, usize, usize)>,
+    policies: Vec<PolicyModel>,
+    bindings: Vec<(usize, usize, Vec<usize>)>,
+}
+
+#[derive(Clone, Debug)]
+struct PropertyModel {
+    service: usize,
+    resource: bool,
+    attributes: usize,
+}
+
+#[derive(Clone, Debug)]
+enum PolicyModel {
+    /// Allow a service by its entity ID
+    Entity(usize),
+    /// Allow subjects having an attribute of an entity property
+    Attribute(usize, usize),
+}
+
+fn doc_model() -> impl Strategy<Value = DocModel> {
+    (
+        1..3usize,
+        0..4usize,
+        prop::collection::vec(prop::collection::vec(0..4usize, 0..4), 0..3),
+        prop::collection::vec(
+            (0..3usize, any::<bool>(), 1..4usize).prop_map(|(service, resource, attributes)| {
+                PropertyModel {
+                    service,
+                    resource,
+                    attributes,
+                }
+            }),
+            0..5,
+        ),
+        prop::collection::vec((0..4usize, 0..5usize, 0..4usize), 0..6),
+        prop::collection::vec(
+            prop_oneof![
+                (0..3usize).prop_map(PolicyModel::Entity),
+                (0..5usize, 0..4usize).prop_map(|(prop, attr)| PolicyModel::Attribute(prop, attr)),
+            ],
+            0..4,
+        ),
+        prop::collection::vec(
+            (0..5usize, 0..4usize, prop::collection::vec(0..4usize, 1..3)),
+            0..4,
+        ),
+    )
+        .prop_map(
+            |(services, personas, groups, properties, assignments, policies, bindings)| DocModel {
+                services,
+                personas,
+                groups,
+                properties,
+                assignments,
+                policies,
+                bindings,
+            },
+        )
+}
+
+impl DocModel {
+    fn property(&self, prop: usize) -> Option<&PropertyModel> {
+        self.properties.get(prop)
+    }
+
+    fn service(&self, property: &PropertyModel) -> usize {
+        property.service % self.services
+    }
+
+    /// The qualified name of an attribute, when the property exists and is of the requested kind
+    fn attribute(&self, prop: usize, attr: usize, resource: bool) -> Option<String> {
+        let property = self.property(prop).filter(|p| p.resource == resource)?;
+        Some(format!(
+            "svc{}:prop{prop}:a{}",
+            self.service(property),
+            attr % property.attributes
+        ))
+    }
+
+    /// The labels of the policies that can be rendered
+    fn policy_labels(&self) -> Vec<String> {
+        (0..self.policies.len())
+            .filter(|idx| self.render_policy(*idx).is_some())
+            .map(|idx| format!("policy{idx}"))
+            .collect()
+    }
+
+    fn render_policy(&self, idx: usize) -> Option<String> {
+        match &self.policies[idx] {
+            PolicyModel::Entity(service) => Some(format!(
+                "Subject.authly:entity == svc{}",
+                service % self.services
+            )),
+            PolicyModel::Attribute(prop, attr) => {
+                let attribute = self.attribute(*prop, *attr, false)?;
+                let property = self.property(*prop)?;
+                Some(format!(
+                    "Subject.svc{}:prop{prop} contains {attribute}",
+                    self.service(property)
+                ))
+            }
+        }
+    }
+
+    fn render(&self) -> String {
+        let mut toml = String::new();
+        writeln!(toml, "[authly-document]").unwrap();
+        writeln!(toml, "id = \"bc9ce588-50c3-47d1-94c1-f88b21eaf299\"").unwrap();
+
+        for svc in 0..self.services {
+            writeln!(toml, "\n[[service-entity]]").unwrap();
+            writeln!(toml, "eid = \"s.{:032x}\"", 0x10000 + svc).unwrap();
+            writeln!(toml, "label = \"svc{svc}\"").unwrap();
+        }
+
+        for persona in 0..self.personas {
+            writeln!(toml, "\n[[entity]]").unwrap();
+            writeln!(toml, "eid = \"p.{:032x}\"", 0x20000 + persona).unwrap();
+            writeln!(toml, "label = \"p{persona}\"").unwrap();
+        }
+
+        for (group, members) in self.groups.iter().enumerate() {
+            writeln!(toml, "\n[[entity]]").unwrap();
+            writeln!(toml, "eid = \"g.{:032x}\"", 0x30000 + group).unwrap();
+            writeln!(toml, "label = \"g{group}\"").unwrap();
+
+            let members: BTreeSet<_> = members
+                .iter()
+                .filter(|member| **member < self.personas)
+                .map(|member| format!("\"p{member}\""))
+                .collect();
+            if !members.is_empty() {
+                writeln!(toml, "\n[[members]]").unwrap();
+                writeln!(toml, "entity = \"g{group}\"").unwrap();
+                writeln!(
+                    toml,
+                    "members = [{}]",
+                    members.into_iter().collect::<Vec<_>>().join(", ")
+                )
+                .unwrap();
+            }
+        }
+
+        for (idx, property) in self.properties.iter().enumerate() {
+            let table = if property.resource {
+                "resource-property"
+            } else {
+                "entity-property"
+            };
+            let attributes: Vec<_> = (0..property.attributes)
+                .map(|attr| format!("\"a{attr}\""))
+                .collect();
+
+            writeln!(toml, "\n[[{table}]]").unwrap();
+            writeln!(toml, "namespace = \"svc{}\"", self.service(property)).unwrap();
+            writeln!(toml, "label = \"prop{idx}\"").unwrap();
+            writeln!(toml, "attributes = [{}]", attributes.join(", ")).unwrap();
+        }
+
+        for (persona, prop, attr) in &self.assignments {
+            let Some(attribute) = self.attribute(*prop, *attr, false) else {
+                continue;
+            };
+            if *persona >= self.personas {
+                continue;
+            }
+
+            writeln!(toml, "\n[[entity-attribute-assignment]]").unwrap();
+            writeln!(toml, "entity = \"p{persona}\"").unwrap();
+            writeln!(toml, "attributes = [\"{attribute}\"]").unwrap();
+        }
+
+        for idx in 0..self.policies.len() {
+            let Some(expr) = self.render_policy(idx) else {
+                continue;
+            };
+            writeln!(toml, "\n[[policy]]").unwrap();
+            writeln!(toml, "label = \"policy{idx}\"").unwrap();
+            writeln!(toml, "allow = \"{expr}\"").unwrap();
+        }
+
+        let policy_labels = self.policy_labels();
+        for (prop, attr, policies) in &self.bindings {
+            let Some(attribute) = self.attribute(*prop, *attr, true) else {
+                continue;
+            };
+            let policies: BTreeSet<_> = policies
+                .iter()
+                .filter_map(|idx| policy_labels.get(*idx))
+                .map(|label| format!("\"{label}\""))
+                .collect();
+            if policies.is_empty() {
+                continue;
+            }
+
+            writeln!(toml, "\n[[policy-binding]]").unwrap();
+            writeln!(toml, "attributes = [\"{attribute}\"]").unwrap();
+            writeln!(
+                toml,
+                "policies = [{}]",
+                policies.into_iter().collect::<Vec<_>>().join(", ")
+            )
+            .unwrap();
+        }
+
+        writeln!(toml, "\n[defaults]").unwrap();
+        writeln!(toml, "namespace = \"svc0\"").unwrap();
+
+        toml
+    }
+}
+
+async fn compile(toml: &str, ctx: &TestCtx) -> CompiledDocument {
+    let (doc, extensions) = parse_document(toml).unwrap();
+    compile_doc(ctx, doc, extensions, DocumentMeta::default())
+        .await
+        .unwrap_or_else(|errors| panic!("{errors:?}\n{toml}"))
+}
+
+/// The generated IDs of the document, by label
+fn generated_ids(compiled: &CompiledDocument) -> BTreeMap<String, [u8; 16]> {
+    let mut ids = BTreeMap::new();
+    for property in &compiled.data.domain_props {
+        ids.insert(property.label.clone(), property.id.to_raw_array());
+        for attribute in &property.attributes {
+            ids.insert(
+                format!("{}:{}", property.label, attribute.label),
+                attribute.id.to_raw_array(),
+            );
+        }
+    }
+    for policy in &compiled.data.policies {
+        ids.insert(policy.1.label.clone(), policy.0.to_raw_array());
+    }
+    ids
+}
+
+fn assert_invariants(model: &DocModel, compiled: &CompiledDocument) {
+    let data = &compiled.data;
+
+    // every generated ID is unique and outside the reserved range
+    let ids = generated_ids(compiled);
+    let unique: BTreeSet<_> = ids.values().collect();
+    assert_eq!(unique.len(), ids.len(), "duplicate IDs: {ids:?}");
+    assert!(ids
+        .values()
+        .all(|id| !is_reserved_id(u128::from_be_bytes(*id))));
+
+    assert_eq!(data.domain_props.len(), model.properties.len());
+    for property in &data.domain_props {
+        let idx: usize = property
+            .label
+            .strip_prefix("prop")
+            .unwrap()
+            .parse()
+            .unwrap();
+        let resource = model.properties[idx].resource;
+        assert_eq!(
+            property.kind,
+            if resource {
+                PropertyKind::Resource
+            } else {
+                PropertyKind::Entity
+            }
+        );
+    }
+
+    // every binding refers to attributes and policies of the document
+    let attributes: BTreeSet<_> = data
+        .domain_props
+        .iter()
+        .flat_map(|property| property.attributes.iter().map(|attr| attr.id))
+        .collect();
+    let policies: BTreeSet<_> = data.policies.iter().map(|policy| policy.0).collect();
+    for binding in &data.policy_bindings {
+        assert!(binding.attr_matcher.is_subset(&attributes));
+        assert!(binding.policies.is_subset(&policies));
+    }
+
+    for assignment in &data.entity_attribute_assignments {
+        assert!(attributes.contains(&assignment.attrid));
+    }
+}
+
+fn check_document(model: DocModel) {
+    let toml = model.render();
+
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(async {
+            let ctx = TestCtx::new().inmemory_db().await.supreme_instance().await;
+
+            let compiled = compile(&toml, &ctx).await;
+            assert_invariants(&model, &compiled);
+
+            // once applied, re-compiling reuses every ID
+            compile_and_apply_doc(&toml, &ctx)
+                .await
+                .unwrap_or_else(|err| panic!("{err:?}\n{toml}"));
+            let applied = compile(&toml, &ctx).await;
+            assert_invariants(&model, &applied);
+
+            compile_and_apply_doc(&toml, &ctx).await.unwrap();
+            let reapplied = compile(&toml, &ctx).await;
+            assert_eq!(generated_ids(&applied), generated_ids(&reapplied));
+        });
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(48))]
+
+    #[test]
+    fn test_compile_generated_documents(model in doc_model()) {
+        check_document(model);
+    }
+}
+
+/// A property with the same label in two services
+#[test]
+fn test_same_property_label_in_two_services() {
+    check_document(DocModel {
+        services: 2,
+        personas: 1,
+        groups: vec![],
+        properties: vec![
+            PropertyModel {
+                service: 0,
+                resource: false,
+                attributes: 2,
+            },
+            PropertyModel {
+                service: 1,
+                resource: true,
+                attributes: 1,
+            },
+        ],
+        assignments: vec![(0, 0, 1)],
+        policies: vec![PolicyModel::Attribute(0, 1), PolicyModel::Entity(1)],
+        bindings: vec![(1, 0, vec![0, 1])],
+    });
+}
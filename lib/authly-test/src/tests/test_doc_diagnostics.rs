@@ -0,0 +1,184 @@
+use std::collections::BTreeSet;
+
+use authly_common::id::{AnyId, PersonaId, PropId};
+use authly_domain::{
+    document::{
+        assertion::AssertionOutcome,
+        diagnostic::{diagnostics, Diagnostic, Position, Severity},
+        error::DocError,
+    },
+    id::BuiltinProp,
+    policy::error::PolicyCompileErrorKind,
+};
+use hexhex::hex_literal;
+use indoc::indoc;
+use serde_json::json;
+use serde_spanned::Spanned;
+
+use crate::{
+    test_ctx::TestCtx,
+    util::{compile_and_apply_doc, TestDocError},
+};
+
+/// The code of the diagnostic and the text its span points at
+fn code_and_text<'s>(source: &'s str, diagnostic: &Diagnostic) -> (&'static str, &'s str) {
+    (diagnostic.code, &source[diagnostic.location.span.clone()])
+}
+
+#[test_log::test(tokio::test)]
+async fn test_compile_errors_as_diagnostics() {
+    let ctx = TestCtx::new().inmemory_db().await;
+    let doc = indoc! {
+        r#"
+        [authly-document]
+        id = "bc9ce588-50c3-47d1-94c1-f88b21eaf299"
+
+        [[service-entity]]
+        eid = "s.e5462a0d22b54d9f9ca37bd96e9b9d8b"
+        label = "svc"
+
+        [[domain]]
+        label = "svc"
+
+        [[entity-attribute-assignment]]
+        entity = "nobody"
+        attributes = ["svc:role:user"]
+
+        [[policy]]
+        label = "empty"
+        "#
+    };
+
+    let TestDocError::Doc(errors) = compile_and_apply_doc(doc, &ctx).await.unwrap_err() else {
+        panic!()
+    };
+    let mut diagnostics = diagnostics(doc, &errors);
+    diagnostics.sort_by_key(|diagnostic| diagnostic.location.span.start);
+
+    assert_eq!(
+        diagnostics
+            .iter()
+            .map(|diagnostic| code_and_text(doc, diagnostic))
+            .collect::<Vec<_>>(),
+        vec![
+            ("name-defined-multiple-times", "\"svc\""),
+            ("unresolved-entity", "\"nobody\""),
+            ("policy-body-missing", "\"empty\""),
+        ]
+    );
+
+    let duplicate = &diagnostics[0];
+    assert_eq!(duplicate.severity, Severity::Error);
+    assert_eq!(duplicate.message, "`svc` is defined more than once");
+    assert_eq!(
+        duplicate.location.start,
+        Position {
+            line: 8,
+            character: 8
+        }
+    );
+    assert_eq!(duplicate.related.len(), 1);
+    assert_eq!(duplicate.related[0].message, "previously defined here");
+    assert_eq!(duplicate.related[0].location.start.line, 5);
+    assert_eq!(&doc[duplicate.related[0].location.span.clone()], "\"svc\"");
+
+    // the human rendering is kept
+    assert_eq!(
+        duplicate.to_string(),
+        "error[name-defined-multiple-times]: `svc` is defined more than once at 9:9\n  note: previously defined here at 6:9"
+    );
+}
+
+#[test]
+fn test_diagnostic_serialization() {
+    let source = "[[policy]]\nlabel = \"ø\" # \"x\"\n";
+    let span = source.rfind("\"x\"").unwrap()..source.len() - 1;
+    let diagnostic = Diagnostic::new(
+        source,
+        &Spanned::new(span.clone(), DocError::UnresolvedPolicy),
+    );
+
+    assert_eq!(
+        serde_json::to_value(&diagnostic).unwrap(),
+        json!({
+            "code": "unresolved-policy",
+            "severity": "error",
+            "message": "unresolved policy",
+            "location": {
+                "span": { "start": span.start, "end": span.end },
+                // columns count UTF-16 code units, `ø` is one
+                "start": { "line": 1, "character": 14 },
+                "end": { "line": 1, "character": 17 },
+            },
+        })
+    );
+}
+
+#[test]
+fn test_every_error_has_a_distinct_code() {
+    let persona: AnyId = PersonaId::from(hex_literal!("015362d6655447c6b7f44865bd111c70")).upcast();
+    let errors = vec![
+        DocError::Syntax("x".to_string()),
+        DocError::UnknownField("entity.lable".to_string()),
+        DocError::LocalSettingNotFound,
+        DocError::InvalidSettingValue("x".to_string()),
+        DocError::NameDefinedMultipleTimes(0..1, "x".to_string()),
+        DocError::UnresolvedDomain,
+        DocError::UnresolvedNamespace,
+        DocError::UnresolvedEntity,
+        DocError::UnresolvedProfile,
+        DocError::UnresolvedGroup,
+        DocError::UnresolvedService,
+        DocError::UnresolvedProperty,
+        DocError::UnresolvedAttribute,
+        DocError::UnresolvedPolicy,
+        DocError::MustBeAServiceId,
+        DocError::PolicyBodyMissing,
+        DocError::AmbiguousPolicyOutcome,
+        DocError::MetadataNotSupported,
+        DocError::InvalidHost("x".to_string()),
+        DocError::InvalidKubernetesAccount("x".to_string()),
+        DocError::ReservedUsername,
+        DocError::Policy(PolicyCompileErrorKind::Parse("x".to_string())),
+        DocError::Policy(PolicyCompileErrorKind::UnknownLabel("x".to_string())),
+        DocError::Policy(PolicyCompileErrorKind::UnknownNamespace("x".to_string())),
+        DocError::Policy(PolicyCompileErrorKind::UnknownProperty("x".to_string())),
+        DocError::Policy(PolicyCompileErrorKind::UnknownAttribute(
+            "x".to_string(),
+            "y".to_string(),
+        )),
+        DocError::Policy(PolicyCompileErrorKind::NoDefaultNamespace("x".to_string())),
+        DocError::Policy(PolicyCompileErrorKind::AmbiguousReference("x".to_string())),
+        DocError::Policy(PolicyCompileErrorKind::InvalidSchedule("x")),
+        DocError::Policy(PolicyCompileErrorKind::InvalidNetwork("x".to_string())),
+        DocError::Policy(PolicyCompileErrorKind::UnsupportedCedar("x")),
+        DocError::Policy(PolicyCompileErrorKind::Misc("x")),
+        DocError::PolicyAssertionFailed {
+            expected: AssertionOutcome::Allow,
+        },
+        DocError::IdentityConflict {
+            prop_id: PropId::from(BuiltinProp::Username),
+            entity: persona,
+            owner: persona,
+        },
+        DocError::ConstraintViolation,
+        DocError::Db("x".to_string()),
+    ];
+
+    let codes: BTreeSet<_> = errors.iter().map(DocError::code).collect();
+    assert_eq!(codes.len(), errors.len());
+
+    for error in &errors {
+        let code = error.code();
+        assert!(
+            code.chars().all(|c| c.is_ascii_lowercase() || c == '-'),
+            "{code}"
+        );
+        assert!(!error.to_string().is_empty(), "{code}");
+    }
+
+    assert_eq!(
+        DocError::Policy(PolicyCompileErrorKind::UnknownLabel("x".to_string())).code(),
+        "policy-unknown-label"
+    );
+}
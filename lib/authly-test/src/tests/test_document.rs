@@ -1,8 +1,9 @@
-use authly_common::id::ServiceId;
+use authly_common::id::{AnyId, PersonaId, ServiceId};
 use authly_domain::{
     ctx::{GetBuiltins, GetDb},
     document::error::DocError,
-    repo::{entity_repo, service_repo},
+    id::BuiltinProp,
+    repo::{crypto_repo, entity_repo, service_repo},
 };
 use hexhex::hex_literal;
 use indoc::indoc;
@@ -95,3 +96,144 @@ async fn test_store_doc_constraint_violation1() {
     ));
     assert_eq!("\"p@mail.com\"", &doc[spanned_error.span()]);
 }
+
+#[test_log::test(tokio::test)]
+async fn test_store_doc_identity_conflict_within_document() {
+    let ctx = TestCtx::new().inmemory_db().await.supreme_instance().await;
+    let doc = indoc! {
+        r#"
+        [authly-document]
+        id = "bc9ce588-50c3-47d1-94c1-f88b21eaf299"
+
+        [[entity]]
+        eid = "p.e5462a0d22b54d9f9ca37bd96e9b9d8b"
+        label = "persona1"
+        email = ["shared@mail.com"]
+
+        [[entity]]
+        eid = "p.015362d6655447c6b7f44865bd111c70"
+        label = "persona2"
+        email = ["Shared@mail.com"]
+        "#
+    };
+
+    let TestDocError::Doc(errors) = compile_and_apply_doc(doc, &ctx).await.unwrap_err() else {
+        panic!()
+    };
+    let spanned_error = errors.into_iter().next().unwrap();
+
+    let persona1: AnyId =
+        PersonaId::from(hex_literal!("e5462a0d22b54d9f9ca37bd96e9b9d8b")).upcast();
+    let persona2: AnyId =
+        PersonaId::from(hex_literal!("015362d6655447c6b7f44865bd111c70")).upcast();
+
+    let DocError::IdentityConflict {
+        prop_id,
+        entity,
+        owner,
+    } = spanned_error.as_ref()
+    else {
+        panic!("unexpected error: {spanned_error:?}");
+    };
+    assert_eq!(*prop_id, BuiltinProp::Email.into());
+    assert_eq!(*entity, persona2);
+    assert_eq!(*owner, persona1);
+    assert_eq!("\"Shared@mail.com\"", &doc[spanned_error.span()]);
+}
+
+#[test_log::test(tokio::test)]
+async fn test_store_doc_identity_conflict_across_documents() {
+    let ctx = TestCtx::new().inmemory_db().await.supreme_instance().await;
+    let doc_a = indoc! {
+        r#"
+        [authly-document]
+        id = "bc9ce588-50c3-47d1-94c1-f88b21eaf299"
+
+        [[entity]]
+        eid = "p.e5462a0d22b54d9f9ca37bd96e9b9d8b"
+        label = "persona1"
+        username = "persona"
+        "#
+    };
+    let doc_b = indoc! {
+        r#"
+        [authly-document]
+        id = "5c1d7e0c-2f5e-4b8e-9a51-0f5a5f4e7c21"
+
+        [[entity]]
+        eid = "p.015362d6655447c6b7f44865bd111c70"
+        label = "persona2"
+        username = "persona"
+        "#
+    };
+
+    let persona1: AnyId =
+        PersonaId::from(hex_literal!("e5462a0d22b54d9f9ca37bd96e9b9d8b")).upcast();
+    let persona2: AnyId =
+        PersonaId::from(hex_literal!("015362d6655447c6b7f44865bd111c70")).upcast();
+
+    // applied twice, re-application keeps the identity
+    compile_and_apply_doc(doc_a, &ctx).await.unwrap();
+
+    let TestDocError::Doc(errors) = compile_and_apply_doc(doc_b, &ctx).await.unwrap_err() else {
+        panic!()
+    };
+    let spanned_error = errors.into_iter().next().unwrap();
+
+    assert!(
+        matches!(
+            spanned_error.as_ref(),
+            DocError::IdentityConflict { entity, owner, .. } if *entity == persona2 && *owner == persona1
+        ),
+        "unexpected error: {spanned_error:?}"
+    );
+
+    // the original owner is preserved, also after another re-apply
+    compile_and_apply_doc(doc_a, &ctx).await.unwrap();
+    assert_eq!(
+        crypto_repo::lookup_obj_ident(&ctx, BuiltinProp::Username.into(), "persona")
+            .await
+            .unwrap(),
+        Some(persona1)
+    );
+}
+
+#[test_log::test(tokio::test)]
+async fn test_store_doc_service_domain_requires_domain() {
+    let ctx = TestCtx::new().inmemory_db().await;
+    let doc = indoc! {
+        r#"
+        [authly-document]
+        id = "bc9ce588-50c3-47d1-94c1-f88b21eaf299"
+
+        [[domain]]
+        label = "dom"
+
+        [[service-entity]]
+        eid = "s.e5462a0d22b54d9f9ca37bd96e9b9d8b"
+        label = "svc"
+
+        [[service-entity]]
+        eid = "s.015362d6655447c6b7f44865bd111c70"
+        label = "other_svc"
+
+        [[service-domain]]
+        service = "svc"
+        domain = "dom"
+
+        [[service-domain]]
+        service = "svc"
+        domain = "other_svc"
+        "#
+    };
+
+    let TestDocError::Doc(errors) = compile_and_apply_doc(doc, &ctx).await.unwrap_err() else {
+        panic!()
+    };
+    assert_eq!(errors.len(), 1);
+    let spanned_error = errors.into_iter().next().unwrap();
+
+    // the service namespace resolves, but is not a domain
+    assert!(matches!(spanned_error.as_ref(), DocError::UnresolvedDomain));
+    assert_eq!("\"other_svc\"", &doc[spanned_error.span()]);
+}
@@ -0,0 +1,217 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use authly_common::id::ServiceId;
+use authly_domain::{
+    ctx::GetDb,
+    document::import::{compose, ImportError},
+};
+use indoc::indoc;
+
+use crate::{
+    test_ctx::TestCtx,
+    util::{compile_and_apply_doc, ServiceProperties},
+};
+
+const SVC: ServiceId = ServiceId::from_uint(42);
+
+fn reader(files: &[(&str, &str)]) -> impl FnMut(&Path) -> anyhow::Result<String> {
+    let files: HashMap<PathBuf, String> = files
+        .iter()
+        .map(|(path, source)| (PathBuf::from(path), source.to_string()))
+        .collect();
+
+    move |path| {
+        files
+            .get(path)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("not found"))
+    }
+}
+
+#[test_log::test(tokio::test)]
+async fn test_compose_imported_fragment() {
+    let ctx = TestCtx::new().inmemory_db().await;
+    let document = indoc! {
+        r#"
+        [authly-document]
+        id = "bc9ce588-50c3-47d1-94c1-f88b21eaf299"
+
+        [[import]]
+        path = "common/properties.toml"
+
+        [[import]]
+        path = "./common/../common/properties.toml"
+
+        [[service-entity]]
+        eid = "s.0000000000000000000000000000002a"
+        label = "svc"
+        "#
+    };
+    let fragment = indoc! {
+        r#"
+        [[resource-property]]
+        namespace = "svc"
+        label = "kind"
+        attributes = ["trousers"]
+        "#
+    };
+
+    let composed = compose(
+        Path::new("/docs/main.toml"),
+        reader(&[
+            ("/docs/main.toml", document),
+            ("/docs/common/properties.toml", fragment),
+        ]),
+    )
+    .unwrap();
+
+    // a fragment imported twice is included once
+    assert_eq!(composed.files.len(), 2);
+
+    // spans point into the file they came from
+    let offset = composed.source.find("attributes").unwrap();
+    let (file, span) = composed.locate(offset..offset + 10).unwrap();
+    assert_eq!(file, Path::new("/docs/common/properties.toml"));
+    assert_eq!(&fragment[span], "attributes");
+
+    compile_and_apply_doc(&composed.source, &ctx).await.unwrap();
+
+    let props = ServiceProperties::load(SVC, ctx.get_db()).await;
+    assert_eq!(
+        props
+            .resource
+            .translate([("svc", "kind", "trousers")])
+            .len(),
+        1
+    );
+}
+
+#[test]
+fn test_import_cycle() {
+    let a = indoc! {
+        r#"
+        [authly-document]
+        id = "bc9ce588-50c3-47d1-94c1-f88b21eaf299"
+
+        [[import]]
+        path = "b.toml"
+        "#
+    };
+    let b = indoc! {
+        r#"
+        [[import]]
+        path = "c.toml"
+        "#
+    };
+    let c = indoc! {
+        r#"
+        [[import]]
+        path = "b.toml"
+        "#
+    };
+
+    let error = compose(
+        Path::new("/docs/a.toml"),
+        reader(&[
+            ("/docs/a.toml", a),
+            ("/docs/b.toml", b),
+            ("/docs/c.toml", c),
+        ]),
+    )
+    .unwrap_err();
+
+    let ImportError::Cycle(cycle) = error else {
+        panic!("unexpected error: {error:?}");
+    };
+    assert_eq!(
+        cycle,
+        [
+            "/docs/a.toml",
+            "/docs/b.toml",
+            "/docs/c.toml",
+            "/docs/b.toml"
+        ]
+        .map(PathBuf::from)
+    );
+}
+
+#[test]
+fn test_fragment_must_not_be_a_document() {
+    let a = indoc! {
+        r#"
+        [authly-document]
+        id = "bc9ce588-50c3-47d1-94c1-f88b21eaf299"
+
+        [[import]]
+        path = "b.toml"
+        "#
+    };
+
+    let error = compose(
+        Path::new("/docs/a.toml"),
+        reader(&[("/docs/a.toml", a), ("/docs/b.toml", a)]),
+    )
+    .unwrap_err();
+    assert!(matches!(error, ImportError::DocumentHeader(_)), "{error:?}");
+}
+
+#[test]
+fn test_import_outside_document_directory() {
+    let fragment = indoc! {
+        r#"
+        [[resource-property]]
+        namespace = "svc"
+        label = "kind"
+        attributes = ["trousers"]
+        "#
+    };
+
+    for import in ["/docs/common/properties.toml", "../etc/properties.toml"] {
+        let document = format!(
+            "[authly-document]\nid = \"bc9ce588-50c3-47d1-94c1-f88b21eaf299\"\n\n[[import]]\npath = \"{import}\"\n"
+        );
+
+        let error = compose(
+            Path::new("/docs/main.toml"),
+            reader(&[
+                ("/docs/main.toml", document.as_str()),
+                ("/docs/common/properties.toml", fragment),
+                ("/etc/properties.toml", fragment),
+            ]),
+        )
+        .unwrap_err();
+
+        let ImportError::OutsideRoot(importer, path) = error else {
+            panic!("unexpected error: {error:?}");
+        };
+        assert_eq!(importer, Path::new("/docs/main.toml"));
+        assert_eq!(path, Path::new(import));
+    }
+
+    // fragments can't lead out of the directory either
+    let document = indoc! {
+        r#"
+        [authly-document]
+        id = "bc9ce588-50c3-47d1-94c1-f88b21eaf299"
+
+        [[import]]
+        path = "common/properties.toml"
+        "#
+    };
+    let error = compose(
+        Path::new("/docs/main.toml"),
+        reader(&[
+            ("/docs/main.toml", document),
+            (
+                "/docs/common/properties.toml",
+                "[[import]]\npath = \"../../etc/properties.toml\"\n",
+            ),
+            ("/etc/properties.toml", fragment),
+        ]),
+    )
+    .unwrap_err();
+    assert!(matches!(error, ImportError::OutsideRoot(..)), "{error:?}");
+}
@@ -0,0 +1,139 @@
+use authly_common::id::ServiceId;
+use authly_domain::{
+    ctx::GetDb,
+    document::interpolate::{interpolate, InterpolationError},
+    repo::service_repo,
+};
+use indoc::indoc;
+
+use crate::{test_ctx::TestCtx, util::compile_and_apply_doc};
+
+const SVC: ServiceId = ServiceId::from_uint(42);
+
+const DOC: &str = indoc! {
+    r#"
+    [authly-document]
+    id = "bc9ce588-50c3-47d1-94c1-f88b21eaf299"
+
+    [[service-entity]]
+    eid = "s.0000000000000000000000000000002a"
+    label = "svc"
+    hosts = ["${AUTHLY_DOC_SVC_HOST}"]
+    "#
+};
+
+fn lookup(name: &str) -> Option<String> {
+    match name {
+        "AUTHLY_DOC_SVC_HOST" => Some("svc.example.com".to_string()),
+        "AUTHLY_DOC_UNSAFE" => Some("\"]\nlabel = \"other".to_string()),
+        _ => None,
+    }
+}
+
+#[test_log::test(tokio::test)]
+async fn test_interpolate_defined_variable() {
+    let ctx = TestCtx::new().inmemory_db().await.supreme_instance().await;
+
+    let doc = interpolate(DOC, lookup).unwrap();
+    compile_and_apply_doc(&doc, &ctx).await.unwrap();
+
+    assert_eq!(
+        service_repo::list_service_hosts(ctx.get_db(), SVC)
+            .await
+            .unwrap(),
+        vec!["svc.example.com"]
+    );
+}
+
+#[test]
+fn test_interpolate_undefined_variable() {
+    assert_eq!(
+        interpolate(DOC, |_| None).unwrap_err(),
+        InterpolationError::Undefined {
+            name: "AUTHLY_DOC_SVC_HOST".to_string(),
+            line: 7
+        }
+    );
+}
+
+#[test]
+fn test_interpolate_restrictions() {
+    // only prefixed variables are interpolated
+    assert_eq!(
+        interpolate("label = \"${HOME} $AUTHLY_DOC_SVC_HOST\"", lookup).unwrap(),
+        "label = \"${HOME} $AUTHLY_DOC_SVC_HOST\""
+    );
+
+    assert_eq!(
+        interpolate("hosts = [\"${AUTHLY_DOC_UNSAFE}\"]", lookup).unwrap_err(),
+        InterpolationError::UnsafeValue {
+            name: "AUTHLY_DOC_UNSAFE".to_string(),
+            line: 1
+        }
+    );
+
+    assert_eq!(
+        interpolate(
+            "[[policy]]\nallow = \"Subject.svc:role == ${AUTHLY_DOC_SVC_HOST}\"",
+            lookup
+        )
+        .unwrap_err(),
+        InterpolationError::PolicyExpression {
+            name: "AUTHLY_DOC_SVC_HOST".to_string(),
+            line: 2
+        }
+    );
+}
+
+#[test]
+fn test_interpolate_policy_tables() {
+    // multi-line policy expressions
+    assert_eq!(
+        interpolate(
+            "[[policy]]\nlabel = \"p\"\nallow = \"\"\"\nSubject.svc:role == ${AUTHLY_DOC_SVC_HOST}\n\"\"\"",
+            lookup
+        )
+        .unwrap_err(),
+        InterpolationError::PolicyExpression {
+            name: "AUTHLY_DOC_SVC_HOST".to_string(),
+            line: 4
+        }
+    );
+
+    // policy bindings
+    assert_eq!(
+        interpolate(
+            "[[policy-binding]]\nattributes = [\"${AUTHLY_DOC_SVC_HOST}\"]\npolicies = [\"p\"]",
+            lookup
+        )
+        .unwrap_err(),
+        InterpolationError::PolicyExpression {
+            name: "AUTHLY_DOC_SVC_HOST".to_string(),
+            line: 2
+        }
+    );
+}
+
+#[test]
+fn test_interpolate_only_string_values() {
+    let source = indoc! {
+        r#"
+        # hosts = ["${AUTHLY_DOC_UNDEFINED}"]
+        [[service-entity]]
+        label = "svc" # ${AUTHLY_DOC_UNDEFINED}
+        hosts = ["${AUTHLY_DOC_SVC_HOST}"]
+        "#
+    };
+
+    assert_eq!(
+        interpolate(source, lookup).unwrap(),
+        indoc! {
+            r#"
+            # hosts = ["${AUTHLY_DOC_UNDEFINED}"]
+            [[service-entity]]
+            label = "svc" # ${AUTHLY_DOC_UNDEFINED}
+            hosts = ["svc.example.com"]
+            "#
+        }
+    );
+}
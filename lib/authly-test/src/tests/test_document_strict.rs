@@ -0,0 +1,119 @@
+use std::fs;
+
+use authly_domain::document::{error::DocError, strict::check_unknown_fields};
+use indoc::indoc;
+
+/// The unknown fields of the source in source order,
+/// with the text their spans point at and its line number
+fn unknown_fields(source: &str) -> Vec<(String, &str, usize)> {
+    let mut errors = check_unknown_fields(source).unwrap_err();
+    errors.sort_by_key(|error| error.span().start);
+
+    errors
+        .into_iter()
+        .map(|error| match error.get_ref() {
+            DocError::UnknownField(path) => {
+                let span = error.span();
+                let line = source[..span.start].matches('\n').count() + 1;
+                (path.clone(), &source[span], line)
+            }
+            other => panic!("unexpected error: {other:?}"),
+        })
+        .collect()
+}
+
+#[test]
+fn test_misspelled_key_is_reported_at_its_location() {
+    let source = indoc! {
+        r#"
+        [authly-document]
+        id = "bc9ce588-50c3-47d1-94c1-f88b21eaf299"
+
+        [[service-entity]]
+        eid = "s.e5462a0d22b54d9f9ca37bd96e9b9d8b"
+        label = "svc"
+        kubernetes-account = { name = "svc", namepsace = "apps" }
+
+        [[entity-property]]
+        namespace = "svc"
+        label = "role"
+        atributes = ["user"]
+        "#
+    };
+
+    assert_eq!(
+        unknown_fields(source),
+        vec![
+            (
+                "service-entity.kubernetes-account.namepsace".to_string(),
+                "namepsace",
+                7
+            ),
+            ("entity-property.atributes".to_string(), "atributes", 12),
+        ]
+    );
+}
+
+#[test]
+fn test_unknown_table_is_reported() {
+    let source = indoc! {
+        r#"
+        [authly-document]
+        id = "bc9ce588-50c3-47d1-94c1-f88b21eaf299"
+
+        [[polcy]]
+        label = "allow"
+        allow = "Subject.authly:entity == svc"
+
+        [[policy-assertion]]
+        subject = "svc"
+        expected = "allow"
+        "#
+    };
+
+    assert_eq!(
+        unknown_fields(source),
+        vec![
+            ("polcy".to_string(), "polcy", 4),
+            ("policy-assertion.expected".to_string(), "expected", 10),
+        ]
+    );
+}
+
+#[test]
+fn test_free_form_tables_are_not_checked() {
+    let source = indoc! {
+        r#"
+        [authly-document]
+        id = "bc9ce588-50c3-47d1-94c1-f88b21eaf299"
+
+        [local-settings]
+        POLICY_TIMEZONE = "+02:00"
+
+        [[domain]]
+        label = "cms"
+        metadata = { any = { nested = "value" } }
+        "#
+    };
+
+    check_unknown_fields(source).unwrap();
+}
+
+#[test]
+fn test_documentation_examples_are_strictly_valid() {
+    for dir in [
+        "../../docs/src/examples/clause_examples",
+        "../../docs/src/examples/full_example",
+        "../../examples/demo",
+    ] {
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_some_and(|ext| ext == "toml") {
+                let source = fs::read_to_string(&path).unwrap();
+                if let Err(errors) = check_unknown_fields(&source) {
+                    panic!("{path:?}: {errors:?}");
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,323 @@
+use authly_common::id::{AttrId, EntityId, PersonaId, ServiceId};
+use authly_domain::{
+    bus::entity_events::{
+        self, AttributeChange, EntityAttributeEvent, EntityAttributeSubscription,
+    },
+    clock::{Clock, ManualClock},
+    id::BuiltinAttr,
+    IsLeaderDb,
+};
+use authly_service::proto::admin_server::{
+    proto::{self, authly_admin_client::AuthlyAdminClient},
+    AuthlyAdminServerImpl,
+};
+use hexhex::hex_literal;
+use indoc::indoc;
+use time::{Duration, OffsetDateTime};
+
+use crate::{
+    test_ctx::TestCtx,
+    util::{compile_and_apply_doc, tonic_request},
+};
+
+const PERSONA: PersonaId =
+    PersonaId::from_raw_array(hex_literal!("6d2b9c5e0a4f4f0c9e3b8a7d1c2e4f60"));
+const SVC_SYNC: ServiceId =
+    ServiceId::from_raw_array(hex_literal!("e5462a0d22b54d9f9ca37bd96e9b9d8b"));
+
+const DOC_SUBSCRIBER: &str = indoc! {
+    r#"
+    [authly-document]
+    id = "bc9ce588-50c3-47d1-94c1-f88b21eaf299"
+
+    [[service-entity]]
+    eid = "s.e5462a0d22b54d9f9ca37bd96e9b9d8b"
+    label = "svc_sync"
+    attributes = ["authly:role:admin"]
+    "#
+};
+
+const DOC_ADMIN: &str = indoc! {
+    r#"
+    [authly-document]
+    id = "9a3c1f0e-6b2d-4e8a-9c5f-1d2e3f4a5b6c"
+
+    [[entity]]
+    eid = "p.6d2b9c5e0a4f4f0c9e3b8a7d1c2e4f60"
+    label = "user"
+
+    [[entity-attribute-assignment]]
+    entity = "p.6d2b9c5e0a4f4f0c9e3b8a7d1c2e4f60"
+    attributes = ["authly:role:admin"]
+    "#
+};
+
+const DOC_APPLY_DOCUMENT: &str = indoc! {
+    r#"
+    [authly-document]
+    id = "9a3c1f0e-6b2d-4e8a-9c5f-1d2e3f4a5b6c"
+
+    [[entity]]
+    eid = "p.6d2b9c5e0a4f4f0c9e3b8a7d1c2e4f60"
+    label = "user"
+
+    [[entity-attribute-assignment]]
+    entity = "p.6d2b9c5e0a4f4f0c9e3b8a7d1c2e4f60"
+    attributes = ["authly:role:apply_document"]
+    "#
+};
+
+fn summary(events: &[EntityAttributeEvent]) -> Vec<(EntityId, AttrId, AttributeChange)> {
+    events
+        .iter()
+        .map(|event| (event.eid, event.attr_id, event.change))
+        .collect()
+}
+
+#[test_log::test(tokio::test)]
+async fn test_attribute_changes_emit_events() {
+    let ctx = TestCtx::new().inmemory_db().await.supreme_instance().await;
+    let mut subscription = EntityAttributeSubscription::new(&ctx, 0);
+
+    // the document is applied twice, the second time must not produce events
+    compile_and_apply_doc(DOC_ADMIN, &ctx).await.unwrap();
+
+    assert_eq!(
+        summary(&subscription.poll(&ctx, 100).await.unwrap()),
+        vec![(
+            PERSONA.upcast(),
+            BuiltinAttr::AuthlyRoleAdmin.into(),
+            AttributeChange::Added
+        )]
+    );
+
+    compile_and_apply_doc(DOC_APPLY_DOCUMENT, &ctx)
+        .await
+        .unwrap();
+
+    let mut events = summary(&subscription.poll(&ctx, 100).await.unwrap());
+    events.sort_by_key(|(_, _, change)| *change == AttributeChange::Added);
+
+    assert_eq!(
+        events,
+        vec![
+            (
+                PERSONA.upcast(),
+                BuiltinAttr::AuthlyRoleAdmin.into(),
+                AttributeChange::Removed
+            ),
+            (
+                PERSONA.upcast(),
+                BuiltinAttr::AuthlyRoleApplyDocument.into(),
+                AttributeChange::Added
+            ),
+        ]
+    );
+    assert!(subscription.poll(&ctx, 100).await.unwrap().is_empty());
+}
+
+#[test_log::test(tokio::test)]
+async fn test_reconnecting_subscriber_receives_missed_events() {
+    let ctx = TestCtx::new().inmemory_db().await.supreme_instance().await;
+
+    let mut subscription = EntityAttributeSubscription::new(&ctx, 0);
+    compile_and_apply_doc(DOC_ADMIN, &ctx).await.unwrap();
+    assert_eq!(subscription.next_batch(&ctx, 100).await.unwrap().len(), 1);
+
+    // the subscriber goes away, remembering its cursor
+    let cursor = subscription.cursor();
+    drop(subscription);
+
+    compile_and_apply_doc(DOC_APPLY_DOCUMENT, &ctx)
+        .await
+        .unwrap();
+
+    let mut subscription = EntityAttributeSubscription::new(&ctx, cursor);
+    let missed = subscription.next_batch(&ctx, 100).await.unwrap();
+    assert_eq!(missed.len(), 2);
+    assert!(missed.iter().all(|event| event.seq > cursor));
+
+    // a connected subscriber is woken up by new changes
+    let waiting = tokio::spawn({
+        let ctx = ctx.clone();
+        async move { subscription.next_batch(&ctx, 100).await.unwrap() }
+    });
+
+    compile_and_apply_doc(DOC_ADMIN, &ctx).await.unwrap();
+
+    assert_eq!(waiting.await.unwrap().len(), 2);
+}
+
+#[test_log::test(tokio::test)]
+async fn test_tail_purges_acknowledged_events() {
+    let ctx = TestCtx::new().inmemory_db().await.supreme_instance().await;
+    compile_and_apply_doc(DOC_SUBSCRIBER, &ctx).await.unwrap();
+    compile_and_apply_doc(DOC_ADMIN, &ctx).await.unwrap();
+
+    let mut client = AuthlyAdminClient::new(AuthlyAdminServerImpl::new_service(ctx.clone()));
+    let mut stream = client
+        .tail_entity_attribute_events(tonic_request(
+            proto::TailEventsRequest { after: 0 },
+            SVC_SYNC,
+        ))
+        .await
+        .unwrap()
+        .into_inner();
+
+    // the role of the subscriber itself, then the role of the persona
+    stream.message().await.unwrap().unwrap();
+    let event = stream.message().await.unwrap().unwrap();
+    assert_eq!(event.entity_id, PERSONA.to_string());
+    assert_eq!(
+        event.attribute_id,
+        AttrId::from(BuiltinAttr::AuthlyRoleAdmin).to_string()
+    );
+    assert!(event.added);
+    drop(stream);
+
+    // resuming acknowledges the processed events, which are then purged
+    let mut stream = client
+        .tail_entity_attribute_events(tonic_request(
+            proto::TailEventsRequest { after: event.seq },
+            SVC_SYNC,
+        ))
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(EntityAttributeSubscription::new(&ctx, 0)
+        .poll(&ctx, 100)
+        .await
+        .unwrap()
+        .is_empty());
+
+    // new events are streamed as they are written
+    compile_and_apply_doc(DOC_APPLY_DOCUMENT, &ctx)
+        .await
+        .unwrap();
+    let next = stream.message().await.unwrap().unwrap();
+    assert!(next.seq > event.seq);
+    assert_eq!(next.entity_id, PERSONA.to_string());
+}
+
+#[test_log::test(tokio::test)]
+async fn test_events_are_stamped_by_the_clock() {
+    let clock = ManualClock::new(OffsetDateTime::now_utc() - Duration::days(3));
+    let ctx = TestCtx::new()
+        .inmemory_db()
+        .await
+        .supreme_instance()
+        .await
+        .with_clock(clock.clone());
+
+    compile_and_apply_doc(DOC_ADMIN, &ctx).await.unwrap();
+    clock.advance(Duration::hours(1));
+    compile_and_apply_doc(DOC_APPLY_DOCUMENT, &ctx)
+        .await
+        .unwrap();
+
+    let events = EntityAttributeSubscription::new(&ctx, 0)
+        .poll(&ctx, 100)
+        .await
+        .unwrap();
+    assert_eq!(events.len(), 3);
+
+    // the removal is stamped with the time of the application, like the additions
+    for event in &events[1..] {
+        assert_eq!(
+            event.created_at.unix_timestamp(),
+            clock.now().unix_timestamp()
+        );
+    }
+}
+
+#[test_log::test(tokio::test)]
+async fn test_unacknowledged_events_expire() {
+    let clock = ManualClock::new(OffsetDateTime::now_utc());
+    let ctx = TestCtx::new()
+        .inmemory_db()
+        .await
+        .supreme_instance()
+        .await
+        .with_clock(clock.clone());
+
+    // without subscribers, events are kept until they expire
+    compile_and_apply_doc(DOC_ADMIN, &ctx).await.unwrap();
+    let events = |ctx: TestCtx| async move {
+        EntityAttributeSubscription::new(&ctx, 0)
+            .poll(&ctx, 100)
+            .await
+            .unwrap()
+    };
+
+    assert_eq!(
+        entity_events::purge_expired_events(&ctx, IsLeaderDb(true), clock.now())
+            .await
+            .unwrap(),
+        0
+    );
+    assert_eq!(events(ctx.clone()).await.len(), 1);
+
+    assert_eq!(
+        entity_events::purge_expired_events(
+            &ctx,
+            IsLeaderDb(false),
+            clock.now() + Duration::days(31)
+        )
+        .await
+        .unwrap(),
+        0
+    );
+    assert_eq!(
+        entity_events::purge_expired_events(
+            &ctx,
+            IsLeaderDb(true),
+            clock.now() + Duration::days(31)
+        )
+        .await
+        .unwrap(),
+        1
+    );
+    assert!(events(ctx.clone()).await.is_empty());
+}
+
+#[test_log::test(tokio::test)]
+async fn test_abandoned_subscriber_is_forgotten() {
+    let clock = ManualClock::new(OffsetDateTime::now_utc() - Duration::days(31));
+    let ctx = TestCtx::new()
+        .inmemory_db()
+        .await
+        .supreme_instance()
+        .await
+        .with_clock(clock.clone());
+
+    // a subscriber that never comes back holds back the purge of acknowledged events
+    entity_events::acknowledge_events(&ctx, ServiceId::random(), 0)
+        .await
+        .unwrap();
+
+    clock.set(OffsetDateTime::now_utc());
+    compile_and_apply_doc(DOC_ADMIN, &ctx).await.unwrap();
+    let events = EntityAttributeSubscription::new(&ctx, 0)
+        .poll(&ctx, 100)
+        .await
+        .unwrap();
+    entity_events::acknowledge_events(&ctx, SVC_SYNC, events.last().unwrap().seq)
+        .await
+        .unwrap();
+    assert_eq!(
+        EntityAttributeSubscription::new(&ctx, 0)
+            .poll(&ctx, 100)
+            .await
+            .unwrap()
+            .len(),
+        events.len()
+    );
+
+    // once forgotten, the events acknowledged by the remaining subscribers are purged
+    assert_eq!(
+        entity_events::purge_expired_events(&ctx, IsLeaderDb(true), clock.now())
+            .await
+            .unwrap(),
+        events.len()
+    );
+}
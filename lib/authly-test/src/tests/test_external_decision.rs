@@ -0,0 +1,212 @@
+use std::{borrow::Cow, time::Duration};
+
+use authly_common::{
+    id::{AttrId, Id128DynamicArrayConv, ServiceId},
+    proto::service::{self as proto, authly_service_client::AuthlyServiceClient},
+};
+use authly_domain::{
+    ctx::GetDb,
+    settings::{Setting, Settings},
+};
+use authly_service::proto::service_server::AuthlyServiceServerImpl;
+use hexhex::hex_literal;
+use indoc::indoc;
+use serde_json::json;
+use wiremock::{
+    matchers::{method, path},
+    Mock, MockServer, ResponseTemplate,
+};
+
+use crate::{
+    test_ctx::TestCtx,
+    util::{compile_and_apply_doc, tonic_request, ServiceProperties},
+};
+
+const SVC: ServiceId = ServiceId::from_raw_array(hex_literal!("e5462a0d22b54d9f9ca37bd96e9b9d8b"));
+
+const DOC: &str = indoc! {
+    r#"
+    [authly-document]
+    id = "bc9ce588-50c3-47d1-94c1-f88b21eaf299"
+
+    [[service-entity]]
+    eid = "s.e5462a0d22b54d9f9ca37bd96e9b9d8b"
+    label = "svc"
+
+    [[entity-property]]
+    namespace = "svc"
+    label = "trait"
+    attributes = ["has_legs", "has_wings"]
+
+    [[resource-property]]
+    namespace = "svc"
+    label = "kind"
+    attributes = ["trousers"]
+
+    [[policy]]
+    label = "allow for legged creatures"
+    allow = "Subject.svc:trait == svc:trait:has_legs"
+
+    [[policy-binding]]
+    attributes = ["svc:kind:trousers"]
+    policies = ["allow for legged creatures"]
+    "#
+};
+
+/// Ask for trousers on behalf of a subject with the given trait, returning the decision value
+async fn access_control(ctx: &TestCtx, subject_trait: &str) -> i32 {
+    let props = ServiceProperties::load(SVC, ctx.get_db()).await;
+
+    AuthlyServiceClient::new(AuthlyServiceServerImpl::new_service(ctx.clone()))
+        .access_control(tonic_request(
+            proto::AccessControlRequest {
+                resource_attributes: attrs_to_proto(
+                    props.resource.translate([("svc", "kind", "trousers")]),
+                ),
+                peer_entity_attributes: attrs_to_proto(props.entity.translate([(
+                    "svc",
+                    "trait",
+                    subject_trait,
+                )])),
+                ..Default::default()
+            },
+            SVC,
+        ))
+        .await
+        .unwrap()
+        .into_inner()
+        .value
+}
+
+fn attrs_to_proto<B: From<Vec<u8>>>(attrs: impl IntoIterator<Item = AttrId>) -> Vec<B> {
+    attrs
+        .into_iter()
+        .map(|attr| attr.to_array_dynamic().to_vec().into())
+        .collect()
+}
+
+/// A policy decision point answering every request with `response`
+async fn mock_pdp(response: ResponseTemplate) -> MockServer {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/v1/data/authly/allow"))
+        .respond_with(response)
+        .mount(&server)
+        .await;
+    server
+}
+
+async fn external_decision_ctx(pdp: &MockServer, fail_mode: &'static str) -> TestCtx {
+    let mut settings = Settings::default();
+    settings
+        .try_set(
+            Setting::ExternalDecisionPoint,
+            Cow::Owned(format!("svc={}/v1/data/authly/allow", pdp.uri())),
+        )
+        .unwrap();
+    settings
+        .try_set(Setting::ExternalDecisionTimeout, Cow::Borrowed("100ms"))
+        .unwrap();
+    settings
+        .try_set(Setting::ExternalDecisionFailMode, Cow::Borrowed(fail_mode))
+        .unwrap();
+
+    let ctx = TestCtx::new()
+        .inmemory_db()
+        .await
+        .supreme_instance()
+        .await
+        .with_settings(settings);
+    compile_and_apply_doc(DOC, &ctx).await.unwrap();
+    ctx
+}
+
+#[test_log::test(tokio::test)]
+async fn test_external_decision_allow() {
+    let pdp = mock_pdp(ResponseTemplate::new(200).set_body_json(json!({ "result": true }))).await;
+    let ctx = external_decision_ctx(&pdp, "local").await;
+
+    // denied by the local policies, but the external decision is used
+    assert_eq!(access_control(&ctx, "has_wings").await, 1);
+
+    let requests = pdp.received_requests().await.unwrap();
+    assert_eq!(requests.len(), 1);
+    let body: serde_json::Value = serde_json::from_slice(&requests[0].body).unwrap();
+    assert_eq!(
+        body["input"]["serviceId"],
+        serde_json::to_value(SVC).unwrap()
+    );
+    assert_eq!(
+        body["input"]["resourceAttributes"]
+            .as_array()
+            .unwrap()
+            .len(),
+        1
+    );
+    assert_eq!(
+        body["input"]["subjectAttributes"].as_array().unwrap().len(),
+        1
+    );
+}
+
+#[test_log::test(tokio::test)]
+async fn test_external_decision_deny() {
+    let pdp = mock_pdp(ResponseTemplate::new(200).set_body_json(json!({ "result": false }))).await;
+    let ctx = external_decision_ctx(&pdp, "local").await;
+
+    // allowed by the local policies, but the external decision is used
+    assert_eq!(access_control(&ctx, "has_legs").await, 0);
+}
+
+fn slow_response() -> ResponseTemplate {
+    ResponseTemplate::new(200)
+        .set_body_json(json!({ "result": true }))
+        .set_delay(Duration::from_secs(5))
+}
+
+#[test_log::test(tokio::test)]
+async fn test_external_decision_timeout_falls_back_to_local() {
+    let pdp = mock_pdp(slow_response()).await;
+    let ctx = external_decision_ctx(&pdp, "local").await;
+
+    assert_eq!(access_control(&ctx, "has_legs").await, 1);
+    assert_eq!(access_control(&ctx, "has_wings").await, 0);
+}
+
+#[test_log::test(tokio::test)]
+async fn test_external_decision_timeout_fail_open() {
+    let pdp = mock_pdp(slow_response()).await;
+    let ctx = external_decision_ctx(&pdp, "open").await;
+
+    assert_eq!(access_control(&ctx, "has_wings").await, 1);
+}
+
+#[test_log::test(tokio::test)]
+async fn test_external_decision_timeout_fail_closed() {
+    let pdp = mock_pdp(slow_response()).await;
+    let ctx = external_decision_ctx(&pdp, "closed").await;
+
+    assert_eq!(access_control(&ctx, "has_legs").await, 0);
+}
+
+#[test_log::test(tokio::test)]
+async fn test_external_decision_other_service_is_local() {
+    let pdp = mock_pdp(ResponseTemplate::new(200).set_body_json(json!({ "result": false }))).await;
+    let mut settings = Settings::default();
+    settings
+        .try_set(
+            Setting::ExternalDecisionPoint,
+            Cow::Owned(format!("other_svc={}/v1/data/authly/allow", pdp.uri())),
+        )
+        .unwrap();
+    let ctx = TestCtx::new()
+        .inmemory_db()
+        .await
+        .supreme_instance()
+        .await
+        .with_settings(settings);
+    compile_and_apply_doc(DOC, &ctx).await.unwrap();
+
+    assert_eq!(access_control(&ctx, "has_legs").await, 1);
+    assert!(pdp.received_requests().await.unwrap().is_empty());
+}
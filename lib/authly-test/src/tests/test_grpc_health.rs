@@ -0,0 +1,78 @@
+use authly_common::proto::service::authly_service_server;
+use authly_domain::{clock_skew::ClockSkew, ctx::GetDb, health::Health, stats::RaftRole};
+use authly_service::proto::health_server::{AuthlyHealthServerImpl, READ_ONLY_METADATA};
+use tonic_health::pb::{
+    health_check_response::ServingStatus, health_client::HealthClient, HealthCheckRequest,
+};
+
+use crate::test_ctx::TestCtx;
+
+async fn check(ctx: &TestCtx, service: &str) -> tonic::Result<ServingStatus> {
+    let response = HealthClient::new(AuthlyHealthServerImpl::new_service(ctx.clone()))
+        .check(tonic::Request::new(HealthCheckRequest {
+            service: service.to_string(),
+        }))
+        .await?;
+
+    Ok(response.into_inner().status())
+}
+
+#[test_log::test(tokio::test)]
+async fn test_grpc_health_serving() {
+    let ctx = TestCtx::new().inmemory_db().await;
+
+    assert_eq!(check(&ctx, "").await.unwrap(), ServingStatus::Serving);
+    assert_eq!(
+        check(&ctx, authly_service_server::SERVICE_NAME)
+            .await
+            .unwrap(),
+        ServingStatus::Serving
+    );
+    assert_eq!(
+        check(&ctx, "unknown.Service").await.unwrap_err().code(),
+        tonic::Code::NotFound
+    );
+}
+
+#[test_log::test(tokio::test)]
+async fn test_grpc_health_read_write_metadata() {
+    let ctx = TestCtx::new().inmemory_db().await;
+
+    let response = HealthClient::new(AuthlyHealthServerImpl::new_service(ctx.clone()))
+        .check(tonic::Request::new(HealthCheckRequest {
+            service: String::new(),
+        }))
+        .await
+        .unwrap();
+
+    assert!(response.metadata().get(READ_ONLY_METADATA).is_none());
+}
+
+#[test]
+fn test_leaderless_read_only_node_is_serving() {
+    let health = Health {
+        db_healthy: true,
+        raft_role: RaftRole::Candidate,
+        read_only: true,
+        clock_skew: ClockSkew::InSync,
+    };
+    assert!(health.is_serving());
+
+    assert!(!Health {
+        raft_role: RaftRole::Shutdown,
+        ..health
+    }
+    .is_serving());
+}
+
+#[test_log::test(tokio::test)]
+async fn test_grpc_health_db_unavailable() {
+    let ctx = TestCtx::new().inmemory_db().await;
+
+    // the in-memory database has a single connection, holding it makes the database unresponsive
+    let conn = ctx.get_db().get().await.unwrap();
+    assert_eq!(check(&ctx, "").await.unwrap(), ServingStatus::NotServing);
+
+    drop(conn);
+    assert_eq!(check(&ctx, "").await.unwrap(), ServingStatus::Serving);
+}
@@ -0,0 +1,270 @@
+use std::{
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+use authly_common::{
+    id::{Id128DynamicArrayConv, ServiceId},
+    mtls_server::PeerServiceEntity,
+    proto::service::{self as proto, authly_service_client::AuthlyServiceClient},
+};
+use authly_domain::{
+    ctx::GetDb,
+    request_id::{RequestId, X_REQUEST_ID},
+};
+use authly_service::proto::{
+    peer_auth::{MethodRoles, PeerAuth},
+    service_server::{AuthlyServiceServerImpl, ALLOWED_METHODS},
+};
+use hexhex::hex_literal;
+use indoc::indoc;
+use tonic::{body::Body, codegen::Service, Code};
+
+use crate::{
+    test_ctx::TestCtx,
+    util::{compile_and_apply_doc, tonic_request, ServiceProperties},
+};
+
+const SVC_A: ServiceId =
+    ServiceId::from_raw_array(hex_literal!("e5462a0d22b54d9f9ca37bd96e9b9d8b"));
+const SVC_B: ServiceId =
+    ServiceId::from_raw_array(hex_literal!("015362d6655447c6b7f44865bd111c70"));
+
+const METHODS: &[MethodRoles] = &[MethodRoles {
+    method: "Call",
+    roles: &[],
+    checked_by_handler: false,
+}];
+
+/// An authenticated call to a method of the test service
+fn authenticated_request(method: &str) -> http::Request<Body> {
+    let mut req = http::Request::new(Body::empty());
+    *req.uri_mut() = format!("/test.Test/{method}").parse().unwrap();
+    req.extensions_mut()
+        .insert(PeerServiceEntity(ServiceId::random()));
+    req
+}
+
+/// Records the request ID of every call that reaches it
+#[derive(Clone, Default)]
+struct Handler {
+    calls: Arc<Mutex<Vec<Option<String>>>>,
+}
+
+impl Service<http::Request<Body>> for Handler {
+    type Response = http::Response<Body>;
+    type Error = std::convert::Infallible;
+    type Future = std::future::Ready<Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, _req: http::Request<Body>) -> Self::Future {
+        self.calls
+            .lock()
+            .unwrap()
+            .push(RequestId::current().map(|request_id| request_id.0));
+        std::future::ready(Ok(http::Response::new(Body::empty())))
+    }
+}
+
+#[test_log::test(tokio::test)]
+async fn test_unauthenticated_call_does_not_reach_handler() {
+    let ctx = TestCtx::new().inmemory_db().await;
+    let handler = Handler::default();
+    let mut svc = PeerAuth::new(handler.clone(), ctx, METHODS);
+
+    let response = svc.call(http::Request::new(Body::empty())).await.unwrap();
+    let status = tonic::Status::from_header_map(response.headers()).unwrap();
+
+    assert_eq!(status.code(), Code::Unauthenticated);
+    assert!(handler.calls.lock().unwrap().is_empty());
+}
+
+#[test_log::test(tokio::test)]
+async fn test_authenticated_call_has_request_id() {
+    let ctx = TestCtx::new().inmemory_db().await;
+    let handler = Handler::default();
+    let mut svc = PeerAuth::new(handler.clone(), ctx, METHODS);
+
+    let mut req = authenticated_request("Call");
+    req.headers_mut()
+        .insert(X_REQUEST_ID, "grpc-request".parse().unwrap());
+    svc.call(req).await.unwrap();
+
+    // calls without a request ID get a generated one
+    svc.call(authenticated_request("Call")).await.unwrap();
+
+    let calls = handler.calls.lock().unwrap();
+    assert_eq!(calls.len(), 2);
+    assert_eq!(calls[0].as_deref(), Some("grpc-request"));
+    assert!(calls[1].as_ref().is_some_and(|id| !id.is_empty()));
+}
+
+#[test_log::test(tokio::test)]
+async fn test_unlisted_method_is_denied() {
+    let ctx = TestCtx::new().inmemory_db().await;
+    let handler = Handler::default();
+    let mut svc = PeerAuth::new(handler.clone(), ctx, METHODS);
+
+    let response = svc.call(authenticated_request("Other")).await.unwrap();
+    let status = tonic::Status::from_header_map(response.headers()).unwrap();
+
+    assert_eq!(status.code(), Code::PermissionDenied);
+    assert!(handler.calls.lock().unwrap().is_empty());
+}
+
+#[test_log::test(tokio::test)]
+async fn test_service_rejects_unauthenticated_peer() {
+    let ctx = TestCtx::new().inmemory_db().await.supreme_instance().await;
+    let mut client = AuthlyServiceClient::new(AuthlyServiceServerImpl::new_service(ctx.clone()));
+
+    let status = client
+        .get_metadata(tonic::Request::new(proto::Empty {}))
+        .await
+        .unwrap_err();
+
+    assert_eq!(status.code(), Code::Unauthenticated);
+}
+
+#[test_log::test(tokio::test)]
+async fn test_method_requires_role() {
+    let ctx = TestCtx::new().inmemory_db().await.supreme_instance().await;
+    let doc = indoc! {
+        r#"
+        [authly-document]
+        id = "bc9ce588-50c3-47d1-94c1-f88b21eaf299"
+
+        [[service-entity]]
+        eid = "s.e5462a0d22b54d9f9ca37bd96e9b9d8b"
+        label = "svc_a"
+        attributes = ["authly:role:get_access_token", "authly:role:sign_certificate"]
+
+        [[service-entity]]
+        eid = "s.015362d6655447c6b7f44865bd111c70"
+        label = "svc_b"
+
+        [[resource-property]]
+        namespace = "svc_b"
+        label = "kind"
+        attributes = ["trousers"]
+        "#
+    };
+    compile_and_apply_doc(doc, &ctx).await.unwrap();
+
+    let mut client = AuthlyServiceClient::new(AuthlyServiceServerImpl::new_service(ctx.clone()));
+
+    // svc_b does not have the role, so it gets no access token
+    let status = client
+        .get_access_token(tonic_request(proto::Empty {}, SVC_B))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::PermissionDenied);
+
+    // svc_a has the role, but there's no user session
+    let status = client
+        .get_access_token(tonic_request(proto::Empty {}, SVC_A))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::Unauthenticated);
+
+    // svc_b does not have the role for signing certificates either
+    let status = client
+        .sign_certificate(tonic_request(
+            proto::CertificateSigningRequest {
+                der: b"not a csr".to_vec().into(),
+            },
+            SVC_B,
+        ))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::PermissionDenied);
+
+    // nor for receiving messages
+    let status = client
+        .messages(tonic_request(proto::Empty {}, SVC_B))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::PermissionDenied);
+
+    // svc_a has the role, the handler rejects the bogus CSR
+    let status = client
+        .sign_certificate(tonic_request(
+            proto::CertificateSigningRequest {
+                der: b"not a csr".to_vec().into(),
+            },
+            SVC_A,
+        ))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::InvalidArgument);
+
+    // access control requires no role, and is still available to svc_b
+    let props = ServiceProperties::load(SVC_B, ctx.get_db()).await;
+    let response = client
+        .access_control(tonic_request(
+            proto::AccessControlRequest {
+                resource_attributes: props
+                    .resource
+                    .translate([("svc_b", "kind", "trousers")])
+                    .into_iter()
+                    .map(|attr| attr.to_array_dynamic().to_vec().into())
+                    .collect(),
+                ..Default::default()
+            },
+            SVC_B,
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.into_inner().value, 0);
+}
+
+#[test_log::test(tokio::test)]
+async fn test_metadata_lists_allowed_methods() {
+    let ctx = TestCtx::new().inmemory_db().await.supreme_instance().await;
+    let doc = indoc! {
+        r#"
+        [authly-document]
+        id = "bc9ce588-50c3-47d1-94c1-f88b21eaf299"
+
+        [[service-entity]]
+        eid = "s.e5462a0d22b54d9f9ca37bd96e9b9d8b"
+        label = "svc_a"
+        attributes = ["authly:role:get_access_token", "authly:role:sign_certificate"]
+
+        [[service-entity]]
+        eid = "s.015362d6655447c6b7f44865bd111c70"
+        label = "svc_b"
+        "#
+    };
+    compile_and_apply_doc(doc, &ctx).await.unwrap();
+
+    let svc_a = allowed_methods(&ctx, SVC_A).await;
+    assert!(svc_a.iter().any(|method| method == "GetAccessToken"));
+    assert!(svc_a.iter().any(|method| method == "AccessControl"));
+    assert!(svc_a.iter().any(|method| method == "SignCertificate"));
+
+    let svc_b = allowed_methods(&ctx, SVC_B).await;
+    assert!(!svc_b.iter().any(|method| method == "GetAccessToken"));
+    assert!(svc_b.iter().any(|method| method == "AccessControl"));
+    assert!(!svc_b.iter().any(|method| method == "SignCertificate"));
+    assert!(!svc_b.iter().any(|method| method == "Messages"));
+}
+
+async fn allowed_methods(ctx: &TestCtx, eid: ServiceId) -> Vec<String> {
+    let response = AuthlyServiceClient::new(AuthlyServiceServerImpl::new_service(ctx.clone()))
+        .get_metadata(tonic_request(proto::Empty {}, eid))
+        .await
+        .unwrap();
+
+    response
+        .metadata()
+        .get(ALLOWED_METHODS)
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .split(',')
+        .map(str::to_string)
+        .collect()
+}
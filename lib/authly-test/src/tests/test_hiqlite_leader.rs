@@ -0,0 +1,484 @@
+use std::{
+    borrow::Cow,
+    collections::BTreeSet,
+    net::TcpListener,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use arc_swap::ArcSwap;
+use authly_common::{
+    id::{AttrId, ServiceId},
+    policy::{code::PolicyValue, engine::AccessControlParams},
+};
+use authly_db::{params, Db, DbError, FromRow};
+use authly_domain::{
+    access_control::{self, AccessControlDecision, DecisionReason},
+    audit::AuditQueue,
+    ctx::{GetAuditQueue, GetDb, GetSettings, GetStats},
+    id::{BuiltinAttr, BuiltinProp},
+    migration::Migrations,
+    repo::init_repo,
+    settings::{Setting, Settings},
+    stats::{AuthlyStats, RaftRole},
+    IsLeaderDb,
+};
+use authly_hiqlite::HiqliteClient;
+use hiqlite::cache_idx::CacheIndex;
+use time::OffsetDateTime;
+
+#[derive(Debug, strum::EnumIter, num_derive::ToPrimitive)]
+enum TestCache {
+    Unused,
+}
+
+impl CacheIndex for TestCache {
+    fn to_usize(self) -> usize {
+        self as usize
+    }
+}
+
+struct Value(String);
+
+impl FromRow for Value {
+    fn from_row(row: &mut impl authly_db::Row) -> Self {
+        Self(row.get_text("value"))
+    }
+}
+
+/// Ports handed out to clusters of this test process, which must not be handed out twice
+static RESERVED_PORTS: Mutex<BTreeSet<u16>> = Mutex::new(BTreeSet::new());
+
+/// Find `count` distinct free ports.
+///
+/// The listeners are held until all ports are found, so the OS doesn't return the same port twice,
+/// and the ports are reserved so concurrently starting clusters don't get them either.
+fn free_ports(count: usize) -> Vec<u16> {
+    let mut reserved = RESERVED_PORTS.lock().unwrap();
+    let mut listeners = vec![];
+    let mut ports = vec![];
+
+    while ports.len() < count {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        if reserved.insert(port) {
+            ports.push(port);
+        }
+        listeners.push(listener);
+    }
+
+    ports
+}
+
+/// An in-process cluster, with its data directory
+struct Cluster {
+    nodes: Vec<HiqliteClient>,
+    data_dir: PathBuf,
+}
+
+impl Cluster {
+    /// Shut down the remaining nodes and remove the data directory
+    async fn shutdown(mut self) {
+        for client in self.nodes.drain(..) {
+            client.shutdown().await.unwrap();
+        }
+    }
+
+    /// Shut down one node, leaving the others running
+    async fn shutdown_node(&mut self, index: usize) {
+        self.nodes.remove(index).shutdown().await.unwrap();
+    }
+
+    async fn leader(&self) -> Option<&HiqliteClient> {
+        for client in &self.nodes {
+            if client.is_leader_db().await {
+                return Some(client);
+            }
+        }
+
+        None
+    }
+}
+
+impl Drop for Cluster {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.data_dir);
+    }
+}
+
+/// Start an in-process cluster of `count` nodes
+async fn start_cluster(count: u64) -> Cluster {
+    let data_dir = std::env::temp_dir().join(format!("authly-hiqlite-{}", ServiceId::random()));
+    let mut ports = free_ports(count as usize * 2).into_iter();
+    let nodes: Vec<hiqlite::Node> = (1..=count)
+        .map(|id| hiqlite::Node {
+            id,
+            addr_api: format!("127.0.0.1:{}", ports.next().unwrap()),
+            addr_raft: format!("127.0.0.1:{}", ports.next().unwrap()),
+        })
+        .collect();
+
+    let clients = futures_util::future::join_all(nodes.iter().map(|node| {
+        let config = hiqlite::NodeConfig {
+            node_id: node.id,
+            nodes: nodes.clone(),
+            data_dir: data_dir
+                .join(node.id.to_string())
+                .to_str()
+                .unwrap()
+                .to_string()
+                .into(),
+            filename_db: "test.db".into(),
+            secret_raft: "test-raft-secret-0123456789".to_string(),
+            secret_api: "test-api-secret-0123456789".to_string(),
+            shutdown_delay_millis: 0,
+            ..Default::default()
+        };
+        async move {
+            HiqliteClient::new(
+                hiqlite::start_node_with_cache::<TestCache>(config)
+                    .await
+                    .unwrap(),
+            )
+        }
+    }))
+    .await;
+
+    for client in &clients {
+        client.wait_until_healthy_db().await;
+    }
+
+    Cluster {
+        nodes: clients,
+        data_dir,
+    }
+}
+
+/// Poll a node until the written value is visible
+async fn wait_for_values(client: &HiqliteClient) -> Vec<Value> {
+    let mut values: Vec<Value> = vec![];
+    for _ in 0..50 {
+        values = Db::query_map(client, "SELECT value FROM test".into(), params!())
+            .await
+            .unwrap();
+        if !values.is_empty() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+
+    values
+}
+
+#[test_log::test(tokio::test(flavor = "multi_thread"))]
+async fn test_follower_write_reaches_leader() {
+    let cluster = start_cluster(3).await;
+
+    let leader = cluster.leader().await.expect("no leader elected");
+    let mut follower = None;
+    for client in &cluster.nodes {
+        if !client.is_leader_db().await {
+            follower = Some(client);
+        }
+    }
+    let follower = follower.expect("no follower");
+
+    Db::execute(
+        follower,
+        "CREATE TABLE test (value TEXT NOT NULL)".into(),
+        params!(),
+    )
+    .await
+    .unwrap();
+    Db::execute(
+        follower,
+        "INSERT INTO test (value) VALUES ($1)".into(),
+        params!("written via follower"),
+    )
+    .await
+    .unwrap();
+
+    // the write is applied through raft, so it eventually becomes visible on the leader
+    let values = wait_for_values(leader).await;
+    assert_eq!(values.len(), 1);
+    assert_eq!(values[0].0, "written via follower");
+
+    cluster.shutdown().await;
+}
+
+#[test_log::test(tokio::test(flavor = "multi_thread"))]
+async fn test_write_during_leader_election() {
+    let mut cluster = start_cluster(3).await;
+
+    Db::execute(
+        &cluster.nodes[0],
+        "CREATE TABLE test (value TEXT NOT NULL)".into(),
+        params!(),
+    )
+    .await
+    .unwrap();
+
+    let mut old_leader = None;
+    for (index, client) in cluster.nodes.iter().enumerate() {
+        if client.is_leader_db().await {
+            old_leader = Some((index, client.metrics_db().await.unwrap().id));
+        }
+    }
+    let (old_leader, old_leader_id) = old_leader.expect("no leader elected");
+    cluster.shutdown_node(old_leader).await;
+
+    // writes right after the leader is gone hit the election, and are retried until a new leader is elected
+    let survivor = &cluster.nodes[0];
+    let mut written = false;
+    for _ in 0..20 {
+        match Db::execute(
+            survivor,
+            "INSERT INTO test (value) VALUES ($1)".into(),
+            params!("written after election"),
+        )
+        .await
+        {
+            Ok(_) => {
+                written = true;
+                break;
+            }
+            Err(DbError::NotLeader { .. }) => {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+            Err(err) => panic!("unexpected error during election: {err:?}"),
+        }
+    }
+    assert!(written, "no write accepted after the leader change");
+
+    let new_leader = cluster.leader().await.expect("no new leader elected");
+    assert_ne!(new_leader.metrics_db().await.unwrap().id, old_leader_id);
+
+    let values = wait_for_values(new_leader).await;
+    assert_eq!(values.len(), 1);
+    assert_eq!(values[0].0, "written after election");
+
+    cluster.shutdown().await;
+}
+
+#[test_log::test(tokio::test(flavor = "multi_thread"))]
+async fn test_quorum_loss_enters_read_only_mode() {
+    let mut cluster = start_cluster(3).await;
+    let survivor = cluster.nodes[2].clone();
+
+    Db::execute(
+        &survivor,
+        "CREATE TABLE test (value TEXT NOT NULL)".into(),
+        params!(),
+    )
+    .await
+    .unwrap();
+    Db::execute(
+        &survivor,
+        "INSERT INTO test (value) VALUES ($1)".into(),
+        params!("written with quorum"),
+    )
+    .await
+    .unwrap();
+    assert!(!survivor.update_read_only().await);
+
+    cluster.shutdown_node(0).await;
+    cluster.shutdown_node(0).await;
+
+    // the survivor notices the lost quorum after a leader election or quorum acknowledgement timeout
+    let mut read_only = false;
+    for _ in 0..300 {
+        if survivor.update_read_only().await {
+            read_only = true;
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert!(read_only, "no read-only mode after losing quorum");
+    assert!(survivor.is_read_only());
+
+    // reads are served from local state
+    let values: Vec<Value> = Db::query_map(&survivor, "SELECT value FROM test".into(), params!())
+        .await
+        .unwrap();
+    assert_eq!(values.len(), 1);
+    assert_eq!(values[0].0, "written with quorum");
+
+    // writes are rejected right away instead of waiting for a leader
+    let write = Db::execute(
+        &survivor,
+        "INSERT INTO test (value) VALUES ($1)".into(),
+        params!("written without quorum"),
+    );
+    let result = tokio::time::timeout(Duration::from_millis(100), write)
+        .await
+        .expect("write was not rejected right away");
+    assert!(matches!(result, Err(DbError::ReadOnly)));
+
+    cluster.shutdown().await;
+}
+
+/// The parts of the app context that access control needs, on top of one node of a cluster
+struct NodeCtx {
+    hql: HiqliteClient,
+    settings: ArcSwap<Settings>,
+    audit_queue: AuditQueue,
+    stats: AuthlyStats,
+}
+
+impl GetDb for NodeCtx {
+    type Db = HiqliteClient;
+
+    fn get_db(&self) -> &Self::Db {
+        &self.hql
+    }
+}
+
+impl GetSettings for NodeCtx {
+    fn get_settings(&self) -> arc_swap::Guard<Arc<Settings>> {
+        self.settings.load()
+    }
+}
+
+impl GetAuditQueue for NodeCtx {
+    fn get_audit_queue(&self) -> &AuditQueue {
+        &self.audit_queue
+    }
+}
+
+impl GetStats for NodeCtx {
+    fn get_stats(&self) -> &AuthlyStats {
+        &self.stats
+    }
+
+    async fn raft_role(&self) -> RaftRole {
+        RaftRole::Follower
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.hql.is_read_only()
+    }
+
+    fn is_insecure_mode(&self) -> bool {
+        false
+    }
+
+    fn slow_query_count(&self) -> u64 {
+        0
+    }
+}
+
+/// Settings with a quota on resources with the `authly:role:get_access_token` attribute
+fn quota_settings(audit: Option<&str>) -> Settings {
+    let mut settings = Settings::default();
+    settings
+        .try_set(
+            Setting::AccessControlQuota,
+            Cow::Borrowed("authly:role:get_access_token=100/1h"),
+        )
+        .unwrap();
+    if let Some(audit) = audit {
+        settings
+            .try_set(Setting::AccessControlAudit, Cow::Borrowed(audit))
+            .unwrap();
+    }
+    settings
+}
+
+/// Run an allowed decision for a resource with the given role attribute through quotas and auditing
+async fn quota_and_audit(ctx: &NodeCtx, resource: BuiltinAttr) -> (bool, DecisionReason) {
+    let svc_eid = ServiceId::random();
+    let mut params = AccessControlParams {
+        resource_attrs: [AttrId::from(resource)].into_iter().collect(),
+        ..Default::default()
+    };
+    params
+        .subject_eids
+        .insert(BuiltinProp::Entity.into(), svc_eid.upcast());
+
+    let now = OffsetDateTime::now_utc();
+    let decision = AccessControlDecision {
+        value: PolicyValue::Allow,
+        reason: DecisionReason::Allowed,
+    };
+    let decision = access_control::enforce_quotas(ctx, &params, decision, now)
+        .await
+        .unwrap();
+    let decision = access_control::audit_decision(ctx, svc_eid, params, decision, now);
+
+    (
+        matches!(decision.value, PolicyValue::Allow),
+        decision.reason,
+    )
+}
+
+#[test_log::test(tokio::test(flavor = "multi_thread"))]
+async fn test_quorum_loss_fails_quotas_and_auditing_closed() {
+    let mut cluster = start_cluster(3).await;
+    let survivor = cluster.nodes[2].clone();
+
+    survivor.migrate::<Migrations>().await.unwrap();
+    init_repo::load_authly_builtins(cluster.leader().await.unwrap(), IsLeaderDb(true))
+        .await
+        .unwrap();
+    init_repo::load_authly_builtins(&survivor, IsLeaderDb(false))
+        .await
+        .unwrap();
+
+    let (audit_queue, _audit_appender) = AuditQueue::new();
+    let ctx = NodeCtx {
+        hql: survivor.clone(),
+        settings: ArcSwap::new(Arc::new(quota_settings(None))),
+        audit_queue,
+        stats: AuthlyStats::default(),
+    };
+
+    // with quorum, the quota is consumed through the leader
+    assert_eq!(
+        quota_and_audit(&ctx, BuiltinAttr::AuthlyRoleGetAccessToken).await,
+        (true, DecisionReason::Allowed)
+    );
+
+    ctx.settings.store(Arc::new(quota_settings(Some("all"))));
+    assert_eq!(
+        quota_and_audit(&ctx, BuiltinAttr::AuthlyRoleGetAccessToken).await,
+        (true, DecisionReason::Allowed)
+    );
+
+    cluster.shutdown_node(0).await;
+    cluster.shutdown_node(0).await;
+
+    let mut read_only = false;
+    for _ in 0..300 {
+        if survivor.update_read_only().await {
+            read_only = true;
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert!(read_only, "no read-only mode after losing quorum");
+
+    // the quota can't be counted, so the request is denied right away instead of waiting for a write
+    let decision = tokio::time::timeout(
+        Duration::from_millis(100),
+        quota_and_audit(&ctx, BuiltinAttr::AuthlyRoleGetAccessToken),
+    )
+    .await
+    .expect("quota was not denied right away");
+    assert_eq!(decision, (false, DecisionReason::QuotaUnavailable));
+
+    // without a quota, the decision can't be audited
+    assert_eq!(
+        quota_and_audit(&ctx, BuiltinAttr::AuthlyRoleAuthenticate).await,
+        (false, DecisionReason::AuditUnavailable)
+    );
+
+    // without auditing, a request without a quota is still answered from the local state
+    ctx.settings.store(Arc::new(Settings::default()));
+    assert_eq!(
+        quota_and_audit(&ctx, BuiltinAttr::AuthlyRoleAuthenticate).await,
+        (true, DecisionReason::Allowed)
+    );
+
+    cluster.shutdown().await;
+}
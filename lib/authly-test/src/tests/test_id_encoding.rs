@@ -0,0 +1,47 @@
+use std::{fmt::Display, str::FromStr};
+
+use authly_common::id::{EntityId, GroupId, PersonaId, ServiceId};
+use hexhex::hex_literal;
+
+const PERSONA: PersonaId =
+    PersonaId::from_raw_array(hex_literal!("0fbcd73e1a884424a1615c3c3fdeebec"));
+const GROUP: GroupId = GroupId::from_raw_array(hex_literal!("81dc1da0fa644142bad35043a9c3b025"));
+const SVC: ServiceId = ServiceId::from_raw_array(hex_literal!("e5462a0d22b54d9f9ca37bd96e9b9d8b"));
+
+fn assert_round_trip<T>(id: T, canonical: &str)
+where
+    T: Display + FromStr + PartialEq + std::fmt::Debug,
+{
+    assert_eq!(id.to_string(), canonical);
+    assert_eq!(T::from_str(canonical).ok(), Some(id));
+}
+
+#[test]
+fn test_canonical_id_round_trip() {
+    assert_round_trip(PERSONA, "p.0fbcd73e1a884424a1615c3c3fdeebec");
+    assert_round_trip(GROUP, "g.81dc1da0fa644142bad35043a9c3b025");
+    assert_round_trip(SVC, "s.e5462a0d22b54d9f9ca37bd96e9b9d8b");
+
+    // a dynamically typed entity ID keeps the prefix of its kind
+    let persona_eid: EntityId = PERSONA.upcast();
+    let svc_eid: EntityId = SVC.upcast();
+    assert_round_trip(persona_eid, &PERSONA.to_string());
+    assert_round_trip(svc_eid, &SVC.to_string());
+}
+
+#[test]
+fn test_canonical_id_rejects_other_kinds() {
+    assert!(PersonaId::from_str(&SVC.to_string()).is_err());
+    assert!(PersonaId::from_str(&GROUP.to_string()).is_err());
+    assert!(GroupId::from_str(&PERSONA.to_string()).is_err());
+    assert!(ServiceId::from_str(&PERSONA.to_string()).is_err());
+    assert!(ServiceId::from_str(&GROUP.to_string()).is_err());
+}
+
+#[test]
+fn test_canonical_id_rejects_malformed() {
+    assert!(ServiceId::from_str("e5462a0d22b54d9f9ca37bd96e9b9d8b").is_err());
+    assert!(ServiceId::from_str("s.e5462a0d22b54d9f9ca37bd96e9b9d8").is_err());
+    assert!(ServiceId::from_str("s.not-an-id").is_err());
+    assert!(ServiceId::from_str("").is_err());
+}
@@ -0,0 +1,100 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use authly_common::id::{AttrId, GroupId, PersonaId, PropId, ServiceId};
+use authly_domain::{
+    id::{is_reserved_id, parse_unreserved_id, BuiltinAttr, BuiltinProp},
+    id_generator::{ExternalIdGenerator, IdGenerator, RandomIdGenerator, TimeOrderedIdGenerator},
+};
+use authly_service::scim::{groups, ScimAuth, ScimJson};
+use axum::extract::State;
+use serde_json::{json, Value};
+
+use crate::test_ctx::TestCtx;
+
+const SVC: ServiceId = ServiceId::from_raw_array([7; 16]);
+
+#[test]
+fn test_time_ordered_ids_are_monotonic() {
+    let generator = TimeOrderedIdGenerator::default();
+    let ids: Vec<u128> = (0..10_000).map(|_| generator.next_id()).collect();
+
+    assert!(ids.windows(2).all(|pair| pair[0] < pair[1]));
+    assert!(ids.iter().all(|id| !is_reserved_id(*id)));
+}
+
+#[test]
+fn test_random_ids_avoid_reserved_range() {
+    assert!((0..10_000).all(|_| !is_reserved_id(RandomIdGenerator.next_id())));
+}
+
+#[test]
+fn test_external_ids() {
+    let next = AtomicU64::new(u16::MAX as u64 - 1);
+    let generator = ExternalIdGenerator::new(move || next.fetch_add(1, Ordering::SeqCst) as u128);
+
+    // reserved IDs supplied from outside are never used
+    assert!(!is_reserved_id(generator.next_id()));
+    assert!(!is_reserved_id(generator.next_id()));
+    assert_eq!(generator.next_id(), u16::MAX as u128 + 1);
+}
+
+#[test]
+fn test_builtins_are_reserved() {
+    for prop in BuiltinProp::iter() {
+        let id = PropId::from(prop);
+        assert!(
+            is_reserved_id(u128::from_be_bytes(id.to_raw_array())),
+            "{prop:?}"
+        );
+    }
+    for attr in BuiltinAttr::iter() {
+        let id = AttrId::from(attr);
+        assert!(
+            is_reserved_id(u128::from_be_bytes(id.to_raw_array())),
+            "{attr:?}"
+        );
+    }
+}
+
+#[test]
+fn test_parse_rejects_reserved_ids() {
+    for reserved in [
+        "p.00000000000000000000000000000000",
+        "p.00000000000000000000000000000001",
+        "p.0000000000000000000000000000ffff",
+    ] {
+        let id: Option<PersonaId> = parse_unreserved_id(reserved);
+        assert_eq!(id, None, "{reserved}");
+    }
+
+    let id: Option<PersonaId> = parse_unreserved_id("p.00000000000000000000000000010000");
+    assert_eq!(id, Some(PersonaId::from_uint(0x10000)));
+
+    // the kind must still match
+    let id: Option<PersonaId> = parse_unreserved_id("s.00000000000000000000000000010000");
+    assert_eq!(id, None);
+}
+
+#[test_log::test(tokio::test)]
+async fn test_injected_id_generator() {
+    let ctx = TestCtx::new()
+        .inmemory_db()
+        .await
+        .supreme_instance()
+        .await
+        .with_id_generator(ExternalIdGenerator::new(|| 1 << 100));
+
+    let response = groups::create_group(
+        State(ctx.clone()),
+        ScimAuth(SVC),
+        ScimJson(json!({ "displayName": "group" })),
+    )
+    .await
+    .unwrap();
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let group: Value = serde_json::from_slice(&bytes).unwrap();
+
+    assert_eq!(group["id"], json!(GroupId::from_uint(1 << 100).to_string()));
+}
@@ -0,0 +1,120 @@
+use std::borrow::Cow;
+
+use authly_common::{
+    document::Document,
+    id::{PersonaId, ServiceId},
+    mtls_server::PeerServiceEntity,
+};
+use authly_domain::{
+    dev::IsDev,
+    document::{compiled_document::DocumentMeta, doc_compiler::compile_doc},
+    id::BuiltinProp,
+    login::{try_username_password_login, LoginOptions},
+    repo::crypto_repo,
+    settings::{Setting, Settings},
+};
+use hexhex::hex_literal;
+use indoc::indoc;
+
+use crate::{test_ctx::TestCtx, util::compile_and_apply_doc};
+
+const PERSONA_ME: PersonaId =
+    PersonaId::from_raw_array(hex_literal!("0fbcd73e1a884424a1615c3c3fdeebec"));
+
+const DOC: &str = indoc! {r#"
+    [authly-document]
+    id = "5f0b44d0-8d4c-4ab5-9d7a-65a1e2f0c1b3"
+
+    [[entity]]
+    eid = "p.0fbcd73e1a884424a1615c3c3fdeebec"
+    label = "me"
+    email = ["Me+Work@Mail.COM"]
+    username = "TestUser"
+    password-hash = [
+        "$argon2id$v=19$m=19456,t=2,p=1$/lj8Yj6ZTJLiqgpYb4Nn0g$z79FFMXstrkY8KmpC0vQWIDcne0lylBbctUAluIVqLk",
+    ]
+"#};
+
+const NORMALIZED: &[(Setting, &str)] = &[
+    (Setting::IdentCaseFold, "true"),
+    (Setting::IdentUnicodeNfc, "true"),
+    (Setting::EmailStripSubaddress, "true"),
+];
+
+async fn ctx_with(settings: &[(Setting, &'static str)]) -> TestCtx {
+    let mut s = Settings::default();
+    for (setting, value) in settings {
+        s.try_set(*setting, Cow::Borrowed(value)).unwrap();
+    }
+
+    TestCtx::new()
+        .inmemory_db()
+        .await
+        .supreme_instance()
+        .await
+        .with_settings(s)
+}
+
+async fn login(ctx: &TestCtx, username: &str) -> bool {
+    try_username_password_login(
+        ctx,
+        PeerServiceEntity(ServiceId::random()),
+        username.to_string(),
+        "secret".to_string(),
+        LoginOptions::default().dev(IsDev(true)),
+    )
+    .await
+    .is_ok()
+}
+
+#[test_log::test(tokio::test)]
+async fn test_document_compilation_normalizes_idents() {
+    let ctx = ctx_with(NORMALIZED).await;
+
+    let compiled = compile_doc(
+        &ctx,
+        Document::from_toml(DOC).unwrap(),
+        Default::default(),
+        DocumentMeta::default(),
+    )
+    .await
+    .unwrap();
+
+    let mut idents: Vec<_> = compiled
+        .data
+        .entity_ident
+        .iter()
+        .map(|(ident, _)| ident.ident.as_str())
+        .collect();
+    idents.sort();
+
+    assert_eq!(idents, vec!["me@mail.com", "testuser"]);
+}
+
+#[test_log::test(tokio::test)]
+async fn test_login_matches_differently_cased_idents() {
+    let ctx = ctx_with(NORMALIZED).await;
+    compile_and_apply_doc(DOC, &ctx).await.unwrap();
+
+    assert!(login(&ctx, "testuser").await);
+    assert!(login(&ctx, "TESTUSER").await);
+
+    for email in ["me@mail.com", "ME@Mail.com", "me+other@MAIL.com"] {
+        assert_eq!(
+            crypto_repo::lookup_obj_ident(&ctx, BuiltinProp::Email.into(), email)
+                .await
+                .unwrap(),
+            Some(PERSONA_ME.upcast()),
+            "{email}"
+        );
+    }
+}
+
+#[test_log::test(tokio::test)]
+async fn test_case_folding_disabled_by_default() {
+    let ctx = ctx_with(&[]).await;
+    compile_and_apply_doc(DOC, &ctx).await.unwrap();
+
+    assert!(login(&ctx, "TestUser").await);
+    assert!(!login(&ctx, "testuser").await);
+}
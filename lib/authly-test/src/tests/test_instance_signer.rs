@@ -0,0 +1,300 @@
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+use async_trait::async_trait;
+use authly_common::id::PersonaId;
+use authly_db::{params, Db, FromRow};
+use authly_domain::{
+    access_token::{create_access_token, verify_access_token},
+    cert::{authly_ca, key_pair, server_cert, CertificateParamsExt},
+    ctx::{GetDb, GetDecryptedDeks, GetIdGenerator, GetInstance, SetInstance},
+    instance::InstanceKeySource,
+    repo::crypto_repo,
+    session::{init_session, AuthClass, SessionKind},
+    signer::{self, InMemorySigner, Signer},
+    IsLeaderDb,
+};
+use rcgen::{
+    CertificateSigningRequestParams, Issuer, KeyPair, PublicKeyData, SignatureAlgorithm,
+    SigningKey, PKCS_ECDSA_P256_SHA256,
+};
+use rustls::{
+    client::{danger::ServerCertVerifier, WebPkiServerVerifier},
+    pki_types::{CertificateDer, ServerName, UnixTime},
+    RootCertStore,
+};
+use time::OffsetDateTime;
+
+use crate::test_ctx::TestCtx;
+
+/// Stand-in for an HSM: the key pair is private to the signer, which only exposes signing
+struct MockHsm {
+    key_pair: KeyPair,
+    sign_count: AtomicUsize,
+}
+
+impl MockHsm {
+    fn new() -> Arc<Self> {
+        Arc::new(Self {
+            key_pair: key_pair(),
+            sign_count: AtomicUsize::new(0),
+        })
+    }
+
+    fn sign_count(&self) -> usize {
+        self.sign_count.load(Ordering::SeqCst)
+    }
+}
+
+#[async_trait]
+impl Signer for MockHsm {
+    fn algorithm(&self) -> &'static SignatureAlgorithm {
+        &PKCS_ECDSA_P256_SHA256
+    }
+
+    fn public_key_raw(&self) -> &[u8] {
+        self.key_pair.der_bytes()
+    }
+
+    async fn sign(&self, msg: &[u8]) -> anyhow::Result<Vec<u8>> {
+        self.sign_count.fetch_add(1, Ordering::SeqCst);
+        Ok(SigningKey::sign(&self.key_pair, msg)?)
+    }
+}
+
+struct StoredInstanceKey(Vec<u8>);
+
+impl FromRow for StoredInstanceKey {
+    fn from_row(row: &mut impl authly_db::Row) -> Self {
+        Self(row.get_blob("private_key_ciph"))
+    }
+}
+
+#[test_log::test(tokio::test)]
+async fn test_external_signer_issues_certificates() {
+    let hsm = MockHsm::new();
+    let ctx = TestCtx::new()
+        .inmemory_db()
+        .await
+        .supreme_instance_with_signer(hsm.clone())
+        .await;
+
+    // the CA and identity certificates were self-signed by the HSM
+    assert!(hsm.sign_count() >= 2);
+
+    // no private key is stored, or available from the signer
+    let stored: Vec<StoredInstanceKey> = ctx
+        .get_db()
+        .query_map(
+            "SELECT private_key_ciph FROM authly_instance".into(),
+            params!(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(stored.len(), 1);
+    assert!(stored[0].0.is_empty());
+    assert!(ctx.get_instance().signer().private_key_der().is_none());
+
+    let sign_count = hsm.sign_count();
+    let server_cert = ctx
+        .get_instance()
+        .sign_with_local_ca(
+            server_cert(
+                "authly",
+                vec!["localhost".to_string()],
+                time::Duration::hours(1),
+            )
+            .unwrap()
+            .with_new_key_pair(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(hsm.sign_count(), sign_count + 1);
+
+    verify_localhost_cert(&ctx.get_instance().trust_root_ca().der, &server_cert.der);
+}
+
+#[test_log::test(tokio::test)]
+async fn test_external_signer_signs_compat_tokens() {
+    let hsm = MockHsm::new();
+    let ctx = TestCtx::new()
+        .inmemory_db()
+        .await
+        .supreme_instance_with_signer(hsm.clone())
+        .await;
+
+    // an installation without a dedicated token signing key signs tokens with the instance key
+    ctx.get_db()
+        .execute("DELETE FROM token_signing_key".into(), params!())
+        .await
+        .unwrap();
+    let instance = crypto_repo::load_authly_instance(
+        IsLeaderDb(true),
+        ctx.get_db(),
+        &ctx.get_decrypted_deks(),
+        ctx.get_instance().key_source().clone(),
+        ctx.get_id_generator(),
+    )
+    .await
+    .unwrap();
+    ctx.set_instance(instance);
+    assert!(ctx.get_instance().is_token_signing_compat());
+
+    let session = init_session(
+        &ctx,
+        PersonaId::random().upcast(),
+        SessionKind::Default,
+        AuthClass::Password,
+    )
+    .await
+    .unwrap();
+
+    let sign_count = hsm.sign_count();
+    let token = create_access_token(
+        &session,
+        Default::default(),
+        &ctx.get_instance(),
+        OffsetDateTime::now_utc(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(hsm.sign_count(), sign_count + 1);
+
+    let claims =
+        verify_access_token(&token, &ctx.get_instance(), OffsetDateTime::now_utc()).unwrap();
+    assert_eq!(claims.authly.entity_id, session.eid);
+}
+
+#[test_log::test(tokio::test)]
+async fn test_external_signer_required_once_configured() {
+    let ctx = TestCtx::new()
+        .inmemory_db()
+        .await
+        .supreme_instance_with_signer(MockHsm::new())
+        .await;
+
+    let result = crypto_repo::load_authly_instance(
+        IsLeaderDb(true),
+        ctx.get_db(),
+        &ctx.get_decrypted_deks(),
+        InstanceKeySource::Database,
+        ctx.get_id_generator(),
+    )
+    .await;
+
+    assert!(result.is_err());
+}
+
+#[test_log::test(tokio::test)]
+async fn test_in_memory_signer_certificates_match_rcgen() {
+    let signer = InMemorySigner::new(key_pair());
+    let ca_params = authly_ca();
+    let params = server_cert(
+        "authly",
+        vec!["localhost".to_string()],
+        time::Duration::hours(1),
+    )
+    .unwrap();
+    let subject_key = key_pair();
+
+    let expected_ca = ca_params.self_signed(signer.key_pair()).unwrap();
+    let ca = signer::self_sign(&signer, &ca_params).await.unwrap();
+
+    let expected_cert = params
+        .signed_by(
+            &subject_key,
+            &Issuer::new(ca_params.clone(), signer.key_pair()),
+        )
+        .unwrap();
+    let cert = signer::sign_certificate(&signer, &ca_params, &params, &subject_key)
+        .await
+        .unwrap();
+
+    // ECDSA signatures are randomized, but everything that is signed must be identical
+    assert_eq!(tbs_certificate(&ca), tbs_certificate(expected_ca.der()));
+    assert_eq!(tbs_certificate(&cert), tbs_certificate(expected_cert.der()));
+
+    verify_localhost_cert(&ca, &cert);
+}
+
+#[test_log::test(tokio::test)]
+async fn test_in_memory_signer_csr() {
+    let signer = InMemorySigner::new(key_pair());
+    let params = server_cert(
+        "authly",
+        vec!["localhost".to_string()],
+        time::Duration::hours(1),
+    )
+    .unwrap();
+
+    let csr = signer::serialize_request(&signer, &params).await.unwrap();
+    let csr_params = CertificateSigningRequestParams::from_der(&csr).unwrap();
+    assert_eq!(csr_params.public_key.der_bytes(), signer.public_key_raw());
+
+    let ca_params = authly_ca();
+    let ca = signer::self_sign(&signer, &ca_params).await.unwrap();
+    let cert = signer::sign_csr(&signer, &ca_params, &csr_params)
+        .await
+        .unwrap();
+
+    verify_localhost_cert(&ca, &cert);
+}
+
+#[test_log::test(tokio::test)]
+async fn test_in_memory_signer_jwt_matches_jsonwebtoken() {
+    let signer = InMemorySigner::new(key_pair());
+    let mut header = jsonwebtoken::Header::new(jsonwebtoken::Algorithm::ES256);
+    header.kid = Some("kid".to_string());
+    let claims = serde_json::json!({ "sub": "test", "exp": 4102444800u64 });
+
+    let expected = jsonwebtoken::encode(
+        &header,
+        &claims,
+        &jsonwebtoken::EncodingKey::from_ec_der(&signer.key_pair().serialize_der()),
+    )
+    .unwrap();
+    let token = signer::encode_jwt(&header, &claims, &signer).await.unwrap();
+
+    let (expected_message, _) = expected.rsplit_once('.').unwrap();
+    let (message, _) = token.rsplit_once('.').unwrap();
+    assert_eq!(message, expected_message);
+
+    let decoded = jsonwebtoken::decode::<serde_json::Value>(
+        &token,
+        &jsonwebtoken::DecodingKey::from_ec_der(signer.public_key_raw()),
+        &jsonwebtoken::Validation::new(jsonwebtoken::Algorithm::ES256),
+    )
+    .unwrap();
+    assert_eq!(decoded.claims, claims);
+}
+
+fn tbs_certificate(der: &[u8]) -> Vec<u8> {
+    let (_, cert) = x509_parser::parse_x509_certificate(der).unwrap();
+    cert.tbs_certificate.as_ref().to_vec()
+}
+
+pub(super) fn verify_localhost_cert(
+    root_ca: &CertificateDer<'static>,
+    cert: &CertificateDer<'static>,
+) {
+    let mut roots = RootCertStore::empty();
+    roots.add(root_ca.clone()).unwrap();
+    let verifier = WebPkiServerVerifier::builder_with_provider(
+        Arc::new(roots),
+        Arc::new(rustls::crypto::ring::default_provider()),
+    )
+    .build()
+    .unwrap();
+
+    verifier
+        .verify_server_cert(
+            cert,
+            &[],
+            &ServerName::try_from("localhost").unwrap(),
+            &[],
+            UnixTime::now(),
+        )
+        .unwrap();
+}
@@ -0,0 +1,131 @@
+use authly_common::id::ServiceId;
+use authly_domain::{
+    ctx::{GetBuiltins, GetDb},
+    document::error::DocError,
+    repo::service_repo,
+    service::{
+        authenticate_k8s_service_account, is_valid_k8s_service_account, K8sAuthenticationError,
+    },
+    stats::collect_stats,
+};
+use indoc::formatdoc;
+use serde_json::json;
+
+use crate::{
+    test_ctx::TestCtx,
+    util::{compile_and_apply_doc, TestDocError},
+};
+
+const SVC: ServiceId = ServiceId::from_uint(42);
+
+fn doc(kubernetes_account: &str) -> String {
+    formatdoc! {
+        r#"
+        [authly-document]
+        id = "bc9ce588-50c3-47d1-94c1-f88b21eaf299"
+
+        [[service-entity]]
+        eid = "s.0000000000000000000000000000002a"
+        label = "svc"
+        kubernetes-account = {kubernetes_account}
+        "#
+    }
+}
+
+#[test_log::test(tokio::test)]
+async fn test_valid_k8s_service_account() {
+    let ctx = TestCtx::new().inmemory_db().await.supreme_instance().await;
+
+    compile_and_apply_doc(
+        &doc(r#"{ name = "svc-account.v1", namespace = "apps" }"#),
+        &ctx,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+        service_repo::list_local_k8s_service_accounts(ctx.get_db(), ctx.get_builtins())
+            .await
+            .unwrap(),
+        vec![(SVC, "apps".to_string(), "svc-account.v1".to_string())]
+    );
+}
+
+/// The claims of tokens reviewed by the k8s auth server, counted per namespace
+#[test_log::test(tokio::test)]
+async fn test_k8s_authentication_stats() {
+    let ctx = TestCtx::new().inmemory_db().await.supreme_instance().await;
+    compile_and_apply_doc(&doc(r#"{ name = "svc", namespace = "apps" }"#), &ctx)
+        .await
+        .unwrap();
+
+    assert_eq!(
+        authenticate_k8s_service_account(&ctx, "apps", "svc")
+            .await
+            .unwrap(),
+        SVC
+    );
+    authenticate_k8s_service_account(&ctx, "apps", "svc")
+        .await
+        .unwrap();
+    assert!(matches!(
+        authenticate_k8s_service_account(&ctx, "apps", "other").await,
+        Err(K8sAuthenticationError::NotFound)
+    ));
+    assert!(matches!(
+        authenticate_k8s_service_account(&ctx, "default", "svc").await,
+        Err(K8sAuthenticationError::NotFound)
+    ));
+
+    let stats = serde_json::to_value(collect_stats(&ctx).await.unwrap()).unwrap();
+    assert_eq!(
+        stats["k8s_authentications"],
+        json!({
+            "apps": { "succeeded": 2, "failed": 1 },
+            "default": { "succeeded": 0, "failed": 1 },
+        })
+    );
+}
+
+#[test_log::test(tokio::test)]
+async fn test_malformed_k8s_service_account() {
+    let ctx = TestCtx::new().inmemory_db().await.supreme_instance().await;
+    let doc = doc(r#"{ name = "Svc_Account", namespace = "apps/prod" }"#);
+
+    let TestDocError::Doc(errors) = compile_and_apply_doc(&doc, &ctx).await.unwrap_err() else {
+        panic!()
+    };
+    let spanned_error = errors.into_iter().next().unwrap();
+
+    assert!(
+        matches!(
+            spanned_error.as_ref(),
+            DocError::InvalidKubernetesAccount(account) if account == "apps/prod/Svc_Account"
+        ),
+        "unexpected error: {spanned_error:?}"
+    );
+    assert_eq!("\"svc\"", &doc[spanned_error.span()]);
+}
+
+#[test]
+fn test_k8s_service_account_format() {
+    assert!(is_valid_k8s_service_account(None, "default"));
+    assert!(is_valid_k8s_service_account(Some("kube-system"), "a.b-c"));
+
+    for (namespace, name) in [
+        (None, ""),
+        (None, "UPPER"),
+        (None, "-leading"),
+        (None, "a..b"),
+        (Some(""), "name"),
+        (Some("*"), "name"),
+        (Some("dotted.namespace"), "name"),
+        (Some("n".repeat(64).as_str()), "name"),
+    ] {
+        assert!(
+            !is_valid_k8s_service_account(namespace, name),
+            "{namespace:?}/{name}"
+        );
+    }
+    assert!(!is_valid_k8s_service_account(None, &"a".repeat(254)));
+}
@@ -0,0 +1,291 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use authly_domain::{
+    cert::{authly_ca, server_cert},
+    load_shed::{self, ConnectionLimit, RequestLimit},
+};
+use axum::routing::get;
+use futures_util::StreamExt;
+use http::{header::RETRY_AFTER, StatusCode};
+use tokio::{io::AsyncReadExt, net::TcpStream, sync::Semaphore};
+use tokio_util::sync::{CancellationToken, DropGuard};
+
+use crate::util::rustls_server_config_no_client_auth;
+
+/// Serve a router whose handler blocks until `release` hands out a permit
+async fn spawn_blocking_server(limit: &RequestLimit, release: Arc<Semaphore>) -> String {
+    let router = limit.layer(axum::Router::new().route(
+        "/",
+        get(move || async move {
+            release.acquire().await.unwrap().forget();
+            "done"
+        }),
+    ));
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, router).await });
+
+    url
+}
+
+/// Serve the same router over TLS, with the connection limit applied
+async fn spawn_blocking_tls_server(
+    limit: &RequestLimit,
+    connection_limit: &ConnectionLimit,
+    release: Arc<Semaphore>,
+) -> (String, reqwest::Certificate, DropGuard) {
+    let ca = authly_ca().with_new_key_pair().self_signed();
+    let server_cert = ca.sign(
+        server_cert(
+            "svc",
+            vec!["localhost".to_string()],
+            time::Duration::hours(1),
+        )
+        .unwrap()
+        .with_new_key_pair(),
+    );
+
+    let router = limit.layer(
+        axum::Router::new()
+            .route(
+                "/",
+                get(move || async move {
+                    release.acquire().await.unwrap().forget();
+                    "done"
+                }),
+            )
+            .route("/now", get(|| async { "done" })),
+    );
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!(
+        "https://localhost:{}",
+        listener.local_addr().unwrap().port()
+    );
+    let cancel = CancellationToken::new();
+    tokio::spawn(
+        connection_limit.clone().serve_tls(
+            listener,
+            futures_util::stream::iter([
+                rustls_server_config_no_client_auth(&[&server_cert]).unwrap()
+            ])
+            .boxed(),
+            authly_common::mtls_server::MTLSMiddleware,
+            router,
+            cancel.clone(),
+        ),
+    );
+
+    (url, (&ca).into(), cancel.drop_guard())
+}
+
+/// A client making its own connection
+fn tls_client(ca: &reqwest::Certificate) -> reqwest::ClientBuilder {
+    reqwest::ClientBuilder::new().add_root_certificate(ca.clone())
+}
+
+#[tokio::test]
+async fn test_excess_requests_are_shed() {
+    let limit = RequestLimit::new(2).unwrap();
+    let release = Arc::new(Semaphore::new(0));
+    let url = spawn_blocking_server(&limit, release.clone()).await;
+    let client = reqwest::Client::new();
+
+    let busy: Vec<_> = (0..2)
+        .map(|_| tokio::spawn(client.get(&url).send()))
+        .collect();
+    while limit.in_flight() < 2 {
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+
+    // the server is saturated, further requests are rejected right away
+    for _ in 0..10 {
+        let response = client.get(&url).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()[RETRY_AFTER], "1");
+    }
+
+    release.add_permits(2);
+    for response in busy {
+        let response = response.await.unwrap().unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    // and capacity is available again once the in-flight requests complete
+    assert_eq!(limit.in_flight(), 0);
+    release.add_permits(1);
+    let response = client.get(&url).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.text().await.unwrap(), "done");
+}
+
+#[tokio::test]
+async fn test_drain_waits_for_in_flight_requests() {
+    let limit = RequestLimit::new(4).unwrap();
+    let release = Arc::new(Semaphore::new(0));
+    let url = spawn_blocking_server(&limit, release.clone()).await;
+    let client = reqwest::Client::new();
+
+    let busy = tokio::spawn(client.get(&url).send());
+    while limit.in_flight() < 1 {
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+
+    let finished = Arc::new(Semaphore::new(0));
+    let drain = tokio::spawn({
+        let limit = limit.clone();
+        let finished = finished.clone();
+        async move {
+            load_shed::drain_within(&limit, Duration::from_secs(10), async {
+                finished.add_permits(1);
+            })
+            .await
+        }
+    });
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    // new requests are shed while draining
+    let response = client.get(&url).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(finished.available_permits(), 0);
+
+    release.add_permits(1);
+    assert_eq!(busy.await.unwrap().unwrap().status(), StatusCode::OK);
+    assert!(drain.await.unwrap());
+    assert_eq!(finished.available_permits(), 1);
+}
+
+#[tokio::test]
+async fn test_drain_gives_up_after_budget() {
+    let limit = RequestLimit::new(4).unwrap();
+    let url = spawn_blocking_server(&limit, Arc::new(Semaphore::new(0))).await;
+
+    // the handler hangs forever
+    let _hung = tokio::spawn(reqwest::Client::new().get(&url).send());
+    while limit.in_flight() < 1 {
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+
+    let finished = Semaphore::new(0);
+    let started = Instant::now();
+    let drained = load_shed::drain_within(&limit, Duration::from_millis(200), async {
+        finished.add_permits(1);
+    })
+    .await;
+
+    assert!(!drained);
+    assert!(started.elapsed() < Duration::from_secs(2));
+    assert_eq!(finished.available_permits(), 0);
+}
+
+#[test]
+fn test_request_limit_must_fit_a_semaphore_acquisition() {
+    assert!(RequestLimit::new(u32::MAX as usize).is_ok());
+    assert!(RequestLimit::new(u32::MAX as usize + 1).is_err());
+}
+
+#[tokio::test]
+async fn test_excess_connections_are_refused() {
+    let limit = RequestLimit::new(16).unwrap();
+    let connection_limit = ConnectionLimit::new(2, 16);
+    let (url, ca, _drop) =
+        spawn_blocking_tls_server(&limit, &connection_limit, Arc::new(Semaphore::new(0))).await;
+    let addr = format!("127.0.0.1:{}", url.rsplit(':').next().unwrap());
+    let url = format!("{url}/now");
+
+    // raw TCP connections take up the limit before any TLS handshake
+    let mut raw = vec![];
+    for _ in 0..2 {
+        raw.push(TcpStream::connect(&addr).await.unwrap());
+    }
+    while connection_limit.open() < 2 {
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+
+    // connections beyond the limit are closed right after being accepted
+    for _ in 0..3 {
+        let mut excess = TcpStream::connect(&addr).await.unwrap();
+        let mut buf = [0u8; 1];
+        let read = tokio::time::timeout(Duration::from_secs(5), excess.read(&mut buf))
+            .await
+            .expect("excess connection was not closed");
+        assert!(matches!(read, Ok(0) | Err(_)));
+    }
+    assert!(tls_client(&ca)
+        .http1_only()
+        .build()
+        .unwrap()
+        .get(&url)
+        .send()
+        .await
+        .is_err());
+    assert_eq!(connection_limit.open(), 2);
+
+    // closing a connection makes room for another
+    drop(raw);
+    while connection_limit.open() > 0 {
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+
+    // each client keeps its connection open
+    let clients: Vec<_> = (0..2)
+        .map(|_| tls_client(&ca).http1_only().build().unwrap())
+        .collect();
+    for client in &clients {
+        let response = client.get(&url).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+    assert_eq!(connection_limit.open(), 2);
+
+    let excess = tls_client(&ca).http1_only().build().unwrap();
+    assert!(excess.get(&url).send().await.is_err());
+
+    drop(clients);
+    while connection_limit.open() > 0 {
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+    let response = excess.get(&url).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_excess_requests_on_one_connection_are_shed() {
+    let limit = RequestLimit::new(16).unwrap();
+    let connection_limit = ConnectionLimit::new(16, 1);
+    let release = Arc::new(Semaphore::new(0));
+    let (url, ca, _drop) =
+        spawn_blocking_tls_server(&limit, &connection_limit, release.clone()).await;
+
+    // HTTP/2 multiplexes the requests of the client onto one connection
+    let client = tls_client(&ca).build().unwrap();
+    let busy = tokio::spawn(client.get(&url).send());
+    while limit.in_flight() < 1 {
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+
+    let response = client.get(&url).send().await.unwrap();
+    assert_eq!(response.version(), http::Version::HTTP_2);
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(connection_limit.open(), 1);
+
+    // another connection has its own limit
+    let response = tls_client(&ca)
+        .build()
+        .unwrap()
+        .get(format!("{url}/now"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    release.add_permits(1);
+    assert_eq!(busy.await.unwrap().unwrap().status(), StatusCode::OK);
+
+    release.add_permits(1);
+    let response = client.get(&url).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
@@ -5,12 +5,17 @@ use authly_common::{
         service_message::ServiceMessageKind,
     },
 };
-use authly_service::proto::service_server::AuthlyServiceServerImpl;
+use authly_db::{param::ToBlob, params, Db, FromRow, Row};
+use authly_domain::ctx::GetDb;
+use authly_service::proto::service_server::{
+    AuthlyServiceServerImpl, ETAG, IF_NONE_MATCH, NOT_MODIFIED,
+};
 use futures_util::StreamExt;
 use hexhex::hex_literal;
-use indoc::indoc;
+use indoc::{formatdoc, indoc};
 use itertools::Itertools;
 use serde_json::json;
+use tonic::metadata::{Ascii, MetadataValue};
 use tracing::info;
 
 use crate::{
@@ -38,6 +43,7 @@ async fn test_svc_namespace_metadata() {
         [[service-entity]]
         eid = "s.e5462a0d22b54d9f9ca37bd96e9b9d8b"
         label = "svc"
+        attributes = ["authly:role:receive_messages"]
 
         [[service-domain]]
         service = "svc"
@@ -117,3 +123,287 @@ async fn test_svc_namespace_metadata() {
         }
     }
 }
+
+#[test_log::test(tokio::test)]
+async fn test_svc_metadata_cache() {
+    let ctx = TestCtx::new().inmemory_db().await;
+    let doc = |svc_label: &str| {
+        formatdoc! {
+            r#"
+            [authly-document]
+            id = "bc9ce588-50c3-47d1-94c1-f88b21eaf299"
+
+            [[service-entity]]
+            eid = "s.e5462a0d22b54d9f9ca37bd96e9b9d8b"
+            label = "{svc_label}"
+            "#
+        }
+    };
+
+    compile_and_apply_doc(&doc("svc"), &ctx).await.unwrap();
+
+    assert_eq!(svc_label(&ctx).await, "svc");
+
+    // a change made behind Authly's back is not seen, because the response is served from cache
+    ctx.get_db()
+        .execute(
+            "UPDATE namespace SET label = 'changed' WHERE id = $1".into(),
+            params!(SVC.to_blob()),
+        )
+        .await
+        .unwrap();
+    assert_eq!(svc_label(&ctx).await, "svc");
+
+    // changing the directory invalidates the cache
+    compile_and_apply_doc(&doc("svc2"), &ctx).await.unwrap();
+    assert_eq!(svc_label(&ctx).await, "svc2");
+}
+
+#[test_log::test(tokio::test)]
+async fn test_metadata_reapply_is_canonical() {
+    let ctx = TestCtx::new().inmemory_db().await;
+    let doc = |metadata: &str| {
+        formatdoc! {
+            r#"
+            [authly-document]
+            id = "bc9ce588-50c3-47d1-94c1-f88b21eaf299"
+
+            [[service-entity]]
+            eid = "s.e5462a0d22b54d9f9ca37bd96e9b9d8b"
+            label = "svc"
+            metadata = {metadata}
+            "#
+        }
+    };
+
+    compile_and_apply_doc(&doc(r#"{ b = 1, a = { y = 2.0, x = "z" } }"#), &ctx)
+        .await
+        .unwrap();
+    let stored = stored_metadata(&ctx).await;
+    assert_eq!(stored, r#"{"a":{"x":"z","y":2},"b":1}"#);
+
+    // an equivalent document with different key order and number formatting stores the same value
+    compile_and_apply_doc(&doc(r#"{ a = { x = "z", y = 2 }, b = 1.0 }"#), &ctx)
+        .await
+        .unwrap();
+    assert_eq!(stored_metadata(&ctx).await, stored);
+}
+
+async fn stored_metadata(ctx: &TestCtx) -> String {
+    struct Value(String);
+
+    impl FromRow for Value {
+        fn from_row(row: &mut impl Row) -> Self {
+            Self(row.get_text("value"))
+        }
+    }
+
+    let [value] = ctx
+        .get_db()
+        .query_map::<Value>(
+            "SELECT value FROM obj_text_attr WHERE obj_id = $1".into(),
+            params!(SVC.to_blob()),
+        )
+        .await
+        .unwrap()
+        .into_iter()
+        .collect_array()
+        .unwrap();
+    value.0
+}
+
+async fn svc_label(ctx: &TestCtx) -> String {
+    AuthlyServiceClient::new(AuthlyServiceServerImpl::new_service(ctx.clone()))
+        .get_metadata(tonic_request(proto::Empty {}, SVC))
+        .await
+        .unwrap()
+        .into_inner()
+        .label
+}
+
+#[test_log::test(tokio::test)]
+async fn test_svc_property_mapping_order() {
+    let ctx = TestCtx::new().inmemory_db().await;
+    let doc = indoc! {
+        r#"
+        [authly-document]
+        id = "bc9ce588-50c3-47d1-94c1-f88b21eaf299"
+
+        [[domain]]
+        label = "zoo"
+
+        [[domain]]
+        label = "garden"
+
+        [[service-entity]]
+        eid = "s.e5462a0d22b54d9f9ca37bd96e9b9d8b"
+        label = "svc"
+
+        [[service-domain]]
+        service = "svc"
+        domain = "zoo"
+
+        [[service-domain]]
+        service = "svc"
+        domain = "garden"
+
+        [[resource-property]]
+        namespace = "zoo"
+        label = "verb"
+        attributes = ["watch", "feed"]
+
+        [[resource-property]]
+        namespace = "zoo"
+        label = "kind"
+        attributes = ["zebra", "lion", "giraffe"]
+
+        [[resource-property]]
+        namespace = "garden"
+        label = "kind"
+        attributes = ["tulip", "rose"]
+
+        [[resource-property]]
+        namespace = "svc"
+        label = "kind"
+        attributes = ["b", "a"]
+        "#
+    };
+
+    compile_and_apply_doc(doc, &ctx).await.unwrap();
+
+    let mut client = AuthlyServiceClient::new(AuthlyServiceServerImpl::new_service(ctx.clone()));
+    let namespaces = client
+        .get_resource_property_mappings(tonic_request(proto::Empty {}, SVC))
+        .await
+        .unwrap()
+        .into_inner()
+        .namespaces;
+
+    let labels: Vec<(&str, &str, Vec<&str>)> = namespaces
+        .iter()
+        .flat_map(|ns| {
+            ns.properties.iter().map(|prop| {
+                (
+                    ns.label.as_str(),
+                    prop.label.as_str(),
+                    prop.attributes
+                        .iter()
+                        .map(|attr| attr.label.as_str())
+                        .collect(),
+                )
+            })
+        })
+        .collect();
+
+    assert_eq!(
+        labels,
+        vec![
+            ("garden", "kind", vec!["rose", "tulip"]),
+            ("svc", "kind", vec!["a", "b"]),
+            ("zoo", "kind", vec!["giraffe", "lion", "zebra"]),
+            ("zoo", "verb", vec!["feed", "watch"]),
+        ]
+    );
+
+    for _ in 0..5 {
+        let again = client
+            .get_resource_property_mappings(tonic_request(proto::Empty {}, SVC))
+            .await
+            .unwrap()
+            .into_inner()
+            .namespaces;
+        assert_eq!(again, namespaces);
+
+        let configuration = client
+            .get_configuration(tonic_request(proto::Empty {}, SVC))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(configuration.property_mapping_namespaces, namespaces);
+    }
+}
+
+#[test_log::test(tokio::test)]
+async fn test_svc_configuration_etag() {
+    let ctx = TestCtx::new().inmemory_db().await;
+    let doc = |attributes: &str| {
+        formatdoc! {
+            r#"
+            [authly-document]
+            id = "bc9ce588-50c3-47d1-94c1-f88b21eaf299"
+
+            [[service-entity]]
+            eid = "s.e5462a0d22b54d9f9ca37bd96e9b9d8b"
+            label = "svc"
+
+            [[resource-property]]
+            namespace = "svc"
+            label = "kind"
+            attributes = {attributes}
+            "#
+        }
+    };
+
+    compile_and_apply_doc(&doc(r#"["a"]"#), &ctx).await.unwrap();
+
+    let mut client = AuthlyServiceClient::new(AuthlyServiceServerImpl::new_service(ctx.clone()));
+    let conditional_request = |etag: &MetadataValue<Ascii>| {
+        let mut request = tonic_request(proto::Empty {}, SVC);
+        request.metadata_mut().insert(IF_NONE_MATCH, etag.clone());
+        request
+    };
+
+    let response = client
+        .get_configuration(tonic_request(proto::Empty {}, SVC))
+        .await
+        .unwrap();
+    let etag = response.metadata().get(ETAG).unwrap().clone();
+    assert!(response.metadata().get(NOT_MODIFIED).is_none());
+    assert_eq!(response.into_inner().property_mapping_namespaces.len(), 1);
+
+    // unchanged configuration
+    let response = client
+        .get_configuration(conditional_request(&etag))
+        .await
+        .unwrap();
+    assert_eq!(response.metadata().get(ETAG), Some(&etag));
+    assert!(response.metadata().get(NOT_MODIFIED).is_some());
+    assert!(response.into_inner().property_mapping_namespaces.is_empty());
+
+    let response = client
+        .get_resource_property_mappings(tonic_request(proto::Empty {}, SVC))
+        .await
+        .unwrap();
+    let mappings_etag = response.metadata().get(ETAG).unwrap().clone();
+    let response = client
+        .get_resource_property_mappings(conditional_request(&mappings_etag))
+        .await
+        .unwrap();
+    assert!(response.metadata().get(NOT_MODIFIED).is_some());
+    assert!(response.into_inner().namespaces.is_empty());
+
+    // changed configuration
+    compile_and_apply_doc(&doc(r#"["a", "b"]"#), &ctx)
+        .await
+        .unwrap();
+
+    let response = client
+        .get_configuration(conditional_request(&etag))
+        .await
+        .unwrap();
+    assert_ne!(response.metadata().get(ETAG), Some(&etag));
+    assert!(response.metadata().get(NOT_MODIFIED).is_none());
+    assert_eq!(
+        response.into_inner().property_mapping_namespaces[0].properties[0]
+            .attributes
+            .len(),
+        2
+    );
+
+    let response = client
+        .get_resource_property_mappings(conditional_request(&mappings_etag))
+        .await
+        .unwrap();
+    assert!(response.metadata().get(NOT_MODIFIED).is_none());
+    assert_eq!(response.into_inner().namespaces.len(), 1);
+}
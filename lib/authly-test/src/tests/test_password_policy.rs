@@ -0,0 +1,171 @@
+use std::borrow::Cow;
+
+use authly_common::id::{PersonaId, ServiceId};
+use authly_domain::{
+    ctx::{GetBuiltins, GetDb},
+    password::{check_complexity, Password, PasswordPolicyError},
+    repo::entity_repo,
+    settings::{Setting, Settings},
+};
+use authly_service::scim::{users, ScimAuth, ScimJson};
+use axum::extract::State;
+use http::StatusCode;
+use serde_json::{json, Value};
+use wiremock::{
+    matchers::{method, path},
+    Mock, MockServer, ResponseTemplate,
+};
+
+use crate::test_ctx::TestCtx;
+
+const SVC: ServiceId = ServiceId::from_raw_array([7; 16]);
+
+/// The SHA-1 digest of `password` is 5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8
+const BREACHED_RANGE: &str = "\
+003D68EB55068C33ACE09247EE4C639306B:3\r\n\
+1E4C9B93F3F0682250B6CF8331B7EE68FD8:52256179\r\n\
+01330C689E5D64F660D6947A93AD634EF8F:0\r\n";
+
+async fn password_ctx(settings: &[(Setting, &str)]) -> TestCtx {
+    let mut policy_settings = Settings::default();
+    for (setting, value) in settings {
+        policy_settings
+            .try_set(*setting, Cow::Borrowed(value))
+            .unwrap();
+    }
+
+    TestCtx::new()
+        .inmemory_db()
+        .await
+        .supreme_instance()
+        .await
+        .with_settings(policy_settings)
+}
+
+async fn create_user(ctx: &TestCtx, user_name: &str, password: &str) -> Result<Value, String> {
+    let response = users::create_user(
+        State(ctx.clone()),
+        ScimAuth(SVC),
+        ScimJson(json!({ "userName": user_name, "password": password })),
+    )
+    .await
+    .map_err(|err| {
+        assert_eq!(err.status, StatusCode::BAD_REQUEST);
+        err.detail
+    })?;
+
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    Ok(serde_json::from_slice(&bytes).unwrap())
+}
+
+#[test_log::test(tokio::test)]
+async fn test_password_complexity() {
+    let ctx = password_ctx(&[
+        (Setting::PasswordMinLength, "12"),
+        (Setting::PasswordCharacterClasses, "3"),
+    ])
+    .await;
+
+    assert_eq!(
+        create_user(&ctx, "alice", "Sh0rt!").await.unwrap_err(),
+        "password must be at least 12 characters long"
+    );
+    assert!(create_user(&ctx, "alice", "onlylowercaseletters")
+        .await
+        .unwrap_err()
+        .contains("at least 3 of"));
+
+    let created = create_user(&ctx, "alice", "correct Horse battery")
+        .await
+        .unwrap();
+
+    // the password is never returned, only its hash is stored
+    assert_eq!(created.get("password"), None);
+    let id: PersonaId = created["id"].as_str().unwrap().parse().unwrap();
+    let stored = entity_repo::find_entity_password_hash(ctx.get_db(), id, ctx.get_builtins())
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(stored.secret_hash.starts_with("$argon2"));
+}
+
+#[test_log::test(tokio::test)]
+async fn test_password_breach_check() {
+    let breach_api = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/range/5BAA6"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(BREACHED_RANGE))
+        .mount(&breach_api)
+        .await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(""))
+        .mount(&breach_api)
+        .await;
+
+    let ctx = password_ctx(&[(
+        Setting::PasswordBreachCheck,
+        &format!("{}/range/", breach_api.uri()),
+    )])
+    .await;
+
+    assert_eq!(
+        create_user(&ctx, "alice", "password").await.unwrap_err(),
+        PasswordPolicyError::Breached.to_string()
+    );
+    create_user(&ctx, "alice", "not in any breach")
+        .await
+        .unwrap();
+}
+
+#[test_log::test(tokio::test)]
+async fn test_password_breach_check_unavailable() {
+    let breach_api = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(503))
+        .mount(&breach_api)
+        .await;
+
+    let ctx = password_ctx(&[(
+        Setting::PasswordBreachCheck,
+        &format!("{}/range/", breach_api.uri()),
+    )])
+    .await;
+
+    // an unavailable breach API does not block provisioning
+    create_user(&ctx, "alice", "password").await.unwrap();
+}
+
+#[test]
+fn test_password_policy_setting() {
+    let mut settings = Settings::default();
+    assert_eq!(
+        check_complexity(&settings.password_policy, &Password("1234567".into())),
+        Err(PasswordPolicyError::TooShort(8))
+    );
+
+    for (setting, invalid) in [
+        (Setting::PasswordMinLength, "-1"),
+        (Setting::PasswordCharacterClasses, "0"),
+        (Setting::PasswordCharacterClasses, "5"),
+        (Setting::PasswordBreachCheck, "not a url"),
+    ] {
+        assert!(
+            settings.try_set(setting, Cow::Borrowed(invalid)).is_err(),
+            "{invalid}"
+        );
+    }
+
+    settings
+        .try_set(Setting::PasswordCharacterClasses, Cow::Borrowed("4"))
+        .unwrap();
+    assert_eq!(
+        check_complexity(&settings.password_policy, &Password("abcDEF123".into())),
+        Err(PasswordPolicyError::TooFewCharacterClasses(4))
+    );
+    assert_eq!(
+        check_complexity(&settings.password_policy, &Password("abcDEF12!".into())),
+        Ok(())
+    );
+}
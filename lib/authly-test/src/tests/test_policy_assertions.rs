@@ -0,0 +1,160 @@
+use authly_common::id::ServiceId;
+use authly_domain::{
+    ctx::GetDb,
+    document::{assertion::AssertionOutcome, error::DocError},
+    repo::policy_repo,
+};
+use hexhex::hex_literal;
+use indoc::{formatdoc, indoc};
+
+use crate::{
+    test_ctx::TestCtx,
+    util::{compile_and_apply_doc, TestDocError},
+};
+
+const SVC: ServiceId = ServiceId::from_raw_array(hex_literal!("e5462a0d22b54d9f9ca37bd96e9b9d8b"));
+
+const DOC: &str = indoc! {
+    r#"
+    [authly-document]
+    id = "bc9ce588-50c3-47d1-94c1-f88b21eaf299"
+
+    [[entity]]
+    eid = "p.0fbcd73e1a884424a1615c3c3fdeebec"
+    label = "me"
+
+    [[service-entity]]
+    eid = "s.e5462a0d22b54d9f9ca37bd96e9b9d8b"
+    label = "svc"
+
+    [[entity-property]]
+    namespace = "svc"
+    label = "trait"
+    attributes = ["has_legs", "has_wings"]
+
+    [[resource-property]]
+    namespace = "svc"
+    label = "kind"
+    attributes = ["trousers"]
+
+    [[policy]]
+    label = "allow for legged creatures"
+    allow = "Subject.svc:trait == svc:trait:has_legs"
+
+    [[policy]]
+    label = "allow for me"
+    allow = "Subject.authly:entity == me"
+    "#
+};
+
+const ASSERTIONS: &str = indoc! {
+    r#"
+    [[policy-assertion]]
+    subject-attributes = ["svc:trait:has_legs"]
+    resource-attributes = ["svc:kind:trousers"]
+    expect = "allow"
+
+    [[policy-assertion]]
+    subject-attributes = ["svc:trait:has_wings"]
+    resource-attributes = ["svc:kind:trousers"]
+    expect = "deny"
+
+    [[policy-assertion]]
+    subject = "me"
+    resource-attributes = ["svc:kind:trousers"]
+    expect = "allow"
+    "#
+};
+
+const BINDING: &str = indoc! {
+    r#"
+    [[policy-binding]]
+    attributes = ["svc:kind:trousers"]
+    policies = ["allow for legged creatures", "allow for me"]
+    "#
+};
+
+async fn svc_policy_count(ctx: &TestCtx) -> usize {
+    policy_repo::load_svc_policies_with_bindings(ctx.get_db(), SVC)
+        .await
+        .unwrap()
+        .policies
+        .len()
+}
+
+#[test_log::test(tokio::test)]
+async fn test_policy_assertions_hold() {
+    let ctx = TestCtx::new().inmemory_db().await;
+
+    // the assertions may appear anywhere in the document
+    let doc = formatdoc! {"
+        {DOC}
+        {ASSERTIONS}
+        {BINDING}
+    "};
+
+    compile_and_apply_doc(&doc, &ctx).await.unwrap();
+
+    assert_eq!(svc_policy_count(&ctx).await, 2);
+}
+
+#[test_log::test(tokio::test)]
+async fn test_policy_assertion_failure_blocks_apply() {
+    let ctx = TestCtx::new().inmemory_db().await;
+    let failing_assertion = indoc! {
+        r#"
+        [[policy-assertion]]
+        subject-attributes = ["svc:trait:has_wings"]
+        resource-attributes = ["svc:kind:trousers"]
+        expect = "allow""#
+    };
+    let doc = formatdoc! {"
+        {DOC}
+        {ASSERTIONS}
+        {failing_assertion}
+        # winged creatures wear no trousers
+
+        {BINDING}
+    "};
+
+    let TestDocError::Doc(errors) = compile_and_apply_doc(&doc, &ctx).await.unwrap_err() else {
+        panic!()
+    };
+    assert_eq!(errors.len(), 1);
+    let spanned_error = errors.into_iter().next().unwrap();
+
+    assert!(matches!(
+        spanned_error.as_ref(),
+        DocError::PolicyAssertionFailed {
+            expected: AssertionOutcome::Allow
+        }
+    ));
+    assert_eq!(failing_assertion, &doc[spanned_error.span()]);
+
+    assert_eq!(svc_policy_count(&ctx).await, 0);
+}
+
+#[test_log::test(tokio::test)]
+async fn test_policy_assertion_unresolved_attribute() {
+    let ctx = TestCtx::new().inmemory_db().await;
+    let doc = formatdoc! {r#"
+        {DOC}
+        {BINDING}
+        [[policy-assertion]]
+        subject-attributes = ["svc:trait:has_fins"]
+        resource-attributes = ["svc:kind:trousers"]
+        expect = "deny"
+    "#};
+
+    let TestDocError::Doc(errors) = compile_and_apply_doc(&doc, &ctx).await.unwrap_err() else {
+        panic!()
+    };
+    assert_eq!(errors.len(), 1);
+    let spanned_error = errors.into_iter().next().unwrap();
+
+    assert!(matches!(
+        spanned_error.as_ref(),
+        DocError::UnresolvedAttribute
+    ));
+    assert_eq!("\"svc:trait:has_fins\"", &doc[spanned_error.span()]);
+}
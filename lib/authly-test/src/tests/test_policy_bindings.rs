@@ -0,0 +1,122 @@
+use authly_common::id::ServiceId;
+use authly_service::proto::policy_server::{
+    proto::{self, authly_policy_client::AuthlyPolicyClient},
+    AuthlyPolicyServerImpl,
+};
+use hexhex::hex_literal;
+use indoc::indoc;
+
+use crate::{
+    test_ctx::TestCtx,
+    util::{compile_and_apply_doc, tonic_request},
+};
+
+const SVC_A: ServiceId =
+    ServiceId::from_raw_array(hex_literal!("e5462a0d22b54d9f9ca37bd96e9b9d8b"));
+const SVC_B: ServiceId =
+    ServiceId::from_raw_array(hex_literal!("015362d6655447c6b7f44865bd111c70"));
+
+/// Get the policy bindings through gRPC, as called by the given service
+async fn get_policy_bindings(
+    ctx: &TestCtx,
+    svc_eid: ServiceId,
+    bytecode: bool,
+) -> proto::PolicyBindings {
+    AuthlyPolicyClient::new(AuthlyPolicyServerImpl::new_service(ctx.clone()))
+        .get_policy_bindings(tonic_request(
+            proto::GetPolicyBindingsRequest { bytecode },
+            svc_eid,
+        ))
+        .await
+        .unwrap()
+        .into_inner()
+}
+
+fn policy_labels(bindings: &proto::PolicyBindings) -> Vec<&str> {
+    let mut labels: Vec<&str> = bindings
+        .policies
+        .iter()
+        .map(|policy| policy.label.as_str())
+        .collect();
+    labels.sort();
+    labels
+}
+
+#[test_log::test(tokio::test)]
+async fn test_policy_bindings_of_own_service() {
+    let ctx = TestCtx::new().inmemory_db().await;
+    let doc = indoc! {
+        r#"
+        [authly-document]
+        id = "bc9ce588-50c3-47d1-94c1-f88b21eaf299"
+
+        [[service-entity]]
+        eid = "s.e5462a0d22b54d9f9ca37bd96e9b9d8b"
+        label = "svc_a"
+
+        [[service-entity]]
+        eid = "s.015362d6655447c6b7f44865bd111c70"
+        label = "svc_b"
+
+        [[entity-property]]
+        namespace = "svc_a"
+        label = "trait"
+        attributes = ["has_legs"]
+
+        [[resource-property]]
+        namespace = "svc_a"
+        label = "kind"
+        attributes = ["trousers"]
+
+        [[entity-property]]
+        namespace = "svc_b"
+        label = "trait"
+        attributes = ["has_wings"]
+
+        [[resource-property]]
+        namespace = "svc_b"
+        label = "kind"
+        attributes = ["sky"]
+
+        [[policy]]
+        label = "allow for legged creatures"
+        allow = "Subject.svc_a:trait == svc_a:trait:has_legs"
+
+        [[policy]]
+        label = "allow for winged creatures"
+        allow = "Subject.svc_b:trait == svc_b:trait:has_wings"
+
+        [[policy-binding]]
+        attributes = ["svc_a:kind:trousers"]
+        policies = ["allow for legged creatures"]
+
+        [[policy-binding]]
+        attributes = ["svc_b:kind:sky"]
+        policies = ["allow for winged creatures"]
+        "#
+    };
+
+    compile_and_apply_doc(doc, &ctx).await.unwrap();
+
+    let bindings_a = get_policy_bindings(&ctx, SVC_A, false).await;
+    assert_eq!(policy_labels(&bindings_a), ["allow for legged creatures"]);
+    assert_eq!(bindings_a.bindings.len(), 1);
+    assert_eq!(
+        bindings_a.bindings[0].policy_ids,
+        [bindings_a.policies[0].id.clone()]
+    );
+    assert_eq!(bindings_a.policies[0].class, "allow");
+    assert!(bindings_a.policies[0].bytecode.is_none());
+
+    let bindings_b = get_policy_bindings(&ctx, SVC_B, true).await;
+    assert_eq!(policy_labels(&bindings_b), ["allow for winged creatures"]);
+    assert_eq!(bindings_b.bindings.len(), 1);
+    assert_ne!(
+        bindings_a.bindings[0].attr_matcher,
+        bindings_b.bindings[0].attr_matcher
+    );
+    assert!(bindings_b.policies[0]
+        .bytecode
+        .as_ref()
+        .is_some_and(|bytecode| !bytecode.is_empty()));
+}
@@ -0,0 +1,132 @@
+use authly_common::{
+    document::Document,
+    id::{AttrId, PropId, ServiceId},
+    policy::code::OpCode,
+};
+use authly_domain::{
+    document::{
+        compiled_document::{CompiledDocumentData, DocumentMeta},
+        doc_compiler::compile_doc,
+    },
+    id::BuiltinProp,
+};
+use hexhex::hex_literal;
+use indoc::indoc;
+
+use crate::test_ctx::TestCtx;
+
+const SVC: ServiceId = ServiceId::from_raw_array(hex_literal!("e5462a0d22b54d9f9ca37bd96e9b9d8b"));
+
+const DOC: &str = indoc! {r#"
+    [authly-document]
+    id = "bc9ce588-50c3-47d1-94c1-f88b21eaf299"
+
+    [[service-entity]]
+    eid = "s.e5462a0d22b54d9f9ca37bd96e9b9d8b"
+    label = "svc"
+
+    [[entity-property]]
+    namespace = "svc"
+    label = "role"
+    attributes = ["root", "guest"]
+
+    [[resource-property]]
+    namespace = "svc"
+    label = "kind"
+    attributes = ["trousers"]
+
+    [[policy]]
+    label = "entity"
+    allow = "Subject.authly:entity == svc"
+
+    [[policy]]
+    label = "subject attribute"
+    allow = "Subject.svc:role contains svc:role:root"
+
+    [[policy]]
+    label = "resource attribute"
+    deny = "Resource.svc:kind contains svc:kind:trousers"
+
+    [[policy]]
+    label = "logic"
+    allow = "(not (Subject.svc:role contains svc:role:root or Subject.svc:role contains svc:role:guest)) and Subject.authly:entity == svc"
+"#};
+
+fn attr(data: &CompiledDocumentData, prop_label: &str, attr_label: &str) -> AttrId {
+    let prop = data
+        .domain_props
+        .iter()
+        .find(|prop| prop.label == prop_label)
+        .unwrap();
+    data.find_attribute_by_label(prop.id, attr_label).unwrap()
+}
+
+#[test_log::test(tokio::test)]
+async fn test_compiled_policy_opcodes() {
+    let ctx = TestCtx::new().inmemory_db().await;
+    let compiled = compile_doc(
+        &ctx,
+        Document::from_toml(DOC).unwrap(),
+        Default::default(),
+        DocumentMeta::default(),
+    )
+    .await
+    .unwrap();
+    let data = &compiled.data;
+
+    let entity = PropId::from(BuiltinProp::Entity);
+    let root = attr(data, "role", "root");
+    let guest = attr(data, "role", "guest");
+    let trousers = attr(data, "kind", "trousers");
+
+    assert_eq!(
+        data.find_policy_opcodes("entity").unwrap(),
+        [
+            OpCode::LoadSubjectId(entity),
+            OpCode::LoadConstEntityId(SVC.upcast()),
+            OpCode::IsEq,
+            OpCode::Return,
+        ]
+    );
+
+    assert_eq!(
+        data.find_policy_opcodes("subject attribute").unwrap(),
+        [
+            OpCode::LoadConstAttrId(root),
+            OpCode::LoadSubjectAttrs,
+            OpCode::IdSetContains,
+            OpCode::Return,
+        ]
+    );
+
+    assert_eq!(
+        data.find_policy_opcodes("resource attribute").unwrap(),
+        [
+            OpCode::LoadConstAttrId(trousers),
+            OpCode::LoadResourceAttrs,
+            OpCode::IdSetContains,
+            OpCode::Return,
+        ]
+    );
+
+    assert_eq!(
+        data.find_policy_opcodes("logic").unwrap(),
+        [
+            OpCode::LoadConstAttrId(root),
+            OpCode::LoadSubjectAttrs,
+            OpCode::IdSetContains,
+            OpCode::LoadConstAttrId(guest),
+            OpCode::LoadSubjectAttrs,
+            OpCode::IdSetContains,
+            OpCode::Or,
+            OpCode::Not,
+            OpCode::LoadSubjectId(entity),
+            OpCode::LoadConstEntityId(SVC.upcast()),
+            OpCode::IsEq,
+            OpCode::And,
+            OpCode::Return,
+        ]
+    );
+
+    assert!(data.find_policy_opcodes("nonexistent").is_none());
+}
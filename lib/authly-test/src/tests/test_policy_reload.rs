@@ -0,0 +1,124 @@
+use std::{collections::BTreeSet, sync::Arc};
+
+use authly_common::{
+    id::{AttrId, PolicyId, ServiceId},
+    policy::{
+        code::{to_bytecode, OpCode, PolicyValue},
+        engine::{AccessControlParams, PolicyEngine},
+    },
+};
+use authly_domain::{
+    access_control::{self, DecisionReason},
+    ctx::GetPolicyEngineCache,
+};
+
+use crate::test_ctx::TestCtx;
+
+const SVC: ServiceId = ServiceId::from_raw_array([7; 16]);
+const OLD: AttrId = AttrId::from_uint(1);
+const NEW: AttrId = AttrId::from_uint(2);
+
+/// An engine with a single policy, allowing access to resources with the given attribute
+fn engine(policy_id: PolicyId, resource_attr: AttrId) -> Arc<PolicyEngine> {
+    let mut engine = PolicyEngine::default();
+    engine.add_policy(
+        policy_id,
+        PolicyValue::Allow,
+        to_bytecode(&[
+            OpCode::LoadConstAttrId(resource_attr),
+            OpCode::LoadResourceAttrs,
+            OpCode::IdSetContains,
+            OpCode::Return,
+        ]),
+    );
+    engine.add_trigger(BTreeSet::from([resource_attr]), BTreeSet::from([policy_id]));
+    Arc::new(engine)
+}
+
+fn params(resource_attr: AttrId) -> AccessControlParams {
+    AccessControlParams {
+        resource_attrs: FromIterator::from_iter([resource_attr]),
+        subject_attrs: FromIterator::from_iter([AttrId::from_uint(3)]),
+        ..Default::default()
+    }
+}
+
+#[test_log::test(tokio::test(flavor = "multi_thread", worker_threads = 4))]
+async fn test_policy_reload_during_evaluation() {
+    let ctx = TestCtx::new().inmemory_db().await.supreme_instance().await;
+    let old_engine = engine(PolicyId::from_uint(1), OLD);
+    let new_engine = engine(PolicyId::from_uint(2), NEW);
+
+    let cache = ctx.get_policy_engine_cache();
+    cache.insert(cache.generation(), SVC, old_engine.clone());
+
+    let evaluators = (0..8)
+        .map(|_| {
+            let ctx = ctx.clone();
+            tokio::spawn(async move {
+                for _ in 0..500 {
+                    let engine = access_control::svc_policy_engine(&ctx, SVC).await.unwrap();
+                    let old = access_control::evaluate(&engine, &params(OLD));
+                    tokio::task::yield_now().await;
+                    let new = access_control::evaluate(&engine, &params(NEW));
+
+                    // one captured engine gives decisions from the same set of policies
+                    match (old.reason, new.reason) {
+                        (DecisionReason::Allowed, DecisionReason::PolicyDenied)
+                        | (DecisionReason::PolicyDenied, DecisionReason::Allowed) => {}
+                        // the engine loaded from the database after an invalidation has no policies
+                        (DecisionReason::NoPolicies, DecisionReason::NoPolicies) => {}
+                        other => panic!("inconsistent decisions: {other:?}"),
+                    }
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let reloader = {
+        let ctx = ctx.clone();
+        tokio::spawn(async move {
+            let cache = ctx.get_policy_engine_cache();
+            for i in 0..500 {
+                cache.invalidate_all();
+                let engine = if i % 2 == 0 { &new_engine } else { &old_engine };
+                cache.insert(cache.generation(), SVC, engine.clone());
+                tokio::task::yield_now().await;
+            }
+        })
+    };
+
+    for evaluator in evaluators {
+        evaluator.await.unwrap();
+    }
+    reloader.await.unwrap();
+}
+
+#[test_log::test(tokio::test)]
+async fn test_policy_engine_cache_generation() {
+    let ctx = TestCtx::new().inmemory_db().await.supreme_instance().await;
+    let cache = ctx.get_policy_engine_cache();
+
+    // an engine loaded before an invalidation is not cached
+    let stale = cache.generation();
+    cache.invalidate_all();
+    cache.insert(stale, SVC, engine(PolicyId::from_uint(1), OLD));
+    assert!(cache.get(SVC).is_none());
+
+    // loaded from the database on a miss, then cached
+    let loaded = access_control::svc_policy_engine(&ctx, SVC).await.unwrap();
+    assert_eq!(loaded.get_policy_count(), 0);
+    assert!(Arc::ptr_eq(&loaded, &cache.get(SVC).unwrap()));
+
+    // an evaluation keeps the engine it started with
+    cache.invalidate_all();
+    cache.insert(cache.generation(), SVC, engine(PolicyId::from_uint(1), OLD));
+    assert_eq!(loaded.get_policy_count(), 0);
+    assert_eq!(
+        access_control::svc_policy_engine(&ctx, SVC)
+            .await
+            .unwrap()
+            .get_policy_count(),
+        1
+    );
+}
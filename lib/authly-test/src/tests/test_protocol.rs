@@ -0,0 +1,112 @@
+use authly_common::{
+    id::ServiceId,
+    proto::service::{self as proto, authly_service_client::AuthlyServiceClient},
+};
+use authly_service::proto::{
+    protocol::{Capability, ServerProtocol, CAPABILITIES, PROTOCOL_VERSION},
+    service_server::{AuthlyServiceServerImpl, ETAG, IF_NONE_MATCH, NOT_MODIFIED},
+};
+use hexhex::hex_literal;
+use indoc::indoc;
+use tonic::metadata::{Ascii, MetadataMap, MetadataValue};
+
+use crate::{
+    test_ctx::TestCtx,
+    util::{compile_and_apply_doc, tonic_request},
+};
+
+const SVC: ServiceId = ServiceId::from_raw_array(hex_literal!("e5462a0d22b54d9f9ca37bd96e9b9d8b"));
+
+const DOC: &str = indoc! {
+    r#"
+    [authly-document]
+    id = "bc9ce588-50c3-47d1-94c1-f88b21eaf299"
+
+    [[service-entity]]
+    eid = "s.e5462a0d22b54d9f9ca37bd96e9b9d8b"
+    label = "svc"
+
+    [[resource-property]]
+    namespace = "svc"
+    label = "action"
+    attributes = ["read"]
+    "#
+};
+
+/// The configuration request of a client, conditional only when the server supports it
+fn configuration_request(
+    protocol: &ServerProtocol,
+    etag: &MetadataValue<Ascii>,
+) -> tonic::Request<proto::Empty> {
+    let mut request = tonic_request(proto::Empty {}, SVC);
+    if protocol.supports(Capability::ConditionalConfiguration) {
+        request.metadata_mut().insert(IF_NONE_MATCH, etag.clone());
+    }
+    request
+}
+
+#[test_log::test(tokio::test)]
+async fn test_server_announces_protocol() {
+    let ctx = TestCtx::new().inmemory_db().await;
+    compile_and_apply_doc(DOC, &ctx).await.unwrap();
+
+    let mut client = AuthlyServiceClient::new(AuthlyServiceServerImpl::new_service(ctx.clone()));
+    let response = client
+        .get_metadata(tonic_request(proto::Empty {}, SVC))
+        .await
+        .unwrap();
+
+    let protocol = ServerProtocol::from_metadata(response.metadata());
+    assert_eq!(protocol, ServerProtocol::current());
+    assert!(protocol.supports(Capability::ConditionalConfiguration));
+}
+
+#[test_log::test(tokio::test)]
+async fn test_client_falls_back_without_capability() {
+    let ctx = TestCtx::new().inmemory_db().await;
+    compile_and_apply_doc(DOC, &ctx).await.unwrap();
+
+    let mut client = AuthlyServiceClient::new(AuthlyServiceServerImpl::new_service(ctx.clone()));
+    let response = client
+        .get_configuration(tonic_request(proto::Empty {}, SVC))
+        .await
+        .unwrap();
+    let etag = response.metadata().get(ETAG).unwrap().clone();
+
+    // a server from before protocol versioning announces nothing
+    let legacy = ServerProtocol::from_metadata(&MetadataMap::new());
+    assert_eq!(legacy.version, 0);
+    assert!(!legacy.supports(Capability::ConditionalConfiguration));
+
+    // so the client asks for the whole configuration
+    let response = client
+        .get_configuration(configuration_request(&legacy, &etag))
+        .await
+        .unwrap();
+    assert!(response.metadata().get(NOT_MODIFIED).is_none());
+    assert_eq!(response.into_inner().property_mapping_namespaces.len(), 1);
+
+    let response = client
+        .get_configuration(configuration_request(&ServerProtocol::current(), &etag))
+        .await
+        .unwrap();
+    assert!(response.metadata().get(NOT_MODIFIED).is_some());
+    assert!(response.into_inner().property_mapping_namespaces.is_empty());
+}
+
+#[test]
+fn test_unknown_capabilities_are_ignored() {
+    let mut metadata = MetadataMap::new();
+    metadata.insert(PROTOCOL_VERSION, MetadataValue::from_static("2"));
+    metadata.insert(
+        CAPABILITIES,
+        MetadataValue::from_static("allowed-methods,batch-access-control"),
+    );
+
+    let protocol = ServerProtocol::from_metadata(&metadata);
+    assert_eq!(protocol.version, 2);
+    assert_eq!(
+        protocol.capabilities.into_iter().collect::<Vec<_>>(),
+        vec![Capability::AllowedMethods]
+    );
+}
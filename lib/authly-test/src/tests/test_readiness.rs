@@ -0,0 +1,80 @@
+use std::time::Duration;
+
+use authly_common::id::DirectoryId;
+use authly_domain::{
+    document::compiled_document::DocumentMeta,
+    readiness::{self, ExpectedDocument, Readiness},
+};
+use http::StatusCode;
+use indoc::indoc;
+
+use crate::{test_ctx::TestCtx, util::compile_and_apply_doc};
+
+const DOC: &str = indoc! {
+    r#"
+    [authly-document]
+    id = "bc9ce588-50c3-47d1-94c1-f88b21eaf299"
+
+    [[service-entity]]
+    eid = "s.e5462a0d22b54d9f9ca37bd96e9b9d8b"
+    label = "svc"
+    "#
+};
+
+async fn spawn_readiness_server(readiness: Readiness) -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/health/readiness", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, readiness::router(readiness)).await });
+
+    url
+}
+
+async fn probe(url: &str) -> StatusCode {
+    reqwest::get(url).await.unwrap().status()
+}
+
+#[test_log::test(tokio::test)]
+async fn test_readiness_gated_on_documents() {
+    let ctx = TestCtx::new().inmemory_db().await;
+    let readiness = Readiness::default();
+    let url = spawn_readiness_server(readiness.clone()).await;
+
+    let waiter = tokio::spawn({
+        let ctx = ctx.clone();
+        let readiness = readiness.clone();
+        async move {
+            let expected = [ExpectedDocument {
+                dir_id: DirectoryId::from_uint(0xbc9ce58850c347d194c1f88b21eaf299),
+                hash: DocumentMeta::default().hash,
+            }];
+            readiness::open_when_documents_applied(
+                &ctx,
+                &readiness,
+                &expected,
+                Duration::from_millis(10),
+            )
+            .await
+        }
+    });
+
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(probe(&url).await, StatusCode::SERVICE_UNAVAILABLE);
+
+    compile_and_apply_doc(DOC, &ctx).await.unwrap();
+
+    tokio::time::timeout(Duration::from_secs(5), waiter)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(probe(&url).await, StatusCode::OK);
+}
+
+#[test_log::test(tokio::test)]
+async fn test_readiness_without_documents() {
+    let ctx = TestCtx::new().inmemory_db().await;
+    let readiness = Readiness::default();
+    let url = spawn_readiness_server(readiness.clone()).await;
+
+    readiness::open_when_documents_applied(&ctx, &readiness, &[], Duration::from_millis(10)).await;
+    assert_eq!(probe(&url).await, StatusCode::OK);
+}
@@ -0,0 +1,340 @@
+use std::borrow::Cow;
+
+use authly_common::{
+    id::{AttrId, PersonaId, ServiceId},
+    mtls_server::PeerServiceEntity,
+};
+use authly_domain::{
+    clock::ManualClock,
+    ctx::{GetDb, GetIdGenerator},
+    directory::JitProvisioning,
+    id::BuiltinProp,
+    login::{self, LoginError, LoginOptions},
+    persona_directory::{self, ForeignClaims, ForeignLinkError, ForeignPersona},
+    repo::{crypto_repo, entity_repo, scim_repo},
+    scim::{self, ScimError},
+    session::Session,
+    settings::{Setting, Settings},
+};
+use authly_service::scim::{users, ListQuery, ScimAuth, ScimErrorResponse, ScimJson};
+use axum::{
+    extract::{FromRequestParts, Path, Query, State},
+    response::Response,
+};
+use hexhex::hex_literal;
+use http::{header::AUTHORIZATION, StatusCode};
+use serde_json::{json, Value};
+use time::{Duration, OffsetDateTime};
+
+use crate::{test_ctx::TestCtx, util::compile_and_apply_doc_dir};
+
+const SVC: ServiceId = ServiceId::from_raw_array([7; 16]);
+const TESTSERVICE: ServiceId =
+    ServiceId::from_raw_array(hex_literal!("f3e799137c034e1eb4cd3e4f65705932"));
+
+async fn scim_ctx() -> TestCtx {
+    let mut settings = Settings::default();
+    settings
+        .try_set(
+            Setting::ScimAttributeMapping,
+            Cow::Borrowed("title=testservice:role"),
+        )
+        .unwrap();
+
+    let ctx = TestCtx::new()
+        .inmemory_db()
+        .await
+        .supreme_instance()
+        .await
+        .with_settings(settings);
+    compile_and_apply_doc_dir("../../examples/demo".into(), &ctx)
+        .await
+        .unwrap();
+    ctx
+}
+
+async fn json_body(response: Response) -> Value {
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    serde_json::from_slice(&bytes).unwrap()
+}
+
+async fn role_attr(ctx: &TestCtx, label: &str) -> AttrId {
+    scim_repo::list_labeled_property_attrs(ctx.get_db(), "testservice", "role")
+        .await
+        .unwrap()
+        .into_iter()
+        .find(|(_, attr_label)| attr_label == label)
+        .unwrap()
+        .0
+}
+
+#[test_log::test(tokio::test)]
+async fn test_scim_user_lifecycle() {
+    let ctx = scim_ctx().await;
+    let ui_user = role_attr(&ctx, "ui/user").await;
+    let ui_admin = role_attr(&ctx, "ui/admin").await;
+
+    // create
+    let response = users::create_user(
+        State(ctx.clone()),
+        ScimAuth(SVC),
+        ScimJson(json!({
+            "schemas": ["urn:ietf:params:scim:schemas:core:2.0:User"],
+            "userName": "alice",
+            "externalId": "ext-1",
+            "emails": [{ "value": "alice@example.com", "primary": true }],
+            "title": "ui/user",
+        })),
+    )
+    .await
+    .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let created = json_body(response).await;
+    assert_eq!(created["userName"], json!("alice"));
+    assert_eq!(created["title"], json!("ui/user"));
+    let id: PersonaId = created["id"].as_str().unwrap().parse().unwrap();
+
+    assert_eq!(
+        crypto_repo::lookup_obj_ident(&ctx, BuiltinProp::Username.into(), "alice")
+            .await
+            .unwrap(),
+        Some(id.upcast())
+    );
+    assert_eq!(
+        crypto_repo::lookup_obj_ident(&ctx, BuiltinProp::Email.into(), "alice@example.com")
+            .await
+            .unwrap(),
+        Some(id.upcast())
+    );
+    let attrs = entity_repo::list_entity_attrs(ctx.get_db(), id.upcast())
+        .await
+        .unwrap();
+    assert!(attrs.contains(&ui_user));
+    assert!(!attrs.contains(&ui_admin));
+
+    // the user name is unique
+    let conflict = users::create_user(
+        State(ctx.clone()),
+        ScimAuth(SVC),
+        ScimJson(json!({ "userName": "alice" })),
+    )
+    .await
+    .err()
+    .unwrap();
+    assert_eq!(conflict.status, StatusCode::CONFLICT);
+
+    // filter
+    let listed = json_body(
+        users::list_users(
+            State(ctx.clone()),
+            ScimAuth(SVC),
+            Query(ListQuery {
+                filter: Some(r#"userName eq "ALICE""#.to_string()),
+                ..Default::default()
+            }),
+        )
+        .await
+        .unwrap(),
+    )
+    .await;
+    assert_eq!(listed["totalResults"], json!(1));
+    assert_eq!(listed["Resources"][0]["id"], created["id"]);
+
+    // patch an attribute
+    let response = users::patch_user(
+        State(ctx.clone()),
+        ScimAuth(SVC),
+        Path(id.to_string()),
+        ScimJson(
+            serde_json::from_value(json!({
+                "schemas": ["urn:ietf:params:scim:api:messages:2.0:PatchOp"],
+                "Operations": [{ "op": "replace", "path": "title", "value": "ui/admin" }],
+            }))
+            .unwrap(),
+        ),
+    )
+    .await
+    .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(json_body(response).await["title"], json!("ui/admin"));
+
+    let attrs = entity_repo::list_entity_attrs(ctx.get_db(), id.upcast())
+        .await
+        .unwrap();
+    assert!(!attrs.contains(&ui_user));
+    assert!(attrs.contains(&ui_admin));
+
+    // unknown attribute values are rejected
+    let invalid = users::patch_user(
+        State(ctx.clone()),
+        ScimAuth(SVC),
+        Path(id.to_string()),
+        ScimJson(
+            serde_json::from_value(json!({
+                "Operations": [{ "op": "add", "path": "title", "value": "ui/nobody" }],
+            }))
+            .unwrap(),
+        ),
+    )
+    .await
+    .err()
+    .unwrap();
+    assert_eq!(invalid.status, StatusCode::BAD_REQUEST);
+
+    // delete
+    let response = users::delete_user(State(ctx.clone()), ScimAuth(SVC), Path(id.to_string()))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    assert_eq!(
+        crypto_repo::lookup_obj_ident(&ctx, BuiltinProp::Username.into(), "alice")
+            .await
+            .unwrap(),
+        None
+    );
+    assert!(entity_repo::list_entity_attrs(ctx.get_db(), id.upcast())
+        .await
+        .unwrap()
+        .is_empty());
+
+    let not_found = users::get_user(State(ctx.clone()), ScimAuth(SVC), Path(id.to_string()))
+        .await
+        .err()
+        .unwrap();
+    assert_eq!(not_found.status, StatusCode::NOT_FOUND);
+}
+
+async fn scim_auth(ctx: &TestCtx, token: &str) -> Result<ScimAuth, ScimErrorResponse> {
+    let (mut parts, _) = http::Request::builder()
+        .header(AUTHORIZATION, format!("Bearer {token}"))
+        .body(())
+        .unwrap()
+        .into_parts();
+    ScimAuth::from_request_parts(&mut parts, ctx).await
+}
+
+#[test_log::test(tokio::test)]
+async fn test_scim_bearer_token() {
+    let clock = ManualClock::new(OffsetDateTime::now_utc());
+    let ctx = scim_ctx().await.with_clock(clock.clone());
+    let token = scim::issue_scim_token(&ctx, TESTSERVICE).await.unwrap();
+
+    assert_eq!(scim_auth(&ctx, &token).await.unwrap().0, TESTSERVICE);
+    assert_eq!(
+        scim_auth(&ctx, "wrong").await.err().unwrap().status,
+        StatusCode::UNAUTHORIZED
+    );
+
+    // tokens are only issued for services that exist
+    assert!(matches!(
+        scim::issue_scim_token(&ctx, SVC).await,
+        Err(ScimError::InvalidValue(_))
+    ));
+
+    // tokens expire
+    clock.advance(Duration::days(91));
+    assert_eq!(
+        scim_auth(&ctx, &token).await.err().unwrap().status,
+        StatusCode::UNAUTHORIZED
+    );
+}
+
+#[test_log::test(tokio::test)]
+async fn test_scim_token_revocation() {
+    let ctx = scim_ctx().await;
+    let tokens = [
+        scim::issue_scim_token(&ctx, TESTSERVICE).await.unwrap(),
+        scim::issue_scim_token(&ctx, TESTSERVICE).await.unwrap(),
+    ];
+
+    assert_eq!(
+        scim::revoke_scim_tokens(&ctx, TESTSERVICE).await.unwrap(),
+        2
+    );
+    for token in &tokens {
+        assert_eq!(
+            scim_auth(&ctx, token).await.err().unwrap().status,
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    // tokens issued after the revocation are valid
+    let token = scim::issue_scim_token(&ctx, TESTSERVICE).await.unwrap();
+    assert_eq!(scim_auth(&ctx, &token).await.unwrap().0, TESTSERVICE);
+}
+
+async fn login_bob(ctx: &TestCtx) -> Result<(PersonaId, Session), LoginError> {
+    login::try_username_password_login(
+        ctx,
+        PeerServiceEntity(TESTSERVICE),
+        "bob".to_string(),
+        "secret password".to_string(),
+        LoginOptions::default(),
+    )
+    .await
+}
+
+#[test_log::test(tokio::test)]
+async fn test_scim_deactivated_user_cannot_log_in() {
+    let ctx = scim_ctx().await;
+
+    let response = users::create_user(
+        State(ctx.clone()),
+        ScimAuth(SVC),
+        ScimJson(json!({
+            "userName": "bob",
+            "emails": [{ "value": "bob@example.com", "primary": true }],
+            "password": "secret password",
+        })),
+    )
+    .await
+    .unwrap();
+    let id: PersonaId = json_body(response).await["id"]
+        .as_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+
+    assert_eq!(login_bob(&ctx).await.ok().unwrap().0, id);
+
+    users::patch_user(
+        State(ctx.clone()),
+        ScimAuth(SVC),
+        Path(id.to_string()),
+        ScimJson(
+            serde_json::from_value(json!({
+                "Operations": [{ "op": "replace", "path": "active", "value": false }],
+            }))
+            .unwrap(),
+        ),
+    )
+    .await
+    .unwrap();
+
+    assert!(matches!(
+        login_bob(&ctx).await,
+        Err(LoginError::Credentials)
+    ));
+
+    // a foreign identity with the same email address is not linked to the deactivated user
+    let (dir_key, _) =
+        scim_repo::get_or_create_scim_directory(ctx.get_db(), ctx.get_id_generator().generate())
+            .await
+            .unwrap();
+    let result = persona_directory::login_foreign_persona(
+        &ctx,
+        dir_key,
+        &JitProvisioning::unrestricted(),
+        ForeignPersona {
+            foreign_id: b"bob".to_vec(),
+            email: "bob@example.com".to_string(),
+        },
+        ForeignClaims::default(),
+    )
+    .await;
+    assert!(matches!(result, Err(ForeignLinkError::Deactivated)));
+}
@@ -0,0 +1,179 @@
+use std::{borrow::Cow, net::IpAddr};
+
+use authly_common::{
+    id::ServiceId,
+    proto::service::{self as proto, authly_service_client::AuthlyServiceClient},
+};
+use authly_domain::{
+    cert::{key_pair, server_cert_csr},
+    clock::ManualClock,
+    service::limit_cert_validity,
+    settings::{Setting, Settings},
+};
+use authly_service::proto::service_server::AuthlyServiceServerImpl;
+use hexhex::hex_literal;
+use indoc::indoc;
+use rcgen::SanType;
+use time::{Duration, OffsetDateTime};
+use tonic::Code;
+
+use crate::{
+    test_ctx::TestCtx,
+    util::{compile_and_apply_doc, tonic_request},
+};
+
+const SVC_A: ServiceId =
+    ServiceId::from_raw_array(hex_literal!("e5462a0d22b54d9f9ca37bd96e9b9d8b"));
+
+async fn cert_ctx(settings: &[(Setting, &str)], now: OffsetDateTime) -> TestCtx {
+    let mut s = Settings::default();
+    for (setting, value) in settings {
+        s.try_set(*setting, Cow::Borrowed(*value)).unwrap();
+    }
+
+    let ctx = TestCtx::new()
+        .inmemory_db()
+        .await
+        .supreme_instance()
+        .await
+        .with_settings(s)
+        .with_clock(ManualClock::new(now));
+    let doc = indoc! {
+        r#"
+        [authly-document]
+        id = "bc9ce588-50c3-47d1-94c1-f88b21eaf299"
+
+        [[service-entity]]
+        eid = "s.e5462a0d22b54d9f9ca37bd96e9b9d8b"
+        label = "svc_a"
+        hosts = ["svc-a"]
+        attributes = ["authly:role:sign_certificate"]
+        "#
+    };
+    compile_and_apply_doc(doc, &ctx).await.unwrap();
+    ctx
+}
+
+/// Request a certificate for SVC_A with the given alt names in addition to its host
+async fn sign_certificate(
+    ctx: &TestCtx,
+    alt_names: Vec<SanType>,
+) -> tonic::Result<x509_parser::time::ASN1Time> {
+    let mut params = server_cert_csr(
+        &SVC_A.to_string(),
+        vec!["svc-a".to_string()],
+        Duration::days(10 * 365),
+    )
+    .unwrap();
+    params.subject_alt_names.extend(alt_names);
+    let der = params
+        .serialize_request(&key_pair())
+        .unwrap()
+        .der()
+        .to_vec();
+
+    let mut client = AuthlyServiceClient::new(AuthlyServiceServerImpl::new_service(ctx.clone()));
+    let certificate = client
+        .sign_certificate(tonic_request(
+            proto::CertificateSigningRequest { der: der.into() },
+            SVC_A,
+        ))
+        .await?
+        .into_inner();
+
+    let (_, cert) = x509_parser::parse_x509_certificate(&certificate.der).unwrap();
+    Ok(cert.validity().not_after)
+}
+
+fn ip(addr: &str) -> SanType {
+    SanType::IpAddress(addr.parse::<IpAddr>().unwrap())
+}
+
+fn uri(uri: &str) -> SanType {
+    SanType::URI(uri.try_into().unwrap())
+}
+
+#[test_log::test(tokio::test)]
+async fn test_issued_cert_validity_is_capped() {
+    let now = OffsetDateTime::now_utc().replace_nanosecond(0).unwrap();
+    let ctx = cert_ctx(&[(Setting::ServiceCertMaxValidity, "30days")], now).await;
+
+    let not_after = sign_certificate(&ctx, vec![]).await.unwrap();
+    assert_eq!(not_after.to_datetime(), now + Duration::days(30));
+}
+
+#[test]
+fn test_requested_validity_is_capped() {
+    let now = OffsetDateTime::now_utc();
+    let settings = Settings::default();
+    let policy = &settings.service_cert;
+
+    let mut params =
+        server_cert_csr("svc", vec!["svc-a".to_string()], Duration::days(10 * 365)).unwrap();
+    params.not_before = now - Duration::days(365);
+    limit_cert_validity(&mut params, policy, now);
+    assert_eq!(params.not_before, now - Duration::days(1));
+    assert_eq!(params.not_after, now + Duration::days(365));
+
+    // a shorter lifetime is kept
+    let mut params = server_cert_csr("svc", vec!["svc-a".to_string()], Duration::hours(1)).unwrap();
+    let requested = params.not_after;
+    limit_cert_validity(&mut params, policy, now);
+    assert_eq!(params.not_after, requested);
+}
+
+#[test_log::test(tokio::test)]
+async fn test_disallowed_san_type_is_rejected() {
+    let ctx = cert_ctx(&[], OffsetDateTime::now_utc()).await;
+
+    for alt_name in [
+        ip("10.0.0.1"),
+        uri("spiffe://example.org/svc-a"),
+        SanType::Rfc822Name("svc-a@example.org".try_into().unwrap()),
+    ] {
+        let status = sign_certificate(&ctx, vec![alt_name]).await.unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+    }
+}
+
+#[test_log::test(tokio::test)]
+async fn test_configured_san_types_are_accepted() {
+    let ctx = cert_ctx(
+        &[
+            (Setting::ServiceCertUriPrefixes, "spiffe://example.org/"),
+            (Setting::ServiceCertIpNetworks, "10.0.0.0/8"),
+        ],
+        OffsetDateTime::now_utc(),
+    )
+    .await;
+
+    sign_certificate(
+        &ctx,
+        vec![ip("10.0.0.1"), uri("spiffe://example.org/svc-a")],
+    )
+    .await
+    .unwrap();
+
+    for alt_name in [
+        ip("192.168.0.1"),
+        uri("spiffe://example.org.evil/svc-a"),
+        uri("spiffe://other.org/svc-a"),
+    ] {
+        let status = sign_certificate(&ctx, vec![alt_name]).await.unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+    }
+}
+
+#[test]
+fn test_service_cert_settings() {
+    let mut settings = Settings::default();
+
+    for (setting, value) in [
+        (Setting::ServiceCertMaxValidity, "0s"),
+        (Setting::ServiceCertUriPrefixes, "spiffe://example.org"),
+        (Setting::ServiceCertUriPrefixes, "not a uri"),
+        (Setting::ServiceCertIpNetworks, "10.0.0.0/33"),
+    ] {
+        assert!(settings.try_set(setting, Cow::Borrowed(value)).is_err());
+    }
+}
@@ -0,0 +1,94 @@
+use authly_common::id::ServiceId;
+use authly_domain::{
+    ctx::GetDb, document::error::DocError, repo::service_repo, service::normalize_hostname,
+};
+use indoc::indoc;
+
+use crate::{
+    test_ctx::TestCtx,
+    util::{compile_and_apply_doc, TestDocError},
+};
+
+const SVC: ServiceId = ServiceId::from_uint(42);
+
+#[test_log::test(tokio::test)]
+async fn test_service_hosts_normalized() {
+    let ctx = TestCtx::new().inmemory_db().await.supreme_instance().await;
+    let doc = indoc! {
+        r#"
+        [authly-document]
+        id = "bc9ce588-50c3-47d1-94c1-f88b21eaf299"
+
+        [[service-entity]]
+        eid = "s.0000000000000000000000000000002a"
+        label = "svc"
+        hosts = ["MyService", "Bücher.Example.", "myservice"]
+        "#
+    };
+
+    compile_and_apply_doc(doc, &ctx).await.unwrap();
+
+    assert_eq!(
+        service_repo::list_service_hosts(ctx.get_db(), SVC)
+            .await
+            .unwrap(),
+        vec!["myservice", "xn--bcher-kva.example"]
+    );
+}
+
+#[test_log::test(tokio::test)]
+async fn test_service_host_invalid() {
+    let ctx = TestCtx::new().inmemory_db().await.supreme_instance().await;
+    let doc = indoc! {
+        r#"
+        [authly-document]
+        id = "bc9ce588-50c3-47d1-94c1-f88b21eaf299"
+
+        [[service-entity]]
+        eid = "s.0000000000000000000000000000002a"
+        label = "svc"
+        hosts = ["svc_internal"]
+        "#
+    };
+
+    let TestDocError::Doc(errors) = compile_and_apply_doc(doc, &ctx).await.unwrap_err() else {
+        panic!()
+    };
+    let spanned_error = errors.into_iter().next().unwrap();
+
+    assert!(
+        matches!(spanned_error.as_ref(), DocError::InvalidHost(host) if host == "svc_internal"),
+        "unexpected error: {spanned_error:?}"
+    );
+    assert_eq!("\"svc\"", &doc[spanned_error.span()]);
+}
+
+#[test]
+fn test_normalize_hostname() {
+    assert_eq!(
+        normalize_hostname("Ultradb-GUI").as_deref(),
+        Some("ultradb-gui")
+    );
+    assert_eq!(
+        normalize_hostname("ØL.example.com").as_deref(),
+        Some("xn--l-4ga.example.com")
+    );
+
+    for invalid in [
+        "",
+        ".",
+        "-svc",
+        "svc-",
+        "a..b",
+        "svc:8080",
+        "svc/path",
+        "user@svc",
+        "svc%2e",
+        "10.0.0.1",
+        "with space",
+        "a".repeat(64).as_str(),
+        ["a"; 128].join(".").as_str(),
+    ] {
+        assert_eq!(normalize_hostname(invalid), None, "{invalid:?}");
+    }
+}
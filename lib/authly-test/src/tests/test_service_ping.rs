@@ -0,0 +1,124 @@
+use std::borrow::Cow;
+
+use authly_common::id::ServiceId;
+use authly_domain::{
+    bus::{service_events::ServiceEventDispatcher, ServiceMessage, ServiceMessageConnection},
+    settings::{Setting, Settings},
+};
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+const SVC_A: ServiceId = ServiceId::from_raw_array([1; 16]);
+const SVC_B: ServiceId = ServiceId::from_raw_array([2; 16]);
+
+fn connect(
+    dispatcher: &ServiceEventDispatcher,
+    svc_eid: ServiceId,
+) -> mpsc::Receiver<ServiceMessage> {
+    let (sender, receiver) = mpsc::channel(16);
+    dispatcher.subscribe(
+        svc_eid,
+        ServiceMessageConnection {
+            sender,
+            addr: "127.0.0.1:1234".parse().unwrap(),
+        },
+    );
+    receiver
+}
+
+fn received(receiver: &mut mpsc::Receiver<ServiceMessage>) -> Vec<ServiceMessage> {
+    let mut messages = vec![];
+    while let Ok(msg) = receiver.try_recv() {
+        messages.push(msg);
+    }
+    messages
+}
+
+#[test_log::test(tokio::test)]
+async fn test_unresponsive_service() {
+    let dispatcher = ServiceEventDispatcher::new(CancellationToken::new());
+    let mut svc_a = connect(&dispatcher, SVC_A);
+    let mut svc_b = connect(&dispatcher, SVC_B);
+
+    // only A answers pings
+    for _ in 0..3 {
+        dispatcher.ping_all(Some(1));
+        dispatcher.record_pong(SVC_A);
+    }
+
+    let health = dispatcher.health();
+    assert!(health[&SVC_A].responsive);
+    assert_eq!(health[&SVC_A].missed_pings, 0);
+    assert!(health[&SVC_A].round_trip_ms.is_some());
+    assert!(!health[&SVC_B].responsive);
+    assert_eq!(health[&SVC_B].missed_pings, 2);
+
+    dispatcher.broadcast_all(ServiceMessage::ReloadCache);
+    assert_eq!(
+        received(&mut svc_a).last(),
+        Some(&ServiceMessage::ReloadCache)
+    );
+    // B still gets pings, but not the notification
+    assert_eq!(received(&mut svc_b), vec![ServiceMessage::Ping; 3]);
+
+    // B returns and catches up on missed notifications
+    dispatcher.record_pong(SVC_B);
+    assert!(dispatcher.health()[&SVC_B].responsive);
+    assert_eq!(received(&mut svc_b), vec![ServiceMessage::ReloadCache]);
+
+    dispatcher.broadcast(SVC_B, ServiceMessage::ReloadCa);
+    assert_eq!(received(&mut svc_b), vec![ServiceMessage::ReloadCa]);
+}
+
+#[test_log::test(tokio::test)]
+async fn test_unresponsive_service_disabled() {
+    let dispatcher = ServiceEventDispatcher::new(CancellationToken::new());
+    let mut svc = connect(&dispatcher, SVC_A);
+
+    for _ in 0..5 {
+        dispatcher.ping_all(None);
+    }
+
+    // missed pings are tracked, but the service keeps getting notifications
+    let health = dispatcher.health();
+    assert!(health[&SVC_A].responsive);
+    assert_eq!(health[&SVC_A].missed_pings, 4);
+
+    received(&mut svc);
+    dispatcher.broadcast_all(ServiceMessage::ReloadCache);
+    assert_eq!(received(&mut svc), vec![ServiceMessage::ReloadCache]);
+}
+
+#[test]
+fn test_service_ping_settings() {
+    let mut settings = Settings::default();
+    assert_eq!(settings.service_ping.max_missed, None);
+
+    for (setting, invalid) in [
+        (Setting::ServicePingInterval, "0s"),
+        (Setting::ServicePingInterval, "often"),
+        (Setting::ServicePingMaxMissed, "-1"),
+    ] {
+        assert!(
+            settings.try_set(setting, Cow::Borrowed(invalid)).is_err(),
+            "{invalid}"
+        );
+    }
+
+    settings
+        .try_set(Setting::ServicePingInterval, Cow::Borrowed("30s"))
+        .unwrap();
+    settings
+        .try_set(Setting::ServicePingMaxMissed, Cow::Borrowed("3"))
+        .unwrap();
+    assert_eq!(
+        settings.service_ping.interval,
+        std::time::Duration::from_secs(30)
+    );
+    assert_eq!(settings.service_ping.max_missed, Some(3));
+
+    settings
+        .try_set(Setting::ServicePingMaxMissed, Cow::Borrowed("off"))
+        .unwrap();
+    assert_eq!(settings.service_ping.max_missed, None);
+}
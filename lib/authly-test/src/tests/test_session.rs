@@ -0,0 +1,301 @@
+use authly_common::{
+    id::{PersonaId, ServiceId},
+    mtls_server::PeerServiceEntity,
+};
+use std::time::Duration;
+
+use authly_db::{params, Db};
+use authly_domain::{
+    bus::ClusterMessage,
+    ctx::{GetClock, GetDb},
+    dev::IsDev,
+    login::{try_username_password_login, LoginOptions},
+    repo::session_repo,
+    session::{
+        self, authenticate_session_cookie, init_session, AuthClass, Session, SessionKind,
+        PERSISTENT_SESSION_TTL,
+    },
+    settings::Settings,
+};
+use hexhex::hex_literal;
+use time::OffsetDateTime;
+
+use crate::{test_ctx::TestCtx, util::compile_and_apply_doc_dir};
+
+const PERSONA_ME: PersonaId =
+    PersonaId::from_raw_array(hex_literal!("0fbcd73e1a884424a1615c3c3fdeebec"));
+
+async fn demo_ctx() -> TestCtx {
+    let ctx = TestCtx::new().inmemory_db().await.supreme_instance().await;
+    compile_and_apply_doc_dir("../../examples/demo".into(), &ctx)
+        .await
+        .unwrap();
+    ctx
+}
+
+#[test_log::test(tokio::test)]
+async fn test_remember_me_session_expiry() {
+    let ctx = demo_ctx().await;
+    let now = OffsetDateTime::now_utc();
+
+    let login = |remember_me| {
+        try_username_password_login(
+            &ctx,
+            PeerServiceEntity(ServiceId::random()),
+            "testuser".to_string(),
+            "secret".to_string(),
+            LoginOptions::default()
+                .dev(IsDev(true))
+                .remember_me(remember_me),
+        )
+    };
+
+    let Ok((_, default)) = login(false).await else {
+        panic!("login failed");
+    };
+    let Ok((_, persistent)) = login(true).await else {
+        panic!("login failed");
+    };
+
+    assert_eq!(default.kind, SessionKind::Default);
+    assert_eq!(persistent.kind, SessionKind::Persistent);
+
+    assert!(
+        default.expires_at
+            <= now + Settings::default().session_absolute_timeout + time::Duration::seconds(5)
+    );
+    assert!(persistent.expires_at >= now + PERSISTENT_SESSION_TTL);
+    assert!(
+        persistent
+            .to_cookie(ctx.get_clock().now())
+            .max_age()
+            .unwrap()
+            > default.to_cookie(ctx.get_clock().now()).max_age().unwrap()
+    );
+
+    let stored = session_repo::get_session(ctx.get_db(), persistent.token)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(stored.kind, SessionKind::Persistent);
+}
+
+#[test_log::test(tokio::test)]
+async fn test_persistent_session_listed_and_revocable() {
+    let ctx = demo_ctx().await;
+    let eid = PERSONA_ME.upcast();
+
+    let default = init_session(&ctx, eid, SessionKind::Default, AuthClass::Password)
+        .await
+        .unwrap();
+    let persistent = init_session(&ctx, eid, SessionKind::Persistent, AuthClass::Password)
+        .await
+        .unwrap();
+
+    let handles = |sessions: Vec<session::Session>| -> Vec<String> {
+        sessions.iter().map(|session| session.handle()).collect()
+    };
+
+    let listed = handles(session::list_sessions(&ctx, eid).await.unwrap());
+    assert_eq!(listed, vec![persistent.handle(), default.handle()]);
+
+    assert!(session::revoke_session(&ctx, eid, &persistent.handle())
+        .await
+        .unwrap());
+
+    let listed = handles(session::list_sessions(&ctx, eid).await.unwrap());
+    assert_eq!(listed, vec![default.handle()]);
+
+    assert!(session_repo::get_session(ctx.get_db(), persistent.token)
+        .await
+        .unwrap()
+        .is_none());
+
+    // revoking an unknown handle is a no-op
+    assert!(!session::revoke_session(&ctx, eid, &persistent.handle())
+        .await
+        .unwrap());
+}
+
+fn timeout_settings() -> Settings {
+    Settings {
+        session_idle_timeout: Duration::from_secs(10 * 60),
+        session_absolute_timeout: Duration::from_secs(60 * 60),
+        ..Default::default()
+    }
+}
+
+/// Move the session timestamps back in time
+async fn backdate_session(ctx: &TestCtx, session: &Session, created_ago: u64, last_seen_ago: u64) {
+    let now = OffsetDateTime::now_utc();
+    let created_at = now - Duration::from_secs(created_ago);
+    let last_seen = now - Duration::from_secs(last_seen_ago);
+
+    ctx.get_db()
+        .execute(
+            "UPDATE session SET created_at = $1, last_seen = $2 WHERE token = $3".into(),
+            params!(
+                created_at.unix_timestamp(),
+                last_seen.unix_timestamp(),
+                session.token.0.clone()
+            ),
+        )
+        .await
+        .unwrap();
+}
+
+#[test_log::test(tokio::test)]
+async fn test_session_idle_timeout() {
+    let ctx = demo_ctx().await.with_settings(timeout_settings());
+    let session = init_session(
+        &ctx,
+        PERSONA_ME.upcast(),
+        SessionKind::Default,
+        AuthClass::Password,
+    )
+    .await
+    .unwrap();
+
+    // inactive for longer than the idle window
+    backdate_session(&ctx, &session, 20 * 60, 11 * 60).await;
+
+    let result = authenticate_session_cookie(&ctx, &session.to_cookie(ctx.get_clock().now())).await;
+    assert_eq!(result.err(), Some("session idle timeout"));
+
+    // remember-me sessions are not subject to idle timeout
+    let persistent = init_session(
+        &ctx,
+        PERSONA_ME.upcast(),
+        SessionKind::Persistent,
+        AuthClass::Password,
+    )
+    .await
+    .unwrap();
+    backdate_session(&ctx, &persistent, 20 * 60, 11 * 60).await;
+
+    assert!(
+        authenticate_session_cookie(&ctx, &persistent.to_cookie(ctx.get_clock().now()))
+            .await
+            .is_ok()
+    );
+}
+
+#[test_log::test(tokio::test)]
+async fn test_session_absolute_timeout() {
+    let ctx = demo_ctx().await.with_settings(timeout_settings());
+    let session = init_session(
+        &ctx,
+        PERSONA_ME.upcast(),
+        SessionKind::Default,
+        AuthClass::Password,
+    )
+    .await
+    .unwrap();
+
+    // recently active, but created before the absolute window
+    backdate_session(&ctx, &session, 61 * 60, 60).await;
+
+    let result = authenticate_session_cookie(&ctx, &session.to_cookie(ctx.get_clock().now())).await;
+    assert_eq!(result.err(), Some("session expired"));
+
+    // remember-me sessions outlive the absolute timeout, but not their own lifetime
+    for (created_ago, expired) in [(61 * 60, false), (31 * 24 * 60 * 60, true)] {
+        let persistent = init_session(
+            &ctx,
+            PERSONA_ME.upcast(),
+            SessionKind::Persistent,
+            AuthClass::Password,
+        )
+        .await
+        .unwrap();
+        backdate_session(&ctx, &persistent, created_ago, 60).await;
+
+        let result =
+            authenticate_session_cookie(&ctx, &persistent.to_cookie(ctx.get_clock().now())).await;
+        assert_eq!(result.is_err(), expired);
+    }
+}
+
+#[test_log::test(tokio::test)]
+async fn test_session_activity_refreshes_last_seen() {
+    let ctx = demo_ctx().await.with_settings(timeout_settings());
+    let session = init_session(
+        &ctx,
+        PERSONA_ME.upcast(),
+        SessionKind::Default,
+        AuthClass::Password,
+    )
+    .await
+    .unwrap();
+    let cookie = session.to_cookie(ctx.get_clock().now());
+
+    // close to, but within the idle window
+    backdate_session(&ctx, &session, 30 * 60, 9 * 60).await;
+
+    let refreshed = authenticate_session_cookie(&ctx, &cookie).await.unwrap();
+    assert!(OffsetDateTime::now_utc() - refreshed.last_seen < time::Duration::seconds(5));
+
+    let stored = session_repo::get_session(ctx.get_db(), session.token)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        stored.last_seen,
+        refreshed.last_seen.replace_nanosecond(0).unwrap()
+    );
+
+    // the refreshed session stays alive past the original idle deadline
+    backdate_session(&ctx, &stored, 30 * 60, 2 * 60).await;
+    assert!(authenticate_session_cookie(&ctx, &cookie).await.is_ok());
+}
+
+#[test_log::test(tokio::test)]
+async fn test_session_cache_hit() {
+    let ctx = demo_ctx().await;
+    let session = init_session(
+        &ctx,
+        PERSONA_ME.upcast(),
+        SessionKind::Default,
+        AuthClass::Password,
+    )
+    .await
+    .unwrap();
+    let cookie = session.to_cookie(ctx.get_clock().now());
+
+    assert!(authenticate_session_cookie(&ctx, &cookie).await.is_ok());
+
+    // remove the session behind the cache's back, the repeated lookup is served from the cache
+    ctx.get_db()
+        .execute(
+            "DELETE FROM session WHERE token = $1".into(),
+            params!(session.token.0.clone()),
+        )
+        .await
+        .unwrap();
+
+    let cached = authenticate_session_cookie(&ctx, &cookie).await.unwrap();
+    assert_eq!(cached.eid, PERSONA_ME.upcast());
+}
+
+#[test_log::test(tokio::test)]
+async fn test_session_cache_miss_after_revocation() {
+    let ctx = demo_ctx().await;
+    let eid = PERSONA_ME.upcast();
+    let session = init_session(&ctx, eid, SessionKind::Default, AuthClass::Password)
+        .await
+        .unwrap();
+    let cookie = session.to_cookie(ctx.get_clock().now());
+
+    assert!(authenticate_session_cookie(&ctx, &cookie).await.is_ok());
+    assert!(session::revoke_session(&ctx, eid, &session.handle())
+        .await
+        .unwrap());
+
+    let result = authenticate_session_cookie(&ctx, &cookie).await;
+    assert_eq!(result.err(), Some("no session"));
+
+    // other cluster nodes are told to evict their cached sessions
+    assert!(ctx
+        .clone_cluster_message_log()
+        .contains(&ClusterMessage::SessionsRevoked { eid }));
+}
@@ -0,0 +1,144 @@
+use authly_common::{
+    id::{PersonaId, ServiceId},
+    mtls_server::PeerServiceEntity,
+};
+use authly_domain::{
+    access_token,
+    audit::Actor,
+    ctx::{GetClock, GetDb, GetInstance},
+    dev::IsDev,
+    login::{simulate_login, try_username_password_login, LoginError, LoginOptions},
+    repo::{admin_event_repo, entity_repo},
+    session,
+};
+use hexhex::hex_literal;
+use serde_json::json;
+
+use crate::{test_ctx::TestCtx, util::compile_and_apply_doc_dir};
+
+const PERSONA_ME: PersonaId =
+    PersonaId::from_raw_array(hex_literal!("0fbcd73e1a884424a1615c3c3fdeebec"));
+const TESTSERVICE: ServiceId =
+    ServiceId::from_raw_array(hex_literal!("f3e799137c034e1eb4cd3e4f65705932"));
+
+async fn demo_ctx() -> TestCtx {
+    let ctx = TestCtx::new().inmemory_db().await.supreme_instance().await;
+    compile_and_apply_doc_dir("../../examples/demo".into(), &ctx)
+        .await
+        .unwrap();
+    ctx
+}
+
+fn operator() -> Actor {
+    Actor(PersonaId::random().upcast())
+}
+
+async fn simulated_login_events(ctx: &TestCtx) -> Vec<serde_json::Value> {
+    admin_event_repo::list_admin_events(ctx.get_db(), 0, 100)
+        .await
+        .unwrap()
+        .into_iter()
+        .filter(|event| event.kind == "simulated_login")
+        .map(|event| event.detail)
+        .collect()
+}
+
+#[test_log::test(tokio::test)]
+async fn test_simulated_login_matches_real_login() {
+    let ctx = demo_ctx().await;
+
+    let Ok((persona_id, session)) = try_username_password_login(
+        &ctx,
+        PeerServiceEntity(TESTSERVICE),
+        "testuser".to_string(),
+        "secret".to_string(),
+        LoginOptions::default().dev(IsDev(true)),
+    )
+    .await
+    else {
+        panic!("login failed");
+    };
+    assert_eq!(persona_id, PERSONA_ME);
+
+    // the access token the service would get for the real session
+    let user_attrs = entity_repo::list_entity_attrs(ctx.get_db(), session.eid)
+        .await
+        .unwrap();
+    let token_attrs =
+        access_token::select_token_attributes(&ctx, session.eid, TESTSERVICE, user_attrs)
+            .await
+            .unwrap();
+    let token = access_token::create_access_token_with(
+        &ctx,
+        &session,
+        token_attrs,
+        &ctx.get_instance(),
+        ctx.get_clock().now(),
+    )
+    .await
+    .unwrap();
+    let real =
+        access_token::decode_access_token(&token, &ctx.get_instance(), ctx.get_clock().now())
+            .unwrap();
+
+    let sessions_before = session::list_sessions(&ctx, PERSONA_ME.upcast())
+        .await
+        .unwrap()
+        .len();
+    let actor = operator();
+
+    let Ok(simulated) = simulate_login(&ctx, actor, "testuser", TESTSERVICE).await else {
+        panic!("simulated login failed");
+    };
+
+    assert_eq!(simulated.persona_id, PERSONA_ME);
+    assert_eq!(
+        simulated.token_claims.claims.authly.entity_id,
+        real.claims.authly.entity_id
+    );
+    assert_eq!(
+        simulated.token_claims.claims.authly.entity_attributes,
+        real.claims.authly.entity_attributes
+    );
+    assert_eq!(
+        simulated.token_claims.authly_attributes_ref,
+        real.authly_attributes_ref
+    );
+    assert!(!simulated.trace.is_empty());
+
+    // no session was issued
+    assert_eq!(
+        session::list_sessions(&ctx, PERSONA_ME.upcast())
+            .await
+            .unwrap()
+            .len(),
+        sessions_before
+    );
+
+    assert_eq!(
+        simulated_login_events(&ctx).await,
+        vec![json!({
+            "actorId": actor.0,
+            "personaId": PERSONA_ME,
+            "serviceId": TESTSERVICE,
+        })]
+    );
+}
+
+#[test_log::test(tokio::test)]
+async fn test_simulated_login_of_unknown_user_is_audited() {
+    let ctx = demo_ctx().await;
+    let actor = operator();
+
+    let result = simulate_login(&ctx, actor, "nobody", TESTSERVICE).await;
+    assert!(matches!(result, Err(LoginError::Credentials)));
+
+    assert_eq!(
+        simulated_login_events(&ctx).await,
+        vec![json!({
+            "actorId": actor.0,
+            "personaId": null,
+            "serviceId": TESTSERVICE,
+        })]
+    );
+}
@@ -0,0 +1,83 @@
+use std::{
+    io::Write,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use authly_db::{params, slow_query::SlowQueryLog, Db, FromRow};
+use authly_sqlite::{SqlitePool, Storage};
+use tracing::instrument::WithSubscriber;
+
+/// Counts two million rows, and ignores the secret parameter
+const SLOW_SQL: &str = "WITH RECURSIVE counter(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM counter WHERE x < 2000000) SELECT count(*) AS n FROM counter WHERE $1 IS NOT NULL";
+
+const SECRET: &str = "hunter2-secret-value";
+
+struct Count(i64);
+
+impl FromRow for Count {
+    fn from_row(row: &mut impl authly_db::Row) -> Self {
+        Self(row.get_int("n"))
+    }
+}
+
+#[derive(Clone, Default)]
+struct CapturedLog(Arc<Mutex<Vec<u8>>>);
+
+impl Write for CapturedLog {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl CapturedLog {
+    fn contents(&self) -> String {
+        String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+    }
+}
+
+async fn run_slow_query(slow_query_log: SlowQueryLog) -> String {
+    let pool = SqlitePool::new(Storage::Memory, 1).with_slow_query_log(slow_query_log);
+    let log = CapturedLog::default();
+    let subscriber = tracing_subscriber::fmt()
+        .with_writer({
+            let log = log.clone();
+            move || log.clone()
+        })
+        .with_ansi(false)
+        .finish();
+
+    let counts: Vec<Count> = pool
+        .query_map(SLOW_SQL.into(), params!(SECRET))
+        .with_subscriber(subscriber)
+        .await
+        .unwrap();
+    assert_eq!(counts[0].0, 2_000_000);
+
+    log.contents()
+}
+
+#[tokio::test]
+async fn test_slow_query_logged_without_values() {
+    let slow_query_log = SlowQueryLog::new(Some(Duration::from_millis(1)));
+    let output = run_slow_query(slow_query_log.clone()).await;
+
+    assert!(output.contains("slow query"), "{output}");
+    assert!(output.contains("WHERE $1 IS NOT NULL"), "{output}");
+    assert!(!output.contains(SECRET), "{output}");
+    assert_eq!(slow_query_log.count(), 1);
+}
+
+#[tokio::test]
+async fn test_slow_query_below_threshold() {
+    let slow_query_log = SlowQueryLog::new(Some(Duration::from_secs(600)));
+    let output = run_slow_query(slow_query_log.clone()).await;
+
+    assert!(!output.contains("slow query"), "{output}");
+    assert_eq!(slow_query_log.count(), 0);
+}
@@ -0,0 +1,79 @@
+use authly_common::{
+    id::{PersonaId, ServiceId},
+    mtls_server::PeerServiceEntity,
+};
+use authly_domain::{
+    dev::IsDev,
+    login::{try_username_password_login, LoginError, LoginOptions},
+    session::{init_session, AuthClass, SessionKind},
+    stats::collect_stats,
+};
+use hexhex::hex_literal;
+use serde_json::json;
+
+use crate::{test_ctx::TestCtx, util::compile_and_apply_doc_dir};
+
+const PERSONA_ME: PersonaId =
+    PersonaId::from_raw_array(hex_literal!("0fbcd73e1a884424a1615c3c3fdeebec"));
+
+#[test_log::test(tokio::test)]
+async fn test_stats_json() {
+    let ctx = TestCtx::new().inmemory_db().await.supreme_instance().await;
+    compile_and_apply_doc_dir("../../examples/demo".into(), &ctx)
+        .await
+        .unwrap();
+
+    let before = serde_json::to_value(collect_stats(&ctx).await.unwrap()).unwrap();
+
+    assert_eq!(before["raft_role"], json!("leader"));
+    assert_eq!(before["slow_queries"], json!(0));
+    assert_eq!(before["authentications"]["total"], json!(0));
+    assert_eq!(before["authentications"]["per_minute"], json!(0));
+    assert_eq!(before["active_sessions"], json!(0));
+    assert_eq!(
+        before["connected_services"],
+        json!({ "services": 0, "connections": 0, "unresponsive": 0 })
+    );
+    // the builtin authly directory and the three demo documents
+    assert_eq!(before["directories"]["directories"], json!(4));
+    // services are counted separately from persona and group entities
+    assert_eq!(before["directories"]["personas"], json!(2));
+    assert_eq!(before["directories"]["groups"], json!(1));
+    assert_eq!(before["directories"]["services"], json!(2));
+    assert_eq!(before["directories"]["policies"], json!(3));
+
+    init_session(
+        &ctx,
+        PERSONA_ME.upcast(),
+        SessionKind::Default,
+        AuthClass::Password,
+    )
+    .await
+    .unwrap();
+    init_session(
+        &ctx,
+        PERSONA_ME.upcast(),
+        SessionKind::Default,
+        AuthClass::Password,
+    )
+    .await
+    .unwrap();
+
+    let result = try_username_password_login(
+        &ctx,
+        PeerServiceEntity(ServiceId::random()),
+        "nobody".to_string(),
+        "secret".to_string(),
+        LoginOptions::default().dev(IsDev(true)),
+    )
+    .await;
+    assert!(matches!(result, Err(LoginError::Credentials)));
+
+    let after = serde_json::to_value(collect_stats(&ctx).await.unwrap()).unwrap();
+
+    assert_eq!(
+        after["authentications"],
+        json!({ "total": 2, "failed": 1, "per_minute": 2 })
+    );
+    assert_eq!(after["active_sessions"], json!(2));
+}
@@ -1,12 +1,17 @@
 use std::{error::Error, sync::Arc};
 
 use authly_common::{id::ServiceId, mtls_server::PeerServiceEntity};
-use authly_domain::cert::{
-    authly_ca, client_cert, server_cert, server_cert_csr, Cert, CertificateParamsExt,
+use authly_domain::{
+    cert::{authly_ca, client_cert, server_cert, server_cert_csr, Cert, CertificateParamsExt},
+    tls::{AlpnProtocol, TlsPolicy, TlsVersion},
 };
 use axum::{response::IntoResponse, Extension};
-use rcgen::{CertificateSigningRequestParams, Issuer};
-use rustls::{pki_types::CertificateSigningRequestDer, ServerConfig};
+use rcgen::{CertificateSigningRequestParams, Issuer, KeyPair};
+use rustls::{
+    crypto::{ring, CryptoProvider},
+    pki_types::{CertificateSigningRequestDer, PrivateKeyDer},
+    RootCertStore, ServerConfig,
+};
 use time::Duration;
 use tokio_util::sync::{CancellationToken, DropGuard};
 
@@ -321,6 +326,226 @@ async fn test_mtls_invalid_issuer() {
     );
 }
 
+#[tokio::test]
+async fn test_tls_min_version() {
+    let ca = authly_ca().with_new_key_pair().self_signed();
+    let server_cert = ca.sign(
+        server_cert("svc", localhost(), Duration::hours(1))
+            .unwrap()
+            .with_new_key_pair(),
+    );
+
+    let policy = TlsPolicy {
+        min_version: TlsVersion::Tls13,
+        ..Default::default()
+    };
+    let (server_port, _drop) = spawn_server(
+        rustls_server_config_with_policy(&server_cert, &policy).unwrap(),
+        Mtls::No,
+    )
+    .await;
+    let url = format!("https://localhost:{server_port}/test");
+
+    let error = reqwest::ClientBuilder::new()
+        .add_root_certificate((&ca).into())
+        .max_tls_version(reqwest::tls::Version::TLS_1_2)
+        .build()
+        .unwrap()
+        .get(&url)
+        .send()
+        .await
+        .unwrap_err();
+    assert!(error.is_connect());
+
+    let text_response = reqwest::ClientBuilder::new()
+        .add_root_certificate((&ca).into())
+        .min_tls_version(reqwest::tls::Version::TLS_1_3)
+        .build()
+        .unwrap()
+        .get(&url)
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert_eq!(text_response, "it works: no client auth");
+}
+
+#[tokio::test]
+async fn test_tls_cipher_suites() {
+    let ca = authly_ca().with_new_key_pair().self_signed();
+    let server_cert = ca.sign(
+        server_cert("svc", localhost(), Duration::hours(1))
+            .unwrap()
+            .with_new_key_pair(),
+    );
+
+    let policy = TlsPolicy {
+        cipher_suites: vec!["TLS13_AES_256_GCM_SHA384".to_string()],
+        ..Default::default()
+    };
+    let (server_port, _drop) = spawn_server(
+        rustls_server_config_with_policy(&server_cert, &policy).unwrap(),
+        Mtls::No,
+    )
+    .await;
+    let url = format!("https://localhost:{server_port}/test");
+
+    let client_offering = |suite| {
+        let provider = CryptoProvider {
+            cipher_suites: vec![suite],
+            ..ring::default_provider()
+        };
+        let mut roots = RootCertStore::empty();
+        roots.add(ca.der.clone()).unwrap();
+
+        let config = rustls::ClientConfig::builder_with_provider(Arc::new(provider))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+
+        reqwest::ClientBuilder::new()
+            .use_preconfigured_tls(config)
+            .build()
+            .unwrap()
+    };
+
+    let error = client_offering(ring::cipher_suite::TLS13_AES_128_GCM_SHA256)
+        .get(&url)
+        .send()
+        .await
+        .unwrap_err();
+    assert!(error.is_connect());
+
+    let text_response = client_offering(ring::cipher_suite::TLS13_AES_256_GCM_SHA384)
+        .get(&url)
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert_eq!(text_response, "it works: no client auth");
+}
+
+#[tokio::test]
+async fn test_alpn_h2_only() {
+    let ca = authly_ca().with_new_key_pair().self_signed();
+    let server_cert = ca.sign(
+        server_cert("svc", localhost(), Duration::hours(1))
+            .unwrap()
+            .with_new_key_pair(),
+    );
+
+    let policy = TlsPolicy {
+        alpn_protocols: vec![AlpnProtocol::H2],
+        ..Default::default()
+    };
+    let (server_port, _drop) = spawn_server(
+        rustls_server_config_with_policy(&server_cert, &policy).unwrap(),
+        Mtls::No,
+    )
+    .await;
+    let url = format!("https://localhost:{server_port}/test");
+
+    let response = reqwest::ClientBuilder::new()
+        .add_root_certificate((&ca).into())
+        .build()
+        .unwrap()
+        .get(&url)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.version(), http::Version::HTTP_2);
+
+    // a client offering only HTTP/1.1 is rejected
+    let error = reqwest::ClientBuilder::new()
+        .add_root_certificate((&ca).into())
+        .http1_only()
+        .build()
+        .unwrap()
+        .get(&url)
+        .send()
+        .await
+        .unwrap_err();
+    assert!(error.is_connect());
+}
+
+#[tokio::test]
+async fn test_alpn_http1_only() {
+    let ca = authly_ca().with_new_key_pair().self_signed();
+    let server_cert = ca.sign(
+        server_cert("svc", localhost(), Duration::hours(1))
+            .unwrap()
+            .with_new_key_pair(),
+    );
+
+    let policy = TlsPolicy {
+        alpn_protocols: vec![AlpnProtocol::Http11],
+        ..Default::default()
+    };
+    let (server_port, _drop) = spawn_server(
+        rustls_server_config_with_policy(&server_cert, &policy).unwrap(),
+        Mtls::No,
+    )
+    .await;
+
+    // the client prefers h2, but the server only agrees to HTTP/1.1
+    let response = reqwest::ClientBuilder::new()
+        .add_root_certificate((&ca).into())
+        .build()
+        .unwrap()
+        .get(format!("https://localhost:{server_port}/test"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.version(), http::Version::HTTP_11);
+    assert_eq!(response.text().await.unwrap(), "it works: no client auth");
+}
+
+#[test]
+fn test_tls_policy_unsupported_names() {
+    let _ = ring::default_provider().install_default();
+
+    let unknown_suite = TlsPolicy {
+        cipher_suites: vec!["TLS_RSA_WITH_RC4_128_MD5".to_string()],
+        ..Default::default()
+    };
+    assert!(unknown_suite.server_config_builder().is_err());
+
+    let unknown_group = TlsPolicy {
+        named_groups: vec!["ffdhe512".to_string()],
+        ..Default::default()
+    };
+    assert!(unknown_group.server_config_builder().is_err());
+
+    let known = TlsPolicy {
+        min_version: TlsVersion::Tls13,
+        cipher_suites: vec!["TLS13_CHACHA20_POLY1305_SHA256".to_string()],
+        named_groups: vec!["X25519".to_string()],
+        ..Default::default()
+    };
+    assert!(known.server_config_builder().is_ok());
+}
+
+fn rustls_server_config_with_policy(
+    server_cert: &Cert<KeyPair>,
+    policy: &TlsPolicy,
+) -> anyhow::Result<Arc<ServerConfig>> {
+    let _ = ring::default_provider().install_default();
+
+    let private_key_der = PrivateKeyDer::try_from(server_cert.key.serialize_der()).unwrap();
+    let mut config = policy
+        .server_config_builder()?
+        .with_no_client_auth()
+        .with_single_cert(vec![server_cert.der.clone()], private_key_der)?;
+
+    policy.apply_alpn(&mut config);
+    Ok(Arc::new(config))
+}
+
 enum Mtls {
     No,
     Yes,
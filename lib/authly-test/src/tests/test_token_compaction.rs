@@ -0,0 +1,238 @@
+use std::borrow::Cow;
+
+use authly_common::id::{AttrId, PersonaId, ServiceId};
+use authly_domain::{
+    access_token::{decode_access_token, verify_and_resolve_access_token, AttributesRef},
+    ctx::{GetDb, GetInstance},
+    repo::entity_repo,
+    session::{init_session, AuthClass, Session, SessionKind},
+    settings::{Setting, Settings},
+};
+use authly_service::proto::token_server::{
+    proto::{self, authly_token_client::AuthlyTokenClient},
+    AuthlyTokenServerImpl,
+};
+use fnv::FnvHashSet;
+use hexhex::hex_literal;
+use indoc::indoc;
+use time::OffsetDateTime;
+use tonic::Code;
+
+use crate::{
+    test_ctx::TestCtx,
+    util::{compile_and_apply_doc, get_access_token, tonic_request},
+};
+
+const SVC: ServiceId = ServiceId::from_raw_array(hex_literal!("e5462a0d22b54d9f9ca37bd96e9b9d8b"));
+
+const PERSONA_SMALL: PersonaId =
+    PersonaId::from_raw_array(hex_literal!("0fbcd73e1a884424a1615c3c3fdeebec"));
+const PERSONA_LARGE: PersonaId =
+    PersonaId::from_raw_array(hex_literal!("96bb8a5a4e1a4a6e9f0c3d7e2b1a5c4d"));
+
+const DOC: &str = indoc! {
+    r#"
+    [authly-document]
+    id = "bc9ce588-50c3-47d1-94c1-f88b21eaf299"
+
+    [[service-entity]]
+    eid = "s.e5462a0d22b54d9f9ca37bd96e9b9d8b"
+    label = "svc"
+    attributes = ["authly:role:get_access_token"]
+
+    [[entity-property]]
+    namespace = "svc"
+    label = "group"
+    attributes = ["a", "b", "c", "d"]
+
+    [[entity]]
+    eid = "p.0fbcd73e1a884424a1615c3c3fdeebec"
+    label = "small"
+
+    [[entity]]
+    eid = "p.96bb8a5a4e1a4a6e9f0c3d7e2b1a5c4d"
+    label = "large"
+
+    [[entity-attribute-assignment]]
+    entity = "p.0fbcd73e1a884424a1615c3c3fdeebec"
+    attributes = ["svc:group:a"]
+
+    [[entity-attribute-assignment]]
+    entity = "p.96bb8a5a4e1a4a6e9f0c3d7e2b1a5c4d"
+    attributes = ["svc:group:a", "svc:group:b", "svc:group:c", "svc:group:d"]
+    "#
+};
+
+async fn compaction_ctx() -> TestCtx {
+    let mut settings = Settings::default();
+    settings
+        .try_set(Setting::TokenAttributeEmbedLimit, Cow::Borrowed("svc=2"))
+        .unwrap();
+
+    let ctx = TestCtx::new()
+        .inmemory_db()
+        .await
+        .supreme_instance()
+        .await
+        .with_settings(settings);
+    compile_and_apply_doc(DOC, &ctx).await.unwrap();
+    ctx
+}
+
+/// An access token for PERSONA_LARGE, with its attribute reference and the attributes it was issued with
+async fn large_token(ctx: &TestCtx) -> (String, AttributesRef, FnvHashSet<AttrId>) {
+    let token = get_access_token(ctx, &session(ctx, PERSONA_LARGE).await, SVC).await;
+    let attributes_ref =
+        decode_access_token(&token, &ctx.get_instance(), OffsetDateTime::now_utc())
+            .unwrap()
+            .authly_attributes_ref
+            .unwrap();
+    let attributes = entity_repo::list_entity_attrs(ctx.get_db(), PERSONA_LARGE.upcast())
+        .await
+        .unwrap();
+
+    (token, attributes_ref, attributes)
+}
+
+/// Resolve an attribute reference through gRPC, as a service verifying access tokens itself would
+async fn get_entity_attributes(
+    ctx: &TestCtx,
+    svc_eid: ServiceId,
+    attributes_ref: &str,
+) -> tonic::Result<FnvHashSet<AttrId>> {
+    let mut client = AuthlyTokenClient::new(AuthlyTokenServerImpl::new_service(ctx.clone()));
+    let attributes = client
+        .get_entity_attributes(tonic_request(
+            proto::GetEntityAttributesRequest {
+                entity_id: PERSONA_LARGE.to_string(),
+                attributes_ref: attributes_ref.to_string(),
+            },
+            svc_eid,
+        ))
+        .await?
+        .into_inner();
+    assert_eq!(attributes.entity_id, PERSONA_LARGE.to_string());
+
+    Ok(attributes
+        .attribute_ids
+        .iter()
+        .map(|attr_id| attr_id.parse().unwrap())
+        .collect())
+}
+
+async fn session(ctx: &TestCtx, persona: PersonaId) -> Session {
+    init_session(
+        ctx,
+        persona.upcast(),
+        SessionKind::Default,
+        AuthClass::Password,
+    )
+    .await
+    .unwrap()
+}
+
+#[test_log::test(tokio::test)]
+async fn test_token_attribute_compaction() {
+    let ctx = compaction_ctx().await;
+
+    // the small set is embedded
+    let small = get_access_token(&ctx, &session(&ctx, PERSONA_SMALL).await, SVC).await;
+    let claims =
+        decode_access_token(&small, &ctx.get_instance(), OffsetDateTime::now_utc()).unwrap();
+    assert!(claims.authly_attributes_ref.is_none());
+    assert_eq!(
+        claims.claims.authly.entity_attributes,
+        entity_repo::list_entity_attrs(ctx.get_db(), PERSONA_SMALL.upcast())
+            .await
+            .unwrap()
+    );
+
+    // the large set is left out of the token
+    let large = get_access_token(&ctx, &session(&ctx, PERSONA_LARGE).await, SVC).await;
+    let claims =
+        decode_access_token(&large, &ctx.get_instance(), OffsetDateTime::now_utc()).unwrap();
+    assert_eq!(claims.authly_attributes_ref.unwrap().service, SVC);
+    assert!(claims.claims.authly.entity_attributes.is_empty());
+
+    // and resolves to the same attributes
+    let expected = entity_repo::list_entity_attrs(ctx.get_db(), PERSONA_LARGE.upcast())
+        .await
+        .unwrap();
+    assert_eq!(expected.len(), 4);
+    assert_eq!(
+        verify_and_resolve_access_token(&large, &ctx)
+            .await
+            .unwrap()
+            .authly
+            .entity_attributes,
+        expected
+    );
+
+    // embedded tokens verify the same way
+    assert_eq!(
+        verify_and_resolve_access_token(&small, &ctx)
+            .await
+            .unwrap()
+            .authly
+            .entity_attributes
+            .len(),
+        1
+    );
+}
+
+#[test_log::test(tokio::test)]
+async fn test_referenced_attributes_are_the_issued_ones() {
+    let ctx = compaction_ctx().await;
+    let (token, _, issued) = large_token(&ctx).await;
+
+    // the attributes of the entity change after the token was issued
+    compile_and_apply_doc(
+        &DOC.replace(
+            r#"["svc:group:a", "svc:group:b", "svc:group:c", "svc:group:d"]"#,
+            r#"["svc:group:a"]"#,
+        ),
+        &ctx,
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        entity_repo::list_entity_attrs(ctx.get_db(), PERSONA_LARGE.upcast())
+            .await
+            .unwrap()
+            .len(),
+        1
+    );
+
+    assert_eq!(
+        verify_and_resolve_access_token(&token, &ctx)
+            .await
+            .unwrap()
+            .authly
+            .entity_attributes,
+        issued
+    );
+}
+
+#[test_log::test(tokio::test)]
+async fn test_service_resolves_referenced_attributes() {
+    let ctx = compaction_ctx().await;
+    let (_, attributes_ref, issued) = large_token(&ctx).await;
+
+    assert_eq!(
+        get_entity_attributes(&ctx, SVC, &attributes_ref.id)
+            .await
+            .unwrap(),
+        issued
+    );
+
+    // only the service the token was issued to resolves the reference
+    let status = get_entity_attributes(&ctx, ServiceId::random(), &attributes_ref.id)
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::NotFound);
+
+    let status = get_entity_attributes(&ctx, SVC, "unknown")
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::NotFound);
+}
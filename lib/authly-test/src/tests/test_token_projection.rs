@@ -0,0 +1,118 @@
+use std::borrow::Cow;
+
+use authly_common::id::{AttrId, PersonaId, ServiceId};
+use authly_domain::{
+    access_token::verify_access_token,
+    ctx::{GetDb, GetInstance},
+    session::{init_session, AuthClass, Session, SessionKind},
+    settings::{Setting, Settings},
+};
+use fnv::FnvHashSet;
+use hexhex::hex_literal;
+use indoc::indoc;
+use time::OffsetDateTime;
+
+use crate::{
+    test_ctx::TestCtx,
+    util::{compile_and_apply_doc, get_access_token, ServiceProperties},
+};
+
+const SVC_A: ServiceId =
+    ServiceId::from_raw_array(hex_literal!("e5462a0d22b54d9f9ca37bd96e9b9d8b"));
+const SVC_B: ServiceId =
+    ServiceId::from_raw_array(hex_literal!("015362d6655447c6b7f44865bd111c70"));
+
+const PERSONA_ME: PersonaId =
+    PersonaId::from_raw_array(hex_literal!("0fbcd73e1a884424a1615c3c3fdeebec"));
+
+const DOC: &str = indoc! {
+    r#"
+    [authly-document]
+    id = "bc9ce588-50c3-47d1-94c1-f88b21eaf299"
+
+    [[service-entity]]
+    eid = "s.e5462a0d22b54d9f9ca37bd96e9b9d8b"
+    label = "svc_a"
+    attributes = ["authly:role:get_access_token"]
+
+    [[service-entity]]
+    eid = "s.015362d6655447c6b7f44865bd111c70"
+    label = "svc_b"
+    attributes = ["authly:role:get_access_token"]
+
+    [[entity-property]]
+    namespace = "svc_a"
+    label = "role"
+    attributes = ["user", "admin"]
+
+    [[entity-property]]
+    namespace = "svc_a"
+    label = "team"
+    attributes = ["red", "blue"]
+
+    [[entity]]
+    eid = "p.0fbcd73e1a884424a1615c3c3fdeebec"
+    label = "me"
+
+    [[entity-attribute-assignment]]
+    entity = "p.0fbcd73e1a884424a1615c3c3fdeebec"
+    attributes = ["svc_a:role:user", "svc_a:team:red"]
+    "#
+};
+
+async fn token_attributes(
+    ctx: &TestCtx,
+    session: &Session,
+    svc_eid: ServiceId,
+) -> FnvHashSet<AttrId> {
+    let token = get_access_token(ctx, session, svc_eid).await;
+
+    verify_access_token(&token, &ctx.get_instance(), OffsetDateTime::now_utc())
+        .unwrap()
+        .authly
+        .entity_attributes
+}
+
+#[test_log::test(tokio::test)]
+async fn test_token_attribute_projection() {
+    let mut settings = Settings::default();
+    settings
+        .try_set(
+            Setting::TokenAttributeProjection,
+            Cow::Borrowed("svc_a=svc_a:role"),
+        )
+        .unwrap();
+
+    let ctx = TestCtx::new()
+        .inmemory_db()
+        .await
+        .supreme_instance()
+        .await
+        .with_settings(settings);
+    compile_and_apply_doc(DOC, &ctx).await.unwrap();
+
+    let session = init_session(
+        &ctx,
+        PERSONA_ME.upcast(),
+        SessionKind::Default,
+        AuthClass::Password,
+    )
+    .await
+    .unwrap();
+
+    let props = ServiceProperties::load(SVC_A, ctx.get_db()).await;
+
+    // svc_a only sees the role
+    assert_eq!(
+        token_attributes(&ctx, &session, SVC_A).await,
+        props.entity.translate([("svc_a", "role", "user")])
+    );
+
+    // svc_b is not configured, so it receives all attributes
+    assert_eq!(
+        token_attributes(&ctx, &session, SVC_B).await,
+        props
+            .entity
+            .translate([("svc_a", "role", "user"), ("svc_a", "team", "red")])
+    );
+}
@@ -0,0 +1,283 @@
+use std::sync::Arc;
+
+use authly_common::id::{PersonaId, ServiceId};
+use authly_db::{params, Db};
+use authly_domain::{
+    access_token::{create_access_token, verify_access_token},
+    cert::{authly_ca, client_cert, key_pair},
+    ctx::{GetDb, GetIdGenerator, GetInstance, SetInstance},
+    instance::{AuthlyId, AuthlyInstance, InstanceKeySource},
+    repo::{crypto_repo, token_signing_repo},
+    session::{init_session, AuthClass, Session, SessionKind},
+    signer::{self, InMemorySigner},
+    tls::{AuthlyCert, AuthlyCertKind},
+    token_signing::rotate_token_signing_key,
+    IsLeaderDb,
+};
+use hexhex::hex_literal;
+use time::OffsetDateTime;
+
+use crate::test_ctx::TestCtx;
+
+const PERSONA_ME: PersonaId =
+    PersonaId::from_raw_array(hex_literal!("0fbcd73e1a884424a1615c3c3fdeebec"));
+
+async fn session(ctx: &TestCtx) -> Session {
+    init_session(
+        ctx,
+        PERSONA_ME.upcast(),
+        SessionKind::Default,
+        AuthClass::Password,
+    )
+    .await
+    .unwrap()
+}
+
+/// Simulate a CA rotation by replacing the instance key and certificates,
+/// keeping whatever dedicated token signing key is stored in the database.
+async fn rotate_ca(ctx: &TestCtx) -> AuthlyInstance {
+    let authly_id = AuthlyId {
+        eid: ServiceId::random(),
+        signer: Arc::new(InMemorySigner::new(key_pair())),
+    };
+    let ca = signer::self_sign(authly_id.signer.as_ref(), &authly_ca())
+        .await
+        .unwrap();
+    let identity = signer::self_sign(
+        authly_id.signer.as_ref(),
+        &client_cert("authly", authly_id.eid, time::Duration::days(365)),
+    )
+    .await
+    .unwrap();
+    let certs = vec![
+        AuthlyCert {
+            kind: AuthlyCertKind::Ca,
+            certifies: authly_id.eid,
+            signed_by: authly_id.eid,
+            params: authly_ca(),
+            der: ca,
+        },
+        AuthlyCert {
+            kind: AuthlyCertKind::Identity,
+            certifies: authly_id.eid,
+            signed_by: authly_id.eid,
+            params: client_cert("authly", authly_id.eid, time::Duration::days(365)),
+            der: identity,
+        },
+    ];
+
+    AuthlyInstance::new(authly_id, certs).with_token_signing_keys(
+        token_signing_repo::load_token_signing_keys(ctx.get_db(), &ctx.get_decrypted_deks())
+            .await
+            .unwrap(),
+    )
+}
+
+fn jwks_kids(ctx: &TestCtx) -> Vec<String> {
+    ctx.get_instance()
+        .jwks()
+        .keys
+        .into_iter()
+        .map(|jwk| jwk.kid)
+        .collect()
+}
+
+#[test_log::test(tokio::test)]
+async fn test_ca_rotation_keeps_tokens_valid_with_separate_key() {
+    let ctx = TestCtx::new().inmemory_db().await.supreme_instance().await;
+
+    // new installations get a dedicated signing key
+    assert!(!ctx.get_instance().is_token_signing_compat());
+
+    let token = create_access_token(
+        &session(&ctx).await,
+        Default::default(),
+        &ctx.get_instance(),
+        OffsetDateTime::now_utc(),
+    )
+    .await
+    .unwrap();
+
+    let old_ca = ctx.get_instance().local_ca().der.clone();
+    ctx.set_instance(rotate_ca(&ctx).await);
+    assert_ne!(ctx.get_instance().local_ca().der, old_ca);
+
+    let claims =
+        verify_access_token(&token, &ctx.get_instance(), OffsetDateTime::now_utc()).unwrap();
+    assert_eq!(claims.authly.entity_id, PERSONA_ME.upcast());
+
+    let jwks = serde_json::to_value(ctx.get_instance().jwks()).unwrap();
+    assert_eq!(
+        jwks["keys"][0]["kid"],
+        ctx.get_instance().token_signing_key().kid()
+    );
+    assert_eq!(jwks["keys"][0]["kty"], "EC");
+    assert_eq!(jwks["keys"][0]["alg"], "ES256");
+}
+
+#[test_log::test(tokio::test)]
+async fn test_compat_mode_signs_with_instance_key() {
+    let ctx = TestCtx::new().inmemory_db().await.supreme_instance().await;
+
+    // an installation from before token signing key separation
+    ctx.get_db()
+        .execute("DELETE FROM token_signing_key".into(), params!())
+        .await
+        .unwrap();
+    let instance = crypto_repo::load_authly_instance(
+        IsLeaderDb(true),
+        ctx.get_db(),
+        &ctx.get_decrypted_deks(),
+        InstanceKeySource::Database,
+        ctx.get_id_generator(),
+    )
+    .await
+    .unwrap();
+    ctx.set_instance(instance);
+    assert!(ctx.get_instance().is_token_signing_compat());
+
+    let token = create_access_token(
+        &session(&ctx).await,
+        Default::default(),
+        &ctx.get_instance(),
+        OffsetDateTime::now_utc(),
+    )
+    .await
+    .unwrap();
+    assert!(verify_access_token(&token, &ctx.get_instance(), OffsetDateTime::now_utc()).is_ok());
+
+    // in compatibility mode, CA rotation invalidates tokens
+    ctx.set_instance(rotate_ca(&ctx).await);
+    assert!(verify_access_token(&token, &ctx.get_instance(), OffsetDateTime::now_utc()).is_err());
+
+    // rotating the token signing key ends compatibility mode on all nodes
+    let kid = rotate_token_signing_key(&ctx).await.unwrap();
+    assert!(!ctx.get_instance().is_token_signing_compat());
+    assert_eq!(ctx.get_instance().token_signing_key().kid(), kid);
+
+    // the instance key verifies the tokens it signed before the rotation
+    assert_eq!(jwks_kids(&ctx).len(), 2);
+    assert!(ctx
+        .get_instance()
+        .token_signing_keys()
+        .find(None, OffsetDateTime::now_utc())
+        .is_some());
+}
+
+#[test_log::test(tokio::test)]
+async fn test_new_installation_does_not_verify_with_instance_key() {
+    let ctx = TestCtx::new().inmemory_db().await.supreme_instance().await;
+
+    // the instance key never signed tokens, so it's neither published nor accepted for tokens without `kid`
+    assert_eq!(
+        jwks_kids(&ctx),
+        vec![ctx.get_instance().token_signing_key().kid().to_string()]
+    );
+    assert!(ctx
+        .get_instance()
+        .token_signing_keys()
+        .find(None, OffsetDateTime::now_utc())
+        .is_none());
+
+    rotate_token_signing_key(&ctx).await.unwrap();
+    assert_eq!(jwks_kids(&ctx).len(), 2);
+}
+
+#[test_log::test(tokio::test)]
+async fn test_token_signing_key_rotation_overlap() {
+    let ctx = TestCtx::new().inmemory_db().await.supreme_instance().await;
+    let old_kid = ctx.get_instance().token_signing_key().kid().to_string();
+
+    let old_token = create_access_token(
+        &session(&ctx).await,
+        Default::default(),
+        &ctx.get_instance(),
+        OffsetDateTime::now_utc(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        jsonwebtoken::decode_header(&old_token).unwrap().kid,
+        Some(old_kid.clone())
+    );
+
+    let new_kid = rotate_token_signing_key(&ctx).await.unwrap();
+    assert_ne!(new_kid, old_kid);
+
+    let new_token = create_access_token(
+        &session(&ctx).await,
+        Default::default(),
+        &ctx.get_instance(),
+        OffsetDateTime::now_utc(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        jsonwebtoken::decode_header(&new_token).unwrap().kid,
+        Some(new_kid.clone())
+    );
+
+    // both tokens are valid during the overlap
+    assert!(
+        verify_access_token(&old_token, &ctx.get_instance(), OffsetDateTime::now_utc()).is_ok()
+    );
+    assert!(
+        verify_access_token(&new_token, &ctx.get_instance(), OffsetDateTime::now_utc()).is_ok()
+    );
+
+    let kids = jwks_kids(&ctx);
+    assert_eq!(kids[0], new_kid);
+    assert!(kids.contains(&old_kid));
+}
+
+#[test_log::test(tokio::test)]
+async fn test_token_signing_key_retirement() {
+    let ctx = TestCtx::new().inmemory_db().await.supreme_instance().await;
+    let first_kid = ctx.get_instance().token_signing_key().kid().to_string();
+    let first_token = create_access_token(
+        &session(&ctx).await,
+        Default::default(),
+        &ctx.get_instance(),
+        OffsetDateTime::now_utc(),
+    )
+    .await
+    .unwrap();
+
+    let second_kid = rotate_token_signing_key(&ctx).await.unwrap();
+    let second_token = create_access_token(
+        &session(&ctx).await,
+        Default::default(),
+        &ctx.get_instance(),
+        OffsetDateTime::now_utc(),
+    )
+    .await
+    .unwrap();
+
+    // the second key superseded the first longer ago than the access token lifetime
+    let now = time::OffsetDateTime::now_utc();
+    for (kid, age) in [(&first_kid, 400), (&second_kid, 380)] {
+        ctx.get_db()
+            .execute(
+                "UPDATE token_signing_key SET created_at = $1 WHERE kid = $2".into(),
+                params!(
+                    (now - time::Duration::days(age)).unix_timestamp(),
+                    kid.clone()
+                ),
+            )
+            .await
+            .unwrap();
+    }
+
+    let third_kid = rotate_token_signing_key(&ctx).await.unwrap();
+
+    assert_eq!(jwks_kids(&ctx), vec![third_kid, second_kid]);
+    assert!(
+        verify_access_token(&first_token, &ctx.get_instance(), OffsetDateTime::now_utc()).is_err()
+    );
+    assert!(verify_access_token(
+        &second_token,
+        &ctx.get_instance(),
+        OffsetDateTime::now_utc()
+    )
+    .is_ok());
+}
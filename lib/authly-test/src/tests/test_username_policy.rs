@@ -0,0 +1,151 @@
+use std::borrow::Cow;
+
+use authly_common::id::ServiceId;
+use authly_domain::{
+    document::error::DocError,
+    settings::{Setting, Settings},
+};
+use authly_service::scim::{users, ScimAuth, ScimJson};
+use axum::extract::{Path, State};
+use http::StatusCode;
+use indoc::indoc;
+use serde_json::{json, Value};
+
+use crate::{
+    test_ctx::TestCtx,
+    util::{compile_and_apply_doc, TestDocError},
+};
+
+const SVC: ServiceId = ServiceId::from_raw_array([7; 16]);
+
+async fn username_ctx(settings: &[(Setting, &str)]) -> TestCtx {
+    let mut username_settings = Settings::default();
+    for (setting, value) in settings {
+        username_settings
+            .try_set(*setting, Cow::Borrowed(value))
+            .unwrap();
+    }
+
+    TestCtx::new()
+        .inmemory_db()
+        .await
+        .supreme_instance()
+        .await
+        .with_settings(username_settings)
+}
+
+async fn json_body(response: axum::response::Response) -> Value {
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    serde_json::from_slice(&bytes).unwrap()
+}
+
+#[test_log::test(tokio::test)]
+async fn test_reserved_username_in_document() {
+    let ctx = username_ctx(&[]).await;
+    let doc = indoc! {
+        r#"
+        [authly-document]
+        id = "bc9ce588-50c3-47d1-94c1-f88b21eaf299"
+
+        [[entity]]
+        eid = "p.e5462a0d22b54d9f9ca37bd96e9b9d8b"
+        label = "persona"
+        username = "Admin"
+        "#
+    };
+
+    let TestDocError::Doc(errors) = compile_and_apply_doc(doc, &ctx).await.unwrap_err() else {
+        panic!()
+    };
+    let spanned_error = errors.into_iter().next().unwrap();
+
+    assert!(
+        matches!(spanned_error.as_ref(), DocError::ReservedUsername),
+        "unexpected error: {spanned_error:?}"
+    );
+    assert_eq!("\"Admin\"", &doc[spanned_error.span()]);
+}
+
+#[test_log::test(tokio::test)]
+async fn test_reserved_username_in_scim() {
+    let ctx = username_ctx(&[(Setting::ReservedUsernames, "superuser, support")]).await;
+
+    let reserved = users::create_user(
+        State(ctx.clone()),
+        ScimAuth(SVC),
+        ScimJson(json!({ "userName": "Support" })),
+    )
+    .await
+    .err()
+    .unwrap();
+    assert_eq!(reserved.status, StatusCode::BAD_REQUEST);
+
+    // the default list is replaced by the setting
+    users::create_user(
+        State(ctx.clone()),
+        ScimAuth(SVC),
+        ScimJson(json!({ "userName": "admin" })),
+    )
+    .await
+    .unwrap();
+}
+
+#[test_log::test(tokio::test)]
+async fn test_username_change_interval() {
+    let ctx = username_ctx(&[(Setting::UsernameChangeInterval, "1d")]).await;
+
+    let created = json_body(
+        users::create_user(
+            State(ctx.clone()),
+            ScimAuth(SVC),
+            ScimJson(json!({ "userName": "alice" })),
+        )
+        .await
+        .unwrap(),
+    )
+    .await;
+    let id = created["id"].as_str().unwrap().to_string();
+
+    // other attributes may change
+    users::replace_user(
+        State(ctx.clone()),
+        ScimAuth(SVC),
+        Path(id.clone()),
+        ScimJson(json!({ "userName": "alice", "displayName": "Alice" })),
+    )
+    .await
+    .unwrap();
+
+    let too_soon = users::replace_user(
+        State(ctx.clone()),
+        ScimAuth(SVC),
+        Path(id.clone()),
+        ScimJson(json!({ "userName": "alicia" })),
+    )
+    .await
+    .err()
+    .unwrap();
+    assert_eq!(too_soon.status, StatusCode::BAD_REQUEST);
+    assert!(too_soon.detail.contains("can't be changed again"));
+}
+
+#[test]
+fn test_username_settings() {
+    let mut settings = Settings::default();
+    assert!(settings.username_policy.is_reserved("ROOT"));
+    assert!(!settings.username_policy.is_reserved("rooted"));
+    assert_eq!(settings.username_policy.change_interval, None);
+
+    assert!(settings
+        .try_set(Setting::UsernameChangeInterval, Cow::Borrowed("often"))
+        .is_err());
+    settings
+        .try_set(Setting::UsernameChangeInterval, Cow::Borrowed("30d"))
+        .unwrap();
+    assert_eq!(
+        settings.username_policy.change_interval,
+        Some(std::time::Duration::from_secs(30 * 24 * 60 * 60))
+    );
+}
@@ -1,7 +1,9 @@
 use authly_common::id::PersonaId;
+use authly_db::{param::ToBlob, params, Db, FromRow};
 use authly_domain::{
-    ctx::GetDb,
+    ctx::{GetDb, GetDecryptedDeks},
     repo::webauthn_repo,
+    session::SessionKind,
     webauthn::{self, Webauthn, WebauthnBuilder},
 };
 use hexhex::hex_literal;
@@ -81,6 +83,7 @@ async fn test_webauthn_happy_path() {
             &localhost_uri(),
             login_session_id,
             credential,
+            SessionKind::Default,
         )
         .await
         .unwrap()
@@ -90,12 +93,16 @@ async fn test_webauthn_happy_path() {
     assert_eq!(session.eid, TESTUSER_ID.upcast());
 
     // test update_last_used
-    let passkey_row = webauthn_repo::list_passkeys_by_entity_id(ctx.get_db(), persona_id)
-        .await
-        .unwrap()
-        .into_iter()
-        .next()
-        .unwrap();
+    let passkey_row = webauthn_repo::list_passkeys_by_entity_id(
+        ctx.get_db(),
+        persona_id,
+        &ctx.load_decrypted_deks(),
+    )
+    .await
+    .unwrap()
+    .into_iter()
+    .next()
+    .unwrap();
 
     webauthn_repo::update_passkey_last_used(
         ctx.get_db(),
@@ -107,6 +114,100 @@ async fn test_webauthn_happy_path() {
     .unwrap();
 }
 
+struct RawPasskey {
+    pk_json: String,
+    pk_ciph: Option<Vec<u8>>,
+}
+
+impl FromRow for RawPasskey {
+    fn from_row(row: &mut impl authly_db::Row) -> Self {
+        Self {
+            pk_json: row.get_text("pk_json"),
+            pk_ciph: Some(row.get_blob("pk_ciph")).filter(|ciph| !ciph.is_empty()),
+        }
+    }
+}
+
+async fn raw_passkeys(ctx: &TestCtx, persona_id: PersonaId) -> Vec<RawPasskey> {
+    ctx.get_db()
+        .query_map::<RawPasskey>(
+            "SELECT pk_json, coalesce(pk_ciph, x'') pk_ciph FROM ent_passkey WHERE eid = $1".into(),
+            params!(persona_id.to_blob()),
+        )
+        .await
+        .unwrap()
+}
+
+#[test_log::test(tokio::test)]
+async fn test_webauthn_passkey_encrypted_at_rest() {
+    let ctx = test_ctx_with_webauthn(webauthn_localhost()).await;
+    compile_and_apply_doc_dir("../../examples/demo".into(), &ctx)
+        .await
+        .unwrap();
+
+    let mut token = new_soft_token();
+    register_token(TESTUSER_ID, &mut token, &ctx).await;
+
+    let raw = raw_passkeys(&ctx, TESTUSER_ID).await;
+    assert_eq!(raw.len(), 1);
+    assert_eq!(raw[0].pk_json, "");
+    let ciph = raw[0].pk_ciph.as_ref().expect("passkey must be encrypted");
+    assert!(
+        serde_json::from_slice::<serde_json::Value>(ciph).is_err(),
+        "ciphertext must not be JSON"
+    );
+
+    let deks = ctx.load_decrypted_deks();
+    let passkeys = webauthn_repo::list_passkeys_by_entity_id(ctx.get_db(), TESTUSER_ID, &deks)
+        .await
+        .unwrap();
+    assert_eq!(passkeys.len(), 1);
+
+    // simulate a passkey stored before encryption was introduced
+    let passkey = passkeys.into_iter().next().unwrap().passkey;
+    ctx.get_db()
+        .execute(
+            "UPDATE ent_passkey SET pk_json = $1, pk_nonce = NULL, pk_ciph = NULL WHERE eid = $2"
+                .into(),
+            params!(
+                serde_json::to_string(&passkey).unwrap(),
+                TESTUSER_ID.to_blob()
+            ),
+        )
+        .await
+        .unwrap();
+
+    // legacy plaintext is still readable
+    let legacy = webauthn_repo::list_passkeys_by_entity_id(ctx.get_db(), TESTUSER_ID, &deks)
+        .await
+        .unwrap();
+    assert_eq!(legacy[0].passkey.cred_id(), passkey.cred_id());
+
+    assert_eq!(
+        webauthn_repo::encrypt_plaintext_passkeys(ctx.get_db(), &deks)
+            .await
+            .unwrap(),
+        1
+    );
+
+    let raw = raw_passkeys(&ctx, TESTUSER_ID).await;
+    assert_eq!(raw[0].pk_json, "");
+    assert!(raw[0].pk_ciph.is_some());
+
+    let migrated = webauthn_repo::list_passkeys_by_entity_id(ctx.get_db(), TESTUSER_ID, &deks)
+        .await
+        .unwrap();
+    assert_eq!(migrated[0].passkey.cred_id(), passkey.cred_id());
+
+    // nothing left to encrypt
+    assert_eq!(
+        webauthn_repo::encrypt_plaintext_passkeys(ctx.get_db(), &deks)
+            .await
+            .unwrap(),
+        0
+    );
+}
+
 #[test_log::test(tokio::test)]
 async fn test_webauthn_invalid_username() {
     let ctx = test_ctx_with_webauthn(webauthn_localhost()).await;
@@ -0,0 +1,95 @@
+use authly_common::{
+    id::{EntityId, PersonaId, ServiceId},
+    mtls_server::PeerServiceEntity,
+};
+use authly_domain::{
+    ctx::GetClock,
+    session::{init_session, AuthClass, SessionKind},
+};
+use axum::Extension;
+use hexhex::hex_literal;
+use http::{header::COOKIE, StatusCode};
+use serde_json::{json, Value};
+
+use crate::{test_ctx::TestCtx, util::compile_and_apply_doc_dir};
+
+const SVC: ServiceId = ServiceId::from_raw_array([7; 16]);
+const PERSONA_ME: PersonaId =
+    PersonaId::from_raw_array(hex_literal!("0fbcd73e1a884424a1615c3c3fdeebec"));
+
+async fn spawn_api(ctx: &TestCtx) -> String {
+    let router = authly_service::openapi::router::router()
+        .layer(Extension(PeerServiceEntity(SVC)))
+        .with_state(ctx.clone());
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let base_url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, router).await });
+
+    base_url
+}
+
+#[test_log::test(tokio::test)]
+async fn test_whoami() {
+    let ctx = TestCtx::new().inmemory_db().await.supreme_instance().await;
+    compile_and_apply_doc_dir("../../examples/demo".into(), &ctx)
+        .await
+        .unwrap();
+    let base_url = spawn_api(&ctx).await;
+    let client = reqwest::Client::new();
+
+    let session = init_session(
+        &ctx,
+        PERSONA_ME.upcast(),
+        SessionKind::Persistent,
+        AuthClass::WebAuthn,
+    )
+    .await
+    .unwrap();
+
+    let response = client
+        .get(format!("{base_url}/api/auth/whoami"))
+        .header(
+            COOKIE,
+            session
+                .to_cookie(ctx.get_clock().now())
+                .stripped()
+                .to_string(),
+        )
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body: Value = response.json().await.unwrap();
+    let eid: EntityId = PERSONA_ME.upcast();
+    assert_eq!(body["entityId"], json!(eid));
+    assert_eq!(body["username"], json!("testuser"));
+    assert_eq!(body["email"], json!("me@mail.com"));
+    assert!(body["entityAttributes"].is_array());
+    assert_eq!(body["session"]["handle"], json!(session.handle()));
+    assert_eq!(body["session"]["kind"], json!("persistent"));
+    assert_eq!(body["session"]["authClass"], json!("webauthn"));
+}
+
+#[test_log::test(tokio::test)]
+async fn test_whoami_unauthenticated() {
+    let ctx = TestCtx::new().inmemory_db().await.supreme_instance().await;
+    let base_url = spawn_api(&ctx).await;
+    let client = reqwest::Client::new();
+
+    let no_session = client
+        .get(format!("{base_url}/api/auth/whoami"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(no_session.status(), StatusCode::UNAUTHORIZED);
+
+    let unknown_session = client
+        .get(format!("{base_url}/api/auth/whoami"))
+        .header(COOKIE, "session-cookie=00112233445566778899aabbccddeeff")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(unknown_session.status(), StatusCode::UNAUTHORIZED);
+}
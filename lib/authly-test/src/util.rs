@@ -1,10 +1,12 @@
 use std::{collections::HashMap, path::PathBuf, sync::Arc};
 
 use authly_common::{
-    document::Document,
     id::{PersonaId, ServiceId},
     mtls_server::PeerServiceEntity,
-    proto::connect::authly_connect_server::AuthlyConnectServer,
+    proto::{
+        connect::authly_connect_server::AuthlyConnectServer,
+        service::{self as proto, authly_service_client::AuthlyServiceClient},
+    },
     service::NamespacePropertyMapping,
 };
 use authly_connect::{
@@ -14,14 +16,21 @@ use authly_connect::{
 use authly_domain::{
     audit::Actor,
     cert::Cert,
+    ctx::GetClock,
     directory::{self, DirectoryError},
-    document::{compiled_document::DocumentMeta, doc_compiler::compile_doc, error::DocError},
+    document::{
+        assertion::parse_document, compiled_document::DocumentMeta, doc_compiler::compile_doc,
+        error::DocError,
+    },
     remote_addr::RemoteAddr,
     repo::{
         document_repo::DocumentDbTxnError,
         service_repo::{self, PropertyKind},
     },
+    session::Session,
+    tls::{alpn_protocol_ids, AlpnProtocol},
 };
+use authly_service::proto::service_server::AuthlyServiceServerImpl;
 use authly_sqlite::SqlitePool;
 use rcgen::KeyPair;
 use rustls::{
@@ -78,8 +87,8 @@ pub async fn compile_and_apply_doc_only_once(
     toml: &str,
     ctx: &TestCtx,
 ) -> Result<(), TestDocError> {
-    let doc = Document::from_toml(toml).map_err(TestDocError::Other)?;
-    let compiled_doc = compile_doc(ctx, doc, DocumentMeta::default())
+    let (doc, extensions) = parse_document(toml).map_err(TestDocError::Other)?;
+    let compiled_doc = compile_doc(ctx, doc, extensions, DocumentMeta::default())
         .await
         .map_err(TestDocError::Doc)?;
 
@@ -105,6 +114,27 @@ pub fn tonic_request<T>(msg: T, eid: ServiceId) -> tonic::Request<T> {
     req
 }
 
+/// Get an access token for the session through the given service
+pub async fn get_access_token(ctx: &TestCtx, session: &Session, svc_eid: ServiceId) -> String {
+    let mut client = AuthlyServiceClient::new(AuthlyServiceServerImpl::new_service(ctx.clone()));
+    let cookie = session.to_cookie(ctx.get_clock().now());
+
+    let mut request = tonic_request(proto::Empty {}, svc_eid);
+    request.metadata_mut().insert(
+        "cookie",
+        format!("{}={}", cookie.name(), cookie.value())
+            .parse()
+            .unwrap(),
+    );
+
+    client
+        .get_access_token(request)
+        .await
+        .unwrap()
+        .into_inner()
+        .token
+}
+
 pub fn rustls_server_config_no_client_auth(
     server_cert_chain: &[&Cert<KeyPair>],
 ) -> anyhow::Result<Arc<rustls::ServerConfig>> {
@@ -123,7 +153,8 @@ pub fn rustls_server_config_no_client_auth(
             private_key_der,
         )?;
 
-    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec(), b"http/1.0".to_vec()];
+    config.alpn_protocols =
+        alpn_protocol_ids(&[AlpnProtocol::H2, AlpnProtocol::Http11, AlpnProtocol::Http10]);
     Ok(Arc::new(config))
 }
 
@@ -149,7 +180,8 @@ pub fn rustls_server_config_mtls(
             private_key_der,
         )?;
 
-    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec(), b"http/1.0".to_vec()];
+    config.alpn_protocols =
+        alpn_protocol_ids(&[AlpnProtocol::H2, AlpnProtocol::Http11, AlpnProtocol::Http10]);
     Ok(Arc::new(config))
 }
 
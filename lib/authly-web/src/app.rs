@@ -6,7 +6,9 @@ use tracing::warn;
 
 use crate::{htmx::HX_REDIRECT, Htmx};
 
+pub mod admin;
 pub mod persona;
+pub mod step_up;
 
 mod tabs;
 
@@ -50,6 +52,8 @@ fn render_app_tab(Htmx { prefix, .. }: &Htmx, tab: Markup, js: Option<String>) -
                     img alt="Authly" src={(prefix)"/static/logo.svg"};
 
                     (tab)
+
+                    div id="stepup" {}
                 }
             }
 
@@ -0,0 +1,199 @@
+use authly_db::{Db, DbResult};
+use authly_domain::{
+    access_control::role,
+    ctx::{GetClockSkew, GetDb, GetStats, ServiceBus},
+    extract::auth::WebAuth,
+    repo::{
+        directory_repo::DbDirectoryAudit,
+        policy_repo::{self, ResourceAttrCoverage},
+        service_repo,
+    },
+    stats::{collect_stats, StatsSnapshot},
+};
+use axum::extract::State;
+use maud::{html, Markup};
+use time::format_description::well_known::Rfc3339;
+
+use crate::{
+    app::tabs::{render_nav_tab_list, Tab},
+    Htmx,
+};
+
+use super::{render_app_tab, AppError};
+
+/// How many audit entries to show on the dashboard
+const RECENT_AUDIT_LIMIT: usize = 10;
+
+/// The operator dashboard
+pub async fn admin<Ctx>(
+    State(ctx): State<Ctx>,
+    htmx: Htmx,
+    _auth: WebAuth<role::Admin>,
+) -> Result<Markup, AppError>
+where
+    Ctx: GetDb + GetStats + GetClockSkew + ServiceBus,
+{
+    let prefix = &htmx.prefix;
+    let stats = collect_stats(&ctx)
+        .await
+        .map_err(|err| AppError::Internal(err.into()))?;
+    let audit = DbDirectoryAudit::query_recent(ctx.get_db(), RECENT_AUDIT_LIMIT)
+        .await
+        .map_err(|err| AppError::Internal(err.into()))?;
+    let coverage = collect_policy_coverage(ctx.get_db())
+        .await
+        .map_err(|err| AppError::Internal(err.into()))?;
+
+    Ok(render_app_tab(
+        &htmx,
+        html! {
+            (render_nav_tab_list(Tab::Admin, prefix, true))
+
+            div id="tab-content" role="tabpanel" class="tab-content" {
+                (render_stats(&htmx, &stats))
+
+                section {
+                    h4 { "Recent changes" }
+
+                    table {
+                        thead {
+                            tr {
+                                th { "Directory" }
+                                th { "Updated" }
+                                th { "Updated by" }
+                            }
+                        }
+                        tbody {
+                            @for entry in audit {
+                                tr {
+                                    td {
+                                        @if let Some(label) = &entry.dir_label {
+                                            (label)
+                                        } @else {
+                                            code { (entry.dir_id) }
+                                        }
+                                    }
+                                    td { relative-time datetime=(entry.upd.format(&Rfc3339)?) {} }
+                                    td { code { (entry.updated_by) } }
+                                }
+                            }
+                        }
+                    }
+                }
+
+                (render_policy_coverage(&coverage))
+            }
+        },
+        None,
+    ))
+}
+
+/// Policy binding coverage of the resource attributes of every service
+async fn collect_policy_coverage(
+    db: &impl Db,
+) -> DbResult<Vec<(String, Vec<ResourceAttrCoverage>)>> {
+    let mut coverage = vec![];
+
+    for (svc_eid, label) in service_repo::list_service_labels(db).await? {
+        let attributes = policy_repo::list_svc_resource_coverage(db, svc_eid).await?;
+        if !attributes.is_empty() {
+            coverage.push((label, attributes));
+        }
+    }
+
+    Ok(coverage)
+}
+
+fn render_policy_coverage(coverage: &[(String, Vec<ResourceAttrCoverage>)]) -> Markup {
+    html! {
+        section {
+            h4 { "Policy coverage" }
+
+            @for (service, attributes) in coverage {
+                table {
+                    caption { (service) }
+                    thead {
+                        tr {
+                            th { "Resource attribute" }
+                            th { "Bindings" }
+                        }
+                    }
+                    tbody {
+                        @for attr in attributes {
+                            tr {
+                                td { code { (attr.namespace)":"(attr.property)":"(attr.attribute) } }
+                                td {
+                                    @if attr.is_covered() {
+                                        (attr.bindings)
+                                    } @else {
+                                        mark { "uncovered" }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The statistics fragment of the dashboard, polled by HTMX
+pub async fn admin_stats<Ctx>(
+    State(ctx): State<Ctx>,
+    htmx: Htmx,
+    _auth: WebAuth<role::Admin>,
+) -> Result<Markup, AppError>
+where
+    Ctx: GetDb + GetStats + GetClockSkew + ServiceBus,
+{
+    let stats = collect_stats(&ctx)
+        .await
+        .map_err(|err| AppError::Internal(err.into()))?;
+
+    Ok(render_stats(&htmx, &stats))
+}
+
+fn render_stats(Htmx { prefix, .. }: &Htmx, stats: &StatsSnapshot) -> Markup {
+    html! {
+        div id="admin-stats" hx-get={(prefix)"/tab/admin/stats"} hx-trigger="every 10s" hx-swap="outerHTML" {
+            @if stats.insecure_mode {
+                article class="error" {
+                    strong { "Encryption is disabled! This node must not be used in production." }
+                }
+            }
+
+            section {
+                h4 { "Directories" }
+
+                table {
+                    tbody {
+                        tr { th { "Directories" } td { (stats.directories.directories) } }
+                        tr { th { "Personas" } td { (stats.directories.personas) } }
+                        tr { th { "Groups" } td { (stats.directories.groups) } }
+                        tr { th { "Services" } td { (stats.directories.services) } }
+                        tr { th { "Policies" } td { (stats.directories.policies) } }
+                    }
+                }
+            }
+
+            section {
+                h4 { "Cluster" }
+
+                table {
+                    tbody {
+                        tr { th { "Raft role" } td { (format!("{:?}", stats.raft_role)) } }
+                        tr { th { "Mode" } td { @if stats.read_only { mark { "read-only" } } @else { "read-write" } } }
+                        tr { th { "Uptime (s)" } td { (stats.uptime_secs) } }
+                        tr { th { "Connected services" } td { (stats.connected_services.services) } }
+                        tr { th { "Active sessions" } td { (stats.active_sessions) } }
+                        tr { th { "Authentications/min" } td { (stats.authentications.per_minute) } }
+                        tr { th { "Failed authentications" } td { (stats.authentications.failed) } }
+                        tr { th { "Secrets backend" } td { @if stats.secrets_healthy { "healthy" } @else { mark { "unhealthy" } } } }
+                        tr { th { "Slow queries" } td { (stats.slow_queries) } }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -2,24 +2,35 @@ use std::collections::BTreeMap;
 
 use authly_common::id::PersonaId;
 use authly_domain::{
-    ctx::{GetDb, GetDecryptedDeks, WebAuthn},
+    access_control::{role, VerifyAuthlyRole},
+    ctx::{
+        ClusterBus, Directories, GetClock, GetDb, GetDecryptedDeks, GetSessionCache, GetSettings,
+        WebAuthn,
+    },
+    directory::PersonaDirectory,
     extract::{auth::WebAuth, base_uri::ProxiedBaseUri},
-    repo::webauthn_repo,
+    persona_directory,
+    repo::{entity_repo, webauthn_repo},
+    session::{self, SessionKind},
     webauthn::{self, RegisterPublicKeyCredential, WebauthnError},
 };
 use axum::{
-    extract::State,
+    extract::{Path, State},
     response::{IntoResponse, Response},
     Form,
 };
 use indoc::formatdoc;
 use maud::{html, Markup};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
 use serde::Deserialize;
 use time::{format_description::well_known::Rfc3339, Duration};
 use tracing::{info, warn};
 
 use crate::{
-    app::tabs::{render_nav_tab_list, Tab},
+    app::{
+        step_up::StepUp,
+        tabs::{render_nav_tab_list, Tab},
+    },
     htmx::{HX_REFRESH, HX_TRIGGER},
     Htmx,
 };
@@ -34,16 +45,31 @@ pub async fn persona<Ctx>(
     auth: WebAuth<()>,
 ) -> Result<Markup, AppError>
 where
-    Ctx: GetDb,
+    Ctx: GetDb + GetDecryptedDeks + GetSettings + GetClock + Directories,
 {
     let prefix = &htmx.prefix;
     let eid = auth.claims.authly.entity_id;
+    let is_admin = role::Admin::verify_roles(&auth.claims.authly.entity_attributes);
 
     let passkeys = if let Ok(persona_id) = PersonaId::try_from(eid) {
-        webauthn_repo::list_passkeys_by_entity_id(ctx.get_db(), persona_id)
-            .await
-            .map(Some)
-            .map_err(|err| AppError::Internal(err.into()))?
+        webauthn_repo::list_passkeys_by_entity_id(
+            ctx.get_db(),
+            persona_id,
+            &ctx.load_decrypted_deks(),
+        )
+        .await
+        .map(Some)
+        .map_err(|err| AppError::Internal(err.into()))?
+    } else {
+        None
+    };
+
+    let sessions = session::list_sessions(&ctx, eid)
+        .await
+        .map_err(|err| AppError::Internal(err.into()))?;
+
+    let linked_accounts = if let Ok(persona_id) = PersonaId::try_from(eid) {
+        Some(list_linked_accounts(&ctx, persona_id).await?)
     } else {
         None
     };
@@ -51,7 +77,7 @@ where
     Ok(render_app_tab(
         &htmx,
         html! {
-            (render_nav_tab_list(Tab::Persona, &prefix))
+            (render_nav_tab_list(Tab::Persona, prefix, is_admin))
 
             div id="tab-content" role="tabpanel" class="tab-content" {
                 p {
@@ -88,6 +114,56 @@ where
 
                     (render_passkeyreg(&htmx, false, None))
                 }
+
+                @if let Some(linked_accounts) = linked_accounts {
+                    @if !linked_accounts.is_empty() {
+                        section {
+                            h4 { "Linked accounts" }
+
+                            table {
+                                tbody {
+                                    @for (label, linked) in linked_accounts {
+                                        (render_linked_account(prefix, &label, linked))
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+
+                section {
+                    h4 { "Sessions" }
+
+                    table {
+                        thead {
+                            tr {
+                                th { "ID" }
+                                th { "Expires" }
+                                th { "Remembered" }
+                                th {}
+                            }
+                        }
+                        tbody {
+                            @for session in sessions {
+                                tr {
+                                    td { code { (session.handle()) } }
+                                    td { relative-time datetime=(session.expires_at.format(&Rfc3339)?) {} }
+                                    td {
+                                        @if session.kind == SessionKind::Persistent { "yes" } @else { "no" }
+                                    }
+                                    td {
+                                        button class="secondary"
+                                            hx-delete={(prefix)"/tab/persona/session/"(session.handle())}
+                                            hx-target="closest tr"
+                                            hx-swap="outerHTML" {
+                                            "Revoke"
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
             }
         },
         Some(formatdoc! {
@@ -128,11 +204,110 @@ where
     ))
 }
 
+/// Revoke one of the persona's own sessions
+pub async fn revoke_session<Ctx>(
+    State(ctx): State<Ctx>,
+    Path(handle): Path<String>,
+    auth: WebAuth<()>,
+) -> Result<Markup, AppError>
+where
+    Ctx: GetDb + GetSettings + GetSessionCache + GetClock + ClusterBus,
+{
+    let eid = auth.claims.authly.entity_id;
+
+    if session::revoke_session(&ctx, eid, &handle)
+        .await
+        .map_err(|err| AppError::Internal(err.into()))?
+    {
+        info!(?eid, "session revoked");
+    }
+
+    // the table row is swapped out with nothing
+    Ok(html! {})
+}
+
+/// Unlink the persona's identity from an OAuth directory
+pub async fn unlink_account<Ctx>(
+    State(ctx): State<Ctx>,
+    htmx: Htmx,
+    Path(label): Path<String>,
+    auth: WebAuth<()>,
+    _step_up: StepUp,
+) -> Result<Markup, AppError>
+where
+    Ctx: GetDb + GetClock + Directories,
+{
+    let persona_id = auth
+        .claims
+        .authly
+        .entity_id
+        .try_into()
+        .map_err(|_| AppError::MustBePersona)?;
+
+    let persona_directories = ctx.load_persona_directories();
+    let Some(PersonaDirectory::OAuth(oauth)) = persona_directories.get(&label) else {
+        return Err(AppError::InvalidInput(anyhow::anyhow!(
+            "no such directory: {label}"
+        )));
+    };
+
+    persona_directory::unlink_foreign_identity(&ctx, oauth.dir_key, persona_id)
+        .await
+        .map_err(|err| AppError::Internal(err.into()))?;
+
+    Ok(render_linked_account(&htmx.prefix, &label, false))
+}
+
+/// The OAuth directories, and whether the persona has linked an identity from each of them
+async fn list_linked_accounts(
+    ctx: &(impl GetDb + Directories),
+    persona_id: PersonaId,
+) -> Result<Vec<(String, bool)>, AppError> {
+    let linked_dirs = entity_repo::list_foreign_persona_link_dirs(ctx.get_db(), persona_id)
+        .await
+        .map_err(|err| AppError::Internal(err.into()))?;
+
+    Ok(ctx
+        .load_persona_directories()
+        .iter()
+        .filter_map(|(label, dir)| match dir {
+            PersonaDirectory::OAuth(oauth) => {
+                Some((label.clone(), linked_dirs.contains(&oauth.dir_key)))
+            }
+        })
+        .collect())
+}
+
+fn render_linked_account(prefix: &str, label: &str, linked: bool) -> Markup {
+    let label_path = utf8_percent_encode(label, NON_ALPHANUMERIC).to_string();
+
+    html! {
+        tr {
+            td { (label) }
+            td {
+                @if linked {
+                    button class="secondary"
+                        hx-delete={(prefix)"/tab/persona/link/"(label_path)}
+                        hx-target="closest tr"
+                        hx-swap="outerHTML" {
+                        "Unlink"
+                    }
+                } @else {
+                    button hx-post={(prefix)"/auth/oauth/"(label_path)"/link"} {
+                        "Link"
+                    }
+                }
+            }
+        }
+    }
+}
+
 pub async fn webauthn_register_start<Ctx>(
     State(ctx): State<Ctx>,
     htmx: Htmx,
     base_uri: ProxiedBaseUri,
     auth: WebAuth<()>,
+    _step_up: StepUp,
 ) -> Result<Response, AppError>
 where
     Ctx: GetDb + WebAuthn + GetDecryptedDeks,
@@ -177,7 +352,7 @@ pub async fn webauthn_register_finish<Ctx>(
     Form(form): Form<RegisterPublicKeyCredentialForm>,
 ) -> Result<Response, AppError>
 where
-    Ctx: GetDb + WebAuthn,
+    Ctx: GetDb + GetDecryptedDeks + GetClock + WebAuthn,
 {
     let persona_id = auth
         .claims
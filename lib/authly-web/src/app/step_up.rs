@@ -0,0 +1,129 @@
+//! Step-up authentication guarding sensitive operations within an active session
+
+use authly_common::id::PersonaId;
+use authly_domain::{
+    ctx::{GetBuiltins, GetClock, GetDb, GetSessionCache, GetSettings, GetStats},
+    login::{verify_persona_password, LoginError},
+    session::{self, authenticate_session_cookie, find_session_cookie, AuthClass, Session},
+};
+use axum::{
+    extract::{FromRequestParts, State},
+    response::{IntoResponse, Response},
+    Form,
+};
+use http::{header::COOKIE, request::Parts, HeaderMap, StatusCode};
+use maud::{html, Markup};
+use serde::Deserialize;
+use tracing::info;
+
+use crate::{
+    htmx::{HX_RESWAP, HX_RETARGET},
+    Htmx,
+};
+
+use super::AppError;
+
+/// Extractor for sensitive routes.
+///
+/// Requires the session to be recently and strongly authenticated.
+/// Otherwise the request is answered with a step-up challenge, which HTMX swaps into the `#stepup` element.
+pub struct StepUp(pub Session);
+
+impl<Ctx> FromRequestParts<Ctx> for StepUp
+where
+    Ctx: GetDb + GetSettings + GetSessionCache + GetClock + Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, ctx: &Ctx) -> Result<Self, Self::Rejection> {
+        let htmx = Htmx::from_request_parts(parts, ctx)
+            .await
+            .map_err(|_| StatusCode::BAD_REQUEST.into_response())?;
+
+        let session = current_session(ctx, &parts.headers)
+            .await
+            .map_err(|msg| (StatusCode::UNAUTHORIZED, msg).into_response())?;
+
+        if session.is_stepped_up(ctx.get_clock().now()) {
+            Ok(Self(session))
+        } else {
+            Err((
+                [(HX_RETARGET, "#stepup"), (HX_RESWAP, "innerHTML")],
+                render_step_up(&htmx, None),
+            )
+                .into_response())
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct StepUpBody {
+    password: String,
+}
+
+/// Re-authenticate the current session with the persona's password
+pub async fn step_up<Ctx>(
+    State(ctx): State<Ctx>,
+    htmx: Htmx,
+    headers: HeaderMap,
+    Form(StepUpBody { password }): Form<StepUpBody>,
+) -> Result<Markup, AppError>
+where
+    Ctx: GetDb + GetBuiltins + GetSettings + GetSessionCache + GetStats + GetClock,
+{
+    let mut session = current_session(&ctx, &headers)
+        .await
+        .map_err(|msg| AppError::InvalidInput(anyhow::anyhow!(msg)))?;
+    let persona_id = PersonaId::try_from(session.eid).map_err(|_| AppError::MustBePersona)?;
+
+    match verify_persona_password(&ctx, persona_id, password).await {
+        Ok(()) => {}
+        Err(LoginError::Db(err)) => return Err(AppError::Internal(err.into())),
+        Err(_) => return Ok(render_step_up(&htmx, Some("Invalid password"))),
+    }
+
+    session::step_up_session(&ctx, &mut session, AuthClass::Password)
+        .await
+        .map_err(|err| AppError::Internal(err.into()))?;
+
+    info!(?persona_id, "session stepped up");
+
+    Ok(html! {
+        article {
+            "Identity confirmed, please retry the operation."
+        }
+    })
+}
+
+async fn current_session(
+    ctx: &(impl GetDb + GetSettings + GetSessionCache + GetClock),
+    headers: &HeaderMap,
+) -> Result<Session, &'static str> {
+    let session_cookie = find_session_cookie(
+        headers
+            .get_all(COOKIE)
+            .iter()
+            .filter_map(|value| value.to_str().ok()),
+    )?;
+
+    authenticate_session_cookie(ctx, &session_cookie).await
+}
+
+/// Render the step-up challenge
+fn render_step_up(Htmx { prefix, .. }: &Htmx, error: Option<&str>) -> Markup {
+    html! {
+        article {
+            header { "Please confirm your identity to continue" }
+
+            form hx-post={(prefix)"/tab/stepup"} hx-target="#stepup" {
+                input name="password" type="password" aria-label="Password" placeholder="Password" required autofocus {}
+
+                @if let Some(error) = error {
+                    div class="error" { (error) }
+                }
+
+                button type="submit" { "Confirm" }
+            }
+        }
+    }
+}
@@ -3,9 +3,10 @@ use maud::{html, Markup};
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub enum Tab {
     Persona,
+    Admin,
 }
 
-pub fn render_nav_tab_list(tab: Tab, prefix: &str) -> Markup {
+pub fn render_nav_tab_list(tab: Tab, prefix: &str, is_admin: bool) -> Markup {
     html! {
         nav {
             ul {
@@ -14,6 +15,13 @@ pub fn render_nav_tab_list(tab: Tab, prefix: &str) -> Markup {
                         "Persona"
                     }
                 }
+                @if is_admin {
+                    li {
+                        a href={(prefix)"/tab/admin"} aria-current=[tab.cur(Tab::Admin)] role="tab" aria-controls="tab-content" {
+                            "Admin"
+                        }
+                    }
+                }
             }
         }
     }
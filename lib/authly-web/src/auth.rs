@@ -2,12 +2,17 @@ use std::collections::BTreeMap;
 
 use authly_common::mtls_server::PeerServiceEntity;
 use authly_domain::{
-    ctx::{GetBuiltins, GetDb, GetDecryptedDeks, WebAuthn},
+    ctx::{
+        Directories, GetBuiltins, GetClock, GetDb, GetDecryptedDeks, GetSettings, GetStats,
+        WebAuthn,
+    },
     dev::IsDev,
+    directory::PersonaDirectory,
     extract::base_uri::{ForwardedPrefix, ProxiedBaseUri},
     login::{try_username_password_login, LoginError, LoginOptions},
     login_session::LoginSession,
-    session::Session,
+    redirect::is_allowed_redirect,
+    session::{Session, SessionKind},
     webauthn::{self, PublicKeyCredential},
 };
 use axum::{
@@ -18,6 +23,7 @@ use axum::{
 use http::{StatusCode, Uri};
 use indoc::formatdoc;
 use maud::{html, Markup, PreEscaped, DOCTYPE};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
 use serde::{Deserialize, Serialize};
 use time::Duration;
 use tracing::{info, warn};
@@ -37,11 +43,22 @@ pub struct QueryParams {
     next: String,
 }
 
-pub async fn index(
+pub async fn index<Ctx>(
+    State(ctx): State<Ctx>,
     ForwardedPrefix(prefix): ForwardedPrefix,
     login_session: LoginSession,
     Query(params): Query<QueryParams>,
-) -> Response {
+) -> Response
+where
+    Ctx: Directories,
+{
+    let oauth_labels: Vec<String> = ctx
+        .load_persona_directories()
+        .iter()
+        .filter(|(_, dir)| matches!(dir, PersonaDirectory::OAuth(_)))
+        .map(|(label, _)| label.clone())
+        .collect();
+
     (
         axum_extra::extract::CookieJar::new().add(login_session.to_cookie()),
         html! {
@@ -66,6 +83,7 @@ pub async fn index(
                             div class="card" {
                                 h2 { "Sign in" }
                                 (login_form(&prefix, &params, None))
+                                (oauth_providers(&prefix, &oauth_labels))
                             }
                         }
                     }
@@ -99,6 +117,7 @@ fn render_script(prefix: &str, params: &QueryParams) -> String {
                     {{
                         target: '#loginform',
                         values: {{
+                            remember_me: document.getElementById('remember_me').checked,
                             json: JSON.stringify({{
                                 id: assertion.id,
                                 rawId: Base64.fromUint8Array(new Uint8Array(assertion.rawId), true),
@@ -131,6 +150,10 @@ fn login_form(prefix: &str, params: &QueryParams, message: Option<&str>) -> Mark
             div class="inputs" {
                 input id="username" name="username" type="text" aria-label="Username" placeholder="Username" required autofocus {}
                 input id="password" name="password" type="password" aria-label="Password" placeholder="Password" {}
+                label {
+                    input id="remember_me" name="remember_me" type="checkbox" value="true" {}
+                    "Remember me"
+                }
             }
             @if let Some(message) = message {
                 div class="error" {
@@ -156,6 +179,21 @@ fn login_form(prefix: &str, params: &QueryParams, message: Option<&str>) -> Mark
     )
 }
 
+/// Links for signing in through each of the configured OAuth providers
+fn oauth_providers(prefix: &str, labels: &[String]) -> Markup {
+    html!(
+        @if !labels.is_empty() {
+            div id="oauth_providers" {
+                @for label in labels {
+                    a role="button" class="secondary" href={(prefix)"/auth/oauth/"(utf8_percent_encode(label, NON_ALPHANUMERIC).to_string())} {
+                        "Sign in with " (label)
+                    }
+                }
+            }
+        }
+    )
+}
+
 /// NOTE: This is currently sent "unencrypted" and can be MITMed by whoever terminates SSL outside Authly's control,
 /// when the web auth routes are exposed on the internet.
 #[derive(Deserialize)]
@@ -164,6 +202,9 @@ pub struct LoginBody {
     action: LoginAction,
     username: String,
     password: String,
+    /// Whether to issue a persistent session
+    #[serde(default)]
+    remember_me: bool,
 }
 
 #[derive(Deserialize)]
@@ -187,14 +228,15 @@ pub async fn login<Ctx>(
         action,
         username,
         password,
+        remember_me,
     }): Form<LoginBody>,
 ) -> Response
 where
-    Ctx: GetDb + GetBuiltins + GetDecryptedDeks + WebAuthn,
+    Ctx: GetDb + GetBuiltins + GetDecryptedDeks + GetSettings + GetStats + GetClock + WebAuthn,
 {
     /// Produce a "hx-trigger" header value that starts webauthn auth flow
     async fn webauthn_start_event_header_value(
-        ctx: &(impl WebAuthn + GetDb + GetDecryptedDeks),
+        ctx: &(impl WebAuthn + GetDb + GetDecryptedDeks + GetSettings),
         base_uri: &Uri,
         login_session: LoginSession,
         username: &str,
@@ -215,12 +257,14 @@ where
 
     match action {
         LoginAction::Login => {
-            let login_options = LoginOptions::default().dev(is_dev);
+            let login_options = LoginOptions::default().dev(is_dev).remember_me(remember_me);
 
             match try_username_password_login(&ctx, peer_svc, username, password, login_options)
                 .await
             {
-                Ok((_persona_id, session)) => login_success_redirect(session, &params),
+                Ok((_persona_id, session)) => {
+                    login_success_redirect(&ctx, &base_uri, &prefix, session, &params)
+                }
                 Err(err) => {
                     match err {
                         LoginError::UnprivilegedService => info!("unprivileged service"),
@@ -253,10 +297,31 @@ where
     }
 }
 
-fn login_success_redirect(session: Session, params: &QueryParams) -> Response {
+/// Redirect to the `next` target after login, if it's allowed by the `LOGIN_REDIRECT_ALLOWLIST`.
+/// Other targets are replaced by the Authly app.
+fn login_success_redirect(
+    ctx: &(impl GetSettings + GetClock),
+    base_uri: &ProxiedBaseUri,
+    prefix: &str,
+    session: Session,
+    params: &QueryParams,
+) -> Response {
+    let next = if is_allowed_redirect(
+        &ctx.get_settings().login_redirect_allowlist,
+        Some(&base_uri.to_string()),
+        &params.next,
+    ) {
+        params.next.clone()
+    } else {
+        if !params.next.is_empty() {
+            warn!(next = %params.next, "rejected login redirect target");
+        }
+        format!("{prefix}/")
+    };
+
     (
-        axum_extra::extract::CookieJar::new().add(session.to_cookie()),
-        [(HX_REDIRECT, &params.next)],
+        axum_extra::extract::CookieJar::new().add(session.to_cookie(ctx.get_clock().now())),
+        [(HX_REDIRECT, next)],
     )
         .into_response()
 }
@@ -265,6 +330,9 @@ fn login_success_redirect(session: Session, params: &QueryParams) -> Response {
 pub struct PublicKeyCredentialForm {
     /// The PublicKeyCredential JSON string
     json: String,
+    /// Whether to issue a persistent session
+    #[serde(default)]
+    remember_me: bool,
 }
 
 pub async fn webauthn_auth_finish<Ctx>(
@@ -274,18 +342,32 @@ pub async fn webauthn_auth_finish<Ctx>(
     base_uri: ProxiedBaseUri,
     ForwardedPrefix(prefix): ForwardedPrefix,
     Query(params): Query<QueryParams>,
-    Form(PublicKeyCredentialForm { json }): Form<PublicKeyCredentialForm>,
+    Form(PublicKeyCredentialForm { json, remember_me }): Form<PublicKeyCredentialForm>,
 ) -> Result<Response, (StatusCode, String)>
 where
-    Ctx: GetDb + WebAuthn + GetBuiltins,
+    Ctx: GetDb + GetDecryptedDeks + GetSettings + GetStats + GetClock + WebAuthn + GetBuiltins,
 {
     let credential = serde_json::from_str::<PublicKeyCredential>(&json)
         .map_err(|err| (StatusCode::UNPROCESSABLE_ENTITY, format!("{err:?}")))?;
 
-    match webauthn::webauthn_finish_authentication(&ctx, &base_uri.0, login_session.0, credential)
-        .await
+    let session_kind = if remember_me {
+        SessionKind::Persistent
+    } else {
+        SessionKind::Default
+    };
+
+    match webauthn::webauthn_finish_authentication(
+        &ctx,
+        &base_uri.0,
+        login_session.0,
+        credential,
+        session_kind,
+    )
+    .await
     {
-        Ok((_persona_id, session)) => Ok(login_success_redirect(session, &params)),
+        Ok((_persona_id, session)) => Ok(login_success_redirect(
+            &ctx, &base_uri, &prefix, session, &params,
+        )),
         Err(err) => {
             info!(?err, "WebAuthn auth finish error");
 
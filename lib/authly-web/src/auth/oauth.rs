@@ -1,26 +1,45 @@
 use std::{borrow::Cow, collections::BTreeMap};
 
 use anyhow::{anyhow, Context};
+use authly_common::id::PersonaId;
 use authly_domain::{
-    ctx::{Directories, GetDb, GetDecryptedDeks, GetHttpClient},
-    directory::{OAuthDirectory, PersonaDirectory},
+    ctx::{
+        Directories, GetClock, GetDb, GetDecryptedDeks, GetHttpClient, GetIdGenerator,
+        GetSessionCache, GetSettings, GetStats,
+    },
+    directory::{JitProvisioning, OAuthDirectory, PersonaDirectory},
     extract::base_uri::ProxiedBaseUri,
-    persona_directory::{self, ForeignPersona},
-    session::init_session,
+    persona_directory::{self, ForeignClaims, ForeignLinkError, ForeignPersona},
+    repo::oauth_repo,
+    session::{
+        authenticate_session_cookie, init_session, AuthClass, SessionKind, SESSION_COOKIE_NAME,
+    },
 };
 use axum::{
     extract::{Path, Query, State},
-    response::{IntoResponse, Response},
+    response::{IntoResponse, Redirect, Response},
+};
+use axum_extra::extract::{
+    cookie::{Cookie, SameSite},
+    CookieJar,
 };
-use axum_extra::extract::CookieJar;
 use http::StatusCode;
 use rand::{rngs::OsRng, Rng};
 use reqwest::Url;
 use tracing::warn;
 
+use crate::{app::step_up::StepUp, htmx::HX_REDIRECT, Htmx};
+
 #[derive(Debug)]
 pub enum OAuthError {
     PersonaDirectoryNotFound,
+    /// The state returned by the provider doesn't match the login started with it
+    StateMismatch,
+    /// Linking an account requires a valid persona session
+    Unauthenticated,
+    /// The directory has no state to carry the link request
+    LinkNotSupported,
+    AlreadyLinked,
     MissingCode,
     FetchToken(reqwest::Error),
     DeserializeToken(reqwest::Error),
@@ -32,6 +51,7 @@ pub enum OAuthError {
     AuthUrl,
     TokenUrl,
     CallbackUrl,
+    NotProvisioned(ForeignLinkError),
     EntityLink(anyhow::Error),
     Session(anyhow::Error),
 }
@@ -42,6 +62,9 @@ impl IntoResponse for OAuthError {
 
         match self {
             Self::PersonaDirectoryNotFound => StatusCode::NOT_FOUND.into_response(),
+            Self::StateMismatch | Self::LinkNotSupported => StatusCode::BAD_REQUEST.into_response(),
+            Self::Unauthenticated => StatusCode::UNAUTHORIZED.into_response(),
+            Self::AlreadyLinked => StatusCode::CONFLICT.into_response(),
             Self::MissingCode => StatusCode::UNPROCESSABLE_ENTITY.into_response(),
             Self::FetchToken(_) | Self::FetchUser(_) => {
                 StatusCode::INTERNAL_SERVER_ERROR.into_response()
@@ -51,6 +74,7 @@ impl IntoResponse for OAuthError {
             | Self::DeserializeUser(_)
             | Self::NoUserId
             | Self::NoUserEmail => StatusCode::BAD_GATEWAY.into_response(),
+            Self::NotProvisioned(_) => StatusCode::FORBIDDEN.into_response(),
             Self::AuthUrl
             | Self::TokenUrl
             | Self::CallbackUrl
@@ -60,20 +84,134 @@ impl IntoResponse for OAuthError {
     }
 }
 
+/// How long an OAuth login may take at the provider
+const OAUTH_STATE_TTL: time::Duration = time::Duration::minutes(10);
+
+/// Prefix of the state of account linking, as opposed to login
+const LINK_STATE_PREFIX: &str = "link-";
+
+/// Start an OAuth login, redirecting to the login website of the provider.
+///
+/// The state sent to the provider is kept in a cookie for the directory,
+/// so logins through different providers can't be mixed up.
+pub async fn oauth_start<Ctx>(
+    State(ctx): State<Ctx>,
+    base_uri: ProxiedBaseUri,
+    Path(label): Path<String>,
+) -> Result<Response, OAuthError>
+where
+    Ctx: Directories,
+{
+    let persona_directories = ctx.load_persona_directories();
+    let Some(PersonaDirectory::OAuth(oauth)) = persona_directories.get(&label) else {
+        return Err(OAuthError::PersonaDirectoryNotFound);
+    };
+
+    start_authorization(oauth, &label, &base_uri, random_state(), false)
+}
+
+/// Start linking an OAuth identity to the persona of the current session.
+///
+/// The flow completes in [oauth_callback], which recognizes the link request by its state.
+/// It is started with a POST, so that merely following a link can't start it,
+/// and like unlinking it requires a recent authentication, see [StepUp].
+pub async fn oauth_link_start<Ctx>(
+    State(ctx): State<Ctx>,
+    htmx: Htmx,
+    base_uri: ProxiedBaseUri,
+    Path(label): Path<String>,
+    StepUp(session): StepUp,
+) -> Result<Response, OAuthError>
+where
+    Ctx: GetDb + Directories + GetSettings + GetSessionCache + GetClock,
+{
+    let persona_directories = ctx.load_persona_directories();
+    let Some(PersonaDirectory::OAuth(oauth)) = persona_directories.get(&label) else {
+        return Err(OAuthError::PersonaDirectoryNotFound);
+    };
+
+    PersonaId::try_from(session.eid).map_err(|_| OAuthError::Unauthenticated)?;
+
+    // without a state, a link request could be forged
+    if oauth.auth_req_nonce_field.is_none() {
+        return Err(OAuthError::LinkNotSupported);
+    }
+
+    start_authorization(
+        oauth,
+        &label,
+        &base_uri,
+        format!("{LINK_STATE_PREFIX}{}", random_state()),
+        htmx.hx_request,
+    )
+}
+
+fn random_state() -> String {
+    let mut state = [0u8; 32];
+    OsRng.fill(state.as_mut_slice());
+    hexhex::hex(state).to_string()
+}
+
+/// Redirect to the login website of the provider, keeping the state in a cookie.
+///
+/// HTMX requests can't follow the redirect to another site, so the browser is told to navigate there instead.
+fn start_authorization(
+    oauth: &OAuthDirectory,
+    label: &str,
+    base_uri: &ProxiedBaseUri,
+    state: String,
+    hx_request: bool,
+) -> Result<Response, OAuthError> {
+    let authorize_url = build_oauth_web_authorize_url(oauth, label, base_uri, &state)?;
+    let state_cookie = Cookie::build((state_cookie_name(oauth), state))
+        .path("/")
+        .http_only(true)
+        // the provider redirects back with a top-level navigation
+        .same_site(SameSite::Lax)
+        .max_age(OAUTH_STATE_TTL)
+        .build();
+
+    if hx_request {
+        return Ok((
+            CookieJar::new().add(state_cookie),
+            [(HX_REDIRECT, authorize_url)],
+        )
+            .into_response());
+    }
+
+    Ok((
+        CookieJar::new().add(state_cookie),
+        Redirect::to(&authorize_url),
+    )
+        .into_response())
+}
+
 pub async fn oauth_callback<Ctx>(
     State(ctx): State<Ctx>,
     base_uri: ProxiedBaseUri,
     Path(label): Path<String>,
+    jar: CookieJar,
     query: Query<BTreeMap<String, String>>,
 ) -> Result<Response, OAuthError>
 where
-    Ctx: GetDb + Directories + GetHttpClient + GetDecryptedDeks,
+    Ctx: GetDb
+        + Directories
+        + GetHttpClient
+        + GetDecryptedDeks
+        + GetIdGenerator
+        + GetSettings
+        + GetSessionCache
+        + GetStats
+        + GetClock,
 {
     let persona_directories = ctx.load_persona_directories();
     let Some(PersonaDirectory::OAuth(oauth)) = persona_directories.get(&label) else {
         return Err(OAuthError::PersonaDirectoryNotFound);
     };
 
+    verify_state(oauth, &jar, &query)?;
+    let link = is_link_request(oauth, &query);
+
     let client = ctx.get_internet_http_client();
 
     let mut token_response: BTreeMap<String, String> = client
@@ -114,22 +252,114 @@ where
         .ok_or(OAuthError::NoUserEmail)?
         .map_err(|_| OAuthError::NoUserEmail)?;
 
-    let (persona_id, _) = persona_directory::link_foreign_persona(
+    let state_cookie = Cookie::build((state_cookie_name(oauth), "")).path("/");
+
+    if link {
+        let persona_id = session_persona(&ctx, &jar).await?;
+
+        persona_directory::link_foreign_identity(
+            &ctx,
+            oauth.dir_key,
+            persona_id,
+            user_id.as_ref().as_bytes().to_vec(),
+        )
+        .await
+        .map_err(|err| match err {
+            ForeignLinkError::AlreadyLinked => OAuthError::AlreadyLinked,
+            err => OAuthError::EntityLink(err.into()),
+        })?;
+
+        return Ok((
+            CookieJar::new().remove(state_cookie),
+            Redirect::to(&base_uri.to_string()),
+        )
+            .into_response());
+    }
+
+    let jit = oauth_repo::find_jit_provisioning(ctx.get_db(), oauth.dir_key)
+        .await
+        .map_err(|err| OAuthError::EntityLink(err.into()))?
+        .unwrap_or_else(JitProvisioning::unrestricted);
+
+    let persona_id = persona_directory::login_foreign_persona(
         &ctx,
         oauth.dir_key,
+        &jit,
         ForeignPersona {
             foreign_id: user_id.as_ref().as_bytes().to_vec(),
             email: email.to_string(),
         },
+        foreign_claims(&user_response, &jit),
+    )
+    .await
+    .map_err(|err| match err {
+        ForeignLinkError::NotProvisioned
+        | ForeignLinkError::DomainNotAllowed
+        | ForeignLinkError::Deactivated
+        | ForeignLinkError::Unlinked => OAuthError::NotProvisioned(err),
+        err => OAuthError::EntityLink(err.into()),
+    })?;
+
+    let session = init_session(
+        &ctx,
+        persona_id.upcast(),
+        SessionKind::Default,
+        AuthClass::Federated,
     )
     .await
-    .map_err(|err| OAuthError::EntityLink(err.into()))?;
+    .map_err(|err| OAuthError::Session(err.into()))?;
 
-    let session = init_session(&ctx, persona_id.upcast())
+    Ok(CookieJar::new()
+        .add(session.to_cookie(ctx.get_clock().now()))
+        .remove(state_cookie)
+        .into_response())
+}
+
+/// The persona of the session cookie
+async fn session_persona(
+    deps: &(impl GetDb + GetSettings + GetSessionCache + GetClock),
+    jar: &CookieJar,
+) -> Result<PersonaId, OAuthError> {
+    let session_cookie = jar
+        .get(SESSION_COOKIE_NAME)
+        .ok_or(OAuthError::Unauthenticated)?;
+    let session = authenticate_session_cookie(deps, session_cookie)
         .await
-        .map_err(|err| OAuthError::Session(err.into()))?;
+        .map_err(|_| OAuthError::Unauthenticated)?;
 
-    Ok(CookieJar::new().add(session.to_cookie()).into_response())
+    PersonaId::try_from(session.eid).map_err(|_| OAuthError::Unauthenticated)
+}
+
+/// The name of the cookie holding the state of a login through the directory
+fn state_cookie_name(oauth: &OAuthDirectory) -> String {
+    format!("authly-oauth-{}", hexhex::hex(oauth.dir_id.to_raw_array()))
+}
+
+/// Check that the state returned by the provider is the one issued for this directory by [oauth_start].
+///
+/// Providers configured without a nonce field have no state to check.
+fn verify_state(
+    oauth: &OAuthDirectory,
+    jar: &CookieJar,
+    query: &BTreeMap<String, String>,
+) -> Result<(), OAuthError> {
+    let Some(field) = oauth.auth_req_nonce_field.as_deref() else {
+        return Ok(());
+    };
+
+    match (jar.get(&state_cookie_name(oauth)), query.get(field)) {
+        (Some(expected), Some(state)) if !state.is_empty() && expected.value() == state => Ok(()),
+        _ => Err(OAuthError::StateMismatch),
+    }
+}
+
+/// Whether the (verified) state belongs to a link request from [oauth_link_start]
+fn is_link_request(oauth: &OAuthDirectory, query: &BTreeMap<String, String>) -> bool {
+    oauth
+        .auth_req_nonce_field
+        .as_deref()
+        .and_then(|field| query.get(field))
+        .is_some_and(|state| state.starts_with(LINK_STATE_PREFIX))
 }
 
 /// Build the URL to the external OAuth login website
@@ -137,6 +367,7 @@ pub fn build_oauth_web_authorize_url(
     oauth: &OAuthDirectory,
     label: &str,
     base_uri: &ProxiedBaseUri,
+    state: &str,
 ) -> Result<String, OAuthError> {
     let mut url = Url::parse(&oauth.auth_url).map_err(|_| OAuthError::AuthUrl)?;
 
@@ -148,10 +379,7 @@ pub fn build_oauth_web_authorize_url(
         }
 
         if let Some(field) = oauth.auth_req_nonce_field.as_deref() {
-            let mut nonce = [0u8; 32];
-            OsRng.fill(nonce.as_mut_slice());
-
-            q.append_pair(field, &hexhex::hex(nonce).to_string());
+            q.append_pair(field, state);
         }
 
         // This is optional but recommended for github, but there is no state for not sending this in the DB
@@ -211,6 +439,40 @@ fn build_oauth_token_url(
     Ok(url.to_string())
 }
 
+/// Extract the claims used for just-in-time provisioning from the user response
+fn foreign_claims(user_response: &serde_json::Value, jit: &JitProvisioning) -> ForeignClaims {
+    if !jit.enabled {
+        return ForeignClaims::default();
+    }
+
+    let username = json_str_by_path_opt(user_response, jit.user_res_username_path.as_deref())
+        .and_then(Result::ok)
+        .map(Cow::into_owned);
+
+    let mut attributes = vec![];
+    for mapping in &jit.claim_attributes {
+        let labels = match json_by_path(user_response, &mapping.claim_path) {
+            Ok(serde_json::Value::String(label)) => vec![label.as_str()],
+            Ok(serde_json::Value::Array(labels)) => {
+                labels.iter().filter_map(|label| label.as_str()).collect()
+            }
+            _ => continue,
+        };
+
+        for label in labels {
+            attributes.push((
+                (mapping.namespace.clone(), mapping.property.clone()),
+                label.to_string(),
+            ));
+        }
+    }
+
+    ForeignClaims {
+        username,
+        attributes,
+    }
+}
+
 fn json_str_by_path_opt<'j>(
     json: &'j serde_json::Value,
     path: Option<&str>,
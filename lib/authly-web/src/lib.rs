@@ -1,11 +1,13 @@
 use authly_domain::{
     ctx::{
-        Directories, GetBuiltins, GetDb, GetDecryptedDeks, GetHttpClient, GetInstance, WebAuthn,
+        ClusterBus, Directories, GetBuiltins, GetClock, GetClockSkew, GetDb, GetDecryptedDeks,
+        GetHttpClient, GetIdGenerator, GetInstance, GetSessionCache, GetSettings, GetStats,
+        ServiceBus, WebAuthn,
     },
     extract::base_uri::ForwardedPrefix,
 };
 use authly_webstatic::static_folder;
-use axum::routing::{get, post};
+use axum::routing::{delete, get, post};
 use http::request::Parts;
 
 pub mod app;
@@ -22,6 +24,14 @@ where
         + GetDecryptedDeks
         + Directories
         + GetHttpClient
+        + GetIdGenerator
+        + GetSettings
+        + GetSessionCache
+        + GetStats
+        + GetClockSkew
+        + GetClock
+        + ServiceBus
+        + ClusterBus
         + WebAuthn
         + Clone
         + Send
@@ -31,6 +41,17 @@ where
     axum::Router::new()
         .route("/", get(app::index))
         .route("/tab/persona", get(app::persona::persona::<Ctx>))
+        .route(
+            "/tab/persona/session/{handle}",
+            delete(app::persona::revoke_session::<Ctx>),
+        )
+        .route(
+            "/tab/persona/link/{label}",
+            delete(app::persona::unlink_account::<Ctx>),
+        )
+        .route("/tab/stepup", post(app::step_up::step_up::<Ctx>))
+        .route("/tab/admin", get(app::admin::admin::<Ctx>))
+        .route("/tab/admin/stats", get(app::admin::admin_stats::<Ctx>))
         .route(
             "/tab/persona/webauthn/register_start",
             post(app::persona::webauthn_register_start::<Ctx>),
@@ -39,16 +60,21 @@ where
             "/tab/persona/webauthn/register_finish",
             post(app::persona::webauthn_register_finish::<Ctx>),
         )
-        .route("/auth", get(auth::index))
-        .route("/auth/", get(auth::index))
+        .route("/auth", get(auth::index::<Ctx>))
+        .route("/auth/", get(auth::index::<Ctx>))
         .route("/auth/login", post(auth::login::<Ctx>))
         .route(
             "/auth/webauthn/finish",
             post(auth::webauthn_auth_finish::<Ctx>),
         )
+        .route("/auth/oauth/{label}", get(auth::oauth::oauth_start::<Ctx>))
+        .route(
+            "/auth/oauth/{label}/link",
+            post(auth::oauth::oauth_link_start::<Ctx>),
+        )
         .route(
             "/auth/oauth/{label}/callback",
-            post(auth::oauth::oauth_callback::<Ctx>),
+            get(auth::oauth::oauth_callback::<Ctx>).post(auth::oauth::oauth_callback::<Ctx>),
         )
         .nest_service("/static", static_folder())
 }
@@ -66,6 +92,12 @@ mod htmx {
     /// https://htmx.org/headers/hx-trigger/
     pub const HX_TRIGGER: HeaderName = HeaderName::from_static("hx-trigger");
 
+    /// https://htmx.org/headers/hx-retarget/
+    pub const HX_RETARGET: HeaderName = HeaderName::from_static("hx-retarget");
+
+    /// https://htmx.org/headers/hx-reswap/
+    pub const HX_RESWAP: HeaderName = HeaderName::from_static("hx-reswap");
+
     /// https://htmx.org/reference/#response_headers
     pub const HX_REFRESH: HeaderName = HeaderName::from_static("hx-refresh");
 }
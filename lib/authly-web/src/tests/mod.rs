@@ -1 +1,3 @@
+mod test_admin;
 mod test_oauth;
+mod test_step_up;
@@ -0,0 +1,162 @@
+use authly_common::{
+    id::{PersonaId, ServiceId},
+    mtls_server::PeerServiceEntity,
+};
+use authly_domain::{
+    access_control::role,
+    ctx::GetClock,
+    dev::IsDev,
+    extract::auth::WebAuth,
+    session::{init_session, AuthClass, SessionKind},
+};
+use authly_test::{test_ctx::TestCtx, util::compile_and_apply_doc};
+use axum::extract::{FromRequestParts, State};
+use hexhex::hex_literal;
+use http::{header::COOKIE, StatusCode};
+use indoc::indoc;
+
+use crate::Htmx;
+
+const ADMIN: PersonaId =
+    PersonaId::from_raw_array(hex_literal!("a8a3ab2a58504d8d9ee2ab8f0b8c3d71"));
+const USER: PersonaId = PersonaId::from_raw_array(hex_literal!("3b3a9f6f0e3f4a4e8b1b9c1f0c7a2d55"));
+
+async fn admin_web_auth(
+    ctx: &TestCtx,
+    persona_id: PersonaId,
+) -> Result<WebAuth<role::Admin>, axum::response::Response> {
+    let session = init_session(
+        ctx,
+        persona_id.upcast(),
+        SessionKind::Default,
+        AuthClass::Password,
+    )
+    .await
+    .unwrap();
+    let cookie = session.to_cookie(ctx.get_clock().now());
+
+    let (mut parts, _) = http::Request::builder()
+        .header(COOKIE, format!("{}={}", cookie.name(), cookie.value()))
+        .extension(PeerServiceEntity(ServiceId::random()))
+        .extension(IsDev(true))
+        .body(())
+        .unwrap()
+        .into_parts();
+
+    WebAuth::<role::Admin>::from_request_parts(&mut parts, ctx).await
+}
+
+async fn seeded_ctx() -> TestCtx {
+    let ctx = TestCtx::new().inmemory_db().await.supreme_instance().await;
+    let doc = indoc! {
+        r#"
+        [authly-document]
+        id = "5c4b0cd3-8f4e-4bb1-9d8b-2a0e3b8e5c11"
+
+        [[entity]]
+        eid = "p.a8a3ab2a58504d8d9ee2ab8f0b8c3d71"
+        label = "admin"
+
+        [[entity]]
+        eid = "p.3b3a9f6f0e3f4a4e8b1b9c1f0c7a2d55"
+        label = "user"
+
+        [[service-entity]]
+        eid = "s.e5462a0d22b54d9f9ca37bd96e9b9d8b"
+        label = "svc"
+
+        [[entity-attribute-assignment]]
+        entity = "p.a8a3ab2a58504d8d9ee2ab8f0b8c3d71"
+        attributes = ["authly:role:admin"]
+        "#
+    };
+
+    compile_and_apply_doc(doc, &ctx).await.unwrap();
+    ctx
+}
+
+#[test_log::test(tokio::test)]
+async fn test_admin_dashboard_renders_counts() {
+    let ctx = seeded_ctx().await;
+    let auth = admin_web_auth(&ctx, ADMIN).await.ok().unwrap();
+
+    let html = crate::app::admin::admin(
+        State(ctx.clone()),
+        Htmx {
+            hx_request: false,
+            prefix: "".to_string(),
+        },
+        auth,
+    )
+    .await
+    .unwrap()
+    .into_string();
+
+    // the builtin authly directory and the seeded document
+    assert!(html.contains("<tr><th>Directories</th><td>2</td></tr>"));
+    assert!(html.contains("<tr><th>Services</th><td>1</td></tr>"));
+    assert!(html.contains("<tr><th>Policies</th><td>0</td></tr>"));
+    assert!(html.contains("Recent changes"));
+}
+
+#[test_log::test(tokio::test)]
+async fn test_admin_dashboard_forbidden_without_admin_role() {
+    let ctx = seeded_ctx().await;
+
+    let Err(response) = admin_web_auth(&ctx, USER).await else {
+        panic!("user without admin role must be rejected");
+    };
+
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+}
+
+#[test_log::test(tokio::test)]
+async fn test_admin_dashboard_policy_coverage() {
+    let ctx = seeded_ctx().await;
+    compile_and_apply_doc(
+        indoc! {
+            r#"
+            [authly-document]
+            id = "8a7e38a4-4ad5-4b8f-a0f6-2f4c9f1b6e3a"
+
+            [[service-entity]]
+            eid = "s.5d0c7e8a2b1f4c6e9a3d7b2e1f0c9a8b"
+            label = "store"
+
+            [[resource-property]]
+            namespace = "store"
+            label = "verb"
+            attributes = ["read", "write"]
+
+            [[policy]]
+            label = "allow store"
+            allow = "Subject.authly:entity == store"
+
+            [[policy-binding]]
+            attributes = ["store:verb:read"]
+            policies = ["allow store"]
+            "#
+        },
+        &ctx,
+    )
+    .await
+    .unwrap();
+    let auth = admin_web_auth(&ctx, ADMIN).await.ok().unwrap();
+
+    let html = crate::app::admin::admin(
+        State(ctx.clone()),
+        Htmx {
+            hx_request: false,
+            prefix: "".to_string(),
+        },
+        auth,
+    )
+    .await
+    .unwrap()
+    .into_string();
+
+    assert!(html.contains("<tr><td><code>store:verb:read</code></td><td>1</td></tr>"));
+    assert!(html.contains(
+        "<tr><td><code>store:verb:write</code></td><td><mark>uncovered</mark></td></tr>"
+    ));
+}
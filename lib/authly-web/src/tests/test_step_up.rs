@@ -0,0 +1,99 @@
+use authly_common::id::PersonaId;
+use authly_db::{params, Db};
+use authly_domain::{
+    ctx::GetDb,
+    session::{init_session, AuthClass, Session, SessionKind},
+};
+use authly_test::{test_ctx::TestCtx, util::compile_and_apply_doc_dir};
+use axum::{
+    extract::{FromRequestParts, State},
+    Form,
+};
+use hexhex::hex_literal;
+use http::{header::COOKIE, HeaderMap, HeaderValue};
+use time::{Duration, OffsetDateTime};
+
+use crate::{
+    app::step_up::{step_up, StepUp},
+    htmx::HX_RETARGET,
+    Htmx,
+};
+
+const PERSONA_ME: PersonaId =
+    PersonaId::from_raw_array(hex_literal!("0fbcd73e1a884424a1615c3c3fdeebec"));
+
+fn cookie_headers(session: &Session) -> HeaderMap {
+    let cookie = session.to_cookie(time::OffsetDateTime::now_utc());
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        COOKIE,
+        HeaderValue::from_str(&format!("{}={}", cookie.name(), cookie.value())).unwrap(),
+    );
+    headers
+}
+
+async fn try_step_up(ctx: &TestCtx, session: &Session) -> Result<StepUp, axum::response::Response> {
+    let mut request = http::Request::builder().body(()).unwrap();
+    *request.headers_mut() = cookie_headers(session);
+    let (mut parts, _) = request.into_parts();
+
+    StepUp::from_request_parts(&mut parts, ctx).await
+}
+
+async fn submit_password(ctx: &TestCtx, session: &Session, password: &str) -> String {
+    step_up(
+        State(ctx.clone()),
+        Htmx {
+            hx_request: true,
+            prefix: "".to_string(),
+        },
+        cookie_headers(session),
+        Form(serde_urlencoded::from_str(&format!("password={password}")).unwrap()),
+    )
+    .await
+    .unwrap()
+    .into_string()
+}
+
+#[test_log::test(tokio::test)]
+async fn test_sensitive_route_requires_step_up() {
+    let ctx = TestCtx::new().inmemory_db().await.supreme_instance().await;
+    compile_and_apply_doc_dir("../../examples/demo".into(), &ctx)
+        .await
+        .unwrap();
+
+    let session = init_session(
+        &ctx,
+        PERSONA_ME.upcast(),
+        SessionKind::Default,
+        AuthClass::Password,
+    )
+    .await
+    .unwrap();
+
+    // a fresh login is strong enough
+    assert!(try_step_up(&ctx, &session).await.is_ok());
+
+    // the login is no longer recent
+    let authenticated_at = OffsetDateTime::now_utc() - Duration::minutes(10);
+    ctx.get_db()
+        .execute(
+            "UPDATE session SET authenticated_at = $1 WHERE token = $2".into(),
+            params!(authenticated_at.unix_timestamp(), session.token.0.clone()),
+        )
+        .await
+        .unwrap();
+
+    let Err(challenge) = try_step_up(&ctx, &session).await else {
+        panic!("stale session must be challenged");
+    };
+    assert_eq!(challenge.headers()[HX_RETARGET], "#stepup");
+
+    let html = submit_password(&ctx, &session, "wrong").await;
+    assert!(html.contains("Invalid password"));
+    assert!(try_step_up(&ctx, &session).await.is_err());
+
+    let html = submit_password(&ctx, &session, "secret").await;
+    assert!(html.contains("Identity confirmed"));
+    assert!(try_step_up(&ctx, &session).await.is_ok());
+}